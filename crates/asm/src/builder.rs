@@ -0,0 +1,426 @@
+//! Macro-assembler writing instructions from code, see [FunctionBuilder].
+//!
+//! The builder allocates the registers and picks the opcodes : constants are loaded into fresh registers, calls use
+//! the opcode matching their number of arguments and returns get a `void` register when needed. Jumps target
+//! [Label]s or instructions of the existing function, their offsets are computed by [FunctionBuilder::finish].
+//!
+//! ```no_run
+//! # use hlbc::Bytecode;
+//! # use hlbc_asm::builder::FunctionBuilder;
+//! let mut code = Bytecode::from_file("hlboot.dat")?;
+//! let hook = code.function_by_qualified_name("pack.Hooks.onHit").unwrap().findex;
+//! let f = code.function_by_qualified_name("pack.Player.takeDamage").unwrap().findex;
+//! // Call onHit("player", 42) at the start of the method
+//! let mut b = FunctionBuilder::insert(&mut code, f, 0)?;
+//! let name = b.string("player")?;
+//! let damage = b.int(42);
+//! b.call(hook, &[name, damage])?;
+//! b.finish()?;
+//! # Ok::<(), hlbc::Error>(())
+//! ```
+
+use hlbc::opcodes::Opcode;
+use hlbc::path::PathTarget;
+use hlbc::types::{Function, JumpTarget, RefFun, RefType, Reg, Type};
+use hlbc::{Bytecode, Error, ResolveMut, Result};
+
+/// A position in the built instructions, created with [FunctionBuilder::label] and placed with
+/// [FunctionBuilder::bind]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Label(usize);
+
+/// Target of a jump of a [FunctionBuilder]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Jump {
+    Label(Label),
+    /// Position of an instruction of the function before the insertion
+    Existing(usize),
+}
+
+impl From<Label> for Jump {
+    fn from(label: Label) -> Self {
+        Jump::Label(label)
+    }
+}
+
+/// Where the built instructions end up
+enum Target {
+    /// A new function
+    New { findex: RefFun, function: Function },
+    /// Inserted in an existing function
+    Insert { f: RefFun, pos: usize },
+}
+
+/// Builds instructions and registers for a new function or for an insertion in an existing function
+pub struct FunctionBuilder<'a> {
+    code: &'a mut Bytecode,
+    /// Registers of the function, the existing ones followed by the new ones
+    regs: Vec<RefType>,
+    ops: Vec<Opcode>,
+    /// Position of each label, `None` until bound
+    labels: Vec<Option<usize>>,
+    /// Jump offsets to compute, as (instruction, offset index, target)
+    jumps: Vec<(usize, usize, Jump)>,
+    target: Target,
+    /// Register returned by [FunctionBuilder::ret_void]
+    void: Option<Reg>,
+}
+
+impl<'a> FunctionBuilder<'a> {
+    /// Start a new function of type `t` (a function type). Its arguments are the first registers.
+    pub fn new(code: &'a mut Bytecode, name: &str, t: RefType) -> Result<Self> {
+        let Some(fun) = code.types.get(t.0).and_then(Type::get_type_fun) else {
+            return Err(Error::InvalidEdit(format!(
+                "type@{} is not a function type",
+                t.0
+            )));
+        };
+        let regs = fun.args.clone();
+        let findex = RefFun(code.functions.len() + code.natives.len());
+        let function = Function {
+            t,
+            findex,
+            regs: Vec::new(),
+            ops: Vec::new(),
+            debug_info: None,
+            assigns: None,
+            name: code.intern_string(name),
+            parent: None,
+        };
+        Ok(Self {
+            code,
+            regs,
+            ops: Vec::new(),
+            labels: Vec::new(),
+            jumps: Vec::new(),
+            target: Target::New { findex, function },
+            void: None,
+        })
+    }
+
+    /// Start instructions inserted at `pos` in the function `f`, see [Function::insert_ops]. The registers of the
+    /// function can be used.
+    pub fn insert(code: &'a mut Bytecode, f: RefFun, pos: usize) -> Result<Self> {
+        let function = (f.0 < code.findex_max())
+            .then(|| f.as_fn(code))
+            .flatten()
+            .ok_or_else(|| Error::InvalidEdit(format!("fn@{} is not a function", f.0)))?;
+        if pos > function.ops.len() {
+            return Err(Error::InvalidEdit(format!(
+                "position {pos} out of fn@{} with {} instructions",
+                f.0,
+                function.ops.len()
+            )));
+        }
+        let regs = function.regs.clone();
+        Ok(Self {
+            code,
+            regs,
+            ops: Vec::new(),
+            labels: Vec::new(),
+            jumps: Vec::new(),
+            target: Target::Insert { f, pos },
+            void: None,
+        })
+    }
+
+    /// The bytecode, e.g. to intern constants
+    pub fn code(&mut self) -> &mut Bytecode {
+        self.code
+    }
+
+    /// A new register of type `t`
+    pub fn reg(&mut self, t: RefType) -> Reg {
+        self.regs.push(t);
+        Reg(self.regs.len() as u32 - 1)
+    }
+
+    /// Type of a register
+    pub fn reg_type(&self, reg: Reg) -> RefType {
+        self.regs[reg.0 as usize]
+    }
+
+    /// Append an instruction
+    pub fn op(&mut self, op: Opcode) {
+        self.ops.push(op);
+    }
+
+    /// A new label, to be bound before [FunctionBuilder::finish]
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Place a label before the next instruction
+    pub fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.ops.len());
+    }
+
+    /// A new label bound before the next instruction, e.g. the start of a loop
+    pub fn here(&mut self) -> Label {
+        let label = self.label();
+        self.bind(label);
+        label
+    }
+
+    /// Append an instruction with jump offsets (a jump, a `Switch` or a `Trap`), their offsets are computed from
+    /// `targets` in the order of [Opcode::offsets_mut].
+    pub fn jump_op(&mut self, op: Opcode, targets: &[Jump]) {
+        let pos = self.ops.len();
+        self.jumps
+            .extend(targets.iter().enumerate().map(|(i, &t)| (pos, i, t)));
+        self.op(op);
+    }
+
+    /// Jump unconditionally
+    pub fn jump(&mut self, target: impl Into<Jump>) {
+        self.jump_op(Opcode::JAlways { offset: 0 }, &[target.into()]);
+    }
+
+    /// Jump if the `bool` register is true
+    pub fn jump_if(&mut self, cond: Reg, target: impl Into<Jump>) {
+        self.jump_op(Opcode::JTrue { cond, offset: 0 }, &[target.into()]);
+    }
+
+    /// Jump if the `bool` register is false
+    pub fn jump_if_not(&mut self, cond: Reg, target: impl Into<Jump>) {
+        self.jump_op(Opcode::JFalse { cond, offset: 0 }, &[target.into()]);
+    }
+
+    /// A new register of a base type
+    fn typed_reg(&mut self, t: Type) -> Reg {
+        let t = self.code.intern_type(t);
+        self.reg(t)
+    }
+
+    /// Load an integer into a new `i32` register
+    pub fn int(&mut self, value: i32) -> Reg {
+        let dst = self.typed_reg(Type::I32);
+        let ptr = self.code.intern_int(value);
+        self.op(Opcode::Int { dst, ptr });
+        dst
+    }
+
+    /// Load a float into a new `f64` register
+    pub fn float(&mut self, value: f64) -> Reg {
+        let dst = self.typed_reg(Type::F64);
+        let ptr = self.code.intern_float(value);
+        self.op(Opcode::Float { dst, ptr });
+        dst
+    }
+
+    /// Load a boolean into a new `bool` register
+    pub fn bool(&mut self, value: bool) -> Reg {
+        let dst = self.typed_reg(Type::Bool);
+        self.op(Opcode::Bool { dst, value });
+        dst
+    }
+
+    /// Load a string into a new `String` register. Fails if the bytecode has no `String` class.
+    pub fn string(&mut self, value: &str) -> Result<Reg> {
+        let Some(PathTarget::Type(t)) = self.code.resolve_path("String") else {
+            return Err(Error::InvalidEdit("no String class".to_string()));
+        };
+        let dst = self.reg(t);
+        let ptr = self.code.intern_string(value);
+        self.op(Opcode::String { dst, ptr });
+        Ok(dst)
+    }
+
+    /// Load `null` into a new register of type `t`
+    pub fn null(&mut self, t: RefType) -> Reg {
+        let dst = self.reg(t);
+        self.op(Opcode::Null { dst });
+        dst
+    }
+
+    /// Call a function or a native with the opcode matching the number of arguments. The result is in a new register
+    /// of the return type.
+    pub fn call(&mut self, fun: RefFun, args: &[Reg]) -> Result<Reg> {
+        if fun.0 >= self.code.findex_max() {
+            return Err(Error::InvalidEdit(format!("fn@{} does not exist", fun.0)));
+        }
+        let ty = fun.ty(self.code);
+        let (nargs, ret) = (ty.args.len(), ty.ret);
+        if nargs != args.len() {
+            return Err(Error::InvalidEdit(format!(
+                "fn@{} expects {nargs} arguments, got {}",
+                fun.0,
+                args.len()
+            )));
+        }
+        let dst = self.reg(ret);
+        self.op(match *args {
+            [] => Opcode::Call0 { dst, fun },
+            [arg0] => Opcode::Call1 { dst, fun, arg0 },
+            [arg0, arg1] => Opcode::Call2 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+            },
+            [arg0, arg1, arg2] => Opcode::Call3 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+            },
+            [arg0, arg1, arg2, arg3] => Opcode::Call4 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+            },
+            _ => Opcode::CallN {
+                dst,
+                fun,
+                args: args.iter().copied().collect(),
+            },
+        });
+        Ok(dst)
+    }
+
+    /// Return a value
+    pub fn ret(&mut self, ret: Reg) {
+        self.op(Opcode::Ret { ret });
+    }
+
+    /// Return from a `void` function, the `void` register is allocated once
+    pub fn ret_void(&mut self) {
+        let ret = match self.void {
+            Some(reg) => reg,
+            None => {
+                let reg = self.typed_reg(Type::Void);
+                self.void = Some(reg);
+                reg
+            }
+        };
+        self.ret(ret);
+    }
+
+    /// Install the instructions : add the new function or insert them in the existing one. Returns the findex of
+    /// the function. Fails if a label isn't bound or a jump targets an instruction that doesn't exist.
+    pub fn finish(self) -> Result<RefFun> {
+        let mut targets = Vec::with_capacity(self.jumps.len());
+        for &(i, slot, jump) in &self.jumps {
+            let target = match jump {
+                Jump::Label(label) => JumpTarget::Local(self.labels[label.0].ok_or_else(|| {
+                    Error::InvalidEdit(format!("label {} is not bound", label.0))
+                })?),
+                Jump::Existing(pos) => JumpTarget::Function(pos),
+            };
+            targets.push((i, slot, target));
+        }
+        match self.target {
+            Target::New {
+                findex,
+                mut function,
+            } => {
+                function.regs = self.regs;
+                function.splice_ops_with_targets(0..0, self.ops, &targets)?;
+                self.code.functions.push(function);
+                self.code.rebuild_indexes();
+                Ok(findex)
+            }
+            Target::Insert { f, pos } => {
+                let function = self.code.get_mut(f).unwrap();
+                function.splice_ops_with_targets(pos..pos, self.ops, &targets)?;
+                function.regs = self.regs;
+                Ok(f)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFun, Type};
+
+    use crate::builder::{FunctionBuilder, Jump};
+    use crate::testing::program;
+
+    #[test]
+    fn test_new_function() -> hlbc::Result<()> {
+        let mut code = program();
+        let main = code.main();
+        let (main, t) = (main.findex, main.t);
+        let mut b = FunctionBuilder::new(&mut code, "generated", t)?;
+        let a = b.int(1);
+        let s = b.string("text")?;
+        b.call(main, &[])?;
+        assert!(b.call(main, &[a, s]).is_err());
+        b.ret_void();
+        b.ret_void();
+        let f = b.finish()?;
+
+        assert_eq!(f, RefFun(2));
+        let f = f.as_fn(&code).unwrap();
+        assert_eq!(&*f.name(&code), "generated");
+        assert!(matches!(f.ops[2], Opcode::Call0 { fun, .. } if fun == main));
+        // The void register is shared
+        assert!(
+            matches!((&f.ops[3], &f.ops[4]), (Opcode::Ret { ret: r0 }, Opcode::Ret { ret: r1 }) if r0 == r1)
+        );
+        assert_eq!(code[f.regs[a.0 as usize]], Type::I32);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert() -> hlbc::Result<()> {
+        let mut code = program();
+        let main = code.main();
+        let (main, len, nregs) = (main.findex, main.ops.len(), main.regs.len());
+        let mut b = FunctionBuilder::insert(&mut code, main, 0)?;
+        b.float(1.5);
+        b.bool(true);
+        b.finish()?;
+        let f = code.main();
+        assert_eq!(f.ops.len(), len + 2);
+        assert_eq!(f.regs.len(), nregs + 2);
+        assert!(matches!(f.ops[0], Opcode::Float { .. }));
+        assert!(matches!(f.ops[1], Opcode::Bool { value: true, .. }));
+        assert!(matches!(f.ops[2], Opcode::String { .. }));
+        assert_eq!(code[f.regs[3]], Type::F64);
+        assert_eq!(code[f.regs[4]], Type::Bool);
+        assert!(FunctionBuilder::insert(&mut code, main, len + 3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_labels() -> hlbc::Result<()> {
+        let mut code = program();
+        let main = code.main();
+        let (main, t, len) = (main.findex, main.t, main.ops.len());
+
+        // while (!b) {}
+        let mut b = FunctionBuilder::new(&mut code, "generated", t)?;
+        let exit = b.label();
+        let cond = b.bool(false);
+        let start = b.here();
+        b.jump_if(cond, exit);
+        b.jump(start);
+        b.bind(exit);
+        b.ret_void();
+        let f = b.finish()?;
+        let f = f.as_fn(&code).unwrap();
+        assert_eq!(f.ops[1].jump_targets(1), vec![3]);
+        assert_eq!(f.ops[2].jump_targets(2), vec![1]);
+
+        // Skip the first instruction of main, the target accounts for the inserted instructions
+        let mut b = FunctionBuilder::insert(&mut code, main, 0)?;
+        b.jump(Jump::Existing(1));
+        b.finish()?;
+        assert_eq!(code.main().ops.len(), len + 1);
+        assert_eq!(code.main().ops[0].jump_targets(0), vec![2]);
+
+        let mut b = FunctionBuilder::insert(&mut code, main, 0)?;
+        let label = b.label();
+        b.jump(label);
+        assert!(b.finish().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+//! Assembler for the textual assembly of [hlbc::fmt::asm], resolving symbolic names against an existing bytecode.
+//!
+//! The canonical syntax emitted by [AsmFmt](hlbc::fmt::AsmFmt) only uses raw pool indices. The [Assembler] accepts
+//! names in their place, rewrites them to indices and parses the result with [parse_functions] :
+//! - `string@"text"` : a string literal with Rust escapes, added to the pool when missing
+//! - `type@Name` : a type written like [EnhancedFmt] displays it (`i32`, `pack.MyClass`), quoted when it contains
+//!   spaces (`type@"(i32) -> void"`)
+//! - `fn@pack.MyClass.method` : a function by its qualified name, or by the name of a function of the same text
+//! - `.fun fn@new <type>` : a new function, with the next free findex
+//!
+//! Edits of existing functions can be distributed as [patch] files instead. Instructions can also be generated from
+//! code with the [builder].
+//!
+//! ```no_run
+//! # use hlbc::Bytecode;
+//! # use hlbc_asm::Assembler;
+//! let mut code = Bytecode::from_file("hlboot.dat")?;
+//! let text = std::fs::read_to_string("patch.hlasm")?;
+//! Assembler::new(&mut code).apply(&text)?;
+//! code.serialize(&mut std::fs::File::create("patched.dat")?)?;
+//! # Ok::<(), hlbc::Error>(())
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+
+use hlbc::fmt::asm::parse_functions;
+use hlbc::fmt::EnhancedFmt;
+use hlbc::types::{Function, RefFun, RefType};
+use hlbc::{Bytecode, Error, ResolveMut, Result};
+
+pub use patch::{apply_patch, Patch};
+
+pub mod builder;
+pub mod patch;
+#[cfg(test)]
+mod testing;
+
+/// Resolves the symbolic names of the assembly text against a bytecode and installs the assembled functions, see the
+/// [crate] documentation.
+pub struct Assembler<'a> {
+    code: &'a mut Bytecode,
+    /// Types by their displayed name, the first one when multiple types display the same
+    types: HashMap<String, RefType>,
+    next_findex: usize,
+}
+
+fn error(line: usize, msg: impl Display) -> Error {
+    Error::MalformedBytecode(format!("line {line}: {msg}"))
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(code: &'a mut Bytecode) -> Self {
+        let mut types = HashMap::new();
+        for i in 0..code.types.len() {
+            types
+                .entry(RefType(i).display::<EnhancedFmt>(code).to_string())
+                .or_insert(RefType(i));
+        }
+        let next_findex = code.functions.len() + code.natives.len();
+        Self {
+            code,
+            types,
+            next_findex,
+        }
+    }
+
+    /// Rewrite the symbolic names of the text to raw pool indices, giving the canonical syntax parsed by
+    /// [parse_functions]. New strings are added to the pool and new functions get their findex.
+    pub fn resolve(&mut self, text: &str) -> Result<String> {
+        // First pass : findexes of the new functions and their names, so they can be called before their definition
+        let mut new_functions = VecDeque::new();
+        let mut locals = HashMap::new();
+        let mut current_new = false;
+        for line in text.lines() {
+            let tokens = tokens(strip_comment(line));
+            match tokens.as_slice() {
+                [".fun", "fn@new", ..] => {
+                    new_functions.push_back(RefFun(self.next_findex));
+                    self.next_findex += 1;
+                    current_new = true;
+                }
+                [".fun", ..] => current_new = false,
+                [".name", name] if current_new => {
+                    if let Some(name) = name.strip_prefix("string@").and_then(unquote) {
+                        locals.insert(name, *new_functions.back().unwrap());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut out = String::with_capacity(text.len());
+        for (i, line) in text.lines().enumerate() {
+            let i = i + 1;
+            let tokens = tokens(strip_comment(line));
+            for (j, token) in tokens.iter().enumerate() {
+                if j > 0 {
+                    out.push(' ');
+                }
+                if let Some(value) = token.strip_prefix("string@").filter(|v| v.starts_with('"')) {
+                    let s = unquote(value)
+                        .ok_or_else(|| error(i, format!("invalid string {value}")))?;
+                    out.push_str(&format!("string@{}", self.code.intern_string(s).0));
+                } else if let Some(name) = token.strip_prefix("type@") {
+                    out.push_str(&format!("type@{}", self.ty(i, name)?.0));
+                } else if *token == "fn@new" && tokens[0] == ".fun" {
+                    out.push_str(&format!("fn@{}", new_functions.pop_front().unwrap().0));
+                } else if let Some(name) = token.strip_prefix("fn@") {
+                    out.push_str(&format!("fn@{}", self.function(i, name, &locals)?.0));
+                } else {
+                    out.push_str(token);
+                }
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    fn ty(&self, line: usize, name: &str) -> Result<RefType> {
+        if let Ok(i) = name.parse() {
+            return Ok(RefType(i));
+        }
+        let name = unquote(name).unwrap_or_else(|| name.to_string());
+        self.types
+            .get(&name)
+            .copied()
+            .ok_or_else(|| error(line, format!("unknown type {name}")))
+    }
+
+    fn function(
+        &self,
+        line: usize,
+        name: &str,
+        locals: &HashMap<String, RefFun>,
+    ) -> Result<RefFun> {
+        if let Ok(i) = name.parse() {
+            return Ok(RefFun(i));
+        }
+        if let Some(&f) = locals.get(name) {
+            return Ok(f);
+        }
+        find_function(self.code, name)
+            .ok_or_else(|| error(line, format!("unknown function {name}")))
+    }
+
+    /// Resolve the symbolic names and parse the functions, see [Assembler::resolve]
+    pub fn assemble(&mut self, text: &str) -> Result<Vec<Function>> {
+        parse_functions(&self.resolve(text)?)
+    }
+
+    /// Assemble the functions of the text and install them in the bytecode. Functions with an existing findex are
+    /// replaced, new functions are appended. Returns the findexes of the assembled functions.
+    pub fn apply(mut self, text: &str) -> Result<Vec<RefFun>> {
+        let functions = self.assemble(text)?;
+        let existing = self.code.findex_max();
+        let mut findexes = Vec::with_capacity(functions.len());
+        for f in functions {
+            findexes.push(f.findex);
+            if f.findex.0 < existing {
+                match self.code.get_mut(f.findex) {
+                    Some(dst) => *dst = f,
+                    None => {
+                        return Err(Error::InvalidEdit(format!("fn@{} is a native", f.findex.0)))
+                    }
+                }
+            } else {
+                self.code.functions.push(f);
+            }
+        }
+        self.code.rebuild_indexes();
+        Ok(findexes)
+    }
+}
+
+/// A function by its qualified name (`pack.MyClass.method`) or its name
+fn find_function(code: &Bytecode, name: &str) -> Option<RefFun> {
+    code.function_by_qualified_name(name)
+        .or_else(|| code.function_by_name(name))
+        .map(|f| f.findex)
+}
+
+/// Part of the line before a comment, a `;` in a string literal doesn't start a comment
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split a line on whitespace, lists between brackets and string literals are single tokens
+fn tokens(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_string = false;
+    let mut in_list = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => in_list = true,
+            ']' if !in_string => in_list = false,
+            c if c.is_whitespace() && !in_string && !in_list => {
+                if let Some(s) = start.take() {
+                    tokens.push(&line[s..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        tokens.push(&line[s..]);
+    }
+    tokens
+}
+
+/// Content of a string literal between double quotes with Rust escapes (`\n`, `\"`, `\u{7f}`, ...)
+fn unquote(s: &str) -> Option<String> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'u' => {
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                char::from_u32(u32::from_str_radix(hex.strip_prefix('{')?, 16).ok()?)?
+            }
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::fmt::{AsmFmt, EnhancedFmt};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::RefFun;
+
+    use crate::testing::program;
+    use crate::{tokens, unquote, Assembler};
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            tokens(r#"  CallN reg0 fn@main [reg1, reg2] string@"a \"b\" c""#),
+            vec![
+                "CallN",
+                "reg0",
+                "fn@main",
+                "[reg1, reg2]",
+                r#"string@"a \"b\" c""#
+            ]
+        );
+        assert_eq!(unquote(r#""a\n\"\u{e9}""#).as_deref(), Some("a\n\"\u{e9}"));
+        assert_eq!(unquote("no quotes"), None);
+    }
+
+    #[test]
+    fn test_canonical() -> hlbc::Result<()> {
+        let mut code = program();
+        let text: String = code
+            .functions
+            .iter()
+            .map(|f| f.display::<AsmFmt>(&code).to_string())
+            .collect();
+        let functions = Assembler::new(&mut code).assemble(&text)?;
+        assert_eq!(functions.len(), 2);
+        for (a, b) in functions.iter().zip(&code.functions) {
+            assert_eq!(format!("{:?}", a.ops), format!("{:?}", b.ops));
+            assert_eq!(a.regs, b.regs);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_symbols() -> hlbc::Result<()> {
+        let mut code = program();
+        let main = code.main();
+        let (findex, t) = (
+            main.findex,
+            main.t.display::<EnhancedFmt>(&code).to_string(),
+        );
+        assert_eq!(t, "() -> void");
+        let nfunctions = code.functions.len();
+        let text = format!(
+            r#"
+            .fun fn@{} type@"{t}"
+            .name string@"main"
+            .reg type@void
+                Call0 reg0 fn@helper ; defined below
+                Ret reg0
+            .end
+            .fun fn@new type@"{t}"
+            .name string@"helper"
+            .reg type@void
+            .reg type@String
+                String reg1 string@"new; string"
+                Ret reg0
+            .end
+            "#,
+            findex.0
+        );
+        let applied = Assembler::new(&mut code).apply(&text)?;
+        assert_eq!(code.functions.len(), nfunctions + 1);
+        let helper = code.function_by_name("helper").unwrap().findex;
+        assert_eq!(helper, RefFun(2));
+        assert_eq!(applied, vec![findex, helper]);
+        assert!(matches!(
+            code.main().ops[0],
+            Opcode::Call0 { fun, .. } if fun == helper
+        ));
+        assert!(code.strings.iter().any(|s| &**s == "new; string"));
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+//! Declarative patches of a bytecode, so mods can be distributed as text instead of modified binaries.
+//!
+//! ```text
+//! # Comments start with '#'
+//! function pack.Player.takeDamage
+//! # Replace the instructions 3 and 4
+//! replace 3..5
+//!     .reg type@i32
+//!     Int reg5 int@0
+//!     Mov reg2 reg5
+//! end
+//! insert 10
+//!     Call1 reg0 fn@Std.string reg1
+//!     # Jump to what was the instruction 20 before this edit
+//!     JFalse reg3 @20
+//! end
+//! nop 12..14
+//!
+//! int@4 = 9999
+//! float@2 = 0.5
+//! string@17 = "Patched !"
+//! string "A new string"
+//! ```
+//!
+//! Edits :
+//! - `function <name>` selects the function edited by the next instructions edits, by its qualified name, its name or
+//!   its findex
+//! - `replace <start>..<end>` and `insert <pos>` followed by instructions in the assembly syntax of the [crate] until
+//!   `end`. Registers declared with `.reg` are appended to the function. Labels can only be used inside the block,
+//!   `@<pos>` targets the instruction at `<pos>` in the function before the edit. Those jump offsets are computed
+//!   when the block is spliced, so they stay valid whatever the number of instructions of the block.
+//! - `nop <start>..<end>` replaces instructions with `Nop`
+//! - `int@<idx> = <value>`, `float@<idx> = <value>` and `string@<idx> = "<text>"` change a constant
+//! - `string "<text>"` adds a string to the pool
+//!
+//! Edits are applied in order, positions account for the previous edits of the function. Jumps over edited ranges are
+//! updated, see [Function::splice_ops].
+
+use std::ops::Range;
+
+use hlbc::opcodes::{Opcode, OperandKind};
+use hlbc::types::{Function, JumpTarget, RefFloat, RefFun, RefInt, RefString};
+use hlbc::{Bytecode, Error, ResolveMut, Result, Str};
+
+use crate::{error, find_function, strip_comment, tokens, unquote, Assembler};
+
+/// An edit of a [Patch]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Select the function edited by the next edits, by its qualified name, its name or its findex
+    Function(String),
+    /// Replace the instructions in the range with the assembled block
+    Replace(Range<usize>, String),
+    /// Insert the assembled block at a position
+    Insert(usize, String),
+    /// Replace the instructions in the range with `Nop`
+    Nop(Range<usize>),
+    Int(RefInt, i32),
+    Float(RefFloat, f64),
+    String(RefString, String),
+    /// Add a string to the pool
+    AddString(String),
+}
+
+/// A parsed patch, see the [module](self) documentation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    /// Edits with their line in the patch text
+    pub edits: Vec<(usize, Edit)>,
+}
+
+fn range(line: usize, s: &str) -> Result<Range<usize>> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| error(line, "expected a range <start>..<end>"))?;
+    let start = position(line, start)?;
+    let end = position(line, end)?;
+    if start > end {
+        return Err(error(line, format!("invalid range {start}..{end}")));
+    }
+    Ok(start..end)
+}
+
+fn position(line: usize, s: &str) -> Result<usize> {
+    s.trim()
+        .parse()
+        .map_err(|_| error(line, format!("invalid position '{s}'")))
+}
+
+fn index(line: usize, s: &str, prefix: &str) -> Result<usize> {
+    s.strip_prefix(prefix)
+        .and_then(|s| s.strip_prefix('@'))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| error(line, format!("expected {prefix}@<index>")))
+}
+
+fn string(line: usize, s: &str) -> Result<String> {
+    unquote(s.trim()).ok_or_else(|| error(line, format!("invalid string {s}")))
+}
+
+impl Patch {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut edits = Vec::new();
+        let mut lines = text.lines().enumerate().map(|(i, l)| (i + 1, l));
+        while let Some((i, line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let mut block = || -> Result<String> {
+                let mut block = String::new();
+                for (_, line) in lines.by_ref() {
+                    if line.trim() == "end" {
+                        return Ok(block);
+                    }
+                    block.push_str(line);
+                    block.push('\n');
+                }
+                Err(error(i, "missing end"))
+            };
+            let edit = match kind {
+                "function" if !rest.is_empty() => Edit::Function(rest.to_string()),
+                "replace" => Edit::Replace(range(i, rest)?, block()?),
+                "insert" => Edit::Insert(position(i, rest)?, block()?),
+                "nop" => Edit::Nop(range(i, rest)?),
+                "string" => Edit::AddString(string(i, rest)?),
+                _ => {
+                    let (constant, value) = line
+                        .split_once('=')
+                        .ok_or_else(|| error(i, format!("unknown edit '{kind}'")))?;
+                    let (constant, value) = (constant.trim(), value.trim());
+                    match constant.split_once('@').map(|(p, _)| p) {
+                        Some("int") => Edit::Int(
+                            RefInt(index(i, constant, "int")?),
+                            value
+                                .parse()
+                                .map_err(|_| error(i, format!("invalid int '{value}'")))?,
+                        ),
+                        Some("float") => Edit::Float(
+                            RefFloat(index(i, constant, "float")?),
+                            value
+                                .parse()
+                                .map_err(|_| error(i, format!("invalid float '{value}'")))?,
+                        ),
+                        Some("string") => Edit::String(
+                            RefString(index(i, constant, "string")?),
+                            string(i, value)?,
+                        ),
+                        _ => return Err(error(i, format!("unknown constant '{constant}'"))),
+                    }
+                }
+            };
+            edits.push((i, edit));
+        }
+        Ok(Self { edits })
+    }
+
+    /// Apply the edits in order. Edits before an error are kept.
+    pub fn apply(&self, code: &mut Bytecode) -> Result<()> {
+        let mut asm = Assembler::new(code);
+        let mut current = None;
+        for (line, edit) in &self.edits {
+            let line = *line;
+            match edit {
+                Edit::Function(name) => {
+                    let f = match name.parse() {
+                        Ok(findex) => Some(RefFun(findex)),
+                        Err(_) => find_function(asm.code, name),
+                    };
+                    current =
+                        f.filter(|f| f.0 < asm.code.findex_max() && f.as_fn(asm.code).is_some());
+                    if current.is_none() {
+                        return Err(invalid(line, format!("no function '{name}'")));
+                    }
+                }
+                Edit::Replace(range, block) => {
+                    let f = current.ok_or_else(|| invalid(line, "no function selected"))?;
+                    let (block, targets) = assemble_block(&mut asm, line, f, block)?;
+                    let f = asm.code.get_mut(f).unwrap();
+                    check_range(line, f, range)?;
+                    f.splice_ops_with_targets(range.clone(), block.ops, &targets)
+                        .map_err(|e| invalid(line, e))?;
+                    f.regs.extend(block.regs);
+                }
+                Edit::Insert(pos, block) => {
+                    let f = current.ok_or_else(|| invalid(line, "no function selected"))?;
+                    let (block, targets) = assemble_block(&mut asm, line, f, block)?;
+                    let f = asm.code.get_mut(f).unwrap();
+                    check_range(line, f, &(*pos..*pos))?;
+                    f.splice_ops_with_targets(*pos..*pos, block.ops, &targets)
+                        .map_err(|e| invalid(line, e))?;
+                    f.regs.extend(block.regs);
+                }
+                Edit::Nop(range) => {
+                    let f = current.ok_or_else(|| invalid(line, "no function selected"))?;
+                    let f = asm.code.get_mut(f).unwrap();
+                    check_range(line, f, range)?;
+                    f.patch_nops(range.clone());
+                }
+                Edit::Int(i, value) => {
+                    *asm.code
+                        .ints
+                        .get_mut(i.0)
+                        .ok_or_else(|| invalid(line, format!("no int@{}", i.0)))? = *value;
+                }
+                Edit::Float(i, value) => {
+                    *asm.code
+                        .floats
+                        .get_mut(i.0)
+                        .ok_or_else(|| invalid(line, format!("no float@{}", i.0)))? = *value;
+                }
+                Edit::String(i, value) => {
+                    *asm.code
+                        .strings
+                        .get_mut(i.0)
+                        .ok_or_else(|| invalid(line, format!("no string@{}", i.0)))? =
+                        Str::from(value.as_str());
+                }
+                Edit::AddString(value) => {
+                    asm.code.intern_string(value.as_str());
+                }
+            }
+        }
+        asm.code.rebuild_indexes();
+        Ok(())
+    }
+}
+
+fn invalid(line: usize, msg: impl std::fmt::Display) -> Error {
+    Error::InvalidEdit(format!("line {line}: {msg}"))
+}
+
+fn check_range(line: usize, f: &Function, range: &Range<usize>) -> Result<()> {
+    if range.end > f.ops.len() {
+        return Err(invalid(
+            line,
+            format!(
+                "range {}..{} out of fn@{} with {} instructions",
+                range.start,
+                range.end,
+                f.findex.0,
+                f.ops.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Jump targets of a block as (instruction, index in [Opcode::offsets_mut], target)
+type BlockTargets = Vec<(usize, usize, JumpTarget)>;
+
+/// Replace the jump targets `@<pos>` of the instructions of a block with a zero offset. Returns the rewritten block
+/// and its targets.
+fn block_targets(line: usize, block: &str) -> Result<(String, BlockTargets)> {
+    let mut out = String::with_capacity(block.len());
+    let mut targets = Vec::new();
+    let mut pos = 0;
+    for (i, l) in block.lines().enumerate() {
+        let tokens = tokens(strip_comment(l));
+        let descs = match tokens.split_first() {
+            Some((name, _)) if name.starts_with('.') || name.ends_with(':') => None,
+            Some((name, _)) => Opcode::from_name(name).map(|op| op.operand_descs()),
+            None => None,
+        };
+        // Directives, labels and invalid instructions are left to the assembler
+        let Some(descs) = descs else {
+            out.push_str(l);
+            out.push('\n');
+            continue;
+        };
+        let mut slot = 0;
+        let mut offset = |token: &str| -> Result<String> {
+            slot += 1;
+            let Some(target) = token.strip_prefix('@') else {
+                return Ok(token.to_string());
+            };
+            let target = target
+                .parse()
+                .map_err(|_| error(line + 1 + i, format!("invalid jump target '{token}'")))?;
+            targets.push((pos, slot - 1, JumpTarget::Function(target)));
+            Ok("0".to_string())
+        };
+        out.push_str(tokens[0]);
+        for (j, &token) in tokens[1..].iter().enumerate() {
+            out.push(' ');
+            match descs.get(j).map(|d| d.kind) {
+                Some(OperandKind::JumpOffset) => out.push_str(&offset(token)?),
+                Some(OperandKind::JumpOffsets) => {
+                    let inner = token.trim_start_matches('[').trim_end_matches(']');
+                    let offsets = inner
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|o| !o.is_empty())
+                        .map(&mut offset)
+                        .collect::<Result<Vec<_>>>()?;
+                    out.push_str(&format!("[{}]", offsets.join(", ")));
+                }
+                _ => out.push_str(token),
+            }
+        }
+        out.push('\n');
+        pos += 1;
+    }
+    Ok((out, targets))
+}
+
+/// Assemble the instructions of a block as a function, its lines keep their number in the patch text. Returns the
+/// function and the jump targets to compute when splicing it, see [block_targets].
+fn assemble_block(
+    asm: &mut Assembler,
+    line: usize,
+    f: RefFun,
+    block: &str,
+) -> Result<(Function, BlockTargets)> {
+    let (block, targets) = block_targets(line, block)?;
+    let text = format!(
+        "{}.fun fn@{} type@0\n{block}.end\n",
+        "\n".repeat(line - 1),
+        f.0
+    );
+    // The text holds a single function
+    Ok((asm.assemble(&text)?.remove(0), targets))
+}
+
+/// Parse and apply a patch, see the [module](self) documentation
+pub fn apply_patch(code: &mut Bytecode, text: &str) -> Result<()> {
+    Patch::parse(text)?.apply(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{JumpTarget, RefInt, RefString, Reg};
+
+    use crate::patch::{apply_patch, block_targets, Edit, Patch};
+    use crate::testing::program;
+
+    #[test]
+    fn test_parse() -> hlbc::Result<()> {
+        let patch = Patch::parse(
+            r#"
+            # comment
+            function main
+            replace 1..2
+                Nop
+            end
+            nop 0..1
+            int@0 = -5
+            string@1 = "a = b"
+            string "new"
+            "#,
+        )?;
+        let edits: Vec<_> = patch.edits.into_iter().map(|(_, e)| e).collect();
+        assert_eq!(
+            edits,
+            vec![
+                Edit::Function("main".to_string()),
+                Edit::Replace(1..2, "                Nop\n".to_string()),
+                Edit::Nop(0..1),
+                Edit::Int(RefInt(0), -5),
+                Edit::String(RefString(1), "a = b".to_string()),
+                Edit::AddString("new".to_string()),
+            ]
+        );
+        assert!(Patch::parse("replace 1..2\nNop").is_err());
+        assert!(Patch::parse("nop 2..1").is_err());
+        assert!(Patch::parse("what").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> hlbc::Result<()> {
+        let mut code = program();
+        let main = code.main();
+        let (findex, len, nregs) = (main.findex, main.ops.len(), main.regs.len());
+        apply_patch(
+            &mut code,
+            &format!(
+                r#"
+                function {}
+                insert 0
+                    .reg type@i32
+                    .reg type@String
+                    Int reg{nregs} int@0
+                    String reg{string_reg} string@"patched"
+                end
+                nop 0..1
+                int@0 = 1234
+                "#,
+                findex.0,
+                string_reg = nregs + 1
+            ),
+        )?;
+        let main = code.main();
+        assert_eq!(main.ops.len(), len + 2);
+        assert_eq!(main.regs.len(), nregs + 2);
+        assert!(matches!(main.ops[0], Opcode::Nop));
+        assert!(matches!(
+            main.ops[1],
+            Opcode::String { dst: Reg(4), ptr } if &*code[ptr] == "patched"
+        ));
+        assert!(matches!(main.ops[2], Opcode::String { dst: Reg(0), .. }));
+        assert_eq!(code.ints, [1234, 0]);
+
+        assert!(apply_patch(&mut code, "function not.a.Function").is_err());
+        assert!(apply_patch(&mut code, "function main\nnop 0..100000").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_jump_targets() -> hlbc::Result<()> {
+        let (block, targets) = block_targets(
+            1,
+            "L0:\n  JAlways @3 ; comment\n  Switch reg0 [@1, L0, 2] @4\n  JAlways L0\n",
+        )?;
+        assert_eq!(
+            block,
+            "L0:\nJAlways 0\nSwitch reg0 [0, L0, 2] 0\nJAlways L0\n"
+        );
+        assert_eq!(
+            targets,
+            vec![
+                (0, 0, JumpTarget::Function(3)),
+                (1, 0, JumpTarget::Function(1)),
+                (1, 3, JumpTarget::Function(4)),
+            ]
+        );
+        assert!(block_targets(1, "JAlways @x").is_err());
+
+        let mut code = program();
+        let main = code.main();
+        let (findex, len) = (main.findex, main.ops.len());
+        apply_patch(
+            &mut code,
+            &format!(
+                "function {}\ninsert 0\n    JAlways @1\n    Nop\nend\n",
+                findex.0
+            ),
+        )?;
+        let main = code.main();
+        assert_eq!(main.ops.len(), len + 2);
+        assert_eq!(main.ops[0].jump_targets(0), vec![3]);
+        assert!(matches!(main.ops[3], Opcode::Int { dst: Reg(1), .. }));
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! A small bytecode built in memory for the tests, in place of a file compiled from Haxe sources.
+
+use hlbc::testing::{bytecode, class, fun, obj};
+use hlbc::types::Type;
+use hlbc::Bytecode;
+
+/// A program with the `String` class and static functions :
+///
+/// ```haxe
+/// class Main {
+///     static function main() {
+///         var s = "hello";
+///         twice(3);
+///     }
+///     static function twice(i:Int) return if (i > 0) i + i else 0;
+/// }
+/// ```
+pub(crate) fn program() -> Bytecode {
+    let mut code = bytecode(
+        &[
+            "", "String", "bytes", "length", "hello", "$Main", "main", "twice",
+        ],
+        vec![
+            Type::Void,
+            Type::I32,
+            Type::Bytes,
+            fun(&[], 0),
+            fun(&[1], 1),
+            Type::Obj(obj(1, None, &[(2, 2), (3, 1)], &[])),
+            class(5, &[(6, 3, 0), (7, 4, 1)]),
+        ],
+        "
+        .fun fn@0 type@3
+        .reg type@2
+        .reg type@1
+        .reg type@0
+            String reg0 string@4
+            Int reg1 int@0
+            Call1 reg1 fn@1 reg1
+            Ret reg2
+        .end
+        .fun fn@1 type@4
+        .reg type@1
+        .reg type@1
+            Int reg1 int@1
+            JSLte reg0 reg1 else
+            Add reg1 reg0 reg0
+        else:
+            Ret reg1
+        .end",
+    );
+    code.ints = vec![3, 0];
+    code
+}
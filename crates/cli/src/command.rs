@@ -4,6 +4,7 @@ use chumsky::prelude::*;
 use chumsky::text::*;
 pub use chumsky::Parser;
 
+use hlbc::types::RefFun;
 use hlbc::Str;
 
 pub type IndexRange = Range<usize>;
@@ -17,10 +18,23 @@ pub enum FileOrIndex {
 #[derive(Debug, Clone)]
 pub enum ElementRef {
     String(usize),
+    Int(usize),
+    Float(usize),
     Global(usize),
+    Type(usize),
+    /// Type and field index
+    Field(usize, usize),
     Fn(usize),
 }
 
+/// Argument of a function to evaluate
+#[derive(Debug, Clone)]
+pub enum EvalArg {
+    Int(i32),
+    /// Index in the strings pool
+    String(usize),
+}
+
 #[derive(Debug, Clone)]
 pub enum Command {
     /// Exit the application
@@ -54,9 +68,55 @@ pub enum Command {
     FileOf(usize),
     SaveTo(Str),
     Callgraph(usize, usize),
+    /// Dot control flow graph of a function
+    Cfg(usize),
     RefTo(ElementRef),
     DecompType(usize),
     Decomp(usize),
+    /// Check the bytecode for invalid references
+    Validate,
+    /// List calls to trace, filtered by the traced string
+    Traces(Str),
+    /// Suggest names for the instantiations of generic classes
+    Generics,
+    /// Show how a string can reach a sink function
+    Provenance(usize, usize),
+    /// List recursive and mutually recursive functions
+    Recursion,
+    /// Functions unreachable from the entrypoint
+    Dead,
+    /// Pool entries never referenced
+    Unused,
+    /// Every use of a type
+    TypeUsage(usize),
+    /// Natives grouped by library
+    Natives,
+    /// Largest classes and files of the serialized bytecode
+    Sizes,
+    /// Opcode frequencies and pool statistics
+    Stats,
+    /// Hottest functions from a profiler dump or folded stacks
+    Profile(Str),
+    /// Generate signatures of the functions to a file
+    SigSave(Str),
+    /// Identify functions with signatures from a file
+    SigApply(Str),
+    /// Save the disassembly as a hyperlinked HTML page
+    Html(Str),
+    /// Write the disassembly to a directory tree mirroring the source files
+    Dump(Str),
+    /// Evaluate a function with constant arguments
+    Eval(usize, Vec<EvalArg>),
+}
+
+impl Command {
+    /// Function processed by the command
+    pub fn function(&self) -> Option<RefFun> {
+        match *self {
+            Command::Cfg(f) | Command::Decomp(f) | Command::Eval(f, _) => Some(RefFun(f)),
+            _ => None,
+        }
+    }
 }
 
 // Used a default max values for index ranges
@@ -122,8 +182,46 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
         cmd!("wiki" => Wiki),
     ));
 
+    let analysis_cmds = choice((
+        cmd!("validate" => Validate),
+        cmd!("traces"; string.clone() => Traces),
+        cmd!("generics" => Generics),
+        cmd!("provenance")
+            .ignore_then(num())
+            .then(num().padded())
+            .map(|(s, sink)| Provenance(s, sink)),
+        cmd!("recursion" => Recursion),
+        cmd!("dead" => Dead),
+        cmd!("unused" => Unused),
+        cmd!("usage"; num() => TypeUsage),
+        cmd!("natives" => Natives),
+        cmd!("sizes" => Sizes),
+        cmd!("stats" => Stats),
+        cmd!("profile"; string.clone() => Profile),
+        cmd!("sigsave"; string.clone() => SigSave),
+        cmd!("sigapply"; string.clone() => SigApply),
+        cmd!("html"; string.clone() => Html),
+        cmd!("dump"; string.clone() => Dump),
+        cmd!("cfg"; num() => Cfg),
+        cmd!("eval")
+            .ignore_then(num())
+            .then(
+                choice((
+                    just("string@").ignore_then(num()).map(EvalArg::String),
+                    just('-').or_not().then(num()).map(|(neg, n)| {
+                        let n = n as i32;
+                        EvalArg::Int(if neg.is_some() { n.wrapping_neg() } else { n })
+                    }),
+                ))
+                .padded()
+                .repeated(),
+            )
+            .map(|(f, args)| Eval(f, args)),
+    ));
+
     choice((
         core_cmds,
+        analysis_cmds,
         cmd!("info" => Info),
         cmd!("entrypoint" => Entrypoint),
         cmd!("int", "i"; index_range(ctx.int_max) => Int),
@@ -155,7 +253,15 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
         cmd!("refto")
             .ignore_then(choice((
                 just("string@").ignore_then(num()).map(ElementRef::String),
+                just("int@").ignore_then(num()).map(ElementRef::Int),
+                just("float@").ignore_then(num()).map(ElementRef::Float),
                 just("global@").ignore_then(num()).map(ElementRef::Global),
+                just("type@").ignore_then(num()).map(ElementRef::Type),
+                just("field@")
+                    .ignore_then(num())
+                    .then_ignore(just('.'))
+                    .then(num())
+                    .map(|(t, f)| ElementRef::Field(t, f)),
                 just("fn@").ignore_then(num()).map(ElementRef::Fn),
             )))
             .map(RefTo),
@@ -272,6 +378,14 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_command_optional_str() {
+        let parsed = parse_command(&ParseContext::default(), "traces");
+        assert!(matches!(parsed, Ok(Command::Traces(s)) if s.is_empty()));
+        let parsed = parse_command(&ParseContext::default(), "traces error");
+        assert!(matches!(parsed, Ok(Command::Traces(s)) if s == "error"));
+    }
+
     #[test]
     fn test_file_or_index() {
         let parsed = parse_command(&ParseContext::default(), "infile 226");
@@ -0,0 +1,166 @@
+pub use hlbc_frontend::i18n::{lang, set_lang, Lang};
+
+/// Get the messages for the current language.
+pub fn tr() -> &'static Messages {
+    lang().pick(&EN, &FR)
+}
+
+/// Every user facing message of the cli
+pub struct Messages {
+    // Only read when the feature is disabled
+    #[cfg_attr(feature = "watch", allow(dead_code))]
+    pub no_watch_feature: &'static str,
+    pub compiling: &'static str,
+    pub loaded: &'static str,
+    pub watching: &'static str,
+    pub error_watching: &'static str,
+    pub no_debug_info: &'static str,
+    pub help: &'static str,
+    pub no_opcode: &'static str,
+    pub example: &'static str,
+    pub unknown: &'static str,
+    pub functions_in_file: &'static str,
+    pub file_not_found: &'static str,
+    pub inlined: &'static str,
+    #[cfg_attr(feature = "graph", allow(dead_code))]
+    pub no_graph_feature: &'static str,
+    pub not_an_obj: &'static str,
+    pub problems_found: &'static str,
+    pub no_provenance: &'static str,
+}
+
+static EN: Messages = Messages {
+    no_watch_feature: "The program was not compiled with the 'watch' feature enabled.",
+    compiling: "Compiling haxe source ...",
+    loaded: "Loaded !",
+    watching: "Watching file",
+    error_watching: "Error while watching :",
+    no_debug_info: "No debug info in this binary",
+    help: r#"Commands :
+exit                         | Exit hlbc-cli
+help                         | This message
+explain     <opcode>         | Get information about an opcode
+wiki                         | Open the bytecode wiki in a browser
+info                         | General information about the bytecode
+entrypoint                   | Get the bytecode entrypoint
+i,int       <idx>            | Get the int at index
+f,float     <idx>            | Get the float at index
+s,string    <idx>            | Get the string at index
+sstr        <str>            | Find a string
+file,debugfile <idx>         | Get the debug file name at index
+sfile       <str>            | Find the debug file named
+t,type      <idx>            | Get the type at index
+g,global    <idx>            | Get global at index
+c,constant  <idx>            | Get constant at index
+n,native    <idx>            | Get native at index
+fnh         <findex>         | Get header of function at index
+fn          <findex>         | Get a function by findex
+fnn,fnamed  <str>            | Get a function by name or path (pack.Class.method)
+sfn         <str>            | Find a function by name
+infile      <idx|str>        | Find functions in file
+fileof      <findex>         | Get the file where findex is defined
+refto       <any@idx>        | Find references to a given bytecode element
+saveto      <filename>       | Serialize the bytecode to a file
+callgraph   <findex> <depth> | Create a dot call graph from a function and a max depth
+cfg         <findex>         | Create a dot control flow graph of a function
+decomp      <findex>         | Decompile a function
+decompt     <idx>            | Decompile a type
+validate                     | Check the bytecode for invalid references and instruction types
+traces      [str]            | List calls to trace, optionally filtered by message
+generics                     | Suggest names for generic classes instantiations (renames format)
+provenance  <idx> <findex>   | Show how a string can reach a function (e.g. a native drawing text)
+recursion                    | List recursive and mutually recursive functions
+dead                         | List functions unreachable from the entrypoint
+unused                       | List strings, constants, globals, natives and types never referenced
+usage       <idx>            | Find every use of a type (registers, fields, globals, instructions)
+natives                      | List natives by library, flagging the referenced non standard ones
+sizes                        | Show the largest classes and source files once serialized
+stats                        | Show opcode frequencies and statistics about functions, strings and types
+profile     <filename>       | Show the hottest functions from hlprofile.dump or folded stacks
+sigsave     <filename>       | Generate signatures of the functions to identify them in another bytecode
+sigapply    <filename>       | Identify functions with signatures (renames format)
+html        <filename>       | Save the types, functions and strings as a hyperlinked HTML page
+dump        <directory>      | Write the disassembly as one .hlasm file per source file
+eval        <findex> [args]  | Evaluate a function with int or string@idx arguments
+
+Remember you can use the range notation in place of an index to navigate through data : a..b
+This is the same range notation as Rust and is supported with most commands."#,
+    no_opcode: "No opcode named",
+    example: "Example",
+    unknown: "unknown",
+    functions_in_file: "Functions in file",
+    file_not_found: "File not found :",
+    inlined: "(inlined)",
+    no_graph_feature: "hlbc-cli has been built without graph support. Build with feature 'graph' to enable callgraph generation",
+    not_an_obj: "is not an obj",
+    problems_found: "problem(s) found",
+    no_provenance: "This string never reaches this function",
+};
+
+static FR: Messages = Messages {
+    no_watch_feature: "Le programme n'a pas été compilé avec la fonctionnalité 'watch'.",
+    compiling: "Compilation des sources haxe ...",
+    loaded: "Chargé !",
+    watching: "Surveillance du fichier",
+    error_watching: "Erreur lors de la surveillance :",
+    no_debug_info: "Aucune information de debug dans ce binaire",
+    help: r#"Commandes :
+exit                         | Quitter hlbc-cli
+help                         | Ce message
+explain     <opcode>         | Informations sur un opcode
+wiki                         | Ouvrir le wiki du bytecode dans un navigateur
+info                         | Informations générales sur le bytecode
+entrypoint                   | Point d'entrée du bytecode
+i,int       <idx>            | Entier à l'index
+f,float     <idx>            | Flottant à l'index
+s,string    <idx>            | Chaîne à l'index
+sstr        <str>            | Chercher une chaîne
+file,debugfile <idx>         | Nom du fichier de debug à l'index
+sfile       <str>            | Chercher un fichier de debug par nom
+t,type      <idx>            | Type à l'index
+g,global    <idx>            | Globale à l'index
+c,constant  <idx>            | Constante à l'index
+n,native    <idx>            | Native à l'index
+fnh         <findex>         | En-tête de la fonction à l'index
+fn          <findex>         | Fonction par findex
+fnn,fnamed  <str>            | Fonction par nom ou chemin (pack.Class.method)
+sfn         <str>            | Chercher une fonction par nom
+infile      <idx|str>        | Fonctions définies dans un fichier
+fileof      <findex>         | Fichier où la fonction est définie
+refto       <any@idx>        | Références vers un élément du bytecode
+saveto      <filename>       | Sérialiser le bytecode dans un fichier
+callgraph   <findex> <depth> | Graphe d'appels (dot) depuis une fonction jusqu'à une profondeur maximale
+cfg         <findex>         | Graphe de flot de contrôle (dot) d'une fonction
+decomp      <findex>         | Décompiler une fonction
+decompt     <idx>            | Décompiler un type
+validate                     | Vérifier les références invalides et les types des instructions du bytecode
+traces      [str]            | Lister les appels à trace, filtrés par message
+generics                     | Suggérer des noms pour les instances de classes génériques (format des renommages)
+provenance  <idx> <findex>   | Montrer comment une chaîne peut atteindre une fonction (ex. une native affichant du texte)
+recursion                    | Lister les fonctions récursives et mutuellement récursives
+dead                         | Lister les fonctions inaccessibles depuis le point d'entrée
+unused                       | Lister les chaînes, constantes, globales, natives et types jamais référencés
+usage       <idx>            | Trouver toutes les utilisations d'un type (registres, champs, globales, instructions)
+natives                      | Lister les natives par bibliothèque, en signalant celles non standard utilisées
+sizes                        | Afficher les classes et fichiers sources les plus volumineux une fois sérialisés
+stats                        | Afficher la fréquence des opcodes et des statistiques sur les fonctions, chaînes et types
+profile     <filename>       | Afficher les fonctions les plus coûteuses d'un hlprofile.dump ou de piles repliées
+sigsave     <filename>       | Générer les signatures des fonctions pour les identifier dans un autre bytecode
+sigapply    <filename>       | Identifier des fonctions avec des signatures (format des renommages)
+html        <filename>       | Enregistrer les types, fonctions et chaînes dans une page HTML avec des liens
+dump        <directory>      | Écrire le désassemblage avec un fichier .hlasm par fichier source
+eval        <findex> [args]  | Évaluer une fonction avec des arguments entiers ou string@idx
+
+La notation d'intervalle peut remplacer un index pour parcourir les données : a..b
+C'est la même notation qu'en Rust, elle est acceptée par la plupart des commandes."#,
+    no_opcode: "Aucun opcode nommé",
+    example: "Exemple",
+    unknown: "inconnu",
+    functions_in_file: "Fonctions du fichier",
+    file_not_found: "Fichier introuvable :",
+    inlined: "(code inliné)",
+    no_graph_feature: "hlbc-cli a été compilé sans le support des graphes. Compilez avec la fonctionnalité 'graph' pour générer des graphes d'appels",
+    not_an_obj: "n'est pas un obj",
+    problems_found: "problème(s) trouvé(s)",
+    no_provenance: "Cette chaîne n'atteint jamais cette fonction",
+};
@@ -1,6 +1,6 @@
+use std::cell::OnceCell;
 use std::fs;
-use std::io::{stdin, BufReader, BufWriter, Write};
-use std::iter::repeat;
+use std::io::{stdin, BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
@@ -9,15 +9,29 @@ use clap::Parser as ClapParser;
 use temp_dir::TempDir;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-use hlbc::fmt::EnhancedFmt;
+use hlbc::analysis::files::FileMap;
+use hlbc::analysis::usage::UsageType;
+use hlbc::analysis::xref::{Xref, XrefIndex};
+use hlbc::fmt::{AnsiFmt, AsmFmt, EnhancedFmt, HtmlFmt};
 use hlbc::opcodes::Opcode;
-use hlbc::types::{FunPtr, RefFun, RefGlobal, Type};
+use hlbc::path::PathTarget;
+use hlbc::summary::Section;
+use hlbc::types::{
+    ConstantDef, ConstantValue, FunPtr, RefField, RefFloat, RefFun, RefGlobal, RefInt, RefString,
+    RefType, Type,
+};
 use hlbc::*;
+use hlbc_frontend::crash;
 
-use crate::command::{commands_parser, Command, ElementRef, FileOrIndex, ParseContext, Parser};
+use crate::command::{
+    commands_parser, Command, ElementRef, EvalArg, FileOrIndex, ParseContext, Parser,
+};
+use crate::i18n::{tr, Lang};
 
 /// Command parser
 mod command;
+/// Translations of user facing messages
+mod i18n;
 
 #[derive(ClapParser, Debug)]
 #[clap(author, version, about)]
@@ -30,14 +44,30 @@ struct Args {
     /// Execute the command at startup
     #[clap(short, long)]
     command: Option<String>,
+    /// Language of the messages (en, fr). Detected from the environment by default.
+    #[clap(long)]
+    lang: Option<String>,
+    /// Write a crash report in the current directory if the program panics
+    #[clap(long)]
+    crash_report: bool,
+    /// Include a reduced bytecode with the function being processed in the crash report (implies --crash-report)
+    #[clap(long)]
+    crash_report_bytecode: bool,
 }
 
 fn main() -> anyhow::Result<()> {
     let args: Args = Args::parse();
 
+    i18n::set_lang(
+        args.lang
+            .as_deref()
+            .and_then(Lang::from_tag)
+            .unwrap_or_else(Lang::detect),
+    );
+
     #[cfg(not(feature = "watch"))]
     if args.watch.is_some() {
-        println!("The program was not compiled with the 'watch' feature enabled.");
+        println!("{}", tr().no_watch_feature);
         return Ok(());
     }
 
@@ -58,7 +88,7 @@ fn main() -> anyhow::Result<()> {
     let dir = TempDir::new()?;
     let file = if is_source {
         if tty {
-            print!("Compiling haxe source ... ");
+            print!("{} ", tr().compiling);
             stdout.flush()?;
         }
         let path = dir.child("bytecode.hl");
@@ -71,15 +101,19 @@ fn main() -> anyhow::Result<()> {
         args.file.clone()
     };
 
+    if args.crash_report || args.crash_report_bytecode {
+        crash::install(
+            concat!("hlbc-cli ", env!("CARGO_PKG_VERSION")),
+            args.crash_report_bytecode.then(|| file.clone()),
+        );
+    }
+
     let start = Instant::now();
 
-    let code = {
-        let mut r = BufReader::new(fs::File::open(&file)?);
-        Bytecode::deserialize(&mut r)?
-    };
+    let code = load(&file)?;
 
     if tty {
-        println!("Loaded ! ({} ms)", start.elapsed().as_millis());
+        println!("{} ({} ms)", tr().loaded, start.elapsed().as_millis());
     }
 
     let parse_ctx = ParseContext {
@@ -95,9 +129,11 @@ fn main() -> anyhow::Result<()> {
     };
 
     let parser = commands_parser(&parse_ctx);
+    // Cross references are indexed on the first command needing them
+    let xrefs = OnceCell::new();
 
     macro_rules! execute_commands {
-        ($code:expr, $commands:expr; $onexit:stmt) => {
+        ($code:expr, $xrefs:expr, $commands:expr; $onexit:stmt) => {
             for cmd in $commands {
                 match cmd {
                     #[allow(redundant_semicolons)]
@@ -105,7 +141,8 @@ fn main() -> anyhow::Result<()> {
                         $onexit;
                     }
                     cmd => {
-                        process_command(&mut stdout, $code, cmd)?;
+                        crash::set_context(format!("{cmd:?}"), cmd.function());
+                        process_command(&mut stdout, $code, $xrefs, cmd)?;
                     }
                 }
                 println!();
@@ -115,7 +152,7 @@ fn main() -> anyhow::Result<()> {
 
     // Execute the -c
     if let Some(initial_cmd) = args.command {
-        execute_commands!(&code, parser.parse(initial_cmd.as_str()).expect("Error while parsing command."); return Ok(()));
+        execute_commands!(&code, &xrefs, parser.parse(initial_cmd.as_str()).expect("Error while parsing command."); return Ok(()));
     }
 
     #[cfg(feature = "watch")]
@@ -133,11 +170,15 @@ fn main() -> anyhow::Result<()> {
             .watch(&args.file, RecursiveMode::NonRecursive)
             .expect("Can't watch file");
 
-        println!("Watching file '{}', command : {watch}", args.file.display());
+        println!(
+            "{} '{}', command : {watch}",
+            tr().watching,
+            args.file.display()
+        );
 
         let commands = parser.parse(watch.as_str()).expect("Can't parse command");
 
-        execute_commands!(&code, commands.clone(); return Ok(()));
+        execute_commands!(&code, &xrefs, commands.clone(); return Ok(()));
 
         'watch: loop {
             match rx.recv() {
@@ -147,20 +188,19 @@ fn main() -> anyhow::Result<()> {
                             compile(&args.file, &file)?;
                         }
 
-                        let code = {
-                            let mut r = BufReader::new(fs::File::open(&file)?);
-                            Bytecode::deserialize(&mut r)?
-                        };
+                        let code = load(&file)?;
 
-                        execute_commands!(&code, commands.clone(); break 'watch);
+                        // Built again on the new bytecode if needed
+                        let xrefs = OnceCell::new();
+                        execute_commands!(&code, &xrefs, commands.clone(); break 'watch);
                     }
                 }
                 Ok(Err(e)) => {
-                    println!("Error while watching : {e:?}");
+                    println!("{} {e:?}", tr().error_watching);
                     break;
                 }
                 Err(e) => {
-                    println!("Error while watching : {e}");
+                    println!("{} {e}", tr().error_watching);
                     break;
                 }
             }
@@ -181,14 +221,26 @@ fn main() -> anyhow::Result<()> {
         let commands = parser
             .parse(line.trim())
             .expect("Error while parsing command.");
-        execute_commands!(&code, commands; break 'main);
+        execute_commands!(&code, &xrefs, commands; break 'main);
     }
     Ok(())
 }
 
+/// Load a bytecode file or the bytecode embedded in an executable
+fn load(file: &Path) -> anyhow::Result<Bytecode> {
+    let mut r = BufReader::new(fs::File::open(file)?);
+    if r.fill_buf()?.starts_with(b"HLB") {
+        Ok(Bytecode::deserialize(&mut r)?)
+    } else {
+        // The first magic header of an executable may not be the bytecode
+        Ok(Bytecode::from_executable(file)?)
+    }
+}
+
 fn process_command(
     stdout: &mut StandardStream,
     code: &Bytecode,
+    xrefs: &OnceCell<XrefIndex>,
     cmd: Command,
 ) -> anyhow::Result<()> {
     macro_rules! print_i {
@@ -203,71 +255,46 @@ fn process_command(
         if let Some(debug_files) = &code.debug_files {
             Some(&debug_files[..])
         } else {
-            println!("No debug info in this binary");
+            println!("{}", tr().no_debug_info);
             None
         }
         .context("No debug info")
     }
 
+    // Colored disassembly, unless the output isn't a terminal or colors are disabled
+    let ansi = AnsiFmt::new(stdout.supports_color());
+
     match cmd {
         Command::Exit => unreachable!(),
         Command::Help => {
-            println!(
-                r#"Commands :
-exit                         | Exit hlbc-cli
-help                         | This message
-explain     <opcode>         | Get information about an opcode
-wiki                         | Open the bytecode wiki in a browser
-info                         | General information about the bytecode
-entrypoint                   | Get the bytecode entrypoint
-i,int       <idx>            | Get the int at index
-f,float     <idx>            | Get the float at index
-s,string    <idx>            | Get the string at index
-sstr        <str>            | Find a string
-file,debugfile <idx>         | Get the debug file name at index
-sfile       <str>            | Find the debug file named
-t,type      <idx>            | Get the type at index
-g,global    <idx>            | Get global at index
-c,constant  <idx>            | Get constant at index
-n,native    <idx>            | Get native at index
-fnh         <findex>         | Get header of function at index
-fn          <findex>         | Get a function by findex
-fnn,fnamed  <str>            | Get a function by name
-sfn         <str>            | Find a function by name
-infile      <idx|str>        | Find functions in file
-fileof      <findex>         | Get the file where findex is defined
-refto       <any@idx>        | Find references to a given bytecode element
-saveto      <filename>       | Serialize the bytecode to a file
-callgraph   <findex> <depth> | Create a dot call graph from a function and a max depth
-decomp      <findex>         | Decompile a function
-decompt     <idx>            | Decompile a type
-
-Remember you can use the range notation in place of an index to navigate through data : a..b
-This is the same range notation as Rust and is supported with most commands."#
-            );
+            println!("{}", tr().help);
         }
         Command::Explain(s) => {
             if let Some(o) = Opcode::from_name(&s) {
                 println!("{} :\n{}", o.name(), o.description());
-                println!("Example : {}", o.display(code, &code.functions[0], 0, 0));
+                println!(
+                    "{} : {}",
+                    tr().example,
+                    o.display(code, &code.functions[0], 0, 0)
+                );
             } else {
-                println!("No opcode named '{s}' exists.");
+                println!("{} '{s}'.", tr().no_opcode);
             }
         }
         Command::Wiki => webbrowser::open("https://github.com/Gui-Yom/hlbc/wiki")?,
         Command::Info => {
-            println!(
-                "version: {}\ndebug: {}\nnints: {}\nnfloats: {}\nnstrings: {}\nntypes: {}\nnnatives: {}\nnfunctions: {}\nnconstants: {}",
-                code.version,
-                code.debug_files.is_some(),
-                code.ints.len(),
-                code.floats.len(),
-                code.strings.len(),
-                code.types.len(),
-                code.natives.len(),
-                code.functions.len(),
-                code.constants.as_ref().map_or(0, |c| c.len())
-            );
+            let summary = code.summary();
+            println!("version: {}", summary.version);
+            println!("debug: {}", summary.debug);
+            println!("entrypoint: fn@{}", summary.entrypoint.0);
+            for s in &summary.sections {
+                if s.section == Section::Header {
+                    println!("{}: {} bytes", s.section.name(), s.size);
+                } else {
+                    println!("{}: {} ({} bytes)", s.section.name(), s.count, s.size);
+                }
+            }
+            println!("total: {} bytes", summary.size);
         }
         Command::Entrypoint => {
             println!("{}", code.entrypoint().display_header::<EnhancedFmt>(code));
@@ -374,12 +401,7 @@ This is the same range notation as Rust and is supported with most commands."#
         Command::Global(range) => {
             for i in range {
                 print_i!(i);
-                println!("{}", code.globals[i].display::<EnhancedFmt>(code));
-                if let Some(&cst) = code.globals_initializers.get(&RefGlobal(i)) {
-                    for init in &code.constants.as_ref().unwrap()[cst].fields {
-                        println!("    {}", init);
-                    }
-                }
+                println!("{}", RefGlobal(i).display_resolved(code));
             }
         }
         Command::Native(range) => {
@@ -391,15 +413,17 @@ This is the same range notation as Rust and is supported with most commands."#
         Command::Constant(range) => {
             for i in range {
                 print_i!(i);
-                println!("{:#?}", code.constants.as_ref().unwrap()[i]);
+                let c = &code.constants.as_ref().unwrap()[i];
+                println!("initializes global@{}", c.global.0);
+                print_constant(code, c);
             }
         }
         Command::FunctionHeader(range) => {
             for findex in range {
                 print_i!(findex);
                 match code.get(RefFun(findex)) {
-                    FunPtr::Fun(f) => println!("{}", f.display_header::<EnhancedFmt>(code)),
-                    FunPtr::Native(n) => println!("{}", n.display::<EnhancedFmt>(code)),
+                    FunPtr::Fun(f) => println!("{}", f.display_header_fmt(ansi, code)),
+                    FunPtr::Native(n) => println!("{}", n.display_fmt(ansi, code)),
                 }
             }
         }
@@ -407,16 +431,22 @@ This is the same range notation as Rust and is supported with most commands."#
             for findex in range {
                 print_i!(findex);
                 match code.get(RefFun(findex)) {
-                    FunPtr::Fun(f) => println!("{}", f.display::<EnhancedFmt>(code)),
-                    FunPtr::Native(n) => println!("{}", n.display::<EnhancedFmt>(code)),
+                    FunPtr::Fun(f) => println!("{}", f.display_fmt(ansi, code)),
+                    FunPtr::Native(n) => println!("{}", n.display_fmt(ansi, code)),
                 }
             }
         }
         Command::FunctionNamed(str) => {
-            if let Some(f) = code.function_by_name(&str) {
-                println!("{}", f.display::<EnhancedFmt>(code));
+            let f = code
+                .function_by_name(&str)
+                .or_else(|| match code.resolve_path(&str) {
+                    Some(PathTarget::Function(f)) => f.as_fn(code),
+                    _ => None,
+                });
+            if let Some(f) = f {
+                println!("{}", f.display_fmt(ansi, code));
             } else {
-                println!("unknown '{str}'");
+                println!("{} '{str}'", tr().unknown);
             }
         }
         Command::SearchFunction(str) => {
@@ -424,39 +454,34 @@ This is the same range notation as Rust and is supported with most commands."#
             if let Some(f) = code.function_by_name(&str) {
                 println!("{}", f.display_header::<EnhancedFmt>(code));
             } else {
-                println!("unknown");
+                println!("{}", tr().unknown);
             }
         }
         Command::InFile(foi) => {
             let debug_files = require_debug_info(code)?;
-            match foi {
-                FileOrIndex::File(str) => {
-                    if let Some(idx) = debug_files.iter().enumerate().find_map(|(i, d)| {
-                        if d == str {
-                            Some(i)
-                        } else {
-                            None
-                        }
-                    }) {
-                        println!("Functions in file@{idx} : {}", debug_files[idx]);
-                        for (i, f) in code.functions.iter().enumerate() {
-                            if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx {
-                                print_i!(i);
-                                println!("{}", f.display_header::<EnhancedFmt>(code));
-                            }
+            let idx = match &foi {
+                FileOrIndex::File(str) => debug_files.iter().position(|d| d == str),
+                FileOrIndex::Index(idx) => (*idx < debug_files.len()).then_some(*idx),
+            };
+            if let Some(idx) = idx {
+                println!("{}@{idx} : {}", tr().functions_in_file, debug_files[idx]);
+                // Functions with any instruction in the file, including inlined code
+                let map = FileMap::new(code);
+                let funs = map.functions_in(idx);
+                for (i, f) in code.functions.iter().enumerate() {
+                    if funs.binary_search(&f.findex).is_ok() {
+                        print_i!(i);
+                        print!("{}", f.display_header::<EnhancedFmt>(code));
+                        if f.file_index() != Some(idx) {
+                            print!(" {}", tr().inlined);
                         }
-                    } else {
-                        println!("File {str} not found !");
+                        println!();
                     }
                 }
-                FileOrIndex::Index(idx) => {
-                    println!("Functions in file@{idx} : {}", debug_files[idx]);
-                    for (i, f) in code.functions.iter().enumerate() {
-                        if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx {
-                            print_i!(i);
-                            println!("{}", f.display_header::<EnhancedFmt>(code));
-                        }
-                    }
+            } else {
+                match foi {
+                    FileOrIndex::File(str) => println!("{} {str}", tr().file_not_found),
+                    FileOrIndex::Index(idx) => println!("{} file@{idx}", tr().file_not_found),
                 }
             }
         }
@@ -464,12 +489,17 @@ This is the same range notation as Rust and is supported with most commands."#
             let debug_files = require_debug_info(code)?;
             match code.get(RefFun(idx)) {
                 FunPtr::Fun(f) => {
-                    let idx = f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0;
-                    println!(
-                        "{} is in file@{idx} : {}",
-                        f.display_header::<EnhancedFmt>(code),
-                        &debug_files[idx]
-                    );
+                    if let Some((idx, file)) = f
+                        .file_index()
+                        .and_then(|idx| Some((idx, debug_files.get(idx)?)))
+                    {
+                        println!(
+                            "{} is in file@{idx} : {file}",
+                            f.display_header::<EnhancedFmt>(code)
+                        );
+                    } else {
+                        println!("{}", tr().no_debug_info);
+                    }
                 }
                 FunPtr::Native(n) => {
                     println!(
@@ -495,92 +525,114 @@ This is the same range notation as Rust and is supported with most commands."#
 
             #[cfg(not(feature = "graph"))]
             {
-                println!("hlbc-cli has been built without graph support. Build with feature 'graph' to enable callgraph generation");
+                println!("{}", tr().no_graph_feature);
             }
         }
-        Command::RefTo(elem) => match elem {
-            ElementRef::String(idx) => {
-                println!(
-                    "Finding references to string@{idx} : {}\n",
-                    code.strings[idx]
-                );
-                if let Some(constants) = &code.constants {
-                    for (i, c) in constants.iter().enumerate() {
-                        if c.fields[0] == idx {
-                            println!(
-                                "constant@{i} expanding to global@{} (now also searching for global)",
-                                c.global.0
-                            );
-                            code.ops().for_each(|(f, (i, o))| match o {
-                                Opcode::GetGlobal { global, .. } => {
-                                    if *global == c.global {
-                                        println!(
-                                            "in {} at {i}: GetGlobal",
-                                            f.display_header::<EnhancedFmt>(code)
-                                        );
-                                    }
-                                }
-                                _ => {}
-                            });
-                            println!();
-                        }
-                    }
+        Command::Cfg(idx) => {
+            #[cfg(feature = "graph")]
+            {
+                use hlbc::analysis::graph::display_cfg;
+
+                if let Some(f) = RefFun(idx).as_fn(code) {
+                    println!("{}", display_cfg(code, f));
                 }
-                code.ops().for_each(|(f, (i, o))| match o {
-                    Opcode::String { ptr, .. } => {
-                        if ptr.0 == idx {
-                            println!("{} at {i}: String", f.display_header::<EnhancedFmt>(code));
-                        }
-                    }
-                    _ => {}
-                });
             }
-            ElementRef::Global(idx) => {
-                println!(
-                    "Finding references to global@{idx} : {}\n",
-                    code.globals[idx].display::<EnhancedFmt>(code)
-                );
-                if let Some(constants) = &code.constants {
-                    for (i, c) in constants.iter().enumerate() {
-                        if c.global.0 == idx {
-                            println!("constant@{i} : {:?}", c);
-                        }
+
+            #[cfg(not(feature = "graph"))]
+            {
+                println!("{}", tr().no_graph_feature);
+            }
+        }
+        Command::RefTo(elem) => {
+            let xrefs = xrefs.get_or_init(|| XrefIndex::new(code));
+            let print_xrefs = |refs: &[Xref]| {
+                for x in refs {
+                    if let Some(f) = x.fun.as_fn(code) {
+                        println!(
+                            "{} at {}: {}",
+                            f.display_header::<EnhancedFmt>(code),
+                            x.pos,
+                            f.ops[x.pos].name()
+                        );
                     }
                 }
-                println!();
-
-                code.ops().for_each(|(f, (i, o))| match o {
-                    Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
-                        if global.0 == idx {
-                            println!(
-                                "{} at {i}: {}",
-                                f.display_header::<EnhancedFmt>(code),
-                                o.name()
-                            );
+            };
+            let print_constant_refs = |value: &ConstantValue| {
+                for (g, field) in code.find_constants(|v| v == value) {
+                    println!(
+                        "field {} of the constant for global@{} (now also searching for global)",
+                        field.0, g.0
+                    );
+                    print_xrefs(xrefs.global(g));
+                    println!();
+                }
+            };
+            match elem {
+                ElementRef::String(idx) => {
+                    println!(
+                        "Finding references to string@{idx} : {}\n",
+                        code.strings[idx]
+                    );
+                    print_constant_refs(&ConstantValue::String(code.strings[idx].clone()));
+                    print_xrefs(xrefs.string(RefString(idx)));
+                }
+                ElementRef::Int(idx) => {
+                    println!("Finding references to int@{idx} : {}\n", code.ints[idx]);
+                    print_constant_refs(&ConstantValue::Int(code.ints[idx]));
+                    print_xrefs(xrefs.int(RefInt(idx)));
+                }
+                ElementRef::Float(idx) => {
+                    println!("Finding references to float@{idx} : {}\n", code.floats[idx]);
+                    print_constant_refs(&ConstantValue::Float(code.floats[idx]));
+                    print_xrefs(xrefs.float(RefFloat(idx)));
+                }
+                ElementRef::Global(idx) => {
+                    println!(
+                        "Finding references to global@{idx} : {}\n",
+                        code.globals[idx].display::<EnhancedFmt>(code)
+                    );
+                    if let Some(constants) = &code.constants {
+                        for (i, c) in constants.iter().enumerate() {
+                            if c.global.0 == idx {
+                                println!("constant@{i} :");
+                                print_constant(code, c);
+                            }
                         }
                     }
-                    _ => {}
-                });
-            }
-            ElementRef::Fn(idx) => {
-                println!(
-                    "Finding references to fn@{idx} : {}\n",
-                    RefFun(idx).display_header::<EnhancedFmt>(code)
-                );
-                code.functions
-                    .iter()
-                    .flat_map(|f| repeat(f).zip(f.find_fun_refs()))
-                    .for_each(|(f, (i, o, fun))| {
-                        if fun.0 == idx {
-                            println!(
-                                "{} at {i}: {}",
-                                f.display_header::<EnhancedFmt>(code),
-                                o.name()
-                            );
-                        }
-                    });
+                    println!();
+                    print_xrefs(xrefs.global(RefGlobal(idx)));
+                }
+                ElementRef::Type(idx) => {
+                    println!(
+                        "Finding references to type@{idx} : {}\n",
+                        RefType(idx).display::<EnhancedFmt>(code)
+                    );
+                    print_xrefs(xrefs.ty(RefType(idx)));
+                }
+                ElementRef::Field(t, field) => {
+                    let name = match &code.types[t] {
+                        Type::Virtual { fields } => fields.get(field).map(|f| code[f.name].clone()),
+                        ty => ty
+                            .get_type_obj()
+                            .and_then(|obj| obj.fields.get(field))
+                            .map(|f| code[f.name].clone()),
+                    };
+                    println!(
+                        "Finding references to field@{t}.{field} : {}.{}\n",
+                        RefType(t).display::<EnhancedFmt>(code),
+                        name.unwrap_or_default()
+                    );
+                    print_xrefs(xrefs.field(RefType(t), RefField(field)));
+                }
+                ElementRef::Fn(idx) => {
+                    println!(
+                        "Finding references to fn@{idx} : {}\n",
+                        RefFun(idx).display_header::<EnhancedFmt>(code)
+                    );
+                    print_xrefs(xrefs.fun(RefFun(idx)));
+                }
             }
-        },
+        }
         Command::Decomp(idx) => {
             if let Some(fun) = RefFun(idx).as_fn(code) {
                 println!(
@@ -601,13 +653,324 @@ This is the same range notation as Rust and is supported with most commands."#
                             .display(code, &hlbc_decompiler::fmt::FormatOptions::new(2))
                     );
                 }
-                _ => println!("Type {idx} is not an obj"),
+                _ => println!("Type {idx} {}", tr().not_an_obj),
+            }
+        }
+        Command::Validate => {
+            let mut diagnostics = code.validate();
+            diagnostics.extend(code.type_check());
+            for d in &diagnostics {
+                println!("{d}");
+            }
+            println!("{} {}", diagnostics.len(), tr().problems_found);
+        }
+        Command::Generics => {
+            // Printed in the renames sidecar format so it can be edited and saved next to the bytecode
+            for group in analysis::generics::find_generic_instances(code) {
+                println!("# {}", group.base);
+                for (ty, name) in group.suggested_names(code) {
+                    if let Some(obj) = code[ty].get_type_obj() {
+                        println!("type {} {name}", obj.name.0);
+                    }
+                }
+            }
+        }
+        Command::Provenance(s, sink) => {
+            let roots =
+                analysis::provenance::string_provenance(code, RefString(s), &[RefFun(sink)], 8);
+            if roots.is_empty() {
+                println!("{}", tr().no_provenance);
+            }
+            for root in &roots {
+                print_provenance(code, root, 0);
+            }
+        }
+        Command::Recursion => {
+            let report = analysis::recursion::find_recursion(code);
+            for f in &report.self_recursive {
+                println!("{}@{}", f.name(code), f.0);
+            }
+            for scc in &report.mutual {
+                println!(
+                    "{}",
+                    scc.iter()
+                        .map(|f| format!("{}@{}", f.name(code), f.0))
+                        .collect::<Vec<_>>()
+                        .join(" <-> ")
+                );
+            }
+        }
+        Command::Dead => {
+            let dead = analysis::dead::dead_functions(code);
+            for f in &dead {
+                println!("{}", f.display_header::<EnhancedFmt>(code));
+            }
+            println!("{} / {}", dead.len(), code.findex_max());
+        }
+        Command::Unused => {
+            let unused = analysis::unused::UnusedPools::new(code)?;
+            for &s in &unused.strings {
+                println!("string@{} : {}", s.0, code[s]);
+            }
+            for &i in &unused.ints {
+                println!("int@{} : {}", i.0, code[i]);
+            }
+            for &f in &unused.floats {
+                println!("float@{} : {}", f.0, code[f]);
+            }
+            for &g in &unused.globals {
+                println!("global@{} : {}", g.0, code[g].display::<EnhancedFmt>(code));
+            }
+            for &n in &unused.natives {
+                println!("{}", n.display_header::<EnhancedFmt>(code));
+            }
+            for &t in &unused.types {
+                println!("type@{} : {}", t.0, t.display::<EnhancedFmt>(code));
+            }
+            println!(
+                "{} strings, {} ints, {} floats, {} globals, {} natives, {} types",
+                unused.strings.len(),
+                unused.ints.len(),
+                unused.floats.len(),
+                unused.globals.len(),
+                unused.natives.len(),
+                unused.types.len()
+            );
+        }
+        Command::TypeUsage(idx) => {
+            let Some(usages) = analysis::usage::usage_report(code).types.get(idx).cloned() else {
+                println!("{} type@{idx}", tr().unknown);
+                return Ok(());
+            };
+            println!(
+                "Usages of type@{idx} : {}\n",
+                RefType(idx).display::<EnhancedFmt>(code)
+            );
+            let fun = |f: RefFun| f.display_header::<EnhancedFmt>(code).to_string();
+            for usage in usages {
+                match usage {
+                    UsageType::Argument(t) => {
+                        println!("argument of {}", t.display::<EnhancedFmt>(code))
+                    }
+                    UsageType::Return(t) => {
+                        println!("return type of {}", t.display::<EnhancedFmt>(code))
+                    }
+                    UsageType::Field(t, i) => {
+                        println!("field {i} of {}", t.display::<EnhancedFmt>(code))
+                    }
+                    UsageType::EnumVariant(t, c, i) => println!(
+                        "field {i} of construct {} of {}",
+                        c.0,
+                        t.display::<EnhancedFmt>(code)
+                    ),
+                    UsageType::Function(f) => println!("type of {}", fun(f)),
+                    UsageType::Register(f, reg) => println!("register {reg} in {}", fun(f)),
+                    UsageType::Global(g) => println!("global@{}", g.0),
+                    UsageType::Instruction(f, pos) => {
+                        let op = f.as_fn(code).and_then(|f| f.ops.get(pos));
+                        println!("{} at {pos}: {}", fun(f), op.map_or("", |op| op.name()))
+                    }
+                }
+            }
+        }
+        Command::Natives => {
+            let catalog = code.native_catalog();
+            print!("{}", catalog.render(code));
+            let xrefs = xrefs.get_or_init(|| XrefIndex::new(code));
+            let referenced = catalog.referenced_non_standard(xrefs);
+            if !referenced.is_empty() {
+                println!("\nReferenced natives from non standard libraries :");
+                for n in referenced {
+                    println!("{}", n.display_header::<EnhancedFmt>(code));
+                }
+            }
+        }
+        Command::Sizes => {
+            let breakdown = code.size_breakdown()?;
+            println!("Classes :");
+            for (t, size) in breakdown.largest_classes().into_iter().take(30) {
+                println!("{size:>10} {}", t.display::<EnhancedFmt>(code));
+            }
+            println!("{:>10} (functions without class)", breakdown.orphans);
+            println!("\nFiles :");
+            for (file, size) in breakdown.largest_files().into_iter().take(30) {
+                println!("{size:>10} {file}");
+            }
+        }
+        Command::Stats => {
+            let stats = code.stats();
+            println!("{} instructions, most used :", stats.total_ops);
+            for (name, count) in stats.most_used_opcodes().into_iter().take(20) {
+                println!(
+                    "{count:>10} {:>6.2}% {name}",
+                    count as f32 * 100.0 / stats.total_ops as f32
+                );
+            }
+            for (what, d) in [
+                ("instructions per function", &stats.ops),
+                ("registers per function", &stats.registers),
+                ("string length", &stats.strings),
+            ] {
+                println!(
+                    "\n{what} : min {} max {} mean {:.2} median {}",
+                    d.min, d.max, d.mean, d.median
+                );
+                for (bucket, count) in &d.histogram {
+                    println!("{count:>10} <= {bucket}");
+                }
+            }
+            println!("\nTypes :");
+            for (kind, count) in &stats.types {
+                println!("{count:>10} {kind}");
+            }
+        }
+        Command::Profile(file) => {
+            use analysis::profile::ProfileData;
+
+            let data = fs::read(&*file)?;
+            let profile = if data.starts_with(b"PROF") {
+                ProfileData::parse_dump(code, data.as_slice())?
+            } else {
+                ProfileData::parse_folded(code, &String::from_utf8_lossy(&data))?
+            };
+            // Self and total samples
+            println!("{} samples, self / total :", profile.samples);
+            for (f, _) in profile.hottest().into_iter().take(30) {
+                println!(
+                    "{:>6.2}% {:>6.2}% {}",
+                    profile.hotness(f) * 100.0,
+                    profile.total_count(f) as f32 * 100.0 / profile.samples as f32,
+                    f.display_header::<EnhancedFmt>(code)
+                );
+            }
+            let unresolved: u64 = profile.unresolved.values().sum();
+            if unresolved > 0 {
+                println!("{unresolved} samples in unresolved frames");
+            }
+        }
+        Command::SigSave(file) => {
+            let sigs = analysis::signatures::SignatureSet::generate(code, |_| true);
+            sigs.save(&*file)?;
+            println!("{} signatures", sigs.len());
+        }
+        Command::SigApply(file) => {
+            let sigs = analysis::signatures::SignatureSet::load(&*file)?;
+            // Printed in the renames sidecar format so it can be edited and saved next to the bytecode
+            let renames = rename::Renames::default();
+            sigs.apply(code, &renames);
+            print!("{}", renames.to_text());
+        }
+        Command::Html(file) => {
+            fs::write(&*file, HtmlFmt::document(code).to_string())?;
+        }
+        Command::Dump(dir) => {
+            let files = hlbc::fmt::tree::write_tree(code, &*dir, &AsmFmt::new(true))?;
+            println!("Wrote {} files to {dir}", files.len());
+        }
+        Command::Eval(f, args) => {
+            let args: Vec<_> = args
+                .into_iter()
+                .map(|arg| match arg {
+                    EvalArg::Int(i) => analysis::eval::Value::Int(i),
+                    EvalArg::String(s) => {
+                        analysis::eval::Value::String(code[RefString(s)].to_string())
+                    }
+                })
+                .collect();
+            match analysis::eval::Evaluator::new(code).eval(RefFun(f), &args) {
+                Ok(value) => println!("{value}"),
+                Err(stop) => println!("{stop}"),
+            }
+        }
+        Command::Traces(filter) => {
+            for trace in analysis::trace::find_traces(code) {
+                let msg = trace.msg.map(|s| code[s].clone());
+                if !filter.is_empty() && !msg.as_ref().is_some_and(|m| m.contains(&*filter)) {
+                    continue;
+                }
+                println!(
+                    "{}@{}:{} {} ({}:{})",
+                    trace.fun.name(code),
+                    trace.fun.0,
+                    trace.pos,
+                    msg.map(|m| format!("{m:?}"))
+                        .unwrap_or_else(|| tr().unknown.to_owned()),
+                    trace.file.map_or(tr().unknown, |f| &*code[f]),
+                    trace
+                        .line
+                        .map(|l| code[l].to_string())
+                        .unwrap_or_else(|| tr().unknown.to_owned()),
+                );
             }
         }
     }
     Ok(())
 }
 
+/// Print a provenance tree, one step per line
+/// Print the fields of a constant with their value
+fn print_constant(code: &Bytecode, c: &ConstantDef) {
+    let Some(obj) = c.obj(code) else {
+        return;
+    };
+    for (f, value) in obj.fields.iter().zip(c.values(code)) {
+        println!("    {}: {value}", f.name(code));
+    }
+}
+
+fn print_provenance(code: &Bytecode, node: &analysis::provenance::ProvenanceNode, depth: usize) {
+    use analysis::provenance::Flow;
+
+    let indent = "  ".repeat(depth);
+    let at = |fun: RefFun, pos: usize| format!("{}@{}:{pos}", fun.name(code), fun.0);
+    match node.flow {
+        Flow::Constant { fun, pos } => println!("{indent}loaded at {}", at(fun, pos)),
+        Flow::GlobalConstant { global, field } => {
+            println!("{indent}constant global@{} field {field}", global.0)
+        }
+        Flow::Argument {
+            fun,
+            pos,
+            callee,
+            arg,
+        } => println!(
+            "{indent}passed at {} to {}@{} (arg {arg})",
+            at(fun, pos),
+            callee.name(code),
+            callee.0
+        ),
+        Flow::StoreGlobal { fun, pos, global } => {
+            println!("{indent}stored at {} to global@{}", at(fun, pos), global.0)
+        }
+        Flow::StoreField {
+            fun,
+            pos,
+            ty,
+            field,
+        } => println!(
+            "{indent}stored at {} to {}.{}",
+            at(fun, pos),
+            ty.display::<EnhancedFmt>(code),
+            field.0
+        ),
+        Flow::Load { fun, pos } => println!("{indent}read at {}", at(fun, pos)),
+        Flow::Sink {
+            fun,
+            pos,
+            sink,
+            arg,
+        } => println!(
+            "{indent}reaches {}@{} at {} (arg {arg})",
+            sink.name(code),
+            sink.0,
+            at(fun, pos)
+        ),
+    }
+    for child in &node.children {
+        print_provenance(code, child, depth + 1);
+    }
+}
+
 /// Compile a Haxe source file to Hashlink bytecode by directly calling the Haxe compiler.
 /// Requires having the haxe compiler in the `PATH`.
 fn compile(source: &Path, bytecode: &Path) -> anyhow::Result<()> {
@@ -314,7 +314,7 @@ mod tests {
         let ctx =
             Bytecode::deserialize(&mut Cursor::new(include_bytes!("../../../data/Branch.hl")))
                 .unwrap();
-        let f = &ctx.functions[*ctx.fnames.get("main").unwrap()];
+        let f = ctx.main();
         println!("{}", BasicBlocks::new(f).make_graph(false).display(&ctx, f));
     }
 
@@ -324,7 +324,7 @@ mod tests {
             "../../../data/BranchNested.hl"
         )))
         .unwrap();
-        let f = &ctx.functions[*ctx.fnames.get("main").unwrap()];
+        let f = ctx.main();
         println!("{}", BasicBlocks::new(f).make_graph(false).display(&ctx, f));
     }
 }
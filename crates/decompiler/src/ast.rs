@@ -12,6 +12,10 @@ pub struct SourceFile {
 #[derive(Debug)]
 pub struct Class {
     pub name: Str,
+    /// Fully qualified names of the types from other packages referenced by this class
+    pub imports: Vec<Str>,
+    /// Large virtual types used by the class, declared before it
+    pub typedefs: Vec<RefType>,
     pub parent: Option<Str>,
     pub fields: Vec<ClassField>,
     pub methods: Vec<Method>,
@@ -29,6 +33,7 @@ pub struct Method {
     pub fun: RefFun,
     pub static_: bool,
     pub dynamic: bool,
+    pub override_: bool,
     pub statements: Vec<Statement>,
 }
 
@@ -128,6 +133,8 @@ pub enum Expr {
     Anonymous(RefType, HashMap<RefField, Expr>),
     /// Array access : array\[index]
     Array(Box<Expr>, Box<Expr>),
+    /// Array literal : \[a, b, c]
+    ArrayInit(Vec<Expr>),
     /// Function call
     Call(Box<Call>),
     /// Constant value
@@ -139,6 +146,8 @@ pub enum Expr {
     EnumConstr(RefType, RefEnumConstruct, Vec<Expr>),
     /// Field access : obj.field
     Field(Box<Expr>, Str),
+    /// Map literal : \[k1 => v1, k2 => v2]
+    MapInit(Vec<(Expr, Expr)>),
     /// Function reference
     FunRef(RefFun),
     /// If/Else expression, both branches expressions types must unify (https://haxe.org/manual/expression-if.html)
@@ -151,6 +160,8 @@ pub enum Expr {
     },
     /// Operator
     Op(Operation),
+    /// Reference to a type by its name, for static accesses : `Math.abs`
+    TypeName(Str),
     // For when there should be something, but we don't known what
     Unknown(String),
     /// Variable identifier
@@ -282,10 +293,13 @@ pub enum Statement {
         /// Else clause if the vec isn't empty
         else_: Vec<Statement>,
     },
+    /// Switch statement, there is no fallthrough between cases
     Switch {
         arg: Expr,
+        /// Default case if the vec isn't empty
         default: Vec<Statement>,
-        cases: Vec<(Expr, Vec<Statement>)>,
+        /// Cases with their patterns, in instruction order
+        cases: Vec<(Vec<Expr>, Vec<Statement>)>,
     },
     /// While statement
     While {
@@ -295,15 +309,25 @@ pub enum Statement {
     Break,
     Continue,
     Throw(Expr),
+    /// Try/Catch statement
     Try {
         stmts: Vec<Statement>,
-    },
-    Catch {
-        stmts: Vec<Statement>,
+        /// Catch clauses, in the order they are tested
+        catches: Vec<CatchClause>,
     },
     Comment(String),
 }
 
+/// A catch clause of a [Statement::Try] : `catch (var:ty) { stmts }`
+#[derive(Debug, Clone)]
+pub struct CatchClause {
+    /// Variable holding the exception
+    pub var: Expr,
+    /// Type of the caught exceptions, `Dynamic` catches everything
+    pub ty: RefType,
+    pub stmts: Vec<Statement>,
+}
+
 /// Create an expression statement
 pub fn stmt(e: Expr) -> Statement {
     Statement::ExprStatement(e)
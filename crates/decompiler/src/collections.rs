@@ -0,0 +1,115 @@
+use hlbc::types::{FunPtr, RefFun};
+use hlbc::{Bytecode, Resolve};
+
+use crate::ast::Expr;
+
+/// Std array types, methods of these types have a special syntax
+const ARRAY_TYPES: &[&str] = &[
+    "hl.types.ArrayBase",
+    "hl.types.ArrayAccess",
+    "hl.types.ArrayObj",
+    "hl.types.ArrayDyn",
+    "hl.types.ArrayBytes_Int",
+    "hl.types.ArrayBytes_Float",
+    "hl.types.ArrayBytes_Single",
+    "hl.types.ArrayBytes_hl_UI16",
+    "hl.types.ArrayBytes_hl_UI8",
+    "hl.types.ArrayBytes_hl_F32",
+];
+
+/// Std map types
+pub(crate) const MAP_TYPES: &[&str] = &["haxe.ds.StringMap", "haxe.ds.IntMap", "haxe.ds.ObjectMap"];
+
+/// Operations on collections with a dedicated syntax
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum CollectionOp {
+    /// `c[i]`, args : (collection, index)
+    Get,
+    /// `c[i] = v`, args : (collection, index, value)
+    Set,
+    /// `arr.push(v)`, args : (array, value)
+    Push,
+    /// Construction of an array from a native array, args : (native array)
+    Alloc,
+    /// Allocation of a native array, args : (type, size)
+    NativeAlloc,
+    /// `map.exists(k)`, args : (map, key)
+    Exists,
+    /// `map.remove(k)`, args : (map, key)
+    Remove,
+    /// `map.keys()`, args : (map)
+    Keys,
+    /// `map.iterator()`, args : (map)
+    Values,
+}
+
+/// Methods of the std collection types : (types, method, op)
+const METHODS: &[(&[&str], &str, CollectionOp)] = &[
+    (ARRAY_TYPES, "getDyn", CollectionOp::Get),
+    (ARRAY_TYPES, "setDyn", CollectionOp::Set),
+    (ARRAY_TYPES, "pushDyn", CollectionOp::Push),
+    (ARRAY_TYPES, "alloc", CollectionOp::Alloc),
+    (MAP_TYPES, "get", CollectionOp::Get),
+    (MAP_TYPES, "set", CollectionOp::Set),
+];
+
+/// Collection natives : (lib, name, op)
+const NATIVES: &[(&str, &str, CollectionOp)] = &[
+    ("std", "alloc_array", CollectionOp::NativeAlloc),
+    // hl.types.BytesMap, backing haxe.ds.StringMap
+    ("std", "hbget", CollectionOp::Get),
+    ("std", "hbset", CollectionOp::Set),
+    ("std", "hbexists", CollectionOp::Exists),
+    ("std", "hbremove", CollectionOp::Remove),
+    ("std", "hbkeys", CollectionOp::Keys),
+    ("std", "hbvalues", CollectionOp::Values),
+    // hl.types.IntMap, backing haxe.ds.IntMap
+    ("std", "higet", CollectionOp::Get),
+    ("std", "hiset", CollectionOp::Set),
+    ("std", "hiexists", CollectionOp::Exists),
+    ("std", "hiremove", CollectionOp::Remove),
+    ("std", "hikeys", CollectionOp::Keys),
+    ("std", "hivalues", CollectionOp::Values),
+    // hl.types.ObjectMap, backing haxe.ds.ObjectMap
+    ("std", "hoget", CollectionOp::Get),
+    ("std", "hoset", CollectionOp::Set),
+    ("std", "hoexists", CollectionOp::Exists),
+    ("std", "horemove", CollectionOp::Remove),
+    ("std", "hokeys", CollectionOp::Keys),
+    ("std", "hovalues", CollectionOp::Values),
+];
+
+/// Find if a function is a method of the std collection types or a collection native.
+///
+/// Map natives work on the native map backing the Haxe map (`map.h`), with the string keys as bytes (`key.bytes`).
+pub(crate) fn collection_op(code: &Bytecode, fun: RefFun) -> Option<CollectionOp> {
+    match code.get(fun) {
+        FunPtr::Fun(f) => {
+            let parent = f.parent?.as_obj(code)?.name(code);
+            // Static methods are members of the static class ($Name)
+            let parent = parent.strip_prefix('$').unwrap_or(&parent);
+            let name = f.name(code);
+            METHODS
+                .iter()
+                .find(|(types, method, _)| *method == &*name && types.contains(&parent))
+                .map(|&(_, _, op)| op)
+        }
+        FunPtr::Native(n) => {
+            let (lib, name) = (n.lib(code), n.name(code));
+            // Lazily loaded libs are prefixed with '?'
+            let lib = lib.strip_prefix('?').unwrap_or(&lib);
+            NATIVES
+                .iter()
+                .find(|(l, nname, _)| *l == lib && *nname == &*name)
+                .map(|&(_, _, op)| op)
+        }
+    }
+}
+
+/// `obj.name` => `obj`
+pub(crate) fn unwrap_field(expr: Expr, name: &str) -> Expr {
+    match expr {
+        Expr::Field(obj, field) if field == name => *obj,
+        e => e,
+    }
+}
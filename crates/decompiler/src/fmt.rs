@@ -2,11 +2,12 @@ use std::fmt;
 use std::fmt::{Display, Formatter};
 
 use hlbc::fmt::{BytecodeFmt, EnhancedFmt};
-use hlbc::types::{Function, RefField, Type};
+use hlbc::types::{Function, RefField, RefType, Type};
 use hlbc::Str;
-use hlbc::{Bytecode, Resolve};
+use hlbc::Bytecode;
 
 use crate::ast::{Class, Constant, ConstructorCall, Expr, Method, Operation, Statement};
+use crate::typedefs::{is_typedef, optional_type, typedef_name};
 
 const INDENT: &str = "                                                                ";
 
@@ -14,6 +15,10 @@ const INDENT: &str = "
 pub struct FormatOptions {
     indent: &'static str,
     inc_indent: usize,
+    /// Package of the class being displayed
+    package: Str,
+    /// Types imported by the class being displayed, displayed with their short name
+    imports: Vec<Str>,
 }
 
 impl FormatOptions {
@@ -21,13 +26,40 @@ impl FormatOptions {
         Self {
             indent: "",
             inc_indent,
+            package: Str::default(),
+            imports: Vec::new(),
         }
     }
 
     pub fn inc_nesting(&self) -> Self {
         FormatOptions {
             indent: &INDENT[..self.indent.len() + self.inc_indent],
-            ..*self
+            ..self.clone()
+        }
+    }
+
+    /// Options to display the content of a class, with the package and imports of that class.
+    fn with_class(&self, class: &Class) -> Self {
+        FormatOptions {
+            package: class
+                .name
+                .rsplit_once('.')
+                .map(|(package, _)| Str::from(package))
+                .unwrap_or_default(),
+            imports: class.imports.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Name to display for a type, the short name is used if the type is imported or in the same package.
+    pub fn type_name<'a>(&self, name: &'a str) -> &'a str {
+        match name.rsplit_once('.') {
+            Some((package, short))
+                if package == &*self.package || self.imports.iter().any(|i| &**i == name) =>
+            {
+                short
+            }
+            _ => name,
         }
     }
 }
@@ -38,9 +70,9 @@ impl Display for FormatOptions {
     }
 }
 
-fn to_haxe_type<'a>(ty: &Type, ctx: &'a Bytecode) -> impl Display + 'a {
+fn to_haxe_type<'a>(ty: RefType, ctx: &'a Bytecode, opts: &FormatOptions) -> impl Display + 'a {
     use crate::Type::*;
-    match ty {
+    match &ctx[ty] {
         Void => Str::from_static("Void"),
         I32 => Str::from_static("Int"),
         F64 => Str::from_static("Float"),
@@ -48,18 +80,54 @@ fn to_haxe_type<'a>(ty: &Type, ctx: &'a Bytecode) -> impl Display + 'a {
         Bytes => Str::from_static("hl.Bytes"),
         Dyn => Str::from_static("Dynamic"),
         Fun(_) => Str::from_static("Function"),
-        Obj(obj) => ctx.get(obj.name),
+        Obj(obj) => Str::from(opts.type_name(&obj.name(ctx))),
+        Ref(inner) => Str::from(format!("hl.Ref<{}>", to_haxe_type(*inner, ctx, opts))),
+        Null(inner) => Str::from(format!("Null<{}>", to_haxe_type(*inner, ctx, opts))),
+        Virtual { .. } if is_typedef(ctx, ty) => typedef_name(ctx, ty),
+        Virtual { fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|f| match optional_type(ctx, f.t) {
+                    Some(t) => format!("?{}: {}", f.virtual_name(ctx), to_haxe_type(t, ctx, opts)),
+                    None => format!("{}: {}", f.virtual_name(ctx), to_haxe_type(f.t, ctx, opts)),
+                })
+                .collect();
+            Str::from(format!("{{ {} }}", fields.join(", ")))
+        }
         _ => Str::from_static("other"),
     }
 }
 
 impl Class {
     pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
+        let opts = opts.with_class(self);
         let new_opts = opts.inc_nesting();
         fmtools::fmt! { move
-            {opts}"class "{self.name} if let Some(parent) = self.parent.as_ref() { " extends "{parent} } " {\n"
+            if !opts.package.is_empty() {
+                {opts}"package "{opts.package}";\n\n"
+            }
+            for import in &self.imports {
+                {opts}"import "{import}";\n"
+            }
+            if !self.imports.is_empty() {
+                "\n"
+            }
+            for &ty in &self.typedefs {
+                {opts}"typedef "{typedef_name(ctx, ty)}" = {\n"
+                if let Type::Virtual { fields } = &ctx[ty] {
+                    for f in fields {
+                        {new_opts}
+                        if optional_type(ctx, f.t).is_some() { "@:optional " }
+                        "var "{f.virtual_name(ctx)}": "
+                        {to_haxe_type(optional_type(ctx, f.t).unwrap_or(f.t), ctx, &new_opts)}";\n"
+                    }
+                }
+                {opts}"}\n\n"
+            }
+            {opts}"class "{opts.type_name(&self.name)}
+            if let Some(parent) = self.parent.as_ref() { " extends "{opts.type_name(parent)} } " {\n"
             for f in &self.fields {
-                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(&ctx[f.ty], ctx)}";\n"
+                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(f.ty, ctx, &new_opts)}";\n"
             }
             for m in &self.methods {
                 "\n"
@@ -75,13 +143,13 @@ impl Method {
         let new_opts = opts.inc_nesting();
         let fun = self.fun.as_fn(ctx).unwrap();
         fmtools::fmt! { move
-            {opts} if self.static_ { "static " } if self.dynamic { "dynamic " }
+            {opts} if self.override_ { "override " } if self.static_ { "static " } if self.dynamic { "dynamic " }
             "function "{fun.name(ctx)}"("
             {fmtools::join(", ", fun.args(ctx).iter().enumerate().skip(if self.static_ { 0 } else { 1 })
                 .map(move |(i, arg)| fmtools::fmt! {move
-                    {fun.arg_name(ctx, i).unwrap_or(Str::from("_"))}": "{to_haxe_type(&ctx[*arg], ctx)}
+                    {fun.arg_name(ctx, i).unwrap_or(Str::from("_"))}": "{to_haxe_type(*arg, ctx, opts)}
                 }))}
-            ")" if !fun.ty(ctx).ret.is_void() { ": "{to_haxe_type(fun.ret(ctx), ctx)} } " {"
+            ")" if !fun.ty(ctx).ret.is_void() { ": "{to_haxe_type(fun.ty(ctx).ret, ctx, opts)} } " {"
 
             if self.statements.is_empty() {
                 "}"
@@ -102,8 +170,8 @@ impl Constant {
         use Constant::*;
         match *self {
             InlineInt(c) => Display::fmt(&c, f),
-            Int(c) => EnhancedFmt.fmt_refint(f, code, c),
-            Float(c) => EnhancedFmt.fmt_reffloat(f, code, c),
+            Int(c) => EnhancedFmt::default().fmt_refint(f, code, c),
+            Float(c) => EnhancedFmt::default().fmt_reffloat(f, code, c),
             String(c) => {
                 write!(f, "\"{}\"", code[c])
             }
@@ -175,7 +243,7 @@ impl Expr {
                             .enumerate()
                             .map(|(i, f)| {
                                 fmtools::fmt! { move
-                                    {f.name(code)}": "{disp!(values.get(&RefField(i)).unwrap())}
+                                    {f.virtual_name(code)}": "{disp!(values.get(&RefField(i)).unwrap())}
                                 }
                             })) }"}"
                     }
@@ -184,18 +252,26 @@ impl Expr {
                 Expr::Array(array, index) => {
                     {disp!(array)}"["{disp!(index)}"]"
                 }
+                Expr::ArrayInit(values) => {
+                    "["{fmtools::join(", ", values.iter().map(|e| disp!(e)))}"]"
+                }
                 Expr::Call(call) => {
                     {disp!(call.fun)}"("{fmtools::join(", ", call.args.iter().map(|e| disp!(e)))}")"
                 }
                 Expr::Constant(c) => {|f| c.fmt(f, code)?;},
                 Expr::Constructor(ConstructorCall { ty, args }) => {
-                    "new "{ty.display::<EnhancedFmt>(code)}"("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
+                    "new "
+                    match ty.as_obj(code) {
+                        Some(obj) => {{indent.type_name(&obj.name(code))}}
+                        None => {{ty.display::<EnhancedFmt>(code)}}
+                    }
+                    "("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
                 }
                 Expr::Closure(f, stmts) => {
                     let fun = f.as_fn(code).unwrap();
                     "("{fmtools::join(", ", fun.ty(code).args.iter().enumerate().map(move |(i, arg)|
                         fmtools::fmt! { move
-                            {fun.arg_name(code, i).unwrap_or(Str::from("_"))}": "{to_haxe_type(&code[*arg], code)}
+                            {fun.arg_name(code, i).unwrap_or(Str::from("_"))}": "{to_haxe_type(*arg, code, indent)}
                         }
                     ))}") -> {\n"
                     let indent2 = indent.inc_nesting();
@@ -205,11 +281,22 @@ impl Expr {
                     {indent}"}"
                 }
                 Expr::EnumConstr(ty, constr, args) => {
-                    {constr.display::<EnhancedFmt>(code, &code[*ty])}"("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
+                    {constr.display::<EnhancedFmt>(code, &code[*ty])}
+                    // Constructors without parameters are referred to by their name
+                    if !args.is_empty() || !matches!(&code[*ty], Type::Enum { constructs, .. }
+                        if constructs.get(constr.0).map_or(false, |c| c.params.is_empty()))
+                    {
+                        "("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
+                    }
                 }
                 Expr::Field(receiver, name) => {
                     {disp!(receiver)}"."{name}
                 }
+                Expr::MapInit(pairs) => {
+                    "["{fmtools::join(", ", pairs.iter().map(|(k, v)| fmtools::fmt! { move
+                        {disp!(k)}" => "{disp!(v)}
+                    }))}"]"
+                }
                 Expr::FunRef(fun) => {{fun.name(code)}},
                 Expr::IfElse { cond, if_, else_ } => {
                     "if ("{disp!(cond)}") {\n"
@@ -224,6 +311,7 @@ impl Expr {
                     {indent}"}"
                 }
                 Expr::Op(op) => {{disp!(op)}},
+                Expr::TypeName(name) => {{name}},
                 Expr::Unknown(msg) => {
                      "["{msg}"]"
                 }
@@ -285,15 +373,15 @@ impl Statement {
                     "switch ("{disp!(arg)}") {\n"
                     let indent2 = indent.inc_nesting();
                     let indent3 = indent2.inc_nesting();
-                    if !default.is_empty() {
-                        {indent2}"default:\n"
-                        for stmt in default {
+                    for (patterns, stmts) in cases {
+                        {indent2}"case "{fmtools::join(", ", patterns.iter().map(|e| disp!(e)))}":\n"
+                        for stmt in stmts {
                             {indent3}{stmt.display(&indent3, code, f)}"\n"
                         }
                     }
-                    for (pattern, stmts) in cases {
-                        {indent2}"case "{disp!(pattern)}":\n"
-                        for stmt in stmts {
+                    if !default.is_empty() {
+                        {indent2}"default:\n"
+                        for stmt in default {
                             {indent3}{stmt.display(&indent3, code, f)}"\n"
                         }
                     }
@@ -314,23 +402,22 @@ impl Statement {
                     "continue;"
                 }
                 Statement::Throw(exc) => {
-                    "throw "{disp!(exc)}
+                    "throw "{disp!(exc)}";"
                 }
-                Statement::Try { stmts } => {
+                Statement::Try { stmts, catches } => {
                     "try {\n"
                     let indent2 = indent.inc_nesting();
                     for stmt in stmts {
                         {indent2}{stmt.display(&indent2, code, f)}"\n"
                     }
                     {indent}"}"
-                }
-                Statement::Catch { stmts } => {
-                    "catch () {\n"
-                    let indent2 = indent.inc_nesting();
-                    for stmt in stmts {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
+                    for catch in catches {
+                        " catch ("{disp!(catch.var)}":"{to_haxe_type(catch.ty, code, indent)}") {\n"
+                        for stmt in &catch.stmts {
+                            {indent2}{stmt.display(&indent2, code, f)}"\n"
+                        }
+                        {indent}"}"
                     }
-                    {indent}"}"
                 }
                 Statement::Comment(comment) => {
                     "// "{comment}
@@ -6,9 +6,14 @@
 use std::collections::{HashMap, HashSet};
 
 use ast::*;
+use collections::CollectionOp;
+use hlbc::analysis::infer::InferredTypes;
 use hlbc::fmt::EnhancedFmt;
 use hlbc::opcodes::Opcode;
-use hlbc::types::{Function, RefField, RefFun, RefString, Reg, Type, TypeObj};
+use hlbc::rename::Symbol;
+use hlbc::types::{
+    Function, ObjField, RefEnumConstruct, RefField, RefFun, RefString, RefType, Reg, Type, TypeObj,
+};
 use hlbc::{Bytecode, Resolve, Str};
 use scopes::*;
 
@@ -16,12 +21,16 @@ use scopes::*;
 mod alt;
 /// A simple representation for the Haxe source code generated by the decompiler
 pub mod ast;
+/// Recognition of the std collection types (arrays and maps)
+mod collections;
 /// Functions to render the [ast] to a string
 pub mod fmt;
 /// AST post-processing
 mod post;
 /// Scope handling structures
 mod scopes;
+/// Names of the large virtual types, declared as typedefs
+pub mod typedefs;
 
 enum ExprCtx {
     Constructor {
@@ -33,6 +42,11 @@ enum ExprCtx {
         fields: HashMap<RefField, Expr>,
         remaining: usize,
     },
+    /// Native array being filled to construct an array literal
+    Array {
+        reg: Reg,
+        values: Vec<Expr>,
+    },
 }
 
 struct DecompilerState<'c> {
@@ -45,6 +59,10 @@ struct DecompilerState<'c> {
     expr_ctx: Vec<ExprCtx>,
     // Variable names we already declared
     seen: HashSet<Str>,
+    // Concrete types of the dynamic registers
+    types: InferredTypes,
+    // Instructions already handled by another one, like the type tests of catch clauses
+    skipped: HashSet<usize>,
     f: &'c Function,
     code: &'c Bytecode,
 }
@@ -77,6 +95,8 @@ impl<'c> DecompilerState<'c> {
             reg_state,
             expr_ctx,
             seen,
+            types: f.infer_types(code),
+            skipped: HashSet::new(),
             f,
             code,
         }
@@ -88,7 +108,7 @@ impl<'c> DecompilerState<'c> {
 
     // Update the register state and create a statement depending on inline rules
     fn push_expr(&mut self, i: usize, dst: Reg, expr: Expr) {
-        let name = self.f.var_name(self.code, i);
+        let name = self.f.assigned_name(self.code, i);
         // Inline check
         if name.is_none() {
             self.reg_state.insert(dst, expr);
@@ -112,6 +132,46 @@ impl<'c> DecompilerState<'c> {
             .unwrap_or_else(|| Expr::Unknown("missing expr".to_owned()))
     }
 
+    /// Make sure a register is a variable before taking a reference to it. The value can change through the
+    /// reference, so it can't be inlined anymore.
+    fn ref_variable(&mut self, reg: Reg) -> Expr {
+        match self.reg_state.get(&reg) {
+            Some(var @ Expr::Variable(_, _)) => var.clone(),
+            Some(expr) => {
+                let expr = expr.clone();
+                let var = Expr::Variable(reg, None);
+                self.push_stmt(Statement::Assign {
+                    declaration: true,
+                    variable: var.clone(),
+                    assign: expr,
+                });
+                self.reg_state.insert(reg, var.clone());
+                var
+            }
+            // Out parameter without initial value
+            None => Expr::Variable(reg, None),
+        }
+    }
+
+    /// Whether the expression of a reference register is the referenced variable itself, `hl.Ref` values are
+    /// implicitly created from variables.
+    fn is_referenced_var(&self, reg: Reg) -> bool {
+        match self.expr(reg) {
+            Expr::Variable(r, _) => !matches!(self.code[self.f[r]], Type::Ref(_)),
+            _ => false,
+        }
+    }
+
+    /// Access to a field of a dynamic, as a field access if we know the register holds an object
+    fn dyn_field(&self, i: usize, obj: Reg, field: RefString) -> Expr {
+        match self.types.get(i, obj).map(|t| &self.code[t]) {
+            Some(Type::Obj(_) | Type::Struct(_) | Type::Virtual { .. }) => {
+                Expr::Field(Box::new(self.expr(obj)), self.code[field].clone())
+            }
+            _ => array(self.expr(obj), cst_string(field)),
+        }
+    }
+
     /// Expands the expression of many registers
     fn args_expr(&self, args: &[Reg]) -> Vec<Expr> {
         args.iter().map(|&r| self.expr(r)).collect()
@@ -131,7 +191,7 @@ impl<'c> DecompilerState<'c> {
                 );
                 self.expr_ctx.pop();
             }
-        } else {
+        } else if !self.push_collection_op(i, dst, fun, args) {
             self.push_stmt(comment(fun.display::<EnhancedFmt>(self.code).to_string()));
             let call = if let Some((func, true)) =
                 fun.as_fn(self.code).map(|func| (func, func.is_method()))
@@ -151,6 +211,244 @@ impl<'c> DecompilerState<'c> {
         }
     }
 
+    /// Push a call to a method of the std collection types with the array or map syntax.
+    /// Returns false if the function isn't recognized.
+    fn push_collection_op(&mut self, i: usize, dst: Reg, fun: RefFun, args: &[Reg]) -> bool {
+        let Some(op) = collections::collection_op(self.code, fun) else {
+            return false;
+        };
+        // Map natives work on the native map (map.h) and the string bytes (key.bytes)
+        let native = fun.as_fn(self.code).is_none();
+        let receiver = |reg| {
+            if native {
+                collections::unwrap_field(self.expr(reg), "h")
+            } else {
+                self.expr(reg)
+            }
+        };
+        let key = |reg| {
+            if native {
+                collections::unwrap_field(self.expr(reg), "bytes")
+            } else {
+                self.expr(reg)
+            }
+        };
+        let method = |receiver: Expr, name: &'static str, args: Vec<Expr>| {
+            call(
+                Expr::Field(Box::new(receiver), Str::from_static(name)),
+                args,
+            )
+        };
+        let expr = match (op, args) {
+            (CollectionOp::Get, &[c, k]) => ast::array(receiver(c), key(k)),
+            (CollectionOp::Set, &[c, k, value]) => {
+                let assign = Statement::Assign {
+                    declaration: false,
+                    variable: ast::array(receiver(c), key(k)),
+                    assign: self.expr(value),
+                };
+                self.push_stmt(assign);
+                return true;
+            }
+            (CollectionOp::Push, &[c, value]) => {
+                method(receiver(c), "push", vec![self.expr(value)])
+            }
+            (CollectionOp::Exists, &[c, k]) => method(receiver(c), "exists", vec![key(k)]),
+            (CollectionOp::Remove, &[c, k]) => method(receiver(c), "remove", vec![key(k)]),
+            (CollectionOp::Keys, &[c]) => method(receiver(c), "keys", Vec::new()),
+            (CollectionOp::Values, &[c]) => method(receiver(c), "iterator", Vec::new()),
+            (CollectionOp::Alloc, &[native]) => match self.expr_ctx.last() {
+                Some(ExprCtx::Array { reg, .. }) if *reg == native => {
+                    let Some(ExprCtx::Array { values, .. }) = self.expr_ctx.pop() else {
+                        unreachable!()
+                    };
+                    Expr::ArrayInit(values)
+                }
+                // Not a literal, the array wraps an existing native array
+                _ => self.expr(native),
+            },
+            (CollectionOp::NativeAlloc, &[_, _]) => {
+                self.expr_ctx.push(ExprCtx::Array {
+                    reg: dst,
+                    values: Vec::new(),
+                });
+                call_fun(fun, self.args_expr(args))
+            }
+            _ => return false,
+        };
+        if fun.ty(self.code).ret.is_void() {
+            self.push_stmt(stmt(expr));
+        } else {
+            self.push_expr(i, dst, expr);
+        }
+        true
+    }
+
+    /// Open a switch scope. Cases start at their jump target, values jumping to the next instruction belong to the
+    /// default case. A switch on the index of an enum (`EnumIndex` just before) matches its constructors.
+    fn push_switch(&mut self, i: usize, reg: Reg, offsets: &[i32], end: i32) {
+        let code = self.code;
+        // Enum value, its type and its constructors
+        let enum_switch = self.f.ops[..i]
+            .iter()
+            .rev()
+            .find(|op| op.defs().contains(&reg))
+            .and_then(|op| match *op {
+                Opcode::EnumIndex { value, .. } => Some(value),
+                _ => None,
+            })
+            .and_then(|value| match &code[self.f[value]] {
+                Type::Enum { constructs, .. } => Some((value, self.f[value], constructs)),
+                _ => None,
+            });
+
+        let mut starts: Vec<(usize, Vec<Expr>)> = Vec::new();
+        for (value, &offset) in offsets.iter().enumerate() {
+            if offset <= 0 {
+                continue;
+            }
+            let pattern = match enum_switch {
+                // Arguments are bound by the EnumField instructions of the case
+                Some((_, t, constructs)) => Expr::EnumConstr(
+                    t,
+                    RefEnumConstruct(value),
+                    constructs.get(value).map_or(Vec::new(), |c| {
+                        vec![Expr::Variable(Reg(0), Some(Str::from_static("_"))); c.params.len()]
+                    }),
+                ),
+                None => Expr::Constant(Constant::InlineInt(value)),
+            };
+            let start = i + 1 + offset as usize;
+            match starts.iter_mut().find(|(s, _)| *s == start) {
+                Some((_, patterns)) => patterns.push(pattern),
+                None => starts.push((start, vec![pattern])),
+            }
+        }
+        starts.sort_by_key(|(start, _)| *start);
+
+        let enum_value = enum_switch.map(|(value, _, _)| value);
+        self.scopes.push_switch(
+            end + 1,
+            self.expr(enum_value.unwrap_or(reg)),
+            starts,
+            i + 1 + end as usize,
+            enum_value,
+        );
+    }
+
+    /// Open a try scope. The handler starts with the catch clauses, each one being a type test jumping to the next
+    /// clause and the assignment of the exception to the catch variable. The type of the variable is the catch type.
+    /// Without a catch-all clause, the handler ends by rethrowing the exception.
+    fn push_try(&mut self, i: usize, exc: Reg, offset: i32) {
+        let f = self.f;
+        let handler = i + 1 + offset as usize;
+        // The try body ends with a jump over the handler
+        let end = match f.ops[handler - 1] {
+            Opcode::JAlways { offset } if offset >= 0 => handler + offset as usize,
+            _ => handler,
+        };
+
+        let mut clauses = Vec::new();
+        let mut start = handler;
+        while start < end {
+            // The prologue of a clause is its type test, a JFalse to the next clause, then the binding of the
+            // exception. The clause body starts at the first other control flow instruction.
+            let mut next = end;
+            let mut bind = None;
+            for (j, op) in f.ops.iter().enumerate().take(end).skip(start) {
+                match *op {
+                    // Never past the type test
+                    _ if j >= next => break,
+                    Opcode::Mov { dst, src }
+                    | Opcode::SafeCast { dst, src }
+                    | Opcode::UnsafeCast { dst, src }
+                        if src == exc =>
+                    {
+                        bind = Some((j, dst));
+                        break;
+                    }
+                    // Wrapping in a haxe.Exception
+                    Opcode::Call1 { dst, arg0, .. } if arg0 == exc => {
+                        bind = Some((j, dst));
+                        break;
+                    }
+                    Opcode::JFalse { offset, .. } if offset >= 0 && next == end => {
+                        next = j + 1 + offset as usize;
+                    }
+                    Opcode::Label
+                    | Opcode::Ret { .. }
+                    | Opcode::Throw { .. }
+                    | Opcode::Rethrow { .. } => break,
+                    _ if !op.jump_targets(j).is_empty() => break,
+                    _ => {}
+                }
+            }
+            let Some((bind, var)) = bind else {
+                break;
+            };
+            self.skipped.extend(start..=bind);
+            clauses.push((
+                bind + 1,
+                Expr::Variable(var, f.assigned_name(self.code, bind)),
+                f.regtype(var),
+            ));
+            start = next;
+        }
+        if matches!(f.ops[end - 1], Opcode::Rethrow { exc: e } if e == exc) && !clauses.is_empty() {
+            self.skipped.insert(end - 1);
+        }
+
+        self.scopes.push_try(offset + 1, end, clauses);
+    }
+
+    /// Whether an instruction ends the array literal being built : control flow, or any use of the native array
+    /// other than storing an element or wrapping it in an array.
+    fn ends_array_literal(&self, i: usize, op: &Opcode) -> bool {
+        let Some(&ExprCtx::Array { reg, .. }) = self.expr_ctx.last() else {
+            return false;
+        };
+        let wraps = match *op {
+            Opcode::Call1 { fun, arg0, .. } => {
+                arg0 == reg
+                    && collections::collection_op(self.code, fun) == Some(CollectionOp::Alloc)
+            }
+            Opcode::SetArray {
+                array, index, src, ..
+            } => array == reg && index != reg && src != reg,
+            _ => false,
+        };
+        let control_flow = !op.jump_targets(i).is_empty()
+            || matches!(
+                op,
+                Opcode::Label
+                    | Opcode::Ret { .. }
+                    | Opcode::Throw { .. }
+                    | Opcode::Rethrow { .. }
+                    | Opcode::EndTrap { .. }
+            );
+        control_flow || (!wraps && (op.uses().contains(&reg) || op.defs().contains(&reg)))
+    }
+
+    /// Emit the elements stored in the native arrays as plain assignments, when they aren't wrapped in an array
+    /// literal after all.
+    fn flush_array_literals(&mut self) {
+        while let Some(ExprCtx::Array { .. }) = self.expr_ctx.last() {
+            let Some(ExprCtx::Array { reg, values }) = self.expr_ctx.pop() else {
+                unreachable!()
+            };
+            for (index, value) in values.into_iter().enumerate() {
+                self.push_stmt(Statement::Assign {
+                    declaration: false,
+                    variable: ast::array(
+                        self.expr(reg),
+                        Expr::Constant(Constant::InlineInt(index)),
+                    ),
+                    assign: value,
+                });
+            }
+        }
+    }
+
     /// Process a jmp instruction, might be the exit condition of a loop or an if
     fn push_jmp(&mut self, i: usize, offset: i32, cond: Expr) {
         if offset > 0 {
@@ -181,6 +479,35 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
 
     let iter = f.ops.iter().enumerate();
     for (i, o) in iter {
+        if let Some(patterns) = state.scopes.switch_case_at(i) {
+            // The previous case doesn't end with a jump out of the switch
+            if !matches!(
+                f.ops[i - 1],
+                Opcode::JAlways { .. }
+                    | Opcode::Ret { .. }
+                    | Opcode::Throw { .. }
+                    | Opcode::Rethrow { .. }
+            ) {
+                state.push_stmt(comment("fallthrough"));
+            }
+            state.scopes.push_switch_case(patterns);
+        }
+        if let Some((var, ty)) = state.scopes.catch_clause_at(i) {
+            if let Expr::Variable(reg, name) = &var {
+                if let Some(name) = name {
+                    state.seen.insert(name.clone());
+                }
+                state.reg_state.insert(*reg, var.clone());
+            }
+            state.scopes.push_catch_clause(var, ty);
+        }
+        if state.skipped.contains(&i) {
+            state.scopes.advance();
+            continue;
+        }
+        if state.ends_array_literal(i, o) {
+            state.flush_array_literals();
+        }
         // Opcodes are grouped by semantic
         // Control flow first because they are the most important
         match o {
@@ -241,12 +568,16 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                         }
                     }
                 } else {
-                    if let Some(offsets) = state.scopes.last_is_switch_ctx() {
-                        if let Some(pos) = offsets.iter().position(|o| *o == i) {
-                            state.scopes.push_switch_case(pos);
-                        } else {
-                            panic!("no matching offset for switch case ({i})");
-                        }
+                    let target = (i as i32 + offset + 1) as usize;
+                    if state.scopes.last_switch_end() == Some(target)
+                        && !state.scopes.last_is_if_closing()
+                    {
+                        // Jump out of a switch case, implicit in Haxe
+                    } else if state.scopes.last_try_end() == Some(target) {
+                        // It's the jump over the catch clauses
+                        state.scopes.push_catch(offset + 1);
+                    } else if state.scopes.last_catch_end() == Some(target) {
+                        // Jump out of a catch clause, implicit in Haxe
                     } else if state.scopes.last_loop_start().is_some() {
                         // Check the instruction just before the jump target
                         // If it's a jump backward of a loop
@@ -268,13 +599,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 }
             }
             Opcode::Switch { reg, offsets, end } => {
-                // Convert to absolute positions
-                state.scopes.push_switch(
-                    *end + 1,
-                    state.expr(*reg),
-                    offsets.iter().map(|o| i + *o as usize).collect(),
-                );
-                // The default switch case is implicit
+                state.push_switch(i, *reg, offsets, *end);
             }
             &Opcode::Label => state.scopes.push_loop(i),
             &Opcode::Ret { ret } => {
@@ -296,10 +621,10 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 state.push_stmt(Statement::Throw(state.expr(exc)));
             }
             &Opcode::Trap { exc, offset } => {
-                state.scopes.push_try(offset + 1);
+                state.push_try(i, exc, offset);
             }
-            &Opcode::EndTrap { exc } => {
-                // TODO try catch
+            &Opcode::EndTrap { .. } => {
+                // The end of the try body is the jump over the handler
             }
             //endregion
 
@@ -327,7 +652,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 // Workaround for when the instructions after this one use dst and src interchangeably.
                 state
                     .reg_state
-                    .insert(src, Expr::Variable(dst, f.var_name(code, i)));
+                    .insert(src, Expr::Variable(dst, f.assigned_name(code, i)));
             }
             &Opcode::Add { dst, a, b } => {
                 state.push_expr(i, dst, add(state.expr(a), state.expr(b)));
@@ -424,7 +749,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                             )),
                         );
                     }
-                } else {
+                } else if !state.push_collection_op(i, *dst, *fun, args) {
                     state.push_stmt(comment(fun.display::<EnhancedFmt>(code).to_string()));
                     let call = call_fun(*fun, state.args_expr(args));
                     if fun.ty(code).ret.is_void() {
@@ -435,19 +760,25 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 }
             }
             Opcode::CallMethod { dst, field, args } => {
-                let call = call(
-                    ast::field(state.expr(args[0]), f.regtype(args[0]), *field, code),
-                    state.args_expr(&args[1..]),
-                );
-                if f.regtype(args[0])
-                    .method(field.0, code)
-                    .and_then(|p| p.findex.as_fn(code))
-                    .map(|fun| fun.ty(code).ret.is_void())
-                    .unwrap_or(false)
-                {
-                    state.push_stmt(stmt(call));
-                } else {
-                    state.push_expr(i, *dst, call);
+                let collection_op = match f.regtype(args[0]).method(field.0, code) {
+                    Some(proto) => state.push_collection_op(i, *dst, proto.findex, args),
+                    None => false,
+                };
+                if !collection_op {
+                    let call = call(
+                        ast::field(state.expr(args[0]), f.regtype(args[0]), *field, code),
+                        state.args_expr(&args[1..]),
+                    );
+                    if f.regtype(args[0])
+                        .method(field.0, code)
+                        .and_then(|p| p.findex.as_fn(code))
+                        .map(|fun| fun.ty(code).ret.is_void())
+                        .unwrap_or(false)
+                    {
+                        state.push_stmt(stmt(call));
+                    } else {
+                        state.push_expr(i, *dst, call);
+                    }
                 }
             }
             Opcode::CallThis { dst, field, args } => {
@@ -606,12 +937,12 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 });
             }
             &Opcode::DynGet { dst, obj, field } => {
-                state.push_expr(i, dst, array(state.expr(obj), cst_string(field)));
+                state.push_expr(i, dst, state.dyn_field(i, obj, field));
             }
             &Opcode::DynSet { obj, field, src } => {
                 state.push_stmt(Statement::Assign {
                     declaration: false,
-                    variable: array(state.expr(obj), cst_string(field)),
+                    variable: state.dyn_field(i, obj, field),
                     assign: state.expr(src),
                 });
             }
@@ -628,17 +959,29 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 state.push_expr(i, dst, state.expr(src));
             }
             &Opcode::Ref { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
+                // Passing the variable converts it to hl.Ref : `native(out)`
+                let var = state.ref_variable(src);
+                state.push_expr(i, dst, var);
             }
             &Opcode::Unref { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
+                if state.is_referenced_var(src) {
+                    state.push_expr(i, dst, state.expr(src));
+                } else {
+                    let get = Expr::Field(Box::new(state.expr(src)), Str::from_static("get"));
+                    state.push_expr(i, dst, call(get, Vec::new()));
+                }
             }
             &Opcode::Setref { dst, value } => {
-                state.push_stmt(Statement::Assign {
-                    declaration: false,
-                    variable: state.expr(dst),
-                    assign: state.expr(value),
-                });
+                if state.is_referenced_var(dst) {
+                    state.push_stmt(Statement::Assign {
+                        declaration: false,
+                        variable: state.expr(dst),
+                        assign: state.expr(value),
+                    });
+                } else {
+                    let set = Expr::Field(Box::new(state.expr(dst)), Str::from_static("set"));
+                    state.push_stmt(stmt(call(set, vec![state.expr(value)])));
+                }
             }
             &Opcode::RefData { dst, src } => {
                 state.push_expr(i, dst, state.expr(src));
@@ -702,11 +1045,28 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 construct,
                 field,
             } => {
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::Field(Box::new(state.expr(value)), Str::from(field.0.to_string())),
-                );
+                // Binding of a constructor argument in a `case Ctor(a, b):`
+                let binding = f.assigned_name(code, i).filter(|name| {
+                    match state.scopes.enum_case_args_mut(value, construct) {
+                        Some(args) if field.0 < args.len() => {
+                            args[field.0] = Expr::Variable(dst, Some(name.clone()));
+                            true
+                        }
+                        _ => false,
+                    }
+                });
+                if let Some(name) = binding {
+                    state
+                        .reg_state
+                        .insert(dst, Expr::Variable(dst, Some(name.clone())));
+                    state.seen.insert(name);
+                } else {
+                    state.push_expr(
+                        i,
+                        dst,
+                        Expr::Field(Box::new(state.expr(value)), Str::from(field.0.to_string())),
+                    );
+                }
             }
             &Opcode::SetEnumField { value, field, src } => match state.expr(value) {
                 Expr::Variable(r, name) => {
@@ -745,11 +1105,32 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 state.push_expr(i, dst, ast::array(state.expr(array), state.expr(index)));
             }
             &Opcode::SetArray { array, index, src } => {
-                state.push_stmt(Statement::Assign {
-                    declaration: false,
-                    variable: ast::array(state.expr(array), state.expr(index)),
-                    assign: state.expr(src),
-                });
+                let index_cst = match state.expr(index) {
+                    Expr::Constant(Constant::InlineInt(c)) => Some(c),
+                    Expr::Constant(Constant::Int(c)) => usize::try_from(code[c]).ok(),
+                    _ => None,
+                };
+                let value = state.expr(src);
+                // Might be the next element of an array literal
+                match state.expr_ctx.last_mut() {
+                    Some(ExprCtx::Array { reg, values })
+                        if *reg == array && index_cst == Some(values.len()) =>
+                    {
+                        values.push(value);
+                    }
+                    _ => {
+                        // Stored out of order, not a literal
+                        if matches!(state.expr_ctx.last(), Some(ExprCtx::Array { reg, .. }) if *reg == array)
+                        {
+                            state.flush_array_literals();
+                        }
+                        state.push_stmt(Statement::Assign {
+                            declaration: false,
+                            variable: ast::array(state.expr(array), state.expr(index)),
+                            assign: value,
+                        });
+                    }
+                }
             }
             //endregion
 
@@ -767,8 +1148,13 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
             //endregion
             _ => {}
         }
+        // Elements stored in a scope stay in that scope
+        if state.scopes.closing() {
+            state.flush_array_literals();
+        }
         state.scopes.advance();
     }
+    state.flush_array_literals();
     let mut statements = state.scopes.statements();
 
     // AST post processing step !
@@ -781,6 +1167,8 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
             Box::new(post::StringConcat),
             Box::new(post::Itos),
             Box::new(post::Trace),
+            Box::new(post::InlinedStd(f)),
+            Box::new(post::MapLiterals),
         ],
     );
 
@@ -793,6 +1181,7 @@ pub fn decompile_function(code: &Bytecode, f: &Function) -> Method {
         fun: f.findex,
         static_: true,
         dynamic: false,
+        override_: false,
         statements: decompile_code(code, f),
     }
 }
@@ -803,28 +1192,24 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
 
     let mut fields = Vec::new();
     for (i, f) in obj.own_fields.iter().enumerate() {
-        if obj
-            .bindings
-            .contains_key(&RefField(i + obj.fields.len() - obj.own_fields.len()))
-        {
+        let field = RefField(i + obj.fields.len() - obj.own_fields.len());
+        if obj.bindings.contains_key(&field) {
             continue;
         }
         fields.push(ClassField {
-            name: f.name(code).to_owned(),
+            name: field_name(code, obj, field, f),
             static_: false,
             ty: f.t,
         });
     }
     if let Some(ty) = static_type {
         for (i, f) in ty.own_fields.iter().enumerate() {
-            if ty
-                .bindings
-                .contains_key(&RefField(i + ty.fields.len() - ty.own_fields.len()))
-            {
+            let field = RefField(i + ty.fields.len() - ty.own_fields.len());
+            if ty.bindings.contains_key(&field) {
                 continue;
             }
             fields.push(ClassField {
-                name: f.name(code).to_owned(),
+                name: field_name(code, ty, field, f),
                 static_: true,
                 ty: f.t,
             });
@@ -837,6 +1222,7 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             fun: *fun,
             static_: false,
             dynamic: true,
+            override_: false,
             statements: decompile_code(code, fun.as_fn(code).unwrap()),
         })
     }
@@ -846,6 +1232,7 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
                 fun: *fun,
                 static_: true,
                 dynamic: false,
+                override_: false,
                 statements: decompile_code(code, fun.as_fn(code).unwrap()),
             })
         }
@@ -855,12 +1242,27 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             fun: f.findex,
             static_: false,
             dynamic: false,
+            // A parent class already declares the method in this slot
+            override_: obj
+                .super_
+                .map_or(false, |s| code.method_origin(s, f.pindex).is_some()),
             statements: decompile_code(code, f.findex.as_fn(code).unwrap()),
         })
     }
 
+    let name = obj.name(code).to_owned();
+    let imports = class_imports(code, &name, obj, &methods, &fields);
+    let typedefs = typedefs::collect_typedefs(
+        code,
+        fields
+            .iter()
+            .map(|f| f.ty)
+            .chain(function_types(code, &methods)),
+    );
     Class {
-        name: obj.name(code).to_owned(),
+        name,
+        imports,
+        typedefs,
         parent: obj
             .super_
             .and_then(|ty| ty.as_obj(code))
@@ -870,16 +1272,665 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
     }
 }
 
+/// Name of a class field, taking renames into account
+fn field_name(code: &Bytecode, obj: &TypeObj, field: RefField, f: &ObjField) -> Str {
+    code.renames
+        .get(Symbol::Field(obj.name, field))
+        .unwrap_or_else(|| f.name(code))
+}
+
+/// Types of the methods and of the closures they create
+fn function_types(code: &Bytecode, methods: &[Method]) -> Vec<RefType> {
+    let mut types = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack: Vec<RefFun> = methods.iter().map(|m| m.fun).rev().collect();
+    while let Some(fun) = stack.pop() {
+        if !visited.insert(fun) {
+            continue;
+        }
+        let Some(f) = fun.as_fn(code) else {
+            continue;
+        };
+        types.push(f.t);
+        for op in &f.ops {
+            if let Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } = op {
+                stack.push(*fun);
+            }
+        }
+    }
+    types
+}
+
+/// Compute the imports of a class : every class or enum from another package referenced by the class
+/// fields, its parent and the registers of its methods (closures included).
+fn class_imports(
+    code: &Bytecode,
+    name: &str,
+    obj: &TypeObj,
+    methods: &[Method],
+    fields: &[ClassField],
+) -> Vec<Str> {
+    let mut types: Vec<RefType> = fields.iter().map(|f| f.ty).collect();
+    types.extend(obj.super_);
+
+    let mut visited = HashSet::new();
+    let mut stack: Vec<RefFun> = methods.iter().map(|m| m.fun).collect();
+    while let Some(fun) = stack.pop() {
+        if !visited.insert(fun) {
+            continue;
+        }
+        let Some(f) = fun.as_fn(code) else {
+            continue;
+        };
+        types.push(f.t);
+        types.extend(&f.regs);
+        for op in &f.ops {
+            match op {
+                Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+                    stack.push(*fun)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let package = name.rsplit_once('.').map(|(p, _)| p);
+    let mut imports: Vec<Str> = Vec::new();
+    for ty in types {
+        let mut ty = &code[ty];
+        // Unwrap Null<T> and other wrappers
+        while let Type::Null(inner) | Type::Ref(inner) | Type::Packed(inner) = ty {
+            ty = &code[*inner];
+        }
+        let mut names = Vec::new();
+        match ty {
+            Type::Obj(obj) | Type::Struct(obj) => names.push(obj.name(code)),
+            Type::Enum { name, .. } => names.push(code.get(*name)),
+            Type::Fun(fun) | Type::Method(fun) => {
+                for t in fun.args.iter().chain(std::iter::once(&fun.ret)) {
+                    if let Some(obj) = t.as_obj(code) {
+                        names.push(obj.name(code));
+                    }
+                }
+            }
+            _ => {}
+        }
+        for n in names {
+            // Static classes ($Foo) and types in the same package don't need an import
+            match n.rsplit_once('.') {
+                Some((p, _)) if !n.starts_with('$') && Some(p) != package && &*n != name => {
+                    imports.push(n)
+                }
+                _ => {}
+            }
+        }
+    }
+    imports.sort();
+    imports.dedup();
+
+    // Types with the same short name can't be both imported, keep them fully qualified
+    let short = |n: &Str| {
+        n.rsplit_once('.')
+            .map(|(_, s)| s.to_owned())
+            .unwrap_or_default()
+    };
+    let own = name
+        .rsplit_once('.')
+        .map(|(_, s)| s)
+        .unwrap_or(name)
+        .to_owned();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for i in &imports {
+        *counts.entry(short(i)).or_default() += 1;
+    }
+    imports.retain(|i| {
+        let s = short(i);
+        counts[&s] == 1 && s != own
+    });
+    imports
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
     use std::hint::black_box;
     use std::io::BufReader;
 
+    use hlbc::testing::{build, class, fun};
+    use hlbc::types::{EnumConstruct, ObjField, RefFun, RefGlobal, RefString, RefType, Type};
     use hlbc::Bytecode;
 
+    use crate::fmt::FormatOptions;
     use crate::{decompile_class, decompile_code, decompile_function};
 
+    /// Decompiled body of a function
+    fn decompiled(code: &Bytecode, findex: usize) -> String {
+        let f = RefFun(findex).as_fn(code).unwrap();
+        let indent = FormatOptions::new(2);
+        decompile_code(code, f)
+            .iter()
+            .map(|stmt| format!("{}\n", stmt.display(&indent, code, f)))
+            .collect()
+    }
+
+    #[test]
+    fn array_literal() {
+        // var a = [1, 2, 3]; show(a); var n = new NativeArray<Int>(2); n[0] = 7; showNative(n);
+        let mut code = build(
+            &[
+                "",
+                "hl.types.ArrayObj",
+                "$hl.types.ArrayObj",
+                "alloc",
+                "std",
+                "alloc_array",
+                "$Arrays",
+                "main",
+                "show",
+                "showNative",
+                "n",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Type,
+                Type::Array,
+                class(1, &[]),
+                fun(&[2, 1], 3),
+                fun(&[3], 4),
+                class(2, &[(3, 6, 1)]),
+                fun(&[], 0),
+                fun(&[4], 0),
+                fun(&[3], 0),
+                class(6, &[(7, 8, 0), (8, 9, 2), (9, 10, 3)]),
+            ],
+            &[(4, 5, 5, 4)],
+            "
+            .fun fn@0 type@8
+            .assigns
+            .assign string@10 16
+            .reg type@2
+            .reg type@1
+            .reg type@3
+            .reg type@1
+            .reg type@1
+            .reg type@4
+            .reg type@0
+                Null reg0
+                Int reg1 int@0
+                Call2 reg2 fn@4 reg0 reg1
+                Int reg3 int@1
+                Int reg4 int@2
+                SetArray reg2 reg3 reg4
+                Int reg3 int@2
+                Int reg4 int@3
+                SetArray reg2 reg3 reg4
+                Int reg3 int@3
+                Int reg4 int@0
+                SetArray reg2 reg3 reg4
+                Call1 reg5 fn@1 reg2
+                Call1 reg6 fn@2 reg5
+                Int reg1 int@3
+                Call2 reg2 fn@4 reg0 reg1
+                Int reg3 int@1
+                Int reg4 int@4
+                SetArray reg2 reg3 reg4
+                Call1 reg6 fn@3 reg2
+                Ret reg6
+            .end
+            .fun fn@1 type@6
+            .reg type@3
+            .reg type@4
+                New reg1
+                Ret reg1
+            .end
+            .fun fn@2 type@9
+            .reg type@4
+            .reg type@0
+                Ret reg1
+            .end
+            .fun fn@3 type@10
+            .reg type@3
+            .reg type@0
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![3, 0, 1, 2, 7];
+        // The elements of a native array are kept as assignments
+        assert_eq!(
+            decompiled(&code, 0),
+            "// show@2\nshow([1, 2, 3]);\nvar n = alloc_array(null, 2);\nn[0] = 7;\n// showNative@3\nshowNative(n);\n"
+        );
+    }
+
+    #[test]
+    fn try_catch() {
+        // try { var a = 0; } catch (e) { var b = 3; }
+        let mut code = build(
+            &[
+                "",
+                "haxe.Exception",
+                "$haxe.Exception",
+                "caught",
+                "$TryCatch",
+                "main",
+                "a",
+                "e",
+                "b",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Dyn,
+                class(1, &[]),
+                fun(&[2], 3),
+                class(2, &[(3, 4, 1)]),
+                fun(&[], 0),
+                class(4, &[(5, 6, 0)]),
+            ],
+            &[],
+            "
+            .fun fn@0 type@6
+            .assigns
+            .assign string@6 2
+            .assign string@7 5
+            .assign string@8 6
+            .reg type@2
+            .reg type@1
+            .reg type@3
+            .reg type@1
+            .reg type@0
+                Trap reg0 handler
+                Int reg1 int@0
+                EndTrap reg0
+                JAlways end
+            handler:
+                Call1 reg2 fn@1 reg0
+                Int reg3 int@1
+            end:
+                Ret reg4
+            .end
+            .fun fn@1 type@4
+            .reg type@2
+            .reg type@3
+                New reg1
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![0, 3];
+        assert_eq!(
+            decompiled(&code, 0),
+            "try {\n  var a = 0;\n} catch (e:haxe.Exception) {\n  var b = 3;\n}\n"
+        );
+    }
+
+    #[test]
+    fn switch_int() {
+        // var b = 3; switch (b) { case 0: b += 1; case 1: if (b > 0) b += 2; case 2: b += 1; }
+        // The if of the second case jumps directly to the end of the switch
+        let mut code = build(
+            &["", "$Switch", "main", "b"],
+            vec![Type::Void, Type::I32, fun(&[], 0), class(1, &[(2, 2, 0)])],
+            &[],
+            "
+            .fun fn@0 type@2
+            .assigns
+            .assign string@3 1
+            .assign string@3 5
+            .assign string@3 10
+            .assign string@3 13
+            .reg type@1
+            .reg type@1
+            .reg type@0
+                Int reg0 int@0
+                Switch reg0 [case0, case1, case2] 11
+                JAlways end
+            case0:
+                Int reg1 int@1
+                Add reg0 reg0 reg1
+                JAlways end
+            case1:
+                Int reg1 int@2
+                JSLte reg0 reg1 end
+                Int reg1 int@3
+                Add reg0 reg0 reg1
+                JAlways end
+            case2:
+                Int reg1 int@1
+                Add reg0 reg0 reg1
+            end:
+                Ret reg2
+            .end",
+        );
+        code.ints = vec![3, 1, 0, 2];
+        assert_eq!(
+            decompiled(&code, 0),
+            "var b = 3;\nswitch (b) {\n  case 0:\n    b = b + 1;\n  case 1:\n    if (0 < b) {\n      b = b + 2;\n    }\n  case 2:\n    b = b + 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn switch_enum() {
+        // var b = 0; switch (e) { case B(x): if (x <= 0) b = -x else b = x; case A: b = 1; }
+        // The else of the first case ends at the end of the switch
+        let mut code = build(
+            &["", "E", "A", "B", "$Switch", "main", "b", "x", "e"],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Enum {
+                    name: RefString(1),
+                    global: RefGlobal(0),
+                    constructs: vec![
+                        EnumConstruct {
+                            name: RefString(2),
+                            params: Vec::new(),
+                        },
+                        EnumConstruct {
+                            name: RefString(3),
+                            params: vec![RefType(1)],
+                        },
+                    ],
+                },
+                fun(&[2], 0),
+                class(4, &[(5, 3, 0)]),
+            ],
+            &[],
+            "
+            .fun fn@0 type@3
+            .assigns
+            .assign string@8 0
+            .assign string@6 1
+            .assign string@7 5
+            .assign string@6 8
+            .assign string@6 10
+            .assign string@6 12
+            .reg type@2
+            .reg type@1
+            .reg type@1
+            .reg type@1
+            .reg type@0
+                Int reg2 int@0
+                EnumIndex reg3 reg0
+                Switch reg3 [caseA, caseB] 9
+                JAlways end
+            caseB:
+                EnumField reg1 reg0 construct@1 field@0
+                Int reg3 int@0
+                JSGt reg1 reg3 else
+                Neg reg2 reg1
+                JAlways end
+            else:
+                Mov reg2 reg1
+                JAlways end
+            caseA:
+                Int reg2 int@1
+            end:
+                Ret reg4
+            .end",
+        );
+        code.ints = vec![0, 1];
+        assert_eq!(
+            decompiled(&code, 0),
+            "var b = 0;\nswitch (e) {\n  case B(x):\n    b = if (0 >= x) {\n      -x;\n    } else {\n      x;\n    };\n  case A:\n    b = 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn map_literal() {
+        // var m = new StringMap(); m.set("a", 1); m.set("b", 2); show(m); m.set("c", 3); var v = m.get("a"); showInt(v);
+        let mut code = build(
+            &[
+                "",
+                "haxe.ds.StringMap",
+                "set",
+                "get",
+                "String",
+                "$Main",
+                "main",
+                "show",
+                "showInt",
+                "m",
+                "v",
+                "a",
+                "b",
+                "c",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                class(1, &[(2, 5, 1), (3, 6, 2)]),
+                class(4, &[]),
+                fun(&[], 0),
+                fun(&[2, 3, 1], 0),
+                fun(&[2, 3], 1),
+                fun(&[2], 0),
+                fun(&[1], 0),
+                class(5, &[(6, 4, 0), (7, 7, 3), (8, 8, 4)]),
+            ],
+            &[],
+            "
+            .fun fn@0 type@4
+            .assigns
+            .assign string@9 1
+            .assign string@10 14
+            .reg type@2
+            .reg type@3
+            .reg type@1
+            .reg type@0
+            .reg type@1
+                New reg0
+                Call1 reg3 fn@5 reg0
+                String reg1 string@11
+                Int reg2 int@0
+                Call3 reg3 fn@1 reg0 reg1 reg2
+                String reg1 string@12
+                Int reg2 int@1
+                Call3 reg3 fn@1 reg0 reg1 reg2
+                Call1 reg3 fn@3 reg0
+                String reg1 string@13
+                Int reg2 int@2
+                Call3 reg3 fn@1 reg0 reg1 reg2
+                String reg1 string@11
+                Call2 reg4 fn@2 reg0 reg1
+                Call1 reg3 fn@4 reg4
+                Ret reg3
+            .end
+            .fun fn@1 type@5
+            .reg type@2
+            .reg type@3
+            .reg type@1
+            .reg type@0
+                Ret reg3
+            .end
+            .fun fn@2 type@6
+            .reg type@2
+            .reg type@3
+                Ret reg1
+            .end
+            .fun fn@3 type@7
+            .reg type@2
+            .reg type@0
+                Ret reg1
+            .end
+            .fun fn@4 type@8
+            .reg type@1
+            .reg type@0
+                Ret reg1
+            .end
+            .fun fn@5 type@7
+            .reg type@2
+            .reg type@0
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![1, 2, 3];
+        // Only the insertions right after the construction are part of the literal
+        assert_eq!(
+            decompiled(&code, 0),
+            "var m = [\"a\" => 1, \"b\" => 2];\n// show@3\nshow(m);\nm[\"c\"] = 3;\nvar v = m[\"a\"];\n// showInt@4\nshowInt(v);\n"
+        );
+    }
+
+    #[test]
+    fn references() {
+        // function main(r:hl.Ref<Int>) { r.set(r.get() + 1); var x = 5; out_native(x); x = 1; r.set(x); }
+        // x is implicitly converted to a reference when passed to the native
+        let mut code = build(
+            &["", "$Main", "main", "r", "x", "std", "out_native"],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Ref(RefType(1)),
+                fun(&[2], 0),
+                class(1, &[(2, 3, 0)]),
+            ],
+            &[(5, 6, 3, 1)],
+            "
+            .fun fn@0 type@3
+            .assigns
+            .assign string@3 0
+            .assign string@4 5
+            .reg type@2
+            .reg type@1
+            .reg type@1
+            .reg type@1
+            .reg type@2
+            .reg type@0
+                Unref reg1 reg0
+                Int reg2 int@0
+                Add reg1 reg1 reg2
+                Setref reg0 reg1
+                Int reg3 int@1
+                Ref reg4 reg3
+                Call1 reg5 fn@1 reg4
+                Setref reg4 reg2
+                Unref reg2 reg4
+                Setref reg0 reg2
+                Ret reg5
+            .end",
+        );
+        code.ints = vec![1, 5];
+        assert_eq!(
+            decompiled(&code, 0),
+            "r.set(r.get() + 1);\nvar x = 5;\n// out_native@1\nout_native(x);\nx = 1;\nr.set(x);\n"
+        );
+    }
+
+    #[test]
+    fn class_imports() {
+        let mut code = build(
+            &[
+                "",
+                "h2d.Point",
+                "game.Enemy",
+                "foo.Vec",
+                "bar.Vec",
+                "haxe.ds.StringMap",
+                "game.Player",
+                "update",
+                "pos",
+                "enemy",
+                "a",
+                "b",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                class(1, &[]),
+                class(2, &[]),
+                class(3, &[]),
+                class(4, &[]),
+                class(5, &[]),
+                fun(&[8], 0),
+                class(6, &[(7, 7, 0)]),
+            ],
+            &[],
+            "
+            .fun fn@0 type@7
+            .reg type@8
+            .reg type@6
+            .reg type@0
+                New reg1
+                Ret reg2
+            .end",
+        );
+        let player = code.types[8].get_type_obj_mut().unwrap();
+        for (name, t) in [(8, 2), (9, 3), (10, 4), (11, 5)] {
+            player.own_fields.push(ObjField {
+                name: RefString(name),
+                t: RefType(t),
+            });
+        }
+        player.fields = player.own_fields.clone();
+
+        let class = decompile_class(&code, code.types[8].get_type_obj().unwrap());
+        // Types in the same package and types with the same short name aren't imported
+        assert_eq!(class.imports, ["h2d.Point", "haxe.ds.StringMap"]);
+        let output = class.display(&code, &FormatOptions::new(2)).to_string();
+        assert!(
+            output.starts_with(
+                "package game;\n\nimport h2d.Point;\nimport haxe.ds.StringMap;\n\nclass Player {\n  var pos: Point;\n  var enemy: Enemy;\n  var a: foo.Vec;\n  var b: bar.Vec;\n"
+            ),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn typedefs() {
+        let virtual_ = |fields: &[(usize, usize)]| Type::Virtual {
+            fields: fields
+                .iter()
+                .map(|&(name, t)| ObjField {
+                    name: RefString(name),
+                    t: RefType(t),
+                })
+                .collect(),
+        };
+        let mut code = build(
+            &[
+                "",
+                "game.Lobby",
+                "lobbyData",
+                "pos",
+                "id",
+                "score",
+                "online",
+                "age",
+                "x",
+                "y",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::F64,
+                Type::Bool,
+                Type::Null(RefType(1)),
+                virtual_(&[(4, 1), (5, 2), (6, 3), (7, 4)]),
+                virtual_(&[(8, 1), (9, 4)]),
+                class(1, &[]),
+            ],
+            &[],
+            "",
+        );
+        let lobby = code.types[7].get_type_obj_mut().unwrap();
+        for (name, t) in [(2, 5), (3, 6)] {
+            lobby.own_fields.push(ObjField {
+                name: RefString(name),
+                t: RefType(t),
+            });
+        }
+        lobby.fields = lobby.own_fields.clone();
+
+        let class = decompile_class(&code, code.types[7].get_type_obj().unwrap());
+        // Named after the field, the small virtual stays inline
+        assert_eq!(class.typedefs, [RefType(5)]);
+        assert_eq!(
+            class.display(&code, &FormatOptions::new(2)).to_string(),
+            "package game;\n\ntypedef LobbyData = {\n  var id: Int;\n  var score: Float;\n  var online: Bool;\n  @:optional var age: Int;\n}\n\nclass Lobby {\n  var lobbyData: LobbyData;\n  var pos: { x: Int, ?y: Int };\n}"
+        );
+    }
+
     #[test]
     fn decomp_code_all() {
         for entry in fs::read_dir("../../data").unwrap() {
@@ -1,18 +1,22 @@
-use hlbc::Bytecode;
+use hlbc::types::{Function, RefField, Type};
+use hlbc::{Bytecode, Str};
 
-use crate::ast::{add, ConstructorCall, Expr, Operation, Statement};
+use crate::ast::{add, call, Constant, ConstructorCall, Expr, Operation, Statement};
+use crate::collections::MAP_TYPES;
 use crate::call_fun;
 
 pub(crate) trait AstVisitor {
     fn visit_stmt(&mut self, code: &Bytecode, stmt: &mut Statement) {}
     fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {}
+    /// Visit a list of statements, after each statement has been visited
+    fn visit_block(&mut self, _code: &Bytecode, _stmts: &mut Vec<Statement>) {}
 }
 
 /// Visit everything depth-first
 pub(crate) fn visit(
     code: &Bytecode,
-    stmts: &mut [Statement],
-    visitors: &mut [Box<dyn AstVisitor>],
+    stmts: &mut Vec<Statement>,
+    visitors: &mut [Box<dyn AstVisitor + '_>],
 ) {
     // Recurse
     macro_rules! rec {
@@ -26,7 +30,7 @@ pub(crate) fn visit(
             visit_expr(code, $e, visitors)
         };
     }
-    for stmt in stmts {
+    for stmt in stmts.iter_mut() {
         // No _ pattern, wouldn't want this match to de-sync when adding new items
         match stmt {
             Statement::Assign {
@@ -66,11 +70,9 @@ pub(crate) fn visit(
             Statement::Throw(e) => {
                 v!(e);
             }
-            Statement::Try { stmts } => {
-                rec!(stmts);
-            }
-            Statement::Catch { stmts } => {
+            Statement::Try { stmts, catches } => {
                 rec!(stmts);
+                catches.iter_mut().for_each(|c| rec!(&mut c.stmts));
             }
             Statement::Comment(_) => {}
         }
@@ -78,10 +80,17 @@ pub(crate) fn visit(
             visitor.visit_stmt(code, stmt);
         }
     }
+    for visitor in visitors.iter_mut() {
+        visitor.visit_block(code, stmts);
+    }
 }
 
 /// Visit expressions by depth-first recursion into [Expr].
-pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<dyn AstVisitor>]) {
+pub(crate) fn visit_expr(
+    code: &Bytecode,
+    expr: &mut Expr,
+    visitors: &mut [Box<dyn AstVisitor + '_>],
+) {
     // Recurse
     macro_rules! rec {
         ($e:expr) => {
@@ -105,6 +114,11 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
             rec!(arr);
             rec!(index);
         }
+        Expr::ArrayInit(values) => {
+            for e in values {
+                rec!(e);
+            }
+        }
         Expr::Call(call) => {
             rec!(&mut call.fun);
             for arg in call.args.iter_mut() {
@@ -127,6 +141,12 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
         Expr::Field(obj, _) => {
             rec!(obj);
         }
+        Expr::MapInit(pairs) => {
+            for (k, v) in pairs {
+                rec!(k);
+                rec!(v);
+            }
+        }
         Expr::FunRef(_) => {}
         Expr::IfElse { cond, if_, else_ } => {
             rec!(cond);
@@ -211,6 +231,7 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
                 rec!(e2);
             }
         },
+        Expr::TypeName(_) => {}
         Expr::Unknown(_) => {}
         Expr::Variable(_, _) => {}
     }
@@ -351,30 +372,447 @@ impl AstVisitor for Itos {
     }
 }
 
-/// Restore inlined `trace` calls.
+/// Restore `trace` calls, `haxe.Log.trace(v, posInfos)` becomes `trace(v)`.
+/// The position from the pos infos is kept as a comment before the call.
+/// ```haxe
+/// // Main.hx:12
+/// trace("hello");
+/// ```
 pub(crate) struct Trace;
 
-impl AstVisitor for Trace {
-    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+impl Trace {
+    /// Returns the `trace` call and the position of the call if the expression is a call to `haxe.Log.trace`.
+    fn replace(code: &Bytecode, expr: &Expr) -> Option<(Expr, Option<String>)> {
         let call = match expr {
-            Expr::Call(call) => match &call.fun {
-                Expr::Field(obj, field) => match obj.as_ref() {
-                    Expr::Variable(_, _) => {
-                        if field == "trace" {
-                            let trace = code.function_by_name(field).unwrap();
-                            Some(call_fun(trace.findex, vec![call.args[0].clone()]))
-                        } else {
-                            None
-                        }
-                    }
-                    _ => None,
-                },
-                _ => None,
-            },
+            Expr::Call(call) => call,
+            _ => return None,
+        };
+        match &call.fun {
+            Expr::Field(obj, field) if field == "trace" && call.args.len() == 2 => {
+                if !matches!(obj.as_ref(), Expr::Variable(_, _)) {
+                    return None;
+                }
+                let trace = code.function_by_name(field)?;
+                Some((
+                    call_fun(trace.findex, vec![call.args[0].clone()]),
+                    Self::position(code, &call.args[1]),
+                ))
+            }
             _ => None,
+        }
+    }
+
+    /// Format the `file:line` position from a `haxe.PosInfos` anonymous structure
+    fn position(code: &Bytecode, pos_infos: &Expr) -> Option<String> {
+        let (ty, values) = match pos_infos {
+            Expr::Anonymous(ty, values) => (ty, values),
+            _ => return None,
         };
-        if let Some(call) = call {
+        let fields = match &code[*ty] {
+            Type::Virtual { fields } => fields,
+            _ => return None,
+        };
+        let value = |name: &str| {
+            fields
+                .iter()
+                .position(|f| f.name(code) == name)
+                .and_then(|i| values.get(&RefField(i)))
+        };
+        match (value("fileName")?, value("lineNumber")?) {
+            (
+                Expr::Constant(Constant::String(file)),
+                Expr::Constant(Constant::Int(line)),
+            ) => Some(format!("{}:{}", code[*file], code[*line])),
+            _ => None,
+        }
+    }
+}
+
+impl AstVisitor for Trace {
+    fn visit_block(&mut self, code: &Bytecode, stmts: &mut Vec<Statement>) {
+        let mut i = 0;
+        while i < stmts.len() {
+            if let Statement::ExprStatement(expr) = &mut stmts[i] {
+                if let Some((trace, position)) = Self::replace(code, expr) {
+                    *expr = trace;
+                    if let Some(position) = position {
+                        stmts.insert(i, Statement::Comment(position));
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Restore calls to std functions inlined by the Haxe compiler.
+/// ```haxe
+/// s.split("a").join("b")
+/// ```
+/// becomes :
+/// ```haxe
+/// StringTools.replace(s, "a", "b")
+/// ```
+/// Patterns are tried in the order of [INLINED_STD]. The function is used to get the type of its registers.
+pub(crate) struct InlinedStd<'f>(pub(crate) &'f Function);
+
+/// A pattern matching an inlined function body, returns the call replacing the expression.
+type InlinedPattern = fn(&Bytecode, &Function, &Expr) -> Option<Expr>;
+
+/// Known inlined std functions
+const INLINED_STD: &[InlinedPattern] = &[
+    string_tools_replace,
+    string_tools_contains,
+    math_abs,
+    math_min_max,
+];
+
+impl InlinedStd<'_> {
+    fn replace(&self, code: &Bytecode, expr: &mut Expr) {
+        if let Some(call) = INLINED_STD
+            .iter()
+            .find_map(|pattern| pattern(code, self.0, expr))
+        {
             *expr = call;
         }
     }
 }
+
+impl AstVisitor for InlinedStd<'_> {
+    fn visit_stmt(&mut self, code: &Bytecode, stmt: &mut Statement) {
+        // If expressions are created on statements after their expressions have been visited
+        if let Statement::Assign {
+            assign: assign @ Expr::IfElse { .. },
+            ..
+        } = stmt
+        {
+            self.replace(code, assign);
+        }
+    }
+
+    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+        self.replace(code, expr);
+    }
+}
+
+/// Call to a static std function
+fn std_call(class: &'static str, fun: &'static str, args: Vec<Expr>) -> Expr {
+    call(
+        Expr::Field(
+            Box::new(Expr::TypeName(Str::from_static(class))),
+            Str::from_static(fun),
+        ),
+        args,
+    )
+}
+
+/// Match a method call : `receiver.name(args)`
+fn method_call<'e>(expr: &'e Expr, name: &str) -> Option<(&'e Expr, &'e [Expr])> {
+    match expr {
+        Expr::Call(call) => match &call.fun {
+            Expr::Field(receiver, field) if field == name => Some((receiver, &call.args)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Match an if expression with a single expression in each branch
+fn ternary(expr: &Expr) -> Option<(&Expr, &Expr, &Expr)> {
+    match expr {
+        Expr::IfElse { cond, if_, else_ } => match (if_.as_slice(), else_.as_slice()) {
+            ([Statement::ExprStatement(a)], [Statement::ExprStatement(b)]) => Some((cond, a, b)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_int(code: &Bytecode, expr: &Expr, value: i32) -> bool {
+    match expr {
+        Expr::Constant(Constant::InlineInt(c)) => value >= 0 && *c == value as usize,
+        Expr::Constant(Constant::Int(c)) => code[*c] == value,
+        Expr::Constant(Constant::Float(c)) => code[*c] == value as f64,
+        _ => false,
+    }
+}
+
+/// We can't compare arbitrary expressions, only variables.
+fn same_var(a: &Expr, b: &Expr) -> bool {
+    matches!((a, b), (Expr::Variable(r1, _), Expr::Variable(r2, _)) if r1 == r2)
+}
+
+/// The type of arbitrary expressions isn't known, only variables and constants.
+fn is_float(code: &Bytecode, f: &Function, expr: &Expr) -> bool {
+    match expr {
+        Expr::Variable(reg, _) => code[f[*reg]].is_float(),
+        Expr::Constant(Constant::Float(_)) => true,
+        _ => false,
+    }
+}
+
+/// Same as [is_float], for a String
+fn is_string(code: &Bytecode, f: &Function, expr: &Expr) -> bool {
+    match expr {
+        Expr::Variable(reg, _) => f[*reg]
+            .as_obj(code)
+            .map(|obj| obj.name(code) == "String")
+            .unwrap_or(false),
+        Expr::Constant(Constant::String(_)) => true,
+        _ => false,
+    }
+}
+
+/// `s.split(sub).join(by)` => `StringTools.replace(s, sub, by)`, arrays also have a `join` method.
+fn string_tools_replace(code: &Bytecode, f: &Function, expr: &Expr) -> Option<Expr> {
+    let (split, [by]) = method_call(expr, "join")? else {
+        return None;
+    };
+    let (s, [sub]) = method_call(split, "split")? else {
+        return None;
+    };
+    if !is_string(code, f, s) {
+        return None;
+    }
+    Some(std_call(
+        "StringTools",
+        "replace",
+        vec![s.clone(), sub.clone(), by.clone()],
+    ))
+}
+
+/// `s.indexOf(value) != -1` => `StringTools.contains(s, value)`
+fn string_tools_contains(code: &Bytecode, _f: &Function, expr: &Expr) -> Option<Expr> {
+    let Expr::Op(Operation::NotEq(a, b)) = expr else {
+        return None;
+    };
+    if !is_int(code, b, -1) {
+        return None;
+    }
+    let (s, [value]) = method_call(a, "indexOf")? else {
+        return None;
+    };
+    Some(std_call(
+        "StringTools",
+        "contains",
+        vec![s.clone(), value.clone()],
+    ))
+}
+
+/// `if (x < 0) -x else x` => `Math.abs(x)`. `Math.abs` returns a Float, the pattern on an Int is left as is.
+fn math_abs(code: &Bytecode, f: &Function, expr: &Expr) -> Option<Expr> {
+    let (cond, a, b) = ternary(expr)?;
+    match (cond, a) {
+        (Expr::Op(Operation::Lt(x, zero)), Expr::Op(Operation::Neg(nx)))
+            if is_int(code, zero, 0)
+                && same_var(x, nx)
+                && same_var(x, b)
+                && is_float(code, f, x) =>
+        {
+            Some(std_call("Math", "abs", vec![b.clone()]))
+        }
+        _ => None,
+    }
+}
+
+/// `if (a < b) a else b` => `Math.min(a, b)`, `if (a < b) b else a` => `Math.max(a, b)`.
+/// Only on Floats like [math_abs], `<` also compares strings.
+fn math_min_max(code: &Bytecode, f: &Function, expr: &Expr) -> Option<Expr> {
+    let (cond, a, b) = ternary(expr)?;
+    if !is_float(code, f, a) || !is_float(code, f, b) {
+        return None;
+    }
+    let (x, y, min) = match cond {
+        Expr::Op(Operation::Lt(x, y)) => (x, y, true),
+        Expr::Op(Operation::Gt(x, y)) => (x, y, false),
+        _ => return None,
+    };
+    let min = if same_var(x, a) && same_var(y, b) {
+        min
+    } else if same_var(x, b) && same_var(y, a) {
+        !min
+    } else {
+        return None;
+    };
+    Some(std_call(
+        "Math",
+        if min { "min" } else { "max" },
+        vec![a.clone(), b.clone()],
+    ))
+}
+
+/// Restore map literals from a map construction followed by insertions.
+/// ```haxe
+/// var m = new haxe.ds.StringMap();
+/// m["a"] = 1;
+/// m["b"] = 2;
+/// ```
+/// becomes :
+/// ```haxe
+/// var m = ["a" => 1, "b" => 2];
+/// ```
+pub(crate) struct MapLiterals;
+
+impl AstVisitor for MapLiterals {
+    fn visit_block(&mut self, code: &Bytecode, stmts: &mut Vec<Statement>) {
+        let mut i = 0;
+        while i < stmts.len() {
+            let map = match &stmts[i] {
+                Statement::Assign {
+                    variable: Expr::Variable(reg, _),
+                    assign: Expr::Constructor(ConstructorCall { ty, args }),
+                    ..
+                } if args.is_empty()
+                    && ty
+                        .as_obj(code)
+                        .map(|obj| MAP_TYPES.contains(&&*obj.name(code)))
+                        .unwrap_or(false) =>
+                {
+                    *reg
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            // Consecutive insertions in that map
+            let mut pairs = Vec::new();
+            while let Some(Statement::Assign {
+                declaration: false,
+                variable: Expr::Array(obj, key),
+                assign,
+            }) = stmts.get(i + 1 + pairs.len())
+            {
+                if !matches!(obj.as_ref(), Expr::Variable(r, _) if *r == map) {
+                    break;
+                }
+                pairs.push((key.as_ref().clone(), assign.clone()));
+            }
+            if !pairs.is_empty() {
+                let n = pairs.len();
+                if let Statement::Assign { assign, .. } = &mut stmts[i] {
+                    *assign = Expr::MapInit(pairs);
+                }
+                stmts.drain(i + 1..i + 1 + n);
+            }
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::testing::{build, class, fun};
+    use hlbc::types::{RefString, Reg, Type};
+    use hlbc::{Bytecode, Str};
+
+    use crate::ast::{call, gt, lt, neg, stmt, Constant, Expr};
+    use crate::fmt::FormatOptions;
+    use crate::post::{AstVisitor, InlinedStd};
+
+    /// Function with registers i:Int, j:Int, x:Float, y:Float, s:String, d:Dynamic
+    fn sample() -> Bytecode {
+        build(
+            &["", "String", "$Main", "main", "a", "b"],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::F64,
+                class(1, &[]),
+                Type::Dyn,
+                fun(&[1, 1, 2, 2, 3, 4], 0),
+                class(2, &[(3, 5, 0)]),
+            ],
+            &[],
+            "
+            .fun fn@0 type@5
+            .reg type@1
+            .reg type@1
+            .reg type@2
+            .reg type@2
+            .reg type@3
+            .reg type@4
+            .reg type@0
+                Ret reg6
+            .end",
+        )
+    }
+
+    fn var(reg: u32) -> Expr {
+        let names = ["i", "j", "x", "y", "s", "d"];
+        Expr::Variable(Reg(reg), Some(Str::from(names[reg as usize])))
+    }
+
+    fn string(s: usize) -> Expr {
+        Expr::Constant(Constant::String(RefString(s)))
+    }
+
+    fn ternary(cond: Expr, a: Expr, b: Expr) -> Expr {
+        Expr::IfElse {
+            cond: Box::new(cond),
+            if_: vec![stmt(a)],
+            else_: vec![stmt(b)],
+        }
+    }
+
+    fn method(receiver: Expr, name: &'static str, args: Vec<Expr>) -> Expr {
+        call(
+            Expr::Field(Box::new(receiver), Str::from_static(name)),
+            args,
+        )
+    }
+
+    /// The expression after the inlined std functions are restored
+    fn inlined(code: &Bytecode, mut expr: Expr) -> String {
+        let f = &code.functions[0];
+        InlinedStd(f).visit_expr(code, &mut expr);
+        expr.display(&FormatOptions::new(2), code, f).to_string()
+    }
+
+    #[test]
+    fn math_abs() {
+        let code = sample();
+        let zero = || Expr::Constant(Constant::InlineInt(0));
+        let abs = |x: u32| ternary(lt(var(x), zero()), neg(var(x)), var(x));
+        assert_eq!(inlined(&code, abs(2)), "Math.abs(x)");
+        // Math.abs returns a Float
+        assert!(inlined(&code, abs(0)).starts_with("if (i < 0)"));
+    }
+
+    #[test]
+    fn math_min_max() {
+        let code = sample();
+        let min = |x, y| ternary(lt(var(x), var(y)), var(x), var(y));
+        assert_eq!(inlined(&code, min(2, 3)), "Math.min(x, y)");
+        assert_eq!(
+            inlined(&code, ternary(gt(var(2), var(3)), var(2), var(3))),
+            "Math.max(x, y)"
+        );
+        assert_eq!(
+            inlined(&code, ternary(lt(var(2), var(3)), var(3), var(2))),
+            "Math.max(y, x)"
+        );
+        assert!(inlined(&code, min(0, 1)).starts_with("if (i < j)"));
+        assert!(inlined(&code, min(4, 5)).starts_with("if (s < d)"));
+    }
+
+    #[test]
+    fn string_tools_replace() {
+        let code = sample();
+        let replace = |s| method(method(s, "split", vec![string(4)]), "join", vec![string(5)]);
+        assert_eq!(
+            inlined(&code, replace(var(4))),
+            "StringTools.replace(s, \"a\", \"b\")"
+        );
+        assert_eq!(
+            inlined(&code, replace(string(4))),
+            "StringTools.replace(\"a\", \"a\", \"b\")"
+        );
+        // Could be an array
+        assert_eq!(
+            inlined(&code, replace(var(5))),
+            "d.split(\"a\").join(\"b\")"
+        );
+    }
+}
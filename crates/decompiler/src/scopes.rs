@@ -1,4 +1,6 @@
-use crate::ast::{Constant, Expr, Statement};
+use hlbc::types::{RefEnumConstruct, RefType, Reg};
+
+use crate::ast::{CatchClause, Expr, Statement};
 
 #[derive(Debug)]
 pub(crate) enum ScopeType {
@@ -16,20 +18,41 @@ pub(crate) enum ScopeData {
         if_cond: Expr,
         if_stmts: Vec<Statement>,
     },
+    /// The statements of the scope are the default case
     Switch {
         arg: Expr,
-        offsets: Vec<usize>,
-        cases: Vec<(Expr, Vec<Statement>)>,
+        /// Position of the first instruction of each case with its patterns, in instruction order
+        starts: Vec<(usize, Vec<Expr>)>,
+        /// Target of the jumps out of the cases
+        end: usize,
+        /// Register holding the enum value of a switch on an enum index
+        enum_value: Option<Reg>,
+        cases: Vec<(Vec<Expr>, Vec<Statement>)>,
     },
     SwitchCase {
-        pattern: Expr,
+        patterns: Vec<Expr>,
     },
     Loop {
         start: usize,
         cond: Expr,
     },
-    Try,
-    Catch,
+    Try {
+        /// Target of the jump over the catch clauses
+        end: usize,
+        /// Position of the first instruction of the body of each catch clause with its variable and its type
+        clauses: Vec<(usize, Expr, RefType)>,
+    },
+    /// The statements of the scope are the instructions of the handler outside the catch clauses (type tests)
+    Catch {
+        try_stmts: Vec<Statement>,
+        end: usize,
+        clauses: Vec<(usize, Expr, RefType)>,
+        catches: Vec<CatchClause>,
+    },
+    CatchClause {
+        var: Expr,
+        ty: RefType,
+    },
 }
 
 #[derive(Debug)]
@@ -70,13 +93,41 @@ impl Scope {
                 cond,
                 stmts: self.stmts,
             },
-            ScopeData::Try => Statement::Try { stmts: self.stmts },
-            ScopeData::Catch => Statement::Catch { stmts: self.stmts },
+            // No catch clause was found after the try body
+            ScopeData::Try { .. } => Statement::Try {
+                stmts: self.stmts,
+                catches: Vec::new(),
+            },
+            ScopeData::Catch {
+                try_stmts, catches, ..
+            } => Statement::Try {
+                stmts: try_stmts,
+                catches,
+            },
             _ => {
                 unreachable!()
             }
         }
     }
+
+    /// Close a switch case or a catch clause into its parent scope, returns the inner scope otherwise
+    fn close_inner(&mut self, inner: Scope) -> Option<Scope> {
+        match (&mut self.data, inner.data) {
+            (ScopeData::Switch { cases, .. }, ScopeData::SwitchCase { patterns }) => {
+                cases.push((patterns, inner.stmts));
+                None
+            }
+            (ScopeData::Catch { catches, .. }, ScopeData::CatchClause { var, ty }) => {
+                catches.push(CatchClause {
+                    var,
+                    ty,
+                    stmts: inner.stmts,
+                });
+                None
+            }
+            (_, data) => Some(Scope { data, ..inner }),
+        }
+    }
 }
 
 /// Helper to process a stack of scopes (branches, loops)
@@ -104,11 +155,12 @@ impl Scopes {
                 if let Some(stmt) = stmt.take() {
                     scope.stmts.push(stmt);
                 }
-                // Exception for Switch where a switch scope can be closed with a switch case open
-                if let ScopeData::Switch { cases, .. } = &mut scope.data {
-                    let case = self.scopes.remove(i);
-                    if let ScopeData::SwitchCase { pattern } = case.data {
-                        cases.push((pattern, case.stmts));
+                // Exception for Switch and Catch where the scope can be closed with a switch case or a catch clause
+                // open
+                if i < self.scopes.len() {
+                    let inner = self.scopes.remove(i);
+                    if let Some(inner) = scope.close_inner(inner) {
+                        self.scopes.insert(i, inner);
                     }
                 }
                 stmt = Some(scope.make_stmt());
@@ -127,6 +179,13 @@ impl Scopes {
         }
     }
 
+    /// true if a scope ends with the current instruction
+    pub(crate) fn closing(&self) -> bool {
+        self.scopes
+            .iter()
+            .any(|s| matches!(s.ty, ScopeType::Len(len) if len == 1))
+    }
+
     pub(crate) fn statements(mut self) -> Vec<Statement> {
         if let Some(Scope { stmts, data, .. }) = self.scopes.pop() {
             if matches!(data, ScopeData::Root) {
@@ -166,50 +225,60 @@ impl Scopes {
         ));
     }
 
-    pub(crate) fn push_switch(&mut self, len: i32, arg: Expr, offsets: Vec<usize>) {
+    pub(crate) fn push_switch(
+        &mut self,
+        len: i32,
+        arg: Expr,
+        starts: Vec<(usize, Vec<Expr>)>,
+        end: usize,
+        enum_value: Option<Reg>,
+    ) {
         self.scopes.push(Scope::new(
             ScopeType::Len(len),
             ScopeData::Switch {
                 arg,
-                offsets,
+                starts,
+                end,
+                enum_value,
                 cases: Vec::new(),
             },
         ))
     }
 
-    pub(crate) fn push_switch_case(&mut self, cst: usize) {
-        // End the previous switch case scope
-        let previous = {
-            let scope = self.scopes.pop().unwrap();
-            match scope.data {
-                ScopeData::SwitchCase { pattern } => Some((pattern, scope.stmts)),
-                _ => {
-                    self.scopes.push(scope);
-                    None
-                }
+    /// End the previous catch clause scope
+    fn end_inner(&mut self) {
+        let n = self.scopes.len();
+        if n >= 2 && matches!(self.scopes[n - 1].data, ScopeData::CatchClause { .. }) {
+            let inner = self.scopes.pop().unwrap();
+            if let Some(inner) = self.scopes.last_mut().unwrap().close_inner(inner) {
+                self.scopes.push(inner);
             }
-        };
-
-        let scope = self.scopes.last_mut().unwrap();
-        match &mut scope.data {
-            ScopeData::Switch { cases, .. } => {
-                if let Some(previous) = previous {
-                    cases.push(previous);
-                }
+        }
+    }
 
-                self.scopes.push(Scope::new(
-                    ScopeType::Manual,
-                    ScopeData::SwitchCase {
-                        pattern: Expr::Constant(Constant::InlineInt(cst)),
-                    },
-                ));
-            }
-            _ => {
-                panic!("Pushing a switch case with no outer switch !");
+    /// Close the scopes nested in the scope at `index`, like a case body ending before its nested scopes
+    fn close_nested(&mut self, index: usize) {
+        while self.scopes.len() > index + 1 {
+            let inner = self.scopes.pop().unwrap();
+            let parent = self.scopes.last_mut().unwrap();
+            if let Some(inner) = parent.close_inner(inner) {
+                parent.stmts.push(inner.make_stmt());
             }
         }
     }
 
+    pub(crate) fn push_switch_case(&mut self, patterns: Vec<Expr>) {
+        let index = self
+            .switch_index()
+            .expect("Pushing a switch case with no outer switch !");
+        // Also ends the previous case
+        self.close_nested(index);
+        self.scopes.push(Scope::new(
+            ScopeType::Manual,
+            ScopeData::SwitchCase { patterns },
+        ));
+    }
+
     pub(crate) fn push_loop(&mut self, start: usize) {
         self.scopes.push(Scope::new(
             ScopeType::Manual,
@@ -220,14 +289,48 @@ impl Scopes {
         ))
     }
 
-    pub(crate) fn push_try(&mut self, len: i32) {
-        self.scopes
-            .push(Scope::new(ScopeType::Len(len), ScopeData::Try))
+    pub(crate) fn push_try(&mut self, len: i32, end: usize, clauses: Vec<(usize, Expr, RefType)>) {
+        self.scopes.push(Scope::new(
+            ScopeType::Len(len),
+            ScopeData::Try { end, clauses },
+        ))
     }
 
+    /// Replace the current try scope with the scope of its catch clauses
     pub(crate) fn push_catch(&mut self, len: i32) {
-        self.scopes
-            .push(Scope::new(ScopeType::Len(len), ScopeData::Catch))
+        let (try_stmts, end, clauses) = self
+            .scopes
+            .pop()
+            .and_then(|s| match s.data {
+                ScopeData::Try { end, clauses } => Some((s.stmts, end, clauses)),
+                _ => None,
+            })
+            .expect("Catch without Try ?");
+
+        self.scopes.push(Scope::new(
+            ScopeType::Len(len),
+            ScopeData::Catch {
+                try_stmts,
+                end,
+                clauses,
+                catches: Vec::new(),
+            },
+        ));
+    }
+
+    pub(crate) fn push_catch_clause(&mut self, var: Expr, ty: RefType) {
+        self.end_inner();
+        match &self.scopes.last().unwrap().data {
+            ScopeData::Catch { .. } => {
+                self.scopes.push(Scope::new(
+                    ScopeType::Manual,
+                    ScopeData::CatchClause { var, ty },
+                ));
+            }
+            _ => {
+                panic!("Pushing a catch clause with no outer catch !");
+            }
+        }
     }
 
     //region QUERIES
@@ -255,16 +358,87 @@ impl Scopes {
         })
     }
 
-    /// Returns the switch jump offsets if the current scope is a switch (or a switch case)
-    pub(crate) fn last_is_switch_ctx(&self) -> Option<&[usize]> {
-        self.scopes.last().and_then(|s| match &s.data {
-            ScopeData::Switch { offsets, .. } => Some(offsets.as_slice()),
-            ScopeData::SwitchCase { .. } => match &self.scopes[self.scopes.len() - 2].data {
-                ScopeData::Switch { offsets, .. } => Some(offsets.as_slice()),
+    /// Index of the innermost switch scope, the current scope may be one of its cases or a scope nested in a case
+    fn switch_index(&self) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rposition(|s| matches!(s.data, ScopeData::Switch { .. }))
+    }
+
+    /// Returns the target of the jumps out of the cases of the innermost switch
+    pub(crate) fn last_switch_end(&self) -> Option<usize> {
+        match self.scopes[self.switch_index()?].data {
+            ScopeData::Switch { end, .. } => Some(end),
+            _ => None,
+        }
+    }
+
+    /// Returns the patterns of the case starting at `pos` if there is an open switch (even with a scope still open in
+    /// the previous case)
+    pub(crate) fn switch_case_at(&self, pos: usize) -> Option<Vec<Expr>> {
+        match &self.scopes[self.switch_index()?].data {
+            ScopeData::Switch { starts, .. } => starts
+                .iter()
+                .find(|(start, _)| *start == pos)
+                .map(|(_, patterns)| patterns.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the arguments of the pattern if we are in the case of an enum switch matching the constructor
+    /// `construct` of the enum in `value`, possibly in a scope nested in the case
+    pub(crate) fn enum_case_args_mut(
+        &mut self,
+        value: Reg,
+        construct: RefEnumConstruct,
+    ) -> Option<&mut Vec<Expr>> {
+        let index = self.scopes.iter().rposition(
+            |s| matches!(s.data, ScopeData::Switch { enum_value: Some(v), .. } if v == value),
+        )?;
+        match &mut self.scopes.get_mut(index + 1)?.data {
+            ScopeData::SwitchCase { patterns } => match patterns.as_mut_slice() {
+                [Expr::EnumConstr(_, c, args)] if *c == construct => Some(args),
                 _ => None,
             },
             _ => None,
-        })
+        }
+    }
+
+    /// Returns the target of the jump over the catch clauses if the current scope is a try
+    pub(crate) fn last_try_end(&self) -> Option<usize> {
+        match self.scopes.last()?.data {
+            ScopeData::Try { end, .. } => Some(end),
+            _ => None,
+        }
+    }
+
+    /// Returns the catch scope if the current scope is a catch (or a catch clause)
+    fn last_catch(&self) -> Option<&ScopeData> {
+        let data = match &self.scopes.last()?.data {
+            ScopeData::CatchClause { .. } => &self.scopes[self.scopes.len() - 2].data,
+            data => data,
+        };
+        matches!(data, ScopeData::Catch { .. }).then_some(data)
+    }
+
+    /// Returns the target of the jumps out of the catch clauses if the current scope is a catch (or a catch clause)
+    pub(crate) fn last_catch_end(&self) -> Option<usize> {
+        match self.last_catch()? {
+            ScopeData::Catch { end, .. } => Some(*end),
+            _ => None,
+        }
+    }
+
+    /// Returns the variable and the type of the catch clause whose body starts at `pos` if the current scope is a
+    /// catch (or a catch clause)
+    pub(crate) fn catch_clause_at(&self, pos: usize) -> Option<(Expr, RefType)> {
+        match self.last_catch()? {
+            ScopeData::Catch { clauses, .. } => clauses
+                .iter()
+                .find(|(start, ..)| *start == pos)
+                .map(|(_, var, ty)| (var.clone(), *ty)),
+            _ => None,
+        }
     }
 
     pub(crate) fn last_is_if(&self) -> bool {
@@ -274,6 +448,13 @@ impl Scopes {
             .unwrap_or(false)
     }
 
+    /// true if the current scope is an if ending with the current instruction
+    pub(crate) fn last_is_if_closing(&self) -> bool {
+        self.scopes.last().map_or(false, |s| {
+            matches!(s.ty, ScopeType::Len(1)) && matches!(s.data, ScopeData::If { .. })
+        })
+    }
+
     pub(crate) fn has_scopes(&self) -> bool {
         self.scopes.len() > 1
     }
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use hlbc::rename::Symbol;
+use hlbc::types::{Function, RefType, Type};
+use hlbc::{Bytecode, Str};
+
+/// Virtual types with at least this many fields are displayed as a named typedef instead of inline
+pub const TYPEDEF_MIN_FIELDS: usize = 4;
+
+/// Whether a type is a virtual large enough to be declared as a typedef
+pub fn is_typedef(code: &Bytecode, ty: RefType) -> bool {
+    matches!(&code[ty], Type::Virtual { fields } if fields.len() >= TYPEDEF_MIN_FIELDS)
+}
+
+/// Name of the typedef declaring a virtual type.
+///
+/// The user rename comes first, then a name from the first class field or function argument with this type
+/// (`lobbyData` gives `LobbyData`). Falls back to `Virtual` followed by the type index.
+pub fn typedef_name(code: &Bytecode, ty: RefType) -> Str {
+    if let Some(name) = code.renames.get(Symbol::Virtual(ty)) {
+        return name;
+    }
+    let field = code
+        .types
+        .iter()
+        .filter_map(|t| t.get_type_obj())
+        .flat_map(|obj| &obj.own_fields)
+        .find(|f| f.t == ty)
+        .map(|f| f.name(code));
+    let arg = || {
+        code.functions.iter().find_map(|f: &Function| {
+            let args = &f.ty(code).args;
+            let i = args.iter().position(|&t| t == ty)?;
+            f.arg_name(code, i)
+        })
+    };
+    match field.or_else(arg) {
+        Some(name) if !name.is_empty() => {
+            let mut chars = name.trim_start_matches('_').chars();
+            match chars.next() {
+                Some(first) => Str::from(first.to_uppercase().chain(chars).collect::<String>()),
+                None => Str::from(format!("Virtual{}", ty.0)),
+            }
+        }
+        _ => Str::from(format!("Virtual{}", ty.0)),
+    }
+}
+
+/// Optional fields of anonymous structures are stored as `Null<T>`, returns `T` for an optional field.
+pub fn optional_type(code: &Bytecode, ty: RefType) -> Option<RefType> {
+    match &code[ty] {
+        Type::Null(inner) => Some(*inner),
+        _ => None,
+    }
+}
+
+/// Typedefs used by some types, nested virtuals included, in order of appearance without duplicates
+pub fn collect_typedefs(code: &Bytecode, types: impl IntoIterator<Item = RefType>) -> Vec<RefType> {
+    let mut typedefs = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack: Vec<RefType> = types.into_iter().collect();
+    stack.reverse();
+    while let Some(ty) = stack.pop() {
+        if !seen.insert(ty) {
+            continue;
+        }
+        match &code[ty] {
+            Type::Null(inner) | Type::Ref(inner) | Type::Packed(inner) => stack.push(*inner),
+            Type::Fun(fun) | Type::Method(fun) => {
+                stack.extend(fun.args.iter().chain(std::iter::once(&fun.ret)).rev())
+            }
+            Type::Virtual { fields } => {
+                if is_typedef(code, ty) {
+                    typedefs.push(ty);
+                }
+                stack.extend(fields.iter().map(|f| f.t).rev());
+            }
+            _ => {}
+        }
+    }
+    typedefs
+}
@@ -1,11 +1,14 @@
 use proc_macro2::TokenStream;
 use quote::quote;
 use syn::{
-    Data, DeriveInput, Expr, ExprLit, GenericArgument, Ident, Lit, LitStr, PathArguments, Type,
-    Variant,
+    Data, DeriveInput, Expr, ExprLit, Field, GenericArgument, Ident, Lit, LitStr, PathArguments,
+    Type, Variant,
 };
 
-#[proc_macro_derive(OpcodeHelper)]
+/// Fields can be annotated with `#[op(...)]` :
+/// - `read`, `write` : how a register operand is accessed, by default `dst` is written and the others are read
+/// - `ignore` : the register is neither read nor written, the jump offset isn't a jump target
+#[proc_macro_derive(OpcodeHelper, attributes(op))]
 pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = syn::parse_macro_input!(input as DeriveInput);
     let variants = match &ast.data {
@@ -18,10 +21,30 @@ pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::Token
     let i = 0..variants.len() as u8;
 
     let initr = variants.iter().map(|v| read_variant(name, v));
+    let skips = variants.iter().map(skip_variant);
+    let iskip = i.clone();
     let initw = variants
         .iter()
         .enumerate()
         .map(|(i, v)| write_variant(name, v, i as u8));
+    let regs = variants
+        .iter()
+        .map(|v| registers_variant(name, v, quote!(Vec), |_| true));
+    let defs = variants.iter().map(|v| {
+        registers_variant(name, v, quote!(crate::types::Regs), |f| {
+            access(f).map_or(false, |a| a.write)
+        })
+    });
+    let uses = variants.iter().map(|v| {
+        registers_variant(name, v, quote!(crate::types::Regs), |f| {
+            access(f).map_or(false, |a| a.read)
+        })
+    });
+    let targets = variants.iter().map(|v| jump_targets_variant(name, v));
+    let offsets_mut = variants.iter().map(|v| offsets_mut_variant(name, v));
+    let descs = variants.iter().map(|v| operand_descs_variant(name, v));
+    let operands = variants.iter().map(|v| operands_variant(name, v));
+    let from_operands = variants.iter().map(|v| from_operands_variant(name, v));
     let vname = variants.iter().map(|v| &v.ident);
     let vname2 = vname.clone();
     let vname_str = variants
@@ -71,7 +94,7 @@ pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::Token
 
                 use byteorder::ReadBytesExt;
                 use crate::types::*;
-                use crate::read::{read_vari, read_varu};
+                use crate::read::{capacity, read_vari, read_varu};
 
                 let op = r.read_u8()?;
                 match op {
@@ -80,6 +103,20 @@ pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::Token
                 }
             }
 
+            /// Skip an encoded instruction without decoding it, used to find the end of a function body
+            pub fn skip(r: &mut impl std::io::Read) -> crate::Result<()> {
+
+                use byteorder::ReadBytesExt;
+                use crate::read::{read_vari, read_varu};
+
+                let op = r.read_u8()?;
+                match op {
+                    #( #iskip => { #skips } )*
+                    other => return Err(crate::Error::MalformedBytecode(format!("Unknown opcode {}", op))),
+                }
+                Ok(())
+            }
+
             /// Encode an instruction
             pub fn write(&self, w: &mut impl std::io::Write) -> crate::Result<()> {
 
@@ -108,6 +145,68 @@ pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::Token
                 }
             }
 
+            /// Get every register operand of this instruction, destination included
+            pub fn registers(&self) -> Vec<crate::types::Reg> {
+                match self {
+                    #( #regs )*
+                }
+            }
+
+            /// Registers written by this instruction
+            pub fn defs(&self) -> crate::types::Regs {
+                match self {
+                    #( #defs )*
+                }
+            }
+
+            /// Registers read by this instruction. A register can be both read and written (e.g. `Incr`).
+            pub fn uses(&self) -> crate::types::Regs {
+                match self {
+                    #( #uses )*
+                }
+            }
+
+            /// Positions this instruction can jump to, it is at position `pos` in its function
+            pub fn jump_targets(&self, pos: usize) -> Vec<usize> {
+                let target = |offset: crate::types::JumpOffset| (pos as i64 + offset as i64 + 1).max(0) as usize;
+                match self {
+                    #( #targets )*
+                }
+            }
+
+            /// Mutable references to every jump offset of this instruction, including those which aren't jump targets
+            pub fn offsets_mut(&mut self) -> Vec<&mut crate::types::JumpOffset> {
+                match self {
+                    #( #offsets_mut )*
+                }
+            }
+
+            /// Static description of the operands of this instruction, in the order of [Self::operands]
+            pub fn operand_descs(&self) -> &'static [crate::opcodes::OperandDesc] {
+                use crate::opcodes::{OperandDesc, OperandKind};
+                match self {
+                    #( #descs )*
+                }
+            }
+
+            /// Get every operand of this instruction with the name of the field
+            pub fn operands(&self) -> Vec<(&'static str, crate::opcodes::Operand<'_>)> {
+                use crate::opcodes::Operand;
+                match self {
+                    #( #operands )*
+                }
+            }
+
+            /// Build an instruction from its name and every operand in the order of [Self::operands].
+            /// Returns None if the name is unknown or the operands don't match.
+            pub fn from_operands(name: &str, operands: &[crate::opcodes::Operand]) -> Option<Self> {
+                use crate::opcodes::Operand;
+                match name {
+                    #( #from_operands )*
+                    _ => None
+                }
+            }
+
             /// Get an opcode from its name. Returns a default value for the variant.
             pub fn from_name(name: &str) -> Option<Self> {
                 match name {
@@ -157,10 +256,10 @@ fn read_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
         "JumpOffset" => quote! {
             #rvi32
         },
-        "Vec<JumpOffset>" => quote! {
+        "JumpOffsets" => quote! {
             {
                 let n = #rvu32 as usize;
-                let mut offsets = Vec::with_capacity(n);
+                let mut offsets = JumpOffsets::with_capacity(capacity(n));
                 for _ in 0..n {
                     offsets.push(#rvu32 as JumpOffset);
                 }
@@ -168,10 +267,10 @@ fn read_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
             }
         },
         "Reg" => reg.clone(),
-        "Vec<Reg>" => quote! {
+        "Regs" => quote! {
             {
                 let n = r.read_u8()? as usize;
-                let mut regs = Vec::with_capacity(n);
+                let mut regs = Regs::with_capacity(n);
                 for _ in 0..n {
                     regs.push(#reg);
                 }
@@ -214,6 +313,28 @@ fn read_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
     }
 }
 
+fn skip_variant(v: &Variant) -> TokenStream {
+    let fskip = v.fields.iter().map(|f| match ident(&f.ty).as_str() {
+        "JumpOffsets" => quote! {
+            for _ in 0..read_varu(r)? {
+                read_vari(r)?;
+            }
+        },
+        "Regs" => quote! {
+            for _ in 0..r.read_u8()? {
+                read_vari(r)?;
+            }
+        },
+        // Every other operand is a single varint
+        _ => quote! {
+            read_vari(r)?;
+        },
+    });
+    quote! {
+        #( #fskip )*
+    }
+}
+
 fn write_variant(enum_name: &Ident, v: &Variant, i: u8) -> TokenStream {
     let vname = &v.ident;
     let fname = v.fields.iter().map(|f| &f.ident);
@@ -227,7 +348,7 @@ fn write_variant(enum_name: &Ident, v: &Variant, i: u8) -> TokenStream {
             "JumpOffset" => quote! {
                 write_var(w, *#fname as i32)?;
             },
-            "Vec<JumpOffset>" => quote! {
+            "JumpOffsets" => quote! {
                 {
                     write_var(w, #fname.len() as i32)?;
                     for r__ in #fname {
@@ -238,7 +359,7 @@ fn write_variant(enum_name: &Ident, v: &Variant, i: u8) -> TokenStream {
             "Reg" => quote! {
                 write_var(w, #fname.0 as i32)?;
             },
-            "Vec<Reg>" => quote! {
+            "Regs" => quote! {
                 {
                     w.write_u8(#fname.len() as u8)?;
                     for r__ in #fname {
@@ -269,3 +390,266 @@ fn write_variant(enum_name: &Ident, v: &Variant, i: u8) -> TokenStream {
         }
     }
 }
+
+/// Flags of the `#[op(...)]` attribute of a field
+#[derive(Default)]
+struct FieldAttrs {
+    read: bool,
+    write: bool,
+    ignore: bool,
+}
+
+fn field_attrs(f: &Field) -> Option<FieldAttrs> {
+    let attr = f.attrs.iter().find(|a| a.path().is_ident("op"))?;
+    let mut attrs = FieldAttrs::default();
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("read") {
+            attrs.read = true;
+        } else if meta.path.is_ident("write") {
+            attrs.write = true;
+        } else if meta.path.is_ident("ignore") {
+            attrs.ignore = true;
+        } else {
+            return Err(meta.error("expected `read`, `write` or `ignore`"));
+        }
+        Ok(())
+    })
+    .unwrap();
+    Some(attrs)
+}
+
+/// How a register operand is accessed, None if the field isn't a register
+fn access(f: &Field) -> Option<FieldAttrs> {
+    if !matches!(ident(&f.ty).as_str(), "Reg" | "Regs") {
+        return None;
+    }
+    Some(field_attrs(f).unwrap_or_else(|| {
+        let dst = f.ident.as_ref().map_or(false, |i| i == "dst");
+        FieldAttrs {
+            read: !dst,
+            write: dst,
+            ignore: false,
+        }
+    }))
+}
+
+/// Collect the register operands matching `filter` in a `container` (Vec or SmallVec)
+fn registers_variant(
+    enum_name: &Ident,
+    v: &Variant,
+    container: TokenStream,
+    filter: impl Fn(&Field) -> bool,
+) -> TokenStream {
+    let vname = &v.ident;
+    let fields: Vec<_> = v
+        .fields
+        .iter()
+        .filter(|f| filter(f))
+        .filter_map(|f| {
+            let fname = f.ident.as_ref().unwrap();
+            match ident(&f.ty).as_str() {
+                "Reg" => Some((fname, quote!(regs.push(*#fname);))),
+                "Regs" => Some((fname, quote!(regs.extend_from_slice(#fname);))),
+                _ => None,
+            }
+        })
+        .collect();
+    if fields.is_empty() {
+        quote! {
+            #enum_name::#vname { .. } => #container::new(),
+        }
+    } else {
+        let fname = fields.iter().map(|(f, _)| f);
+        let fpush = fields.iter().map(|(_, p)| p);
+        quote! {
+            #enum_name::#vname { #( #fname, )* .. } => {
+                let mut regs = #container::new();
+                #( #fpush )*
+                regs
+            }
+        }
+    }
+}
+
+fn jump_targets_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
+    let vname = &v.ident;
+    let fields: Vec<_> = v
+        .fields
+        .iter()
+        .filter(|f| !field_attrs(f).map_or(false, |a| a.ignore))
+        .filter_map(|f| {
+            let fname = f.ident.as_ref().unwrap();
+            match ident(&f.ty).as_str() {
+                "JumpOffset" => Some((fname, quote!(targets.push(target(*#fname));))),
+                "JumpOffsets" => Some((
+                    fname,
+                    quote!(targets.extend(#fname.iter().map(|&o| target(o)));),
+                )),
+                _ => None,
+            }
+        })
+        .collect();
+    if fields.is_empty() {
+        quote! {
+            #enum_name::#vname { .. } => Vec::new(),
+        }
+    } else {
+        let fname = fields.iter().map(|(f, _)| f);
+        let fpush = fields.iter().map(|(_, p)| p);
+        quote! {
+            #enum_name::#vname { #( #fname, )* .. } => {
+                let mut targets = Vec::new();
+                #( #fpush )*
+                targets
+            }
+        }
+    }
+}
+
+fn offsets_mut_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
+    let vname = &v.ident;
+    let fields: Vec<_> = v
+        .fields
+        .iter()
+        .filter_map(|f| {
+            let fname = f.ident.as_ref().unwrap();
+            match ident(&f.ty).as_str() {
+                // The operand can be named `offsets` too
+                "JumpOffset" => Some((fname, quote!(refs.push(#fname);))),
+                "JumpOffsets" => Some((fname, quote!(refs.extend(#fname.iter_mut());))),
+                _ => None,
+            }
+        })
+        .collect();
+    if fields.is_empty() {
+        quote! {
+            #enum_name::#vname { .. } => Vec::new(),
+        }
+    } else {
+        let fname = fields.iter().map(|(f, _)| f);
+        let fpush = fields.iter().map(|(_, p)| p);
+        quote! {
+            #enum_name::#vname { #( #fname, )* .. } => {
+                let mut refs: Vec<&mut crate::types::JumpOffset> = Vec::new();
+                #( #fpush )*
+                refs
+            }
+        }
+    }
+}
+
+fn operand_descs_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
+    let vname = &v.ident;
+    let descs = v.fields.iter().filter_map(|f| {
+        let fname = f.ident.as_ref().unwrap();
+        let fname_str = LitStr::new(&fname.to_string(), fname.span());
+        let kind = match ident(&f.ty).as_str() {
+            "Reg" => quote!(Reg),
+            "Regs" => quote!(Regs),
+            "InlineBool" => quote!(InlineBool),
+            "InlineInt" => quote!(InlineInt),
+            "JumpOffset" => quote!(JumpOffset),
+            "JumpOffsets" => quote!(JumpOffsets),
+            "RefInt" => quote!(Int),
+            "RefFloat" => quote!(Float),
+            "RefBytes" => quote!(Bytes),
+            "RefString" => quote!(String),
+            "RefType" => quote!(Type),
+            "RefFun" => quote!(Fun),
+            "RefField" => quote!(Field),
+            "RefGlobal" => quote!(Global),
+            "RefEnumConstruct" => quote!(EnumConstruct),
+            _ => return None,
+        };
+        let access = access(f).unwrap_or_default();
+        let (read, write) = (access.read, access.write);
+        let jump = matches!(ident(&f.ty).as_str(), "JumpOffset" | "JumpOffsets")
+            && !field_attrs(f).map_or(false, |a| a.ignore);
+        Some(quote! {
+            OperandDesc {
+                name: #fname_str,
+                kind: OperandKind::#kind,
+                read: #read,
+                write: #write,
+                jump: #jump,
+            }
+        })
+    });
+    quote! {
+        #enum_name::#vname { .. } => &[#( #descs, )*],
+    }
+}
+
+fn operands_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
+    let vname = &v.ident;
+    let fname = v.fields.iter().map(|f| &f.ident);
+    let foperand = v.fields.iter().filter_map(|f| {
+        let fname = f.ident.as_ref().unwrap();
+        let fname_str = LitStr::new(&fname.to_string(), fname.span());
+        let operand = match ident(&f.ty).as_str() {
+            "Reg" => quote!(Operand::Reg(*#fname)),
+            "Regs" => quote!(Operand::Regs(#fname.as_slice())),
+            "InlineBool" => quote!(Operand::InlineBool(*#fname)),
+            "InlineInt" => quote!(Operand::InlineInt(*#fname)),
+            "JumpOffset" => quote!(Operand::JumpOffset(*#fname)),
+            "JumpOffsets" => quote!(Operand::JumpOffsets(#fname.as_slice())),
+            "RefInt" => quote!(Operand::Int(*#fname)),
+            "RefFloat" => quote!(Operand::Float(*#fname)),
+            "RefBytes" => quote!(Operand::Bytes(*#fname)),
+            "RefString" => quote!(Operand::String(*#fname)),
+            "RefType" => quote!(Operand::Type(*#fname)),
+            "RefFun" => quote!(Operand::Fun(*#fname)),
+            "RefField" => quote!(Operand::Field(*#fname)),
+            "RefGlobal" => quote!(Operand::Global(*#fname)),
+            "RefEnumConstruct" => quote!(Operand::EnumConstruct(*#fname)),
+            _ => return None,
+        };
+        Some(quote!((#fname_str, #operand)))
+    });
+    quote! {
+        #enum_name::#vname { #( #fname, )* } => vec![#( #foperand, )*],
+    }
+}
+
+fn from_operands_variant(enum_name: &Ident, v: &Variant) -> TokenStream {
+    let vname = &v.ident;
+    let vname_str = LitStr::new(&vname.to_string(), vname.span());
+    let fname: Vec<_> = v.fields.iter().map(|f| &f.ident).collect();
+    let fvalue = v.fields.iter().map(|f| {
+        let fname = f.ident.as_ref().unwrap();
+        let value = match ident(&f.ty).as_str() {
+            "Reg" => quote!(Operand::Reg(v) => v),
+            "Regs" => quote!(Operand::Regs(v) => crate::types::Regs::from_slice(v)),
+            "InlineBool" => quote!(Operand::InlineBool(v) => v),
+            "InlineInt" => quote!(Operand::InlineInt(v) => v),
+            "JumpOffset" => quote!(Operand::JumpOffset(v) => v),
+            "JumpOffsets" => {
+                quote!(Operand::JumpOffsets(v) => crate::types::JumpOffsets::from_slice(v))
+            }
+            "RefInt" => quote!(Operand::Int(v) => v),
+            "RefFloat" => quote!(Operand::Float(v) => v),
+            "RefBytes" => quote!(Operand::Bytes(v) => v),
+            "RefString" => quote!(Operand::String(v) => v),
+            "RefType" => quote!(Operand::Type(v) => v),
+            "RefFun" => quote!(Operand::Fun(v) => v),
+            "RefField" => quote!(Operand::Field(v) => v),
+            "RefGlobal" => quote!(Operand::Global(v) => v),
+            "RefEnumConstruct" => quote!(Operand::EnumConstruct(v) => v),
+            other => unreachable!("unknown operand type {}", other),
+        };
+        quote! {
+            match *#fname {
+                #value,
+                _ => return None,
+            }
+        }
+    });
+    quote! {
+        #vname_str => {
+            let [#( #fname, )*] = operands else {
+                return None;
+            };
+            Some(#enum_name::#vname { #( #fname: #fvalue, )* })
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Opt-in crash reports
+
+use std::backtrace::Backtrace;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hlbc::link::Linker;
+use hlbc::opcodes::Opcode;
+use hlbc::types::{RefFun, Reg};
+use hlbc::Bytecode;
+
+use crate::load_bytecode;
+
+/// Description of what was being executed, included in the report.
+static CONTEXT: Mutex<Option<String>> = Mutex::new(None);
+/// Function being processed, the report includes a reduced bytecode with only this function.
+static FUNCTION: Mutex<Option<RefFun>> = Mutex::new(None);
+/// Set while the bytecode is reduced, a panic there doesn't get its own report.
+static REDUCING: AtomicBool = AtomicBool::new(false);
+
+/// Record what the program is currently doing (e.g. the command being executed) and the function it processes, if
+/// any.
+pub fn set_context(context: String, function: Option<RefFun>) {
+    if let Ok(mut ctx) = CONTEXT.lock() {
+        *ctx = Some(context);
+    }
+    if let Ok(mut fun) = FUNCTION.lock() {
+        *fun = function;
+    }
+}
+
+/// Install a panic hook writing a report bundle in the current directory. `app` is the name and version of the
+/// program written at the top of the report.
+///
+/// Nothing is sent anywhere, the report is only written to disk so it can be attached to an issue.
+/// A reduced bytecode with the function being processed is only included when `bytecode` is [Some], this requires
+/// the user's consent.
+pub fn install(app: &'static str, bytecode: Option<PathBuf>) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if REDUCING.load(Ordering::Relaxed) {
+            return;
+        }
+        let backtrace = Backtrace::force_capture();
+        let context = CONTEXT.lock().ok().and_then(|ctx| ctx.clone());
+        let function = FUNCTION.lock().ok().and_then(|fun| *fun);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let dir = PathBuf::from(format!("hlbc-crash-{timestamp}"));
+        match write_report(
+            &dir,
+            app,
+            &info.to_string(),
+            &backtrace,
+            context,
+            bytecode.as_deref().zip(function),
+        ) {
+            Ok(()) => eprintln!(
+                "A crash report has been written to '{}'. Please attach it when opening an issue at https://github.com/Gui-Yom/hlbc/issues",
+                dir.display()
+            ),
+            Err(e) => eprintln!("Failed to write the crash report : {e}"),
+        }
+    }));
+}
+
+fn write_report(
+    dir: &Path,
+    app: &str,
+    message: &str,
+    backtrace: &Backtrace,
+    context: Option<String>,
+    function: Option<(&Path, RefFun)>,
+) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let mut report = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(report, "{app}");
+    let _ = writeln!(
+        report,
+        "target: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let _ = writeln!(
+        report,
+        "context: {}",
+        context.as_deref().unwrap_or("<none>")
+    );
+    if let Some((_, f)) = function {
+        let _ = writeln!(report, "function: fn@{}", f.0);
+    }
+    let _ = writeln!(report, "\n{message}\n\n{backtrace}");
+    // Written before the reduction, which is more likely to fail
+    let path = dir.join("report.txt");
+    fs::write(&path, report)?;
+
+    if let Some((bytecode, f)) = function {
+        let note = match write_reduced(dir, bytecode, f) {
+            Ok(entrypoint) => format!("fn@{} in bytecode.hl", entrypoint.0),
+            Err(e) => format!("failed ({e})"),
+        };
+        let mut report = fs::OpenOptions::new().append(true).open(&path)?;
+        writeln!(report, "reduced bytecode: {note}")?;
+    }
+    Ok(())
+}
+
+/// Write a reduced bytecode with only the function `f` to `dir/bytecode.hl`, returns the new index of `f`.
+///
+/// A panic in the panic hook aborts the process, the reduction runs on another thread so a panic there only fails the
+/// reduction.
+fn write_reduced(dir: &Path, bytecode: &Path, f: RefFun) -> Result<RefFun, String> {
+    let (dir, bytecode) = (dir.to_owned(), bytecode.to_owned());
+    REDUCING.store(true, Ordering::Relaxed);
+    // Loaded again, the bytecode isn't shared with the hook
+    let reduced = thread::spawn(move || -> hlbc::Result<RefFun> {
+        let min = minimize(&load_bytecode(&fs::read(bytecode)?)?, f)?;
+        let mut data = Vec::new();
+        min.serialize(&mut data)?;
+        fs::write(dir.join("bytecode.hl"), data)?;
+        Ok(min.entrypoint)
+    })
+    .join();
+    REDUCING.store(false, Ordering::Relaxed);
+    match reduced {
+        Ok(reduced) => reduced.map_err(|e| e.to_string()),
+        Err(_) => Err("the reduction panicked".to_owned()),
+    }
+}
+
+/// Reduced bytecode with only the function `f` and its dependencies (types, globals, called functions, ...).
+/// The function is the entrypoint. Only the function and the closures it creates keep their code, the other functions
+/// are reduced to their signature.
+fn minimize(code: &Bytecode, f: RefFun) -> hlbc::Result<Bytecode> {
+    // Some fields of the bytecode are private, they are built by the linker
+    let mut min = Bytecode::default();
+    min.version = code.version;
+    min.bytes = code.bytes.as_ref().map(|_| (Vec::new(), Vec::new()));
+    min.debug_files = code.debug_files.as_ref().map(|_| Vec::new());
+    min.constants = code.constants.as_ref().map(|_| Vec::new());
+    let mut linker = Linker::new(&mut min, code)?;
+    let entrypoint = linker.function(f);
+    linker.finish();
+    min.entrypoint = entrypoint;
+
+    // Closures are decompiled with the function
+    let mut kept = vec![entrypoint];
+    let mut i = 0;
+    while let Some(fun) = kept.get(i).and_then(|f| f.as_fn(&min)) {
+        for op in &fun.ops {
+            if let Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } = op {
+                if !kept.contains(fun) {
+                    kept.push(*fun);
+                }
+            }
+        }
+        i += 1;
+    }
+    for fun in &mut min.functions {
+        if kept.contains(&fun.findex) {
+            continue;
+        }
+        let Some(ty) = min.types[fun.t.0].get_type_fun() else {
+            continue;
+        };
+        // Returns its uninitialized last register
+        fun.regs = ty.args.clone();
+        fun.regs.push(ty.ret);
+        fun.ops = vec![Opcode::Ret {
+            ret: Reg(ty.args.len() as u32),
+        }];
+        if let Some(debug_info) = &mut fun.debug_info {
+            debug_info.truncate(1);
+        }
+        if let Some(assigns) = &mut fun.assigns {
+            assigns.clear();
+        }
+    }
+    // Without the code of the other functions, most of the constants are unused
+    min.gc_pools()?;
+    Ok(min)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::backtrace::Backtrace;
+    use std::fs;
+
+    use temp_dir::TempDir;
+
+    use hlbc::testing::{build, class, fun};
+    use hlbc::types::{RefFun, Type};
+    use hlbc::Bytecode;
+
+    use crate::crash::{minimize, write_report};
+
+    fn sample() -> Bytecode {
+        let mut code = build(
+            &["", "$Main", "main", "helper", "unused"],
+            vec![
+                Type::Void,
+                Type::I32,
+                fun(&[], 0),
+                fun(&[1], 1),
+                class(1, &[(2, 2, 0), (3, 3, 1), (4, 2, 2)]),
+            ],
+            &[],
+            "
+            .fun fn@0 type@2
+            .reg type@1
+            .reg type@2
+            .reg type@0
+                Int reg0 int@0
+                Call1 reg0 fn@1 reg0
+                StaticClosure reg1 fn@3
+                Ret reg2
+            .end
+            .fun fn@1 type@3
+            .reg type@1
+            .reg type@1
+                Int reg1 int@1
+                Add reg0 reg0 reg1
+                Ret reg0
+            .end
+            .fun fn@2 type@2
+            .reg type@1
+            .reg type@0
+                Int reg0 int@2
+                Ret reg1
+            .end
+            .fun fn@3 type@2
+            .reg type@1
+            .reg type@0
+                Int reg0 int@3
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![1, 2, 42, 7];
+        code
+    }
+
+    #[test]
+    fn test_minimize() {
+        let code = sample();
+        let min = minimize(&code, RefFun(1)).unwrap();
+        // helper is the entrypoint, its class and the other functions are kept for their signature
+        assert_eq!(min.entrypoint.name(&min), "helper");
+        assert_eq!(min.functions.len(), 4);
+        for f in &min.functions {
+            let len = if f.findex == min.entrypoint { 3 } else { 1 };
+            assert_eq!(f.ops.len(), len);
+        }
+        assert_eq!(min.ints, vec![2]);
+
+        let min = minimize(&code, RefFun(0)).unwrap();
+        assert_eq!(min.entrypoint.name(&min), "main");
+        // The closure keeps its code
+        let closure = min.functions.iter().find(|f| f.parent.is_none()).unwrap();
+        assert_eq!(closure.ops.len(), 2);
+        assert_eq!(min.functions.len(), 4);
+        assert_eq!(min.ints, vec![1, 7]);
+
+        let mut data = Vec::new();
+        min.serialize(&mut data).unwrap();
+        let min = Bytecode::deserialize(data.as_slice()).unwrap();
+        assert_eq!(min.main().name(&min), "main");
+    }
+    #[test]
+    fn test_report() {
+        let dir = TempDir::new().unwrap();
+        let bytecode = dir.path().join("input.hl");
+        let mut file = fs::File::create(&bytecode).unwrap();
+        sample().serialize(&mut file).unwrap();
+        write_report(
+            dir.path(),
+            "hlbc-test",
+            "boom",
+            &Backtrace::disabled(),
+            None,
+            Some((&bytecode, RefFun(1))),
+        )
+        .unwrap();
+        let report = fs::read_to_string(dir.path().join("report.txt")).unwrap();
+        assert!(report.contains("context: <none>\nfunction: fn@1\n"));
+        assert!(report.ends_with("reduced bytecode: fn@1 in bytecode.hl\n"));
+        let min = fs::read(dir.path().join("bytecode.hl")).unwrap();
+        let min = Bytecode::deserialize(min.as_slice()).unwrap();
+        assert_eq!(min.entrypoint.name(&min), "helper");
+    }
+
+    #[test]
+    fn test_report_failed_reduction() {
+        let dir = TempDir::new().unwrap();
+        write_report(
+            dir.path(),
+            "hlbc-test",
+            "boom",
+            &Backtrace::disabled(),
+            Some("decomp 1".to_owned()),
+            Some((&dir.path().join("missing.hl"), RefFun(1))),
+        )
+        .unwrap();
+        // The report is still written when the bytecode can't be reduced
+        let report = fs::read_to_string(dir.path().join("report.txt")).unwrap();
+        assert!(report.starts_with("hlbc-test\n"));
+        assert!(report.contains("context: decomp 1\nfunction: fn@1\n"));
+        assert!(report.contains("boom"));
+        assert!(report.contains("reduced bytecode: failed ("));
+        assert!(!dir.path().join("bytecode.hl").exists());
+    }
+}
@@ -0,0 +1,114 @@
+//! Language selection of the user facing messages
+//!
+//! Each app defines its own `Messages` table with a static per language and selects it with [Lang::pick]. Tables are
+//! plain structs so a missing translation is a compile error.
+
+use std::env;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Language of the user facing messages
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Lang {
+    En,
+    Fr,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::Fr];
+
+    /// Name of the language, in that language
+    pub fn name(self) -> &'static str {
+        match self {
+            Lang::En => "English",
+            Lang::Fr => "Français",
+        }
+    }
+
+    /// Parse a language tag like `fr`, `fr_FR.UTF-8` or `en-US`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split(['_', '-', '.']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "en" | "c" | "posix" => Some(Lang::En),
+            "fr" => Some(Lang::Fr),
+            _ => None,
+        }
+    }
+
+    /// Detect the language from the environment (`HLBC_LANG`, `LC_ALL`, `LC_MESSAGES` then `LANG`).
+    /// Defaults to english, which is always the case on the web.
+    pub fn detect() -> Self {
+        Self::detect_from(|var| env::var(var).ok())
+    }
+
+    fn detect_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        ["HLBC_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(var)
+            .find(|v| !v.is_empty())
+            .and_then(|v| Self::from_tag(&v))
+            .unwrap_or(Lang::En)
+    }
+
+    /// Select the messages table of this language.
+    pub fn pick<T: ?Sized>(self, en: &'static T, fr: &'static T) -> &'static T {
+        match self {
+            Lang::En => en,
+            Lang::Fr => fr,
+        }
+    }
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+/// Get the current language.
+pub fn lang() -> Lang {
+    if LANG.load(Ordering::Relaxed) == Lang::Fr as u8 {
+        Lang::Fr
+    } else {
+        Lang::En
+    }
+}
+
+/// Set the current language.
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i18n::Lang;
+
+    #[test]
+    fn test_lang_tags() {
+        assert_eq!(Lang::from_tag("fr_FR.UTF-8"), Some(Lang::Fr));
+        assert_eq!(Lang::from_tag("en-US"), Some(Lang::En));
+        assert_eq!(Lang::from_tag("C"), Some(Lang::En));
+        assert_eq!(Lang::from_tag("de_DE"), None);
+    }
+
+    #[test]
+    fn test_detect() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == var)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert_eq!(Lang::detect_from(env(&[])), Lang::En);
+        assert_eq!(Lang::detect_from(env(&[("LANG", "fr_FR.UTF-8")])), Lang::Fr);
+        // LC_MESSAGES takes precedence over LANG, empty variables are skipped
+        assert_eq!(
+            Lang::detect_from(env(&[
+                ("LC_ALL", ""),
+                ("LC_MESSAGES", "fr_FR"),
+                ("LANG", "en_US")
+            ])),
+            Lang::Fr
+        );
+        assert_eq!(
+            Lang::detect_from(env(&[("HLBC_LANG", "en"), ("LANG", "fr_FR")])),
+            Lang::En
+        );
+    }
+}
@@ -0,0 +1,16 @@
+//! Code shared by the cli and the gui.
+
+use hlbc::Bytecode;
+
+pub mod crash;
+pub mod i18n;
+
+/// Load a bytecode file or the bytecode embedded in an executable, see [hlbc::embedded]
+pub fn load_bytecode(data: &[u8]) -> hlbc::Result<Bytecode> {
+    if data.starts_with(b"HLB") {
+        Bytecode::deserialize(data)
+    } else {
+        // The first magic header of an executable may not be the bytecode
+        Ok(hlbc::embedded::extract(data)?.code)
+    }
+}
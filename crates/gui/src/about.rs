@@ -2,11 +2,13 @@ use eframe::egui;
 use eframe::egui::load::Bytes;
 use eframe::egui::{Context, ImageSource};
 
+use crate::i18n::tr;
 use crate::style::text_stitch;
 use crate::HLBC_ICON;
 
 pub(crate) fn about_window(ctx: &Context, open: &mut bool) {
-    egui::Window::new("About")
+    egui::Window::new(tr().about)
+        .id(egui::Id::new("about_window"))
         .open(open)
         .resizable(false)
         .collapsible(false)
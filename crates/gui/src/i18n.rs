@@ -0,0 +1,140 @@
+pub use hlbc_frontend::i18n::{lang, set_lang, Lang};
+
+/// Get the messages for the current language. A change of language takes effect on the next frame.
+pub fn tr() -> &'static Messages {
+    lang().pick(&EN, &FR)
+}
+
+/// User facing text of the menus, homepage and windows
+pub struct Messages {
+    pub loading: &'static str,
+    pub no_file: &'static str,
+    pub loaded: &'static str,
+    pub loaded_example: &'static str,
+    pub load_to_start: &'static str,
+    pub stays_local: &'static str,
+    pub open_file: &'static str,
+    pub open_hover: &'static str,
+    pub open_hover_web: &'static str,
+    pub example: &'static str,
+    pub load_example: &'static str,
+    pub menu_file: &'static str,
+    pub open: &'static str,
+    pub close: &'static str,
+    pub save_renames: &'static str,
+    pub save_renames_hover: &'static str,
+    pub menu_views: &'static str,
+    pub info: &'static str,
+    pub classes: &'static str,
+    pub functions: &'static str,
+    pub files: &'static str,
+    pub globals: &'static str,
+    pub strings: &'static str,
+    pub search: &'static str,
+    pub menu_navigate: &'static str,
+    pub back: &'static str,
+    pub forward: &'static str,
+    pub options: &'static str,
+    pub menu_help: &'static str,
+    pub wiki: &'static str,
+    pub issues: &'static str,
+    pub issues_hover: &'static str,
+    pub discussions: &'static str,
+    pub discussions_hover: &'static str,
+    pub discord_hover: &'static str,
+    pub contact: &'static str,
+    pub about: &'static str,
+    pub display: &'static str,
+    pub code_display: &'static str,
+    pub asm_style: &'static str,
+    pub language: &'static str,
+}
+
+static EN: Messages = Messages {
+    loading: "Loading bytecode ...",
+    no_file: "No bytecode file loaded.",
+    loaded: "Loaded bytecode successfully",
+    loaded_example: "Loaded example successfully",
+    load_to_start: "Load a bytecode file to start",
+    stays_local: "Your file stays local and is not uploaded to any server",
+    open_file: "Open file",
+    open_hover: "Load a bytecode file or a HashLink executable",
+    open_hover_web: "Load a bytecode file or a HashLink executable. Everything stays local.",
+    example: "Example",
+    load_example: "Load example",
+    menu_file: "File",
+    open: "Open",
+    close: "Close",
+    save_renames: "Save renames",
+    save_renames_hover: "Save the renamed elements next to the bytecode file",
+    menu_views: "Views",
+    info: "Info",
+    classes: "Classes",
+    functions: "Functions",
+    files: "Files",
+    globals: "Globals",
+    strings: "Strings",
+    search: "Search",
+    menu_navigate: "Navigate",
+    back: "Back",
+    forward: "Forward",
+    options: "Options",
+    menu_help: "Help",
+    wiki: "Wiki",
+    issues: "Issues",
+    issues_hover: "Report bugs, feature requests",
+    discussions: "Discussions",
+    discussions_hover: "Q&A, feature requests",
+    discord_hover: "No discord server yet, dm me instead",
+    contact: "Contact by email",
+    about: "About",
+    display: "Display",
+    code_display: "Code display",
+    asm_style: "Assembly style",
+    language: "Language",
+};
+
+static FR: Messages = Messages {
+    loading: "Chargement du bytecode ...",
+    no_file: "Aucun fichier de bytecode chargé.",
+    loaded: "Bytecode chargé avec succès",
+    loaded_example: "Exemple chargé avec succès",
+    load_to_start: "Chargez un fichier de bytecode pour commencer",
+    stays_local: "Votre fichier reste en local et n'est envoyé à aucun serveur",
+    open_file: "Ouvrir un fichier",
+    open_hover: "Charger un fichier de bytecode ou un exécutable HashLink",
+    open_hover_web:
+        "Charger un fichier de bytecode ou un exécutable HashLink. Tout reste en local.",
+    example: "Exemple",
+    load_example: "Charger un exemple",
+    menu_file: "Fichier",
+    open: "Ouvrir",
+    close: "Fermer",
+    save_renames: "Enregistrer les renommages",
+    save_renames_hover: "Enregistrer les éléments renommés à côté du fichier de bytecode",
+    menu_views: "Vues",
+    info: "Infos",
+    classes: "Classes",
+    functions: "Fonctions",
+    files: "Fichiers",
+    globals: "Globales",
+    strings: "Chaînes",
+    search: "Recherche",
+    menu_navigate: "Navigation",
+    back: "Précédent",
+    forward: "Suivant",
+    options: "Options",
+    menu_help: "Aide",
+    wiki: "Wiki",
+    issues: "Tickets",
+    issues_hover: "Signaler des bugs, proposer des fonctionnalités",
+    discussions: "Discussions",
+    discussions_hover: "Questions, propositions de fonctionnalités",
+    discord_hover: "Pas encore de serveur discord, envoyez-moi plutôt un message privé",
+    contact: "Contact par email",
+    about: "À propos",
+    display: "Affichage",
+    code_display: "Affichage du code",
+    asm_style: "Style de l'assembleur",
+    language: "Langue",
+};
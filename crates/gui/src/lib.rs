@@ -7,7 +7,9 @@ use egui_dock::{DockArea, DockState, Node, NodeIndex, Split, SurfaceIndex};
 use poll_promise::Promise;
 
 use hlbc::Bytecode;
+use hlbc_frontend::load_bytecode;
 
+use crate::i18n::{tr, Lang};
 use crate::model::{AppCtx, AppCtxHandle};
 use crate::views::{
     AppView, ClassesView, DefaultAppView, DynamicTabViewer, FilesView, FunctionsView, GlobalsView,
@@ -17,7 +19,11 @@ use crate::views::{
 mod about;
 #[cfg(feature = "examples")]
 mod examples;
+/// Translations of the user interface
+mod i18n;
 mod model;
+#[cfg(feature = "search")]
+mod quick_search;
 mod shortcuts;
 mod style;
 mod views;
@@ -36,12 +42,15 @@ pub struct App {
     style: egui_dock::Style,
     options_window_open: bool,
     about_window_open: bool,
+    #[cfg(feature = "search")]
+    quick_search: quick_search::QuickSearch,
     status: Cow<'static, str>,
 }
 
 impl App {
     pub fn new(loader: Option<BytecodeLoader>, style: egui_dock::Style) -> Self {
         let is_loading = loader.is_some();
+        i18n::set_lang(Lang::detect());
         Self {
             loader,
             ctx: None,
@@ -49,10 +58,12 @@ impl App {
             style,
             options_window_open: false,
             about_window_open: false,
+            #[cfg(feature = "search")]
+            quick_search: Default::default(),
             status: Cow::Borrowed(if is_loading {
-                "Loading bytecode ..."
+                tr().loading
             } else {
-                "No bytecode file loaded."
+                tr().no_file
             }),
         }
     }
@@ -72,8 +83,10 @@ impl eframe::App for App {
                 match loader.try_take() {
                     Ok(Ok(Some((file, code)))) => {
                         self.ctx = Some(AppCtxHandle::new(AppCtx::new_from_code(file, code)));
+                        #[cfg(feature = "search")]
+                        self.quick_search.clear();
                         self.dock_state = default_tabs();
-                        self.status = Cow::Borrowed("Loaded bytecode successfully");
+                        self.status = Cow::Borrowed(tr().loaded);
                     }
                     Ok(Ok(None)) => {
                         // No file has been picked
@@ -120,12 +133,12 @@ impl App {
             ui.add_space(100.0);
             ui.label(style::text("hlbc", style::get().heading_title.clone()));
             ui.label(style::text(
-                "Load a bytecode file to start",
+                tr().load_to_start,
                 style::get().heading_subtitle.clone(),
             ));
             #[cfg(target_arch = "wasm32")]
             ui.label(style::text(
-                "Your file stays local and is not uploaded to any server",
+                tr().stays_local,
                 style::get().heading_subtitle.clone(),
             ));
             ui.add_space(10.0);
@@ -134,15 +147,15 @@ impl App {
             if ui
                 .add(
                     Button::new(style::text(
-                        "Open file",
+                        tr().open_file,
                         style::get().homepage_button.clone(),
                     ))
                         .shortcut_text(ui.ctx().format_shortcut(&shortcuts::OPEN)),
                 )
                 .on_hover_text(if cfg!(target_arch = "wasm32") {
-                    "Load a bytecode file. Everything stays local."
+                    tr().open_hover_web
                 } else {
-                    "Load a bytecode file"
+                    tr().open_hover
                 })
                 .clicked()
             {
@@ -151,7 +164,7 @@ impl App {
 
             #[cfg(feature = "examples")]
             ui.menu_button(
-                style::text("Example", style::get().homepage_button.clone()),
+                style::text(tr().example, style::get().homepage_button.clone()),
                 |ui| {
                     for example in examples::EXAMPLES {
                         if ui.button(example.name).clicked() {
@@ -204,7 +217,7 @@ impl App {
 
     #[cfg(feature = "examples")]
     fn load_examples_button(&mut self, ui: &mut Ui) {
-        ui.menu_button("Load example", |ui| {
+        ui.menu_button(tr().load_example, |ui| {
             for example in examples::EXAMPLES {
                 if ui.button(example.name).clicked() {
                     self.load_example(example);
@@ -221,11 +234,13 @@ impl App {
             example.name.to_owned(),
             code,
         )));
+        #[cfg(feature = "search")]
+        self.quick_search.clear();
         self.dock_state = default_tabs();
         self.dock_state.main_surface_mut()[NodeIndex::root().right()].append_tab(Box::new(
             views::SourceView::new(example.name, example.source),
         ));
-        self.status = Cow::Borrowed("Loaded example successfully");
+        self.status = Cow::Borrowed(tr().loaded_example);
     }
 
     fn open_file(&mut self) {
@@ -233,10 +248,7 @@ impl App {
         {
             self.loader = Some(Promise::spawn_local(async {
                 if let Some(file) = rfd::AsyncFileDialog::new().pick_file().await {
-                    Ok(Some((
-                        file.file_name(),
-                        Bytecode::deserialize(&file.read().await[..]).unwrap(),
-                    )))
+                    load_bytecode(&file.read().await).map(|code| Some((file.file_name(), code)))
                 } else {
                     Ok(None)
                 }
@@ -246,10 +258,9 @@ impl App {
         {
             self.loader = Some(Promise::spawn_thread("bg_loader", || {
                 if let Some(file) = rfd::FileDialog::new().pick_file() {
-                    Ok(Some((
-                        file.display().to_string(),
-                        Bytecode::from_file(file)?,
-                    )))
+                    let code = load_bytecode(&fs::read(&file)?)?;
+                    load_renames(&code, &file.display().to_string());
+                    Ok(Some((file.display().to_string(), code)))
                 } else {
                     Ok(None)
                 }
@@ -262,16 +273,16 @@ impl App {
             .frame(Frame::none().outer_margin(Margin::same(4.0)))
             .show(ctx, |ui| {
                 egui::menu::bar(ui, |ui| {
-                    ui.menu_button("File", |ui| {
+                    ui.menu_button(tr().menu_file, |ui| {
                         if ui
                             .add(
-                                Button::new("Open")
+                                Button::new(tr().open)
                                     .shortcut_text(ctx.format_shortcut(&shortcuts::OPEN)),
                             )
                             .on_hover_text(if cfg!(target_arch = "wasm32") {
-                                "Load a bytecode file. Everything stays local."
+                                tr().open_hover_web
                             } else {
-                                "Load a bytecode file"
+                                tr().open_hover
                             })
                             .clicked()
                         {
@@ -281,9 +292,24 @@ impl App {
                         #[cfg(feature = "examples")]
                         self.load_examples_button(ui);
 
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if let Some(ctx) = &self.ctx {
+                            if ui
+                                .button(tr().save_renames)
+                                .on_hover_text(tr().save_renames_hover)
+                                .clicked()
+                            {
+                                if let Err(e) =
+                                    ctx.code().renames.save(renames_path(&ctx.file()))
+                                {
+                                    println!("{e}");
+                                }
+                            }
+                        }
+
                         if ui
                             .add(
-                                Button::new("Close")
+                                Button::new(tr().close)
                                     .shortcut_text(ctx.format_shortcut(&shortcuts::CLOSE)),
                             )
                             .clicked()
@@ -292,49 +318,45 @@ impl App {
                         }
                     });
                     if let Some(ctx) = &self.ctx {
-                        ui.menu_button("Views", |ui| {
-                            Self::view_button_default::<InfoView>(&mut self.dock_state, ui, "Info");
+                        ui.menu_button(tr().menu_views, |ui| {
+                            Self::view_button_default::<InfoView>(
+                                &mut self.dock_state,
+                                ui,
+                                tr().info,
+                            );
                             Self::view_button_default::<ClassesView>(
                                 &mut self.dock_state,
                                 ui,
-                                "Classes",
+                                tr().classes,
                             );
                             Self::view_button_default::<FunctionsView>(
                                 &mut self.dock_state,
                                 ui,
-                                "Functions",
+                                tr().functions,
                             );
                             Self::view_button::<FilesView>(
                                 &mut self.dock_state,
                                 ui,
-                                "Files",
+                                tr().files,
                                 || Box::new(FilesView::new(ctx.code())),
                             );
                             Self::view_button_default::<GlobalsView>(
                                 &mut self.dock_state,
                                 ui,
-                                "Globals",
+                                tr().globals,
                             );
                             Self::view_button_default::<StringsView>(
                                 &mut self.dock_state,
                                 ui,
-                                "Strings",
+                                tr().strings,
                             );
-                            #[cfg(feature = "search")]
-                            if ui.button("Search").clicked() {
-                                self.dock_state
-                                    .main_surface_mut()
-                                    .push_to_focused_leaf(Box::new(views::SearchView::new(
-                                        ctx.code(),
-                                    )));
-                            }
                         });
 
-                        ui.menu_button("Navigate", |ui| {
+                        ui.menu_button(tr().menu_navigate, |ui| {
                             if ui
                                 .add_enabled(
                                     ctx.can_navigate_back(),
-                                    Button::new("Back").shortcut_text(
+                                    Button::new(tr().back).shortcut_text(
                                         ui.ctx().format_shortcut(&shortcuts::NAV_BACK),
                                     ),
                                 )
@@ -346,7 +368,7 @@ impl App {
                             if ui
                                 .add_enabled(
                                     ctx.can_navigate_forward(),
-                                    Button::new("Forward").shortcut_text(
+                                    Button::new(tr().forward).shortcut_text(
                                         ui.ctx().format_shortcut(&shortcuts::NAV_FORWARD),
                                     ),
                                 )
@@ -356,36 +378,40 @@ impl App {
                             }
                         });
                     }
-                    if ui.button("Options").clicked() {
+                    if ui.button(tr().options).clicked() {
                         self.options_window_open = !self.options_window_open;
                     }
-                    ui.menu_button("Help", |ui| {
-                        if ui.button("Wiki").clicked() {
+                    ui.menu_button(tr().menu_help, |ui| {
+                        if ui.button(tr().wiki).clicked() {
                             webbrowser::open("https://github.com/Gui-Yom/hlbc/wiki").ok();
                         }
                         if ui
-                            .button("Issues")
-                            .on_hover_text("Report bugs, feature requests")
+                            .button(tr().issues)
+                            .on_hover_text(tr().issues_hover)
                             .clicked()
                         {
                             webbrowser::open("https://github.com/Gui-Yom/hlbc/issues").ok();
                         }
                         if ui
-                            .button("Discussions")
-                            .on_hover_text("Q&A, feature requests")
+                            .button(tr().discussions)
+                            .on_hover_text(tr().discussions_hover)
                             .clicked()
                         {
                             webbrowser::open("https://github.com/Gui-Yom/hlbc/discussions").ok();
                         }
                         ui.label("Discord: limelion")
-                            .on_hover_text("No discord server yet, dm me instead");
-                        if ui.button("Contact by email").clicked() {
+                            .on_hover_text(tr().discord_hover);
+                        if ui.button(tr().contact).clicked() {
                             webbrowser::open("mailto:guillaume.anthouard+hlbc@hotmail.fr").ok();
                         }
-                        if ui.button("About").clicked() {
+                        if ui.button(tr().about).clicked() {
                             self.about_window_open = !self.about_window_open;
                         }
                     });
+                    #[cfg(feature = "search")]
+                    if let Some(ctx) = &self.ctx {
+                        self.quick_search.ui(ui, ctx);
+                    }
                 });
             });
     }
@@ -406,17 +432,42 @@ impl App {
     }
 
     fn windows(&mut self, ctx: &egui::Context) {
-        egui::Window::new("Options")
+        egui::Window::new(tr().options)
+            .id(egui::Id::new("options_window"))
             .open(&mut self.options_window_open)
             .show(ctx, |ui| {
-                ui.collapsing("Display", |ui| {
+                egui::ComboBox::from_label(tr().language)
+                    .selected_text(i18n::lang().name())
+                    .show_ui(ui, |ui| {
+                        for lang in Lang::ALL {
+                            if ui
+                                .selectable_label(i18n::lang() == lang, lang.name())
+                                .clicked()
+                            {
+                                i18n::set_lang(lang);
+                            }
+                        }
+                    });
+                ui.collapsing(tr().display, |ui| {
                     // TODO ui theme
                     #[cfg(debug_assertions)]
                     ScrollArea::vertical().show(ui, |ui| {
                         ctx.style_ui(ui);
                     });
                 });
-                ui.collapsing("Code display", |_ui| {
+                ui.collapsing(tr().code_display, |ui| {
+                    egui::ComboBox::from_label(tr().asm_style)
+                        .selected_text(style::asm_style().name())
+                        .show_ui(ui, |ui| {
+                            for s in style::ASM_STYLES {
+                                if ui
+                                    .selectable_label(style::asm_style() == s, s.name())
+                                    .clicked()
+                                {
+                                    style::set_asm_style(s);
+                                }
+                            }
+                        });
                     // TODO code font
                     // TODO code font size
                     // TODO code theme
@@ -424,13 +475,34 @@ impl App {
             });
 
         about::about_window(ctx, &mut self.about_window_open);
+        if let Some(app) = &self.ctx {
+            views::rename_propagation_window(ctx, app);
+        }
     }
     fn close_file(&mut self) {
         self.ctx = None;
+        #[cfg(feature = "search")]
+        self.quick_search.clear();
         self.dock_state = DockState::new(Vec::new())
     }
 }
 
+/// Path of the sidecar file storing the renames of a bytecode file
+pub fn renames_path(file: &str) -> String {
+    format!("{file}.renames")
+}
+
+/// Load the renames sidecar file of a bytecode file if there is one.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_renames(code: &Bytecode, file: &str) {
+    let path = renames_path(file);
+    if fs::metadata(&path).is_ok() {
+        if let Err(e) = code.renames.load(path) {
+            println!("{e}");
+        }
+    }
+}
+
 fn default_tabs() -> DockState<Box<dyn AppView>> {
     let mut dock_state: DockState<Box<dyn AppView>> = DockState::new(vec![
         InfoView::default_view(),
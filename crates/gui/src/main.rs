@@ -1,6 +1,7 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 use std::env;
+use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -9,14 +10,16 @@ use egui_ui_refresh::RefreshedTheme;
 use image::ImageFormat;
 use poll_promise::Promise;
 
-use hlbc::Bytecode;
-use hlbc_gui::{App, HLBC_ICON};
+use hlbc_frontend::{crash, load_bytecode};
+use hlbc_gui::{load_renames, App, HLBC_ICON};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod image_loader;
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> eframe::Result<()> {
+    // Without the bytecode, it would require the user's consent
+    crash::install(concat!("hlbc-gui ", env!("CARGO_PKG_VERSION")), None);
     let icon = image::load_from_memory_with_format(HLBC_ICON, ImageFormat::Ico).unwrap();
     let icon_data = Arc::new(IconData {
         width: icon.width(),
@@ -45,10 +48,9 @@ fn main() -> eframe::Result<()> {
             } else {
                 let path = PathBuf::from(args.clone());
                 Some(Promise::spawn_thread("bg_loader", move || {
-                    Ok(Some((
-                        args,
-                        Bytecode::from_file(path)?,
-                    )))
+                    let code = load_bytecode(&fs::read(path)?)?;
+                    load_renames(&code, &args);
+                    Ok(Some((args, code)))
                 }))
             };
             cc.egui_ctx
@@ -2,8 +2,10 @@ use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::rc::Rc;
 
+use hlbc::analysis::calls::CallIndex;
 use hlbc::analysis::usage::{usage_report, FullUsageReport};
 use hlbc::fmt::EnhancedFmt;
+use hlbc::summary::ModuleSummary;
 use hlbc::types::{RefFun, RefGlobal, RefString, RefType};
 use hlbc::Bytecode;
 
@@ -43,6 +45,23 @@ impl AppCtxHandle {
         &self.0.usage
     }
 
+    pub(crate) fn summary(&self) -> &ModuleSummary {
+        &self.0.summary
+    }
+
+    pub(crate) fn calls(&self) -> &CallIndex {
+        &self.0.calls
+    }
+
+    /// Command line running an hlbc command on the loaded file, e.g. `hlbc game.hl -c "decomp 1234"`
+    pub(crate) fn cli_command(&self, command: &str) -> String {
+        format!(
+            "hlbc {} -c {}",
+            shell_quote(&self.0.file),
+            shell_quote(command)
+        )
+    }
+
     pub(crate) fn open_tab(&self, tab: impl AppView + 'static) {
         self.0.new_tab.set(Some(Box::new(tab)));
     }
@@ -57,6 +76,15 @@ impl AppCtxHandle {
         self.0.navigate_to(s)
     }
 
+    /// Whether the compiler generated functions are listed, see [hlbc::Function::is_compiler_generated]
+    pub(crate) fn show_generated(&self) -> bool {
+        self.0.show_generated.get()
+    }
+
+    pub(crate) fn set_show_generated(&self, show: bool) {
+        self.0.show_generated.set(show);
+    }
+
     delegate!(can_navigate_forward; bool);
     delegate!(can_navigate_back; bool);
     delegate!(navigate_forward);
@@ -70,6 +98,10 @@ pub(crate) struct AppCtx {
     file: String,
     code: Bytecode,
     usage: FullUsageReport,
+    summary: ModuleSummary,
+    /// Whole program call graph
+    calls: CallIndex,
+    show_generated: Cell<bool>,
     /// Selection index in the navigation history buffer
     selection: Cell<usize>,
     /// Ring buffer of navigation history
@@ -82,10 +114,15 @@ pub(crate) struct AppCtx {
 impl AppCtx {
     pub(crate) fn new_from_code(file: String, code: Bytecode) -> Self {
         let usage = usage_report(&code);
+        let summary = code.summary();
+        let calls = CallIndex::new(&code);
         Self {
             file,
             code,
             usage,
+            summary,
+            calls,
+            show_generated: Cell::new(false),
             selection: Cell::new(0),
             new_tab: Cell::new(None),
             navigation_history: RefCell::new(VecDeque::with_capacity(NAVIGATION_HISTORY_MAX)),
@@ -167,4 +204,36 @@ impl Item {
             _ => String::new(),
         }
     }
+
+    /// hlbc command printing this item
+    pub(crate) fn command(&self) -> Option<String> {
+        match self {
+            Item::Fun(fun) => Some(format!("fn {}", fun.0)),
+            Item::Type(t) => Some(format!("type {}", t.0)),
+            Item::Global(g) => Some(format!("global {}", g.0)),
+            Item::String(s) => Some(format!("string {}", s.0)),
+            Item::None => None,
+        }
+    }
+}
+
+/// Quote an argument for a POSIX shell if it needs to
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:@=+,".contains(c))
+    {
+        return arg.to_string();
+    }
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
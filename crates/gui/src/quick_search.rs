@@ -0,0 +1,200 @@
+use eframe::egui::{
+    Area, Color32, Frame, Key, Modifiers, Order, RichText, ScrollArea, TextEdit, Ui,
+};
+
+use hlbc::fmt::EnhancedFmt;
+use hlbc::types::Function;
+use hlbc::Bytecode;
+use hlbc_indexing::{Element, ElementSearcher};
+
+use crate::i18n::tr;
+use crate::model::{AppCtxHandle, Item};
+use crate::shortcuts;
+use crate::style::singleline_simple;
+use crate::views::inspector_ui;
+
+/// Number of results shown in the popup
+const RESULTS: usize = 30;
+
+/// Search box in the menu bar with the results of every kind in a popup below it
+#[derive(Default)]
+pub(crate) struct QuickSearch {
+    searcher: ElementSearcher,
+    query: String,
+    results: Vec<Element>,
+    /// Highlighted result, moved with the arrow keys
+    selected: usize,
+    open: bool,
+    /// Value of [AppCtxHandle::show_generated] for the current results
+    show_generated: bool,
+}
+
+impl QuickSearch {
+    /// Forget the results, they point into the previous bytecode
+    pub(crate) fn clear(&mut self) {
+        self.query.clear();
+        self.results.clear();
+        self.selected = 0;
+        self.open = false;
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui, ctx: &AppCtxHandle) {
+        let code = ctx.code();
+        let edit = ui.add(
+            TextEdit::singleline(&mut self.query)
+                .hint_text(format!(
+                    "{} ({})",
+                    tr().search,
+                    ui.ctx().format_shortcut(&shortcuts::QUICK_SEARCH)
+                ))
+                .desired_width(250.0),
+        );
+        if ui.input_mut(|i| i.consume_shortcut(&shortcuts::QUICK_SEARCH)) {
+            edit.request_focus();
+        }
+        if edit.changed() || self.show_generated != ctx.show_generated() {
+            self.show_generated = ctx.show_generated();
+            self.results = self.searcher.search(code, &self.query, RESULTS);
+            if !self.show_generated {
+                self.results.retain(
+                    |elem| !matches!(elem, Element::Function(f) if f.is_compiler_generated(code)),
+                );
+            }
+            self.selected = 0;
+        }
+        if edit.gained_focus() || edit.changed() {
+            self.open = true;
+        }
+
+        let mut moved = false;
+        if edit.has_focus() && !self.results.is_empty() {
+            if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::ArrowDown)) {
+                self.selected = (self.selected + 1).min(self.results.len() - 1);
+                moved = true;
+            }
+            if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::ArrowUp)) {
+                self.selected = self.selected.saturating_sub(1);
+                moved = true;
+            }
+        }
+        if ui.input(|i| i.key_pressed(Key::Escape)) {
+            self.open = false;
+        }
+        if edit.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+            if let Some(&elem) = self.results.get(self.selected) {
+                self.select(ctx, elem);
+            }
+        }
+
+        if !self.open || self.results.is_empty() {
+            return;
+        }
+        let popup = Area::new(edit.id.with("results"))
+            .order(Order::Foreground)
+            .fixed_pos(edit.rect.left_bottom())
+            .show(ui.ctx(), |ui| {
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal_top(|ui| {
+                        ScrollArea::vertical()
+                            .id_source("quick search results")
+                            .max_height(400.0)
+                            .show(ui, |ui| {
+                                ui.set_width(300.0);
+                                for i in 0..self.results.len() {
+                                    let elem = self.results[i];
+                                    let res = ui
+                                        .horizontal(|ui| {
+                                            ui.label(
+                                                RichText::new(elem.kind())
+                                                    .small()
+                                                    .color(Color32::GRAY),
+                                            );
+                                            ui.selectable_label(
+                                                i == self.selected,
+                                                singleline_simple(ui, element_name(code, elem)),
+                                            )
+                                        })
+                                        .inner;
+                                    if moved && i == self.selected {
+                                        res.scroll_to_me(None);
+                                    }
+                                    if res.hovered() {
+                                        self.selected = i;
+                                    }
+                                    if res.clicked() {
+                                        self.select(ctx, elem);
+                                    }
+                                }
+                            });
+                        ui.separator();
+                        ScrollArea::vertical()
+                            .id_source("quick search preview")
+                            .max_height(400.0)
+                            .show(ui, |ui| {
+                                ui.set_width(300.0);
+                                if let Some(&elem) = self.results.get(self.selected) {
+                                    preview(ui, ctx, elem);
+                                }
+                            });
+                    });
+                });
+            });
+        if popup.response.clicked_elsewhere() && !edit.has_focus() {
+            self.open = false;
+        }
+    }
+
+    fn select(&mut self, ctx: &AppCtxHandle, elem: Element) {
+        if let Some(item) = element_item(ctx.code(), elem) {
+            ctx.set_selected(item);
+        }
+        self.open = false;
+    }
+}
+
+fn element_name(code: &Bytecode, elem: Element) -> String {
+    match elem {
+        Element::Function(f) => f.display_header::<EnhancedFmt>(code).to_string(),
+        Element::Class(t) => t.display::<EnhancedFmt>(code).to_string(),
+        Element::String(s) => code[s].to_string(),
+        Element::File(i) => code
+            .debug_file(i)
+            .map(|file| file.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Item to select for a result, a file selects its first function
+fn element_item(code: &Bytecode, elem: Element) -> Option<Item> {
+    match elem {
+        Element::Function(f) => Some(Item::Fun(f)),
+        Element::Class(t) => Some(Item::Type(t)),
+        Element::String(s) => Some(Item::String(s)),
+        Element::File(i) => code
+            .functions
+            .iter()
+            .find(|f| in_file(f, i))
+            .map(|f| Item::Fun(f.findex)),
+    }
+}
+
+/// A function belongs to the file of its first instruction
+fn in_file(f: &Function, file: usize) -> bool {
+    matches!(&f.debug_info, Some(debug) if debug.first().map(|d| d.0) == Some(file))
+}
+
+fn preview(ui: &mut Ui, ctx: &AppCtxHandle, elem: Element) {
+    match elem {
+        Element::File(i) => {
+            let code = ctx.code();
+            ui.heading(element_name(code, elem));
+            let count = code.functions.iter().filter(|f| in_file(f, i)).count();
+            ui.label(format!("{count} functions"));
+        }
+        _ => {
+            if let Some(item) = element_item(ctx.code(), elem) {
+                inspector_ui(ui, ctx.clone(), item);
+            }
+        }
+    }
+}
@@ -4,3 +4,4 @@ pub const NAV_BACK: KeyboardShortcut = KeyboardShortcut::new(Modifiers::ALT, Key
 pub const NAV_FORWARD: KeyboardShortcut = KeyboardShortcut::new(Modifiers::ALT, Key::ArrowRight);
 pub const OPEN: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::O);
 pub const CLOSE: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::W);
+pub const QUICK_SEARCH: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::P);
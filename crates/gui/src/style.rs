@@ -1,4 +1,5 @@
 use std::convert::Into;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::OnceLock;
 
 use eframe::egui::text::{LayoutJob, TextWrapping};
@@ -6,6 +7,8 @@ use eframe::egui::{
     Color32, FontFamily, FontId, InnerResponse, RichText, ScrollArea, TextStyle, Ui, WidgetText,
 };
 use egui_ui_refresh::fonts::{font_family_italic, font_family_medium};
+use hlbc::fmt::FmtStyle;
+
 use crate::model::{AppCtxHandle, Item};
 
 pub(crate) struct Styles {
@@ -23,6 +26,24 @@ pub(crate) fn get() -> &'static Styles {
     })
 }
 
+/// Formatters producing plain text, the ones that can be chosen for the assembly
+pub(crate) const ASM_STYLES: [FmtStyle; 3] =
+    [FmtStyle::Enhanced, FmtStyle::Display, FmtStyle::Debug];
+
+static ASM_STYLE: AtomicU8 = AtomicU8::new(0);
+
+/// Formatter used to display the assembly
+pub(crate) fn asm_style() -> FmtStyle {
+    ASM_STYLES[ASM_STYLE.load(Ordering::Relaxed) as usize]
+}
+
+/// Set the formatter used by [asm_style]. Takes effect on the next frame.
+pub(crate) fn set_asm_style(style: FmtStyle) {
+    if let Some(i) = ASM_STYLES.iter().position(|&s| s == style) {
+        ASM_STYLE.store(i as u8, Ordering::Relaxed);
+    }
+}
+
 pub(crate) fn text(text: impl Into<String>, font: FontId) -> impl Into<WidgetText> {
     RichText::new(text).font(font)
 }
@@ -51,9 +72,12 @@ pub(crate) fn list_view<Elem: Copy>(
                         Color32::WHITE,
                     ),
                 );
-                if let Some(context_menu) = &context_menu {
-                    label.context_menu(|ui| context_menu(ui, &ctx, elem));
-                }
+                label.context_menu(|ui| {
+                    if let Some(context_menu) = &context_menu {
+                        context_menu(ui, &ctx, elem);
+                    }
+                    copy_command_button(ui, &ctx, create_selection(elem).command());
+                });
                 if label.clicked() {
                     ctx.set_selected(create_selection(elem));
                 }
@@ -62,6 +86,22 @@ pub(crate) fn list_view<Elem: Copy>(
     );
 }
 
+/// Button copying the command line that shows the same thing with the hlbc cli
+pub(crate) fn copy_command_button(ui: &mut Ui, ctx: &AppCtxHandle, command: Option<String>) {
+    let Some(command) = command else {
+        return;
+    };
+    let cli = ctx.cli_command(&command);
+    if ui
+        .small_button("Copy as hlbc command")
+        .on_hover_text(&cli)
+        .clicked()
+    {
+        ui.ctx().copy_text(cli);
+        ui.close_menu();
+    }
+}
+
 /// White plain single line text without wrapping and with ellipsis
 pub(crate) fn singleline_simple(ui: &Ui, text: impl Into<String>) -> LayoutJob {
     singleline(
@@ -1,13 +1,16 @@
-use eframe::egui::{Color32, RichText, ScrollArea, Ui, WidgetText};
+use std::ops::Range;
+
+use eframe::egui::{Align, Color32, Layout, RichText, ScrollArea, TextStyle, Ui, WidgetText};
 
 use hlbc::fmt::EnhancedFmt;
 use hlbc::types::FunPtr;
 use hlbc::Resolve;
 use hlbc_decompiler::fmt::FormatOptions;
 use hlbc_decompiler::{decompile_class, decompile_function};
+use hlbc_frontend::crash;
 
 use crate::model::{AppCtxHandle, Item};
-use crate::views::{haxe_source_view, impl_id, impl_view_id};
+use crate::views::{haxe_source_view, impl_id, impl_view_id, minimap};
 use crate::AppView;
 
 #[derive(Default)]
@@ -15,6 +18,8 @@ pub(crate) struct DecompilerView {
     output: String,
     // Cache key for decompilation
     cache_selected: Item,
+    /// Lines visible during the last frame
+    visible: Range<usize>,
 }
 
 impl_view_id!(DecompilerView: unique);
@@ -28,9 +33,22 @@ impl AppView for DecompilerView {
             .into()
     }
 
+    fn command(&self, ctx: AppCtxHandle) -> Option<String> {
+        match ctx.selected() {
+            Item::Fun(fun) => Some(format!("decomp {}", fun.0)),
+            Item::Type(t) => Some(format!("decompt {}", t.0)),
+            _ => None,
+        }
+    }
+
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
         if ctx.selected() != self.cache_selected {
             let code = ctx.code();
+            let fun = match ctx.selected() {
+                Item::Fun(fun) => Some(fun),
+                _ => None,
+            };
+            crash::set_context(self.command(ctx.clone()).unwrap_or_default(), fun);
 
             self.output = match ctx.selected() {
                 Item::Fun(fun) => match code.get(fun) {
@@ -47,12 +65,32 @@ impl AppView for DecompilerView {
             self.cache_selected = ctx.selected();
         }
 
-        ScrollArea::both()
-            .auto_shrink([false, false])
-            .show(ui, |ui| {
-                // TextEdit will show us text we can edit (we don't want that)
-                // We need to pass a mut reference to an immutable str
-                haxe_source_view(ui, &self.output);
+        let lines: Vec<&str> = self.output.lines().collect();
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        ui.with_layout(Layout::right_to_left(Align::Min), |ui| {
+            let scroll_to = minimap(
+                ui,
+                lines.len(),
+                |i| (lines[i].trim().len() as f32 / 80.0).min(1.0),
+                &[],
+                self.visible.clone(),
+            );
+            ui.with_layout(Layout::top_down(Align::Min), |ui| {
+                let mut area = ScrollArea::both().auto_shrink([false, false]);
+                if let Some(line) = scroll_to {
+                    let half = self.visible.len() / 2;
+                    area =
+                        area.vertical_scroll_offset(line.saturating_sub(half) as f32 * row_height);
+                }
+                let output = area.show(ui, |ui| {
+                    // TextEdit will show us text we can edit (we don't want that)
+                    // We need to pass a mut reference to an immutable str
+                    haxe_source_view(ui, &self.output);
+                });
+                let first = (output.state.offset.y / row_height) as usize;
+                let count = (output.inner_rect.height() / row_height).ceil() as usize;
+                self.visible = first..(first + count).min(lines.len());
             });
+        });
     }
 }
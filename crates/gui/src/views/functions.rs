@@ -1,10 +1,12 @@
+use std::collections::HashSet;
+
 use eframe::egui::{Color32, RichText, Ui, WidgetText};
 
-use hlbc::fmt::EnhancedFmt;
+use hlbc::analysis::recursion::find_recursion;
 use hlbc::types::RefFun;
 
 use crate::model::{AppCtxHandle, Item};
-use crate::style::list_view;
+use crate::style::{asm_style, list_view};
 use crate::views::{impl_id, impl_view_id, DecompilerView, InspectorView};
 use crate::AppView;
 
@@ -12,8 +14,12 @@ use crate::AppView;
 pub(crate) struct FunctionsView {
     show_natives: bool,
     show_std: bool,
+    /// Value of [AppCtxHandle::show_generated] when the cache was built
+    show_generated: bool,
     cache: Vec<RefFun>,
     cache_valid: bool,
+    /// Recursive functions, marked in the list
+    recursive: Option<HashSet<RefFun>>,
 }
 
 impl_view_id!(FunctionsView: unique);
@@ -27,11 +33,14 @@ impl AppView for FunctionsView {
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
         // Function list cache
-        if !self.cache_valid {
+        if !self.cache_valid || self.show_generated != ctx.show_generated() {
             self.cache = Vec::new();
+            self.show_generated = ctx.show_generated();
             let code = ctx.code();
             for f in &code.functions {
-                if self.show_std || !f.is_from_std(code) {
+                if (self.show_std || !f.is_from_std(code))
+                    && (self.show_generated || !f.is_compiler_generated(code))
+                {
                     self.cache.push(f.findex);
                 }
             }
@@ -44,6 +53,9 @@ impl AppView for FunctionsView {
             }
             self.cache_valid = true;
         }
+        let recursive = self
+            .recursive
+            .get_or_insert_with(|| find_recursion(ctx.code()).recursive());
 
         ui.horizontal_wrapped(|ui| {
             if ui
@@ -55,6 +67,14 @@ impl AppView for FunctionsView {
             if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
                 self.cache_valid = false;
             }
+            let mut show_generated = self.show_generated;
+            if ui
+                .checkbox(&mut show_generated, "Show compiler generated")
+                .on_hover_text("Entrypoint and special methods like __string or __alloc__")
+                .changed()
+            {
+                ctx.set_show_generated(show_generated);
+            }
         });
 
         ui.add_space(6.0);
@@ -65,7 +85,14 @@ impl AppView for FunctionsView {
             self.cache.len(),
             |i| self.cache[i],
             Item::Fun,
-            |ctx, f| f.display_header::<EnhancedFmt>(ctx.code()).to_string(),
+            |ctx, f| {
+                let header = f.display_header_fmt(asm_style().formatter(), ctx.code());
+                if recursive.contains(&f) {
+                    format!("⟳ {header}")
+                } else {
+                    header.to_string()
+                }
+            },
             Some(|ui: &mut Ui, ctx: &AppCtxHandle, f| {
                 if ui.small_button("Open in inspector").clicked() {
                     let tab = InspectorView::new(Item::Fun(f), ctx.code());
@@ -27,7 +27,7 @@ impl AppView for GlobalsView {
             num,
             RefGlobal,
             Item::Global,
-            |_, g| format!("global@{}", g.0),
+            |ctx, g| g.display_resolved(ctx.code()).to_string(),
             None::<&dyn Fn(&mut Ui, &AppCtxHandle, RefGlobal)>,
         );
     }
@@ -1,5 +1,7 @@
 use eframe::egui::{Color32, Grid, RichText, ScrollArea, Ui, WidgetText};
 
+use hlbc::summary::Section;
+
 use crate::views::{impl_id, impl_view_id, AppView};
 use crate::AppCtxHandle;
 
@@ -21,68 +23,49 @@ impl AppView for InfoView {
             .show(ui, |ui| {
                 Grid::new("info_grid")
                     .striped(true)
-                    .num_columns(2)
+                    .num_columns(3)
                     .show(ui, |ui| {
+                        let summary = ctx.summary();
                         ui.label("File")
                             .on_hover_text("Currently opened bytecode file");
                         ui.label(ctx.file());
+                        ui.label(size(summary.size));
                         ui.end_row();
-                        let code = ctx.code();
                         ui.label("Bytecode version")
                             .on_hover_text("Bytecode file version");
-                        ui.label(code.version.to_string());
+                        ui.label(summary.version.to_string());
                         ui.end_row();
                         ui.label("Debug info")
                             .on_hover_text("Was the bytecode built with debug information ?");
-                        ui.label(if code.debug_files.is_some() {
-                            "yes"
-                        } else {
-                            "no"
-                        });
-                        ui.end_row();
-                        ui.label("Integers").on_hover_text("Integer constants");
-                        ui.label(code.ints.len().to_string());
-                        ui.end_row();
-                        ui.label("Floats").on_hover_text("Float constants");
-                        ui.label(code.floats.len().to_string());
-                        ui.end_row();
-                        ui.label("Strings").on_hover_text("String constants");
-                        ui.label(code.strings.len().to_string());
-                        ui.end_row();
-                        if let Some((_, pos)) = code.bytes.as_ref() {
-                            ui.label("Bytes strings")
-                                .on_hover_text("Bytes constants (since bytecode v5)");
-                            ui.label(pos.len().to_string());
-                            ui.end_row();
-                        }
-                        if let Some(files) = code.debug_files.as_ref() {
-                            ui.label("Source files")
-                                .on_hover_text("Linked source files (debug info)");
-                            ui.label(files.len().to_string());
-                            ui.end_row();
-                        }
-                        ui.label("Types")
-                            .on_hover_text("Hashlink types (classes, functions, ...)");
-                        ui.label(code.types.len().to_string());
-                        ui.end_row();
-                        ui.label("Globals").on_hover_text("Global variables");
-                        ui.label(code.globals.len().to_string());
-                        ui.end_row();
-                        ui.label("Natives")
-                            .on_hover_text("Native function references");
-                        ui.label(code.natives.len().to_string());
+                        ui.label(if summary.debug { "yes" } else { "no" });
                         ui.end_row();
-                        ui.label("Functions")
-                            .on_hover_text("Functions, methods, closures");
-                        ui.label(code.functions.len().to_string());
+                        ui.label("Entrypoint")
+                            .on_hover_text("Function executed on startup");
+                        ui.label(format!("fn@{}", summary.entrypoint.0));
                         ui.end_row();
-                        if let Some(cst) = code.constants.as_ref() {
-                            ui.label("Constant definitions")
-                                .on_hover_text("Global variables initializers (since bytecode v4)");
-                            ui.label(cst.len().to_string());
+                        for s in &summary.sections {
+                            ui.label(s.section.name())
+                                .on_hover_text(s.section.description());
+                            if s.section == Section::Header {
+                                ui.label("");
+                            } else {
+                                ui.label(s.count.to_string());
+                            }
+                            ui.label(size(s.size));
                             ui.end_row();
                         }
                     });
             });
     }
 }
+
+/// Human readable size
+fn size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
@@ -1,18 +1,23 @@
+use std::ops::Range;
+
 use eframe::egui::{
-    CollapsingHeader, Color32, Grid, Link, RichText, ScrollArea, TextEdit, TextStyle, Ui,
-    WidgetText,
+    Align, CollapsingHeader, Color32, Context, Grid, Id, Layout, Link, RichText, ScrollArea,
+    TextEdit, TextStyle, Ui, WidgetText, Window,
 };
 
 use hlbc::analysis::usage::{UsageString, UsageType};
 use hlbc::fmt::EnhancedFmt;
+use hlbc::opcodes::{Opcode, Operand};
+use hlbc::rename::Symbol;
 use hlbc::types::{
-    EnumConstruct, FunPtr, ObjField, RefField, RefFun, RefGlobal, RefString, RefType, Type, TypeObj,
+    ConstantValue, EnumConstruct, FunPtr, ObjField, RefField, RefFun, RefGlobal, RefString,
+    RefType, Type, TypeObj,
 };
 use hlbc::{Bytecode, Resolve};
 
 use crate::model::{AppCtxHandle, Item};
-use crate::style::text_stitch;
-use crate::views::{impl_id, impl_view_id, ViewId};
+use crate::style::{copy_command_button, text_stitch};
+use crate::views::{impl_id, impl_view_id, minimap, Mark, ViewId};
 use crate::{shortcuts, AppView};
 
 /// View detailed information about a bytecode element.
@@ -43,6 +48,10 @@ impl AppView for SyncInspectorView {
         inspector_ui(ui, ctx, selected)
     }
 
+    fn command(&self, ctx: AppCtxHandle) -> Option<String> {
+        ctx.selected().command()
+    }
+
     fn closeable(&self) -> bool {
         false
     }
@@ -73,12 +82,16 @@ impl AppView for InspectorView {
         self.name.clone().into()
     }
 
+    fn command(&self, _ctx: AppCtxHandle) -> Option<String> {
+        self.item.command()
+    }
+
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
         inspector_ui(ui, ctx, self.item);
     }
 }
 
-fn inspector_ui(ui: &mut Ui, ctx: AppCtxHandle, item: Item) {
+pub(crate) fn inspector_ui(ui: &mut Ui, ctx: AppCtxHandle, item: Item) {
     match item {
         Item::Fun(fun) => {
             function_inspector(ui, ctx, fun);
@@ -108,6 +121,7 @@ pub(crate) fn inspector_link(ui: &mut Ui, ctx: AppCtxHandle, item: Item) {
             ctx.open_tab(InspectorView::new(item, ctx.code()));
             ui.close_menu();
         }
+        copy_command_button(ui, &ctx, item.command());
     });
     if res.clicked() {
         ctx.set_selected(item);
@@ -119,6 +133,7 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
     match code.get(fun) {
         FunPtr::Fun(f) => {
             ui.heading(format!("Function : {}@{}", f.name(code), f.findex.0));
+            rename_ui(ui, &ctx, Symbol::Function(fun));
 
             if fun == code.entrypoint {
                 ui.label("Compiler generated entrypoint function");
@@ -145,37 +160,10 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
                     });
             });
 
+            calls_ui(ui, ctx.clone(), fun);
+
             ui.add_space(6.0);
-            ScrollArea::vertical()
-                .id_source("inspector::function::instructions")
-                .auto_shrink([false, false])
-                .show_rows(
-                    ui,
-                    ui.text_style_height(&TextStyle::Monospace),
-                    f.ops.len(),
-                    |ui, range| {
-                        for (i, o) in f
-                            .ops
-                            .iter()
-                            .enumerate()
-                            .skip(range.start)
-                            .take(range.end - range.start)
-                        {
-                            text_stitch(ui, |ui| {
-                                ui.label(
-                                    RichText::new(format!("{i:>3}"))
-                                        .color(Color32::GRAY)
-                                        .monospace(),
-                                );
-                                ui.add_space(10.0);
-                                ui.monospace(o.display(code, f, i as i32, 11).to_string())
-                                    .on_hover_text(o.description());
-                            });
-                            // TODO syntax highlighting
-                            // TODO linking (requires bytecode visitor)
-                        }
-                    },
-                );
+            instructions_ui(ui, code, f);
         }
         FunPtr::Native(n) => {
             ui.heading("Native function");
@@ -183,7 +171,275 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
             ui.label(format!("function name : {}", n.name(code)));
             ui.label(format!("function index : {}", n.findex.0))
                 .on_hover_text("This is the native function unique index in the function pool.");
+            calls_ui(ui, ctx.clone(), fun);
+        }
+    }
+}
+
+/// Callers and callees of a function
+fn calls_ui(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
+    let calls = ctx.calls();
+    for (title, callers) in [("Called by", true), ("Calls", false)] {
+        let edges: Vec<_> = if callers {
+            calls.callers(fun).collect()
+        } else {
+            calls.callees(fun).collect()
+        };
+        CollapsingHeader::new(format!("{title} ({})", edges.len()))
+            .id_source(("inspector::function::calls", callers))
+            .show(ui, |ui| {
+                ScrollArea::vertical()
+                    .id_source(("inspector::function::calls::scroll", callers))
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        for e in edges {
+                            text_stitch(ui, |ui| {
+                                let other = if callers { e.caller } else { e.callee };
+                                inspector_link(ui, ctx.clone(), Item::Fun(other));
+                                ui.label(format!("at {} ({:?})", e.pos, e.kind));
+                            });
+                        }
+                    });
+            });
+    }
+}
+
+/// State of the instructions list of a function, kept in egui memory
+#[derive(Clone, Default)]
+struct InstructionsState {
+    query: String,
+    /// Instructions matching the query
+    hits: Vec<usize>,
+    bookmarks: Vec<usize>,
+    /// Rows visible during the last frame
+    visible: Range<usize>,
+}
+
+/// Instructions of a function with a minimap to navigate long functions
+fn instructions_ui(ui: &mut Ui, code: &Bytecode, f: &hlbc::types::Function) {
+    let id = ui
+        .id()
+        .with(("inspector::function::instructions", f.findex));
+    let mut state = ui
+        .data_mut(|d| d.get_temp::<InstructionsState>(id))
+        .unwrap_or_default();
+
+    ui.horizontal(|ui| {
+        ui.label("Find :");
+        if ui.text_edit_singleline(&mut state.query).changed() {
+            let query = state.query.to_lowercase();
+            state.hits = if query.is_empty() {
+                Vec::new()
+            } else {
+                f.ops()
+                    .filter(|(i, o)| {
+                        o.display(code, f, *i as i32, 11)
+                            .to_string()
+                            .to_lowercase()
+                            .contains(&query)
+                    })
+                    .map(|(i, _)| i)
+                    .collect()
+            };
+        }
+        if !state.query.is_empty() {
+            ui.label(format!("{} found", state.hits.len()));
+        }
+    });
+
+    let mut marks: Vec<(usize, Mark)> = Vec::new();
+    for (i, o) in f.ops() {
+        for (_, operand) in o.operands() {
+            let offsets = match operand {
+                Operand::JumpOffset(offset) => vec![offset],
+                Operand::JumpOffsets(offsets) => offsets.to_vec(),
+                _ => continue,
+            };
+            marks.push((i, Mark::Jump));
+            for offset in offsets {
+                let target = i as i32 + offset + 1;
+                if target >= 0 {
+                    marks.push((target as usize, Mark::Jump));
+                }
+            }
+        }
+    }
+    marks.extend(state.hits.iter().map(|&i| (i, Mark::SearchHit)));
+    marks.extend(state.bookmarks.iter().map(|&i| (i, Mark::Bookmark)));
+
+    let row_height = ui.text_style_height(&TextStyle::Monospace);
+    ui.with_layout(Layout::right_to_left(Align::Min), |ui| {
+        let scroll_to = minimap(
+            ui,
+            f.ops.len(),
+            |i| density(&f.ops[i]),
+            &marks,
+            state.visible.clone(),
+        );
+        ui.with_layout(Layout::top_down(Align::Min), |ui| {
+            let mut area = ScrollArea::vertical()
+                .id_source("inspector::function::instructions")
+                .auto_shrink([false, false]);
+            if let Some(line) = scroll_to {
+                // Center the line
+                let half = state.visible.len() / 2;
+                area = area.vertical_scroll_offset(
+                    line.saturating_sub(half) as f32 * (row_height + ui.spacing().item_spacing.y),
+                );
+            }
+            area.show_rows(ui, row_height, f.ops.len(), |ui, range| {
+                state.visible = range.clone();
+                for (i, o) in f.ops().skip(range.start).take(range.end - range.start) {
+                    text_stitch(ui, |ui| {
+                        let bookmarked = state.bookmarks.contains(&i);
+                        let color = if bookmarked {
+                            Color32::from_rgb(80, 160, 240)
+                        } else if state.hits.contains(&i) {
+                            Color32::from_rgb(240, 220, 60)
+                        } else {
+                            Color32::GRAY
+                        };
+                        ui.label(RichText::new(format!("{i:>3}")).color(color).monospace())
+                            .context_menu(|ui| {
+                                let label = if bookmarked {
+                                    "Remove bookmark"
+                                } else {
+                                    "Add bookmark"
+                                };
+                                if ui.button(label).clicked() {
+                                    if bookmarked {
+                                        state.bookmarks.retain(|&b| b != i);
+                                    } else {
+                                        state.bookmarks.push(i);
+                                    }
+                                    ui.close_menu();
+                                }
+                            });
+                        ui.add_space(10.0);
+                        ui.monospace(o.display(code, f, i as i32, 11).to_string())
+                            .on_hover_text(o.description());
+                    });
+                    // TODO syntax highlighting
+                    // TODO linking (requires bytecode visitor)
+                }
+            });
+        });
+    });
+
+    ui.data_mut(|d| d.insert_temp(id, state));
+}
+
+/// Visual weight of an instruction on the minimap
+fn density(op: &Opcode) -> f32 {
+    match op {
+        Opcode::Label | Opcode::Nop => 0.0,
+        _ => (0.25 + op.registers().len() as f32 * 0.15).min(1.0),
+    }
+}
+
+/// Text field to rename a bytecode element, an empty name restores the original one.
+/// The rename of a method or a field can be propagated to the related elements, see [rename_propagation_window].
+fn rename_ui(ui: &mut Ui, ctx: &AppCtxHandle, symbol: Symbol) {
+    let code = ctx.code();
+    let renames = &code.renames;
+    let id = ui.id().with(("rename", symbol));
+    let mut name = ui
+        .data_mut(|d| d.get_temp::<String>(id))
+        .unwrap_or_else(|| {
+            renames
+                .get(symbol)
+                .map(|name| name.to_string())
+                .unwrap_or_default()
+        });
+    ui.horizontal(|ui| {
+        ui.label("Rename :");
+        if ui
+            .add(TextEdit::singleline(&mut name).hint_text("original name"))
+            .changed()
+        {
+            // A single line edit has no line break
+            let _ = renames.set(symbol, name.as_str());
+        }
+        if matches!(symbol, Symbol::Function(_) | Symbol::Field(..))
+            && !name.is_empty()
+            && ui
+                .button("Propagate")
+                .on_hover_text("Rename the overriding methods, the bound functions and the matching virtual fields too")
+                .clicked()
+        {
+            let sites = code.rename_propagation(symbol);
+            ui.data_mut(|d| d.insert_temp(Id::new(PROPAGATION_ID), (sites, name.clone())));
         }
+    });
+    ui.data_mut(|d| d.insert_temp(id, name));
+}
+
+const PROPAGATION_ID: &str = "rename propagation";
+
+/// Preview of the elements a rename propagates to, opened by [rename_ui]
+pub(crate) fn rename_propagation_window(egui: &Context, ctx: &AppCtxHandle) {
+    let id = Id::new(PROPAGATION_ID);
+    let Some((sites, name)) = egui.data_mut(|d| d.get_temp::<(Vec<Symbol>, String)>(id)) else {
+        return;
+    };
+    let code = ctx.code();
+    let mut open = true;
+    let mut done = false;
+    Window::new("Propagate rename")
+        .id(id)
+        .open(&mut open)
+        .collapsible(false)
+        .show(egui, |ui| {
+            if sites.is_empty() {
+                ui.label("No other element to rename");
+                return;
+            }
+            ui.label(format!("Also rename to {name} :"));
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for &site in &sites {
+                    ui.monospace(symbol_name(code, site));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    for &site in &sites {
+                        let _ = code.renames.set(site, name.as_str());
+                    }
+                    done = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    done = true;
+                }
+            });
+        });
+    if !open || done {
+        egui.data_mut(|d| d.remove::<(Vec<Symbol>, String)>(id));
+    }
+}
+
+/// Description of a renamable element
+fn symbol_name(code: &Bytecode, symbol: Symbol) -> String {
+    match symbol {
+        Symbol::Function(f) => f.display_header::<EnhancedFmt>(code).to_string(),
+        Symbol::Type(s) => code[s].to_string(),
+        Symbol::Field(class, field) => {
+            let field = code
+                .types
+                .iter()
+                .filter_map(Type::get_type_obj)
+                .find(|obj| obj.name == class)
+                .and_then(|obj| obj.fields.get(field.0))
+                .map(|f| f.name(code))
+                .unwrap_or_default();
+            format!("{}.{field}", code[class])
+        }
+        Symbol::Global(g) => format!("global{g}"),
+        Symbol::Virtual(t) => t.display::<EnhancedFmt>(code).to_string(),
+        Symbol::VirtualField(s, t) => format!(
+            "virtual field {} : {}",
+            code[s],
+            t.display::<EnhancedFmt>(code)
+        ),
     }
 }
 
@@ -261,8 +517,16 @@ fn type_usage_report(ui: &mut Ui, ctx: AppCtxHandle, t: RefType) {
                             ui.label("Type of function");
                             inspector_link(ui, ctx.clone(), Item::Fun(f));
                         }
-                        UsageType::Register(f) => {
-                            ui.label("Type of function register in");
+                        UsageType::Register(f, reg) => {
+                            ui.label(format!("Type of register {reg} in"));
+                            inspector_link(ui, ctx.clone(), Item::Fun(f));
+                        }
+                        UsageType::Global(g) => {
+                            ui.label("Type of");
+                            inspector_link(ui, ctx.clone(), Item::Global(g));
+                        }
+                        UsageType::Instruction(f, pos) => {
+                            ui.label(format!("Instruction {pos} in"));
                             inspector_link(ui, ctx.clone(), Item::Fun(f));
                         }
                     });
@@ -289,6 +553,7 @@ fn wrapper_type_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType) {
 fn obj_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType, obj: &TypeObj) {
     let code = ctx.code();
     ui.heading(format!("Class : {}", t.display::<EnhancedFmt>(code)));
+    rename_ui(ui, &ctx, Symbol::Type(obj.name));
     if let Some(super_) = obj.super_ {
         text_stitch(ui, |ui| {
             ui.label("extends");
@@ -315,12 +580,13 @@ fn obj_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType, obj: &TypeObj) {
                     .num_columns(3)
                     .show(ui, |ui| {
                         for (i, f) in obj.own_fields.iter().enumerate() {
-                            ui.label(&*f.name(code));
+                            let field = RefField(i + obj.fields.len() - obj.own_fields.len());
+                            let symbol = Symbol::Field(obj.name, field);
+                            ui.label(&*code.renames.get(symbol).unwrap_or_else(|| f.name(code)))
+                                .on_hover_text("Right click to rename")
+                                .context_menu(|ui| rename_ui(ui, &ctx, symbol));
                             inspector_link(ui, ctx.clone(), Item::Type(f.t));
-                            if let Some(&binding) = obj
-                                .bindings
-                                .get(&RefField(i + obj.fields.len() - obj.own_fields.len()))
-                            {
+                            if let Some(&binding) = obj.bindings.get(&field) {
                                 text_stitch(ui, |ui| {
                                     ui.monospace("bound to");
                                     inspector_link(ui, ctx.clone(), Item::Fun(binding));
@@ -393,6 +659,7 @@ fn enum_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType) {
 
 fn virtual_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType, fields: &[ObjField]) {
     ui.heading(t.display::<EnhancedFmt>(ctx.code()).to_string());
+    rename_ui(ui, &ctx, Symbol::Virtual(t));
     ui.separator();
     let code = ctx.code();
     Grid::new("inspector::virtual::fields")
@@ -400,7 +667,9 @@ fn virtual_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType, fields: &[ObjFi
         .num_columns(2)
         .show(ui, |ui| {
             for f in fields {
-                ui.label(&*f.name(code));
+                ui.label(&*f.virtual_name(code))
+                    .on_hover_text("Right click to rename")
+                    .context_menu(|ui| rename_ui(ui, &ctx, Symbol::VirtualField(f.name, f.t)));
                 inspector_link(ui, ctx.clone(), Item::Type(f.t));
                 ui.end_row();
             }
@@ -427,7 +696,29 @@ fn global_inspector(ui: &mut Ui, ctx: AppCtxHandle, g: RefGlobal) {
         .map(|(csts, &idx)| &csts[idx])
     {
         ui.label("This global is initialized by a constant definition");
-        ui.label(format!("field initializers : {:?}", cst.fields));
+        let code = ctx.code();
+        if let Some(obj) = cst.obj(code) {
+            Grid::new("inspector::global::constant")
+                .striped(true)
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (f, value) in obj.fields.iter().zip(cst.values(code)) {
+                        ui.label(&*f.name(code));
+                        match value {
+                            ConstantValue::Type(t) => {
+                                inspector_link(ui, ctx.clone(), Item::Type(t))
+                            }
+                            ConstantValue::Global(g) => {
+                                inspector_link(ui, ctx.clone(), Item::Global(g))
+                            }
+                            value => {
+                                ui.monospace(value.to_string());
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+        }
     } else {
         ui.label("This global is initialized with code");
         text_stitch(ui, |ui| {
@@ -0,0 +1,96 @@
+use std::ops::Range;
+
+use eframe::egui::{pos2, vec2, Color32, Rect, Sense, Stroke, Ui};
+
+/// Width of the minimap strip
+const WIDTH: f32 = 16.0;
+
+/// A mark on a line of the minimap
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Mark {
+    /// Source or destination of a jump
+    Jump,
+    SearchHit,
+    Bookmark,
+}
+
+impl Mark {
+    fn color(self) -> Color32 {
+        match self {
+            Mark::Jump => Color32::from_rgb(220, 140, 40),
+            Mark::SearchHit => Color32::from_rgb(240, 220, 60),
+            Mark::Bookmark => Color32::from_rgb(80, 160, 240),
+        }
+    }
+}
+
+/// Vertical strip showing the density of a long list of lines, with marks on some lines and the visible range.
+/// `density` gives a value between 0 and 1 for each line. Returns the line clicked or dragged to.
+pub(crate) fn minimap(
+    ui: &mut Ui,
+    lines: usize,
+    density: impl Fn(usize) -> f32,
+    marks: &[(usize, Mark)],
+    visible: Range<usize>,
+) -> Option<usize> {
+    let (rect, response) =
+        ui.allocate_exact_size(vec2(WIDTH, ui.available_height()), Sense::click_and_drag());
+    if lines == 0 || !ui.is_rect_visible(rect) {
+        return None;
+    }
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let line_y = |line: usize| rect.top() + rect.height() * line as f32 / lines as f32;
+
+    // One row of pixels can cover multiple lines, we show the densest one
+    let rows = rect.height().max(1.0) as usize;
+    for row in 0..rows {
+        let start = row * lines / rows;
+        let end = ((row + 1) * lines / rows).max(start + 1).min(lines);
+        let d = (start..end).map(&density).fold(0.0, f32::max);
+        if d > 0.0 {
+            let width = (WIDTH - 4.0) * d.clamp(0.0, 1.0);
+            let y = rect.top() + row as f32;
+            painter.rect_filled(
+                Rect::from_min_size(pos2(rect.left() + 2.0, y), vec2(width, 1.0)),
+                0.0,
+                Color32::from_gray(110),
+            );
+        }
+    }
+
+    // Visible range
+    if visible.start < visible.end {
+        let view = Rect::from_x_y_ranges(
+            rect.x_range(),
+            line_y(visible.start)..=line_y(visible.end).max(line_y(visible.start) + 2.0),
+        );
+        painter.rect_filled(view, 0.0, Color32::from_white_alpha(20));
+        painter.rect_stroke(view, 0.0, Stroke::new(1.0, Color32::from_white_alpha(60)));
+    }
+
+    // Marks are drawn over the density, bookmarks last so they stay visible
+    for kind in [Mark::Jump, Mark::SearchHit, Mark::Bookmark] {
+        let (x, width) = match kind {
+            Mark::Jump => (rect.right() - 4.0, 4.0),
+            _ => (rect.left(), WIDTH),
+        };
+        for &(line, _) in marks.iter().filter(|(_, k)| *k == kind) {
+            painter.rect_filled(
+                Rect::from_min_size(pos2(x, line_y(line) - 1.0), vec2(width, 2.0)),
+                0.0,
+                kind.color(),
+            );
+        }
+    }
+
+    if response.clicked() || response.dragged() {
+        response.interact_pointer_pos().map(|pos| {
+            let t = ((pos.y - rect.top()) / rect.height()).clamp(0.0, 1.0);
+            ((t * lines as f32) as usize).min(lines - 1)
+        })
+    } else {
+        None
+    }
+}
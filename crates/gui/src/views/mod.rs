@@ -2,7 +2,7 @@ use std::any::type_name;
 use std::hash::{Hash, Hasher};
 
 use eframe::egui::{Ui, WidgetText};
-use egui_dock::TabViewer;
+use egui_dock::{NodeIndex, SurfaceIndex, TabViewer};
 
 pub(crate) use classes::*;
 pub(crate) use decompiler::*;
@@ -12,13 +12,13 @@ pub(crate) use globals::*;
 pub(crate) use haxe_source_view::*;
 pub(crate) use info::*;
 pub(crate) use inspector::*;
-#[cfg(feature = "search")]
-pub(crate) use search::*;
+pub(crate) use minimap::*;
 #[cfg(feature = "examples")]
 pub(crate) use source::*;
 pub(crate) use strings::*;
 
 use crate::model::AppCtxHandle;
+use crate::style::copy_command_button;
 
 #[cfg(feature = "callgraph")]
 mod callgraph;
@@ -30,8 +30,7 @@ mod globals;
 mod haxe_source_view;
 mod info;
 mod inspector;
-#[cfg(feature = "search")]
-mod search;
+mod minimap;
 #[cfg(feature = "examples")]
 mod source;
 mod strings;
@@ -59,6 +58,16 @@ impl TabViewer for DynamicTabViewer {
     fn scroll_bars(&self, tab: &Self::Tab) -> [bool; 2] {
         [false, false]
     }
+
+    fn context_menu(
+        &mut self,
+        ui: &mut Ui,
+        tab: &mut Self::Tab,
+        _surface: SurfaceIndex,
+        _node: NodeIndex,
+    ) {
+        copy_command_button(ui, &self.0, tab.command(self.0.clone()));
+    }
 }
 
 /// The actual trait that needs to be implemented by a view
@@ -72,6 +81,11 @@ pub(crate) trait AppView {
     fn closeable(&self) -> bool {
         true
     }
+
+    /// hlbc command showing the content of the view, see [AppCtxHandle::cli_command]
+    fn command(&self, _ctx: AppCtxHandle) -> Option<String> {
+        None
+    }
 }
 
 impl PartialEq for dyn AppView {
@@ -1,5 +1,6 @@
 use eframe::egui::{Color32, RichText, Ui, WidgetText};
 
+use hlbc::analysis::trace::find_traces;
 use hlbc::types::RefString;
 
 use crate::model::{AppCtxHandle, Item};
@@ -8,7 +9,10 @@ use crate::views::{impl_id, impl_view_id};
 use crate::AppView;
 
 #[derive(Default)]
-pub(crate) struct StringsView;
+pub(crate) struct StringsView {
+    /// Strings passed to trace, only shown when Some
+    traces: Option<Vec<RefString>>,
+}
 
 impl_view_id!(StringsView: unique);
 
@@ -20,15 +24,44 @@ impl AppView for StringsView {
     }
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
-        let num = ctx.code().strings.len();
-        list_view(
-            ui,
-            ctx,
-            num,
-            RefString,
-            Item::String,
-            |ctx, s| ctx.code()[s].to_string(),
-            None::<&dyn Fn(&mut Ui, &AppCtxHandle, RefString)>,
-        );
+        let mut traces_only = self.traces.is_some();
+        if ui
+            .checkbox(&mut traces_only, "Traces only")
+            .on_hover_text("Only show the strings passed to trace()")
+            .changed()
+        {
+            self.traces = traces_only.then(|| {
+                let mut traces: Vec<RefString> = find_traces(ctx.code())
+                    .into_iter()
+                    .filter_map(|t| t.msg)
+                    .collect();
+                traces.sort_unstable();
+                traces.dedup();
+                traces
+            });
+        }
+
+        if let Some(traces) = &self.traces {
+            list_view(
+                ui,
+                ctx,
+                traces.len(),
+                |i| traces[i],
+                Item::String,
+                |ctx, s| ctx.code()[s].to_string(),
+                None::<&dyn Fn(&mut Ui, &AppCtxHandle, RefString)>,
+            );
+        } else {
+            let num = ctx.code().strings.len();
+            list_view(
+                ui,
+                ctx,
+                num,
+                RefString,
+                Item::String,
+                |ctx, s| ctx.code()[s].to_string(),
+                None::<&dyn Fn(&mut Ui, &AppCtxHandle, RefString)>,
+            );
+        }
     }
 }
@@ -0,0 +1,52 @@
+//! Measure the time it takes to decode a bytecode file.
+//!
+//! ```shell
+//! cargo bench -p hlbc --bench load -- [file] [iterations]
+//! ```
+//!
+//! The file defaults to `data/Empty.hl`, use a big game file for meaningful results. The file is read once in memory
+//! so only the decoding is measured.
+
+use std::fs;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use hlbc::opcodes::Opcode;
+use hlbc::Bytecode;
+
+fn main() -> hlbc::Result<()> {
+    // cargo passes --bench to the benchmark
+    let mut args = std::env::args().skip(1).filter(|a| !a.starts_with("--"));
+    let path = args.next().unwrap_or_else(|| "../../data/Empty.hl".to_owned());
+    let iterations: u32 = args
+        .next()
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(20);
+
+    let data = fs::read(&path)?;
+    let code = Bytecode::deserialize(data.as_slice())?;
+    let ops: usize = code.functions.iter().map(|f| f.ops.len()).sum();
+    println!(
+        "{path} : {} functions, {ops} instructions, {} bytes per instruction",
+        code.functions.len(),
+        size_of::<Opcode>()
+    );
+
+    let mut min = Duration::MAX;
+    let mut total = Duration::ZERO;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let code = Bytecode::deserialize(data.as_slice())?;
+        let elapsed = start.elapsed();
+        drop(code);
+        min = min.min(elapsed);
+        total += elapsed;
+    }
+    println!(
+        "decode : mean {:?}, min {:?} over {iterations} iterations",
+        total / iterations,
+        min
+    );
+    Ok(())
+}
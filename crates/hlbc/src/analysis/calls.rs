@@ -0,0 +1,164 @@
+//! Whole program call graph with the inverse edges.
+//!
+//! Unlike [graph::call_graph](crate::analysis::graph), this doesn't need petgraph and covers every function at once.
+//! Both directions are indexed so finding the callers or the callees of a function is a lookup.
+
+use crate::analysis::devirt::{closure_source, ClosureSource, Devirtualizer};
+use crate::types::{Function, RefFun};
+use crate::{Bytecode, Opcode};
+
+/// How a function reaches another
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CallKind {
+    /// Call0, Call1, ..., CallN
+    Direct,
+    /// CallMethod or CallThis on a known type
+    Method,
+    /// CallMethod or CallThis landing in an override from a subclass of the receiver type
+    Override,
+    /// CallClosure of a closure created in the same function
+    Closure,
+    /// CallClosure of a class field bound to a function
+    Binding,
+    /// Closure created with StaticClosure or InstanceClosure, it might be called later
+    Reference,
+}
+
+/// An edge of the call graph
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct CallEdge {
+    pub caller: RefFun,
+    pub callee: RefFun,
+    pub kind: CallKind,
+    /// Position of the instruction in the caller
+    pub pos: usize,
+}
+
+/// Call graph of the whole bytecode, indexed by callers and callees
+#[derive(Debug, Clone, Default)]
+pub struct CallIndex {
+    edges: Vec<CallEdge>,
+    /// Edges from each function
+    callees: Vec<Vec<usize>>,
+    /// Edges to each function
+    callers: Vec<Vec<usize>>,
+}
+
+impl CallIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let n = code.findex_max();
+        let mut index = Self {
+            edges: Vec::new(),
+            callees: vec![Vec::new(); n],
+            callers: vec![Vec::new(); n],
+        };
+        let devirt = Devirtualizer::new(code);
+        for f in &code.functions {
+            for pos in 0..f.ops.len() {
+                for (kind, callee) in resolve_call(&devirt, code, f, pos) {
+                    if callee.0 < n && f.findex.0 < n {
+                        let e = index.edges.len();
+                        index.edges.push(CallEdge {
+                            caller: f.findex,
+                            callee,
+                            kind,
+                            pos,
+                        });
+                        index.callees[f.findex.0].push(e);
+                        index.callers[callee.0].push(e);
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    /// Every edge of the graph
+    pub fn edges(&self) -> &[CallEdge] {
+        &self.edges
+    }
+
+    /// Edges going out of a function, in instruction order
+    pub fn callees(&self, f: RefFun) -> impl Iterator<Item = &CallEdge> + '_ {
+        self.callees
+            .get(f.0)
+            .into_iter()
+            .flatten()
+            .map(|&e| &self.edges[e])
+    }
+
+    /// Edges coming into a function
+    pub fn callers(&self, f: RefFun) -> impl Iterator<Item = &CallEdge> + '_ {
+        self.callers
+            .get(f.0)
+            .into_iter()
+            .flatten()
+            .map(|&e| &self.edges[e])
+    }
+}
+
+/// Functions that an instruction calls or references
+fn resolve_call(
+    devirt: &Devirtualizer,
+    code: &Bytecode,
+    f: &Function,
+    pos: usize,
+) -> Vec<(CallKind, RefFun)> {
+    match f.ops[pos] {
+        Opcode::Call0 { fun, .. }
+        | Opcode::Call1 { fun, .. }
+        | Opcode::Call2 { fun, .. }
+        | Opcode::Call3 { fun, .. }
+        | Opcode::Call4 { fun, .. }
+        | Opcode::CallN { fun, .. } => vec![(CallKind::Direct, fun)],
+        Opcode::CallMethod { .. } | Opcode::CallThis { .. } => devirt
+            .targets(f, pos)
+            .into_iter()
+            .enumerate()
+            .map(|(i, fun)| {
+                let kind = if i == 0 {
+                    CallKind::Method
+                } else {
+                    CallKind::Override
+                };
+                (kind, fun)
+            })
+            .collect(),
+        Opcode::CallClosure { fun, .. } => match closure_source(code, f, fun, pos) {
+            Some(ClosureSource::Closure(fun)) => vec![(CallKind::Closure, fun)],
+            Some(ClosureSource::Binding(fun)) => vec![(CallKind::Binding, fun)],
+            None => Vec::new(),
+        },
+        Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+            vec![(CallKind::Reference, fun)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::calls::{CallEdge, CallIndex, CallKind};
+    use crate::testing::sample;
+    use crate::types::RefFun;
+
+    #[test]
+    fn test_call_index() {
+        let code = sample();
+        let index = CallIndex::new(&code);
+        let edge = |callee, pos| CallEdge {
+            caller: RefFun(0),
+            callee: RefFun(callee),
+            kind: CallKind::Direct,
+            pos,
+        };
+        assert_eq!(index.edges(), &[edge(1, 3), edge(2, 4)]);
+        assert_eq!(index.callees(RefFun(0)).count(), 2);
+        assert_eq!(
+            index.callers(RefFun(2)).collect::<Vec<_>>(),
+            vec![&edge(2, 4)]
+        );
+        assert_eq!(index.callers(RefFun(0)).count(), 0);
+        assert_eq!(index.callers(RefFun(3)).count(), 0);
+    }
+}
@@ -0,0 +1,315 @@
+//! Control flow graph of a function.
+//!
+//! A function is split into basic blocks, sequences of instructions with a single entry and a single exit. Blocks
+//! are delimited by jump targets, `Label` (the start of a loop) and by the instructions altering the control flow
+//! (jumps, `Switch`, `Trap`, `Ret`, `Throw` and `Rethrow`).
+
+use std::ops::Range;
+
+use crate::types::{Function, JumpOffset};
+use crate::Opcode;
+
+/// Index of a block in [Cfg::blocks]
+pub type BlockId = usize;
+
+/// The reason control can flow from a block to another
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction
+    Fallthrough,
+    /// Unconditional jump
+    Jump,
+    /// Conditional jump taken
+    Branch,
+    /// Jump to a case of a `Switch`, with its index
+    Case(usize),
+    /// Jump to the exception handler of a `Trap`
+    Exception,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Edge {
+    pub to: BlockId,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BasicBlock {
+    /// Instructions of this block
+    pub ops: Range<usize>,
+    pub succs: Vec<Edge>,
+    /// Blocks with an edge to this one, in ascending order
+    pub preds: Vec<BlockId>,
+}
+
+impl BasicBlock {
+    /// Index of the last instruction in this block
+    pub fn last(&self) -> usize {
+        self.ops.end - 1
+    }
+
+    /// Blocks control can flow to after this one, without duplicates
+    pub fn successors(&self) -> impl Iterator<Item = BlockId> + '_ {
+        self.succs
+            .iter()
+            .enumerate()
+            .filter(|&(i, e)| !self.succs[..i].iter().any(|prev| prev.to == e.to))
+            .map(|(_, e)| e.to)
+    }
+}
+
+/// Control flow graph of a function, the entry block is the first one.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cfg {
+    /// Blocks in the order of their instructions
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    pub fn new(f: &Function) -> Self {
+        let nops = f.ops.len();
+        if nops == 0 {
+            return Self { blocks: Vec::new() };
+        }
+
+        // Find the start of every block
+        let mut leaders = vec![false; nops];
+        leaders[0] = true;
+        for (i, op) in f.ops.iter().enumerate() {
+            if matches!(op, Opcode::Label) {
+                leaders[i] = true;
+            }
+            let (targets, _) = successors(i, op);
+            if !targets.is_empty() || is_terminator(op) {
+                if i + 1 < nops {
+                    leaders[i + 1] = true;
+                }
+                for (target, _) in targets {
+                    if let Some(l) = leaders.get_mut(target) {
+                        *l = true;
+                    }
+                }
+            }
+        }
+
+        // Map each instruction to its block
+        let mut block_of = vec![0; nops];
+        let mut starts = Vec::new();
+        for (i, &leader) in leaders.iter().enumerate() {
+            if leader {
+                starts.push(i);
+            }
+            block_of[i] = starts.len() - 1;
+        }
+
+        let mut blocks: Vec<BasicBlock> = starts
+            .iter()
+            .enumerate()
+            .map(|(b, &start)| {
+                let end = starts.get(b + 1).copied().unwrap_or(nops);
+                let last = end - 1;
+                let (targets, fallthrough) = successors(last, &f.ops[last]);
+                let mut succs: Vec<Edge> = targets
+                    .into_iter()
+                    .filter(|&(target, _)| target < nops)
+                    .map(|(target, kind)| Edge {
+                        to: block_of[target],
+                        kind,
+                    })
+                    .collect();
+                if fallthrough && end < nops {
+                    succs.push(Edge {
+                        to: b + 1,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                }
+                BasicBlock {
+                    ops: start..end,
+                    succs,
+                    preds: Vec::new(),
+                }
+            })
+            .collect();
+
+        for b in 0..blocks.len() {
+            for i in 0..blocks[b].succs.len() {
+                let to = blocks[b].succs[i].to;
+                if !blocks[to].preds.contains(&b) {
+                    blocks[to].preds.push(b);
+                }
+            }
+        }
+        for block in &mut blocks {
+            block.preds.sort_unstable();
+        }
+
+        Self { blocks }
+    }
+
+    /// Find the block containing an instruction
+    pub fn block_at(&self, pos: usize) -> Option<BlockId> {
+        let b = self.blocks.partition_point(|b| b.ops.end <= pos);
+        (b < self.blocks.len() && self.blocks[b].ops.contains(&pos)).then_some(b)
+    }
+
+    /// Blocks which can't be reached from the entry block
+    pub fn unreachable(&self) -> Vec<BlockId> {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut stack = Vec::new();
+        if !self.blocks.is_empty() {
+            stack.push(0);
+        }
+        while let Some(b) = stack.pop() {
+            if !seen[b] {
+                seen[b] = true;
+                stack.extend(self.blocks[b].succs.iter().map(|e| e.to));
+            }
+        }
+        seen.iter()
+            .enumerate()
+            .filter_map(|(b, seen)| (!seen).then_some(b))
+            .collect()
+    }
+}
+
+impl Function {
+    /// Build the control flow graph of this function
+    pub fn cfg(&self) -> Cfg {
+        Cfg::new(self)
+    }
+
+    /// Split this function in basic blocks, the first one is the entry block.
+    /// Use [Function::cfg] to also get the block containing an instruction.
+    pub fn basic_blocks(&self) -> Vec<BasicBlock> {
+        Cfg::new(self).blocks
+    }
+}
+
+/// Jump targets of an instruction and whether execution can continue with the next instruction.
+fn successors(pos: usize, op: &Opcode) -> (Vec<(usize, EdgeKind)>, bool) {
+    let target = |offset: JumpOffset| (pos as i64 + offset as i64 + 1).max(0) as usize;
+    match op {
+        Opcode::JTrue { offset, .. }
+        | Opcode::JFalse { offset, .. }
+        | Opcode::JNull { offset, .. }
+        | Opcode::JNotNull { offset, .. }
+        | Opcode::JSLt { offset, .. }
+        | Opcode::JSGte { offset, .. }
+        | Opcode::JSGt { offset, .. }
+        | Opcode::JSLte { offset, .. }
+        | Opcode::JULt { offset, .. }
+        | Opcode::JUGte { offset, .. }
+        | Opcode::JNotLt { offset, .. }
+        | Opcode::JNotGte { offset, .. }
+        | Opcode::JEq { offset, .. }
+        | Opcode::JNotEq { offset, .. } => (vec![(target(*offset), EdgeKind::Branch)], true),
+        Opcode::JAlways { offset } => (vec![(target(*offset), EdgeKind::Jump)], false),
+        // Values out of range continue with the next instruction
+        Opcode::Switch { offsets, .. } => (
+            offsets
+                .iter()
+                .enumerate()
+                .map(|(i, &offset)| (target(offset), EdgeKind::Case(i)))
+                .collect(),
+            true,
+        ),
+        Opcode::Trap { offset, .. } => (vec![(target(*offset), EdgeKind::Exception)], true),
+        _ => (Vec::new(), !is_terminator(op)),
+    }
+}
+
+/// Instructions after which execution never continues in the same function
+fn is_terminator(op: &Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::Ret { .. } | Opcode::Throw { .. } | Opcode::Rethrow { .. } | Opcode::JAlways { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::{Cfg, EdgeKind};
+    use crate::types::{Function, RefFun, RefString, RefType, Reg};
+    use crate::Opcode;
+
+    #[test]
+    fn test_cfg() -> crate::Result<()> {
+        let f = Function::from_asm(
+            "
+            .fun fn@0 type@0
+            .reg type@0
+                Switch reg0 [case0, case1] 0
+                JAlways end
+            case0:
+                Trap reg0 handler
+                Ret reg0
+            case1:
+                Nop
+                JAlways end
+            handler:
+                Rethrow reg0
+                Nop
+            end:
+                Ret reg0
+            .end",
+        )?;
+        let cfg = Cfg::new(&f);
+        let ranges: Vec<_> = cfg.blocks.iter().map(|b| b.ops.clone()).collect();
+        assert_eq!(ranges, vec![0..1, 1..2, 2..3, 3..4, 4..6, 6..7, 7..8, 8..9]);
+        let succs = |b: usize| -> Vec<(usize, EdgeKind)> {
+            cfg.blocks[b].succs.iter().map(|e| (e.to, e.kind)).collect()
+        };
+        assert_eq!(
+            succs(0),
+            vec![
+                (2, EdgeKind::Case(0)),
+                (4, EdgeKind::Case(1)),
+                (1, EdgeKind::Fallthrough)
+            ]
+        );
+        assert_eq!(succs(1), vec![(7, EdgeKind::Jump)]);
+        assert_eq!(
+            succs(2),
+            vec![(5, EdgeKind::Exception), (3, EdgeKind::Fallthrough)]
+        );
+        assert!(succs(3).is_empty() && succs(5).is_empty() && succs(7).is_empty());
+        assert_eq!(succs(6), vec![(7, EdgeKind::Fallthrough)]);
+        assert_eq!(cfg.blocks[7].preds, vec![1, 4, 6]);
+        assert_eq!(cfg.unreachable(), vec![6]);
+        assert_eq!(cfg.block_at(5), Some(4));
+        assert_eq!(cfg.block_at(9), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_basic_blocks() {
+        // while (r0) {} return
+        let f = Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![RefType(0)],
+            ops: vec![
+                Opcode::Nop,
+                Opcode::Label,
+                Opcode::JFalse {
+                    cond: Reg(0),
+                    offset: 1,
+                },
+                Opcode::JAlways { offset: -3 },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+            debug_info: None,
+            assigns: None,
+            name: RefString(0),
+            parent: None,
+        };
+        let blocks = f.basic_blocks();
+        let ranges: Vec<_> = blocks.iter().map(|b| b.ops.clone()).collect();
+        assert_eq!(ranges, vec![0..1, 1..3, 3..4, 4..5]);
+        assert_eq!(blocks[0].successors().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(blocks[1].successors().collect::<Vec<_>>(), vec![3, 2]);
+        assert_eq!(blocks[2].succs[0].kind, EdgeKind::Jump);
+        assert_eq!(blocks[2].succs[0].to, 1);
+        assert_eq!(blocks[1].preds, vec![0, 2]);
+    }
+}
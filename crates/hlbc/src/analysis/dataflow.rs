@@ -0,0 +1,373 @@
+//! A generic data flow analysis framework over the [Cfg] of a function.
+//!
+//! An analysis defines a lattice (its domain with a join operation) and the effect of each instruction. [solve] then
+//! computes the fixed point with a worklist algorithm, forward or backward. [ReachingDefinitions] and
+//! [ConstantPropagation] are provided as ready-made analyses over registers.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::analysis::cfg::{BlockId, Cfg};
+use crate::types::{Function, RefFloat, RefString, Reg};
+use crate::{Bytecode, Opcode};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A data flow problem
+pub trait Analysis {
+    /// Element of the lattice
+    type Domain: Clone + Eq;
+
+    const DIRECTION: Direction;
+
+    /// Value at the start of the entry block (forward) or the end of exit blocks (backward)
+    fn boundary(&self, f: &Function) -> Self::Domain;
+
+    /// Initial value of every other block, the bottom of the lattice
+    fn bottom(&self, f: &Function) -> Self::Domain;
+
+    /// Merge the value from another path into `into`
+    fn join(&self, into: &mut Self::Domain, other: &Self::Domain);
+
+    /// Apply the effect of an instruction. For backward analyses, `state` is the value after the instruction.
+    fn transfer(&self, f: &Function, pos: usize, op: &Opcode, state: &mut Self::Domain);
+}
+
+/// Fixed point of an analysis, values in program order regardless of the direction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Results<D> {
+    /// Value before the first instruction of each block
+    pub entry: Vec<D>,
+    /// Value after the last instruction of each block
+    pub exit: Vec<D>,
+}
+
+impl<D: Clone + Eq> Results<D> {
+    /// Value just before an instruction (forward) or just after (backward)
+    pub fn at<A: Analysis<Domain = D>>(
+        &self,
+        analysis: &A,
+        f: &Function,
+        cfg: &Cfg,
+        pos: usize,
+    ) -> Option<D> {
+        let b = cfg.block_at(pos)?;
+        let ops = cfg.blocks[b].ops.clone();
+        Some(match A::DIRECTION {
+            Direction::Forward => {
+                let mut state = self.entry[b].clone();
+                for i in ops.start..pos {
+                    analysis.transfer(f, i, &f.ops[i], &mut state);
+                }
+                state
+            }
+            Direction::Backward => {
+                let mut state = self.exit[b].clone();
+                for i in (pos + 1..ops.end).rev() {
+                    analysis.transfer(f, i, &f.ops[i], &mut state);
+                }
+                state
+            }
+        })
+    }
+}
+
+/// Compute the fixed point of an analysis
+pub fn solve<A: Analysis>(analysis: &A, f: &Function, cfg: &Cfg) -> Results<A::Domain> {
+    let n = cfg.blocks.len();
+    let mut entry = vec![analysis.bottom(f); n];
+    let mut exit = vec![analysis.bottom(f); n];
+    let forward = A::DIRECTION == Direction::Forward;
+
+    let mut worklist: VecDeque<BlockId> = if forward {
+        (0..n).collect()
+    } else {
+        (0..n).rev().collect()
+    };
+    let mut queued = vec![true; n];
+
+    while let Some(b) = worklist.pop_front() {
+        queued[b] = false;
+        let block = &cfg.blocks[b];
+        if forward {
+            let mut state = if b == 0 {
+                analysis.boundary(f)
+            } else {
+                analysis.bottom(f)
+            };
+            for &p in &block.preds {
+                analysis.join(&mut state, &exit[p]);
+            }
+            entry[b] = state.clone();
+            for i in block.ops.clone() {
+                analysis.transfer(f, i, &f.ops[i], &mut state);
+            }
+            if state != exit[b] {
+                exit[b] = state;
+                for e in &block.succs {
+                    if !queued[e.to] {
+                        queued[e.to] = true;
+                        worklist.push_back(e.to);
+                    }
+                }
+            }
+        } else {
+            let mut state = if block.succs.is_empty() {
+                analysis.boundary(f)
+            } else {
+                analysis.bottom(f)
+            };
+            for e in &block.succs {
+                analysis.join(&mut state, &entry[e.to]);
+            }
+            exit[b] = state.clone();
+            for i in block.ops.clone().rev() {
+                analysis.transfer(f, i, &f.ops[i], &mut state);
+            }
+            if state != entry[b] {
+                entry[b] = state;
+                for &p in &block.preds {
+                    if !queued[p] {
+                        queued[p] = true;
+                        worklist.push_back(p);
+                    }
+                }
+            }
+        }
+    }
+
+    Results { entry, exit }
+}
+
+/// Register written by an instruction
+pub(crate) fn def(op: &Opcode) -> Option<Reg> {
+    op.defs().first().copied()
+}
+
+/// Registers read by an instruction
+pub(crate) fn uses(op: &Opcode) -> Vec<Reg> {
+    op.uses().into_vec()
+}
+
+/// Where a register value comes from
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Def {
+    /// Function argument
+    Argument,
+    /// Assigned by an instruction
+    At(usize),
+}
+
+/// The definitions which can reach each instruction
+pub struct ReachingDefinitions<'a> {
+    pub code: &'a Bytecode,
+}
+
+impl Analysis for ReachingDefinitions<'_> {
+    /// Every (register, definition) pair reaching a point
+    type Domain = BTreeSet<(Reg, Def)>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, f: &Function) -> Self::Domain {
+        (0..f.args(self.code).len())
+            .map(|i| (Reg(i as u32), Def::Argument))
+            .collect()
+    }
+
+    fn bottom(&self, _f: &Function) -> Self::Domain {
+        BTreeSet::new()
+    }
+
+    fn join(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        into.extend(other.iter().copied());
+    }
+
+    fn transfer(&self, _f: &Function, pos: usize, op: &Opcode, state: &mut Self::Domain) {
+        if let Some(dst) = def(op) {
+            state.retain(|&(r, _)| r != dst);
+            state.insert((dst, Def::At(pos)));
+        }
+    }
+}
+
+/// Value of a register in [ConstantPropagation]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Const {
+    /// Not assigned yet (bottom)
+    Undefined,
+    Int(i32),
+    /// Only constants loaded from the pool are tracked
+    Float(RefFloat),
+    Bool(bool),
+    String(RefString),
+    Null,
+    /// Can take multiple values (top)
+    Unknown,
+}
+
+impl Const {
+    fn join(self, other: Const) -> Const {
+        match (self, other) {
+            (Const::Undefined, c) | (c, Const::Undefined) => c,
+            (a, b) if a == b => a,
+            _ => Const::Unknown,
+        }
+    }
+}
+
+/// The constant value of each register
+pub struct ConstantPropagation<'a> {
+    pub code: &'a Bytecode,
+}
+
+impl Analysis for ConstantPropagation<'_> {
+    /// The value of each register
+    type Domain = Vec<Const>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, f: &Function) -> Self::Domain {
+        let mut state = vec![Const::Undefined; f.regs.len()];
+        for c in state.iter_mut().take(f.args(self.code).len()) {
+            *c = Const::Unknown;
+        }
+        state
+    }
+
+    fn bottom(&self, f: &Function) -> Self::Domain {
+        vec![Const::Undefined; f.regs.len()]
+    }
+
+    fn join(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        for (a, &b) in into.iter_mut().zip(other) {
+            *a = a.join(b);
+        }
+    }
+
+    fn transfer(&self, _f: &Function, _pos: usize, op: &Opcode, state: &mut Self::Domain) {
+        let Some(dst) = def(op) else {
+            return;
+        };
+        let get = |r: Reg| state.get(r.0 as usize).copied().unwrap_or(Const::Unknown);
+        let int = |a: Reg, b: Reg, op: fn(i32, i32) -> Option<i32>| match (get(a), get(b)) {
+            (Const::Int(a), Const::Int(b)) => op(a, b).map_or(Const::Unknown, Const::Int),
+            _ => Const::Unknown,
+        };
+        let value = match *op {
+            Opcode::Mov { src, .. } => get(src),
+            Opcode::Int { ptr, .. } => Const::Int(self.code[ptr]),
+            Opcode::Float { ptr, .. } => Const::Float(ptr),
+            Opcode::Bool { value, .. } => Const::Bool(value),
+            Opcode::String { ptr, .. } => Const::String(ptr),
+            Opcode::Null { .. } => Const::Null,
+            Opcode::Add { a, b, .. } => int(a, b, |a, b| Some(a.wrapping_add(b))),
+            Opcode::Sub { a, b, .. } => int(a, b, |a, b| Some(a.wrapping_sub(b))),
+            Opcode::Mul { a, b, .. } => int(a, b, |a, b| Some(a.wrapping_mul(b))),
+            Opcode::SDiv { a, b, .. } => int(a, b, i32::checked_div),
+            Opcode::SMod { a, b, .. } => int(a, b, i32::checked_rem),
+            Opcode::Shl { a, b, .. } => int(a, b, |a, b| Some(a.wrapping_shl(b as u32))),
+            Opcode::SShr { a, b, .. } => int(a, b, |a, b| Some(a.wrapping_shr(b as u32))),
+            Opcode::UShr { a, b, .. } => {
+                int(a, b, |a, b| Some((a as u32).wrapping_shr(b as u32) as i32))
+            }
+            Opcode::And { a, b, .. } => int(a, b, |a, b| Some(a & b)),
+            Opcode::Or { a, b, .. } => int(a, b, |a, b| Some(a | b)),
+            Opcode::Xor { a, b, .. } => int(a, b, |a, b| Some(a ^ b)),
+            Opcode::Neg { src, .. } => match get(src) {
+                Const::Int(v) => Const::Int(v.wrapping_neg()),
+                _ => Const::Unknown,
+            },
+            Opcode::Not { src, .. } => match get(src) {
+                Const::Bool(v) => Const::Bool(!v),
+                _ => Const::Unknown,
+            },
+            Opcode::Incr { dst } => match get(dst) {
+                Const::Int(v) => Const::Int(v.wrapping_add(1)),
+                _ => Const::Unknown,
+            },
+            Opcode::Decr { dst } => match get(dst) {
+                Const::Int(v) => Const::Int(v.wrapping_sub(1)),
+                _ => Const::Unknown,
+            },
+            _ => Const::Unknown,
+        };
+        if let Some(c) = state.get_mut(dst.0 as usize) {
+            *c = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::Cfg;
+    use crate::analysis::dataflow::{solve, Const, ConstantPropagation, Def, ReachingDefinitions};
+    use crate::testing::{bytecode, fun};
+    use crate::types::{Reg, Type};
+
+    #[test]
+    fn test_dataflow() {
+        // fn(r0) { r1 = 2; r2 = r0 ? 3 : 3; r2 += r1; r1 *= r0; return r2; }
+        let mut code = bytecode(
+            &["main"],
+            vec![Type::Void, Type::I32, fun(&[1], 1)],
+            "
+            .fun fn@0 type@2
+            .reg type@1
+            .reg type@1
+            .reg type@1
+                Int reg1 int@0
+                JFalse reg0 else
+                Int reg2 int@1
+                JAlways end
+            else:
+                Int reg2 int@1
+            end:
+                Add reg2 reg2 reg1
+                Mul reg1 reg1 reg0
+                Ret reg2
+            .end",
+        );
+        code.ints = vec![2, 3];
+        let f = &code.functions[0];
+        let cfg = Cfg::new(f);
+        assert_eq!(cfg.blocks.len(), 4);
+
+        let reaching = solve(&ReachingDefinitions { code: &code }, f, &cfg);
+        assert_eq!(
+            reaching.entry[0].iter().copied().collect::<Vec<_>>(),
+            vec![(Reg(0), Def::Argument)]
+        );
+        assert_eq!(
+            reaching.entry[3].iter().copied().collect::<Vec<_>>(),
+            vec![
+                (Reg(0), Def::Argument),
+                (Reg(1), Def::At(0)),
+                (Reg(2), Def::At(2)),
+                (Reg(2), Def::At(4)),
+            ]
+        );
+
+        let analysis = ConstantPropagation { code: &code };
+        let consts = solve(&analysis, f, &cfg);
+        assert_eq!(
+            consts.entry[0],
+            vec![Const::Unknown, Const::Undefined, Const::Undefined]
+        );
+        assert_eq!(
+            consts.entry[3],
+            vec![Const::Unknown, Const::Int(2), Const::Int(3)]
+        );
+        assert_eq!(
+            consts.at(&analysis, f, &cfg, 6),
+            Some(vec![Const::Unknown, Const::Int(2), Const::Int(5)])
+        );
+        assert_eq!(
+            consts.exit[3],
+            vec![Const::Unknown, Const::Unknown, Const::Int(5)]
+        );
+    }
+}
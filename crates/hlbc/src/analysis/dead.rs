@@ -0,0 +1,118 @@
+//! Detection of the functions that can never execute.
+//!
+//! Functions are reachable from the entrypoint through the [CallIndex]. Methods and functions bound to class fields
+//! can be called dynamically (reflection, interfaces, natives calling back), so they are also considered reachable,
+//! as well as the functions stored in constants.
+
+use crate::analysis::calls::CallIndex;
+use crate::types::{RefFun, Type};
+use crate::Bytecode;
+
+/// Reachability of every function
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Reachability {
+    /// Indexed by findex
+    reachable: Vec<bool>,
+}
+
+impl Reachability {
+    pub fn new(code: &Bytecode) -> Self {
+        Self::with_calls(code, &CallIndex::new(code))
+    }
+
+    /// Reuse an already built call graph
+    pub fn with_calls(code: &Bytecode, calls: &CallIndex) -> Self {
+        let mut reachable = vec![false; code.findex_max()];
+        let mut work = roots(code);
+        while let Some(f) = work.pop() {
+            match reachable.get_mut(f.0) {
+                Some(r) if !*r => *r = true,
+                _ => continue,
+            }
+            work.extend(calls.callees(f).map(|e| e.callee));
+        }
+        Self { reachable }
+    }
+
+    pub fn is_reachable(&self, f: RefFun) -> bool {
+        self.reachable.get(f.0).copied().unwrap_or(false)
+    }
+
+    /// Functions and natives that can never execute
+    pub fn dead(&self) -> impl Iterator<Item = RefFun> + '_ {
+        self.reachable
+            .iter()
+            .enumerate()
+            .filter(|(_, &r)| !r)
+            .map(|(i, _)| RefFun(i))
+    }
+}
+
+/// Functions that can be called without a call instruction in the bytecode
+fn roots(code: &Bytecode) -> Vec<RefFun> {
+    let mut roots = vec![code.entrypoint];
+    for t in &code.types {
+        if let Some(obj) = t.get_type_obj() {
+            roots.extend(obj.protos.iter().map(|p| p.findex));
+            roots.extend(obj.bindings.values().copied());
+        }
+    }
+    // Constant fields typed as functions hold a findex
+    for c in code.constants.iter().flatten() {
+        let Some(obj) = code
+            .globals
+            .get(c.global.0)
+            .and_then(|&g| code[g].get_type_obj())
+        else {
+            continue;
+        };
+        for (field, &value) in obj.fields.iter().zip(&c.fields) {
+            if matches!(code[field.t], Type::Fun(_)) {
+                roots.push(RefFun(value));
+            }
+        }
+    }
+    roots
+}
+
+/// Functions and natives that can never execute
+pub fn dead_functions(code: &Bytecode) -> Vec<RefFun> {
+    Reachability::new(code).dead().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::dead::{dead_functions, Reachability};
+    use crate::testing::{bytecode, fun, sample};
+    use crate::types::{RefFun, Type};
+
+    #[test]
+    fn test_reachability() {
+        // fn@1 is only called by fn@2 which is never called
+        let code = bytecode(
+            &[""],
+            vec![Type::Void, fun(&[], 0)],
+            "
+            .fun fn@0 type@1
+            .reg type@0
+                Ret reg0
+            .end
+            .fun fn@1 type@1
+            .reg type@0
+                Ret reg0
+            .end
+            .fun fn@2 type@1
+            .reg type@0
+                Call0 reg0 fn@1
+                Ret reg0
+            .end",
+        );
+        let reach = Reachability::new(&code);
+        assert!(reach.is_reachable(RefFun(0)));
+        assert!(!reach.is_reachable(RefFun(1)));
+        assert_eq!(dead_functions(&code), vec![RefFun(1), RefFun(2)]);
+
+        // Methods and static functions can be called by reflection
+        assert!(dead_functions(&sample()).is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+//! Devirtualization of method and closure calls.
+//!
+//! A [Opcode::CallMethod] can land in the method of the receiver type or in any override from its subclasses. A
+//! [Opcode::CallClosure] calls whatever was last assigned to its register : a closure created in the function or a
+//! class field bound to a function.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::dataflow::def;
+use crate::types::{Function, RefFun, RefType, Reg};
+use crate::{Bytecode, Opcode};
+
+/// Where the function held by a closure register comes from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClosureSource {
+    /// Created in the function with StaticClosure or InstanceClosure
+    Closure(RefFun),
+    /// Loaded from a class field bound to a function
+    Binding(RefFun),
+}
+
+impl ClosureSource {
+    pub fn fun(self) -> RefFun {
+        match self {
+            ClosureSource::Closure(f) | ClosureSource::Binding(f) => f,
+        }
+    }
+}
+
+/// Function held by a register just before an instruction, if it can be known from the instruction assigning it
+pub fn closure_source(
+    code: &Bytecode,
+    f: &Function,
+    reg: Reg,
+    pos: usize,
+) -> Option<ClosureSource> {
+    let assign = f.ops[..pos.min(f.ops.len())]
+        .iter()
+        .rev()
+        .find(|op| def(op) == Some(reg))?;
+    let (obj, field) = match *assign {
+        Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+            return Some(ClosureSource::Closure(fun))
+        }
+        Opcode::Field { obj, field, .. } => (obj, field),
+        Opcode::GetThis { field, .. } => (Reg(0), field),
+        _ => return None,
+    };
+    code[f[obj]]
+        .get_type_obj()
+        .and_then(|o| o.bindings.get(&field).copied())
+        .map(ClosureSource::Binding)
+}
+
+/// Resolves the possible targets of calls
+pub struct Devirtualizer<'a> {
+    code: &'a Bytecode,
+    /// Direct subclasses of each class
+    subclasses: HashMap<RefType, Vec<RefType>>,
+}
+
+impl<'a> Devirtualizer<'a> {
+    pub fn new(code: &'a Bytecode) -> Self {
+        let mut subclasses: HashMap<RefType, Vec<RefType>> = HashMap::new();
+        for (i, t) in code.types.iter().enumerate() {
+            if let Some(super_) = t.get_type_obj().and_then(|o| o.super_) {
+                subclasses.entry(super_).or_default().push(RefType(i));
+            }
+        }
+        Self { code, subclasses }
+    }
+
+    /// Possible targets of a call instruction, the statically known target comes first.
+    /// Empty if the instruction isn't a call or its target is unknown.
+    pub fn targets(&self, f: &Function, pos: usize) -> Vec<RefFun> {
+        match f.ops[pos] {
+            Opcode::Call0 { fun, .. }
+            | Opcode::Call1 { fun, .. }
+            | Opcode::Call2 { fun, .. }
+            | Opcode::Call3 { fun, .. }
+            | Opcode::Call4 { fun, .. }
+            | Opcode::CallN { fun, .. } => vec![fun],
+            Opcode::CallMethod {
+                field, ref args, ..
+            } => args
+                .first()
+                .map(|&obj| self.method_targets(f[obj], field.0))
+                .unwrap_or_default(),
+            Opcode::CallThis { field, .. } if !f.regs.is_empty() => {
+                self.method_targets(f.regs[0], field.0)
+            }
+            Opcode::CallClosure { fun, .. } => closure_source(self.code, f, fun, pos)
+                .map(|s| vec![s.fun()])
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// A method of a class followed by its overrides in the subclasses
+    pub fn method_targets(&self, ty: RefType, method: usize) -> Vec<RefFun> {
+        let Some(proto) = self.code[ty]
+            .get_type_obj()
+            .and_then(|o| o.protos.get(method))
+        else {
+            return Vec::new();
+        };
+        let mut targets = vec![proto.findex];
+        let mut seen = HashSet::from([ty]);
+        let mut work: Vec<RefType> = self.subclasses.get(&ty).cloned().unwrap_or_default();
+        while let Some(sub) = work.pop() {
+            if !seen.insert(sub) {
+                continue;
+            }
+            if let Some(obj) = self.code[sub].get_type_obj() {
+                for p in obj.protos.iter().filter(|p| p.name == proto.name) {
+                    if !targets.contains(&p.findex) {
+                        targets.push(p.findex);
+                    }
+                }
+            }
+            work.extend(self.subclasses.get(&sub).into_iter().flatten());
+        }
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::devirt::{closure_source, ClosureSource, Devirtualizer};
+    use crate::testing::shapes;
+    use crate::types::{RefFun, RefType, Reg};
+
+    #[test]
+    fn test_devirtualize() {
+        let code = shapes();
+        let devirt = Devirtualizer::new(&code);
+        let main = &code.functions[0];
+        let targets: Vec<_> = (0..main.ops.len())
+            .map(|pos| devirt.targets(main, pos))
+            .collect();
+        let fns = |f: &[usize]| f.iter().map(|&f| RefFun(f)).collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                vec![],
+                fns(&[1, 2]),
+                vec![],
+                fns(&[1]),
+                vec![],
+                vec![],
+                fns(&[3]),
+                fns(&[3]),
+                vec![],
+            ]
+        );
+        assert_eq!(devirt.targets(&code.functions[1], 0), fns(&[1, 2]));
+        assert_eq!(devirt.method_targets(RefType(5), 0), fns(&[2]));
+        assert!(devirt.method_targets(RefType(4), 1).is_empty());
+
+        assert_eq!(
+            closure_source(&code, main, Reg(3), 3),
+            Some(ClosureSource::Closure(RefFun(1)))
+        );
+        assert_eq!(
+            closure_source(&code, main, Reg(3), 6),
+            Some(ClosureSource::Binding(RefFun(3)))
+        );
+        assert_eq!(closure_source(&code, main, Reg(3), 2), None);
+    }
+}
@@ -0,0 +1,420 @@
+//! Comparison of two bytecode files, e.g. two versions of a game.
+//!
+//! Elements indexes are not stable between two compilations, so elements are matched by their name. Functions that
+//! can't be matched by name (closures, renamed functions) are matched by their [Fingerprint], then by the similarity
+//! of their bodies.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::fingerprint::Fingerprint;
+use crate::fmt::EnhancedFmt;
+use crate::types::{Function, RefFun, RefGlobal, RefString, RefType, Type};
+use crate::{Bytecode, Opcode, Str};
+
+/// Minimum similarity for two functions to be matched by their body
+pub const SIMILARITY_THRESHOLD: f32 = 0.9;
+
+/// A difference between two bytecode files
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Change<T> {
+    /// Only present in the new bytecode
+    Added(T),
+    /// Only present in the old bytecode
+    Removed(T),
+    /// Present in both but different
+    Changed { old: T, new: T },
+}
+
+/// Differences between two bytecode files
+#[derive(Debug, Clone, Default)]
+pub struct BytecodeDiff {
+    /// Every function of the old bytecode matched with a function of the new bytecode, changed or not
+    pub matches: Vec<(RefFun, RefFun)>,
+    pub functions: Vec<Change<RefFun>>,
+    /// Named types (classes, enums, abstracts)
+    pub types: Vec<Change<RefType>>,
+    /// Strings are compared by value, they can't change
+    pub strings: Vec<Change<RefString>>,
+    /// Globals are matched by their type, they can't change
+    pub globals: Vec<Change<RefGlobal>>,
+}
+
+impl BytecodeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+            && self.types.is_empty()
+            && self.strings.is_empty()
+            && self.globals.is_empty()
+    }
+}
+
+/// Compare two bytecode files
+pub fn diff(old: &Bytecode, new: &Bytecode) -> BytecodeDiff {
+    let mut diff = BytecodeDiff::default();
+    diff_functions(old, new, &mut diff);
+    diff_types(old, new, &mut diff);
+    diff_strings(old, new, &mut diff);
+    diff_globals(old, new, &mut diff);
+    diff
+}
+
+/// Name of a function including its class, not unique (closures, overloads in different files)
+fn function_key(code: &Bytecode, f: &Function) -> String {
+    let name = &code[f.name];
+    match f.parent.and_then(|p| code[p].get_type_obj()) {
+        Some(obj) => format!("{}.{name}", code[obj.name]),
+        None => name.to_string(),
+    }
+}
+
+fn diff_functions(old: &Bytecode, new: &Bytecode, diff: &mut BytecodeDiff) {
+    let mut by_key = HashMap::<String, (Vec<&Function>, Vec<&Function>)>::new();
+    for f in &old.functions {
+        by_key.entry(function_key(old, f)).or_default().0.push(f);
+    }
+    for f in &new.functions {
+        by_key.entry(function_key(new, f)).or_default().1.push(f);
+    }
+
+    // Match by name when there is no ambiguity
+    let mut unmatched_old = Vec::new();
+    let mut unmatched_new = Vec::new();
+    for (_, (olds, news)) in by_key {
+        if olds.len() == news.len() {
+            diff.matches
+                .extend(olds.iter().zip(&news).map(|(o, n)| (o.findex, n.findex)));
+        } else {
+            unmatched_old.extend(olds);
+            unmatched_new.extend(news);
+        }
+    }
+
+    // Match by fingerprint when it is unique among the remaining functions
+    let mut by_fingerprint = HashMap::<Fingerprint, (Vec<&Function>, Vec<&Function>)>::new();
+    for f in unmatched_old.drain(..) {
+        by_fingerprint
+            .entry(f.fingerprint(old))
+            .or_default()
+            .0
+            .push(f);
+    }
+    for f in unmatched_new.drain(..) {
+        by_fingerprint
+            .entry(f.fingerprint(new))
+            .or_default()
+            .1
+            .push(f);
+    }
+    for (_, (olds, news)) in by_fingerprint {
+        if let ([o], [n]) = (olds.as_slice(), news.as_slice()) {
+            diff.matches.push((o.findex, n.findex));
+        } else {
+            unmatched_old.extend(olds);
+            unmatched_new.extend(news);
+        }
+    }
+    unmatched_old.sort_by_key(|f| f.findex);
+    unmatched_new.sort_by_key(|f| f.findex);
+
+    // Match the remaining functions by similarity, greedily. Only functions with the same signature and the same
+    // number of registers are compared.
+    let bucket = |code: &Bytecode, f: &Function| (type_name(code, f.t), f.regs.len());
+    let mut buckets = HashMap::<(String, usize), Vec<usize>>::new();
+    for (i, f) in unmatched_new.iter().enumerate() {
+        buckets.entry(bucket(new, f)).or_default().push(i);
+    }
+    let histograms_new: Vec<_> = unmatched_new.iter().map(|f| ops_histogram(f)).collect();
+    let mut taken = vec![false; unmatched_new.len()];
+    for fo in unmatched_old {
+        let ho = ops_histogram(fo);
+        let best = buckets
+            .get(&bucket(old, fo))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&i| !taken[i])
+            .map(|i| (i, similarity(&ho, fo, &histograms_new[i], unmatched_new[i])))
+            .filter(|&(_, s)| s >= SIMILARITY_THRESHOLD)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        match best {
+            Some((i, _)) => {
+                taken[i] = true;
+                diff.matches.push((fo.findex, unmatched_new[i].findex));
+            }
+            None => diff.functions.push(Change::Removed(fo.findex)),
+        }
+    }
+    diff.functions.extend(
+        unmatched_new
+            .iter()
+            .zip(taken)
+            .filter(|(_, taken)| !taken)
+            .map(|(f, _)| Change::Added(f.findex)),
+    );
+
+    diff.matches.sort_unstable();
+    for &(o, n) in &diff.matches {
+        if let (Some(fo), Some(fnew)) = (o.as_fn(old), n.as_fn(new)) {
+            if !same_body(old, fo, new, fnew) {
+                diff.functions.push(Change::Changed { old: o, new: n });
+            }
+        }
+    }
+    diff.functions.sort_unstable_by_key(change_key);
+}
+
+/// Number of instructions of each kind
+fn ops_histogram(f: &Function) -> HashMap<&'static str, usize> {
+    let mut histogram = HashMap::new();
+    for o in &f.ops {
+        *histogram.entry(o.name()).or_insert(0) += 1;
+    }
+    histogram
+}
+
+/// Similarity between 0 and 1 of two functions based on the kinds of their instructions
+fn similarity(
+    ha: &HashMap<&'static str, usize>,
+    a: &Function,
+    hb: &HashMap<&'static str, usize>,
+    b: &Function,
+) -> f32 {
+    let max = a.ops.len().max(b.ops.len());
+    if max == 0 || a.regs.len() != b.regs.len() {
+        return 0.0;
+    }
+    let common: usize = ha
+        .iter()
+        .map(|(name, &count)| count.min(hb.get(name).copied().unwrap_or(0)))
+        .sum();
+    common as f32 / max as f32
+}
+
+/// Compare function bodies, ignoring indexes that can change between compilations
+fn same_body(old: &Bytecode, fo: &Function, new: &Bytecode, fnew: &Function) -> bool {
+    fo.regs.len() == fnew.regs.len()
+        && fo
+            .regs
+            .iter()
+            .zip(&fnew.regs)
+            .all(|(a, b)| type_name(old, *a) == type_name(new, *b))
+        && fo.ops.len() == fnew.ops.len()
+        && fo
+            .ops
+            .iter()
+            .zip(&fnew.ops)
+            .all(|(a, b)| same_op(old, a, new, b))
+}
+
+fn same_op(old: &Bytecode, a: &Opcode, new: &Bytecode, b: &Opcode) -> bool {
+    if a.name() != b.name() || a.registers() != b.registers() {
+        return false;
+    }
+    match (a, b) {
+        (Opcode::Int { ptr: x, .. }, Opcode::Int { ptr: y, .. }) => old[*x] == new[*y],
+        (Opcode::Float { ptr: x, .. }, Opcode::Float { ptr: y, .. }) => {
+            old[*x].to_bits() == new[*y].to_bits()
+        }
+        (Opcode::String { ptr: x, .. }, Opcode::String { ptr: y, .. }) => old[*x] == new[*y],
+        (Opcode::Call0 { fun: x, .. }, Opcode::Call0 { fun: y, .. })
+        | (Opcode::Call1 { fun: x, .. }, Opcode::Call1 { fun: y, .. })
+        | (Opcode::Call2 { fun: x, .. }, Opcode::Call2 { fun: y, .. })
+        | (Opcode::Call3 { fun: x, .. }, Opcode::Call3 { fun: y, .. })
+        | (Opcode::Call4 { fun: x, .. }, Opcode::Call4 { fun: y, .. })
+        | (Opcode::CallN { fun: x, .. }, Opcode::CallN { fun: y, .. })
+        | (Opcode::StaticClosure { fun: x, .. }, Opcode::StaticClosure { fun: y, .. })
+        | (Opcode::InstanceClosure { fun: x, .. }, Opcode::InstanceClosure { fun: y, .. }) => {
+            x.name(old) == y.name(new)
+        }
+        _ => true,
+    }
+}
+
+fn type_name(code: &Bytecode, ty: RefType) -> String {
+    ty.display::<EnhancedFmt>(code).to_string()
+}
+
+/// Name of the named types
+fn named_type(code: &Bytecode, ty: &Type) -> Option<RefString> {
+    match ty {
+        Type::Obj(obj) | Type::Struct(obj) => Some(obj.name),
+        Type::Enum { name, .. } | Type::Abstract { name } => Some(*name),
+        _ => None,
+    }
+    .filter(|name| !code[*name].is_empty())
+}
+
+/// Description of the structure of a type, two types with the same shape are considered equal
+fn type_shape(code: &Bytecode, ty: &Type) -> Vec<String> {
+    match ty {
+        Type::Obj(obj) | Type::Struct(obj) => obj
+            .super_
+            .map(|s| type_name(code, s))
+            .into_iter()
+            .chain(
+                obj.own_fields
+                    .iter()
+                    .map(|f| format!("{}: {}", code[f.name], type_name(code, f.t))),
+            )
+            .chain(obj.protos.iter().map(|p| code[p.name].to_string()))
+            .collect(),
+        Type::Enum { constructs, .. } => constructs
+            .iter()
+            .map(|c| {
+                let params: Vec<_> = c.params.iter().map(|&p| type_name(code, p)).collect();
+                format!("{}({})", code[c.name], params.join(", "))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn diff_types(old: &Bytecode, new: &Bytecode, diff: &mut BytecodeDiff) {
+    let named = |code: &Bytecode| -> HashMap<Str, RefType> {
+        code.types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| named_type(code, t).map(|name| (code[name].clone(), RefType(i))))
+            .collect()
+    };
+    let olds = named(old);
+    let news = named(new);
+    for (name, &o) in &olds {
+        match news.get(name) {
+            Some(&n) => {
+                if type_shape(old, &old[o]) != type_shape(new, &new[n]) {
+                    diff.types.push(Change::Changed { old: o, new: n });
+                }
+            }
+            None => diff.types.push(Change::Removed(o)),
+        }
+    }
+    diff.types.extend(
+        news.iter()
+            .filter(|(name, _)| !olds.contains_key(*name))
+            .map(|(_, &n)| Change::Added(n)),
+    );
+    diff.types.sort_unstable_by_key(change_key);
+}
+
+fn diff_strings(old: &Bytecode, new: &Bytecode, diff: &mut BytecodeDiff) {
+    let olds: HashSet<&Str> = old.strings.iter().collect();
+    let news: HashSet<&Str> = new.strings.iter().collect();
+    diff.strings.extend(
+        old.strings
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !news.contains(s))
+            .map(|(i, _)| Change::Removed(RefString(i))),
+    );
+    diff.strings.extend(
+        new.strings
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !olds.contains(s))
+            .map(|(i, _)| Change::Added(RefString(i))),
+    );
+}
+
+fn diff_globals(old: &Bytecode, new: &Bytecode, diff: &mut BytecodeDiff) {
+    // Globals have no name, they're identified by their type and their rank among globals of the same type
+    let keyed = |code: &Bytecode| -> HashMap<(String, usize), RefGlobal> {
+        let mut ranks = HashMap::<String, usize>::new();
+        code.globals
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| {
+                let name = type_name(code, t);
+                let rank = ranks.entry(name.clone()).or_insert(0);
+                *rank += 1;
+                ((name, *rank), RefGlobal(i))
+            })
+            .collect()
+    };
+    let olds = keyed(old);
+    let news = keyed(new);
+    diff.globals.extend(
+        olds.iter()
+            .filter(|(key, _)| !news.contains_key(*key))
+            .map(|(_, &g)| Change::Removed(g)),
+    );
+    diff.globals.extend(
+        news.iter()
+            .filter(|(key, _)| !olds.contains_key(*key))
+            .map(|(_, &g)| Change::Added(g)),
+    );
+    diff.globals.sort_unstable_by_key(change_key);
+}
+
+/// Stable order for changes : removed, added then changed, by index
+fn change_key<T: Copy>(change: &Change<T>) -> (u8, T) {
+    match *change {
+        Change::Removed(t) => (0, t),
+        Change::Added(t) => (1, t),
+        Change::Changed { old, .. } => (2, old),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::diff::{diff, Change};
+    use crate::testing::{reload, sample};
+    use crate::types::{RefFun, RefGlobal, RefString, RefType};
+    use crate::{Opcode, Str};
+
+    #[test]
+    fn test_diff() {
+        let old = sample();
+        assert!(diff(&old, &sample()).is_empty());
+        assert_eq!(diff(&old, &sample()).matches.len(), 4);
+
+        let mut new = sample();
+        // unused is renamed, helper is renamed and changed, a function and a global are added
+        new.strings[5] = Str::from("helper2");
+        new.strings[6] = Str::from("unused2");
+        new.ints[1] = 2;
+        let mut added = new.functions[3].clone();
+        added.findex = RefFun(4);
+        added.ops.insert(0, Opcode::Nop);
+        new.functions.push(added);
+        new.globals.push(RefType(1));
+        let new = reload(&new);
+
+        let d = diff(&old, &new);
+        let same = |f| (RefFun(f), RefFun(f));
+        assert_eq!(d.matches, vec![same(0), same(1), same(2), same(3)]);
+        assert_eq!(
+            d.functions,
+            vec![
+                Change::Added(RefFun(4)),
+                // The call to helper now calls helper2
+                Change::Changed {
+                    old: RefFun(0),
+                    new: RefFun(0)
+                },
+                Change::Changed {
+                    old: RefFun(2),
+                    new: RefFun(2)
+                }
+            ]
+        );
+        assert_eq!(
+            d.strings,
+            vec![
+                Change::Removed(RefString(5)),
+                Change::Removed(RefString(6)),
+                Change::Added(RefString(5)),
+                Change::Added(RefString(6)),
+            ]
+        );
+        assert_eq!(d.globals, vec![Change::Added(RefGlobal(1))]);
+        // The fields of the static functions have been renamed
+        assert_eq!(
+            d.types,
+            vec![Change::Changed {
+                old: RefType(6),
+                new: RefType(6)
+            }]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Dominators and natural loops of a [Cfg].
+//!
+//! Dominators are computed with the iterative algorithm from Cooper, Harvey and Kennedy,
+//! "A Simple, Fast Dominance Algorithm".
+
+use crate::analysis::cfg::{BlockId, Cfg};
+
+/// Dominator tree of a control flow graph
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Dominators {
+    /// Immediate dominator of each block, None for the root and unreachable blocks
+    idom: Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    /// Compute the dominators, rooted at the entry block
+    pub fn new(cfg: &Cfg) -> Self {
+        let n = cfg.blocks.len();
+        if n == 0 {
+            return Self { idom: Vec::new() };
+        }
+        let succs: Vec<Vec<usize>> = cfg
+            .blocks
+            .iter()
+            .map(|b| b.succs.iter().map(|e| e.to).collect())
+            .collect();
+        let preds: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.preds.clone()).collect();
+        Self {
+            idom: compute(0, &succs, &preds),
+        }
+    }
+
+    /// Compute the post dominators, rooted at a virtual exit block following every block without successor.
+    /// The immediate post dominator is None for blocks only post dominated by the exit and blocks never exiting
+    /// (infinite loops).
+    pub fn post(cfg: &Cfg) -> Self {
+        let n = cfg.blocks.len();
+        // The reversed graph with the virtual exit at index n
+        let mut succs: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.preds.clone()).collect();
+        let mut preds: Vec<Vec<usize>> = cfg
+            .blocks
+            .iter()
+            .map(|b| b.succs.iter().map(|e| e.to).collect())
+            .collect();
+        let exits: Vec<usize> = (0..n).filter(|&b| cfg.blocks[b].succs.is_empty()).collect();
+        for &b in &exits {
+            preds[b].push(n);
+        }
+        succs.push(exits);
+        preds.push(Vec::new());
+        let mut idom = compute(n, &succs, &preds);
+        idom.pop();
+        for d in &mut idom {
+            if *d == Some(n) {
+                *d = None;
+            }
+        }
+        Self { idom }
+    }
+
+    /// Immediate dominator of a block
+    pub fn idom(&self, b: BlockId) -> Option<BlockId> {
+        self.idom.get(b).copied().flatten()
+    }
+
+    /// Iterate on the dominators of a block, from the block itself to the root
+    pub fn dominators(&self, b: BlockId) -> impl Iterator<Item = BlockId> + '_ {
+        std::iter::successors(Some(b), |&b| self.idom(b))
+    }
+
+    /// true if every path from the root to `b` goes through `a`
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        self.dominators(b).any(|d| d == a)
+    }
+}
+
+/// Get the immediate dominators of every node of a graph
+fn compute(root: usize, succs: &[Vec<usize>], preds: &[Vec<usize>]) -> Vec<Option<usize>> {
+    let n = succs.len();
+
+    // Postorder numbering, iterative to not overflow the stack on huge functions
+    let mut po = vec![usize::MAX; n];
+    let mut order = Vec::with_capacity(n);
+    let mut visited = vec![false; n];
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    while let Some((node, i)) = stack.pop() {
+        if let Some(&next) = succs[node].get(i) {
+            stack.push((node, i + 1));
+            if !visited[next] {
+                visited[next] = true;
+                stack.push((next, 0));
+            }
+        } else {
+            po[node] = order.len();
+            order.push(node);
+        }
+    }
+
+    let mut idom = vec![None; n];
+    idom[root] = Some(root);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // Reverse postorder
+        for &b in order.iter().rev().skip(1) {
+            let mut new: Option<BlockId> = None;
+            for &p in &preds[b] {
+                if idom[p].is_none() {
+                    continue;
+                }
+                new = Some(match new {
+                    None => p,
+                    Some(mut other) => {
+                        let mut p = p;
+                        while p != other {
+                            while po[p] < po[other] {
+                                p = idom[p].unwrap();
+                            }
+                            while po[other] < po[p] {
+                                other = idom[other].unwrap();
+                            }
+                        }
+                        p
+                    }
+                });
+            }
+            if new.is_some() && idom[b] != new {
+                idom[b] = new;
+                changed = true;
+            }
+        }
+    }
+    idom[root] = None;
+    idom
+}
+
+/// A natural loop
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Loop {
+    /// Entry of the loop, dominates every block of the loop
+    pub header: BlockId,
+    /// Blocks jumping back to the header
+    pub latches: Vec<BlockId>,
+    /// Every block in the loop, header included, in ascending order
+    pub body: Vec<BlockId>,
+    /// Blocks outside the loop reached from inside the loop, in ascending order
+    pub exits: Vec<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, b: BlockId) -> bool {
+        self.body.binary_search(&b).is_ok()
+    }
+}
+
+/// Find the natural loops of a control flow graph.
+/// Loops sharing the same header are merged, the result is ordered by header.
+pub fn find_loops(cfg: &Cfg, dom: &Dominators) -> Vec<Loop> {
+    let mut loops: Vec<Loop> = Vec::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for e in &block.succs {
+            let header = e.to;
+            // Back edge
+            if !dom.dominates(header, b) {
+                continue;
+            }
+            let mut body = vec![header];
+            let mut stack = vec![b];
+            while let Some(n) = stack.pop() {
+                if !body.contains(&n) {
+                    body.push(n);
+                    stack.extend_from_slice(&cfg.blocks[n].preds);
+                }
+            }
+            match loops.iter_mut().find(|l| l.header == header) {
+                Some(l) => {
+                    l.latches.push(b);
+                    l.body.extend(body);
+                }
+                None => loops.push(Loop {
+                    header,
+                    latches: vec![b],
+                    body,
+                    exits: Vec::new(),
+                }),
+            }
+        }
+    }
+
+    for l in &mut loops {
+        l.latches.sort_unstable();
+        l.latches.dedup();
+        l.body.sort_unstable();
+        l.body.dedup();
+        let mut exits: Vec<BlockId> = l
+            .body
+            .iter()
+            .flat_map(|&b| cfg.blocks[b].succs.iter().map(|e| e.to))
+            .filter(|to| l.body.binary_search(to).is_err())
+            .collect();
+        exits.sort_unstable();
+        exits.dedup();
+        l.exits = exits;
+    }
+    loops.sort_unstable_by_key(|l| l.header);
+    loops
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::Cfg;
+    use crate::analysis::dom::{find_loops, Dominators, Loop};
+    use crate::types::Function;
+
+    #[test]
+    fn test_dominators() -> crate::Result<()> {
+        // while (r1) { if (r1) r0++; }
+        let f = Function::from_asm(
+            "
+            .fun fn@0 type@0
+            .reg type@0
+            .reg type@0
+                Int reg0 int@0
+            loop:
+                Label
+                JFalse reg1 end
+                JFalse reg1 skip
+                Incr reg0
+            skip:
+                JAlways loop
+            end:
+                Ret reg0
+            .end",
+        )?;
+        let cfg = Cfg::new(&f);
+        assert_eq!(cfg.blocks.len(), 6);
+        let dom = Dominators::new(&cfg);
+        let idoms: Vec<_> = (0..6).map(|b| dom.idom(b)).collect();
+        assert_eq!(
+            idoms,
+            vec![None, Some(0), Some(1), Some(2), Some(2), Some(1)]
+        );
+        assert!(dom.dominates(1, 4));
+        assert!(!dom.dominates(3, 4));
+        assert_eq!(dom.dominators(3).collect::<Vec<_>>(), vec![3, 2, 1, 0]);
+
+        let post = Dominators::post(&cfg);
+        let ipdoms: Vec<_> = (0..6).map(|b| post.idom(b)).collect();
+        assert_eq!(
+            ipdoms,
+            vec![Some(1), Some(5), Some(4), Some(4), Some(1), None]
+        );
+
+        assert_eq!(
+            find_loops(&cfg, &dom),
+            vec![Loop {
+                header: 1,
+                latches: vec![4],
+                body: vec![1, 2, 3, 4],
+                exits: vec![5],
+            }]
+        );
+        Ok(())
+    }
+}
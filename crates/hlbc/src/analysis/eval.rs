@@ -0,0 +1,621 @@
+//! Partial evaluation of functions with known inputs.
+//!
+//! The [Evaluator] executes the instructions of a function with concrete values for its arguments and the globals
+//! it knows. Anything that depends on the runtime (objects, arrays, natives) is [Value::Unknown], evaluation stops
+//! when the control flow depends on an unknown value. This is enough to compute decrypted strings or switch keys
+//! built from constants. A few methods of the std `String` class are evaluated directly.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::analysis::dataflow::def;
+use crate::analysis::devirt::Devirtualizer;
+use crate::types::{ConstantValue, FunPtr, Function, JumpOffset, RefFun, RefGlobal, Reg};
+use crate::{Bytecode, Opcode, Resolve};
+
+/// A value known at evaluation time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i32),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// A closure without bound value
+    Fun(RefFun),
+    /// Depends on the runtime
+    Unknown,
+}
+
+impl Value {
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Value::Unknown)
+    }
+
+    /// Like `Std.string`, None if the value is unknown
+    pub fn to_haxe_string(&self) -> Option<String> {
+        Some(match self {
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) if f.is_nan() => "NaN".to_string(),
+            Value::Float(f) if f.is_infinite() => {
+                (if *f > 0.0 { "Infinity" } else { "-Infinity" }).to_string()
+            }
+            Value::Float(f) => f.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Bytes(_) | Value::Fun(_) | Value::Unknown => return None,
+        })
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => Display::fmt(b, f),
+            Value::Int(i) => Display::fmt(i, f),
+            Value::Float(fl) => Display::fmt(fl, f),
+            Value::String(s) => fmt::Debug::fmt(s, f),
+            Value::Bytes(b) => write!(f, "bytes{b:02x?}"),
+            Value::Fun(fun) => write!(f, "fn{fun}"),
+            Value::Unknown => f.write_str("<unknown>"),
+        }
+    }
+}
+
+/// Why the evaluation couldn't complete
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum Stop {
+    #[error("Branch on an unknown value in fn@{} at {pos}", fun.0)]
+    UnknownBranch { fun: RefFun, pos: usize },
+    #[error("Exception thrown in fn@{} at {pos}", fun.0)]
+    Throw { fun: RefFun, pos: usize },
+    #[error("Instruction limit reached")]
+    Limit,
+    #[error("The function returned before reaching the instruction")]
+    NotReached,
+}
+
+/// Executes functions with known values
+pub struct Evaluator<'a> {
+    code: &'a Bytecode,
+    devirt: Devirtualizer<'a>,
+    globals: HashMap<RefGlobal, Value>,
+    /// Maximum number of instructions executed by a single evaluation
+    pub max_steps: usize,
+    /// Maximum depth of nested calls, deeper calls return [Value::Unknown]
+    pub max_depth: usize,
+    steps: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Globals holding constant strings are known from the start
+    pub fn new(code: &'a Bytecode) -> Self {
+        let mut globals = HashMap::new();
+        for c in code.constants.iter().flatten() {
+            if !matches!(c.obj(code), Some(obj) if &*code.get(obj.name) == "String") {
+                continue;
+            }
+            if let Some(ConstantValue::String(s)) = c.values(code).first() {
+                globals.insert(c.global, Value::String(s.to_string()));
+            }
+        }
+        Self {
+            code,
+            devirt: Devirtualizer::new(code),
+            globals,
+            max_steps: 100_000,
+            max_depth: 16,
+            steps: 0,
+        }
+    }
+
+    /// Set the value of a global before evaluating
+    pub fn set_global(&mut self, global: RefGlobal, value: Value) {
+        self.globals.insert(global, value);
+    }
+
+    /// Value of a global, after an evaluation it includes the values assigned by the evaluated code
+    pub fn global(&self, global: RefGlobal) -> &Value {
+        self.globals.get(&global).unwrap_or(&Value::Unknown)
+    }
+
+    /// Evaluate a function with some arguments, missing arguments are unknown
+    pub fn eval(&mut self, fun: RefFun, args: &[Value]) -> Result<Value, Stop> {
+        self.steps = 0;
+        match self.code.get(fun) {
+            FunPtr::Fun(f) => self.run(f, args.to_vec(), 0, None),
+            FunPtr::Native(_) => Ok(self.intrinsic(fun, args).unwrap_or(Value::Unknown)),
+        }
+    }
+
+    /// Value of a register the first time the instruction at `pos` is about to execute
+    pub fn eval_reg(
+        &mut self,
+        fun: RefFun,
+        args: &[Value],
+        pos: usize,
+        reg: Reg,
+    ) -> Result<Value, Stop> {
+        self.steps = 0;
+        match self.code.get(fun) {
+            FunPtr::Fun(f) => self.run(f, args.to_vec(), 0, Some((pos, reg))),
+            FunPtr::Native(_) => Err(Stop::NotReached),
+        }
+    }
+
+    fn run(
+        &mut self,
+        f: &Function,
+        args: Vec<Value>,
+        depth: usize,
+        until: Option<(usize, Reg)>,
+    ) -> Result<Value, Stop> {
+        let mut regs = args;
+        regs.resize(f.regs.len(), Value::Unknown);
+        let mut pos = 0;
+        while let Some(op) = f.ops.get(pos) {
+            if let Some((at, reg)) = until {
+                if at == pos {
+                    return Ok(regs.get(reg.0 as usize).cloned().unwrap_or(Value::Unknown));
+                }
+            }
+            self.steps += 1;
+            if self.steps > self.max_steps {
+                return Err(Stop::Limit);
+            }
+            let get = |r: Reg| regs.get(r.0 as usize).cloned().unwrap_or(Value::Unknown);
+            let branch = Stop::UnknownBranch { fun: f.findex, pos };
+            let cond = match *op {
+                Opcode::JTrue { cond, offset } | Opcode::JFalse { cond, offset } => {
+                    match get(cond) {
+                        Value::Bool(b) => Some((b == matches!(op, Opcode::JTrue { .. }), offset)),
+                        _ => return Err(branch),
+                    }
+                }
+                Opcode::JNull { reg, offset } | Opcode::JNotNull { reg, offset } => {
+                    match get(reg) {
+                        Value::Unknown => return Err(branch),
+                        v => Some((
+                            (v == Value::Null) == matches!(op, Opcode::JNull { .. }),
+                            offset,
+                        )),
+                    }
+                }
+                Opcode::JSLt { a, b, offset }
+                | Opcode::JSGte { a, b, offset }
+                | Opcode::JSGt { a, b, offset }
+                | Opcode::JSLte { a, b, offset }
+                | Opcode::JULt { a, b, offset }
+                | Opcode::JUGte { a, b, offset }
+                | Opcode::JNotLt { a, b, offset }
+                | Opcode::JNotGte { a, b, offset }
+                | Opcode::JEq { a, b, offset }
+                | Opcode::JNotEq { a, b, offset } => match compare(op, &get(a), &get(b)) {
+                    Some(taken) => Some((taken, offset)),
+                    None => return Err(branch),
+                },
+                Opcode::JAlways { offset } => Some((true, offset)),
+                _ => None,
+            };
+            if let Some((taken, offset)) = cond {
+                pos = if taken { target(pos, offset) } else { pos + 1 };
+                continue;
+            }
+            let value = match *op {
+                Opcode::Ret { ret } => return Ok(get(ret)),
+                Opcode::Throw { .. } | Opcode::Rethrow { .. } => {
+                    return Err(Stop::Throw { fun: f.findex, pos })
+                }
+                Opcode::NullCheck { reg } => match get(reg) {
+                    Value::Null => return Err(Stop::Throw { fun: f.findex, pos }),
+                    _ => None,
+                },
+                Opcode::Switch {
+                    reg, ref offsets, ..
+                } => match get(reg) {
+                    Value::Int(i) => {
+                        pos = match usize::try_from(i).ok().and_then(|i| offsets.get(i)) {
+                            Some(&offset) => target(pos, offset),
+                            None => pos + 1,
+                        };
+                        continue;
+                    }
+                    _ => return Err(branch),
+                },
+                Opcode::SetGlobal { global, src } => {
+                    self.globals.insert(global, get(src));
+                    None
+                }
+                Opcode::GetGlobal { global, .. } => Some(self.global(global).clone()),
+                Opcode::Mov { src, .. }
+                | Opcode::ToDyn { src, .. }
+                | Opcode::SafeCast { src, .. }
+                | Opcode::UnsafeCast { src, .. }
+                | Opcode::ToVirtual { src, .. } => Some(get(src)),
+                Opcode::Int { ptr, .. } => Some(Value::Int(self.code[ptr])),
+                Opcode::Float { ptr, .. } => Some(Value::Float(self.code[ptr])),
+                Opcode::Bool { value, .. } => Some(Value::Bool(value)),
+                Opcode::String { ptr, .. } => Some(Value::String(self.code[ptr].to_string())),
+                Opcode::Bytes { ptr, .. } => Some(Value::Bytes(self.code.get(ptr).to_vec())),
+                Opcode::Null { .. } => Some(Value::Null),
+                Opcode::StaticClosure { fun, .. } => Some(Value::Fun(fun)),
+                Opcode::Field { obj, field, .. } => {
+                    let name = f[obj]
+                        .field(field, self.code)
+                        .map(|field| self.code.get(field.name));
+                    match (get(obj), name.as_deref()) {
+                        (Value::String(s), Some("length")) => {
+                            Some(Value::Int(s.encode_utf16().count() as i32))
+                        }
+                        (Value::String(s), Some("bytes")) => Some(Value::String(s)),
+                        _ => Some(Value::Unknown),
+                    }
+                }
+                _ => match arith(op, &get) {
+                    Some(value) => Some(value),
+                    None => Some(
+                        self.exec_call(f, pos, &get, depth)?
+                            .unwrap_or(Value::Unknown),
+                    ),
+                },
+            };
+            if let (Some(value), Some(dst)) = (value, def(op)) {
+                if let Some(r) = regs.get_mut(dst.0 as usize) {
+                    *r = value;
+                }
+            }
+            pos += 1;
+        }
+        Ok(Value::Unknown)
+    }
+
+    /// Result of a call instruction, None if the instruction isn't a call
+    fn exec_call(
+        &mut self,
+        f: &Function,
+        pos: usize,
+        get: &dyn Fn(Reg) -> Value,
+        depth: usize,
+    ) -> Result<Option<Value>, Stop> {
+        let (fun, args): (Option<RefFun>, Vec<Reg>) = match f.ops[pos] {
+            Opcode::Call0 { fun, .. } => (Some(fun), vec![]),
+            Opcode::Call1 { fun, arg0, .. } => (Some(fun), vec![arg0]),
+            Opcode::Call2 {
+                fun, arg0, arg1, ..
+            } => (Some(fun), vec![arg0, arg1]),
+            Opcode::Call3 {
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                ..
+            } => (Some(fun), vec![arg0, arg1, arg2]),
+            Opcode::Call4 {
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+                ..
+            } => (Some(fun), vec![arg0, arg1, arg2, arg3]),
+            Opcode::CallN { fun, ref args, .. } => (Some(fun), args.to_vec()),
+            Opcode::CallMethod { ref args, .. } => (self.single_target(f, pos), args.to_vec()),
+            Opcode::CallThis { ref args, .. } => {
+                let args = std::iter::once(Reg(0)).chain(args.iter().copied());
+                (self.single_target(f, pos), args.collect())
+            }
+            Opcode::CallClosure { fun, ref args, .. } => match get(fun) {
+                Value::Fun(fun) => (Some(fun), args.to_vec()),
+                _ => (None, Vec::new()),
+            },
+            _ => return Ok(None),
+        };
+        let Some(fun) = fun else {
+            return Ok(Some(Value::Unknown));
+        };
+        let args: Vec<Value> = args.into_iter().map(get).collect();
+        Ok(Some(self.call(fun, args, depth + 1)?))
+    }
+
+    fn single_target(&self, f: &Function, pos: usize) -> Option<RefFun> {
+        match self.devirt.targets(f, pos).as_slice() {
+            &[target] => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Evaluate a nested call. Calls that can't be evaluated return an unknown value and forget the globals they assign.
+    fn call(&mut self, fun: RefFun, args: Vec<Value>, depth: usize) -> Result<Value, Stop> {
+        if let Some(value) = self.intrinsic(fun, &args) {
+            return Ok(value);
+        }
+        let Some(f) = fun.as_fn(self.code) else {
+            return Ok(Value::Unknown);
+        };
+        let result = if depth <= self.max_depth {
+            self.run(f, args, depth, None)
+        } else {
+            Ok(Value::Unknown)
+        };
+        match result {
+            Ok(value) if value.is_known() => Ok(value),
+            Ok(_) | Err(Stop::UnknownBranch { .. }) => {
+                for op in &f.ops {
+                    if let Opcode::SetGlobal { global, .. } = op {
+                        self.globals.insert(*global, Value::Unknown);
+                    }
+                }
+                Ok(Value::Unknown)
+            }
+            Err(stop) => Err(stop),
+        }
+    }
+
+    /// Std functions evaluated directly, identified by their class and name
+    fn intrinsic(&self, fun: RefFun, args: &[Value]) -> Option<Value> {
+        let f = fun.as_fn(self.code)?;
+        let parent = self.code.get(f.parent?.as_obj(self.code)?.name);
+        // Static methods are members of the static class ($Name)
+        let parent = parent.strip_prefix('$').unwrap_or(&parent);
+        let name = self.code.get(f.name);
+        let int = |i: usize| match args.get(i) {
+            Some(Value::Int(v)) => Some(Some(*v)),
+            Some(Value::Null) | None => Some(None),
+            _ => None,
+        };
+        let string = |s: String| Some(Value::String(s));
+        match (parent, &*name, args.first()) {
+            ("String", "__add__", _) => {
+                let a = args.first()?.to_haxe_string()?;
+                string(a + &args.get(1)?.to_haxe_string()?)
+            }
+            ("Std", "string", Some(v)) => string(v.to_haxe_string()?),
+            ("String", "fromCharCode", Some(&Value::Int(c))) => string(
+                char::from_u32(c as u32)
+                    .map(String::from)
+                    .unwrap_or_default(),
+            ),
+            ("String", _, Some(Value::String(s))) => {
+                let units: Vec<u16> = s.encode_utf16().collect();
+                let len = units.len() as i32;
+                let slice = |start: i32, end: i32| {
+                    String::from_utf16_lossy(&units[start as usize..end as usize])
+                };
+                match &*name {
+                    "charCodeAt" => Some(match int(1)? {
+                        Some(i) if (0..len).contains(&i) => Value::Int(units[i as usize] as i32),
+                        _ => Value::Null,
+                    }),
+                    "charAt" => match int(1)? {
+                        Some(i) if (0..len).contains(&i) => string(slice(i, i + 1)),
+                        _ => string(String::new()),
+                    },
+                    "substr" => {
+                        let (start, count) = substr(len, int(1)?.unwrap_or(0), int(2)?);
+                        string(slice(start, start + count))
+                    }
+                    "substring" => {
+                        let clamp = |i: i32| i.clamp(0, len);
+                        let start = clamp(int(1)?.unwrap_or(0));
+                        let end = clamp(int(2)?.unwrap_or(len));
+                        string(slice(start.min(end), start.max(end)))
+                    }
+                    "toUpperCase" => string(s.to_uppercase()),
+                    "toLowerCase" => string(s.to_lowercase()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Position of a jump target
+fn target(pos: usize, offset: JumpOffset) -> usize {
+    (pos as i64 + offset as i64 + 1) as usize
+}
+
+/// Start and length of `String.substr`, following the Hashlink implementation
+fn substr(slen: i32, mut pos: i32, len: Option<i32>) -> (i32, i32) {
+    let mut len = len.unwrap_or(slen);
+    if len == 0 || (pos != 0 && len < 0) {
+        return (0, 0);
+    }
+    if pos < 0 {
+        pos = (slen + pos).max(0);
+    } else if len < 0 {
+        len = slen + len - pos;
+        if len < 0 {
+            return (0, 0);
+        }
+    }
+    if (pos + len) as u32 > slen as u32 {
+        len = slen - pos;
+    }
+    if pos < 0 || len <= 0 {
+        (0, 0)
+    } else {
+        (pos, len)
+    }
+}
+
+/// Whether a conditional jump is taken, None if it depends on unknown values
+fn compare(op: &Opcode, a: &Value, b: &Value) -> Option<bool> {
+    use std::cmp::Ordering;
+    let ord = match (a, b) {
+        (Value::Int(a), Value::Int(b)) => match op {
+            Opcode::JULt { .. } | Opcode::JUGte { .. } => Some((*a as u32).cmp(&(*b as u32))),
+            _ => Some(a.cmp(b)),
+        },
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::Null, Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Fun(_))
+        | (Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Fun(_), Value::Null) => {
+            return match op {
+                Opcode::JEq { .. } => Some(false),
+                Opcode::JNotEq { .. } => Some(true),
+                _ => None,
+            }
+        }
+        (Value::Fun(a), Value::Fun(b)) if a == b => Some(Ordering::Equal),
+        _ => return None,
+    };
+    Some(match op {
+        Opcode::JSLt { .. } | Opcode::JULt { .. } => ord == Some(Ordering::Less),
+        Opcode::JSGte { .. } | Opcode::JUGte { .. } => {
+            matches!(ord, Some(Ordering::Greater | Ordering::Equal))
+        }
+        Opcode::JSGt { .. } => ord == Some(Ordering::Greater),
+        Opcode::JSLte { .. } => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+        Opcode::JNotLt { .. } => ord != Some(Ordering::Less),
+        Opcode::JNotGte { .. } => !matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+        Opcode::JEq { .. } => ord == Some(Ordering::Equal),
+        Opcode::JNotEq { .. } => ord != Some(Ordering::Equal),
+        _ => return None,
+    })
+}
+
+/// Result of an arithmetic or conversion instruction, None for other instructions
+fn arith(op: &Opcode, get: &dyn Fn(Reg) -> Value) -> Option<Value> {
+    let binop =
+        |a: Reg, b: Reg, int: fn(i32, i32) -> Option<i32>, float: Option<fn(f64, f64) -> f64>| {
+            match (get(a), get(b)) {
+                (Value::Int(a), Value::Int(b)) => int(a, b).map_or(Value::Unknown, Value::Int),
+                (Value::Float(a), Value::Float(b)) => {
+                    float.map_or(Value::Unknown, |f| Value::Float(f(a, b)))
+                }
+                _ => Value::Unknown,
+            }
+        };
+    let unop = |src: Reg, f: fn(Value) -> Option<Value>| f(get(src)).unwrap_or(Value::Unknown);
+    Some(match *op {
+        Opcode::Add { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_add(b)), Some(|a, b| a + b)),
+        Opcode::Sub { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_sub(b)), Some(|a, b| a - b)),
+        Opcode::Mul { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_mul(b)), Some(|a, b| a * b)),
+        Opcode::SDiv { a, b, .. } => binop(a, b, i32::checked_div, Some(|a, b| a / b)),
+        Opcode::SMod { a, b, .. } => binop(a, b, i32::checked_rem, Some(|a, b| a % b)),
+        Opcode::UDiv { a, b, .. } => binop(
+            a,
+            b,
+            |a, b| (a as u32).checked_div(b as u32).map(|v| v as i32),
+            None,
+        ),
+        Opcode::UMod { a, b, .. } => binop(
+            a,
+            b,
+            |a, b| (a as u32).checked_rem(b as u32).map(|v| v as i32),
+            None,
+        ),
+        Opcode::Shl { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_shl(b as u32)), None),
+        Opcode::SShr { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_shr(b as u32)), None),
+        Opcode::UShr { a, b, .. } => binop(
+            a,
+            b,
+            |a, b| Some((a as u32).wrapping_shr(b as u32) as i32),
+            None,
+        ),
+        Opcode::And { a, b, .. } => binop(a, b, |a, b| Some(a & b), None),
+        Opcode::Or { a, b, .. } => binop(a, b, |a, b| Some(a | b), None),
+        Opcode::Xor { a, b, .. } => binop(a, b, |a, b| Some(a ^ b), None),
+        Opcode::Neg { src, .. } => unop(src, |v| match v {
+            Value::Int(i) => Some(Value::Int(i.wrapping_neg())),
+            Value::Float(f) => Some(Value::Float(-f)),
+            _ => None,
+        }),
+        Opcode::Not { src, .. } => unop(src, |v| match v {
+            Value::Bool(b) => Some(Value::Bool(!b)),
+            _ => None,
+        }),
+        Opcode::Incr { dst } => unop(dst, |v| match v {
+            Value::Int(i) => Some(Value::Int(i.wrapping_add(1))),
+            _ => None,
+        }),
+        Opcode::Decr { dst } => unop(dst, |v| match v {
+            Value::Int(i) => Some(Value::Int(i.wrapping_sub(1))),
+            _ => None,
+        }),
+        Opcode::ToSFloat { src, .. } => unop(src, |v| match v {
+            Value::Int(i) => Some(Value::Float(i as f64)),
+            Value::Float(f) => Some(Value::Float(f)),
+            _ => None,
+        }),
+        Opcode::ToUFloat { src, .. } => unop(src, |v| match v {
+            Value::Int(i) => Some(Value::Float(i as u32 as f64)),
+            _ => None,
+        }),
+        Opcode::ToInt { src, .. } => unop(src, |v| match v {
+            Value::Float(f) => Some(Value::Int(f as i32)),
+            Value::Int(i) => Some(Value::Int(i)),
+            _ => None,
+        }),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::eval::{substr, Evaluator, Stop, Value};
+    use crate::testing::{bytecode, fun};
+    use crate::types::{RefFun, RefGlobal, RefType, Reg, Type};
+
+    #[test]
+    fn test_eval() {
+        // fn@0 sums the integers up to its argument, fn@1 stores the sum in a global
+        let mut code = bytecode(
+            &[""],
+            vec![Type::Void, Type::I32, fun(&[1], 1)],
+            "
+            .fun fn@0 type@2
+            .reg type@1
+            .reg type@1
+            .reg type@1
+                Int reg1 int@0
+                Int reg2 int@0
+            loop:
+                Label
+                JSGte reg1 reg0 end
+                Incr reg1
+                Add reg2 reg2 reg1
+                JAlways loop
+            end:
+                Ret reg2
+            .end
+            .fun fn@1 type@2
+            .reg type@1
+            .reg type@1
+                Call1 reg1 fn@0 reg0
+                SetGlobal global@0 reg1
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![0];
+        code.globals = vec![RefType(1)];
+        let mut eval = Evaluator::new(&code);
+        assert_eq!(eval.eval(RefFun(0), &[Value::Int(4)]), Ok(Value::Int(10)));
+        assert_eq!(
+            eval.eval_reg(RefFun(0), &[Value::Int(4)], 5, Reg(1)),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            eval.eval(RefFun(0), &[]),
+            Err(Stop::UnknownBranch {
+                fun: RefFun(0),
+                pos: 3
+            })
+        );
+        assert_eq!(eval.global(RefGlobal(0)), &Value::Unknown);
+        assert_eq!(eval.eval(RefFun(1), &[Value::Int(3)]), Ok(Value::Int(6)));
+        assert_eq!(eval.global(RefGlobal(0)), &Value::Int(6));
+        eval.max_steps = 5;
+        assert_eq!(eval.eval(RefFun(0), &[Value::Int(4)]), Err(Stop::Limit));
+
+        assert_eq!(substr(5, 1, Some(3)), (1, 3));
+        assert_eq!(substr(5, -2, None), (3, 2));
+        assert_eq!(substr(5, 0, Some(-1)), (0, 4));
+    }
+}
@@ -1,19 +1,23 @@
-use std::collections::HashMap;
-use std::ops::Range;
+//! Mapping between the source files and lines and the functions and instructions, from the debug info.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Range, RangeInclusive};
 
 use crate::types::{Function, RefFun};
 use crate::{Bytecode, Str};
 
 /// Finds the function which are present in a given file.
-/// Only looks at the first instruction to determine the file.
+/// Only looks at the first instruction to determine the file, functions without instructions are skipped.
 ///
 /// Panics if no debug info is present.
 pub fn functions_in_files(code: &Bytecode) -> HashMap<Str, Vec<RefFun>> {
     let df = code.debug_files.as_ref().unwrap();
     let mut funs = vec![Vec::new(); df.len()];
     for f in &code.functions {
-        let file = f.debug_info.as_ref().unwrap()[0].0;
-        funs[file].push(f.findex);
+        let first = f.debug_info.as_ref().unwrap().first();
+        if let Some(funs) = first.and_then(|&(file, _)| funs.get_mut(file)) {
+            funs.push(f.findex);
+        }
     }
     df.iter().cloned().zip(funs).collect()
 }
@@ -25,13 +29,15 @@ pub fn functions_in_files(code: &Bytecode) -> HashMap<Str, Vec<RefFun>> {
 /// Panics if no debug info.
 pub fn files_in_function(code: &Bytecode, f: &Function) -> HashMap<Str, Vec<Range<usize>>> {
     let dbg = f.debug_info.as_ref().unwrap();
-    let mut start = 0;
-    let mut curr_file = dbg[0].0;
     let mut ranges = HashMap::<_, Vec<Range<usize>>>::new();
+    let Some(&(mut curr_file, _)) = dbg.first() else {
+        return ranges;
+    };
+    let mut start = 0;
     for (i, &(file, _)) in dbg.iter().enumerate() {
         if file != curr_file {
             ranges
-                .entry(code.debug_file(file).unwrap())
+                .entry(code.debug_file(curr_file).unwrap_or_default())
                 .or_default()
                 .push(start..i);
             start = i;
@@ -39,12 +45,97 @@ pub fn files_in_function(code: &Bytecode, f: &Function) -> HashMap<Str, Vec<Rang
         }
     }
     ranges
+        .entry(code.debug_file(curr_file).unwrap_or_default())
+        .or_default()
+        .push(start..dbg.len());
+    ranges
+}
+
+/// Lines of each file a function has instructions in, including inlined code. Sorted by file index.
+///
+/// Empty if no debug info.
+pub fn line_ranges(f: &Function) -> Vec<(usize, RangeInclusive<usize>)> {
+    let mut ranges = BTreeMap::<usize, RangeInclusive<usize>>::new();
+    for &(file, line) in f.debug_info.iter().flatten() {
+        ranges
+            .entry(file)
+            .and_modify(|r| *r = (*r.start()).min(line)..=(*r.end()).max(line))
+            .or_insert(line..=line);
+    }
+    ranges.into_iter().collect()
+}
+
+/// Bidirectional mapping between the debug files and lines and the functions and instructions.
+///
+/// Every instruction is used, so functions containing inlined code appear in multiple files.
+#[derive(Debug, Clone, Default)]
+pub struct FileMap {
+    /// Functions with instructions in each file, indexed by debug file
+    functions: Vec<Vec<RefFun>>,
+    /// Instructions (function, position) at each line of each file
+    lines: Vec<BTreeMap<usize, Vec<(RefFun, usize)>>>,
+}
+
+impl FileMap {
+    /// Empty if there is no debug info
+    pub fn new(code: &Bytecode) -> Self {
+        let nfiles = code.debug_files.as_ref().map_or(0, |df| df.len());
+        let mut map = Self {
+            functions: vec![Vec::new(); nfiles],
+            lines: vec![BTreeMap::new(); nfiles],
+        };
+        for f in &code.functions {
+            for (pos, &(file, line)) in f.debug_info.iter().flatten().enumerate() {
+                let (Some(funs), Some(lines)) =
+                    (map.functions.get_mut(file), map.lines.get_mut(file))
+                else {
+                    continue;
+                };
+                if funs.last() != Some(&f.findex) {
+                    funs.push(f.findex);
+                }
+                lines.entry(line).or_default().push((f.findex, pos));
+            }
+        }
+        for funs in &mut map.functions {
+            funs.sort_unstable();
+            funs.dedup();
+        }
+        map
+    }
+
+    /// Functions with instructions in a file, in findex order
+    pub fn functions_in(&self, file: usize) -> &[RefFun] {
+        self.functions.get(file).map_or(&[], Vec::as_slice)
+    }
+
+    /// Instructions (function, position) generated from a line
+    pub fn ops_at(&self, file: usize, line: usize) -> &[(RefFun, usize)] {
+        self.lines
+            .get(file)
+            .and_then(|lines| lines.get(&line))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Lines of a file that generated instructions, in ascending order
+    pub fn lines_in(&self, file: usize) -> impl Iterator<Item = usize> + '_ {
+        self.lines
+            .get(file)
+            .into_iter()
+            .flat_map(|l| l.keys().copied())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::analysis::files::{files_in_function, functions_in_files};
-    use crate::Bytecode;
+    use std::collections::HashMap;
+
+    use crate::analysis::files::{files_in_function, functions_in_files, line_ranges, FileMap};
+    use crate::fmt::asm::parse_functions;
+    use crate::fmt::EnhancedFmt;
+    use crate::testing::{fun, reload};
+    use crate::types::{RefField, RefFun, RefType, Type};
+    use crate::{Bytecode, Str};
 
     #[test]
     fn test_files() {
@@ -59,4 +150,99 @@ mod tests {
         let files = files_in_function(&code, code.entrypoint());
         dbg!(files);
     }
+
+    #[test]
+    fn test_empty_function() {
+        let mut code = located();
+        let mut f = code.functions[1].clone();
+        f.ops.clear();
+        f.debug_info = Some(Vec::new());
+        assert_eq!(f.file_index(), None);
+        assert!(!f.is_from_std(&code));
+        assert!(files_in_function(&code, &f).is_empty());
+        assert!(f
+            .display::<EnhancedFmt>(&code)
+            .to_string()
+            .contains("0 ops"));
+        assert!(RefType(0).field(RefField(usize::MAX), &code).is_none());
+        code.functions.push(f);
+        let files = functions_in_files(&code);
+        assert_eq!(files[&Str::from("Util.hx")], vec![RefFun(1)]);
+    }
+
+    #[test]
+    // A single range is expected for Util.hx
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_located() {
+        let code = located();
+        assert_eq!(
+            functions_in_files(&code),
+            HashMap::from([
+                (Str::from("Main.hx"), vec![RefFun(0)]),
+                (Str::from("Util.hx"), vec![RefFun(1)])
+            ])
+        );
+        assert_eq!(
+            files_in_function(&code, &code.functions[0]),
+            HashMap::from([
+                (Str::from("Main.hx"), vec![0..1, 2..3]),
+                (Str::from("Util.hx"), vec![1..2])
+            ])
+        );
+        assert_eq!(
+            line_ranges(&code.functions[0]),
+            vec![(0, 3..=4), (1, 10..=10)]
+        );
+    }
+
+    #[test]
+    fn test_file_map() {
+        let code = located();
+        let map = FileMap::new(&code);
+        assert_eq!(map.functions_in(0), &[RefFun(0)]);
+        assert_eq!(map.functions_in(1), &[RefFun(0), RefFun(1)]);
+        assert!(map.functions_in(2).is_empty());
+        assert_eq!(map.ops_at(0, 3), &[(RefFun(0), 0)]);
+        assert_eq!(map.ops_at(1, 10), &[(RefFun(0), 1)]);
+        assert_eq!(map.ops_at(1, 12), &[(RefFun(1), 0), (RefFun(1), 1)]);
+        assert!(map.ops_at(0, 10).is_empty());
+        assert_eq!(map.lines_in(0).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(map.lines_in(1).collect::<Vec<_>>(), vec![10, 12]);
+    }
+
+    /// Main.hx:3-4 with a line inlined from Util.hx:10, and a function of Util.hx
+    fn located() -> Bytecode {
+        reload(&Bytecode {
+            strings: vec![Str::from("")],
+            bytes: Some((Vec::new(), Vec::new())),
+            ints: vec![0],
+            debug_files: Some(vec![Str::from("Main.hx"), Str::from("Util.hx")]),
+            types: vec![Type::I32, fun(&[], 0)],
+            functions: parse_functions(
+                "
+            .fun fn@0 type@1
+            .reg type@0
+            .debug
+            .assigns
+            .loc 0 3
+                Int reg0 int@0
+            .loc 1 10
+                Incr reg0
+            .loc 0 4
+                Ret reg0
+            .end
+            .fun fn@1 type@1
+            .reg type@0
+            .debug
+            .assigns
+            .loc 1 12
+                Int reg0 int@0
+                Ret reg0
+            .end",
+            )
+            .unwrap(),
+            constants: Some(Vec::new()),
+            ..Default::default()
+        })
+    }
 }
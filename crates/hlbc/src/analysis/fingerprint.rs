@@ -0,0 +1,237 @@
+//! Fingerprints of function bodies, stable across compilations.
+//!
+//! A fingerprint hashes the kinds of the instructions, the registers, the jumps and the constant values. Indexes of
+//! strings, types, functions, globals and fields change when the program is recompiled, they are replaced by what
+//! stays the same : the value of the string, the kind of the type, the arity of the function. Two versions of a game
+//! built from the same source for a function give the same fingerprint even if everything else moved.
+//!
+//! The hash is FNV-1a, it doesn't depend on the platform or the Rust version so fingerprints can be stored.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::opcodes::Operand;
+use crate::types::{FunPtr, Function, RefFun, RefType, Type};
+use crate::{Bytecode, Resolve};
+
+/// Normalized hash of a function body, see the [module documentation](self)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Fingerprint(pub u64);
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Length prefixed so consecutive strings can't collide
+    fn str(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.bytes(s.as_bytes());
+    }
+}
+
+/// Kind of a type, the tag used in the binary format
+fn type_kind(code: &Bytecode, t: RefType) -> u64 {
+    match code.types.get(t.0) {
+        None => u64::MAX,
+        Some(ty) => match ty {
+            Type::Void => 0,
+            Type::UI8 => 1,
+            Type::UI16 => 2,
+            Type::I32 => 3,
+            Type::I64 => 4,
+            Type::F32 => 5,
+            Type::F64 => 6,
+            Type::Bool => 7,
+            Type::Bytes => 8,
+            Type::Dyn => 9,
+            Type::Fun(_) => 10,
+            Type::Obj(_) => 11,
+            Type::Array => 12,
+            Type::Type => 13,
+            Type::Ref(_) => 14,
+            Type::Virtual { .. } => 15,
+            Type::DynObj => 16,
+            Type::Abstract { .. } => 17,
+            Type::Enum { .. } => 18,
+            Type::Null(_) => 19,
+            Type::Method(_) => 20,
+            Type::Struct(_) => 21,
+            Type::Packed(_) => 22,
+        },
+    }
+}
+
+fn fun_arity(code: &Bytecode, f: RefFun) -> u64 {
+    if f.0 >= code.findex_max() {
+        return u64::MAX;
+    }
+    let t = match code.get(f) {
+        FunPtr::Fun(fun) => fun.t,
+        FunPtr::Native(n) => n.t,
+    };
+    code.types
+        .get(t.0)
+        .and_then(Type::get_type_fun)
+        .map_or(0, |fun| fun.args.len() as u64)
+}
+
+impl Function {
+    /// Normalized hash of the body, see [Fingerprint]
+    pub fn fingerprint(&self, code: &Bytecode) -> Fingerprint {
+        let mut h = Fnv::new();
+        h.u64(self.regs.len() as u64);
+        for &r in &self.regs {
+            h.u64(type_kind(code, r));
+        }
+        h.u64(self.ops.len() as u64);
+        for op in &self.ops {
+            h.str(op.name());
+            for (_, operand) in op.operands() {
+                match operand {
+                    Operand::Reg(r) => h.u64(r.0 as u64),
+                    Operand::Regs(regs) => {
+                        h.u64(regs.len() as u64);
+                        for r in regs {
+                            h.u64(r.0 as u64);
+                        }
+                    }
+                    Operand::InlineBool(b) => h.u64(b as u64),
+                    Operand::InlineInt(i) => h.u64(i as u64),
+                    Operand::JumpOffset(o) => h.u64(o as u64),
+                    Operand::JumpOffsets(offsets) => {
+                        h.u64(offsets.len() as u64);
+                        for &o in offsets {
+                            h.u64(o as u64);
+                        }
+                    }
+                    Operand::Int(i) => h.u64(code.ints.get(i.0).map_or(0, |&v| v as u64)),
+                    Operand::Float(f) => h.u64(code.floats.get(f.0).map_or(0, |v| v.to_bits())),
+                    Operand::String(s) => h.str(code.strings.get(s.0).map_or("", |s| &**s)),
+                    Operand::Bytes(b) => h.u64(code.bytes_len(b).unwrap_or(0) as u64),
+                    Operand::Type(t) => h.u64(type_kind(code, t)),
+                    Operand::Fun(f) => h.u64(fun_arity(code, f)),
+                    // Depend on the layout of a type or on the globals order
+                    Operand::Field(_) | Operand::Global(_) | Operand::EnumConstruct(_) => h.u64(0),
+                }
+            }
+        }
+        Fingerprint(h.0)
+    }
+}
+
+/// Functions of a bytecode by fingerprint
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintIndex {
+    pub fingerprints: Vec<Fingerprint>,
+    functions: HashMap<Fingerprint, Vec<RefFun>>,
+}
+
+impl FingerprintIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut index = Self::default();
+        for f in &code.functions {
+            let fp = f.fingerprint(code);
+            index.fingerprints.push(fp);
+            index.functions.entry(fp).or_default().push(f.findex);
+        }
+        index
+    }
+
+    /// Functions with this fingerprint
+    pub fn get(&self, fp: Fingerprint) -> &[RefFun] {
+        self.functions.get(&fp).map_or(&[], Vec::as_slice)
+    }
+
+    /// The only function with this fingerprint
+    pub fn unique(&self, fp: Fingerprint) -> Option<RefFun> {
+        match self.get(fp) {
+            &[f] => Some(f),
+            _ => None,
+        }
+    }
+}
+
+/// Functions of two bytecode files with the same fingerprint, when the fingerprint is unique in both
+pub fn match_fingerprints(old: &Bytecode, new: &Bytecode) -> Vec<(RefFun, RefFun)> {
+    let old_index = FingerprintIndex::new(old);
+    let new_index = FingerprintIndex::new(new);
+    let mut matches: Vec<_> = old_index
+        .functions
+        .iter()
+        .filter_map(|(&fp, funs)| match funs.as_slice() {
+            &[o] => new_index.unique(fp).map(|n| (o, n)),
+            _ => None,
+        })
+        .collect();
+    matches.sort_unstable();
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::fingerprint::{match_fingerprints, FingerprintIndex};
+    use crate::testing::{sample, shapes};
+    use crate::types::{RefFun, RefString};
+    use crate::{Opcode, Str};
+
+    #[test]
+    fn test_fingerprint() {
+        let code = sample();
+        let mut moved = sample();
+        // Move "hello" in the strings pool, the index changes but not the value
+        moved.strings.push(Str::from("hello"));
+        for op in &mut moved.functions[0].ops {
+            if let Opcode::String { ptr, .. } = op {
+                *ptr = RefString(9);
+            }
+        }
+        // Change the constant in helper
+        moved.ints[1] = 2;
+
+        let a = FingerprintIndex::new(&code);
+        let b = FingerprintIndex::new(&moved);
+        assert_eq!(a.fingerprints[0], b.fingerprints[0]);
+        assert_eq!(a.fingerprints[1], b.fingerprints[1]);
+        assert_ne!(a.fingerprints[2], b.fingerprints[2]);
+        assert_eq!(a.fingerprints[0].to_string().len(), 16);
+        assert_eq!(a.get(a.fingerprints[2]), &[RefFun(2)]);
+        assert_eq!(
+            match_fingerprints(&code, &moved),
+            vec![
+                (RefFun(0), RefFun(0)),
+                (RefFun(1), RefFun(1)),
+                (RefFun(3), RefFun(3))
+            ]
+        );
+
+        // Square.area and Main.f have the same body, they can't be told apart
+        let code = shapes();
+        let index = FingerprintIndex::new(&code);
+        let fp = index.fingerprints[2];
+        assert_eq!(index.fingerprints[3], fp);
+        assert_eq!(index.get(fp), &[RefFun(2), RefFun(3)]);
+        assert_eq!(index.unique(fp), None);
+        assert_eq!(index.unique(index.fingerprints[0]), Some(RefFun(0)));
+    }
+}
@@ -0,0 +1,254 @@
+//! Detection of the instantiations of generic classes.
+//!
+//! Classes marked `@:generic` are specialized by the Haxe compiler for each set of type parameters, they appear as
+//! distinct classes with mangled names (`Container_Int`, `Container_String`) sharing the same structure.
+
+use std::collections::HashMap;
+
+use crate::fmt::EnhancedFmt;
+use crate::rename::Symbol;
+use crate::types::{RefType, Type, TypeObj};
+use crate::{Bytecode, Result, Str};
+
+/// Instantiations of the same generic class
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GenericGroup {
+    /// Name of the generic class without the mangled type parameters
+    pub base: Str,
+    /// Each instantiated class with its deduced type parameters
+    pub instances: Vec<(RefType, Vec<RefType>)>,
+}
+
+impl GenericGroup {
+    /// Suggested names for the instantiations like `Container<Int>`.
+    /// Type parameters that couldn't be deduced from the fields fall back to the mangled suffix.
+    pub fn suggested_names(&self, code: &Bytecode) -> Vec<(RefType, String)> {
+        self.instances
+            .iter()
+            .map(|(ty, params)| {
+                let params = if params.is_empty() {
+                    code[*ty]
+                        .get_type_obj()
+                        .map(|obj| {
+                            code[obj.name]
+                                .strip_prefix(&*self.base)
+                                .unwrap_or_default()
+                                .trim_start_matches('_')
+                                .replace('_', ", ")
+                        })
+                        .unwrap_or_default()
+                } else {
+                    params
+                        .iter()
+                        .map(|&p| haxe_type_name(code, p))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                (*ty, format!("{}<{params}>", self.base))
+            })
+            .collect()
+    }
+
+    /// Rename the instantiations with the given names, usually the suggested ones after confirmation by the user.
+    pub fn apply(&self, code: &Bytecode, names: &[(RefType, String)]) -> Result<()> {
+        for (ty, name) in names {
+            if let Some(obj) = code[*ty].get_type_obj() {
+                code.renames.set(Symbol::Type(obj.name), name.as_str())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the classes which are instantiations of the same generic class.
+///
+/// Classes are grouped when they have the same parent, the same fields and methods names and a name with a common
+/// prefix ending with `_`. Type parameters are the fields types that differ between instantiations.
+pub fn find_generic_instances(code: &Bytecode) -> Vec<GenericGroup> {
+    let mut groups = HashMap::<(Str, Vec<Str>), Vec<(RefType, &TypeObj)>>::new();
+    for (i, t) in code.types.iter().enumerate() {
+        if let Type::Obj(obj) = t {
+            let name = &code[obj.name];
+            let short = name.rsplit('.').next().unwrap_or(name);
+            let prefix = match short.find('_') {
+                // Compiler generated types start with $
+                Some(pos) if pos > 0 && !name.starts_with('$') => {
+                    &name[..name.len() - short.len() + pos]
+                }
+                _ => continue,
+            };
+            let shape = obj
+                .super_
+                .map(|s| Str::from(format!("{}", s.0)))
+                .into_iter()
+                .chain(obj.own_fields.iter().map(|f| code[f.name].clone()))
+                .chain(obj.protos.iter().map(|p| code[p.name].clone()))
+                .collect();
+            groups
+                .entry((Str::from(prefix), shape))
+                .or_default()
+                .push((RefType(i), obj));
+        }
+    }
+
+    let mut groups: Vec<GenericGroup> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let base = common_base(code, &members);
+            // Fields whose type differs between instantiations
+            let nfields = members[0].1.own_fields.len();
+            let params: Vec<usize> = (0..nfields)
+                .filter(|&f| {
+                    members
+                        .iter()
+                        .any(|(_, obj)| obj.own_fields[f].t != members[0].1.own_fields[f].t)
+                })
+                .collect();
+            let mut instances: Vec<(RefType, Vec<RefType>)> = members
+                .iter()
+                .map(|(ty, obj)| {
+                    let mut args: Vec<RefType> = Vec::new();
+                    for &f in &params {
+                        let t = obj.own_fields[f].t;
+                        if !args.contains(&t) {
+                            args.push(t);
+                        }
+                    }
+                    (*ty, args)
+                })
+                .collect();
+            instances.sort_unstable();
+            GenericGroup { base, instances }
+        })
+        .collect();
+    groups.sort_unstable_by(|a, b| a.base.cmp(&b.base));
+    groups
+}
+
+/// Longest common prefix of the names of the instantiations, without the trailing `_`
+fn common_base(code: &Bytecode, members: &[(RefType, &TypeObj)]) -> Str {
+    let first = &code[members[0].1.name];
+    let mut len = first.len();
+    for (_, obj) in &members[1..] {
+        len = first
+            .bytes()
+            .zip(code[obj.name].bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    let prefix = &first[..len];
+    Str::from(prefix.rfind('_').map_or(prefix, |pos| &prefix[..pos]))
+}
+
+/// Demangle the name of a generic class instantiation : `Container_Int_haxe_ds_StringMap` is
+/// `Container<Int, haxe.ds.StringMap>`.
+///
+/// The type parameters are the parts after the first `_` of the class name. A parameter ends with a part starting with
+/// an uppercase letter, the lowercase parts before it are its package. None if the name doesn't look mangled.
+pub fn demangle(name: &str) -> Option<String> {
+    let start = name.rfind('.').map_or(0, |i| i + 1);
+    let (base, rest) = name[start..].split_once('_')?;
+    if !base.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut params = Vec::new();
+    let mut package = Vec::new();
+    for part in rest.split('_') {
+        if part.is_empty() {
+            return None;
+        }
+        package.push(part);
+        if part.starts_with(|c: char| c.is_ascii_uppercase()) {
+            params.push(package.join("."));
+            package.clear();
+        }
+    }
+    if !package.is_empty() {
+        return None;
+    }
+    Some(format!("{}{base}<{}>", &name[..start], params.join(", ")))
+}
+
+/// Haxe name of a type parameter
+fn haxe_type_name(code: &Bytecode, ty: RefType) -> String {
+    match &code[ty] {
+        Type::I32 => "Int".to_owned(),
+        Type::F64 => "Float".to_owned(),
+        Type::Bool => "Bool".to_owned(),
+        Type::Dyn => "Dynamic".to_owned(),
+        Type::Null(inner) => format!("Null<{}>", haxe_type_name(code, *inner)),
+        Type::Obj(obj) => obj.name(code).to_string(),
+        Type::Enum { name, .. } | Type::Abstract { name } => code[*name].to_string(),
+        _ => ty.display::<EnhancedFmt>(code).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::generics::{demangle, find_generic_instances};
+    use crate::testing::{bytecode, obj};
+    use crate::types::{RefType, Type};
+
+    #[test]
+    fn test_generic_instances() {
+        // Container<Int> and Container<Float> with a value field, and an unrelated class
+        let code = bytecode(
+            &[
+                "",
+                "pack.Container_Int",
+                "pack.Container_Float",
+                "value",
+                "get",
+                "Other_Int",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::F64,
+                Type::Obj(obj(1, None, &[(3, 1)], &[])),
+                Type::Obj(obj(2, None, &[(3, 2)], &[])),
+                Type::Obj(obj(5, None, &[(4, 1)], &[])),
+            ],
+            "",
+        );
+        let groups = find_generic_instances(&code);
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0];
+        assert_eq!(&*group.base, "pack.Container");
+        assert_eq!(
+            group.instances,
+            vec![
+                (RefType(3), vec![RefType(1)]),
+                (RefType(4), vec![RefType(2)])
+            ]
+        );
+        let names = group.suggested_names(&code);
+        assert_eq!(
+            names,
+            vec![
+                (RefType(3), "pack.Container<Int>".to_owned()),
+                (RefType(4), "pack.Container<Float>".to_owned())
+            ]
+        );
+        group.apply(&code, &names).unwrap();
+        assert_eq!(
+            &*code[RefType(4)].get_type_obj().unwrap().name(&code),
+            "pack.Container<Float>"
+        );
+    }
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(demangle("Container_Int").as_deref(), Some("Container<Int>"));
+        assert_eq!(
+            demangle("pack.Pair_Int_haxe_ds_StringMap").as_deref(),
+            Some("pack.Pair<Int, haxe.ds.StringMap>")
+        );
+        assert_eq!(demangle("Container"), None);
+        assert_eq!(demangle("$Container_Int"), None);
+        assert_eq!(demangle("Container_lower"), None);
+        assert_eq!(demangle("Container__Int"), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! What each global holds once the program is initialized.
+//!
+//! Globals of classes and enums hold their static part, they are known from the type definitions. Other globals are
+//! initialized by the constants pool or assigned by the init code : the entrypoint and the functions it calls before
+//! `main`.
+
+use std::collections::HashSet;
+
+use crate::fmt::EnhancedFmt;
+use crate::types::{Function, RefFun, RefGlobal, RefType};
+use crate::{Bytecode, Opcode, Resolve, Str, Type};
+
+/// How a global is initialized
+#[derive(Debug, Clone)]
+pub enum GlobalInit {
+    /// Static part of a class or enum
+    Class(RefType),
+    /// Initialized with the constant at this index in the constants pool
+    Constant(usize),
+    /// Assigned by a `SetGlobal` in the init code
+    Assigned {
+        fun: RefFun,
+        /// Position of the `SetGlobal`
+        pos: usize,
+        /// Instruction producing the value, None if not found
+        value: Option<Opcode>,
+    },
+}
+
+/// Initialization of every global
+#[derive(Debug, Clone, Default)]
+pub struct GlobalInits {
+    /// Indexed by global
+    pub inits: Vec<Option<GlobalInit>>,
+    /// Globals assigned by the init code, in execution order. Those of classes and constants aren't included.
+    pub order: Vec<RefGlobal>,
+}
+
+impl GlobalInits {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut inits = vec![None; code.globals.len()];
+        for (i, t) in code.types.iter().enumerate() {
+            if let Some(g) = type_global(t) {
+                if let Some(init @ None) = inits.get_mut(g.0) {
+                    *init = Some(GlobalInit::Class(RefType(i)));
+                }
+            }
+        }
+        for (&g, &c) in &code.globals_initializers {
+            if let Some(init @ None) = inits.get_mut(g.0) {
+                *init = Some(GlobalInit::Constant(c));
+            }
+        }
+
+        let mut order = Vec::new();
+        let main = code.function_by_name("main").map(|f| f.findex);
+        let mut seen = HashSet::new();
+        // Depth first in call order, which is the execution order for the straight init code
+        let mut stack = vec![code.entrypoint];
+        while let Some(findex) = stack.pop() {
+            if Some(findex) == main || !seen.insert(findex) {
+                continue;
+            }
+            let Some(f) = findex.as_fn(code) else {
+                continue;
+            };
+            let mut callees = Vec::new();
+            for (pos, op) in f.ops.iter().enumerate() {
+                match *op {
+                    Opcode::SetGlobal { global, src } => {
+                        if let Some(init @ None) = inits.get_mut(global.0) {
+                            let value = f.ops[..pos]
+                                .iter()
+                                .rev()
+                                .find(|op| op.defs().contains(&src))
+                                .cloned();
+                            *init = Some(GlobalInit::Assigned {
+                                fun: findex,
+                                pos,
+                                value,
+                            });
+                            order.push(global);
+                        }
+                    }
+                    Opcode::Call0 { fun, .. }
+                    | Opcode::Call1 { fun, .. }
+                    | Opcode::Call2 { fun, .. }
+                    | Opcode::Call3 { fun, .. }
+                    | Opcode::Call4 { fun, .. }
+                    | Opcode::CallN { fun, .. } => callees.push(fun),
+                    _ => {}
+                }
+            }
+            stack.extend(callees.into_iter().rev());
+        }
+        Self { inits, order }
+    }
+
+    pub fn get(&self, global: RefGlobal) -> Option<&GlobalInit> {
+        self.inits.get(global.0)?.as_ref()
+    }
+
+    /// Short description of the value of a global, e.g. the name of a class
+    pub fn describe(&self, code: &Bytecode, global: RefGlobal) -> Option<Str> {
+        match self.get(global)? {
+            GlobalInit::Class(t) => Some(Str::from(t.display::<EnhancedFmt>(code).to_string())),
+            GlobalInit::Constant(c) => Some(Str::from(format!("constant@{c}"))),
+            GlobalInit::Assigned { value, .. } => match value.as_ref()? {
+                Opcode::String { ptr, .. } => Some(Str::from(format!("{:?}", code.get(*ptr)))),
+                Opcode::New { dst } => {
+                    let f = self.assigned_fun(code, global)?;
+                    Some(Str::from(format!(
+                        "new {}",
+                        f.regtype(*dst).display::<EnhancedFmt>(code)
+                    )))
+                }
+                Opcode::StaticClosure { fun, .. } => Some(fun.name(code)),
+                _ => None,
+            },
+        }
+    }
+
+    fn assigned_fun<'a>(&self, code: &'a Bytecode, global: RefGlobal) -> Option<&'a Function> {
+        match self.get(global)? {
+            GlobalInit::Assigned { fun, .. } => fun.as_fn(code),
+            _ => None,
+        }
+    }
+}
+
+/// Global holding the static part of a class or enum
+fn type_global(t: &Type) -> Option<RefGlobal> {
+    let global = match t {
+        Type::Obj(obj) | Type::Struct(obj) => obj.global,
+        Type::Enum { global, .. } => *global,
+        _ => return None,
+    };
+    // 0 means no global, the index is shifted by one
+    global.0.checked_sub(1).map(RefGlobal)
+}
+
+impl Bytecode {
+    /// Class or enum whose static part is stored in a global
+    pub fn global_class(&self, global: RefGlobal) -> Option<RefType> {
+        self.types
+            .iter()
+            .position(|t| type_global(t) == Some(global))
+            .map(RefType)
+    }
+
+    /// Find how every global is initialized, see [GlobalInits]
+    pub fn global_inits(&self) -> GlobalInits {
+        GlobalInits::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::globals::GlobalInit;
+    use crate::testing::{bind, bytecode, fun, obj, reload};
+    use crate::types::{ConstantDef, RefFun, RefGlobal, RefType, Type};
+    use crate::Opcode;
+
+    #[test]
+    fn test_global_inits() {
+        // The entrypoint calls an init function then main
+        let mut point = obj(2, None, &[], &[]);
+        point.global = RefGlobal(1);
+        let mut code = bytecode(
+            &["", "main", "Point", "$Main", "hello"],
+            vec![
+                Type::Void,
+                Type::I32,
+                fun(&[], 0),
+                Type::Obj(point),
+                Type::Bytes,
+                Type::Obj(bind(obj(3, None, &[(1, 2)], &[]), 0, 2)),
+            ],
+            "
+            .fun fn@0 type@2
+            .reg type@0
+                Call0 reg0 fn@1
+                Call0 reg0 fn@2
+                Ret reg0
+            .end
+            .fun fn@1 type@2
+            .reg type@4
+            .reg type@3
+            .reg type@0
+                String reg0 string@4
+                SetGlobal global@1 reg0
+                New reg1
+                SetGlobal global@2 reg1
+                Ret reg2
+            .end
+            .fun fn@2 type@2
+            .reg type@1
+            .reg type@0
+                Int reg0 int@0
+                SetGlobal global@3 reg0
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![0];
+        code.globals = vec![RefType(3), RefType(4), RefType(3), RefType(1), RefType(1)];
+        code.constants = Some(vec![ConstantDef {
+            global: RefGlobal(4),
+            fields: Vec::new(),
+        }]);
+        let code = reload(&code);
+
+        let inits = code.global_inits();
+        assert_eq!(code.global_class(RefGlobal(0)), Some(RefType(3)));
+        assert!(matches!(
+            inits.get(RefGlobal(0)),
+            Some(GlobalInit::Class(RefType(3)))
+        ));
+        assert!(matches!(
+            inits.get(RefGlobal(1)),
+            Some(GlobalInit::Assigned {
+                fun: RefFun(1),
+                pos: 1,
+                value: Some(Opcode::String { .. })
+            })
+        ));
+        assert!(matches!(
+            inits.get(RefGlobal(2)),
+            Some(GlobalInit::Assigned {
+                fun: RefFun(1),
+                pos: 3,
+                value: Some(Opcode::New { .. })
+            })
+        ));
+        // Assigned in main, after the initialization
+        assert!(inits.get(RefGlobal(3)).is_none());
+        assert!(matches!(
+            inits.get(RefGlobal(4)),
+            Some(GlobalInit::Constant(0))
+        ));
+        assert_eq!(inits.order, vec![RefGlobal(1), RefGlobal(2)]);
+
+        let describe = |g| inits.describe(&code, RefGlobal(g)).map(|s| s.to_string());
+        assert_eq!(describe(0).as_deref(), Some("Point"));
+        assert_eq!(describe(1).as_deref(), Some("\"hello\""));
+        assert_eq!(describe(2).as_deref(), Some("new Point"));
+        assert_eq!(describe(3), None);
+        assert_eq!(describe(4).as_deref(), Some("constant@0"));
+    }
+}
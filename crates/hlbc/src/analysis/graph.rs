@@ -1,24 +1,49 @@
 //! Utilities to generate a callgraph and generate dot graphs
+//!
+//! The callgraph is a [petgraph] graph, any algorithm from petgraph can be used on it :
+//! ```no_run
+//! # use hlbc::Bytecode;
+//! use hlbc::analysis::graph::{full_call_graph, petgraph};
+//!
+//! let code = Bytecode::from_file("hlboot.dat")?;
+//! let g = full_call_graph(&code);
+//! // Groups of mutually recursive functions
+//! let sccs = petgraph::algo::tarjan_scc(&g);
+//! # Ok::<(), hlbc::Error>(())
+//! ```
 
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
 pub use petgraph;
+use petgraph::graph::DiGraph;
 use petgraph::graphmap::DiGraphMap;
 use petgraph::visit::{EdgeRef, IntoEdgeReferences, IntoNodeReferences, NodeIndexable, NodeRef};
 
+use crate::analysis::calls::{CallIndex, CallKind};
+use crate::analysis::cfg::{Cfg, EdgeKind};
 use crate::types::{FunPtr, Function, RefFun};
 use crate::{Bytecode, Opcode, Resolve, Type};
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Call {
-    // Called with Call0, Call1, ...
+    /// Called with Call0, Call1, ...
     Direct,
-    // Called a closure
+    /// Called a closure
     Closure,
 }
 
-pub type Callgraph = DiGraphMap<RefFun, Call>;
+/// A call instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CallSite {
+    pub kind: Call,
+    /// Position of the call instruction in the caller
+    pub pos: usize,
+}
+
+/// Nodes are functions, edges are all the call sites from a function to another
+pub type Callgraph = DiGraphMap<RefFun, Vec<CallSite>>;
 // Function argument number to function ptr
 type RegCtx = HashMap<usize, RefFun>;
 
@@ -26,7 +51,7 @@ pub fn find_calls<'a>(
     code: &'a Bytecode,
     f: &'a Function,
     reg_ctx: &'a RegCtx,
-) -> impl Iterator<Item = (Call, RefFun, RegCtx)> + 'a {
+) -> impl Iterator<Item = (CallSite, RefFun, RegCtx)> + 'a {
     macro_rules! build_ctx {
         ($i:ident; $args:expr) => {{
             let mut tmp = RegCtx::new();
@@ -44,43 +69,46 @@ pub fn find_calls<'a>(
         }};
     }
 
-    f.ops.iter().enumerate().filter_map(move |(i, o)| match o {
-        Opcode::Call0 { fun, .. } => Some((Call::Direct, *fun, RegCtx::new())),
-        Opcode::Call1 { fun, arg0, .. } => Some((Call::Direct, *fun, build_ctx!(i; [arg0]))),
-        Opcode::Call2 {
-            fun, arg0, arg1, ..
-        } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1]))),
-        Opcode::Call3 {
-            fun,
-            arg0,
-            arg1,
-            arg2,
-            ..
-        } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1, arg2]))),
-        Opcode::Call4 {
-            fun,
-            arg0,
-            arg1,
-            arg2,
-            arg3,
-            ..
-        } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1, arg2, arg3]))),
-        Opcode::CallN { fun, args, .. } => Some((Call::Direct, *fun, build_ctx!(i; args))),
-        Opcode::CallClosure { fun, args, .. } => f
-            .find_last_closure_assign(code, *fun, i)
-            .map(|f| (Call::Closure, f, build_ctx!(i; args)))
-            .or_else(|| {
-                reg_ctx
-                    .get(&(fun.0 as usize))
-                    .map(|param| (Call::Closure, *param, build_ctx!(i; args)))
-            }),
-        Opcode::CallMethod { field, args, .. } => code[f[args[0]]]
-            .get_type_obj()
-            .map(|o| (Call::Direct, o.protos[field.0].findex, build_ctx!(i; args))),
-        Opcode::CallThis { field, args, .. } => code[f.regs[0]]
-            .get_type_obj()
-            .map(|o| (Call::Direct, o.protos[field.0].findex, build_ctx!(i; args))),
-        _ => None,
+    f.ops.iter().enumerate().filter_map(move |(i, o)| {
+        match o {
+            Opcode::Call0 { fun, .. } => Some((Call::Direct, *fun, RegCtx::new())),
+            Opcode::Call1 { fun, arg0, .. } => Some((Call::Direct, *fun, build_ctx!(i; [arg0]))),
+            Opcode::Call2 {
+                fun, arg0, arg1, ..
+            } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1]))),
+            Opcode::Call3 {
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                ..
+            } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1, arg2]))),
+            Opcode::Call4 {
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+                ..
+            } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1, arg2, arg3]))),
+            Opcode::CallN { fun, args, .. } => Some((Call::Direct, *fun, build_ctx!(i; args))),
+            Opcode::CallClosure { fun, args, .. } => f
+                .find_last_closure_assign(code, *fun, i)
+                .map(|f| (Call::Closure, f, build_ctx!(i; args)))
+                .or_else(|| {
+                    reg_ctx
+                        .get(&(fun.0 as usize))
+                        .map(|param| (Call::Closure, *param, build_ctx!(i; args)))
+                }),
+            Opcode::CallMethod { field, args, .. } => code[f[args[0]]]
+                .get_type_obj()
+                .map(|o| (Call::Direct, o.protos[field.0].findex, build_ctx!(i; args))),
+            Opcode::CallThis { field, args, .. } => code[f.regs[0]]
+                .get_type_obj()
+                .map(|o| (Call::Direct, o.protos[field.0].findex, build_ctx!(i; args))),
+            _ => None,
+        }
+        .map(|(kind, fun, ctx)| (CallSite { kind, pos: i }, fun, ctx))
     })
 }
 
@@ -111,19 +139,60 @@ fn build_graph_rec(code: &Bytecode, g: &mut Callgraph, f: &Function, ctx: &RegCt
                         //println!("call to {} with args: {:?}", fun.display_header(code), ctx);
                         build_graph_rec(code, g, fun, &ctx, depth - 1);
                     }
-                    g.add_edge(f.findex, fun.findex, call);
+                    add_call(g, f.findex, fun.findex, call);
                 }
                 FunPtr::Native(n) => {
                     if !g.contains_node(n.findex) {
                         g.add_node(n.findex);
                     }
-                    g.add_edge(f.findex, n.findex, call);
+                    add_call(g, f.findex, n.findex, call);
                 }
             }
         }
     }
 }
 
+fn add_call(g: &mut Callgraph, caller: RefFun, callee: RefFun, site: CallSite) {
+    if let Some(sites) = g.edge_weight_mut(caller, callee) {
+        sites.push(site);
+    } else {
+        g.add_edge(caller, callee, vec![site]);
+    }
+}
+
+/// Callgraph of the whole bytecode, every function and native is a node. Edges come from [CallIndex].
+pub fn full_call_graph(code: &Bytecode) -> Callgraph {
+    let mut g = Callgraph::new();
+    for f in code.functions() {
+        g.add_node(f.findex());
+    }
+    for e in CallIndex::new(code).edges() {
+        let kind = match e.kind {
+            CallKind::Direct | CallKind::Method | CallKind::Override => Call::Direct,
+            CallKind::Closure | CallKind::Binding => Call::Closure,
+            CallKind::Reference => continue,
+        };
+        add_call(&mut g, e.caller, e.callee, CallSite { kind, pos: e.pos });
+    }
+    g
+}
+
+/// Remove the compiler generated functions from a callgraph, see [Function::is_compiler_generated]
+pub fn remove_compiler_generated(code: &Bytecode, g: &mut Callgraph) {
+    let generated: Vec<RefFun> = g
+        .nodes()
+        .filter(|f| f.is_compiler_generated(code))
+        .collect();
+    for f in generated {
+        g.remove_node(f);
+    }
+}
+
+/// Convert a callgraph to an index based graph, required by some petgraph algorithms
+pub fn to_graph(g: Callgraph) -> DiGraph<RefFun, Vec<CallSite>> {
+    g.into_graph()
+}
+
 static TYPE: [&str; 2] = ["graph", "digraph"];
 static EDGE: [&str; 2] = ["--", "->"];
 static INDENT: &str = "    ";
@@ -168,9 +237,10 @@ impl Display for GraphDisplay<'_> {
                 self.g.to_index(edge.source()),
                 EDGE[self.g.is_directed() as usize],
                 self.g.to_index(edge.target()),
-                match edge.weight() {
-                    Call::Direct => "",
-                    Call::Closure => "closure",
+                if edge.weight().iter().any(|s| s.kind == Call::Closure) {
+                    "closure"
+                } else {
+                    ""
                 }
             )?;
         }
@@ -184,3 +254,128 @@ impl Display for GraphDisplay<'_> {
 pub fn display_graph<'a>(g: &'a Callgraph, code: &'a Bytecode) -> GraphDisplay<'a> {
     GraphDisplay { g, code }
 }
+
+pub struct CfgDisplay<'a> {
+    code: &'a Bytecode,
+    f: &'a Function,
+    cfg: Cfg,
+}
+
+impl Display for CfgDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {{", TYPE[1])?;
+
+        writeln!(f, "{}fontname=\"Helvetica,Arial,sans-serif\"", INDENT)?;
+        writeln!(
+            f,
+            "{}node [fontname=\"Courier,monospace\" style=filled fillcolor=\"#f8f8f8\" shape=box]",
+            INDENT
+        )?;
+        writeln!(
+            f,
+            "{}edge [fontname=\"Helvetica,Arial,sans-serif\"]",
+            INDENT
+        )?;
+
+        // output all blocks with their instructions, left aligned
+        for (b, block) in self.cfg.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for pos in block.ops.clone() {
+                let op = self.f.ops[pos].display(self.code, self.f, pos as i32, 11);
+                label.push_str(&escape_label(&format!("{pos:>3}: {op}")));
+                label.push_str("\\l");
+            }
+            write!(f, "{}{} [ label = \"{}\"", INDENT, b, label)?;
+            if b == 0 {
+                write!(f, " color=\"#b20400\" fillcolor=\"#edd6d5\"")?;
+            }
+            writeln!(f, " ]")?;
+        }
+        // output all edges
+        for (b, block) in self.cfg.blocks.iter().enumerate() {
+            let last = &self.f.ops[block.last()];
+            let conditional = block.succs.iter().any(|e| e.kind == EdgeKind::Branch);
+            for edge in &block.succs {
+                let label = match edge.kind {
+                    EdgeKind::Branch => "true".to_string(),
+                    EdgeKind::Case(i) => format!("case {i}"),
+                    EdgeKind::Fallthrough if conditional => "false".to_string(),
+                    EdgeKind::Fallthrough if matches!(last, Opcode::Switch { .. }) => {
+                        "default".to_string()
+                    }
+                    EdgeKind::Exception => "trap".to_string(),
+                    EdgeKind::Fallthrough | EdgeKind::Jump => String::new(),
+                };
+                write!(
+                    f,
+                    "{}{} {} {} [ label = \"{}\"",
+                    INDENT, b, EDGE[1], edge.to, label
+                )?;
+                if edge.kind == EdgeKind::Exception {
+                    write!(f, " style=dashed")?;
+                }
+                writeln!(f, " ]")?;
+            }
+        }
+
+        writeln!(f, "}}")?;
+        Ok(())
+    }
+}
+
+/// Escape a string to be used in a dot label
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Generate dot language for the control flow graph of a function, see [Cfg].
+/// Nodes are the basic blocks with their instructions, edges are labeled with the branch taken.
+pub fn display_cfg<'a>(code: &'a Bytecode, f: &'a Function) -> CfgDisplay<'a> {
+    CfgDisplay {
+        code,
+        f,
+        cfg: f.cfg(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::graph::display_cfg;
+    use crate::testing::{bytecode, fun};
+    use crate::types::Type;
+
+    #[test]
+    fn test_cfg_dot() {
+        // r1 = 0; if (r0) r1++; return r1;
+        let mut code = bytecode(
+            &[""],
+            vec![Type::I32, Type::Bool, fun(&[1], 0)],
+            "
+            .fun fn@0 type@2
+            .reg type@1
+            .reg type@0
+                Int reg1 int@0
+                JFalse reg0 end
+                Incr reg1
+            end:
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![0];
+        let dot = display_cfg(&code, &code.functions[0]).to_string();
+        assert!(dot.starts_with("digraph {"));
+        let lines: Vec<_> = dot.lines().skip(4).map(str::trim).collect();
+        assert_eq!(
+            lines,
+            [
+                r##"0 [ label = "  0: Int         reg1 = 0\l  1: JFalse      if reg0 == false jump to 3\l" color="#b20400" fillcolor="#edd6d5" ]"##,
+                r#"1 [ label = "  2: Incr        reg1++\l" ]"#,
+                r#"2 [ label = "  3: Ret         reg1\l" ]"#,
+                r#"0 -> 2 [ label = "true" ]"#,
+                r#"0 -> 1 [ label = "false" ]"#,
+                r#"1 -> 2 [ label = "" ]"#,
+                "}",
+            ]
+        );
+    }
+}
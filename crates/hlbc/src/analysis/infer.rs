@@ -0,0 +1,214 @@
+//! Refinement of the `dynamic` and `null<T>` register types.
+//!
+//! Many registers are typed `dynamic` while holding a value of a known type : the value was boxed with
+//! [Opcode::ToDyn], or a [Opcode::SafeCast] proved its type. This forward data flow analysis over the [Cfg] tracks the
+//! concrete type held by those registers at each instruction.
+
+use crate::analysis::cfg::Cfg;
+use crate::analysis::dataflow::{def, solve, uses, Analysis, Direction};
+use crate::types::{Function, RefType, Reg, Type};
+use crate::{Bytecode, Opcode};
+
+/// Type held by a register in [TypeInference]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Refined {
+    /// Not assigned yet (bottom)
+    Undefined,
+    /// Holds a value of this type
+    Type(RefType),
+    /// Only the declared type is known (top)
+    Unknown,
+}
+
+impl Refined {
+    fn join(self, other: Refined) -> Refined {
+        match (self, other) {
+            (Refined::Undefined, r) | (r, Refined::Undefined) => r,
+            (a, b) if a == b => a,
+            _ => Refined::Unknown,
+        }
+    }
+}
+
+/// The concrete type held by each `dynamic` or `null<T>` register
+pub struct TypeInference<'a> {
+    pub code: &'a Bytecode,
+}
+
+impl TypeInference<'_> {
+    /// Only registers with an imprecise declared type are refined
+    fn refinable(&self, f: &Function, reg: Reg) -> bool {
+        matches!(self.code[f[reg]], Type::Dyn | Type::Null(_))
+    }
+
+    /// Most precise type of a register
+    fn concrete(&self, f: &Function, state: &[Refined], reg: Reg) -> Option<RefType> {
+        match state.get(reg.0 as usize) {
+            Some(&Refined::Type(t)) => Some(t),
+            _ if !self.refinable(f, reg) => Some(f[reg]),
+            _ => None,
+        }
+    }
+}
+
+impl Analysis for TypeInference<'_> {
+    type Domain = Vec<Refined>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, f: &Function) -> Self::Domain {
+        vec![Refined::Unknown; f.regs.len()]
+    }
+
+    fn bottom(&self, f: &Function) -> Self::Domain {
+        vec![Refined::Undefined; f.regs.len()]
+    }
+
+    fn join(&self, into: &mut Self::Domain, other: &Self::Domain) {
+        for (a, &b) in into.iter_mut().zip(other) {
+            *a = a.join(b);
+        }
+    }
+
+    fn transfer(&self, f: &Function, _pos: usize, op: &Opcode, state: &mut Self::Domain) {
+        let set = |state: &mut Self::Domain, reg: Reg, value: Refined| {
+            if let Some(r) = state.get_mut(reg.0 as usize) {
+                *r = value;
+            }
+        };
+        match *op {
+            Opcode::Mov { dst, src } | Opcode::ToDyn { dst, src } if self.refinable(f, dst) => {
+                let value = self
+                    .concrete(f, state, src)
+                    .filter(|&t| t != f[dst])
+                    .map_or(Refined::Unknown, Refined::Type);
+                set(state, dst, value);
+            }
+            Opcode::SafeCast { dst, src } => {
+                set(state, dst, Refined::Unknown);
+                // The cast throws if the value doesn't have the type
+                if self.refinable(f, src) && !self.refinable(f, dst) {
+                    set(state, src, Refined::Type(f[dst]));
+                }
+            }
+            _ => {
+                if let Some(dst) = def(op) {
+                    set(state, dst, Refined::Unknown);
+                }
+            }
+        }
+    }
+}
+
+/// Refined register types of a function, see [TypeInference]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InferredTypes {
+    /// Refined types of the registers of each instruction.
+    /// The register assigned by the instruction has its type after the instruction.
+    ops: Vec<Vec<(Reg, RefType)>>,
+}
+
+impl InferredTypes {
+    pub fn new(code: &Bytecode, f: &Function) -> Self {
+        let analysis = TypeInference { code };
+        let cfg = Cfg::new(f);
+        let results = solve(&analysis, f, &cfg);
+
+        let mut ops = vec![Vec::new(); f.ops.len()];
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let mut state = results.entry[b].clone();
+            for pos in block.ops.clone() {
+                let op = &f.ops[pos];
+                let refined = |state: &[Refined], reg: Reg| match state.get(reg.0 as usize) {
+                    Some(&Refined::Type(t)) => Some((reg, t)),
+                    _ => None,
+                };
+                let mut types: Vec<(Reg, RefType)> = Vec::new();
+                for reg in uses(op) {
+                    if let Some(r) = refined(&state, reg) {
+                        if !types.contains(&r) {
+                            types.push(r);
+                        }
+                    }
+                }
+                analysis.transfer(f, pos, op, &mut state);
+                if let Some(r) = def(op).and_then(|dst| refined(&state, dst)) {
+                    types.retain(|&(reg, _)| reg != r.0);
+                    types.push(r);
+                }
+                ops[pos] = types;
+            }
+        }
+        Self { ops }
+    }
+
+    /// Refined type of a register at an instruction, `None` if only the declared type is known
+    pub fn get(&self, pos: usize, reg: Reg) -> Option<RefType> {
+        self.ops
+            .get(pos)?
+            .iter()
+            .find_map(|&(r, t)| if r == reg { Some(t) } else { None })
+    }
+
+    /// All the refined register types at an instruction
+    pub fn refined(&self, pos: usize) -> &[(Reg, RefType)] {
+        self.ops.get(pos).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+impl Function {
+    /// Infer the concrete types of the `dynamic` and `null<T>` registers
+    pub fn infer_types(&self, code: &Bytecode) -> InferredTypes {
+        InferredTypes::new(code, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::infer::InferredTypes;
+    use crate::testing::{bytecode, fun};
+    use crate::types::{RefType, Reg, Type};
+
+    #[test]
+    fn test_infer_types() {
+        let mut code = bytecode(
+            &[""],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Dyn,
+                Type::Bool,
+                Type::F64,
+                fun(&[], 2),
+            ],
+            "
+            .fun fn@0 type@5
+            .reg type@1
+            .reg type@2
+            .reg type@2
+            .reg type@3
+            .reg type@4
+                Int reg0 int@0
+                ToDyn reg1 reg0
+                Mov reg2 reg1
+                JFalse reg3 skip
+                Null reg2
+            skip:
+                SafeCast reg4 reg2
+                Ret reg2
+            .end",
+        );
+        code.ints = vec![0];
+        let types = InferredTypes::new(&code, &code.functions[0]);
+        let (int, float) = (RefType(1), RefType(4));
+        assert!(types.refined(0).is_empty());
+        assert_eq!(types.refined(1), &[(Reg(1), int)]);
+        assert_eq!(types.refined(2), &[(Reg(1), int), (Reg(2), int)]);
+        assert!(types.refined(4).is_empty());
+        // Merge of an int and null
+        assert!(types.refined(5).is_empty());
+        // Proven by the cast
+        assert_eq!(types.get(6, Reg(2)), Some(float));
+        assert_eq!(types.get(6, Reg(1)), None);
+    }
+}
@@ -0,0 +1,209 @@
+//! Size and complexity metrics of the functions, to triage what to reverse first.
+//!
+//! Metrics are computed for every function and aggregated per parent type and per source file.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::calls::CallIndex;
+use crate::types::{Function, RefFun, RefType};
+use crate::{Bytecode, Str};
+
+/// Metrics of a single function
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FunctionMetrics {
+    pub findex: RefFun,
+    /// Number of instructions
+    pub ops: usize,
+    /// Number of registers
+    pub regs: usize,
+    /// Cyclomatic complexity of the control flow graph, 1 for a function without branches
+    pub complexity: usize,
+    /// Number of distinct functions calling this one
+    pub fan_in: usize,
+    /// Number of distinct functions this one calls
+    pub fan_out: usize,
+    /// Maximum number of nested traps, 0 without any trap
+    pub trap_depth: usize,
+}
+
+impl FunctionMetrics {
+    /// Metrics of a function, the call graph is used for the fan-in and fan-out
+    pub fn new(calls: &CallIndex, f: &Function) -> Self {
+        Self {
+            findex: f.findex,
+            ops: f.ops.len(),
+            regs: f.regs.len(),
+            complexity: f.cyclomatic_complexity(),
+            fan_in: calls
+                .callers(f.findex)
+                .map(|e| e.caller)
+                .collect::<HashSet<_>>()
+                .len(),
+            fan_out: calls
+                .callees(f.findex)
+                .map(|e| e.callee)
+                .collect::<HashSet<_>>()
+                .len(),
+            trap_depth: f
+                .trap_regions()
+                .iter()
+                .map(|r| r.depth + 1)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Metrics of a group of functions
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct AggregateMetrics {
+    /// Number of functions
+    pub functions: usize,
+    /// Total number of instructions
+    pub ops: usize,
+    /// Total cyclomatic complexity
+    pub complexity: usize,
+    /// Highest cyclomatic complexity of a function
+    pub max_complexity: usize,
+    /// Highest trap nesting of a function
+    pub max_trap_depth: usize,
+}
+
+impl AggregateMetrics {
+    fn add(&mut self, m: &FunctionMetrics) {
+        self.functions += 1;
+        self.ops += m.ops;
+        self.complexity += m.complexity;
+        self.max_complexity = self.max_complexity.max(m.complexity);
+        self.max_trap_depth = self.max_trap_depth.max(m.trap_depth);
+    }
+}
+
+/// Metrics of every function in the bytecode
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// In the order of [Bytecode::functions]
+    pub functions: Vec<FunctionMetrics>,
+    /// Aggregated by parent type, functions without a parent are skipped
+    pub types: HashMap<RefType, AggregateMetrics>,
+    /// Aggregated by source file, functions without debug info are skipped
+    pub files: HashMap<Str, AggregateMetrics>,
+}
+
+impl Metrics {
+    pub fn new(code: &Bytecode) -> Self {
+        let calls = CallIndex::new(code);
+        let mut types = HashMap::<_, AggregateMetrics>::new();
+        let mut files = HashMap::<_, AggregateMetrics>::new();
+        let functions = code
+            .functions
+            .iter()
+            .map(|f| {
+                let m = FunctionMetrics::new(&calls, f);
+                if let Some(parent) = f.parent {
+                    types.entry(parent).or_default().add(&m);
+                }
+                if let Some(file) = f.file_index().and_then(|i| code.debug_file(i)) {
+                    files.entry(file).or_default().add(&m);
+                }
+                m
+            })
+            .collect();
+        Self {
+            functions,
+            types,
+            files,
+        }
+    }
+
+    /// Metrics of a function, None for natives
+    pub fn get(&self, findex: RefFun) -> Option<&FunctionMetrics> {
+        self.functions.iter().find(|m| m.findex == findex)
+    }
+
+    /// Functions sorted by decreasing complexity, then by decreasing size
+    pub fn most_complex(&self) -> Vec<&FunctionMetrics> {
+        let mut sorted: Vec<_> = self.functions.iter().collect();
+        sorted.sort_by_key(|m| Reverse((m.complexity, m.ops)));
+        sorted
+    }
+}
+
+impl Function {
+    /// Cyclomatic complexity (edges - blocks + 2) of the control flow graph, at least 1
+    pub fn cyclomatic_complexity(&self) -> usize {
+        let blocks = self.basic_blocks();
+        let edges: usize = blocks.iter().map(|b| b.successors().count()).sum();
+        (edges + 2).saturating_sub(blocks.len()).max(1)
+    }
+}
+
+impl Bytecode {
+    /// Compute the metrics of every function, see [Metrics]
+    pub fn metrics(&self) -> Metrics {
+        Metrics::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::metrics::{AggregateMetrics, FunctionMetrics};
+    use crate::testing::shapes;
+    use crate::types::{Function, RefFun, RefType};
+
+    #[test]
+    fn test_metrics() {
+        let code = shapes();
+        let metrics = code.metrics();
+        assert_eq!(
+            metrics.get(RefFun(0)),
+            Some(&FunctionMetrics {
+                findex: RefFun(0),
+                ops: 9,
+                regs: 5,
+                complexity: 1,
+                fan_in: 0,
+                fan_out: 3,
+                trap_depth: 0,
+            })
+        );
+        // Shape.area calls itself and the override
+        let area = metrics.get(RefFun(1)).unwrap();
+        assert_eq!((area.fan_in, area.fan_out), (2, 2));
+        assert_eq!(
+            metrics.types[&RefType(7)],
+            AggregateMetrics {
+                functions: 2,
+                ops: 10,
+                complexity: 2,
+                max_complexity: 1,
+                max_trap_depth: 0,
+            }
+        );
+        assert_eq!(metrics.types[&RefType(5)].functions, 1);
+        assert!(metrics.files.is_empty());
+        let sorted: Vec<_> = metrics.most_complex().iter().map(|m| m.findex.0).collect();
+        assert_eq!(sorted, vec![0, 1, 2, 3]);
+
+        // while (r0) { if (r0) {} else {} }
+        let f = Function::from_asm(
+            "
+            .fun fn@0 type@0
+            .reg type@0
+            loop:
+                Label
+                JFalse reg0 end
+                JFalse reg0 else
+                Nop
+                JAlways loop
+            else:
+                JAlways loop
+            end:
+                Ret reg0
+            .end",
+        )
+        .unwrap();
+        assert_eq!(f.cyclomatic_complexity(), 3);
+    }
+}
@@ -2,14 +2,42 @@
 
 use std::iter::repeat;
 
+use crate::opcodes::Operand;
 use crate::types::{FunPtr, Reg};
 use crate::{Bytecode, Function, Native, Opcode, RefFun, RefType, Resolve, Type, TypeObj};
 
 #[cfg(feature = "graph")]
 pub mod graph;
 
+pub mod calls;
+pub mod cfg;
+pub mod dataflow;
+pub mod dead;
+pub mod devirt;
+pub mod diff;
+pub mod dom;
+pub mod eval;
 pub mod files;
+pub mod fingerprint;
+pub mod generics;
+pub mod globals;
+pub mod infer;
+pub mod metrics;
+pub mod natives;
+pub mod obfuscation;
+pub mod overrides;
+pub mod pattern;
+pub mod profile;
+pub mod provenance;
+pub mod recursion;
+pub mod signatures;
+pub mod ssa;
+pub mod stats;
+pub mod trace;
+pub mod traps;
+pub mod unused;
 pub mod usage;
+pub mod xref;
 
 impl Bytecode {
     /// Iterate on every instruction of every function
@@ -28,36 +56,40 @@ impl RefFun {
             FunPtr::Native(n) => n.is_from_std(code),
         }
     }
+
+    /// return true if the function has been generated by the compiler (entrypoint, `__string`, `__alloc__`, ...)
+    pub fn is_compiler_generated(&self, code: &Bytecode) -> bool {
+        match code.get(*self) {
+            FunPtr::Fun(fun) => fun.is_compiler_generated(code),
+            FunPtr::Native(_) => false,
+        }
+    }
 }
 
 impl Function {
     /// return true if the function is from the standard library
     pub fn is_from_std(&self, code: &Bytecode) -> bool {
-        if let Some(debug_info) = &self.debug_info {
-            // We look at the Ret opcode which is probably not from inlined code.
-            let (file, _) = debug_info[self.ops.len() - 1];
-            let filename = &code.debug_files.as_ref().unwrap()[file];
-            filename.contains("std")
-        } else {
-            false
-        }
+        self.file_index()
+            .and_then(|file| code.debug_file(file))
+            .map(|filename| filename.contains("std"))
+            .unwrap_or(false)
+    }
+
+    /// return true if the function has been generated by the compiler.
+    /// Those are the entrypoint and the special methods like `__string`, `__alloc__` or `__type__`.
+    pub fn is_compiler_generated(&self, code: &Bytecode) -> bool {
+        self.findex == code.entrypoint || self.name(code).starts_with("__")
     }
 
     /// Find any outbound references to other functions in a function
     pub fn find_fun_refs(&self) -> impl Iterator<Item = (usize, &Opcode, RefFun)> + '_ {
-        // TODO method calls (CallMethod & CallThis)
-        self.ops.iter().enumerate().filter_map(|(i, o)| match o {
-            // Direct call
-            Opcode::Call0 { fun, .. } => Some((i, o, *fun)),
-            Opcode::Call1 { fun, .. } => Some((i, o, *fun)),
-            Opcode::Call2 { fun, .. } => Some((i, o, *fun)),
-            Opcode::Call3 { fun, .. } => Some((i, o, *fun)),
-            Opcode::Call4 { fun, .. } => Some((i, o, *fun)),
-            Opcode::CallN { fun, .. } => Some((i, o, *fun)),
-            // Reference through closure
-            Opcode::StaticClosure { fun, .. } => Some((i, o, *fun)),
-            Opcode::InstanceClosure { fun, .. } => Some((i, o, *fun)),
-            _ => None,
+        // TODO method calls (CallMethod & CallThis), see analysis::devirt
+        // Direct calls and references through closures
+        self.ops.iter().enumerate().filter_map(|(i, o)| {
+            o.operands().into_iter().find_map(|(_, operand)| match operand {
+                Operand::Fun(fun) => Some((i, o, fun)),
+                _ => None,
+            })
         })
     }
 
@@ -0,0 +1,181 @@
+//! Catalog of the native functions grouped by library.
+//!
+//! Natives from libraries outside the HashLink distribution are usually game specific hdlls, those are the first
+//! thing to look at when reversing a game.
+
+use std::fmt::Write;
+
+use crate::analysis::xref::XrefIndex;
+use crate::fmt::EnhancedFmt;
+use crate::types::RefFun;
+use crate::{Bytecode, Str};
+
+/// Libraries shipped with HashLink
+pub const STANDARD_LIBS: &[&str] = &[
+    "std", "fmt", "sdl", "directx", "dx12", "openal", "ui", "uv", "ssl", "mysql", "sqlite",
+    "video", "mesa",
+];
+
+/// Natives of a library
+#[derive(Debug, Clone)]
+pub struct NativeLib {
+    /// Library name without the lazy loading marker
+    pub name: Str,
+    /// The library is lazily loaded (the name starts with `?`)
+    pub lazy: bool,
+    /// The library is shipped with HashLink, see [STANDARD_LIBS]
+    pub standard: bool,
+    /// In the order of the natives pool
+    pub natives: Vec<RefFun>,
+}
+
+/// Natives grouped by library
+#[derive(Debug, Clone, Default)]
+pub struct NativeCatalog {
+    /// Libraries sorted by name
+    pub libs: Vec<NativeLib>,
+}
+
+impl NativeCatalog {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut libs: Vec<NativeLib> = Vec::new();
+        for n in &code.natives {
+            let lib = n.lib(code);
+            let lazy = lib.starts_with('?');
+            let name = Str::from(lib.trim_start_matches('?'));
+            match libs.iter_mut().find(|l| l.name == name) {
+                Some(l) => {
+                    l.lazy |= lazy;
+                    l.natives.push(n.findex);
+                }
+                None => libs.push(NativeLib {
+                    standard: STANDARD_LIBS.contains(&&*name),
+                    name,
+                    lazy,
+                    natives: vec![n.findex],
+                }),
+            }
+        }
+        libs.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { libs }
+    }
+
+    /// Find a library by name, with or without the lazy loading marker
+    pub fn lib(&self, name: &str) -> Option<&NativeLib> {
+        let name = name.trim_start_matches('?');
+        self.libs.iter().find(|l| l.name == name)
+    }
+
+    /// Natives from non standard libraries which are referenced by an instruction
+    pub fn referenced_non_standard(&self, xrefs: &XrefIndex) -> Vec<RefFun> {
+        self.libs
+            .iter()
+            .filter(|l| !l.standard)
+            .flat_map(|l| &l.natives)
+            .copied()
+            .filter(|&n| !xrefs.fun(n).is_empty())
+            .collect()
+    }
+
+    /// Render the catalog with the signature of every native, one library per section
+    pub fn render(&self, code: &Bytecode) -> String {
+        let mut out = String::new();
+        for lib in &self.libs {
+            let _ = writeln!(
+                out,
+                "{}{} ({} natives){}",
+                if lib.lazy { "?" } else { "" },
+                lib.name,
+                lib.natives.len(),
+                if lib.standard { "" } else { " [non standard]" }
+            );
+            for &n in &lib.natives {
+                let _ = writeln!(out, "  {}", n.display_header::<EnhancedFmt>(code));
+            }
+        }
+        out
+    }
+}
+
+impl Bytecode {
+    /// Group the natives by library, see [NativeCatalog]
+    pub fn native_catalog(&self) -> NativeCatalog {
+        NativeCatalog::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::xref::XrefIndex;
+    use crate::testing::{bytecode, fun, reload};
+    use crate::types::{Native, RefFun, RefString, RefType, Type};
+
+    #[test]
+    fn test_native_catalog() {
+        let mut code = bytecode(
+            &[
+                "",
+                "main",
+                "std",
+                "sys_print",
+                "?steam",
+                "init",
+                "mygame",
+                "decrypt",
+                "unused",
+            ],
+            vec![Type::Void, fun(&[], 0)],
+            "
+            .fun fn@0 type@1
+            .reg type@0
+                Call0 reg0 fn@2
+                Call0 reg0 fn@3
+                Ret reg0
+            .end",
+        );
+        let native = |lib, name, findex| Native {
+            name: RefString(name),
+            lib: RefString(lib),
+            t: RefType(1),
+            findex: RefFun(findex),
+        };
+        code.natives = vec![
+            native(2, 3, 1),
+            native(4, 5, 2),
+            native(6, 7, 3),
+            native(6, 8, 4),
+        ];
+        let code = reload(&code);
+
+        let catalog = code.native_catalog();
+        let libs: Vec<_> = catalog
+            .libs
+            .iter()
+            .map(|l| (&*l.name, l.lazy, l.standard, l.natives.clone()))
+            .collect();
+        assert_eq!(
+            libs,
+            vec![
+                ("mygame", false, false, vec![RefFun(3), RefFun(4)]),
+                ("std", false, true, vec![RefFun(1)]),
+                ("steam", true, false, vec![RefFun(2)]),
+            ]
+        );
+        assert_eq!(catalog.lib("?steam").unwrap().name, catalog.libs[2].name);
+        assert_eq!(
+            catalog.referenced_non_standard(&XrefIndex::new(&code)),
+            vec![RefFun(3), RefFun(2)]
+        );
+        let render = catalog.render(&code);
+        let headers: Vec<_> = render.lines().filter(|l| !l.starts_with(' ')).collect();
+        assert_eq!(
+            headers,
+            vec![
+                "mygame (2 natives) [non standard]",
+                "std (1 natives)",
+                "?steam (1 natives) [non standard]",
+            ]
+        );
+        assert_eq!(render.lines().count(), 7);
+    }
+}
@@ -0,0 +1,443 @@
+//! Heuristics detecting obfuscated bytecode and recovering encrypted strings.
+//!
+//! Three common obfuscations are looked for :
+//! - name mangling : classes and methods renamed to meaningless identifiers (`a`, `_0x1f2e`, `IlIl1`)
+//! - string encryption : string constants passed through a decoding function before use
+//! - control flow flattening : the body of a function turned into a state machine, a `switch` in a loop
+//!
+//! Encrypted strings are recovered by a [StringDecoder] called on each call to a decoding function with constant
+//! arguments, [EvalDecoder] evaluates the decoding function itself with the [Evaluator].
+
+use crate::analysis::eval::{Evaluator, Value};
+use crate::rename::{Renames, Symbol};
+use crate::types::{Function, RefFun, RefGlobal, RefType, Reg};
+use crate::{Bytecode, Opcode, Resolve, TryResolve, Type};
+
+/// Findings of the heuristics
+#[derive(Debug, Clone, Default)]
+pub struct ObfuscationReport {
+    /// Classes with a mangled name
+    pub mangled_types: Vec<RefType>,
+    /// Functions with a mangled name
+    pub mangled_functions: Vec<RefFun>,
+    /// Ratio of mangled names among the names of the program (outside the std)
+    pub mangled_ratio: f32,
+    /// Functions looking like string decoders
+    pub string_decoders: Vec<RefFun>,
+    /// Functions looking like a flattened state machine
+    pub flattened: Vec<RefFun>,
+}
+
+impl ObfuscationReport {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut report = Self::default();
+        let mut names = 0;
+        for i in 0..code.types.len() {
+            let t = RefType(i);
+            if t.is_from_std(code) {
+                continue;
+            }
+            if let Some(obj) = code[t].get_type_obj() {
+                names += 1;
+                let name = code.try_get(obj.name).unwrap_or_default();
+                // Only the class name, not the package nor the `$` of the static class
+                let class = name.rsplit('.').next().unwrap_or_default();
+                if is_mangled_name(class.trim_start_matches('$')) {
+                    report.mangled_types.push(t);
+                }
+            }
+        }
+        for f in &code.functions {
+            if f.is_from_std(code) || f.is_compiler_generated(code) {
+                continue;
+            }
+            names += 1;
+            if is_mangled_name(&code.try_get(f.name).unwrap_or_default()) {
+                report.mangled_functions.push(f.findex);
+            }
+            if is_string_decoder(code, f) {
+                report.string_decoders.push(f.findex);
+            }
+            if is_flattened(f) {
+                report.flattened.push(f.findex);
+            }
+        }
+        if names > 0 {
+            let mangled = report.mangled_types.len() + report.mangled_functions.len();
+            report.mangled_ratio = mangled as f32 / names as f32;
+        }
+        report
+    }
+
+    /// The program looks obfuscated
+    pub fn is_obfuscated(&self) -> bool {
+        self.mangled_ratio > 0.5 || !self.string_decoders.is_empty() || !self.flattened.is_empty()
+    }
+}
+
+/// The name looks generated by an obfuscator : hex identifiers, characters outside of ASCII, confusable characters
+/// only (`IlI1`) or long names without any vowel. Short names (`a`, `b`) alone aren't enough, see
+/// [ObfuscationReport::mangled_ratio].
+pub fn is_mangled_name(name: &str) -> bool {
+    let name = name.trim_start_matches('_');
+    if name.is_empty() {
+        return false;
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return true;
+    }
+    if let Some(hex) = name.strip_prefix("0x") {
+        return hex.chars().all(|c| c.is_ascii_hexdigit());
+    }
+    let confusable = |c| matches!(c, 'I' | 'l' | '1' | 'O' | '0' | '_');
+    if name.len() >= 4 && name.chars().all(confusable) {
+        return true;
+    }
+    name.len() >= 8
+        && !name
+            .chars()
+            .any(|c| matches!(c.to_ascii_lowercase(), 'a' | 'e' | 'i' | 'o' | 'u' | 'y'))
+}
+
+fn is_string(code: &Bytecode, t: RefType) -> bool {
+    match code.types.get(t.0) {
+        Some(Type::Obj(obj)) => code.try_get(obj.name).as_deref() == Some("String"),
+        _ => false,
+    }
+}
+
+/// Takes a string or bytes, returns a string and loops over the bytes with arithmetic on them
+fn is_string_decoder(code: &Bytecode, f: &Function) -> bool {
+    let Some(Type::Fun(fun)) = code.types.get(f.t.0) else {
+        return false;
+    };
+    let takes_data = fun
+        .args
+        .iter()
+        .any(|&a| is_string(code, a) || matches!(code.types.get(a.0), Some(Type::Bytes)));
+    if !takes_data || !is_string(code, fun.ret) {
+        return false;
+    }
+    let loops = f
+        .ops
+        .iter()
+        .enumerate()
+        .any(|(pos, op)| op.jump_targets(pos).iter().any(|&t| t <= pos));
+    let reads_bytes = f.ops.iter().any(|op| {
+        matches!(
+            op,
+            Opcode::GetI8 { .. } | Opcode::GetI16 { .. } | Opcode::GetMem { .. }
+        )
+    });
+    let arith = f.ops.iter().any(|op| {
+        matches!(
+            op,
+            Opcode::Xor { .. } | Opcode::Shl { .. } | Opcode::SShr { .. } | Opcode::UShr { .. }
+        )
+    });
+    loops && reads_bytes && arith
+}
+
+/// A switch in a loop whose cases set the switch register to the next state
+fn is_flattened(f: &Function) -> bool {
+    f.ops.iter().enumerate().any(|(pos, op)| {
+        let Opcode::Switch {
+            reg, ref offsets, ..
+        } = *op
+        else {
+            return false;
+        };
+        if offsets.len() < 3 {
+            return false;
+        }
+        let body = &f.ops[pos + 1..];
+        let states = body
+            .iter()
+            .filter(|op| matches!(op, Opcode::Int { dst, .. } if *dst == reg))
+            .count();
+        let back = body
+            .iter()
+            .enumerate()
+            .any(|(i, op)| op.jump_targets(pos + 1 + i).iter().any(|&t| t <= pos));
+        back && states * 2 >= offsets.len()
+    })
+}
+
+/// Decodes the result of a call to a string decoding function
+pub trait StringDecoder {
+    /// `args` are the values of the call arguments, [Value::Unknown] when they aren't constant.
+    /// Returns None if the string can't be decoded.
+    fn decode(&mut self, eval: &mut Evaluator, decoder: RefFun, args: &[Value]) -> Option<String>;
+}
+
+impl<F> StringDecoder for F
+where
+    F: FnMut(&mut Evaluator, RefFun, &[Value]) -> Option<String>,
+{
+    fn decode(&mut self, eval: &mut Evaluator, decoder: RefFun, args: &[Value]) -> Option<String> {
+        self(eval, decoder, args)
+    }
+}
+
+/// Evaluates the decoding function with the [Evaluator]
+pub struct EvalDecoder;
+
+impl StringDecoder for EvalDecoder {
+    fn decode(&mut self, eval: &mut Evaluator, decoder: RefFun, args: &[Value]) -> Option<String> {
+        match eval.eval(decoder, args).ok()? {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A string recovered from a call to a decoding function
+#[derive(Debug, Clone)]
+pub struct RecoveredString {
+    /// Function calling the decoder
+    pub fun: RefFun,
+    /// Position of the call
+    pub pos: usize,
+    pub decoder: RefFun,
+    pub value: String,
+    /// Global storing the result right after the call
+    pub global: Option<RefGlobal>,
+}
+
+/// Decode every call to the `decoders` with constant arguments
+pub fn recover_strings(
+    code: &Bytecode,
+    decoders: &[RefFun],
+    decoder: &mut impl StringDecoder,
+) -> Vec<RecoveredString> {
+    let mut eval = Evaluator::new(code);
+    let mut recovered = Vec::new();
+    for f in &code.functions {
+        for (pos, op) in f.ops.iter().enumerate() {
+            let Some((dst, fun, args)) = direct_call(op) else {
+                continue;
+            };
+            if !decoders.contains(&fun) {
+                continue;
+            }
+            let args: Vec<Value> = args.iter().map(|&r| constant(code, f, pos, r)).collect();
+            if !args.iter().any(Value::is_known) {
+                continue;
+            }
+            if let Some(value) = decoder.decode(&mut eval, fun, &args) {
+                let global = match f.ops.get(pos + 1) {
+                    Some(&Opcode::SetGlobal { global, src }) if src == dst => Some(global),
+                    _ => None,
+                };
+                recovered.push(RecoveredString {
+                    fun: f.findex,
+                    pos,
+                    decoder: fun,
+                    value,
+                    global,
+                });
+            }
+        }
+    }
+    recovered
+}
+
+/// Name the globals holding recovered strings after their value (`str_hello_world`).
+/// Globals already renamed are left untouched. Returns the number of renamed globals.
+pub fn annotate_strings(recovered: &[RecoveredString], renames: &Renames) -> usize {
+    let mut count = 0;
+    for r in recovered {
+        let Some(global) = r.global else {
+            continue;
+        };
+        let symbol = Symbol::Global(global);
+        if renames.get(symbol).is_some() {
+            continue;
+        }
+        let ident: String = r
+            .value
+            .chars()
+            .take(32)
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if renames.set(symbol, format!("str_{ident}")).is_ok() {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn direct_call(op: &Opcode) -> Option<(Reg, RefFun, Vec<Reg>)> {
+    Some(match *op {
+        Opcode::Call1 { dst, fun, arg0 } => (dst, fun, vec![arg0]),
+        Opcode::Call2 {
+            dst,
+            fun,
+            arg0,
+            arg1,
+        } => (dst, fun, vec![arg0, arg1]),
+        Opcode::Call3 {
+            dst,
+            fun,
+            arg0,
+            arg1,
+            arg2,
+        } => (dst, fun, vec![arg0, arg1, arg2]),
+        Opcode::Call4 {
+            dst,
+            fun,
+            arg0,
+            arg1,
+            arg2,
+            arg3,
+        } => (dst, fun, vec![arg0, arg1, arg2, arg3]),
+        Opcode::CallN { dst, fun, ref args } => (dst, fun, args.to_vec()),
+        _ => return None,
+    })
+}
+
+/// Value of a register loaded with a constant by the last instruction defining it before `pos`
+fn constant(code: &Bytecode, f: &Function, pos: usize, reg: Reg) -> Value {
+    let def = f.ops[..pos]
+        .iter()
+        .rev()
+        .find(|op| op.defs().contains(&reg));
+    match def {
+        Some(&Opcode::Int { ptr, .. }) => Value::Int(code[ptr]),
+        Some(&Opcode::Float { ptr, .. }) => Value::Float(code[ptr]),
+        Some(&Opcode::Bool { value, .. }) => Value::Bool(value),
+        Some(&Opcode::String { ptr, .. }) => Value::String(code[ptr].to_string()),
+        Some(&Opcode::Bytes { ptr, .. }) => Value::Bytes(code.get(ptr).to_vec()),
+        Some(Opcode::Null { .. }) => Value::Null,
+        _ => Value::Unknown,
+    }
+}
+
+impl Bytecode {
+    /// Run the obfuscation heuristics, see [ObfuscationReport]
+    pub fn obfuscation_report(&self) -> ObfuscationReport {
+        ObfuscationReport::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::eval::{Evaluator, Value};
+    use crate::analysis::obfuscation::{annotate_strings, is_mangled_name, recover_strings};
+    use crate::rename::{Renames, Symbol};
+    use crate::testing::{bind, bytecode, fun, obj, sample};
+    use crate::types::{RefFun, RefGlobal, RefType, Type};
+
+    #[test]
+    fn test_mangled_names() {
+        for name in ["_0x1f2e", "IlIl1", "lI1I", "xkcdprtz", "é"] {
+            assert!(is_mangled_name(name), "{name}");
+        }
+        for name in ["main", "a", "toString", "__alloc__", "Std", "onKeyDown"] {
+            assert!(!is_mangled_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_obfuscation_report() {
+        // A string decoder and a flattened function, both with mangled names
+        let mut code = bytecode(
+            &["", "main", "$Main", "String", "IlIl1", "_0x1f2e", "secret"],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Bytes,
+                Type::Obj(obj(3, None, &[], &[])),
+                fun(&[], 0),
+                fun(&[2], 3),
+                Type::Obj(bind(
+                    bind(
+                        bind(obj(2, None, &[(1, 4), (4, 5), (5, 4)], &[]), 0, 0),
+                        1,
+                        1,
+                    ),
+                    2,
+                    2,
+                )),
+            ],
+            "
+            .fun fn@0 type@4
+            .reg type@2
+            .reg type@3
+            .reg type@0
+                String reg0 string@6
+                Call1 reg1 fn@1 reg0
+                SetGlobal global@0 reg1
+                Call0 reg2 fn@2
+                Ret reg2
+            .end
+            .fun fn@1 type@5
+            .reg type@2
+            .reg type@1
+            .reg type@1
+            .reg type@3
+                Int reg1 int@0
+            loop:
+                Label
+                JSGte reg1 reg2 end
+                GetI8 reg2 reg0 reg1
+                Xor reg2 reg2 reg1
+                Incr reg1
+                JAlways loop
+            end:
+                Null reg3
+                Ret reg3
+            .end
+            .fun fn@2 type@4
+            .reg type@1
+            .reg type@0
+                Int reg0 int@0
+            loop:
+                Label
+                Switch reg0 [s0, s1, s2] 0
+                JAlways end
+            s0:
+                Int reg0 int@1
+                JAlways loop
+            s1:
+                Int reg0 int@2
+                JAlways loop
+            s2:
+                Ret reg1
+            end:
+                Ret reg1
+            .end",
+        );
+        code.ints = vec![0, 1, 2];
+        code.globals = vec![RefType(3)];
+        let report = code.obfuscation_report();
+        assert!(report.mangled_types.is_empty());
+        assert_eq!(report.mangled_functions, vec![RefFun(1), RefFun(2)]);
+        assert_eq!(report.mangled_ratio, 0.5);
+        assert_eq!(report.string_decoders, vec![RefFun(1)]);
+        assert_eq!(report.flattened, vec![RefFun(2)]);
+        assert!(report.is_obfuscated());
+        assert!(!sample().obfuscation_report().is_obfuscated());
+
+        fn reverse(_: &mut Evaluator, _: RefFun, args: &[Value]) -> Option<String> {
+            match &args[0] {
+                Value::String(s) => Some(s.chars().rev().collect()),
+                _ => None,
+            }
+        }
+        let recovered = recover_strings(&code, &report.string_decoders, &mut reverse);
+        assert_eq!(recovered.len(), 1);
+        let r = &recovered[0];
+        assert_eq!((r.fun, r.pos, r.decoder), (RefFun(0), 1, RefFun(1)));
+        assert_eq!(r.value, "terces");
+        assert_eq!(r.global, Some(RefGlobal(0)));
+
+        let renames = Renames::default();
+        assert_eq!(annotate_strings(&recovered, &renames), 1);
+        assert_eq!(
+            renames.get(Symbol::Global(RefGlobal(0))).as_deref(),
+            Some("str_terces")
+        );
+        assert_eq!(annotate_strings(&recovered, &renames), 0);
+    }
+}
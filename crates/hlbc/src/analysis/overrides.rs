@@ -0,0 +1,95 @@
+//! Method overrides across the class hierarchy.
+//!
+//! Every virtual method of a class has a slot in its virtual table given by [ObjProto::pindex]. A subclass overriding
+//! the method declares a proto with the same slot, methods with a negative slot are not virtual.
+//!
+//! [ObjProto::pindex]: crate::types::ObjProto::pindex
+
+use crate::types::{RefFun, RefType, Type};
+use crate::Bytecode;
+
+impl Bytecode {
+    /// The type and its parents, starting from the type itself
+    fn super_chain(&self, ty: RefType) -> Vec<RefType> {
+        let mut chain = vec![ty];
+        let mut current = ty;
+        // Bound the walk in case of a malformed cyclic hierarchy
+        while chain.len() <= self.types.len() {
+            match self
+                .types
+                .get(current.0)
+                .and_then(Type::get_type_obj)
+                .and_then(|obj| obj.super_)
+            {
+                Some(s) => {
+                    chain.push(s);
+                    current = s;
+                }
+                None => break,
+            }
+        }
+        chain
+    }
+
+    /// Function bound to a virtual table slot in a class, only if the class declares it
+    fn proto_in(&self, ty: RefType, pindex: i32) -> Option<RefFun> {
+        self.types
+            .get(ty.0)
+            .and_then(Type::get_type_obj)?
+            .protos
+            .iter()
+            .find(|p| p.pindex == pindex)
+            .map(|p| p.findex)
+    }
+
+    /// The class introducing the method in the virtual table slot `pindex`, searching `ty` and its parents.
+    /// None if the method is not virtual or no class in the hierarchy declares it.
+    pub fn method_origin(&self, ty: RefType, pindex: i32) -> Option<(RefType, RefFun)> {
+        if pindex < 0 {
+            return None;
+        }
+        self.super_chain(ty)
+            .into_iter()
+            .filter_map(|t| self.proto_in(t, pindex).map(|f| (t, f)))
+            .last()
+    }
+
+    /// Whether the method of `ty` in the slot `pindex` overrides a method of a parent class
+    pub fn is_override(&self, ty: RefType, pindex: i32) -> bool {
+        self.proto_in(ty, pindex).is_some()
+            && matches!(self.method_origin(ty, pindex), Some((origin, _)) if origin != ty)
+    }
+
+    /// Subclasses of `ty` overriding the method in the slot `pindex`, with the overriding function
+    pub fn method_overrides(&self, ty: RefType, pindex: i32) -> Vec<(RefType, RefFun)> {
+        if pindex < 0 {
+            return Vec::new();
+        }
+        (0..self.types.len())
+            .map(RefType)
+            .filter(|&t| t != ty && self.super_chain(t).contains(&ty))
+            .filter_map(|t| self.proto_in(t, pindex).map(|f| (t, f)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::shapes;
+    use crate::types::{RefFun, RefType};
+
+    #[test]
+    fn test_method_overrides() {
+        // Square (type@5) overrides area from Shape (type@4)
+        let code = shapes();
+        let (shape, square) = (RefType(4), RefType(5));
+        assert_eq!(code.method_origin(square, 0), Some((shape, RefFun(1))));
+        assert_eq!(code.method_origin(shape, 0), Some((shape, RefFun(1))));
+        assert!(code.is_override(square, 0));
+        assert!(!code.is_override(shape, 0));
+        assert_eq!(code.method_overrides(shape, 0), vec![(square, RefFun(2))]);
+        assert!(code.method_overrides(square, 0).is_empty());
+        assert_eq!(code.method_origin(shape, -1), None);
+        assert_eq!(code.method_origin(RefType(7), 0), None);
+    }
+}
@@ -0,0 +1,221 @@
+//! Search for instruction patterns across functions.
+//!
+//! A [Pattern] is a sequence of instruction patterns, each one must match within a number of instructions after the
+//! previous one. Operands are matched by name (like [Opcode::operands]) and registers can be bound to variables so
+//! the same register is used by multiple instructions. Distances count instructions in order, jumps are not followed.
+//!
+//! ```no_run
+//! # use hlbc::analysis::pattern::{OpPattern, OperandPattern, Pattern};
+//! # use hlbc::opcodes::Operand;
+//! # use hlbc::types::{RefFun, RefGlobal};
+//! # let code = hlbc::Bytecode::from_file("code.hl").unwrap();
+//! // GetGlobal followed within 3 instructions by a Call2 to fn@42 using the global as first argument
+//! let pattern = Pattern::new()
+//!     .then(
+//!         OpPattern::new("GetGlobal")
+//!             .operand("dst", OperandPattern::Var(0))
+//!             .operand("global", OperandPattern::Exact(Operand::Global(RefGlobal(7)))),
+//!     )
+//!     .within(
+//!         3,
+//!         OpPattern::new("Call2")
+//!             .operand("fun", OperandPattern::Exact(Operand::Fun(RefFun(42))))
+//!             .operand("arg0", OperandPattern::Var(0)),
+//!     );
+//! for m in pattern.find(&code) {
+//!     println!("fn@{} at {:?}", m.fun.0, m.positions);
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::opcodes::Operand;
+use crate::types::{Function, RefFun, Reg};
+use crate::{Bytecode, Opcode};
+
+/// Constraint on an operand
+#[derive(Debug, Clone, PartialEq)]
+pub enum OperandPattern {
+    Any,
+    /// A register bound to a variable, every operand with the same variable must be the same register
+    Var(usize),
+    /// This exact operand
+    Exact(Operand<'static>),
+}
+
+/// Constraint on a single instruction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpPattern {
+    /// Name of the opcode, any opcode if None
+    pub opcode: Option<String>,
+    /// Constraints on the operands by name, operands not listed can be anything
+    pub operands: Vec<(String, OperandPattern)>,
+}
+
+impl OpPattern {
+    pub fn new(opcode: &str) -> Self {
+        Self {
+            opcode: Some(opcode.to_string()),
+            operands: Vec::new(),
+        }
+    }
+
+    /// Matches any instruction
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    pub fn operand(mut self, name: &str, pattern: OperandPattern) -> Self {
+        self.operands.push((name.to_string(), pattern));
+        self
+    }
+
+    /// Check an instruction, binding new variables
+    fn matches(&self, op: &Opcode, vars: &mut HashMap<usize, Reg>) -> bool {
+        if matches!(&self.opcode, Some(name) if name != op.name()) {
+            return false;
+        }
+        let operands = op.operands();
+        self.operands.iter().all(|(name, pattern)| {
+            let Some(&(_, operand)) = operands.iter().find(|(n, _)| n == name) else {
+                return false;
+            };
+            match pattern {
+                OperandPattern::Any => true,
+                OperandPattern::Exact(exact) => operand == *exact,
+                OperandPattern::Var(var) => match operand {
+                    Operand::Reg(reg) => *vars.entry(*var).or_insert(reg) == reg,
+                    _ => false,
+                },
+            }
+        })
+    }
+}
+
+/// A sequence of instruction patterns
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pattern {
+    /// Instruction patterns with the maximum distance from the previous match
+    pub steps: Vec<(usize, OpPattern)>,
+}
+
+/// Positions of the instructions matching each step of a pattern
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Match {
+    pub fun: RefFun,
+    pub positions: Vec<usize>,
+}
+
+impl Pattern {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next instruction must match
+    pub fn then(self, op: OpPattern) -> Self {
+        self.within(1, op)
+    }
+
+    /// An instruction in the next `distance` instructions must match
+    pub fn within(mut self, distance: usize, op: OpPattern) -> Self {
+        self.steps.push((distance.max(1), op));
+        self
+    }
+
+    /// Matches in every function of the bytecode
+    pub fn find(&self, code: &Bytecode) -> Vec<Match> {
+        code.functions
+            .iter()
+            .flat_map(|f| self.find_in(f))
+            .collect()
+    }
+
+    /// Matches in a function, at most one per start position
+    pub fn find_in(&self, f: &Function) -> Vec<Match> {
+        let Some((_, first)) = self.steps.first() else {
+            return Vec::new();
+        };
+        let mut matches = Vec::new();
+        for (pos, op) in f.ops.iter().enumerate() {
+            let mut vars = HashMap::new();
+            if !first.matches(op, &mut vars) {
+                continue;
+            }
+            let mut positions = vec![pos];
+            if self.match_from(f, 1, &vars, &mut positions) {
+                matches.push(Match {
+                    fun: f.findex,
+                    positions,
+                });
+            }
+        }
+        matches
+    }
+
+    /// Match the remaining steps after the last position, backtracking on the variables bindings
+    fn match_from(
+        &self,
+        f: &Function,
+        step: usize,
+        vars: &HashMap<usize, Reg>,
+        positions: &mut Vec<usize>,
+    ) -> bool {
+        let Some((distance, op)) = self.steps.get(step) else {
+            return true;
+        };
+        let last = *positions.last().unwrap();
+        let end = (last + distance + 1).min(f.ops.len());
+        for pos in last + 1..end {
+            let mut vars = vars.clone();
+            if op.matches(&f.ops[pos], &mut vars) {
+                positions.push(pos);
+                if self.match_from(f, step + 1, &vars, positions) {
+                    return true;
+                }
+                positions.pop();
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::pattern::{Match, OpPattern, OperandPattern, Pattern};
+    use crate::opcodes::Operand;
+    use crate::testing::sample;
+    use crate::types::RefFun;
+
+    #[test]
+    fn test_pattern() {
+        let code = sample();
+        let rets = Pattern::new().then(OpPattern::new("Ret")).find(&code);
+        assert_eq!(
+            rets.iter().map(|m| m.fun.0).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+
+        // An int loaded in a register then passed as the argument of a call
+        let pattern = |distance| {
+            Pattern::new()
+                .then(OpPattern::new("Int").operand("dst", OperandPattern::Var(0)))
+                .within(
+                    distance,
+                    OpPattern::new("Call1").operand("arg0", OperandPattern::Var(0)),
+                )
+        };
+        assert_eq!(
+            pattern(3).find(&code),
+            vec![Match {
+                fun: RefFun(0),
+                positions: vec![1, 4],
+            }]
+        );
+        assert!(pattern(2).find(&code).is_empty());
+
+        let call =
+            OpPattern::new("Call1").operand("fun", OperandPattern::Exact(Operand::Fun(RefFun(2))));
+        let found = Pattern::new().then(call).find_in(&code.functions[0]);
+        assert_eq!(found[0].positions, vec![4]);
+    }
+}
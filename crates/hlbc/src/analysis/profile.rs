@@ -0,0 +1,222 @@
+//! Sample counts from the HashLink profiler attached to functions.
+//!
+//! Two inputs are supported :
+//! - the binary dump written by `hl --profile` (`hlprofile.dump`), see [ProfileData::parse_dump]
+//! - folded stacks, one stack per line with the frames separated by `;` and the sample count at the end
+//!   (`Main.main;Game.update;Game.render 42`), see [ProfileData::parse_folded]
+//!
+//! Frames are symbols like `pack.Class.method(File.hx:12)`, they are resolved to functions with their qualified name.
+//! `fn@<findex>` is also accepted.
+
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::types::RefFun;
+use crate::{Bytecode, Error, Result, Str};
+
+/// Sample counts per function
+#[derive(Debug, Clone, Default)]
+pub struct ProfileData {
+    /// Total number of samples
+    pub samples: u64,
+    /// Samples with the function at the top of the stack
+    pub self_samples: HashMap<RefFun, u64>,
+    /// Samples with the function anywhere in the stack, counted once per sample
+    pub total_samples: HashMap<RefFun, u64>,
+    /// Samples of the frames which couldn't be resolved to a function, by symbol
+    pub unresolved: HashMap<Str, u64>,
+}
+
+impl ProfileData {
+    /// Parse a binary profiler dump. The layout read is :
+    /// - `PROF` then the version as an i32
+    /// - samples until the end : time (f64), thread id (i32), number of frames (i32) then each frame from the top
+    ///   of the stack. A frame is a symbol id (i32), an id of -1 introduces a new symbol with its length (i32) and its
+    ///   UTF-16 characters, it takes the next id.
+    pub fn parse_dump(code: &Bytecode, mut r: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != *b"PROF" {
+            return Err(Error::MalformedBytecode(
+                "not a HashLink profiler dump".to_string(),
+            ));
+        }
+        let _version = r.read_i32::<LittleEndian>()?;
+        let mut symbols: Vec<Str> = Vec::new();
+        let mut data = Self::default();
+        loop {
+            // A dump can be truncated if the program has been killed
+            match r.read_f64::<LittleEndian>() {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let _thread = r.read_i32::<LittleEndian>()?;
+            let count = r.read_i32::<LittleEndian>()?;
+            let mut stack = Vec::with_capacity(count.max(0) as usize);
+            for _ in 0..count {
+                let id = r.read_i32::<LittleEndian>()?;
+                let symbol = if id == -1 {
+                    let len = r.read_i32::<LittleEndian>()?.max(0) as usize;
+                    let chars = (0..len)
+                        .map(|_| r.read_u16::<LittleEndian>())
+                        .collect::<std::io::Result<Vec<_>>>()?;
+                    symbols.push(Str::from(String::from_utf16_lossy(&chars)));
+                    symbols.last().unwrap().clone()
+                } else {
+                    symbols.get(id as usize).cloned().ok_or_else(|| {
+                        Error::MalformedBytecode(format!("unknown profiler symbol {id}"))
+                    })?
+                };
+                stack.push(symbol);
+            }
+            data.add_stack(code, &stack, 1);
+        }
+        Ok(data)
+    }
+
+    /// Parse folded stacks, frames are listed from the bottom of the stack like most flamegraph tools do
+    pub fn parse_folded(code: &Bytecode, text: &str) -> Result<Self> {
+        let mut data = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (stack, count) = line
+                .rsplit_once(char::is_whitespace)
+                .and_then(|(stack, count)| Some((stack, count.parse::<u64>().ok()?)))
+                .ok_or_else(|| {
+                    Error::MalformedBytecode(format!("invalid folded stack at line {}", i + 1))
+                })?;
+            let stack: Vec<Str> = stack.split(';').rev().map(Str::from).collect();
+            data.add_stack(code, &stack, count);
+        }
+        Ok(data)
+    }
+
+    /// Add `count` samples of a stack, listed from the top
+    pub fn add_stack(&mut self, code: &Bytecode, stack: &[Str], count: u64) {
+        self.samples += count;
+        let mut seen = Vec::with_capacity(stack.len());
+        for (depth, symbol) in stack.iter().enumerate() {
+            let Some(f) = resolve_symbol(code, symbol) else {
+                if depth == 0 {
+                    *self.unresolved.entry(symbol.clone()).or_default() += count;
+                }
+                continue;
+            };
+            if depth == 0 {
+                *self.self_samples.entry(f).or_default() += count;
+            }
+            // Recursive calls appear multiple times in the same stack
+            if !seen.contains(&f) {
+                seen.push(f);
+                *self.total_samples.entry(f).or_default() += count;
+            }
+        }
+    }
+
+    pub fn self_count(&self, f: RefFun) -> u64 {
+        self.self_samples.get(&f).copied().unwrap_or(0)
+    }
+
+    pub fn total_count(&self, f: RefFun) -> u64 {
+        self.total_samples.get(&f).copied().unwrap_or(0)
+    }
+
+    /// Fraction of the samples spent in the function itself, between 0 and 1
+    pub fn hotness(&self, f: RefFun) -> f32 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.self_count(f) as f32 / self.samples as f32
+        }
+    }
+
+    /// Functions sorted by decreasing self samples
+    pub fn hottest(&self) -> Vec<(RefFun, u64)> {
+        let mut hottest: Vec<_> = self.self_samples.iter().map(|(&f, &c)| (f, c)).collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hottest
+    }
+}
+
+/// Find the function of a profiler frame
+pub fn resolve_symbol(code: &Bytecode, symbol: &str) -> Option<RefFun> {
+    let symbol = symbol.trim();
+    if let Some(findex) = symbol.strip_prefix("fn@") {
+        return findex.parse().ok().map(RefFun);
+    }
+    // Remove the location
+    let name = symbol
+        .split_once('(')
+        .map_or(symbol, |(name, _)| name)
+        .trim();
+    // Static methods are in the `$Class` type
+    let name = name.replace('$', "");
+    code.function_by_qualified_name(&name).map(|f| f.findex)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use crate::analysis::profile::ProfileData;
+    use crate::testing::sample;
+    use crate::types::RefFun;
+    use crate::Str;
+
+    #[test]
+    fn test_parse_folded() -> crate::Result<()> {
+        let code = sample();
+        let data = ProfileData::parse_folded(
+            &code,
+            "# comment
+            $Main.main;Point.length(Main.hx:3) 3
+            $Main.main;$Main.helper;$Main.helper 2
+            fn@0;unknown 1",
+        )?;
+        assert_eq!(data.samples, 6);
+        assert_eq!(data.self_count(RefFun(1)), 3);
+        assert_eq!(data.self_count(RefFun(2)), 2);
+        assert_eq!(data.total_count(RefFun(2)), 2);
+        assert_eq!(data.self_count(RefFun(0)), 0);
+        assert_eq!(data.total_count(RefFun(0)), 6);
+        assert_eq!(data.unresolved[&Str::from("unknown")], 1);
+        assert_eq!(data.hottest(), vec![(RefFun(1), 3), (RefFun(2), 2)]);
+        assert_eq!(data.hotness(RefFun(1)), 0.5);
+        assert!(ProfileData::parse_folded(&code, "main").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dump() -> crate::Result<()> {
+        let code = sample();
+        let main = RefFun(0);
+        let mut dump = b"PROF".to_vec();
+        dump.write_i32::<LittleEndian>(1)?;
+        for i in 0..2 {
+            dump.write_f64::<LittleEndian>(i as f64)?;
+            dump.write_i32::<LittleEndian>(0)?;
+            dump.write_i32::<LittleEndian>(1)?;
+            if i == 0 {
+                let symbol: Vec<u16> = "Main.main(Main.hx:8)".encode_utf16().collect();
+                dump.write_i32::<LittleEndian>(-1)?;
+                dump.write_i32::<LittleEndian>(symbol.len() as i32)?;
+                for c in symbol {
+                    dump.write_u16::<LittleEndian>(c)?;
+                }
+            } else {
+                dump.write_i32::<LittleEndian>(0)?;
+            }
+        }
+        let data = ProfileData::parse_dump(&code, dump.as_slice())?;
+        assert_eq!(data.samples, 2);
+        assert_eq!(data.self_count(main), 2);
+        assert!(ProfileData::parse_dump(&code, &b"NOPE"[..]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+//! Provenance of constant strings.
+//!
+//! Follows a string from the places it is loaded, through registers, function calls and returns, globals and object
+//! fields, up to the calls of given sink functions (e.g. a native drawing text). This answers the question "which code
+//! can display this text" without chaining cross references by hand.
+//!
+//! The analysis is an over approximation : instructions are scanned linearly without considering the control flow,
+//! a register stays tainted once a value derived from the string is assigned to it. Only direct calls are followed,
+//! the result of a native call is considered derived from its arguments.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::types::{FunPtr, Function, RefField, RefFun, RefGlobal, RefString, RefType, Reg, Type};
+use crate::{Bytecode, Opcode, Resolve};
+
+/// A step in the flow of a string
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Flow {
+    /// String loaded as a code constant
+    Constant { fun: RefFun, pos: usize },
+    /// String is a field of the constant initializing a global
+    GlobalConstant { global: RefGlobal, field: usize },
+    /// Passed as an argument to a function
+    Argument {
+        fun: RefFun,
+        pos: usize,
+        callee: RefFun,
+        arg: usize,
+    },
+    /// Stored into a global
+    StoreGlobal {
+        fun: RefFun,
+        pos: usize,
+        global: RefGlobal,
+    },
+    /// Stored into an object field
+    StoreField {
+        fun: RefFun,
+        pos: usize,
+        ty: RefType,
+        field: RefField,
+    },
+    /// Read back from a global or an object field
+    Load { fun: RefFun, pos: usize },
+    /// Passed as an argument to a sink
+    Sink {
+        fun: RefFun,
+        pos: usize,
+        sink: RefFun,
+        arg: usize,
+    },
+}
+
+/// A node of the provenance tree, children are the next steps of the flow
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProvenanceNode {
+    pub flow: Flow,
+    pub children: Vec<ProvenanceNode>,
+}
+
+impl ProvenanceNode {
+    /// true if this flow reaches a sink
+    pub fn reaches_sink(&self) -> bool {
+        matches!(self.flow, Flow::Sink { .. }) || self.children.iter().any(|c| c.reaches_sink())
+    }
+}
+
+/// Build the provenance tree of a string, roots are the places where the string is loaded.
+///
+/// When `sinks` isn't empty, only the flows reaching one of the sinks are kept.
+/// `max_depth` limits the number of calls followed.
+pub fn string_provenance(
+    code: &Bytecode,
+    s: RefString,
+    sinks: &[RefFun],
+    max_depth: usize,
+) -> Vec<ProvenanceNode> {
+    let mut tracer = Tracer {
+        code,
+        sinks,
+        max_depth,
+        returns: HashMap::new(),
+        globals: HashSet::new(),
+        fields: HashSet::new(),
+    };
+
+    let mut roots = Vec::new();
+    for f in &code.functions {
+        for (pos, op) in f.ops.iter().enumerate() {
+            if let Opcode::String { dst, ptr } = *op {
+                if ptr == s {
+                    roots.push(ProvenanceNode {
+                        flow: Flow::Constant { fun: f.findex, pos },
+                        children: tracer.function(f, pos + 1, dst, 0).0,
+                    });
+                }
+            }
+        }
+    }
+    for c in code.constants.iter().flatten() {
+        let Some(obj) = code[code[c.global]].get_type_obj() else {
+            continue;
+        };
+        for (i, (&value, field)) in c.fields.iter().zip(&obj.fields).enumerate() {
+            if value == s.0 && matches!(code[field.t], Type::Bytes) {
+                roots.push(ProvenanceNode {
+                    flow: Flow::GlobalConstant {
+                        global: c.global,
+                        field: i,
+                    },
+                    children: tracer.field(code[c.global], RefField(i), 0),
+                });
+            }
+        }
+    }
+
+    if !sinks.is_empty() {
+        prune(&mut roots);
+    }
+    roots
+}
+
+/// Only keep the nodes reaching a sink
+fn prune(nodes: &mut Vec<ProvenanceNode>) {
+    nodes.retain(|n| n.reaches_sink());
+    for n in nodes {
+        prune(&mut n.children);
+    }
+}
+
+struct Tracer<'a> {
+    code: &'a Bytecode,
+    sinks: &'a [RefFun],
+    max_depth: usize,
+    /// Functions already visited from an argument with whether they return the value
+    returns: HashMap<(RefFun, usize), bool>,
+    globals: HashSet<RefGlobal>,
+    fields: HashSet<(RefType, RefField)>,
+}
+
+impl Tracer<'_> {
+    /// Follow a register from a position in a function.
+    /// Returns the flows and whether the value can be returned.
+    fn function(
+        &mut self,
+        f: &Function,
+        start: usize,
+        reg: Reg,
+        depth: usize,
+    ) -> (Vec<ProvenanceNode>, bool) {
+        let mut tainted = vec![false; f.regs.len()];
+        let is = |tainted: &[bool], r: Reg| tainted.get(r.0 as usize).copied().unwrap_or(false);
+        let set = |tainted: &mut [bool], r: Reg| {
+            if let Some(t) = tainted.get_mut(r.0 as usize) {
+                *t = true;
+            }
+        };
+        set(&mut tainted, reg);
+
+        let mut nodes = Vec::new();
+        let mut returned = false;
+        for (pos, op) in f.ops.iter().enumerate().skip(start) {
+            match op {
+                Opcode::Mov { dst, src }
+                | Opcode::ToDyn { dst, src }
+                | Opcode::ToVirtual { dst, src }
+                | Opcode::SafeCast { dst, src }
+                | Opcode::UnsafeCast { dst, src }
+                | Opcode::Ref { dst, src }
+                | Opcode::Unref { dst, src }
+                    if is(&tainted, *src) =>
+                {
+                    set(&mut tainted, *dst)
+                }
+                Opcode::Call1 { dst, fun, arg0 } => {
+                    let args = [*arg0];
+                    self.call(f, pos, *fun, *dst, &args, &mut tainted, &mut nodes, depth)
+                }
+                Opcode::Call2 {
+                    dst,
+                    fun,
+                    arg0,
+                    arg1,
+                } => {
+                    let args = [*arg0, *arg1];
+                    self.call(f, pos, *fun, *dst, &args, &mut tainted, &mut nodes, depth)
+                }
+                Opcode::Call3 {
+                    dst,
+                    fun,
+                    arg0,
+                    arg1,
+                    arg2,
+                } => {
+                    let args = [*arg0, *arg1, *arg2];
+                    self.call(f, pos, *fun, *dst, &args, &mut tainted, &mut nodes, depth)
+                }
+                Opcode::Call4 {
+                    dst,
+                    fun,
+                    arg0,
+                    arg1,
+                    arg2,
+                    arg3,
+                } => {
+                    let args = [*arg0, *arg1, *arg2, *arg3];
+                    self.call(f, pos, *fun, *dst, &args, &mut tainted, &mut nodes, depth)
+                }
+                Opcode::CallN { dst, fun, args } => {
+                    self.call(f, pos, *fun, *dst, args, &mut tainted, &mut nodes, depth)
+                }
+                Opcode::Ret { ret } => returned |= is(&tainted, *ret),
+                Opcode::SetGlobal { global, src } if is(&tainted, *src) => {
+                    nodes.push(ProvenanceNode {
+                        flow: Flow::StoreGlobal {
+                            fun: f.findex,
+                            pos,
+                            global: *global,
+                        },
+                        children: self.global(*global, depth),
+                    });
+                }
+                Opcode::SetField { obj, field, src } if is(&tainted, *src) => {
+                    nodes.push(self.store_field(f, pos, f[*obj], *field, depth));
+                }
+                Opcode::SetThis { field, src } if is(&tainted, *src) => {
+                    nodes.push(self.store_field(f, pos, f.regs[0], *field, depth));
+                }
+                _ => {}
+            }
+        }
+        (nodes, returned)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn call(
+        &mut self,
+        f: &Function,
+        pos: usize,
+        fun: RefFun,
+        dst: Reg,
+        args: &[Reg],
+        tainted: &mut [bool],
+        nodes: &mut Vec<ProvenanceNode>,
+        depth: usize,
+    ) {
+        for (arg, &r) in args.iter().enumerate() {
+            if !tainted.get(r.0 as usize).copied().unwrap_or(false) {
+                continue;
+            }
+            let returned = if self.sinks.contains(&fun) {
+                nodes.push(ProvenanceNode {
+                    flow: Flow::Sink {
+                        fun: f.findex,
+                        pos,
+                        sink: fun,
+                        arg,
+                    },
+                    children: Vec::new(),
+                });
+                false
+            } else {
+                match self.code.get(fun) {
+                    FunPtr::Native(_) => true,
+                    FunPtr::Fun(callee) => {
+                        // A function already followed only shows its flows the first time
+                        let (children, returned) =
+                            if let Some(&returned) = self.returns.get(&(fun, arg)) {
+                                (Vec::new(), returned)
+                            } else if depth < self.max_depth {
+                                self.returns.insert((fun, arg), false);
+                                let (children, returned) =
+                                    self.function(callee, 0, Reg(arg as u32), depth + 1);
+                                self.returns.insert((fun, arg), returned);
+                                (children, returned)
+                            } else {
+                                continue;
+                            };
+                        nodes.push(ProvenanceNode {
+                            flow: Flow::Argument {
+                                fun: f.findex,
+                                pos,
+                                callee: fun,
+                                arg,
+                            },
+                            children,
+                        });
+                        returned
+                    }
+                }
+            };
+            if returned {
+                if let Some(t) = tainted.get_mut(dst.0 as usize) {
+                    *t = true;
+                }
+            }
+        }
+    }
+
+    fn store_field(
+        &mut self,
+        f: &Function,
+        pos: usize,
+        ty: RefType,
+        field: RefField,
+        depth: usize,
+    ) -> ProvenanceNode {
+        ProvenanceNode {
+            flow: Flow::StoreField {
+                fun: f.findex,
+                pos,
+                ty,
+                field,
+            },
+            children: self.field(ty, field, depth),
+        }
+    }
+
+    /// Every read of a global
+    fn global(&mut self, global: RefGlobal, depth: usize) -> Vec<ProvenanceNode> {
+        if !self.globals.insert(global) {
+            return Vec::new();
+        }
+        self.loads(depth, |_, op| match *op {
+            Opcode::GetGlobal { dst, global: g } if g == global => Some(dst),
+            _ => None,
+        })
+    }
+
+    /// Every read of an object field
+    fn field(&mut self, ty: RefType, field: RefField, depth: usize) -> Vec<ProvenanceNode> {
+        if !self.fields.insert((ty, field)) {
+            return Vec::new();
+        }
+        self.loads(depth, |f, op| match *op {
+            Opcode::Field {
+                dst,
+                obj,
+                field: fi,
+            } if fi == field && f[obj] == ty => Some(dst),
+            Opcode::GetThis { dst, field: fi } if fi == field && f.regs[0] == ty => Some(dst),
+            _ => None,
+        })
+    }
+
+    /// Follow every register loaded by matching instructions
+    fn loads(
+        &mut self,
+        depth: usize,
+        load: impl Fn(&Function, &Opcode) -> Option<Reg>,
+    ) -> Vec<ProvenanceNode> {
+        let code = self.code;
+        let mut nodes = Vec::new();
+        for f in &code.functions {
+            for (pos, op) in f.ops.iter().enumerate() {
+                if let Some(dst) = load(f, op) {
+                    nodes.push(ProvenanceNode {
+                        flow: Flow::Load { fun: f.findex, pos },
+                        children: self.function(f, pos + 1, dst, depth).0,
+                    });
+                }
+            }
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::provenance::{string_provenance, Flow, ProvenanceNode};
+    use crate::testing::{bytecode, fun};
+    use crate::types::{RefFun, RefGlobal, RefString, RefType, Type};
+
+    fn node(flow: Flow, children: Vec<ProvenanceNode>) -> ProvenanceNode {
+        ProvenanceNode { flow, children }
+    }
+
+    #[test]
+    fn test_provenance() {
+        // A string stored in a global and passed through a function to the sink fn@2
+        let mut code = bytecode(
+            &["", "main", "msg"],
+            vec![Type::Void, Type::Bytes, fun(&[], 0), fun(&[1], 0)],
+            "
+            .fun fn@0 type@2
+            .reg type@1
+            .reg type@0
+                String reg0 string@2
+                SetGlobal global@0 reg0
+                Call1 reg1 fn@1 reg0
+                Ret reg1
+            .end
+            .fun fn@1 type@3
+            .reg type@1
+            .reg type@0
+                Call1 reg1 fn@2 reg0
+                Ret reg1
+            .end
+            .fun fn@2 type@3
+            .reg type@1
+            .reg type@0
+                Ret reg1
+            .end
+            .fun fn@3 type@2
+            .reg type@1
+            .reg type@0
+                GetGlobal reg0 global@0
+                Call1 reg1 fn@2 reg0
+                Ret reg1
+            .end",
+        );
+        code.globals = vec![RefType(1)];
+        let sink = |fun, pos| Flow::Sink {
+            fun: RefFun(fun),
+            pos,
+            sink: RefFun(2),
+            arg: 0,
+        };
+        let arg = |fun, pos, callee| Flow::Argument {
+            fun: RefFun(fun),
+            pos,
+            callee: RefFun(callee),
+            arg: 0,
+        };
+        let store = node(
+            Flow::StoreGlobal {
+                fun: RefFun(0),
+                pos: 1,
+                global: RefGlobal(0),
+            },
+            vec![node(
+                Flow::Load {
+                    fun: RefFun(3),
+                    pos: 0,
+                },
+                vec![node(arg(3, 1, 2), vec![])],
+            )],
+        );
+        let constant = Flow::Constant {
+            fun: RefFun(0),
+            pos: 0,
+        };
+        assert_eq!(
+            string_provenance(&code, RefString(2), &[], 4),
+            vec![node(
+                constant,
+                vec![
+                    store.clone(),
+                    node(arg(0, 2, 1), vec![node(arg(1, 0, 2), vec![])])
+                ]
+            )]
+        );
+
+        // The paths to the sink
+        let mut store = store;
+        store.children[0].children[0] = node(sink(3, 1), vec![]);
+        assert_eq!(
+            string_provenance(&code, RefString(2), &[RefFun(2)], 4),
+            vec![node(
+                constant,
+                vec![store, node(arg(0, 2, 1), vec![node(sink(1, 0), vec![])])]
+            )]
+        );
+        // Not deep enough to go through fn@1
+        assert_eq!(
+            string_provenance(&code, RefString(2), &[RefFun(2)], 0)[0]
+                .children
+                .len(),
+            1
+        );
+        assert!(string_provenance(&code, RefString(1), &[], 4).is_empty());
+    }
+}
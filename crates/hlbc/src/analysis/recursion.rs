@@ -0,0 +1,169 @@
+//! Detection of recursive functions with the strongly connected components of the callgraph.
+
+use std::collections::HashSet;
+
+use crate::types::RefFun;
+use crate::{Bytecode, Opcode};
+
+/// Recursive functions of a bytecode
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RecursionReport {
+    /// Functions calling themselves directly
+    pub self_recursive: Vec<RefFun>,
+    /// Groups of mutually recursive functions, each group has at least 2 functions
+    pub mutual: Vec<Vec<RefFun>>,
+}
+
+impl RecursionReport {
+    /// Every recursive function, directly or through other functions
+    pub fn recursive(&self) -> HashSet<RefFun> {
+        self.self_recursive
+            .iter()
+            .chain(self.mutual.iter().flatten())
+            .copied()
+            .collect()
+    }
+
+    pub fn is_recursive(&self, f: RefFun) -> bool {
+        self.self_recursive.contains(&f) || self.mutual.iter().any(|scc| scc.contains(&f))
+    }
+}
+
+/// Functions called by a function, through direct calls, closures and method calls on known types
+fn callees(code: &Bytecode, f: &crate::types::Function) -> Vec<RefFun> {
+    let mut callees: Vec<RefFun> = f.find_fun_refs().map(|(_, _, fun)| fun).collect();
+    for op in &f.ops {
+        let (obj, field) = match op {
+            Opcode::CallMethod { args, field, .. } if !args.is_empty() => (f[args[0]], field),
+            Opcode::CallThis { field, .. } if !f.regs.is_empty() => (f.regs[0], field),
+            _ => continue,
+        };
+        if let Some(proto) = code[obj].get_type_obj().and_then(|o| o.protos.get(field.0)) {
+            callees.push(proto.findex);
+        }
+    }
+    callees
+}
+
+/// Find the recursive functions with Tarjan's strongly connected components algorithm
+pub fn find_recursion(code: &Bytecode) -> RecursionReport {
+    let n = code.findex_max();
+    let mut succs = vec![Vec::new(); n];
+    for f in &code.functions {
+        succs[f.findex.0] = callees(code, f)
+            .into_iter()
+            .map(|fun| fun.0)
+            .filter(|&fun| fun < n)
+            .collect();
+    }
+
+    let mut report = RecursionReport::default();
+    for (f, s) in succs.iter().enumerate() {
+        if s.contains(&f) {
+            report.self_recursive.push(RefFun(f));
+        }
+    }
+
+    // Iterative Tarjan, the callgraph can be deep
+    let mut index = vec![usize::MAX; n];
+    let mut lowlink = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut next = 0;
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        let mut work = vec![(root, 0)];
+        index[root] = next;
+        lowlink[root] = next;
+        next += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        while let Some(&mut (v, ref mut i)) = work.last_mut() {
+            if let Some(&w) = succs[v].get(*i) {
+                *i += 1;
+                if index[w] == usize::MAX {
+                    index[w] = next;
+                    lowlink[w] = next;
+                    next += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    lowlink[v] = lowlink[v].min(index[w]);
+                }
+                continue;
+            }
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                lowlink[parent] = lowlink[parent].min(lowlink[v]);
+            }
+            if lowlink[v] == index[v] {
+                let mut scc = Vec::new();
+                while let Some(w) = stack.pop() {
+                    on_stack[w] = false;
+                    scc.push(RefFun(w));
+                    if w == v {
+                        break;
+                    }
+                }
+                if scc.len() > 1 {
+                    scc.sort_unstable();
+                    report.mutual.push(scc);
+                }
+            }
+        }
+    }
+    report.mutual.sort_unstable();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::recursion::find_recursion;
+    use crate::testing::{bytecode, fun, shapes};
+    use crate::types::{RefFun, Type};
+
+    #[test]
+    fn test_find_recursion() {
+        // fn@1 and fn@2 call each other, fn@3 calls itself
+        let code = bytecode(
+            &[""],
+            vec![Type::Void, fun(&[], 0)],
+            "
+            .fun fn@0 type@1
+            .reg type@0
+                Call0 reg0 fn@1
+                Ret reg0
+            .end
+            .fun fn@1 type@1
+            .reg type@0
+                Call0 reg0 fn@2
+                Ret reg0
+            .end
+            .fun fn@2 type@1
+            .reg type@0
+                Call0 reg0 fn@1
+                Ret reg0
+            .end
+            .fun fn@3 type@1
+            .reg type@0
+                Call0 reg0 fn@3
+                Ret reg0
+            .end",
+        );
+        let mut report = find_recursion(&code);
+        assert_eq!(report.self_recursive, vec![RefFun(3)]);
+        report.mutual[0].sort();
+        assert_eq!(report.mutual, vec![vec![RefFun(1), RefFun(2)]]);
+        assert!(report.is_recursive(RefFun(2)));
+        assert!(!report.is_recursive(RefFun(0)));
+        assert_eq!(report.recursive().len(), 3);
+
+        // A method calling itself through this
+        let report = find_recursion(&shapes());
+        assert_eq!(report.self_recursive, vec![RefFun(1)]);
+        assert!(report.mutual.is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+//! Identification of library functions in stripped bytecode, like IDA FLIRT signatures.
+//!
+//! Signatures are generated from a bytecode compiled with debug names (e.g. a sample using the Haxe std or heaps.io)
+//! and map the [Fingerprint] of each function to its qualified name. Applied to an unknown bytecode, every function
+//! with a known fingerprint is renamed. Small functions are skipped since their body is too common to identify them.
+//!
+//! Signatures are stored as text, one function per line : `<fingerprint> <instructions> <pack.Class.method>`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::analysis::fingerprint::Fingerprint;
+use crate::rename::{Renames, Symbol};
+use crate::types::{Function, RefFun};
+use crate::{Bytecode, Error, Result, Str};
+
+/// Functions with fewer instructions are not signed by default
+pub const MIN_OPS: usize = 8;
+
+/// Known functions by fingerprint
+#[derive(Debug, Clone, Default)]
+pub struct SignatureSet {
+    /// Qualified names with the number of instructions. A fingerprint can have multiple names, in which case it can't
+    /// identify a function.
+    sigs: HashMap<Fingerprint, (usize, Vec<Str>)>,
+}
+
+impl SignatureSet {
+    /// Sign every function of a bytecode with at least [MIN_OPS] instructions and matching the filter
+    pub fn generate(code: &Bytecode, filter: impl Fn(&Function) -> bool) -> Self {
+        let mut set = Self::default();
+        for f in &code.functions {
+            if f.ops.len() < MIN_OPS || f.is_compiler_generated(code) || !filter(f) {
+                continue;
+            }
+            set.add(
+                f.fingerprint(code),
+                f.ops.len(),
+                f.findex.qualified_name(code),
+            );
+        }
+        set
+    }
+
+    pub fn add(&mut self, fp: Fingerprint, ops: usize, name: Str) {
+        let (_, names) = self.sigs.entry(fp).or_insert((ops, Vec::new()));
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    /// Add the signatures of another set
+    pub fn merge(&mut self, other: &SignatureSet) {
+        for (&fp, (ops, names)) in &other.sigs {
+            for name in names {
+                self.add(fp, *ops, name.clone());
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sigs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sigs.is_empty()
+    }
+
+    /// The name of the function with this fingerprint, if there is only one
+    pub fn get(&self, fp: Fingerprint) -> Option<&Str> {
+        match self.sigs.get(&fp) {
+            Some((_, names)) if names.len() == 1 => names.first(),
+            _ => None,
+        }
+    }
+
+    /// Functions of a bytecode identified by a signature
+    pub fn identify(&self, code: &Bytecode) -> Vec<(RefFun, Str)> {
+        code.functions
+            .iter()
+            .filter(|f| f.ops.len() >= MIN_OPS && !f.is_compiler_generated(code))
+            .filter_map(|f| Some((f.findex, self.get(f.fingerprint(code))?.clone())))
+            .collect()
+    }
+
+    /// Rename the identified functions and their class, elements already renamed are left untouched.
+    /// Returns the number of renamed functions.
+    pub fn apply(&self, code: &Bytecode, renames: &Renames) -> usize {
+        let mut count = 0;
+        for (f, name) in self.identify(code) {
+            let (class, method) = match name.rsplit_once('.') {
+                Some((class, method)) => (Some(class), method),
+                None => (None, &*name),
+            };
+            let symbol = Symbol::Function(f);
+            if renames.get(symbol).is_none()
+                && &*f.name(code) != method
+                && renames.set(symbol, method).is_ok()
+            {
+                count += 1;
+            }
+            let parent = f.as_fn(code).and_then(|f| f.parent);
+            if let (Some(class), Some(parent)) = (class, parent) {
+                if let Some(symbol) = Symbol::of_type(code, parent) {
+                    let current = code[parent].get_type_obj().map(|o| o.name(code));
+                    let current = current.as_deref().map(|c| c.trim_start_matches('$'));
+                    if renames.get(symbol).is_none() && current != Some(class) {
+                        let _ = renames.set(symbol, class);
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Serialize the signatures, sorted by name
+    pub fn to_text(&self) -> String {
+        let mut lines: Vec<_> = self
+            .sigs
+            .iter()
+            .flat_map(|(fp, (ops, names))| names.iter().map(move |n| (n, *fp, *ops)))
+            .collect();
+        lines.sort_by(|a, b| (&**a.0, a.1).cmp(&(&**b.0, b.1)));
+        let mut out = String::new();
+        for (name, fp, ops) in lines {
+            let _ = writeln!(out, "{fp} {ops} {name}");
+        }
+        out
+    }
+
+    /// Parse signatures serialized with [SignatureSet::to_text]
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut set = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (fp, ops, name) = parse_line(line).ok_or_else(|| {
+                Error::MalformedBytecode(format!("invalid signature at line {}", i + 1))
+            })?;
+            set.add(fp, ops, name);
+        }
+        Ok(set)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(path, self.to_text())?)
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Fingerprint, usize, Str)> {
+    let mut parts = line.splitn(3, ' ');
+    let fp = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let ops = parts.next()?.parse().ok()?;
+    let name = parts.next()?.trim();
+    Some((Fingerprint(fp), ops, Str::from(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::signatures::SignatureSet;
+    use crate::rename::{Renames, Symbol};
+    use crate::testing::{reload, sample};
+    use crate::types::{RefFun, RefType};
+    use crate::Str;
+
+    #[test]
+    fn test_signatures() -> crate::Result<()> {
+        // The entrypoint is never signed, it is usually a generated function calling main
+        let mut code = sample();
+        code.entrypoint = RefFun(3);
+        // Only main is big enough to be signed
+        let set = SignatureSet::generate(&code, |_| true);
+        assert_eq!(set.len(), 1);
+        let text = set.to_text();
+        assert!(text.ends_with(" 8 Main.main\n"), "{text}");
+        let parsed = SignatureSet::parse(&text)?;
+        assert_eq!(parsed.to_text(), text);
+        assert!(SignatureSet::parse("nothex 1 Foo.bar").is_err());
+
+        // The same program with obfuscated names
+        let mut stripped = sample();
+        stripped.strings[8] = Str::from("a");
+        stripped.strings[4] = Str::from("$B");
+        stripped.entrypoint = RefFun(3);
+        let stripped = reload(&stripped);
+        assert_eq!(
+            parsed.identify(&stripped),
+            vec![(RefFun(0), Str::from("Main.main"))]
+        );
+        let renames = Renames::default();
+        assert_eq!(parsed.apply(&stripped, &renames), 1);
+        assert_eq!(
+            renames.get(Symbol::Function(RefFun(0))).as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            renames
+                .get(Symbol::of_type(&stripped, RefType(6)).unwrap())
+                .as_deref(),
+            Some("Main")
+        );
+        // Nothing to rename on the original
+        let renames = Renames::default();
+        assert_eq!(parsed.apply(&code, &renames), 0);
+        assert!(renames.entries().is_empty());
+        Ok(())
+    }
+}
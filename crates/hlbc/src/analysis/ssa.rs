@@ -0,0 +1,308 @@
+//! Static single assignment form of a function.
+//!
+//! Every register assignment creates a new version of the register, so each version is assigned exactly once.
+//! Where control flow merges different versions of a register, a phi node selects the version depending on the
+//! incoming block. The construction follows Cytron et al. (phi insertion at the iterated dominance frontier) and only
+//! inserts phi nodes for registers live across blocks (semi pruned form). The bytecode itself is left untouched, the
+//! SSA form maps each instruction to the versions of the registers it uses and defines.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::analysis::cfg::{BlockId, Cfg};
+use crate::analysis::dataflow::{def, uses};
+use crate::analysis::dom::Dominators;
+use crate::types::{Function, Reg};
+
+/// A version of a register. Version 0 is the value at the function entry (arguments).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Var {
+    pub reg: Reg,
+    pub version: u32,
+}
+
+impl Display for Var {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.reg, self.version)
+    }
+}
+
+/// Select the version of a register depending on the incoming block
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Phi {
+    pub dst: Var,
+    /// Version coming from each predecessor block
+    pub args: Vec<(BlockId, Var)>,
+}
+
+/// Registers versions of an instruction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsaOp {
+    /// Position in the function
+    pub pos: usize,
+    pub uses: Vec<Var>,
+    pub def: Option<Var>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsaBlock {
+    pub phis: Vec<Phi>,
+    pub ops: Vec<SsaOp>,
+}
+
+/// A function in SSA form
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Ssa {
+    pub cfg: Cfg,
+    pub blocks: Vec<SsaBlock>,
+    /// Number of versions of each register
+    pub versions: Vec<u32>,
+}
+
+impl Ssa {
+    pub fn new(f: &Function) -> Self {
+        let cfg = Cfg::new(f);
+        let dom = Dominators::new(&cfg);
+        let n = cfg.blocks.len();
+        let nregs = f.regs.len();
+
+        // Dominance frontiers
+        let mut frontiers = vec![BTreeSet::new(); n];
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            if block.preds.len() < 2 {
+                continue;
+            }
+            for &p in &block.preds {
+                let mut runner = Some(p);
+                while let Some(r) = runner {
+                    if Some(r) == dom.idom(b) || !dom.dominates(0, r) {
+                        break;
+                    }
+                    frontiers[r].insert(b);
+                    runner = dom.idom(r);
+                }
+            }
+        }
+
+        // Registers used before being assigned in a block and blocks assigning each register
+        let mut global = vec![false; nregs];
+        let mut def_blocks = vec![Vec::new(); nregs];
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let mut killed = vec![false; nregs];
+            for i in block.ops.clone() {
+                for r in uses(&f.ops[i]) {
+                    if let Some(false) = killed.get(r.0 as usize) {
+                        global[r.0 as usize] = true;
+                    }
+                }
+                if let Some(r) = def(&f.ops[i]) {
+                    if let Some(k) = killed.get_mut(r.0 as usize) {
+                        *k = true;
+                        def_blocks[r.0 as usize].push(b);
+                    }
+                }
+            }
+        }
+
+        // Phi insertion at the iterated dominance frontier
+        let mut phi_regs = vec![Vec::new(); n];
+        for (r, blocks) in def_blocks.iter().enumerate() {
+            if !global[r] {
+                continue;
+            }
+            let mut has_phi = vec![false; n];
+            let mut work = blocks.clone();
+            while let Some(b) = work.pop() {
+                for &d in &frontiers[b] {
+                    if !has_phi[d] {
+                        has_phi[d] = true;
+                        phi_regs[d].push(Reg(r as u32));
+                        work.push(d);
+                    }
+                }
+            }
+        }
+
+        let mut ssa = Ssa {
+            blocks: phi_regs
+                .iter()
+                .map(|regs| SsaBlock {
+                    phis: regs
+                        .iter()
+                        .map(|&reg| Phi {
+                            dst: Var { reg, version: 0 },
+                            args: Vec::new(),
+                        })
+                        .collect(),
+                    ops: Vec::new(),
+                })
+                .collect(),
+            versions: vec![1; nregs],
+            cfg,
+        };
+
+        // Renaming, walking the dominator tree from the entry. Unreachable blocks are renamed on their own.
+        let mut children = vec![Vec::new(); n];
+        for b in 0..n {
+            if let Some(d) = dom.idom(b) {
+                children[d].push(b);
+            }
+        }
+        let mut stacks: Vec<Vec<u32>> = vec![vec![0]; nregs];
+        for root in (0..n).filter(|&b| dom.idom(b).is_none()) {
+            ssa.rename(f, &children, &mut stacks, root);
+        }
+        ssa
+    }
+
+    fn rename(
+        &mut self,
+        f: &Function,
+        children: &[Vec<BlockId>],
+        stacks: &mut [Vec<u32>],
+        root: BlockId,
+    ) {
+        enum Step {
+            Enter(BlockId),
+            /// Pop the versions pushed by the block
+            Exit(Vec<Reg>),
+        }
+
+        let mut work = vec![Step::Enter(root)];
+        while let Some(step) = work.pop() {
+            let b = match step {
+                Step::Enter(b) => b,
+                Step::Exit(pushed) => {
+                    for r in pushed {
+                        stacks[r.0 as usize].pop();
+                    }
+                    continue;
+                }
+            };
+            let mut pushed = Vec::new();
+            let mut new_version = |stacks: &mut [Vec<u32>], pushed: &mut Vec<Reg>, reg: Reg| {
+                let version = self.versions[reg.0 as usize];
+                self.versions[reg.0 as usize] += 1;
+                stacks[reg.0 as usize].push(version);
+                pushed.push(reg);
+                Var { reg, version }
+            };
+
+            let mut phis = std::mem::take(&mut self.blocks[b].phis);
+            for phi in &mut phis {
+                phi.dst = new_version(stacks, &mut pushed, phi.dst.reg);
+            }
+            self.blocks[b].phis = phis;
+
+            let mut ops = Vec::with_capacity(self.cfg.blocks[b].ops.len());
+            for pos in self.cfg.blocks[b].ops.clone() {
+                let op = &f.ops[pos];
+                let uses = uses(op)
+                    .into_iter()
+                    .map(|reg| Var {
+                        reg,
+                        version: current(stacks, reg),
+                    })
+                    .collect();
+                let def = def(op)
+                    .filter(|r| (r.0 as usize) < stacks.len())
+                    .map(|r| new_version(stacks, &mut pushed, r));
+                ops.push(SsaOp { pos, uses, def });
+            }
+            self.blocks[b].ops = ops;
+
+            for i in 0..self.cfg.blocks[b].succs.len() {
+                let s = self.cfg.blocks[b].succs[i].to;
+                for phi in &mut self.blocks[s].phis {
+                    if !phi.args.iter().any(|&(p, _)| p == b) {
+                        let version = current(stacks, phi.dst.reg);
+                        phi.args.push((
+                            b,
+                            Var {
+                                reg: phi.dst.reg,
+                                version,
+                            },
+                        ));
+                    }
+                }
+            }
+
+            work.push(Step::Exit(pushed));
+            work.extend(children[b].iter().rev().map(|&c| Step::Enter(c)));
+        }
+    }
+
+    /// Registers versions of an instruction
+    pub fn op(&self, pos: usize) -> Option<&SsaOp> {
+        let b = self.cfg.block_at(pos)?;
+        let block = &self.blocks[b];
+        block.ops.get(pos - self.cfg.blocks[b].ops.start)
+    }
+}
+
+impl Function {
+    /// Build the SSA form of this function
+    pub fn ssa(&self) -> Ssa {
+        Ssa::new(self)
+    }
+}
+
+/// Version of a register currently in scope
+fn current(stacks: &[Vec<u32>], reg: Reg) -> u32 {
+    stacks
+        .get(reg.0 as usize)
+        .and_then(|s| s.last())
+        .copied()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::ssa::{Phi, Ssa, Var};
+    use crate::types::{Function, Reg};
+
+    fn var(reg: u32, version: u32) -> Var {
+        Var {
+            reg: Reg(reg),
+            version,
+        }
+    }
+
+    #[test]
+    fn test_ssa() -> crate::Result<()> {
+        // r0 = 0; while (r1) r0++; return r0;
+        let f = Function::from_asm(
+            "
+            .fun fn@0 type@0
+            .reg type@0
+            .reg type@0
+                Int reg0 int@0
+            loop:
+                Label
+                JFalse reg1 end
+                Incr reg0
+                JAlways loop
+            end:
+                Ret reg0
+            .end",
+        )?;
+        let ssa = Ssa::new(&f);
+        assert_eq!(ssa.blocks.len(), 4);
+        assert!(ssa.blocks[0].phis.is_empty());
+        assert_eq!(
+            ssa.blocks[1].phis,
+            vec![Phi {
+                dst: var(0, 2),
+                args: vec![(0, var(0, 1)), (2, var(0, 3))],
+            }]
+        );
+        assert_eq!(ssa.op(0).unwrap().def, Some(var(0, 1)));
+        assert_eq!(ssa.op(2).unwrap().uses, vec![var(1, 0)]);
+        assert_eq!(ssa.op(3).unwrap().uses, vec![var(0, 2)]);
+        assert_eq!(ssa.op(3).unwrap().def, Some(var(0, 3)));
+        assert_eq!(ssa.op(5).unwrap().uses, vec![var(0, 2)]);
+        assert_eq!(ssa.versions, vec![4, 1]);
+        Ok(())
+    }
+}
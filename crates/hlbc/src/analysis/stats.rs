@@ -0,0 +1,161 @@
+//! Statistics about the instructions and the pools of a bytecode.
+
+use std::collections::BTreeMap;
+
+use crate::{Bytecode, Type};
+
+/// Distribution of a quantity, e.g. the number of registers of each function
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Distribution {
+    pub count: usize,
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: usize,
+    /// Number of values in each bucket, keyed by the bucket upper bound (inclusive). Buckets are powers of two :
+    /// 0, 1, 2, 4, 8, ...
+    pub histogram: BTreeMap<usize, usize>,
+}
+
+impl Distribution {
+    pub fn new(values: impl IntoIterator<Item = usize>) -> Self {
+        let mut values: Vec<usize> = values.into_iter().collect();
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_unstable();
+        let mut histogram = BTreeMap::new();
+        for &v in &values {
+            let bucket = if v == 0 { 0 } else { v.next_power_of_two() };
+            *histogram.entry(bucket).or_insert(0) += 1;
+        }
+        Self {
+            count: values.len(),
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<usize>() as f64 / values.len() as f64,
+            median: values[values.len() / 2],
+            histogram,
+        }
+    }
+}
+
+/// Statistics of a bytecode, see [Bytecode::stats]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Stats {
+    /// Number of instructions of each kind, by opcode name
+    pub opcodes: BTreeMap<&'static str, usize>,
+    /// Number of instructions in the whole bytecode
+    pub total_ops: usize,
+    /// Number of instructions of each function
+    pub ops: Distribution,
+    /// Number of registers of each function
+    pub registers: Distribution,
+    /// Length in bytes of the strings of the pool
+    pub strings: Distribution,
+    /// Number of types of each kind
+    pub types: BTreeMap<&'static str, usize>,
+}
+
+impl Stats {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut opcodes = BTreeMap::new();
+        let mut total_ops = 0;
+        for f in &code.functions {
+            for op in &f.ops {
+                *opcodes.entry(op.name()).or_insert(0) += 1;
+                total_ops += 1;
+            }
+        }
+        let mut types = BTreeMap::new();
+        for t in &code.types {
+            *types.entry(type_kind(t)).or_insert(0) += 1;
+        }
+        Self {
+            opcodes,
+            total_ops,
+            ops: Distribution::new(code.functions.iter().map(|f| f.ops.len())),
+            registers: Distribution::new(code.functions.iter().map(|f| f.regs.len())),
+            strings: Distribution::new(code.strings.iter().map(|s| s.len())),
+            types,
+        }
+    }
+
+    /// Opcodes sorted by decreasing frequency
+    pub fn most_used_opcodes(&self) -> Vec<(&'static str, usize)> {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(&n, &c)| (n, c)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        opcodes
+    }
+}
+
+/// Name of the kind of a type
+fn type_kind(t: &Type) -> &'static str {
+    match t {
+        Type::Void => "void",
+        Type::UI8 => "ui8",
+        Type::UI16 => "ui16",
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        Type::Bool => "bool",
+        Type::Bytes => "bytes",
+        Type::Dyn => "dynamic",
+        Type::Fun(_) => "fun",
+        Type::Obj(_) => "obj",
+        Type::Array => "array",
+        Type::Type => "type",
+        Type::Ref(_) => "ref",
+        Type::Virtual { .. } => "virtual",
+        Type::DynObj => "dynobj",
+        Type::Abstract { .. } => "abstract",
+        Type::Enum { .. } => "enum",
+        Type::Null(_) => "null",
+        Type::Method(_) => "method",
+        Type::Struct(_) => "struct",
+        Type::Packed(_) => "packed",
+    }
+}
+
+impl Bytecode {
+    /// Compute statistics about the instructions and the pools, see [Stats]
+    pub fn stats(&self) -> Stats {
+        Stats::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::stats::Distribution;
+    use crate::testing::sample;
+
+    #[test]
+    fn test_distribution() {
+        let d = Distribution::new([0, 1, 3, 4, 9]);
+        assert_eq!((d.count, d.min, d.max, d.median), (5, 0, 9, 3));
+        assert_eq!(d.mean, 17.0 / 5.0);
+        assert_eq!(
+            d.histogram.into_iter().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 1), (4, 2), (16, 1)]
+        );
+        assert_eq!(Distribution::new([]), Distribution::default());
+    }
+
+    #[test]
+    fn test_stats() {
+        let code = sample();
+        let stats = code.stats();
+        assert_eq!(stats.total_ops, 14);
+        assert_eq!(stats.opcodes["Ret"], 4);
+        assert_eq!(stats.opcodes["Call1"], 2);
+        assert_eq!(stats.most_used_opcodes()[0], ("Ret", 4));
+        assert_eq!((stats.ops.min, stats.ops.max, stats.ops.median), (1, 8, 3));
+        assert_eq!(stats.registers.count, 4);
+        assert_eq!(stats.types["obj"], 2);
+        assert_eq!(stats.types["fun"], 3);
+        assert_eq!(stats.strings.max, 6);
+    }
+}
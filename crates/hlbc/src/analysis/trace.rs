@@ -0,0 +1,174 @@
+use crate::types::{Function, RefFun, RefInt, RefString, Reg, Type};
+use crate::{Bytecode, Opcode};
+
+/// A call to `haxe.Log.trace`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceCall {
+    /// Function containing the call
+    pub fun: RefFun,
+    /// Index of the call instruction
+    pub pos: usize,
+    /// Traced value if it is a constant string
+    pub msg: Option<RefString>,
+    /// Source file from the position infos passed to trace
+    pub file: Option<RefString>,
+    /// Source line from the position infos passed to trace
+    pub line: Option<RefInt>,
+}
+
+/// Finds every call to `trace` in the bytecode.
+///
+/// `trace(v)` compiles to a call to the `haxe.Log.trace` closure with the traced value and an anonymous
+/// structure with the position of the call (`haxe.PosInfos`).
+pub fn find_traces(code: &Bytecode) -> Vec<TraceCall> {
+    code.functions
+        .iter()
+        .flat_map(|f| {
+            f.ops
+                .iter()
+                .enumerate()
+                .filter_map(move |(pos, op)| match op {
+                    Opcode::CallClosure { fun, args, .. }
+                        if args.len() == 2
+                            && matches!(f.find_last_closure_assign(code, *fun, pos),
+                                Some(fun) if &*fun.name(code) == "trace") =>
+                    {
+                        Some(TraceCall {
+                            fun: f.findex,
+                            pos,
+                            msg: last_string(f, args[0], pos),
+                            file: posinfos_field(code, f, args[1], pos, "fileName")
+                                .and_then(|(src, at)| last_string(f, src, at)),
+                            line: posinfos_field(code, f, args[1], pos, "lineNumber")
+                                .and_then(|(src, at)| last_int(f, src, at)),
+                        })
+                    }
+                    _ => None,
+                })
+        })
+        .collect()
+}
+
+/// Last string constant assigned to a register before `pos`
+fn last_string(f: &Function, reg: Reg, pos: usize) -> Option<RefString> {
+    f.ops[..pos].iter().rev().find_map(|o| match *o {
+        Opcode::String { dst, ptr } if dst == reg => Some(ptr),
+        _ => None,
+    })
+}
+
+/// Last integer constant assigned to a register before `pos`
+fn last_int(f: &Function, reg: Reg, pos: usize) -> Option<RefInt> {
+    f.ops[..pos].iter().rev().find_map(|o| match *o {
+        Opcode::Int { dst, ptr } if dst == reg => Some(ptr),
+        _ => None,
+    })
+}
+
+/// Find the last assignment to the field of the position infos structure before `pos`.
+/// Returns the register assigned and the position of the assignment.
+fn posinfos_field(
+    code: &Bytecode,
+    f: &Function,
+    posinfos: Reg,
+    pos: usize,
+    name: &str,
+) -> Option<(Reg, usize)> {
+    let fields = match &code[f.regtype(posinfos)] {
+        Type::Virtual { fields } => fields,
+        _ => return None,
+    };
+    f.ops[..pos]
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(i, o)| match *o {
+            Opcode::SetField { obj, field, src }
+                if obj == posinfos
+                    && matches!(fields.get(field.0), Some(field) if code[field.name] == name) =>
+            {
+                Some((src, i))
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::trace::find_traces;
+    use crate::testing::{bind, bytecode, fun, obj};
+    use crate::types::{ObjField, RefFun, RefInt, RefString, RefType, Type};
+
+    #[test]
+    fn test_find_traces() {
+        // trace("Hello") in Trace.hx at line 3, and a call to another closure
+        let mut code = bytecode(
+            &[
+                "main",
+                "trace",
+                "$Log",
+                "Hello",
+                "Trace.hx",
+                "fileName",
+                "lineNumber",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Dyn,
+                fun(&[], 0),
+                fun(&[2, 5], 0),
+                Type::Virtual {
+                    fields: vec![
+                        ObjField {
+                            name: RefString(5),
+                            t: RefType(6),
+                        },
+                        ObjField {
+                            name: RefString(6),
+                            t: RefType(1),
+                        },
+                    ],
+                },
+                Type::Bytes,
+                Type::Obj(bind(obj(2, None, &[(1, 4)], &[]), 0, 1)),
+            ],
+            "
+            .fun fn@0 type@3
+            .reg type@7
+            .reg type@4
+            .reg type@6
+            .reg type@5
+            .reg type@6
+            .reg type@1
+            .reg type@0
+                Null reg0
+                Field reg1 reg0 field@0
+                String reg2 string@3
+                New reg3
+                String reg4 string@4
+                SetField reg3 field@0 reg4
+                Int reg5 int@0
+                SetField reg3 field@1 reg5
+                CallClosure reg6 reg1 [reg2, reg3]
+                StaticClosure reg1 fn@0
+                CallClosure reg6 reg1 [reg2, reg3]
+                Ret reg6
+            .end
+            .fun fn@1 type@4
+            .reg type@2
+            .reg type@5
+            .reg type@0
+                Ret reg2
+            .end",
+        );
+        code.ints = vec![3];
+        let traces = find_traces(&code);
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        assert_eq!((trace.fun, trace.pos), (RefFun(0), 8));
+        assert_eq!(trace.msg, Some(RefString(3)));
+        assert_eq!(trace.file, Some(RefString(4)));
+        assert_eq!(trace.line, Some(RefInt(0)));
+    }
+}
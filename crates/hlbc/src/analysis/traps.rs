@@ -0,0 +1,236 @@
+//! Exception table of a function.
+//!
+//! A `Trap` starts a protected region, an exception thrown inside jumps to the handler with the exception in the
+//! trap register. The matching `EndTrap` closes the region when execution leaves it normally. Regions are nested
+//! like the `try` blocks they come from.
+//!
+//! A typed `catch` compiles to a check of the exception against the type (usually `hl.BaseType.check`) at the start
+//! of the handler, the exception is rethrown when no check matches.
+
+use std::collections::HashMap;
+
+use crate::types::{Function, RefType, Reg};
+use crate::{Bytecode, Opcode};
+
+/// A region protected by a `Trap`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TrapRegion {
+    /// Position of the `Trap` instruction
+    pub trap: usize,
+    /// Position of the matching `EndTrap`, None if the region is never closed
+    pub end: Option<usize>,
+    /// Register receiving the exception
+    pub exc: Reg,
+    /// First instruction of the handler
+    pub handler: usize,
+    /// Index of the enclosing region in the table
+    pub parent: Option<usize>,
+    /// Number of enclosing regions
+    pub depth: usize,
+}
+
+impl TrapRegion {
+    /// Instructions protected by this region, an unclosed region extends up to its handler
+    pub fn covers(&self, pos: usize) -> bool {
+        pos > self.trap && pos < self.end.unwrap_or(self.handler)
+    }
+
+    /// Exceptions caught by this region, guessed from the type checks in the handler
+    pub fn caught(&self, code: &Bytecode, f: &Function) -> Caught {
+        // Registers holding a type, loaded with Type or the global of a class
+        let mut types: HashMap<Reg, RefType> = HashMap::new();
+        let mut checked = Vec::new();
+        for op in f.ops.iter().skip(self.handler) {
+            match *op {
+                Opcode::Type { dst, ty } => {
+                    types.insert(dst, ty);
+                }
+                Opcode::GetGlobal { dst, global } => match code.global_class(global) {
+                    Some(ty) => {
+                        types.insert(dst, ty);
+                    }
+                    None => {
+                        types.remove(&dst);
+                    }
+                },
+                Opcode::Rethrow { exc } if exc == self.exc => return Caught::Types(checked),
+                _ => {
+                    let args = op.uses();
+                    if args.contains(&self.exc) {
+                        checked.extend(args.iter().filter_map(|r| types.get(r)).copied());
+                    }
+                    for dst in op.defs() {
+                        types.remove(&dst);
+                    }
+                }
+            }
+        }
+        // The last catch isn't typed
+        Caught::All
+    }
+}
+
+/// Exceptions caught by a [TrapRegion]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Caught {
+    /// Every exception, a catch of `Dynamic` or the checks weren't recognized
+    All,
+    /// Only the exceptions of those types, the others are rethrown
+    Types(Vec<RefType>),
+}
+
+impl Function {
+    /// Exception regions in order of their `Trap` instruction
+    pub fn trap_regions(&self) -> Vec<TrapRegion> {
+        let mut regions: Vec<TrapRegion> = Vec::new();
+        // Open regions, innermost last
+        let mut open: Vec<usize> = Vec::new();
+        for (pos, op) in self.ops.iter().enumerate() {
+            match *op {
+                Opcode::Trap { exc, offset } => {
+                    open.push(regions.len());
+                    regions.push(TrapRegion {
+                        trap: pos,
+                        end: None,
+                        exc,
+                        handler: (pos as i32 + offset + 1) as usize,
+                        parent: open.iter().rev().nth(1).copied(),
+                        depth: open.len() - 1,
+                    });
+                }
+                Opcode::EndTrap { exc } => {
+                    // The innermost region with this register, the last one if none matches
+                    let i = open
+                        .iter()
+                        .rposition(|&r| regions[r].exc == exc)
+                        .or_else(|| open.len().checked_sub(1));
+                    if let Some(i) = i {
+                        regions[open.remove(i)].end = Some(pos);
+                    }
+                }
+                _ => {}
+            }
+        }
+        regions
+    }
+
+    /// Indexes in [Function::trap_regions] of the regions active at each instruction, innermost last
+    pub fn active_traps(&self) -> Vec<Vec<usize>> {
+        let regions = self.trap_regions();
+        (0..self.ops.len())
+            .map(|pos| {
+                regions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, r)| r.covers(pos))
+                    .map(|(i, _)| i)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Handler receiving the exceptions thrown at an instruction, None if uncaught in this function
+    pub fn handler_at(&self, pos: usize) -> Option<usize> {
+        self.trap_regions()
+            .iter()
+            .filter(|r| r.covers(pos))
+            .max_by_key(|r| r.depth)
+            .map(|r| r.handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::traps::{Caught, TrapRegion};
+    use crate::types::{Function, RefFun, RefString, RefType, Reg};
+    use crate::{Bytecode, Opcode};
+
+    /// try { try { f() } catch(e:T) {} } catch(e:Dynamic) {}
+    fn nested_try() -> Function {
+        Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![RefType(0); 3],
+            ops: vec![
+                Opcode::Trap {
+                    exc: Reg(0),
+                    offset: 7,
+                },
+                Opcode::Trap {
+                    exc: Reg(1),
+                    offset: 3,
+                },
+                Opcode::Nop,
+                Opcode::EndTrap { exc: Reg(1) },
+                Opcode::JAlways { offset: 3 },
+                Opcode::Type {
+                    dst: Reg(2),
+                    ty: RefType(4),
+                },
+                Opcode::Call2 {
+                    dst: Reg(2),
+                    fun: RefFun(1),
+                    arg0: Reg(2),
+                    arg1: Reg(1),
+                },
+                Opcode::Rethrow { exc: Reg(1) },
+                Opcode::EndTrap { exc: Reg(0) },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+            debug_info: None,
+            assigns: None,
+            name: RefString(0),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn test_trap_regions() {
+        let regions = nested_try().trap_regions();
+        assert_eq!(
+            regions,
+            vec![
+                TrapRegion {
+                    trap: 0,
+                    end: Some(8),
+                    exc: Reg(0),
+                    handler: 8,
+                    parent: None,
+                    depth: 0,
+                },
+                TrapRegion {
+                    trap: 1,
+                    end: Some(3),
+                    exc: Reg(1),
+                    handler: 5,
+                    parent: Some(0),
+                    depth: 1,
+                },
+            ]
+        );
+        assert!(regions[1].covers(2));
+        assert!(!regions[1].covers(3));
+        assert!(regions[0].covers(6));
+    }
+
+    #[test]
+    fn test_active_traps() {
+        let f = nested_try();
+        let regions = f.trap_regions();
+        assert_eq!(regions[1].parent, Some(0));
+        let active = f.active_traps();
+        assert_eq!(active[2], vec![0, 1]);
+        assert_eq!(active[6], vec![0]);
+        assert!(active[9].is_empty());
+        assert_eq!(f.handler_at(2), Some(5));
+        assert_eq!(f.handler_at(6), Some(8));
+        assert_eq!(f.handler_at(9), None);
+
+        let code = Bytecode::default();
+        assert_eq!(
+            regions[1].caught(&code, &f),
+            Caught::Types(vec![RefType(4)])
+        );
+        assert_eq!(regions[0].caught(&code, &f), Caught::All);
+    }
+}
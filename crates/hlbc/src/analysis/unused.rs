@@ -0,0 +1,324 @@
+//! Detection of the pool entries that are never referenced.
+//!
+//! An entry is used when it is referenced by an instruction, a constant, a function or the definition of another used
+//! type, global or native. Functions are always used, see [dead](crate::analysis::dead) for the unreachable ones.
+//! [Bytecode::gc_pools] removes the unused entries.
+
+use crate::opcodes::Opcode;
+use crate::types::{ConstantPool, RefFloat, RefFun, RefGlobal, RefInt, RefString, RefType, Type};
+use crate::{Bytecode, Result};
+
+/// Pool entries never referenced
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UnusedPools {
+    pub strings: Vec<RefString>,
+    pub ints: Vec<RefInt>,
+    pub floats: Vec<RefFloat>,
+    pub globals: Vec<RefGlobal>,
+    pub natives: Vec<RefFun>,
+    pub types: Vec<RefType>,
+}
+
+impl UnusedPools {
+    /// Fails if a lazily loaded function body can't be decoded
+    pub fn new(code: &Bytecode) -> Result<Self> {
+        Ok(PoolUsage::new(code)?.unused(code))
+    }
+
+    /// Total number of unused entries
+    pub fn len(&self) -> usize {
+        self.strings.len()
+            + self.ints.len()
+            + self.floats.len()
+            + self.globals.len()
+            + self.natives.len()
+            + self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Whether each pool entry is used, indexed like the pools
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PoolUsage {
+    pub(crate) strings: Vec<bool>,
+    pub(crate) ints: Vec<bool>,
+    pub(crate) floats: Vec<bool>,
+    pub(crate) globals: Vec<bool>,
+    pub(crate) types: Vec<bool>,
+    /// Indexed like the natives pool, not by findex
+    pub(crate) natives: Vec<bool>,
+}
+
+impl PoolUsage {
+    pub(crate) fn new(code: &Bytecode) -> Result<Self> {
+        let mut natives = vec![None; code.findex_max()];
+        for (i, n) in code.natives.iter().enumerate() {
+            if let Some(slot) = natives.get_mut(n.findex.0) {
+                *slot = Some(i);
+            }
+        }
+        let mut marker = Marker {
+            code,
+            usage: PoolUsage {
+                strings: vec![false; code.strings.len()],
+                ints: vec![false; code.ints.len()],
+                floats: vec![false; code.floats.len()],
+                globals: vec![false; code.globals.len()],
+                types: vec![false; code.types.len()],
+                natives: vec![false; code.natives.len()],
+            },
+            natives,
+            pending: Vec::new(),
+        };
+        // Index 0 means 'no string'
+        marker.string(RefString(0));
+        marker.fun(code.entrypoint);
+        for f in &code.functions {
+            marker.string(f.name);
+            marker.ty(f.t);
+            for &r in &f.regs {
+                marker.ty(r);
+            }
+            if let Some(parent) = f.parent {
+                marker.ty(parent);
+            }
+            for &(s, _) in f.assigns.iter().flatten() {
+                marker.string(s);
+            }
+            // Lazily loaded bodies are not in f.ops
+            for op in code.function_ops(f)?.iter() {
+                marker.op(op);
+            }
+        }
+        for c in code.constants.iter().flatten() {
+            marker.global(c.global);
+            let Some(obj) = c.obj(code) else {
+                continue;
+            };
+            for (&value, field) in c.fields.iter().zip(&obj.fields) {
+                match ConstantPool::of(code.types.get(field.t.0)) {
+                    ConstantPool::Int => marker.int(RefInt(value)),
+                    ConstantPool::Float => marker.float(RefFloat(value)),
+                    ConstantPool::Bool => {}
+                    ConstantPool::String => marker.string(RefString(value)),
+                    ConstantPool::Type => marker.ty(RefType(value)),
+                    ConstantPool::Global => marker.global(RefGlobal(value)),
+                }
+            }
+        }
+        marker.run();
+        Ok(marker.usage)
+    }
+
+    pub(crate) fn unused(&self, code: &Bytecode) -> UnusedPools {
+        fn unused<T>(used: &[bool], f: impl Fn(usize) -> T) -> Vec<T> {
+            used.iter()
+                .enumerate()
+                .filter(|(_, &u)| !u)
+                .map(|(i, _)| f(i))
+                .collect()
+        }
+        UnusedPools {
+            strings: unused(&self.strings, RefString),
+            ints: unused(&self.ints, RefInt),
+            floats: unused(&self.floats, RefFloat),
+            globals: unused(&self.globals, RefGlobal),
+            natives: unused(&self.natives, |i| code.natives[i].findex),
+            types: unused(&self.types, RefType),
+        }
+    }
+}
+
+/// Definitions to visit, their content is used
+enum Pending {
+    Type(RefType),
+    Global(RefGlobal),
+    /// Index in the natives pool
+    Native(usize),
+}
+
+struct Marker<'a> {
+    code: &'a Bytecode,
+    usage: PoolUsage,
+    /// Index in the natives pool of each findex
+    natives: Vec<Option<usize>>,
+    pending: Vec<Pending>,
+}
+
+/// Mark an entry as used, returns true if it wasn't already
+fn mark(used: &mut [bool], i: usize) -> bool {
+    match used.get_mut(i) {
+        Some(u) if !*u => {
+            *u = true;
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Marker<'_> {
+    fn string(&mut self, s: RefString) {
+        mark(&mut self.usage.strings, s.0);
+    }
+
+    fn int(&mut self, i: RefInt) {
+        mark(&mut self.usage.ints, i.0);
+    }
+
+    fn float(&mut self, f: RefFloat) {
+        mark(&mut self.usage.floats, f.0);
+    }
+
+    fn ty(&mut self, t: RefType) {
+        if mark(&mut self.usage.types, t.0) {
+            self.pending.push(Pending::Type(t));
+        }
+    }
+
+    fn global(&mut self, g: RefGlobal) {
+        if mark(&mut self.usage.globals, g.0) {
+            self.pending.push(Pending::Global(g));
+        }
+    }
+
+    /// Globals references of types are 1-based, 0 means no global
+    fn type_global(&mut self, g: RefGlobal) {
+        if g.0 > 0 {
+            self.global(RefGlobal(g.0 - 1));
+        }
+    }
+
+    /// Only natives are tracked, functions are always used
+    fn fun(&mut self, f: RefFun) {
+        if let Some(&Some(i)) = self.natives.get(f.0) {
+            if mark(&mut self.usage.natives, i) {
+                self.pending.push(Pending::Native(i));
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Opcode) {
+        match op {
+            Opcode::Int { ptr, .. } => self.int(*ptr),
+            Opcode::Float { ptr, .. } => self.float(*ptr),
+            // Before bytecode v5, bytes are stored in the strings pool
+            Opcode::Bytes { ptr, .. } if self.code.bytes.is_none() => self.string(RefString(ptr.0)),
+            Opcode::String { ptr, .. } => self.string(*ptr),
+            Opcode::Call0 { fun, .. }
+            | Opcode::Call1 { fun, .. }
+            | Opcode::Call2 { fun, .. }
+            | Opcode::Call3 { fun, .. }
+            | Opcode::Call4 { fun, .. }
+            | Opcode::CallN { fun, .. }
+            | Opcode::StaticClosure { fun, .. }
+            | Opcode::InstanceClosure { fun, .. } => self.fun(*fun),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                self.global(*global)
+            }
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => self.string(*field),
+            Opcode::Type { ty, .. } => self.ty(*ty),
+            _ => {}
+        }
+    }
+
+    fn run(&mut self) {
+        let code = self.code;
+        while let Some(pending) = self.pending.pop() {
+            match pending {
+                Pending::Type(t) => self.type_def(t),
+                Pending::Global(g) => {
+                    if let Some(&t) = code.globals.get(g.0) {
+                        self.ty(t);
+                    }
+                }
+                Pending::Native(i) => {
+                    let n = &code.natives[i];
+                    self.string(n.lib);
+                    self.string(n.name);
+                    self.ty(n.t);
+                }
+            }
+        }
+    }
+
+    fn type_def(&mut self, t: RefType) {
+        let code = self.code;
+        let Some(ty) = code.types.get(t.0) else {
+            return;
+        };
+        match ty {
+            Type::Fun(fun) | Type::Method(fun) => {
+                for &arg in &fun.args {
+                    self.ty(arg);
+                }
+                self.ty(fun.ret);
+            }
+            Type::Obj(obj) | Type::Struct(obj) => {
+                self.string(obj.name);
+                if let Some(super_) = obj.super_ {
+                    self.ty(super_);
+                }
+                self.type_global(obj.global);
+                for f in obj.fields.iter().chain(&obj.own_fields) {
+                    self.string(f.name);
+                    self.ty(f.t);
+                }
+                for p in &obj.protos {
+                    self.string(p.name);
+                    self.fun(p.findex);
+                }
+                for &f in obj.bindings.values() {
+                    self.fun(f);
+                }
+            }
+            Type::Ref(inner) | Type::Null(inner) | Type::Packed(inner) => self.ty(*inner),
+            Type::Virtual { fields } => {
+                for f in fields {
+                    self.string(f.name);
+                    self.ty(f.t);
+                }
+            }
+            Type::Abstract { name } => self.string(*name),
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => {
+                self.string(*name);
+                self.type_global(*global);
+                for c in constructs {
+                    self.string(c.name);
+                    for &p in &c.params {
+                        self.ty(p);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::unused::UnusedPools;
+    use crate::testing::sample;
+    use crate::types::{RefInt, RefString, RefType, Type};
+
+    #[test]
+    fn test_unused() {
+        let mut code = sample();
+        assert_eq!(UnusedPools::new(&code).unwrap(), UnusedPools::default());
+        let s = code.intern_string("never_used");
+        code.ints.push(42);
+        code.types.push(Type::F64);
+        let unused = UnusedPools::new(&code).unwrap();
+        assert_eq!(unused.strings, vec![s]);
+        assert_eq!(s, RefString(9));
+        assert_eq!(unused.ints, vec![RefInt(2)]);
+        assert_eq!(unused.types, vec![RefType(8)]);
+        assert_eq!(unused.len(), 3);
+    }
+}
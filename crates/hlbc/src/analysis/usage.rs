@@ -5,10 +5,10 @@
 
 use std::ops::Index;
 
-use crate::opcodes::Opcode;
+use crate::opcodes::{Opcode, Operand};
 use crate::types::{
     EnumConstruct, FunPtr, Function, ObjField, ObjProto, RefEnumConstruct, RefField, RefFun,
-    RefString, RefType, Reg, Type, TypeFun, TypeObj,
+    RefGlobal, RefString, RefType, Reg, Type, TypeFun, TypeObj,
 };
 use crate::Bytecode;
 
@@ -41,7 +41,11 @@ pub enum UsageType {
     /// Type of a function
     Function(RefFun),
     /// Type of a function register
-    Register(RefFun),
+    Register(RefFun, Reg),
+    /// Type of a global
+    Global(RefGlobal),
+    /// Instruction referencing the type (`Type`, `New`, casts, ...) in a function, at a position
+    Instruction(RefFun, usize),
 }
 
 /// The different ways a string can be used
@@ -156,10 +160,31 @@ impl FullUsageReport {
 
     fn compute_usage_fun(&mut self, code: &Bytecode, f: &Function) {
         self.types[f.t.0].push(UsageType::Function(f.findex));
-        for reg in &f.regs {
-            self.types[reg.0].push(UsageType::Register(f.findex));
+        for (r, reg) in f.regs.iter().enumerate() {
+            self.types[reg.0].push(UsageType::Register(f.findex, Reg(r as u32)));
         }
         for (i, op) in f.ops() {
+            // Type operands and the target type of allocations and casts
+            let dst_type = match *op {
+                Opcode::New { dst }
+                | Opcode::SafeCast { dst, .. }
+                | Opcode::UnsafeCast { dst, .. }
+                | Opcode::ToVirtual { dst, .. } => f.regs.get(dst.0 as usize).copied(),
+                _ => None,
+            };
+            let types = op
+                .operands()
+                .into_iter()
+                .filter_map(|(_, operand)| match operand {
+                    Operand::Type(t) => Some(t),
+                    _ => None,
+                })
+                .chain(dst_type);
+            for t in types {
+                if let Some(usages) = self.types.get_mut(t.0) {
+                    usages.push(UsageType::Instruction(f.findex, i));
+                }
+            }
             match op {
                 // Calls
                 Opcode::Call0 { fun, .. }
@@ -200,6 +225,10 @@ impl FullUsageReport {
             self.compute_usage_type(code, ref_ty);
         }
 
+        for (g, t) in code.globals.iter().enumerate() {
+            self.types[t.0].push(UsageType::Global(RefGlobal(g)));
+        }
+
         for f in code.functions() {
             match f {
                 FunPtr::Fun(fun) => {
@@ -247,7 +276,9 @@ pub fn usage_report(code: &Bytecode) -> FullUsageReport {
 
 #[cfg(test)]
 mod tests {
-    use crate::analysis::usage::FullUsageReport;
+    use crate::analysis::usage::{usage_report, FullUsageReport};
+    use crate::testing::sample;
+    use crate::types::RefType;
     use crate::Bytecode;
 
     #[test]
@@ -265,4 +296,25 @@ mod tests {
         usage.compute_usage_all(&code);
         dbg!(usage);
     }
+
+    #[test]
+    fn test_type_usage() {
+        let code = sample();
+        let usage = usage_report(&code);
+        let usage = |t| format!("{:?}", &usage[RefType(t)]);
+        assert_eq!(
+            usage(1),
+            "[Return(RefType(3)), Field(RefType(4), 0), Argument(RefType(5)), Return(RefType(5)), \
+            Global(RefGlobal(0)), Register(RefFun(0), Reg(1)), Register(RefFun(1), Reg(1)), \
+            Register(RefFun(2), Reg(0)), Register(RefFun(2), Reg(1))]"
+        );
+        // Point is the argument of length, a register and created by main
+        assert_eq!(
+            usage(4),
+            "[Argument(RefType(3)), Register(RefFun(0), Reg(0)), Instruction(RefFun(0), 0), \
+            Register(RefFun(1), Reg(0))]"
+        );
+        assert_eq!(usage(5), "[Field(RefType(6), 1), Function(RefFun(2))]");
+        assert_eq!(usage(6), "[]");
+    }
 }
@@ -0,0 +1,216 @@
+//! Cross references from the instructions to the elements of the bytecode.
+//!
+//! Every instruction is scanned once to build the index, then finding the instructions referencing a string, a
+//! constant, a global, a type, a field or a function is a lookup.
+
+use crate::analysis::devirt::Devirtualizer;
+use crate::opcodes::{Opcode, Operand};
+use crate::types::{
+    Function, RefBytes, RefField, RefFloat, RefFun, RefGlobal, RefInt, RefString, RefType, Reg,
+    Type,
+};
+use crate::visit::BytecodeVisitor;
+use crate::Bytecode;
+
+/// An instruction referencing an element
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Xref {
+    /// Function containing the instruction
+    pub fun: RefFun,
+    /// Position of the instruction in the function
+    pub pos: usize,
+}
+
+/// Instructions referencing each element of the bytecode, indexed like the pools
+#[derive(Debug, Clone, Default)]
+pub struct XrefIndex {
+    strings: Vec<Vec<Xref>>,
+    ints: Vec<Vec<Xref>>,
+    floats: Vec<Vec<Xref>>,
+    bytes: Vec<Vec<Xref>>,
+    globals: Vec<Vec<Xref>>,
+    types: Vec<Vec<Xref>>,
+    functions: Vec<Vec<Xref>>,
+    /// Fields of each type
+    fields: Vec<Vec<Vec<Xref>>>,
+}
+
+fn push(refs: &mut [Vec<Xref>], i: usize, xref: Xref) {
+    if let Some(refs) = refs.get_mut(i) {
+        if refs.last() != Some(&xref) {
+            refs.push(xref);
+        }
+    }
+}
+
+/// Builds the index while walking the bytecode
+struct Builder<'a> {
+    index: XrefIndex,
+    devirt: Devirtualizer<'a>,
+}
+
+impl BytecodeVisitor for Builder<'_> {
+    fn visit_opcode(&mut self, _: &Bytecode, f: &Function, pos: usize, op: &Opcode) {
+        let xref = Xref { fun: f.findex, pos };
+        match op {
+            // The type allocated is the type of the destination
+            Opcode::New { dst } => push(&mut self.index.types, f[*dst].0, xref),
+            Opcode::CallMethod { .. } | Opcode::CallThis { .. } | Opcode::CallClosure { .. } => {
+                for target in self.devirt.targets(f, pos) {
+                    push(&mut self.index.functions, target.0, xref);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_operand(
+        &mut self,
+        code: &Bytecode,
+        f: &Function,
+        pos: usize,
+        _: &'static str,
+        operand: Operand<'_>,
+    ) {
+        let index = &mut self.index;
+        let xref = Xref { fun: f.findex, pos };
+        match operand {
+            Operand::String(s) => push(&mut index.strings, s.0, xref),
+            Operand::Int(i) => push(&mut index.ints, i.0, xref),
+            Operand::Float(fl) => push(&mut index.floats, fl.0, xref),
+            Operand::Bytes(b) => push(&mut index.bytes, b.0, xref),
+            Operand::Global(g) => push(&mut index.globals, g.0, xref),
+            Operand::Type(t) => push(&mut index.types, t.0, xref),
+            Operand::Fun(fun) => push(&mut index.functions, fun.0, xref),
+            Operand::Field(field) => index.push_field(code, f, &f.ops[pos], field, xref),
+            _ => {}
+        }
+    }
+}
+
+impl XrefIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut builder = Builder {
+            index: Self {
+                strings: vec![Vec::new(); code.strings.len()],
+                ints: vec![Vec::new(); code.ints.len()],
+                floats: vec![Vec::new(); code.floats.len()],
+                bytes: vec![Vec::new(); code.bytes.as_ref().map_or(0, |(_, pos)| pos.len())],
+                globals: vec![Vec::new(); code.globals.len()],
+                types: vec![Vec::new(); code.types.len()],
+                functions: vec![Vec::new(); code.findex_max()],
+                fields: vec![Vec::new(); code.types.len()],
+            },
+            devirt: Devirtualizer::new(code),
+        };
+        code.visit(&mut builder);
+        builder.index
+    }
+
+    fn push_field(
+        &mut self,
+        code: &Bytecode,
+        f: &Function,
+        op: &Opcode,
+        field: RefField,
+        xref: Xref,
+    ) {
+        // The field of a method call is the index of a proto
+        if matches!(op, Opcode::CallMethod { .. } | Opcode::CallThis { .. }) {
+            return;
+        }
+        let Some(parent) = op
+            .parent_reg()
+            .filter(|&Reg(r)| (r as usize) < f.regs.len())
+        else {
+            return;
+        };
+        let ty = f[parent];
+        if !matches!(
+            code[ty],
+            Type::Obj(_) | Type::Struct(_) | Type::Virtual { .. }
+        ) {
+            return;
+        }
+        if let Some(fields) = self.fields.get_mut(ty.0) {
+            if fields.len() <= field.0 {
+                fields.resize(field.0 + 1, Vec::new());
+            }
+            push(fields, field.0, xref);
+        }
+    }
+
+    /// Instructions using a string, as a constant or as the name of a dynamic field
+    pub fn string(&self, s: RefString) -> &[Xref] {
+        self.strings.get(s.0).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn int(&self, i: RefInt) -> &[Xref] {
+        self.ints.get(i.0).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn float(&self, f: RefFloat) -> &[Xref] {
+        self.floats.get(f.0).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn bytes(&self, b: RefBytes) -> &[Xref] {
+        self.bytes.get(b.0).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Instructions reading or writing a global
+    pub fn global(&self, g: RefGlobal) -> &[Xref] {
+        self.globals.get(g.0).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Instructions with a type operand or allocating an object of this type
+    pub fn ty(&self, t: RefType) -> &[Xref] {
+        self.types.get(t.0).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Instructions calling a function or creating a closure of it. Method and closure calls are devirtualized.
+    pub fn fun(&self, f: RefFun) -> &[Xref] {
+        self.functions
+            .get(f.0)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Instructions accessing a field of an object, struct or virtual type
+    pub fn field(&self, t: RefType, field: RefField) -> &[Xref] {
+        self.fields
+            .get(t.0)
+            .and_then(|fields| fields.get(field.0))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::xref::{Xref, XrefIndex};
+    use crate::testing::sample;
+    use crate::types::{RefField, RefFun, RefGlobal, RefInt, RefString, RefType};
+
+    fn at(fun: usize, pos: usize) -> Xref {
+        Xref {
+            fun: RefFun(fun),
+            pos,
+        }
+    }
+
+    #[test]
+    fn test_xref() {
+        let code = sample();
+        let index = XrefIndex::new(&code);
+        assert_eq!(index.string(RefString(7)), &[at(0, 6)]);
+        assert!(index.string(RefString(0)).is_empty());
+        assert_eq!(index.int(RefInt(0)), &[at(0, 1)]);
+        assert_eq!(index.int(RefInt(1)), &[at(2, 0)]);
+        assert_eq!(index.global(RefGlobal(0)), &[at(0, 5)]);
+        assert_eq!(index.ty(RefType(4)), &[at(0, 0)]);
+        assert_eq!(index.fun(RefFun(1)), &[at(0, 3)]);
+        assert_eq!(index.fun(RefFun(2)), &[at(0, 4)]);
+        assert!(index.fun(RefFun(3)).is_empty());
+        assert_eq!(index.field(RefType(4), RefField(0)), &[at(0, 2), at(1, 0)]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Type compatibility, following the rules of the HashLink runtime (`hl_same_type` and `hl_safe_cast`).
+//!
+//! A value can be stored in a register of another type without a conversion instruction when :
+//! - the target is `dyn` and the value is already boxed (see [Type::is_dynamic])
+//! - both are classes and the target is a superclass of the value type
+//! - both are virtuals and the fields of the target are a prefix of the value fields
+//! - both are functions with the same arity, a compatible return type and contravariant arguments
+//! - both are `null` of compatible types
+//!
+//! `Null<T>` is not compatible with `T` and conversely, this requires `ToDyn` or `SafeCast`.
+
+use std::collections::HashSet;
+
+use crate::types::{ObjField, RefType, TypeFun};
+use crate::{Bytecode, TryResolve, Type};
+
+impl Type {
+    /// Values of this type are boxed and can be stored in a `dyn` register as is
+    pub fn is_dynamic(&self) -> bool {
+        matches!(
+            self,
+            Type::Dyn
+                | Type::Fun(_)
+                | Type::Obj(_)
+                | Type::Array
+                | Type::Virtual { .. }
+                | Type::DynObj
+                | Type::Enum { .. }
+                | Type::Null(_)
+                | Type::Method(_)
+        )
+    }
+}
+
+impl Bytecode {
+    /// Structural equality of two types. Classes, structs and enums are only equal to themselves.
+    pub fn same_type(&self, a: RefType, b: RefType) -> bool {
+        Compat::new(self).same(a, b)
+    }
+
+    /// A value of type `from` can be stored in a register of type `to` without a conversion
+    pub fn safe_cast(&self, from: RefType, to: RefType) -> bool {
+        Compat::new(self).cast(from, to)
+    }
+
+    /// A function of type `fun` can be used where a function of type `expected` is expected
+    pub fn fun_compatible(&self, fun: &TypeFun, expected: &TypeFun) -> bool {
+        Compat::new(self).fun(fun, expected)
+    }
+}
+
+struct Compat<'a> {
+    code: &'a Bytecode,
+    /// Pairs being compared, assumed equal to terminate on recursive types
+    assumed: HashSet<(RefType, RefType)>,
+}
+
+impl<'a> Compat<'a> {
+    fn new(code: &'a Bytecode) -> Self {
+        Self {
+            code,
+            assumed: HashSet::new(),
+        }
+    }
+
+    fn ty(&self, t: RefType) -> Option<&'a Type> {
+        self.code.types.get(t.0)
+    }
+
+    fn same(&mut self, a: RefType, b: RefType) -> bool {
+        if a == b || !self.assumed.insert((a, b)) {
+            return true;
+        }
+        let same = self.same_uncached(a, b);
+        if !same {
+            self.assumed.remove(&(a, b));
+        }
+        same
+    }
+
+    fn same_uncached(&mut self, a: RefType, b: RefType) -> bool {
+        let (Some(ta), Some(tb)) = (self.ty(a), self.ty(b)) else {
+            return false;
+        };
+        match (ta, tb) {
+            (Type::Fun(fa), Type::Fun(fb)) | (Type::Method(fa), Type::Method(fb)) => {
+                fa.args.len() == fb.args.len()
+                    && self.same(fa.ret, fb.ret)
+                    && fa.args.iter().zip(&fb.args).all(|(&x, &y)| self.same(x, y))
+            }
+            (Type::Virtual { fields: fa }, Type::Virtual { fields: fb }) => {
+                fa.len() == fb.len() && self.fields(fa, fb)
+            }
+            (Type::Ref(x), Type::Ref(y))
+            | (Type::Null(x), Type::Null(y))
+            | (Type::Packed(x), Type::Packed(y)) => self.same(*x, *y),
+            (Type::Abstract { name: na }, Type::Abstract { name: nb }) => {
+                self.code.try_get(*na) == self.code.try_get(*nb)
+            }
+            (Type::Obj(_), Type::Obj(_))
+            | (Type::Struct(_), Type::Struct(_))
+            | (Type::Enum { .. }, Type::Enum { .. }) => false,
+            _ => std::mem::discriminant(ta) == std::mem::discriminant(tb),
+        }
+    }
+
+    /// Same names and same types for the fields of `prefix`
+    fn fields(&mut self, prefix: &[ObjField], fields: &[ObjField]) -> bool {
+        prefix.iter().zip(fields).all(|(a, b)| {
+            self.code.try_get(a.name) == self.code.try_get(b.name) && self.same(a.t, b.t)
+        })
+    }
+
+    fn cast(&mut self, from: RefType, to: RefType) -> bool {
+        if from == to {
+            return true;
+        }
+        let (Some(tf), Some(tt)) = (self.ty(from), self.ty(to)) else {
+            return false;
+        };
+        match (tf, tt) {
+            (_, Type::Dyn) => tf.is_dynamic(),
+            (Type::Obj(_), Type::Obj(_)) | (Type::Struct(_), Type::Struct(_)) => {
+                // Walk up the hierarchy, bounded in case of a cycle
+                let mut current = Some(from);
+                for _ in 0..self.code.types.len() {
+                    match current {
+                        Some(t) if t == to => return true,
+                        Some(t) => {
+                            current = self
+                                .ty(t)
+                                .and_then(Type::get_type_obj)
+                                .and_then(|o| o.super_)
+                        }
+                        None => return false,
+                    }
+                }
+                false
+            }
+            (Type::Virtual { fields: ff }, Type::Virtual { fields: ft }) => {
+                ft.len() <= ff.len() && self.fields(ft, ff)
+            }
+            (Type::Fun(ff), Type::Fun(ft)) | (Type::Method(ff), Type::Method(ft)) => {
+                self.fun(ff, ft)
+            }
+            (Type::Null(x), Type::Null(y)) => self.cast(*x, *y),
+            _ => self.same(from, to),
+        }
+    }
+
+    fn fun(&mut self, fun: &TypeFun, expected: &TypeFun) -> bool {
+        fun.args.len() == expected.args.len()
+            && self.cast(fun.ret, expected.ret)
+            // Arguments are contravariant, a function taking dyn accepts any boxed value
+            && fun.args.iter().zip(&expected.args).all(|(&arg, &exp)| {
+                self.cast(exp, arg)
+                    || (matches!(self.ty(arg), Some(Type::Dyn))
+                        && self.ty(exp).map_or(false, Type::is_dynamic))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::shapes;
+    use crate::types::{ObjField, RefType, TypeFun};
+    use crate::Type;
+
+    #[test]
+    fn test_safe_cast() {
+        let mut code = shapes();
+        let i32 = code.intern_type(Type::I32);
+        let dyn_ = code.intern_type(Type::Dyn);
+        let null_i32 = code.intern_type(Type::Null(i32));
+        assert!(!code.safe_cast(i32, dyn_));
+        assert!(code.safe_cast(null_i32, dyn_));
+        assert!(!code.safe_cast(i32, null_i32));
+        assert!(!code.safe_cast(null_i32, i32));
+
+        let [x, y] = ["x", "y"].map(|s| code.intern_string(s));
+        let xy = code.intern_type(Type::Virtual {
+            fields: vec![ObjField { name: x, t: i32 }, ObjField { name: y, t: i32 }],
+        });
+        let x_only = code.intern_type(Type::Virtual {
+            fields: vec![ObjField { name: x, t: i32 }],
+        });
+        assert!(code.safe_cast(xy, x_only));
+        assert!(!code.safe_cast(x_only, xy));
+
+        // (dyn) -> null<i32> can be used as (null<i32>) -> dyn
+        let f = code.intern_type(Type::Fun(TypeFun {
+            args: vec![dyn_],
+            ret: null_i32,
+        }));
+        let g = code.intern_type(Type::Fun(TypeFun {
+            args: vec![null_i32],
+            ret: dyn_,
+        }));
+        assert!(code.safe_cast(f, g));
+        assert!(!code.safe_cast(g, f));
+        assert!(code.same_type(f, f));
+        assert!(!code.same_type(f, g));
+
+        // Square extends Shape
+        let (shape, square) = (RefType(4), RefType(5));
+        assert!(code.safe_cast(square, shape));
+        assert!(!code.safe_cast(shape, square));
+        assert!(code.safe_cast(square, dyn_));
+        assert!(!code.safe_cast(shape, i32));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use crate::{Bytecode, Str};
+
+/// Removing and generating debug information.
+impl Bytecode {
+    /// Remove every debug information : source files, per instruction file and line and variable names.
+    /// The result is the same as a bytecode compiled without `-debug`.
+    pub fn strip_debug(&mut self) {
+        self.debug_files = None;
+        for f in &mut self.functions {
+            f.debug_info = None;
+            f.assigns = None;
+        }
+    }
+
+    /// Attach generated debug information to a bytecode compiled without `-debug`, for tools that require it.
+    /// Does nothing if the bytecode already has debug information.
+    ///
+    /// Each function is attributed to a fake source file named after its class (`pkg/Class.hx`) and each
+    /// instruction is given its own line (its index + 1). No variable names are generated.
+    pub fn synthesize_debug(&mut self) {
+        if self.debug_files.is_some() {
+            return;
+        }
+        let mut files: Vec<Str> = Vec::new();
+        let mut indexes: HashMap<Str, usize> = HashMap::new();
+        let fun_files: Vec<usize> = self
+            .functions
+            .iter()
+            .map(|f| {
+                let file = f
+                    .parent
+                    .and_then(|parent| self[parent].get_type_obj())
+                    // Static functions belong to the `$Class` type
+                    .map(|obj| {
+                        let name = self[obj.name].trim_start_matches('$').replace('.', "/");
+                        Str::from(format!("{name}.hx"))
+                    })
+                    .unwrap_or_else(|| Str::from_static("Unknown.hx"));
+                *indexes.entry(file.clone()).or_insert_with(|| {
+                    files.push(file);
+                    files.len() - 1
+                })
+            })
+            .collect();
+
+        let assigns = self.version >= 3;
+        for (f, file) in self.functions.iter_mut().zip(fun_files) {
+            f.debug_info = Some((0..f.ops.len()).map(|i| (file, i + 1)).collect());
+            if assigns {
+                f.assigns = Some(Vec::new());
+            }
+        }
+        if files.is_empty() {
+            files.push(Str::from_static("Unknown.hx"));
+        }
+        self.debug_files = Some(files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{bytecode, reload, sample};
+    use crate::types::{Function, RefFun, Reg, TypeFun};
+    use crate::{Opcode, Str, Type};
+
+    #[test]
+    fn test_strip_synthesize() {
+        let mut code = sample();
+        code.synthesize_debug();
+        let mut code = reload(&code);
+        assert_eq!(
+            code.debug_files,
+            Some(vec![Str::from("Main.hx"), Str::from("Point.hx")])
+        );
+        assert_eq!(code.functions[1].debug_info, Some(vec![(1, 1), (1, 2)]));
+        assert_eq!(
+            code.functions[2].debug_info,
+            Some(vec![(0, 1), (0, 2), (0, 3)])
+        );
+        assert_eq!(code.functions[3].assigns, Some(vec![]));
+        // Already present
+        code.debug_files = Some(vec![Str::from("Other.hx")]);
+        code.synthesize_debug();
+        assert_eq!(code.debug_files, Some(vec![Str::from("Other.hx")]));
+
+        code.strip_debug();
+        let code = reload(&code);
+        assert!(code.debug_files.is_none());
+        assert!(code
+            .functions
+            .iter()
+            .all(|f| f.debug_info.is_none() && f.assigns.is_none()));
+    }
+
+    #[test]
+    fn test_var_names() {
+        let mut code = bytecode(&[""], Vec::new(), "");
+        let i32 = code.intern_type(Type::I32);
+        let void = code.intern_type(Type::Void);
+        let t = code.intern_type(Type::Fun(TypeFun {
+            args: vec![i32],
+            ret: void,
+        }));
+        let ptr = code.intern_int(1);
+        let [a, x, y] = ["a", "x", "y"].map(|s| code.intern_string(s));
+        let f = Function {
+            t,
+            findex: RefFun(0),
+            regs: vec![i32; 3],
+            ops: vec![
+                Opcode::Int { dst: Reg(1), ptr },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+            debug_info: None,
+            assigns: Some(vec![(a, 0), (x, 1), (y, 2)]),
+            name: a,
+            parent: None,
+        };
+        assert_eq!(f.arg_name(&code, 0).as_deref(), Some("a"));
+        assert_eq!(f.var_name(&code, 0, Reg(0)).as_deref(), Some("a"));
+        assert_eq!(f.var_name(&code, 0, Reg(1)).as_deref(), Some("x"));
+        assert_eq!(f.var_name(&code, 2, Reg(1)).as_deref(), Some("y"));
+        assert_eq!(f.var_name(&code, 2, Reg(2)), None);
+        assert_eq!(f.assigned_name(&code, 1).as_deref(), Some("y"));
+        assert_eq!(f.assigned_name(&code, 2), None);
+    }
+}
@@ -0,0 +1,408 @@
+use std::ops::Range;
+
+use crate::types::{Function, JumpOffset, JumpTarget, RefFun};
+use crate::{Bytecode, Error, Opcode, ResolveMut, Result};
+
+/// Editing the instructions of a function.
+///
+/// Jump offsets are relative to the instruction, inserting or removing instructions would break every jump over the
+/// edited range. Those methods update the offsets of the other instructions (jumps, `Switch` tables and `Trap`
+/// handlers), the debug info and the variable assignments.
+impl Function {
+    /// Replace the instructions in `range` with `ops` and return the removed instructions.
+    ///
+    /// The offsets of the new instructions are kept as is, they are relative to their position in the new function.
+    /// Jumps into the removed range land on the first new instruction, as do jumps to `range.start` when nothing
+    /// is removed. The new instructions take the debug info of the first replaced instruction (or the previous one).
+    ///
+    /// ### Panics
+    /// Panics if the range is out of bounds.
+    pub fn splice_ops(
+        &mut self,
+        range: Range<usize>,
+        ops: impl IntoIterator<Item = Opcode>,
+    ) -> Vec<Opcode> {
+        let Range { start, end } = range;
+        assert!(
+            start <= end && end <= self.ops.len(),
+            "invalid range {start}..{end} for a function with {} instructions",
+            self.ops.len()
+        );
+        let new: Vec<Opcode> = ops.into_iter().collect();
+        let inserted = new.len();
+        let delta = inserted as i64 - (end - start) as i64;
+        // Position of an instruction outside the range in the new function
+        let moved = |pos: usize| {
+            if pos < end {
+                pos as i64
+            } else {
+                pos as i64 + delta
+            }
+        };
+        // Position of a jump target in the new function
+        let target = |t: i64| {
+            if t <= start as i64 {
+                t
+            } else if t < end as i64 {
+                start as i64
+            } else {
+                t + delta
+            }
+        };
+        for (pos, op) in self.ops.iter_mut().enumerate() {
+            if (start..end).contains(&pos) {
+                continue;
+            }
+            let new_pos = moved(pos);
+            for offset in op.offsets_mut() {
+                let t = pos as i64 + *offset as i64 + 1;
+                *offset = (target(t) - new_pos - 1) as JumpOffset;
+            }
+        }
+
+        if let Some(debug_info) = &mut self.debug_info {
+            let fill = debug_info
+                .get(start)
+                .or_else(|| start.checked_sub(1).and_then(|i| debug_info.get(i)))
+                .copied();
+            if let Some(fill) = fill {
+                let end = end.min(debug_info.len());
+                let start = start.min(end);
+                debug_info.splice(start..end, std::iter::repeat(fill).take(inserted));
+            }
+        }
+        if let Some(assigns) = &mut self.assigns {
+            // Assigns are stored with the position of the instruction + 1, 0 for the arguments
+            assigns.retain(|&(_, i)| i == 0 || !(start..end).contains(&(i - 1)));
+            for (_, i) in assigns.iter_mut() {
+                if *i > end {
+                    *i = (*i as i64 + delta) as usize;
+                }
+            }
+        }
+
+        self.ops.splice(start..end, new).collect()
+    }
+
+    /// Like [Function::splice_ops], with the offsets of some jumps of the new instructions computed from their
+    /// target. Each target is given as (index in `ops`, index of the offset in [Opcode::offsets_mut], target), the
+    /// other offsets are kept as is.
+    ///
+    /// Fails without changing the function when a target doesn't exist.
+    ///
+    /// ### Panics
+    /// Panics if the range is out of bounds.
+    pub fn splice_ops_with_targets(
+        &mut self,
+        range: Range<usize>,
+        ops: impl IntoIterator<Item = Opcode>,
+        targets: &[(usize, usize, JumpTarget)],
+    ) -> Result<Vec<Opcode>> {
+        let Range { start, end } = range;
+        let mut ops: Vec<Opcode> = ops.into_iter().collect();
+        let inserted = ops.len();
+        for &(i, slot, target) in targets {
+            // Position of the target in the new function
+            let pos = match target {
+                JumpTarget::Local(t) if t <= inserted => start + t,
+                JumpTarget::Function(t) if t < start => t,
+                JumpTarget::Function(t) if t >= end && t <= self.ops.len() => {
+                    t - (end - start) + inserted
+                }
+                _ => {
+                    return Err(Error::InvalidEdit(format!(
+                        "invalid jump target {target:?} for {start}..{end}"
+                    )))
+                }
+            };
+            let offset = ops
+                .get_mut(i)
+                .and_then(|op| op.offsets_mut().into_iter().nth(slot))
+                .ok_or_else(|| {
+                    Error::InvalidEdit(format!("instruction {i} has no jump offset {slot}"))
+                })?;
+            *offset = (pos as i64 - (start + i) as i64 - 1) as JumpOffset;
+        }
+        Ok(self.splice_ops(start..end, ops))
+    }
+
+    /// Insert instructions before the instruction at `pos`, jumps to `pos` execute the new instructions first
+    pub fn insert_ops(&mut self, pos: usize, ops: impl IntoIterator<Item = Opcode>) {
+        self.splice_ops(pos..pos, ops);
+    }
+
+    /// Remove instructions, jumps into the removed range land on the instruction following it
+    pub fn remove_ops(&mut self, range: Range<usize>) -> Vec<Opcode> {
+        self.splice_ops(range, [])
+    }
+}
+
+/// Patching instructions in place.
+///
+/// Unlike [Function::splice_ops], the number of instructions never changes : every instruction keeps its index, which
+/// is what the debug info, the assigns and external tools referencing instructions by index rely on.
+impl Function {
+    /// Replace the instruction at `pos` with a `Nop`, returns the previous instruction
+    pub fn patch_nop(&mut self, pos: usize) -> Opcode {
+        std::mem::replace(&mut self.ops[pos], Opcode::Nop)
+    }
+
+    /// Replace every instruction in `range` with a `Nop`, returns the previous instructions
+    pub fn patch_nops(&mut self, range: Range<usize>) -> Vec<Opcode> {
+        self.ops[range]
+            .iter_mut()
+            .map(|op| std::mem::replace(op, Opcode::Nop))
+            .collect()
+    }
+
+    /// Replace the instructions in `range` with `ops`, padding with `Nop` when there are fewer instructions than the
+    /// range length. Returns the previous instructions.
+    /// Jump offsets of `ops` are relative to their position in the function, starting at `range.start`.
+    pub fn patch_in_place(
+        &mut self,
+        range: Range<usize>,
+        ops: impl IntoIterator<Item = Opcode>,
+    ) -> Result<Vec<Opcode>> {
+        let ops: Vec<Opcode> = ops.into_iter().collect();
+        if ops.len() > range.len() {
+            return Err(Error::InvalidEdit(format!(
+                "{} instructions do not fit in {} instructions",
+                ops.len(),
+                range.len()
+            )));
+        }
+        let padding = range.len() - ops.len();
+        Ok(self
+            .ops
+            .splice(
+                range,
+                ops.into_iter()
+                    .chain(std::iter::repeat(Opcode::Nop).take(padding)),
+            )
+            .collect())
+    }
+
+    /// Make the conditional jump at `pos` always (`taken`) or never jump.
+    /// Returns the previous instruction, or None if it isn't a conditional jump.
+    pub fn patch_branch(&mut self, pos: usize, taken: bool) -> Option<Opcode> {
+        let op = &self.ops[pos];
+        if matches!(op, Opcode::JAlways { .. } | Opcode::Trap { .. }) {
+            return None;
+        }
+        let &[target] = op.jump_targets(pos).as_slice() else {
+            return None;
+        };
+        let patched = if taken {
+            Opcode::JAlways {
+                offset: target as JumpOffset - pos as JumpOffset - 1,
+            }
+        } else {
+            Opcode::Nop
+        };
+        Some(std::mem::replace(&mut self.ops[pos], patched))
+    }
+}
+
+/// Copying function bodies inside a bytecode. See [crate::link::Linker] to copy them from another bytecode.
+impl Bytecode {
+    /// Replace the body (registers, instructions, debug info and assigns) of the function `dst` with the body of `src`.
+    /// The name, parent and findex of `dst` are kept. Both functions must have the same type.
+    ///
+    /// `src` must reference the pools of this bytecode.
+    pub fn replace_function_body(&mut self, dst: RefFun, src: &Function) -> Result<()> {
+        let t = match dst.as_fn(self) {
+            Some(f) => f.t,
+            None => return Err(Error::InvalidEdit(format!("fn@{} is a native", dst.0))),
+        };
+        if !self.same_type(t, src.t) {
+            return Err(Error::InvalidEdit(format!(
+                "fn@{} and fn@{} have different types",
+                dst.0, src.findex.0
+            )));
+        }
+        let has_debug = self.debug_files.is_some();
+        let f = self.get_mut(dst).unwrap();
+        f.regs = src.regs.clone();
+        f.ops = src.ops.clone();
+        f.debug_info = src.debug_info.clone().filter(|_| has_debug);
+        f.assigns = src.assigns.clone().filter(|_| has_debug);
+        Ok(())
+    }
+
+    /// Copy a function under a new findex, returns the findex of the copy.
+    /// The copy isn't referenced anywhere, e.g. it isn't a method of its parent type.
+    pub fn clone_function(&mut self, src: RefFun) -> Result<RefFun> {
+        let Some(f) = src.as_fn(self) else {
+            return Err(Error::InvalidEdit(format!("fn@{} is a native", src.0)));
+        };
+        let findex = RefFun(self.functions.len() + self.natives.len());
+        let copy = Function {
+            findex,
+            ..f.clone()
+        };
+        self.functions.push(copy);
+        self.rebuild_indexes();
+        Ok(findex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{reload, sample};
+    use crate::types::{Function, JumpTarget, Native, RefFun, RefString, RefType, Reg};
+    use crate::Opcode;
+
+    fn function(ops: Vec<Opcode>) -> Function {
+        Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![RefType(0)],
+            debug_info: Some((0..ops.len()).map(|i| (0, i + 1)).collect()),
+            assigns: Some(vec![(RefString(1), 0), (RefString(2), 5)]),
+            ops,
+            name: RefString(0),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn test_splice_ops() {
+        let mut f = function(vec![
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 2,
+            },
+            Opcode::Nop,
+            Opcode::Nop,
+            Opcode::Label,
+            Opcode::Incr { dst: Reg(0) },
+            Opcode::JAlways { offset: -3 },
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        let targets = |f: &Function| -> Vec<Vec<usize>> {
+            f.ops
+                .iter()
+                .enumerate()
+                .map(|(i, op)| op.jump_targets(i))
+                .collect()
+        };
+        assert_eq!(targets(&f)[0], vec![3]);
+        assert_eq!(targets(&f)[5], vec![3]);
+
+        // Remove the two Nop
+        let removed = f.remove_ops(1..3);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(targets(&f)[0], vec![1]);
+        assert_eq!(targets(&f)[3], vec![1]);
+        assert_eq!(f.debug_info.as_ref().unwrap().len(), f.ops.len());
+        // Incr was at 4, now at 2
+        assert_eq!(f.assigns.as_ref().unwrap()[1].1, 3);
+
+        // Jumps to the label now execute the inserted instruction first
+        f.insert_ops(1, [Opcode::Nop]);
+        assert_eq!(targets(&f)[0], vec![1]);
+        assert_eq!(targets(&f)[4], vec![1]);
+        assert!(matches!(f.ops[2], Opcode::Label));
+        assert_eq!(f.debug_info.as_ref().unwrap()[1], (0, 4));
+        assert_eq!(f.assigns.as_ref().unwrap()[1].1, 4);
+    }
+
+    #[test]
+    fn test_splice_ops_with_targets() -> crate::Result<()> {
+        let mut f = function(vec![
+            Opcode::Label,
+            Opcode::Nop,
+            Opcode::Nop,
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        let jump = Opcode::JAlways { offset: 0 };
+        f.splice_ops_with_targets(
+            1..2,
+            [jump.clone(), jump.clone(), jump.clone()],
+            &[
+                (0, 0, JumpTarget::Function(0)),
+                (1, 0, JumpTarget::Function(3)),
+                (2, 0, JumpTarget::Local(3)),
+            ],
+        )?;
+        let targets: Vec<_> = (1..4).map(|i| f.ops[i].jump_targets(i)).collect();
+        assert_eq!(targets, vec![vec![0], vec![5], vec![4]]);
+
+        // Removed instruction
+        let ops = format!("{:?}", f.ops);
+        assert!(f
+            .splice_ops_with_targets(1..2, [jump.clone()], &[(0, 0, JumpTarget::Function(1))])
+            .is_err());
+        assert!(f
+            .splice_ops_with_targets(1..2, [Opcode::Nop], &[(0, 0, JumpTarget::Local(0))])
+            .is_err());
+        assert_eq!(format!("{:?}", f.ops), ops);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_function() -> crate::Result<()> {
+        let mut code = sample();
+        code.natives = vec![Native {
+            name: RefString(7),
+            lib: RefString(7),
+            t: RefType(2),
+            findex: RefFun(4),
+        }];
+        let mut code = reload(&code);
+        let main = code.main().findex;
+        let copy = code.clone_function(main)?;
+        assert_eq!(copy, RefFun(5));
+        assert_eq!(code.functions.len(), 5);
+        assert_eq!(
+            format!("{:?}", copy.as_fn(&code).unwrap().ops),
+            format!("{:?}", code.main().ops)
+        );
+
+        // Empty the copy then restore it
+        let mut empty = code.main().clone();
+        empty.ops = vec![Opcode::Ret { ret: Reg(0) }];
+        empty.debug_info = empty.debug_info.map(|d| d[..1].to_vec());
+        code.replace_function_body(copy, &empty)?;
+        assert_eq!(copy.as_fn(&code).unwrap().ops.len(), 1);
+        let main = code.main().clone();
+        code.replace_function_body(copy, &main)?;
+        assert_eq!(copy.as_fn(&code).unwrap().ops.len(), main.ops.len());
+
+        let native = RefFun(4);
+        assert!(code.clone_function(native).is_err());
+        assert!(code.replace_function_body(native, &main).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_in_place() -> crate::Result<()> {
+        let mut f = function(vec![
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 2,
+            },
+            Opcode::Incr { dst: Reg(0) },
+            Opcode::Incr { dst: Reg(0) },
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        let len = f.ops.len();
+        let jump = f.patch_branch(0, true).unwrap();
+        assert!(matches!(jump, Opcode::JFalse { .. }));
+        assert_eq!(f.ops[0].jump_targets(0), vec![3]);
+        assert!(f.patch_branch(0, false).is_none());
+        assert!(f.patch_branch(1, false).is_none());
+
+        assert!(matches!(f.patch_nop(1), Opcode::Incr { .. }));
+        assert!(f
+            .patch_in_place(1..3, [Opcode::Decr { dst: Reg(0) }])?
+            .iter()
+            .any(|op| matches!(op, Opcode::Incr { .. })));
+        assert!(matches!(f.ops[1], Opcode::Decr { .. }));
+        assert!(matches!(f.ops[2], Opcode::Nop));
+        assert!(f.patch_in_place(3..4, [Opcode::Nop, Opcode::Nop]).is_err());
+        assert_eq!(f.patch_nops(0..2).len(), 2);
+        assert_eq!(f.ops.len(), len);
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Bytecode embedded in HashLink executables.
+//!
+//! Games often ship the bytecode inside the executable (appended to it or stored as a resource) instead of a
+//! `hlboot.dat` next to it. The executable format (PE, ELF, Mach-O) doesn't matter : the input is scanned for the
+//! `HLB` magic header followed by a supported version, and each candidate is decoded until one succeeds. An `HLB`
+//! appearing by chance in the machine code or the data of the executable can't be decoded and is skipped.
+
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{Bytecode, Error, Result};
+
+/// A module found in a larger input
+#[derive(Debug)]
+pub struct EmbeddedModule {
+    pub code: Bytecode,
+    /// Position of the module in the input, these bytes can be saved as a standalone `hlboot.dat`
+    pub range: Range<usize>,
+}
+
+/// Offsets of the magic headers followed by a supported version
+pub fn candidates(data: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    memchr::memmem::find_iter(data, b"HLB").filter(|&i| matches!(data.get(i + 3), Some(4 | 5)))
+}
+
+/// Find and decode the first module of the input
+pub fn extract(data: &[u8]) -> Result<EmbeddedModule> {
+    let mut first_error = None;
+    for start in candidates(data) {
+        match Bytecode::deserialize_module(&data[start..]) {
+            Ok((code, len)) => {
+                return Ok(EmbeddedModule {
+                    code,
+                    range: start..start + len,
+                });
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error
+        .unwrap_or_else(|| Error::MalformedBytecode("No embedded bytecode found".to_string())))
+}
+
+/// Find and decode every module of the input, candidates inside a decoded module are skipped
+pub fn extract_all(data: &[u8]) -> Vec<EmbeddedModule> {
+    let mut modules: Vec<EmbeddedModule> = Vec::new();
+    for start in candidates(data) {
+        if modules.last().map_or(false, |m| start < m.range.end) {
+            continue;
+        }
+        if let Ok((code, len)) = Bytecode::deserialize_module(&data[start..]) {
+            modules.push(EmbeddedModule {
+                code,
+                range: start..start + len,
+            });
+        }
+    }
+    modules
+}
+
+impl Bytecode {
+    /// Load the bytecode embedded in an executable, see [crate::embedded].
+    /// A bytecode file is loaded too.
+    pub fn from_executable(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(extract(&fs::read(path)?)?.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedded::{extract, extract_all};
+    use crate::testing::sample;
+
+    #[test]
+    fn test_extract() -> crate::Result<()> {
+        let mut module = Vec::new();
+        sample().serialize(&mut module)?;
+        // Fake executable with a decoy header in its code and the module appended
+        let mut exe = b"MZ\x90\x00 code HLB\x04\xff\xff\xff\xff data".to_vec();
+        let start = exe.len();
+        exe.extend_from_slice(&module);
+        exe.extend_from_slice(b"trailer");
+
+        let extracted = extract(&exe)?;
+        assert_eq!(extracted.range, start..start + module.len());
+        assert_eq!(extracted.code.functions.len(), 4);
+        assert_eq!(extracted.code.main().name(&extracted.code), "main");
+        assert_eq!(extract_all(&exe).len(), 1);
+        assert!(extract(b"no bytecode here").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoys() {
+        // Headers followed by random bytes, their lengths and counts are garbage
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        };
+        for i in 0..20_000 {
+            let mut data = vec![b'H', b'L', b'B', 4 + (i % 2) as u8];
+            let len = next() as usize * 4;
+            data.extend((0..len).map(|_| next()));
+            let _ = extract(&data);
+        }
+    }
+}
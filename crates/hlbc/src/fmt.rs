@@ -4,20 +4,39 @@
 //! - [DebugFmt]: based on the [Debug] impl.
 //! - [DisplayFmt]: based on the [Display] impl. This formatting can't access the [Bytecode] context and is limited.
 //! - [EnhancedFmt]: Advanced formatter for showing the bytecode with the most help for the reader.
+//! - [HtmlFmt]: Hyperlinked and colored HTML, for reports.
+//! - [AnsiFmt]: [EnhancedFmt] colored for terminals.
+//! - [AsmFmt]: Canonical assembly which can be parsed back, see [asm].
+//!
+//! The disassembly of a whole module can be written to a directory tree with [tree::write_tree].
 
 use std::fmt::{Debug, Display, Formatter, Result};
 use std::iter::repeat;
+use std::rc::Rc;
+use std::sync::Arc;
 
+pub use ansi::AnsiFmt;
+pub use asm::AsmFmt;
 pub use fmtools::fmt;
+pub use html::HtmlFmt;
 
+use crate::analysis::devirt::closure_source;
+use crate::analysis::generics::demangle;
+use crate::analysis::infer::InferredTypes;
 use crate::opcodes::Opcode;
+use crate::rename::Symbol;
 use crate::types::{
-    FunPtr, Function, Native, RefEnumConstruct, RefField, RefFloat, RefGlobal, RefInt, RefString,
-    RefType, Reg, Type, TypeFun, TypeObj,
+    ConstantDef, ConstantValue, FunPtr, Function, Native, RefBytes, RefEnumConstruct, RefField,
+    RefFloat, RefGlobal, RefInt, RefString, RefType, Reg, Type, TypeFun, TypeObj,
 };
-use crate::Resolve;
+use crate::{Resolve, TryResolve};
 use crate::{Bytecode, RefFun};
 
+mod ansi;
+pub mod asm;
+mod html;
+pub mod tree;
+
 //region Display impls
 
 impl Display for Reg {
@@ -44,6 +63,12 @@ impl Display for RefString {
     }
 }
 
+impl Display for RefBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "@{}", self.0)
+    }
+}
+
 impl Display for RefType {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         // We can already know the type for some of them
@@ -68,6 +93,19 @@ impl Display for RefField {
     }
 }
 
+impl Display for ConstantValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            ConstantValue::Int(i) => Display::fmt(i, f),
+            ConstantValue::Float(fl) => Display::fmt(fl, f),
+            ConstantValue::Bool(b) => Display::fmt(b, f),
+            ConstantValue::String(s) => Debug::fmt(&**s, f),
+            ConstantValue::Type(t) => write!(f, "type{t}"),
+            ConstantValue::Global(g) => write!(f, "global{g}"),
+        }
+    }
+}
+
 impl Display for RefEnumConstruct {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "<construct{}>", self.0)
@@ -304,8 +342,133 @@ pub struct DisplayFmt;
 
 impl BytecodeFmt for DisplayFmt {}
 
+/// Rendering options of [EnhancedFmt] and [Opcode::display_opts]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FmtOptions {
+    /// Show the file and line of each instruction when the bytecode has debug information
+    pub debug_info: bool,
+    /// Show the type of a register after the first instruction using it
+    pub reg_types: bool,
+    /// Show the value of the constants instead of their index
+    pub constants: bool,
+    /// Show the position of each instruction
+    pub indices: bool,
+    /// Width of the opcode name column
+    pub align: usize,
+    /// Maximum width of an instruction, longer operand lists (call arguments, switch offsets) are wrapped on multiple
+    /// lines aligned with their first element. 0 to never wrap.
+    pub width: usize,
+    /// Maximum number of elements shown in an operand list, the others are elided. 0 to show them all.
+    pub max_list: usize,
+    /// Abbreviate virtual types to a synthetic name `AnonN` (N being the type index) instead of their fields, see
+    /// [EnhancedFmt::expand] for their definition
+    pub short_virtuals: bool,
+    /// Show the instantiations of generic classes as `Container<Int>` instead of their mangled name `Container_Int`,
+    /// see [demangle]
+    pub demangle: bool,
+    /// Append a `; comment` to the instructions with the context resolved from their operands, see
+    /// [Opcode::comment]
+    pub comments: bool,
+}
+
+impl FmtOptions {
+    pub const DEFAULT: FmtOptions = FmtOptions {
+        debug_info: true,
+        reg_types: false,
+        constants: true,
+        indices: true,
+        align: 11,
+        width: 0,
+        max_list: 0,
+        short_virtuals: false,
+        demangle: false,
+        comments: false,
+    };
+}
+
+impl Default for FmtOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 #[derive(Copy, Clone, Default)]
-pub struct EnhancedFmt;
+pub struct EnhancedFmt {
+    pub options: FmtOptions,
+}
+
+impl EnhancedFmt {
+    pub fn new(options: FmtOptions) -> Self {
+        Self { options }
+    }
+
+    /// Definition of a type abbreviated with [FmtOptions::short_virtuals], its fields are displayed with the same
+    /// options so nested virtual types are expanded on demand too
+    pub fn expand<'a>(&'a self, ctx: &'a Bytecode, t: RefType) -> impl Display + 'a {
+        fmt(move |f| match &ctx[t] {
+            ty @ Type::Virtual { .. } => EnhancedFmt::new(FmtOptions {
+                short_virtuals: false,
+                ..self.options
+            })
+            .fmt_virtual(f, ctx, ty, self),
+            ty => self.fmt_type(f, ctx, ty),
+        })
+    }
+
+    /// Name of a class, demangled if enabled and the class hasn't been renamed
+    fn fmt_obj_name(&self, f: &mut Formatter, ctx: &Bytecode, obj: &TypeObj) -> Result {
+        if self.options.demangle && ctx.renames.get(Symbol::Type(obj.name)).is_none() {
+            if let Some(name) = demangle(&ctx[obj.name]) {
+                return f.write_str(&name);
+            }
+        }
+        f.write_str(&obj.name(ctx))
+    }
+
+    /// A virtual type with its fields, whose types are displayed with `fields`
+    fn fmt_virtual(
+        &self,
+        f: &mut Formatter,
+        ctx: &Bytecode,
+        v: &Type,
+        fields: &EnhancedFmt,
+    ) -> Result {
+        let Type::Virtual { fields: vfields } = v else {
+            return self.fmt_type(f, ctx, v);
+        };
+        if self.options.short_virtuals {
+            return match ctx.types.iter().position(|t| std::ptr::eq(t, v)) {
+                Some(i) => write!(f, "Anon{i}"),
+                None => write!(f, "virtual<{} fields>", vfields.len()),
+            };
+        }
+        f.write_str("virtual<")?;
+        for (i, fi) in vfields.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            self.fmt_refstring(f, ctx, fi.name)?;
+            f.write_str(": ")?;
+            match &ctx[fi.t] {
+                t @ Type::Virtual { .. } if fields.options.short_virtuals => {
+                    fields.fmt_type(f, ctx, t)?
+                }
+                Type::Virtual { .. } => write!(f, "{v}{}", fi.t)?,
+                Type::Fun(fun) | Type::Method(fun) => write!(f, "{fun}{}", fi.t)?,
+                t => fields.fmt_type(f, ctx, t)?,
+            }
+        }
+        f.write_str(">")
+    }
+
+    /// Name of a type (enum, abstract) taking renames into account
+    fn fmt_named(&self, f: &mut Formatter, ctx: &Bytecode, name: RefString) -> Result {
+        match ctx.renames.get(Symbol::Type(name)) {
+            Some(name) => f.write_str(&name),
+            None => self.fmt_refstring(f, ctx, name),
+        }
+    }
+}
 
 impl BytecodeFmt for EnhancedFmt {
     fn fmt_refint(&self, f: &mut Formatter, ctx: &Bytecode, v: RefInt) -> Result {
@@ -323,8 +486,9 @@ impl BytecodeFmt for EnhancedFmt {
     fn fmt_reftype(&self, f: &mut Formatter, ctx: &Bytecode, v: RefType) -> Result {
         let ty = &ctx[v];
         self.fmt_type(f, ctx, ty)?;
-        // No need to display @number if type is known
-        if !v.is_known() && !ty.is_wrapper_type() {
+        // No need to display @number if type is known, abbreviated virtuals already hold it
+        let short = self.options.short_virtuals && matches!(ty, Type::Virtual { .. });
+        if !v.is_known() && !ty.is_wrapper_type() && !short {
             Display::fmt(&v, f)?
         }
         Ok(())
@@ -338,7 +502,9 @@ impl BytecodeFmt for EnhancedFmt {
         parent: &Type,
     ) -> Result {
         if let Some(obj) = parent.get_type_obj() {
-            if v.0 < obj.fields.len() {
+            if let Some(name) = ctx.renames.get(Symbol::Field(obj.name, v)) {
+                f.write_str(&name)
+            } else if v.0 < obj.fields.len() {
                 self.fmt_refstring(f, ctx, obj.fields[v.0].name)
             } else {
                 // panic!(
@@ -349,7 +515,10 @@ impl BytecodeFmt for EnhancedFmt {
                 Display::fmt(&v, f)
             }
         } else if let Type::Virtual { fields } = parent {
-            self.fmt_refstring(f, ctx, fields[v.0].name)
+            match fields.get(v.0) {
+                Some(field) => f.write_str(&field.virtual_name(ctx)),
+                None => Display::fmt(&v, f),
+            }
         } else {
             Display::fmt(&v, f)
         }
@@ -378,34 +547,17 @@ impl BytecodeFmt for EnhancedFmt {
     fn fmt_type(&self, f: &mut Formatter, ctx: &Bytecode, v: &Type) -> Result {
         match v {
             Type::Fun(fun) => self.fmt_typefun(f, ctx, fun),
-            Type::Obj(TypeObj { name, .. }) => self.fmt_refstring(f, ctx, *name),
+            Type::Obj(obj) => self.fmt_obj_name(f, ctx, obj),
             Type::Ref(reftype) => fmtools::write!(f,
                 "ref<"
                 |f| self.fmt_type(f, ctx, &ctx[*reftype])?;
                 ">"
             ),
-            Type::Virtual { fields } => fmtools::write!(f,
-                "virtual<"{fmtools::join(", ", fields.iter().map(|fi|
-                    fmtools::fmt!{
-                        |f| self.fmt_refstring(f, ctx, fi.name)?;": "
-                        match &ctx[fi.t] {
-                            Type::Virtual {..} => {
-                                {v}{fi.t}
-                            }
-                            Type::Fun(fun) | Type::Method(fun) => {
-                                {fun}{fi.t}
-                            }
-                            _ => {
-                                |f| self.fmt_type(f, ctx, &ctx[fi.t])?;
-                            }
-                        }
-                    }
-                ))}">"
-            ),
-            Type::Abstract { name } => self.fmt_refstring(f, ctx, *name),
+            Type::Virtual { .. } => self.fmt_virtual(f, ctx, v, self),
+            Type::Abstract { name } => self.fmt_named(f, ctx, *name),
             Type::Enum { name, .. } => fmtools::write!(f,
                 "enum<"
-                |f| self.fmt_refstring(f, ctx, *name)?;
+                |f| self.fmt_named(f, ctx, *name)?;
                 ">"
             ),
             Type::Null(reftype) => fmtools::write!(f,
@@ -414,7 +566,7 @@ impl BytecodeFmt for EnhancedFmt {
                 ">"
             ),
             Type::Method(fun) => self.fmt_typefun(f, ctx, fun),
-            Type::Struct(TypeObj { name, .. }) => self.fmt_refstring(f, ctx, *name),
+            Type::Struct(obj) => self.fmt_obj_name(f, ctx, obj),
             Type::Packed(reftype) => fmtools::write!(f,
                 "packed<"
                 |f| self.fmt_reftype(f, ctx, *reftype)?;
@@ -456,34 +608,229 @@ impl BytecodeFmt for EnhancedFmt {
     }
 
     fn fmt_function(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
-        write!(
-            f,
-            "{}",
-            fmtools::fmt! {
-                |f| self.fmt_function_header(f, ctx, v)?;" ("{v.regs.len()}" regs, "{v.ops.len()}" ops)\n"
-                for (i, reg) in v.regs.iter().enumerate() {
-                    "    reg"{i:<2}" "|f| self.fmt_type(f, ctx, &ctx[*reg])?;"\n"
+        let opts = &self.options;
+        self.fmt_function_header(f, ctx, v)?;
+        writeln!(f, " ({} regs, {} ops)", v.regs.len(), v.ops.len())?;
+        for (i, reg) in v.regs.iter().enumerate() {
+            write!(f, "    reg{i:<2} ")?;
+            self.fmt_type(f, ctx, &ctx[*reg])?;
+            writeln!(f)?;
+        }
+        for r in v.trap_regions() {
+            write!(f, "    {}trap {}..", "  ".repeat(r.depth), r.trap)?;
+            match r.end {
+                Some(end) => write!(f, "{end}")?,
+                None => f.write_str("?")?,
+            }
+            writeln!(f, " {} handler {}", r.exc, r.handler)?;
+        }
+        let debug = v.debug_info.as_ref().filter(|_| opts.debug_info);
+        // Registers already annotated with their type
+        let mut seen = vec![false; v.regs.len()];
+        for (i, o) in v.ops.iter().enumerate() {
+            // Width of the columns before the instruction, continuation lines of a wrapped instruction are indented by
+            // this much
+            let mut prefix = 0;
+            if let Some(debug) = debug {
+                let Some((file, line)) = debug.get(i) else {
+                    break;
+                };
+                let column = format!(
+                    "{:>12}:{line:<3} ",
+                    ctx.debug_file(*file).unwrap_or_default()
+                );
+                prefix += column.chars().count();
+                f.write_str(&column)?;
+            }
+            if opts.indices {
+                let column = format!("{i:>3}: ");
+                prefix += column.len();
+                f.write_str(&column)?;
+            }
+            let op = o.display_opts(ctx, v, i as i32, opts).to_string();
+            for (j, line) in op.split('\n').enumerate() {
+                if j > 0 {
+                    write!(f, "\n{:prefix$}", "")?;
                 }
-                if let Some(debug) = &v.debug_info {
-                    for ((i, o), (file, line)) in v.ops
-                        .iter()
-                        .enumerate()
-                        .zip(debug.iter())
-                    {
-                        {ctx.debug_files.as_ref().unwrap()[*file]:>12}":"{line:<3}" "{i:>3}": "{o.display(ctx, v, i as i32, 11)}"\n"
-                    }
-                } else {
-                    for (i, o) in v.ops
-                        .iter()
-                        .enumerate() {
-                        {i:>3}": "{o.display(ctx, v, i as i32, 11)}"\n"
+                f.write_str(line)?;
+            }
+            if opts.reg_types {
+                let first: Vec<Reg> = o
+                    .defs()
+                    .into_iter()
+                    .chain(o.uses())
+                    .filter(|r| {
+                        seen.get_mut(r.0 as usize)
+                            .map_or(false, |s| !std::mem::replace(s, true))
+                    })
+                    .collect();
+                if !first.is_empty() {
+                    f.write_str(if op.contains("//") { ", " } else { " // " })?;
+                    for (j, r) in first.into_iter().enumerate() {
+                        if j > 0 {
+                            f.write_str(", ")?;
+                        }
+                        write!(f, "{r}: ")?;
+                        self.fmt_type(f, ctx, &ctx[v[r]])?;
                     }
                 }
             }
-        )
+            // Constants shown inline don't need to be repeated
+            let inline = opts.constants
+                && matches!(
+                    o,
+                    Opcode::Int { .. }
+                        | Opcode::Float { .. }
+                        | Opcode::String { .. }
+                        | Opcode::Bytes { .. }
+                );
+            if opts.comments && !inline {
+                if let Some(comment) = o.comment(ctx, v, i) {
+                    write!(f, " ; {comment}")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
     }
 }
 
+//region Dynamic formatters
+// Formatters used through references and smart pointers, so a `&dyn BytecodeFmt` chosen at runtime can be passed
+// wherever a formatter is expected.
+
+macro_rules! delegate_fmt {
+    ($($ptr:ty),*) => {$(
+        impl<T: BytecodeFmt + ?Sized> BytecodeFmt for $ptr {
+            fn fmt_reg(&self, f: &mut Formatter, ctx: &Bytecode, v: Reg) -> Result {
+                (**self).fmt_reg(f, ctx, v)
+            }
+
+            fn fmt_refint(&self, f: &mut Formatter, ctx: &Bytecode, v: RefInt) -> Result {
+                (**self).fmt_refint(f, ctx, v)
+            }
+
+            fn fmt_reffloat(&self, f: &mut Formatter, ctx: &Bytecode, v: RefFloat) -> Result {
+                (**self).fmt_reffloat(f, ctx, v)
+            }
+
+            fn fmt_refstring(&self, f: &mut Formatter, ctx: &Bytecode, v: RefString) -> Result {
+                (**self).fmt_refstring(f, ctx, v)
+            }
+
+            fn fmt_reftype(&self, f: &mut Formatter, ctx: &Bytecode, v: RefType) -> Result {
+                (**self).fmt_reftype(f, ctx, v)
+            }
+
+            fn fmt_reffield(
+                &self,
+                f: &mut Formatter,
+                ctx: &Bytecode,
+                v: RefField,
+                parent: &Type,
+            ) -> Result {
+                (**self).fmt_reffield(f, ctx, v, parent)
+            }
+
+            fn fmt_refenumconstruct(
+                &self,
+                f: &mut Formatter,
+                ctx: &Bytecode,
+                v: RefEnumConstruct,
+                parent: &Type,
+            ) -> Result {
+                (**self).fmt_refenumconstruct(f, ctx, v, parent)
+            }
+
+            fn fmt_type(&self, f: &mut Formatter, ctx: &Bytecode, v: &Type) -> Result {
+                (**self).fmt_type(f, ctx, v)
+            }
+
+            fn fmt_typefun(&self, f: &mut Formatter, ctx: &Bytecode, v: &TypeFun) -> Result {
+                (**self).fmt_typefun(f, ctx, v)
+            }
+
+            fn fmt_reffun(&self, f: &mut Formatter, ctx: &Bytecode, v: RefFun) -> Result {
+                (**self).fmt_reffun(f, ctx, v)
+            }
+
+            fn fmt_native(&self, f: &mut Formatter, ctx: &Bytecode, v: &Native) -> Result {
+                (**self).fmt_native(f, ctx, v)
+            }
+
+            fn fmt_function_header(
+                &self,
+                f: &mut Formatter,
+                ctx: &Bytecode,
+                v: &Function,
+            ) -> Result {
+                (**self).fmt_function_header(f, ctx, v)
+            }
+
+            fn fmt_function(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+                (**self).fmt_function(f, ctx, v)
+            }
+        }
+    )*};
+}
+
+delegate_fmt!(&T, Box<T>, Rc<T>, Arc<T>);
+
+/// The formatters of this module, to choose one at runtime (e.g. from a user setting)
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum FmtStyle {
+    Debug,
+    Display,
+    #[default]
+    Enhanced,
+    Ansi,
+    Html,
+    Asm,
+}
+
+impl FmtStyle {
+    pub const ALL: [FmtStyle; 6] = [
+        FmtStyle::Debug,
+        FmtStyle::Display,
+        FmtStyle::Enhanced,
+        FmtStyle::Ansi,
+        FmtStyle::Html,
+        FmtStyle::Asm,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FmtStyle::Debug => "debug",
+            FmtStyle::Display => "display",
+            FmtStyle::Enhanced => "enhanced",
+            FmtStyle::Ansi => "ansi",
+            FmtStyle::Html => "html",
+            FmtStyle::Asm => "asm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    pub fn formatter(self) -> &'static dyn BytecodeFmt {
+        const ENHANCED: EnhancedFmt = EnhancedFmt {
+            options: FmtOptions::DEFAULT,
+        };
+
+        match self {
+            FmtStyle::Debug => &DebugFmt,
+            FmtStyle::Display => &DisplayFmt,
+            FmtStyle::Enhanced => &ENHANCED,
+            FmtStyle::Ansi => &AnsiFmt { colors: true },
+            FmtStyle::Html => &HtmlFmt,
+            FmtStyle::Asm => &AsmFmt { comments: false },
+        }
+    }
+}
+
+//endregion
+
 //region Display methods
 // Boilerplate code that makes using [BytecodeFmt] spark a bit more joy.
 
@@ -596,6 +943,134 @@ impl FunPtr<'_> {
     }
 }
 
+impl ConstantDef {
+    /// The value of this constant : a string literal for a `String` object, the fields of the object otherwise
+    pub fn display_value<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
+        fmt(move |f| {
+            let Some(obj) = self.obj(ctx) else {
+                return f.write_str("?");
+            };
+            let values = self.values(ctx);
+            match values.first() {
+                Some(ConstantValue::String(s)) if &*obj.name(ctx) == "String" => {
+                    return Debug::fmt(&**s, f);
+                }
+                _ => {}
+            }
+            f.write_str("{")?;
+            for (i, (field, value)) in obj.fields.iter().zip(&values).enumerate() {
+                if i > 0 {
+                    f.write_str(",")?;
+                }
+                write!(f, " {}: {value}", field.name(ctx))?;
+            }
+            f.write_str(if values.is_empty() { "}" } else { " }" })
+        })
+    }
+}
+
+impl RefGlobal {
+    /// A global with its type and the value of its constant initializer if it has one, e.g.
+    /// `global@42 : String = "Hello"`
+    pub fn display_resolved<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
+        fmt(move |f| {
+            write!(f, "global{self}")?;
+            if let Some(t) = ctx.globals.get(self.0) {
+                write!(f, " : {}", t.display::<EnhancedFmt>(ctx))?;
+            }
+            if let Some(c) = ctx.global_constant(*self) {
+                write!(f, " = {}", c.display_value(ctx))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl RefBytes {
+    /// Short preview of a bytes constant, see [bytes_preview]
+    pub fn preview<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
+        fmt(move |f| match ctx.try_get(*self) {
+            Some(bytes) => f.write_str(&bytes_preview(bytes, 32)),
+            None => write!(f, "<invalid bytes {self}>"),
+        })
+    }
+}
+
+/// Preview of raw bytes, as a string literal if they hold nul terminated UTF-16 text (like the strings generated by
+/// the compiler), else as hex limited to `max` bytes.
+pub fn bytes_preview(bytes: &[u8], max: usize) -> String {
+    if let Some(text) = utf16_text(bytes) {
+        return format!("u{text:?}");
+    }
+    let mut out = String::with_capacity(2 + 2 * max.min(bytes.len()));
+    out.push_str("0x");
+    for b in bytes.iter().take(max) {
+        out.push_str(&format!("{b:02x}"));
+    }
+    if bytes.len() > max {
+        out.push_str(&format!("... ({} bytes)", bytes.len()));
+    }
+    out
+}
+
+/// Decode nul terminated UTF-16 text made of printable characters
+fn utf16_text(bytes: &[u8]) -> Option<String> {
+    if bytes.len() < 4 || bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let (&last, units) = units.split_last()?;
+    if last != 0 {
+        return None;
+    }
+    let text = String::from_utf16(units).ok()?;
+    text.chars()
+        .all(|c| !c.is_control() || c.is_whitespace())
+        .then_some(text)
+}
+
+/// Join the elements of an operand list starting at column `start`, according to [FmtOptions::width] and
+/// [FmtOptions::max_list]
+fn layout_list<T: Display>(
+    start: usize,
+    items: impl IntoIterator<Item = T>,
+    opts: &FmtOptions,
+) -> String {
+    let items: Vec<String> = items.into_iter().map(|i| i.to_string()).collect();
+    let shown = if opts.max_list > 0 {
+        items.len().min(opts.max_list)
+    } else {
+        items.len()
+    };
+    let mut elements = items[..shown].to_vec();
+    if shown < items.len() {
+        elements.push(format!("... {} more", items.len() - shown));
+    }
+    let mut out = String::new();
+    let mut column = start;
+    for (i, e) in elements.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+            column += 1;
+            // Wrap when the element doesn't fit, a line always holds at least one element
+            if opts.width > 0 && column + 1 + e.chars().count() + 1 > opts.width {
+                out.push('\n');
+                out.extend(repeat(' ').take(start));
+                column = start;
+            } else {
+                out.push(' ');
+                column += 1;
+            }
+        }
+        out.push_str(e);
+        column += e.chars().count();
+    }
+    out
+}
+
 //endregion
 
 impl Opcode {
@@ -607,18 +1082,84 @@ impl Opcode {
         pos: i32,
         align: usize,
     ) -> impl Display {
+        self.display_opts(
+            ctx,
+            parent,
+            pos,
+            &FmtOptions {
+                align,
+                ..FmtOptions::DEFAULT
+            },
+        )
+    }
+
+    /// Context resolved from the operands which is not part of [Opcode::display] : the instruction a jump goes to,
+    /// the global with its class and constant value, the value of the constants. `None` when there is nothing to add.
+    ///
+    /// Formatters append it as a trailing `; comment`, see [FmtOptions::comments].
+    pub fn comment(&self, ctx: &Bytecode, parent: &Function, pos: usize) -> Option<String> {
+        match self {
+            Opcode::Int { ptr, .. } => Some(ptr.display::<EnhancedFmt>(ctx).to_string()),
+            Opcode::Float { ptr, .. } => Some(ptr.display::<EnhancedFmt>(ctx).to_string()),
+            Opcode::String { ptr, .. } => Some(format!("{:?}", ctx.try_get(*ptr)?)),
+            Opcode::Bytes { ptr, .. } => Some(ptr.preview(ctx).to_string()),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                match ctx.global_class(*global) {
+                    Some(t) => Some(format!(
+                        "{} (class {})",
+                        global.display_resolved(ctx),
+                        t.display::<EnhancedFmt>(ctx)
+                    )),
+                    None => Some(global.display_resolved(ctx).to_string()),
+                }
+            }
+            _ => match self.jump_targets(pos)[..] {
+                [target] => {
+                    let op = parent.ops.get(target)?;
+                    Some(format!(
+                        "-> {target}: {}",
+                        op.display(ctx, parent, target as i32, 0)
+                    ))
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Same as [Opcode::display] with the alignment and the display of the constants from `opts`
+    pub fn display_opts(
+        &self,
+        ctx: &Bytecode,
+        parent: &Function,
+        pos: i32,
+        opts: &FmtOptions,
+    ) -> impl Display {
+        let align = opts.align;
         macro_rules! op {
             ($($arg:tt)*) => {
                 format!("{:<align$} {}", self.name(), format_args!($($arg)*))
             };
         }
+        // An operand list following `head`, laid out from the column where it starts
+        macro_rules! list {
+            ($items:expr, $($head:tt)*) => {{
+                let head = format!($($head)*);
+                let start = align.max(self.name().len()) + 1 + head.chars().count();
+                format!("{head}{}", layout_list(start, $items, opts))
+            }};
+        }
 
         match self {
             Opcode::Mov { dst, src } => op!("{dst} = {src}"),
+            Opcode::Int { dst, ptr } if !opts.constants => op!("{dst} = int@{}", ptr.0),
+            Opcode::Float { dst, ptr } if !opts.constants => op!("{dst} = float@{}", ptr.0),
+            Opcode::String { dst, ptr } if !opts.constants => op!("{dst} = string@{}", ptr.0),
+            Opcode::Bytes { dst, ptr } if !opts.constants => op!("{dst} = bytes@{}", ptr.0),
             Opcode::Int { dst, ptr } => op!("{dst} = {}", ptr.display::<EnhancedFmt>(ctx)),
             Opcode::Float { dst, ptr } => op!("{dst} = {}", ptr.display::<EnhancedFmt>(ctx)),
             Opcode::Bool { dst, value } => op!("{dst} = {}", value),
             Opcode::String { dst, ptr } => op!("{dst} = \"{}\"", ptr.display::<EnhancedFmt>(ctx)),
+            Opcode::Bytes { dst, ptr } => op!("{dst} = {}", ptr.preview(ctx)),
             Opcode::Null { dst } => op!("{dst} = null"),
             Opcode::Add { dst, a, b } => op!("{dst} = {a} + {b}"),
             Opcode::Sub { dst, a, b } => op!("{dst} = {a} - {b}"),
@@ -673,30 +1214,41 @@ impl Opcode {
             ),
             Opcode::CallN { dst, fun, args } => {
                 op!(
-                    "{dst} = {}({})",
-                    fun.display::<EnhancedFmt>(ctx),
-                    fmtools::join(", ", args)
+                    "{})",
+                    list!(args, "{dst} = {}(", fun.display::<EnhancedFmt>(ctx))
                 )
             }
             Opcode::CallMethod { dst, field, args } => {
-                let mut args = args.iter();
-                let arg0 = args.next().unwrap();
+                let arg0 = args[0];
                 op!(
-                    "{dst} = {}.{}({})",
-                    arg0,
-                    field.display::<EnhancedFmt>(ctx, &ctx[parent[*arg0]]),
-                    fmtools::join(", ", args)
+                    "{})",
+                    list!(
+                        &args[1..],
+                        "{dst} = {}.{}(",
+                        arg0,
+                        field.display::<EnhancedFmt>(ctx, &ctx[parent[arg0]])
+                    )
                 )
             }
             Opcode::CallThis { dst, field, args } => {
                 op!(
-                    "{dst} = reg0.{}({})",
-                    field.display::<EnhancedFmt>(ctx, &ctx[parent.regs[0]]),
-                    fmtools::join(", ", args)
+                    "{})",
+                    list!(
+                        args,
+                        "{dst} = reg0.{}(",
+                        field.display::<EnhancedFmt>(ctx, &ctx[parent.regs[0]])
+                    )
                 )
             }
             Opcode::CallClosure { dst, fun, args } => {
-                op!("{dst} = {fun}({})", fmtools::join(", ", args))
+                match closure_source(ctx, parent, *fun, pos as usize) {
+                    Some(source) => op!(
+                        "{}) // {fun} is {}",
+                        list!(args, "{dst} = {fun}("),
+                        source.fun().display::<EnhancedFmt>(ctx)
+                    ),
+                    None => op!("{})", list!(args, "{dst} = {fun}(")),
+                }
             }
             Opcode::StaticClosure { dst, fun } => {
                 op!("{dst} = {:?}", ctx.get(*fun))
@@ -704,12 +1256,22 @@ impl Opcode {
             Opcode::InstanceClosure { dst, fun, obj } => {
                 op!("{dst} = {obj}.{:?}", ctx.get(*fun))
             }
-            Opcode::GetGlobal { dst, global } => {
-                op!("{dst} = global@{}", global.0)
-            }
-            Opcode::SetGlobal { global, src } => {
-                op!("global@{} = {src}", global.0)
-            }
+            Opcode::GetGlobal { dst, global } => match ctx.renames.get(Symbol::Global(*global)) {
+                Some(name) => op!("{dst} = {name}"),
+                None => match ctx.global_class(*global) {
+                    // Static part of a class
+                    Some(t) => op!(
+                        "{dst} = global@{} // {}",
+                        global.0,
+                        t.display::<EnhancedFmt>(ctx)
+                    ),
+                    None => op!("{dst} = global@{}", global.0),
+                },
+            },
+            Opcode::SetGlobal { global, src } => match ctx.renames.get(Symbol::Global(*global)) {
+                Some(name) => op!("{name} = {src}"),
+                None => op!("global@{} = {src}", global.0),
+            },
             Opcode::Field { dst, obj, field } => {
                 op!(
                     "{dst} = {obj}.{}",
@@ -843,9 +1405,12 @@ impl Opcode {
                 args,
             } => {
                 op!(
-                    "{dst} = variant {} ({})",
-                    construct.display::<EnhancedFmt>(ctx, &ctx[parent[*dst]]),
-                    fmtools::join(", ", args)
+                    "{})",
+                    list!(
+                        args,
+                        "{dst} = variant {} (",
+                        construct.display::<EnhancedFmt>(ctx, &ctx[parent[*dst]])
+                    )
                 )
             }
             Opcode::EnumAlloc { dst, construct } => {
@@ -872,21 +1437,57 @@ impl Opcode {
             Opcode::SetEnumField { value, field, src } => {
                 op!("{value}.{} = {src}", field.0)
             }
+            Opcode::Switch { reg, offsets, end } => {
+                let targets = offsets.iter().map(|offset| pos + offset + 1);
+                op!(
+                    "{}] end {}",
+                    list!(targets, "switch {reg} ["),
+                    pos + end + 1
+                )
+            }
             // Fallback to debug impl
             _ => format!("{self:?}"),
         }
     }
+
+    /// Same as [Opcode::display], followed by the refined types of the `dynamic` and `null<T>` registers
+    pub fn display_inferred(
+        &self,
+        ctx: &Bytecode,
+        parent: &Function,
+        pos: i32,
+        align: usize,
+        types: &InferredTypes,
+    ) -> impl Display {
+        let op = self.display(ctx, parent, pos, align).to_string();
+        let refined = types.refined(pos as usize);
+        if refined.is_empty() {
+            op
+        } else {
+            format!(
+                "{op} // {}",
+                fmtools::join(
+                    ", ",
+                    refined
+                        .iter()
+                        .map(|(reg, t)| format!("{reg}: {}", t.display::<EnhancedFmt>(ctx)))
+                )
+            )
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::fmt::{Display, Write};
+    use std::fmt::Write;
     use std::fs;
     use std::path::Path;
 
-    use crate::fmt::{fmt, DisplayFmt, EnhancedFmt};
+    use crate::fmt::{bytes_preview, fmt, DisplayFmt, EnhancedFmt, FmtOptions, FmtStyle};
     use crate::fmt::{BytecodeFmt, DebugFmt};
-    use crate::types::{FunPtr, Reg};
+    use crate::opcodes::Opcode;
+    use crate::testing::{bind, bytecode, fun, obj, reload};
+    use crate::types::{ConstantDef, FunPtr, ObjField, RefGlobal, RefString, RefType, Reg, Type};
     use crate::Bytecode;
 
     struct Null;
@@ -917,6 +1518,242 @@ mod test {
         )
     }
 
+    /// A static string and a counter, read and written by `main`
+    fn program() -> Bytecode {
+        let mut code = bytecode(
+            &[
+                "", "String", "bytes", "length", "hello", "$Main", "main", "Box_Int", "value", "x",
+            ],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Bytes,
+                fun(&[], 0),
+                Type::Obj(obj(1, None, &[(2, 2), (3, 1)], &[])),
+                Type::Obj(obj(7, None, &[(8, 1)], &[])),
+                Type::Virtual {
+                    fields: vec![ObjField {
+                        name: RefString(9),
+                        t: RefType(1),
+                    }],
+                },
+                Type::Obj(bind(obj(5, None, &[(6, 3)], &[]), 0, 0)),
+            ],
+            "
+            .fun fn@0 type@3
+            .reg type@4
+            .reg type@1
+            .reg type@2
+            .reg type@0
+                GetGlobal reg0 global@0
+                String reg2 string@4
+                Int reg1 int@0
+                JAlways end
+                Incr reg1
+            end:
+                SetGlobal global@1 reg1
+                Ret reg3
+            .end",
+        );
+        code.ints = vec![5];
+        code.globals = vec![RefType(4), RefType(1)];
+        code.constants = Some(vec![ConstantDef {
+            global: RefGlobal(0),
+            fields: vec![4, 0],
+        }]);
+        reload(&code)
+    }
+
+    #[test]
+    fn dyn_formatter() {
+        let code = program();
+        let main = code.main();
+        for style in FmtStyle::ALL {
+            assert_eq!(FmtStyle::from_name(style.name()), Some(style));
+            write!(Null, "{}", main.display_fmt(style.formatter(), &code)).unwrap();
+        }
+        let boxed: Box<dyn BytecodeFmt> = Box::new(EnhancedFmt::default());
+        assert_eq!(
+            main.display_fmt(boxed, &code).to_string(),
+            main.display::<EnhancedFmt>(&code).to_string()
+        );
+        assert!(main
+            .display::<EnhancedFmt>(&code)
+            .to_string()
+            .starts_with("fn main@0 () -> void (4 regs, 7 ops)\n"));
+    }
+
+    #[test]
+    fn fmt_options() {
+        let code = program();
+        let f = code.main();
+        let default = f.display::<EnhancedFmt>(&code).to_string();
+        assert_eq!(
+            f.display_fmt(EnhancedFmt::new(FmtOptions::default()), &code)
+                .to_string(),
+            default
+        );
+
+        let options = FmtOptions {
+            debug_info: false,
+            reg_types: true,
+            constants: false,
+            indices: false,
+            align: 0,
+            width: 0,
+            max_list: 0,
+            short_virtuals: false,
+            demangle: false,
+            comments: false,
+        };
+        let text = f.display_fmt(EnhancedFmt::new(options), &code).to_string();
+        assert_eq!(
+            text,
+            r#"fn main@0 () -> void (4 regs, 7 ops)
+    reg0  String
+    reg1  i32
+    reg2  bytes
+    reg3  void
+GetGlobal reg0 = global@0 // reg0: String
+String reg2 = string@4 // reg2: bytes
+Int reg1 = int@0 // reg1: i32
+JAlways jump to 5
+Incr reg1++
+SetGlobal global@1 = reg1
+Ret reg3 // reg3: void
+"#
+        );
+        assert!(default.contains("  1: String      reg2 = \"hello\"\n"));
+    }
+
+    #[test]
+    fn layout_lists() {
+        let code = program();
+        let f = &code.functions[0];
+        let op = Opcode::CallN {
+            dst: Reg(0),
+            fun: f.findex,
+            args: (1..=12).map(Reg).collect(),
+        };
+        let single = op.display(&code, f, 0, 11).to_string();
+        assert_eq!(single.lines().count(), 1);
+
+        // Continuation lines are aligned with the first argument
+        let start = single.find('(').unwrap() + 1;
+        let opts = FmtOptions {
+            width: start + 20,
+            ..FmtOptions::DEFAULT
+        };
+        let wrapped = op.display_opts(&code, f, 0, &opts).to_string();
+        assert!(wrapped.lines().count() > 1);
+        for line in wrapped.lines().skip(1) {
+            assert!(line[..start].trim().is_empty());
+            assert!(line.starts_with(&format!("{:start$}reg", "")));
+        }
+        assert_eq!(wrapped.replace(&format!("\n{:start$}", ""), " "), single);
+
+        let opts = FmtOptions {
+            max_list: 3,
+            ..FmtOptions::DEFAULT
+        };
+        let elided = op.display_opts(&code, f, 0, &opts).to_string();
+        assert!(elided.ends_with("(reg1, reg2, reg3, ... 9 more)"));
+
+        let op = Opcode::Switch {
+            reg: Reg(0),
+            offsets: [1, 2, 3].into_iter().collect(),
+            end: 3,
+        };
+        assert!(op
+            .display(&code, f, 0, 0)
+            .to_string()
+            .ends_with("switch reg0 [2, 3, 4] end 4"));
+    }
+
+    #[test]
+    fn resolved_globals() {
+        let code = program();
+        assert_eq!(
+            RefGlobal(0).display_resolved(&code).to_string(),
+            "global@0 : String = \"hello\""
+        );
+        assert_eq!(
+            RefGlobal(1).display_resolved(&code).to_string(),
+            "global@1 : i32"
+        );
+        assert!(code.global_constant(RefGlobal(1)).is_none());
+    }
+
+    #[test]
+    fn readable_types() {
+        let code = program();
+        let short = EnhancedFmt::new(FmtOptions {
+            short_virtuals: true,
+            demangle: true,
+            ..FmtOptions::DEFAULT
+        });
+        assert_eq!(RefType(5).display_fmt(short, &code).to_string(), "Box<Int>");
+        assert_eq!(RefType(6).display_fmt(short, &code).to_string(), "Anon6");
+        assert_eq!(
+            short.expand(&code, RefType(6)).to_string(),
+            "virtual<x: i32>"
+        );
+        assert_eq!(
+            RefType(5).display::<EnhancedFmt>(&code).to_string(),
+            "Box_Int"
+        );
+        assert_eq!(
+            RefType(6).display::<EnhancedFmt>(&code).to_string(),
+            "virtual<x: i32>"
+        );
+    }
+
+    #[test]
+    fn preview_bytes() {
+        assert_eq!(bytes_preview(b"h\0i\0\0\0", 32), "u\"hi\"");
+        assert_eq!(bytes_preview(&[0xde, 0xad, 0xbe, 0xef], 32), "0xdeadbeef");
+        assert_eq!(
+            bytes_preview(&[0xde, 0xad, 0xbe, 0xef], 2),
+            "0xdead... (4 bytes)"
+        );
+    }
+
+    #[test]
+    fn op_comments() {
+        let code = program();
+        let f = code.main();
+        let comments: Vec<_> = f
+            .ops
+            .iter()
+            .enumerate()
+            .map(|(i, o)| o.comment(&code, f, i))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                Some("global@0 : String = \"hello\""),
+                Some("\"hello\""),
+                Some("5"),
+                Some("-> 5: SetGlobal global@1 = reg1"),
+                None,
+                Some("global@1 : i32"),
+                None,
+            ]
+            .map(|c| c.map(str::to_owned))
+        );
+        let text = f
+            .display_fmt(
+                EnhancedFmt::new(FmtOptions {
+                    comments: true,
+                    ..FmtOptions::DEFAULT
+                }),
+                &code,
+            )
+            .to_string();
+        assert!(text.contains("  3: JAlways     jump to 5 ; -> 5: SetGlobal global@1 = reg1\n"));
+        assert!(text.contains("  6: Ret         reg3\n"));
+    }
+
     fn test_fmt(path: impl AsRef<Path>) {
         let code = Bytecode::from_file(path).unwrap();
         for f in code.functions() {
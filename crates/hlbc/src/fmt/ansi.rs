@@ -0,0 +1,265 @@
+//! Colored output for terminals, see [AnsiFmt].
+
+use std::fmt::{Display, Formatter, Result, Write};
+
+use crate::fmt::{BytecodeFmt, EnhancedFmt};
+use crate::types::{
+    Function, Native, RefEnumConstruct, RefField, RefFloat, RefInt, RefString, RefType, Reg, Type,
+    TypeFun,
+};
+use crate::{Bytecode, RefFun};
+
+/// Formatter emitting the text of [EnhancedFmt] colored with ANSI escape codes.
+///
+/// Opcode names, registers, strings, functions, types and comments have distinct colors. With colors disabled, the
+/// output is exactly the one of [EnhancedFmt] : for terminals without color support or when writing to a file.
+#[derive(Copy, Clone)]
+pub struct AnsiFmt {
+    pub colors: bool,
+}
+
+impl Default for AnsiFmt {
+    fn default() -> Self {
+        Self { colors: true }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const OPCODE: &str = "\x1b[34m";
+const REG: &str = "\x1b[36m";
+const STRING: &str = "\x1b[32m";
+const FUN: &str = "\x1b[33m";
+const TYPE: &str = "\x1b[35m";
+const COMMENT: &str = "\x1b[90m";
+
+impl AnsiFmt {
+    pub fn new(colors: bool) -> Self {
+        Self { colors }
+    }
+
+    fn paint(&self, f: &mut Formatter, color: &str, v: impl Display) -> Result {
+        if self.colors {
+            write!(f, "{color}{v}{RESET}")
+        } else {
+            write!(f, "{v}")
+        }
+    }
+
+    /// Color an instruction displayed with [crate::opcodes::Opcode::display] : the opcode name, the registers, the
+    /// string literals and the trailing comment.
+    fn fmt_op(&self, f: &mut Formatter, text: &str) -> Result {
+        let name_len = text.find(' ').unwrap_or(text.len());
+        self.paint(f, OPCODE, &text[..name_len])?;
+        let mut rest = &text[name_len..];
+        // Part of an identifier, not the start of a register
+        let mut in_word = false;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("//") {
+                return self.paint(f, COMMENT, rest);
+            }
+            let len = if c == '"' {
+                // Up to the closing quote
+                let len = rest[1..].find('"').map_or(rest.len(), |i| i + 2);
+                self.paint(f, STRING, &rest[..len])?;
+                len
+            } else if !in_word
+                && rest.starts_with("reg")
+                && rest[3..].starts_with(|c: char| c.is_ascii_digit())
+            {
+                let len = 3 + rest[3..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len() - 3);
+                self.paint(f, REG, &rest[..len])?;
+                len
+            } else {
+                f.write_char(c)?;
+                c.len_utf8()
+            };
+            in_word = c.is_alphanumeric() || c == '_';
+            rest = &rest[len..];
+        }
+        Ok(())
+    }
+}
+
+impl BytecodeFmt for AnsiFmt {
+    fn fmt_reg(&self, f: &mut Formatter, _ctx: &Bytecode, v: Reg) -> Result {
+        self.paint(f, REG, v)
+    }
+
+    fn fmt_refint(&self, f: &mut Formatter, ctx: &Bytecode, v: RefInt) -> Result {
+        EnhancedFmt::default().fmt_refint(f, ctx, v)
+    }
+
+    fn fmt_reffloat(&self, f: &mut Formatter, ctx: &Bytecode, v: RefFloat) -> Result {
+        EnhancedFmt::default().fmt_reffloat(f, ctx, v)
+    }
+
+    fn fmt_refstring(&self, f: &mut Formatter, ctx: &Bytecode, v: RefString) -> Result {
+        EnhancedFmt::default().fmt_refstring(f, ctx, v)
+    }
+
+    fn fmt_reftype(&self, f: &mut Formatter, ctx: &Bytecode, v: RefType) -> Result {
+        self.paint(f, TYPE, v.display::<EnhancedFmt>(ctx))
+    }
+
+    fn fmt_reffield(
+        &self,
+        f: &mut Formatter,
+        ctx: &Bytecode,
+        v: RefField,
+        parent: &Type,
+    ) -> Result {
+        EnhancedFmt::default().fmt_reffield(f, ctx, v, parent)
+    }
+
+    fn fmt_refenumconstruct(
+        &self,
+        f: &mut Formatter,
+        ctx: &Bytecode,
+        v: RefEnumConstruct,
+        parent: &Type,
+    ) -> Result {
+        EnhancedFmt::default().fmt_refenumconstruct(f, ctx, v, parent)
+    }
+
+    fn fmt_type(&self, f: &mut Formatter, ctx: &Bytecode, v: &Type) -> Result {
+        match v {
+            Type::Fun(fun) | Type::Method(fun) => self.fmt_typefun(f, ctx, fun),
+            _ => self.paint(f, TYPE, v.display::<EnhancedFmt>(ctx)),
+        }
+    }
+
+    fn fmt_typefun(&self, f: &mut Formatter, ctx: &Bytecode, v: &TypeFun) -> Result {
+        f.write_char('(')?;
+        for (i, a) in v.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            self.fmt_type(f, ctx, &ctx[*a])?;
+        }
+        f.write_str(") -> ")?;
+        self.fmt_type(f, ctx, &ctx[v.ret])
+    }
+
+    fn fmt_reffun(&self, f: &mut Formatter, ctx: &Bytecode, v: RefFun) -> Result {
+        self.paint(f, FUN, v.display::<EnhancedFmt>(ctx))
+    }
+
+    fn fmt_native(&self, f: &mut Formatter, ctx: &Bytecode, v: &Native) -> Result {
+        self.paint(
+            f,
+            FUN,
+            format_args!("{}/{}{}", ctx[v.lib], ctx[v.name], v.findex),
+        )?;
+        f.write_char(' ')?;
+        self.fmt_type(f, ctx, &ctx[v.t])
+    }
+
+    fn fmt_function_header(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+        f.write_str("fn ")?;
+        self.fmt_reffun(f, ctx, v.findex)?;
+        f.write_char(' ')?;
+        self.fmt_type(f, ctx, &ctx[v.t])
+    }
+
+    fn fmt_function(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+        if !self.colors {
+            return EnhancedFmt::default().fmt_function(f, ctx, v);
+        }
+        self.fmt_function_header(f, ctx, v)?;
+        writeln!(f, " ({} regs, {} ops)", v.regs.len(), v.ops.len())?;
+        for (i, reg) in v.regs.iter().enumerate() {
+            f.write_str("    ")?;
+            self.paint(f, REG, format_args!("reg{i:<2}"))?;
+            f.write_char(' ')?;
+            self.fmt_type(f, ctx, &ctx[*reg])?;
+            f.write_char('\n')?;
+        }
+        for r in v.trap_regions() {
+            let end = r.end.map_or("?".to_string(), |end| end.to_string());
+            f.write_str("    ")?;
+            self.paint(
+                f,
+                COMMENT,
+                format_args!(
+                    "{}trap {}..{end} {} handler {}",
+                    "  ".repeat(r.depth),
+                    r.trap,
+                    r.exc,
+                    r.handler
+                ),
+            )?;
+            f.write_char('\n')?;
+        }
+        for (i, o) in v.ops.iter().enumerate() {
+            if let Some((file, line)) = v.debug_info.as_ref().and_then(|d| d.get(i)) {
+                let file = ctx.debug_file(*file).unwrap_or_default();
+                self.paint(f, COMMENT, format_args!("{file:>12}:{line:<3}"))?;
+                f.write_char(' ')?;
+            }
+            write!(f, "{i:>3}: ")?;
+            self.fmt_op(f, &o.display(ctx, v, i as i32, 11).to_string())?;
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fmt::{AnsiFmt, EnhancedFmt};
+    use crate::testing::sample;
+    use crate::types::FunPtr;
+
+    /// Remove the escape sequences
+    fn strip(s: &str) -> String {
+        let mut out = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_ansi() -> crate::Result<()> {
+        let code = sample();
+        let headers: Vec<_> = code
+            .functions()
+            .map(|f| strip(&f.display_header::<AnsiFmt>(&code).to_string()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                "fn main@0 () -> void",
+                "fn length@1 (Point) -> i32",
+                "fn helper@2 (i32) -> i32",
+                "fn unused@3 () -> void"
+            ]
+        );
+        let colored = code.functions[0].display::<AnsiFmt>(&code).to_string();
+        assert!(colored.contains('\x1b'));
+        for f in code.functions() {
+            let enhanced = f.display_header::<EnhancedFmt>(&code).to_string();
+            let colored = f.display_header::<AnsiFmt>(&code).to_string();
+            let plain = f.display_header_fmt(AnsiFmt::new(false), &code).to_string();
+            assert_eq!(strip(&colored), enhanced);
+            assert_eq!(plain, enhanced);
+            if let FunPtr::Fun(f) = f {
+                let enhanced = f.display::<EnhancedFmt>(&code).to_string();
+                let colored = f.display::<AnsiFmt>(&code).to_string();
+                assert_eq!(strip(&colored), enhanced);
+                assert_eq!(
+                    f.display_fmt(AnsiFmt::new(false), &code).to_string(),
+                    enhanced
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,610 @@
+//! Canonical textual assembly of functions, see [AsmFmt].
+//!
+//! Every serialized part of a function is written with raw pool indices, so parsing the text back with
+//! [Function::from_asm] reproduces the exact same encoding. Jump targets are written as symbolic labels.
+//!
+//! ```text
+//! .fun fn@27 type@12 ; main
+//! .name string@8
+//! .reg type@3 ; reg0 i32
+//! .reg type@7 ; reg1 bool
+//! .debug
+//! .assigns
+//! .assign string@14 1
+//! .loc 0 4 ; Main.hx
+//!     Int reg0 int@0
+//!     JSLt reg0 reg0 L0
+//!     Bool reg1 true
+//! L0:
+//! .loc 0 5 ; Main.hx
+//!     Ret reg1
+//! .end
+//! ```
+//!
+//! Directives :
+//! - `.fun <fn@findex> <type@t>` starts a function and `.end` terminates it
+//! - `.name <string@idx>` and `.parent <type@idx>` are not serialized but kept for display
+//! - `.reg <type@idx>` declares the next register
+//! - `.debug` declares the presence of debug info, `.loc <file> <line>` sets the position of the next instructions
+//! - `.assigns` declares the presence of variable names, `.assign <string@idx> <pos>` adds one
+//!
+//! Instructions are written as their opcode name followed by the operands in the order of [Opcode::operands]. Lists
+//! are written between brackets (`[reg1, reg2]`). A jump offset is either a label or a raw signed offset when its
+//! target is outside the function. Everything after a `;` is a comment.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter, Result, Write};
+
+use crate::fmt::{BytecodeFmt, EnhancedFmt};
+use crate::opcodes::{Opcode, Operand, OperandDesc, OperandKind};
+use crate::types::{
+    Function, JumpOffset, JumpOffsets, RefBytes, RefEnumConstruct, RefField, RefFloat, RefGlobal,
+    RefInt, RefString, RefType, Reg, Regs, Type,
+};
+use crate::{Bytecode, Error, RefFun, TryResolve};
+
+/// Formatter emitting the canonical assembly syntax, see the [module](self) documentation.
+///
+/// Comments are added to make the output readable, they are ignored by the parser.
+#[derive(Copy, Clone, Default)]
+pub struct AsmFmt {
+    /// Append a `; comment` to the instructions with the context resolved from their operands, see
+    /// [Opcode::comment]
+    pub comments: bool,
+}
+
+/// Absolute position of the target of a jump offset of the instruction at `pos`
+fn target(pos: usize, offset: JumpOffset) -> i64 {
+    pos as i64 + offset as i64 + 1
+}
+
+/// Labels of a function by position, numbered in order
+fn labels(v: &Function) -> BTreeMap<usize, usize> {
+    let mut targets = BTreeMap::new();
+    for (pos, op) in v.ops.iter().enumerate() {
+        for (desc, (_, operand)) in op.operand_descs().iter().zip(op.operands()) {
+            let offsets = match operand {
+                Operand::JumpOffset(offset) if desc.jump => vec![offset],
+                Operand::JumpOffsets(offsets) if desc.jump => offsets.to_vec(),
+                _ => continue,
+            };
+            for offset in offsets {
+                let target = target(pos, offset);
+                if (0..=v.ops.len() as i64).contains(&target) {
+                    targets.insert(target as usize, 0);
+                }
+            }
+        }
+    }
+    for (i, label) in targets.values_mut().enumerate() {
+        *label = i;
+    }
+    targets
+}
+
+impl AsmFmt {
+    pub fn new(comments: bool) -> Self {
+        Self { comments }
+    }
+
+    fn fmt_offset(
+        &self,
+        f: &mut Formatter,
+        labels: &BTreeMap<usize, usize>,
+        pos: usize,
+        offset: JumpOffset,
+        jump: bool,
+    ) -> Result {
+        let target = target(pos, offset);
+        match labels.get(&(target as usize)) {
+            Some(label) if jump && target >= 0 => write!(f, "L{label}"),
+            _ => write!(f, "{offset}"),
+        }
+    }
+
+    fn fmt_operand(
+        &self,
+        f: &mut Formatter,
+        labels: &BTreeMap<usize, usize>,
+        pos: usize,
+        desc: &OperandDesc,
+        operand: Operand,
+    ) -> Result {
+        match operand {
+            Operand::Reg(r) => write!(f, "{r}"),
+            Operand::Regs(regs) => {
+                f.write_char('[')?;
+                for (i, r) in regs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{r}")?;
+                }
+                f.write_char(']')
+            }
+            Operand::InlineBool(b) => write!(f, "{b}"),
+            Operand::InlineInt(i) => write!(f, "{i}"),
+            Operand::JumpOffset(offset) => self.fmt_offset(f, labels, pos, offset, desc.jump),
+            Operand::JumpOffsets(offsets) => {
+                f.write_char('[')?;
+                for (i, &offset) in offsets.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    self.fmt_offset(f, labels, pos, offset, desc.jump)?;
+                }
+                f.write_char(']')
+            }
+            Operand::Int(v) => write!(f, "int@{}", v.0),
+            Operand::Float(v) => write!(f, "float@{}", v.0),
+            Operand::Bytes(v) => write!(f, "bytes@{}", v.0),
+            Operand::String(v) => write!(f, "string@{}", v.0),
+            Operand::Type(v) => write!(f, "type@{}", v.0),
+            Operand::Fun(v) => write!(f, "fn@{}", v.0),
+            Operand::Field(v) => write!(f, "field@{}", v.0),
+            Operand::Global(v) => write!(f, "global@{}", v.0),
+            Operand::EnumConstruct(v) => write!(f, "construct@{}", v.0),
+        }
+    }
+}
+
+impl BytecodeFmt for AsmFmt {
+    fn fmt_refint(&self, f: &mut Formatter, _ctx: &Bytecode, v: RefInt) -> Result {
+        write!(f, "int@{}", v.0)
+    }
+
+    fn fmt_reffloat(&self, f: &mut Formatter, _ctx: &Bytecode, v: RefFloat) -> Result {
+        write!(f, "float@{}", v.0)
+    }
+
+    fn fmt_refstring(&self, f: &mut Formatter, _ctx: &Bytecode, v: RefString) -> Result {
+        write!(f, "string@{}", v.0)
+    }
+
+    fn fmt_reftype(&self, f: &mut Formatter, _ctx: &Bytecode, v: RefType) -> Result {
+        write!(f, "type@{}", v.0)
+    }
+
+    fn fmt_reffield(
+        &self,
+        f: &mut Formatter,
+        _ctx: &Bytecode,
+        v: RefField,
+        _parent: &Type,
+    ) -> Result {
+        write!(f, "field@{}", v.0)
+    }
+
+    fn fmt_refenumconstruct(
+        &self,
+        f: &mut Formatter,
+        _ctx: &Bytecode,
+        v: RefEnumConstruct,
+        _parent: &Type,
+    ) -> Result {
+        write!(f, "construct@{}", v.0)
+    }
+
+    fn fmt_reffun(&self, f: &mut Formatter, _ctx: &Bytecode, v: RefFun) -> Result {
+        write!(f, "fn@{}", v.0)
+    }
+
+    fn fmt_function_header(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+        write!(
+            f,
+            ".fun fn@{} type@{} ; {}",
+            v.findex.0,
+            v.t.0,
+            v.findex.display_header::<EnhancedFmt>(ctx)
+        )
+    }
+
+    fn fmt_function(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+        self.fmt_function_header(f, ctx, v)?;
+        writeln!(f)?;
+        writeln!(f, ".name string@{}", v.name.0)?;
+        if let Some(parent) = v.parent {
+            writeln!(f, ".parent type@{}", parent.0)?;
+        }
+        for (i, reg) in v.regs.iter().enumerate() {
+            writeln!(
+                f,
+                ".reg type@{} ; reg{i} {}",
+                reg.0,
+                reg.display::<EnhancedFmt>(ctx)
+            )?;
+        }
+        if v.debug_info.is_some() {
+            writeln!(f, ".debug")?;
+        }
+        if let Some(assigns) = &v.assigns {
+            writeln!(f, ".assigns")?;
+            for &(name, pos) in assigns {
+                let var = ctx.try_get(name).unwrap_or_default();
+                writeln!(f, ".assign string@{} {pos} ; {var:?}", name.0)?;
+            }
+        }
+
+        let labels = labels(v);
+        let mut loc = None;
+        for (pos, op) in v.ops.iter().enumerate() {
+            if let Some(label) = labels.get(&pos) {
+                writeln!(f, "L{label}:")?;
+            }
+            if let Some(&(file, line)) = v.debug_info.as_ref().and_then(|d| d.get(pos)) {
+                if loc != Some((file, line)) {
+                    loc = Some((file, line));
+                    let name = ctx.debug_file(file).unwrap_or_default();
+                    writeln!(f, ".loc {file} {line} ; {name}")?;
+                }
+            }
+            write!(f, "    {}", op.name())?;
+            for (desc, (_, operand)) in op.operand_descs().iter().zip(op.operands()) {
+                f.write_char(' ')?;
+                self.fmt_operand(f, &labels, pos, desc, operand)?;
+            }
+            if self.comments {
+                if let Some(comment) = op.comment(ctx, v, pos) {
+                    write!(f, " ; {comment}")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        if let Some(label) = labels.get(&v.ops.len()) {
+            writeln!(f, "L{label}:")?;
+        }
+        writeln!(f, ".end")
+    }
+}
+
+//region Parser
+
+fn error(line: usize, msg: impl Display) -> Error {
+    Error::MalformedBytecode(format!("line {line}: {msg}"))
+}
+
+/// Parse a pool index written as `<prefix>@<idx>`
+fn index(line: usize, token: &str, prefix: &str) -> crate::Result<usize> {
+    token
+        .strip_prefix(prefix)
+        .and_then(|t| t.strip_prefix('@'))
+        .and_then(|i| i.parse().ok())
+        .ok_or_else(|| error(line, format_args!("expected {prefix}@<idx>, got '{token}'")))
+}
+
+fn number<T: std::str::FromStr>(line: usize, token: &str) -> crate::Result<T> {
+    token
+        .parse()
+        .map_err(|_| error(line, format_args!("invalid value '{token}'")))
+}
+
+fn reg(line: usize, token: &str) -> crate::Result<Reg> {
+    token
+        .strip_prefix("reg")
+        .and_then(|i| i.parse().ok())
+        .map(Reg)
+        .ok_or_else(|| error(line, format_args!("expected a register, got '{token}'")))
+}
+
+/// Elements of a list written between brackets
+fn list(line: usize, token: &str) -> crate::Result<Vec<&str>> {
+    let inner = token
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| error(line, format_args!("expected a list, got '{token}'")))?;
+    Ok(inner
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .collect())
+}
+
+/// Split the operands of an instruction, a list between brackets is a single token
+fn tokens(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let end = if rest.starts_with('[') {
+            rest.find(']').map_or(rest.len(), |i| i + 1)
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        tokens.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    tokens
+}
+
+/// An operand being parsed, lists are owned until the instruction is built
+enum Value {
+    Single(Operand<'static>),
+    Regs(Regs),
+    Offsets(JumpOffsets),
+}
+
+impl Value {
+    fn operand(&self) -> Operand<'_> {
+        match self {
+            Value::Single(operand) => *operand,
+            Value::Regs(regs) => Operand::Regs(regs),
+            Value::Offsets(offsets) => Operand::JumpOffsets(offsets),
+        }
+    }
+}
+
+/// A function being parsed
+struct FunctionParser<'a> {
+    fun: Function,
+    /// Location of the next instructions
+    loc: Option<(usize, usize)>,
+    labels: HashMap<&'a str, usize>,
+    /// Offsets to resolve once every label is known : instruction, index in [Opcode::offsets_mut], label, line
+    fixups: Vec<(usize, usize, &'a str, usize)>,
+}
+
+impl<'a> FunctionParser<'a> {
+    fn new(line: usize, args: &[&str]) -> crate::Result<Self> {
+        let [findex, t] = args else {
+            return Err(error(line, "expected .fun <fn@findex> <type@t>"));
+        };
+        Ok(Self {
+            fun: Function {
+                t: RefType(index(line, t, "type")?),
+                findex: RefFun(index(line, findex, "fn")?),
+                regs: Vec::new(),
+                ops: Vec::new(),
+                debug_info: None,
+                assigns: None,
+                name: RefString(0),
+                parent: None,
+            },
+            loc: None,
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+        })
+    }
+
+    fn directive(&mut self, line: usize, name: &str, args: &[&str]) -> crate::Result<()> {
+        match (name, args) {
+            (".name", [name]) => self.fun.name = RefString(index(line, name, "string")?),
+            (".parent", [parent]) => self.fun.parent = Some(RefType(index(line, parent, "type")?)),
+            (".reg", [t]) => self.fun.regs.push(RefType(index(line, t, "type")?)),
+            (".debug", []) => self.fun.debug_info = Some(Vec::new()),
+            (".assigns", []) => self.fun.assigns = Some(Vec::new()),
+            (".assign", [name, pos]) => {
+                let assign = (RefString(index(line, name, "string")?), number(line, pos)?);
+                self.fun
+                    .assigns
+                    .as_mut()
+                    .ok_or_else(|| error(line, ".assign without .assigns"))?
+                    .push(assign);
+            }
+            (".loc", [file, l]) => {
+                if self.fun.debug_info.is_none() {
+                    return Err(error(line, ".loc without .debug"));
+                }
+                self.loc = Some((number(line, file)?, number(line, l)?));
+            }
+            _ => return Err(error(line, format_args!("invalid directive '{name}'"))),
+        }
+        Ok(())
+    }
+
+    fn label(&mut self, line: usize, label: &'a str) -> crate::Result<()> {
+        if label.is_empty() || label.contains(char::is_whitespace) {
+            return Err(error(line, format_args!("invalid label '{label}'")));
+        }
+        if self.labels.insert(label, self.fun.ops.len()).is_some() {
+            return Err(error(line, format_args!("duplicate label '{label}'")));
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, line: usize, name: &str, args: &[&'a str]) -> crate::Result<()> {
+        let descs = Opcode::from_name(name)
+            .ok_or_else(|| error(line, format_args!("unknown opcode '{name}'")))?
+            .operand_descs();
+        if descs.len() != args.len() {
+            return Err(error(
+                line,
+                format_args!("{name} expects {} operands", descs.len()),
+            ));
+        }
+        let pos = self.fun.ops.len();
+        // Index of the next jump offset in [Opcode::offsets_mut]
+        let mut slot = 0;
+        let mut offset = |token: &'a str| -> crate::Result<JumpOffset> {
+            slot += 1;
+            if token.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+                number(line, token)
+            } else {
+                self.fixups.push((pos, slot - 1, token, line));
+                Ok(0)
+            }
+        };
+        let mut values = Vec::with_capacity(args.len());
+        for (desc, &token) in descs.iter().zip(args) {
+            let single = match desc.kind {
+                OperandKind::Reg => Operand::Reg(reg(line, token)?),
+                OperandKind::Regs => {
+                    let regs = list(line, token)?.into_iter().map(|r| reg(line, r));
+                    values.push(Value::Regs(regs.collect::<crate::Result<_>>()?));
+                    continue;
+                }
+                OperandKind::InlineBool => Operand::InlineBool(number(line, token)?),
+                OperandKind::InlineInt => Operand::InlineInt(number(line, token)?),
+                OperandKind::JumpOffset => Operand::JumpOffset(offset(token)?),
+                OperandKind::JumpOffsets => {
+                    let offsets = list(line, token)?.into_iter().map(&mut offset);
+                    values.push(Value::Offsets(offsets.collect::<crate::Result<_>>()?));
+                    continue;
+                }
+                OperandKind::Int => Operand::Int(RefInt(index(line, token, "int")?)),
+                OperandKind::Float => Operand::Float(RefFloat(index(line, token, "float")?)),
+                OperandKind::Bytes => Operand::Bytes(RefBytes(index(line, token, "bytes")?)),
+                OperandKind::String => Operand::String(RefString(index(line, token, "string")?)),
+                OperandKind::Type => Operand::Type(RefType(index(line, token, "type")?)),
+                OperandKind::Fun => Operand::Fun(RefFun(index(line, token, "fn")?)),
+                OperandKind::Field => Operand::Field(RefField(index(line, token, "field")?)),
+                OperandKind::Global => Operand::Global(RefGlobal(index(line, token, "global")?)),
+                OperandKind::EnumConstruct => {
+                    Operand::EnumConstruct(RefEnumConstruct(index(line, token, "construct")?))
+                }
+            };
+            values.push(Value::Single(single));
+        }
+        let operands: Vec<_> = values.iter().map(Value::operand).collect();
+        let op = Opcode::from_operands(name, &operands)
+            .ok_or_else(|| error(line, format_args!("invalid operands for {name}")))?;
+
+        if let Some(debug_info) = &mut self.fun.debug_info {
+            debug_info.push(
+                self.loc
+                    .ok_or_else(|| error(line, "missing .loc before the instruction"))?,
+            );
+        }
+        self.fun.ops.push(op);
+        Ok(())
+    }
+
+    fn finish(mut self) -> crate::Result<Function> {
+        for (pos, slot, label, line) in self.fixups {
+            let target = *self
+                .labels
+                .get(label)
+                .ok_or_else(|| error(line, format_args!("unknown label '{label}'")))?;
+            *self.fun.ops[pos].offsets_mut()[slot] = (target as i64 - pos as i64 - 1) as JumpOffset;
+        }
+        Ok(self.fun)
+    }
+}
+
+/// Parse every function written with [AsmFmt]
+pub fn parse_functions(text: &str) -> crate::Result<Vec<Function>> {
+    let mut functions = Vec::new();
+    let mut current: Option<FunctionParser> = None;
+    for (i, line) in text.lines().enumerate() {
+        let i = i + 1;
+        let line = line.split_once(';').map_or(line, |(l, _)| l).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(label) = line.strip_suffix(':') {
+            current
+                .as_mut()
+                .ok_or_else(|| error(i, "label outside of a function"))?
+                .label(i, label)?;
+            continue;
+        }
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = tokens(rest);
+        match (name, current.as_mut()) {
+            (".fun", None) => current = Some(FunctionParser::new(i, &args)?),
+            (".fun", Some(_)) => return Err(error(i, "missing .end before .fun")),
+            (".end", Some(_)) => functions.push(current.take().unwrap().finish()?),
+            (_, None) => return Err(error(i, "expected .fun")),
+            (_, Some(p)) if name.starts_with('.') => p.directive(i, name, &args)?,
+            (_, Some(p)) => p.instruction(i, name, &args)?,
+        }
+    }
+    if current.is_some() {
+        return Err(Error::MalformedBytecode("missing .end".to_string()));
+    }
+    Ok(functions)
+}
+
+impl Function {
+    /// Parse a single function written with [AsmFmt]
+    pub fn from_asm(text: &str) -> crate::Result<Function> {
+        let mut functions = parse_functions(text)?;
+        match functions.len() {
+            1 => Ok(functions.pop().unwrap()),
+            n => Err(Error::MalformedBytecode(format!(
+                "expected a single function, got {n}"
+            ))),
+        }
+    }
+}
+
+//endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::fmt::asm::parse_functions;
+    use crate::fmt::AsmFmt;
+    use crate::testing::{reload, sample};
+    use crate::types::Function;
+
+    fn encode(f: &Function) -> Vec<u8> {
+        let mut data = Vec::new();
+        f.write(&mut data, &f.ops).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_roundtrip() -> crate::Result<()> {
+        let mut code = sample();
+        code.synthesize_debug();
+        let code = reload(&code);
+        assert_eq!(
+            code.functions[1].display::<AsmFmt>(&code).to_string(),
+            ".fun fn@1 type@3 ; fn length@1 (Point) -> i32
+.name string@3
+.parent type@4
+.reg type@4 ; reg0 Point
+.reg type@1 ; reg1 i32
+.debug
+.assigns
+.loc 1 1 ; Point.hx
+    Field reg1 reg0 field@0
+.loc 1 2 ; Point.hx
+    Ret reg1
+.end
+"
+        );
+        let mut text = String::new();
+        for f in &code.functions {
+            let asm = f.display::<AsmFmt>(&code).to_string();
+            let parsed = Function::from_asm(&asm)?;
+            assert_eq!(encode(&parsed), encode(f), "{asm}");
+            assert_eq!(parsed.name, f.name);
+            assert_eq!(parsed.parent, f.parent);
+            // Canonical : printing again gives the same text
+            assert_eq!(parsed.display::<AsmFmt>(&code).to_string(), asm);
+            // Comments are ignored
+            let commented = f.display_fmt(AsmFmt::new(true), &code).to_string();
+            assert_eq!(encode(&Function::from_asm(&commented)?), encode(f));
+            text.push_str(&asm);
+        }
+        assert_eq!(parse_functions(&text)?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() -> crate::Result<()> {
+        let f = Function::from_asm(
+            "
+            .fun fn@1 type@2 ; comment
+            .reg type@3
+            .reg type@7
+            start:
+                Int reg0 int@0
+                JSLt reg0 reg0 end
+                Bool reg1 true
+                JAlways start
+                Switch reg0 [start, end] -3
+                CallN reg1 fn@4 [reg0, reg1]
+            end:
+                Ret reg1
+            .end",
+        )?;
+        assert_eq!(f.ops.len(), 7);
+        assert_eq!(f.ops[1].jump_targets(1), vec![6]);
+        assert_eq!(f.ops[3].jump_targets(3), vec![0]);
+        assert_eq!(f.ops[4].jump_targets(4), vec![0, 6]);
+        assert!(Function::from_asm(".fun fn@1 type@2\n    Unknown reg0\n.end").is_err());
+        assert!(Function::from_asm(".fun fn@1 type@2\n    JAlways nowhere\n.end").is_err());
+        assert!(Function::from_asm(".fun fn@1 type@2\n    Ret reg0").is_err());
+        Ok(())
+    }
+}
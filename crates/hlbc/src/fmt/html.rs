@@ -0,0 +1,397 @@
+//! HTML output, see [HtmlFmt].
+
+use std::fmt::{Display, Formatter, Result, Write};
+
+use crate::fmt::{fmt, BytecodeFmt, EnhancedFmt};
+use crate::opcodes::{Opcode, Operand};
+use crate::types::{
+    Function, Native, RefEnumConstruct, RefField, RefFloat, RefInt, RefString, RefType, Reg, Type,
+    TypeFun,
+};
+use crate::{Bytecode, RefFun, Resolve};
+
+/// Formatter emitting syntax colored HTML with hyperlinks.
+///
+/// Functions, types and strings link to the anchors `fn-<findex>`, `type-<index>` and `string-<index>`, jumps link to
+/// the instructions anchored as `fn-<findex>-<pos>`. [HtmlFmt::document] generates a standalone page defining every
+/// anchor. Elements are tagged with css classes for coloring : `reg`, `num`, `str`, `type`, `fun`, `field`, `global`,
+/// `op` and `jump`.
+#[derive(Copy, Clone, Default)]
+pub struct HtmlFmt;
+
+const STYLE: &str = "body { background: #1e1e1e; color: #d4d4d4; font-family: monospace; }
+a { text-decoration: none; }
+a:hover { text-decoration: underline; }
+:target { background: #264f78; }
+.reg { color: #9cdcfe; }
+.num { color: #b5cea8; }
+.str { color: #ce9178; }
+.type { color: #4ec9b0; }
+.fun { color: #dcdcaa; }
+.field { color: #9cdcfe; }
+.global { color: #c586c0; }
+.op { color: #569cd6; }
+.jump { color: #d7ba7d; }
+";
+
+/// Write text with the HTML special characters escaped
+fn escape(f: &mut Formatter, s: &str) -> Result {
+    for c in s.chars() {
+        match c {
+            '&' => f.write_str("&amp;")?,
+            '<' => f.write_str("&lt;")?,
+            '>' => f.write_str("&gt;")?,
+            '"' => f.write_str("&quot;")?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+fn escaped(v: impl Display) -> impl Display {
+    fmt(move |f| escape(f, &v.to_string()))
+}
+
+impl HtmlFmt {
+    /// Standalone HTML page listing every type, function and string of the bytecode
+    pub fn document(code: &Bytecode) -> impl Display + '_ {
+        fmt(move |f| {
+            f.write_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>\n")?;
+            f.write_str(STYLE)?;
+            f.write_str("</style>\n</head>\n<body>\n")?;
+            f.write_str("<h2>Types</h2>\n<pre>\n")?;
+            for (i, t) in code.types.iter().enumerate() {
+                write!(f, "<span id=\"type-{i}\">{i:>5}</span>: ")?;
+                HtmlFmt.fmt_type_definition(f, code, t)?;
+                f.write_char('\n')?;
+            }
+            f.write_str("</pre>\n<h2>Natives</h2>\n<pre>\n")?;
+            for n in &code.natives {
+                write!(f, "<span id=\"fn-{}\">", n.findex.0)?;
+                HtmlFmt.fmt_native(f, code, n)?;
+                f.write_str("</span>\n")?;
+            }
+            f.write_str("</pre>\n<h2>Functions</h2>\n")?;
+            for fun in &code.functions {
+                HtmlFmt.fmt_function(f, code, fun)?;
+            }
+            f.write_str("<h2>Strings</h2>\n<pre>\n")?;
+            for (i, s) in code.strings.iter().enumerate() {
+                write!(f, "<span id=\"string-{i}\">{i:>5}</span>: ")?;
+                escape(f, s)?;
+                f.write_char('\n')?;
+            }
+            f.write_str("</pre>\n</body>\n</html>\n")
+        })
+    }
+
+    /// Classes with their fields and methods, enums with their constructs, other types as is
+    fn fmt_type_definition(&self, f: &mut Formatter, ctx: &Bytecode, v: &Type) -> Result {
+        match v {
+            Type::Obj(obj) | Type::Struct(obj) => {
+                write!(
+                    f,
+                    "class <span class=\"type\">{}</span>",
+                    escaped(obj.name(ctx))
+                )?;
+                if let Some(super_) = obj.super_ {
+                    f.write_str(" extends ")?;
+                    self.fmt_reftype(f, ctx, super_)?;
+                }
+                f.write_str(" {\n")?;
+                for field in &obj.own_fields {
+                    write!(
+                        f,
+                        "         <span class=\"field\">{}</span>: ",
+                        escaped(field.name(ctx))
+                    )?;
+                    self.fmt_reftype(f, ctx, field.t)?;
+                    f.write_char('\n')?;
+                }
+                for proto in &obj.protos {
+                    write!(
+                        f,
+                        "         fn <span class=\"field\">{}</span> = ",
+                        escaped(ctx.get(proto.name))
+                    )?;
+                    self.fmt_reffun(f, ctx, proto.findex)?;
+                    f.write_char('\n')?;
+                }
+                f.write_str("       }")
+            }
+            Type::Enum { constructs, .. } => {
+                self.fmt_type(f, ctx, v)?;
+                f.write_str(" {\n")?;
+                for c in constructs {
+                    write!(
+                        f,
+                        "         <span class=\"field\">{}</span>(",
+                        escaped(c.name(ctx))
+                    )?;
+                    for (i, &p) in c.params.iter().enumerate() {
+                        if i > 0 {
+                            f.write_str(", ")?;
+                        }
+                        self.fmt_reftype(f, ctx, p)?;
+                    }
+                    f.write_str(")\n")?;
+                }
+                f.write_str("       }")
+            }
+            _ => self.fmt_type(f, ctx, v),
+        }
+    }
+
+    fn fmt_operand(
+        &self,
+        f: &mut Formatter,
+        ctx: &Bytecode,
+        parent: &Function,
+        op: &Opcode,
+        pos: usize,
+        operand: Operand,
+    ) -> Result {
+        let target = |offset: i32| pos as i64 + offset as i64 + 1;
+        // Type of the object (or enum) for fields and constructs
+        let owner = op
+            .parent_reg()
+            .and_then(|r| parent.regs.get(r.0 as usize))
+            .map(|&t| &ctx[t]);
+        match operand {
+            Operand::Reg(r) => self.fmt_reg(f, ctx, r),
+            Operand::Regs(regs) => {
+                f.write_char('(')?;
+                for (i, &r) in regs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    self.fmt_reg(f, ctx, r)?;
+                }
+                f.write_char(')')
+            }
+            Operand::InlineBool(b) => write!(f, "<span class=\"num\">{b}</span>"),
+            Operand::InlineInt(i) => write!(f, "<span class=\"num\">{i}</span>"),
+            Operand::JumpOffset(offset) => {
+                let t = target(offset);
+                write!(
+                    f,
+                    "<a class=\"jump\" href=\"#fn-{}-{t}\">{t}</a>",
+                    parent.findex.0
+                )
+            }
+            Operand::JumpOffsets(offsets) => {
+                f.write_char('[')?;
+                for (i, &offset) in offsets.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    let t = target(offset);
+                    write!(
+                        f,
+                        "<a class=\"jump\" href=\"#fn-{}-{t}\">{t}</a>",
+                        parent.findex.0
+                    )?;
+                }
+                f.write_char(']')
+            }
+            Operand::Int(i) => self.fmt_refint(f, ctx, i),
+            Operand::Float(fl) => self.fmt_reffloat(f, ctx, fl),
+            Operand::Bytes(b) => {
+                write!(f, "<span class=\"str\">{}</span>", escaped(b.preview(ctx)))
+            }
+            Operand::String(s) => {
+                f.write_str("&quot;")?;
+                self.fmt_refstring(f, ctx, s)?;
+                f.write_str("&quot;")
+            }
+            Operand::Type(t) => self.fmt_reftype(f, ctx, t),
+            Operand::Fun(fun) => self.fmt_reffun(f, ctx, fun),
+            Operand::Field(field) => match (op, owner) {
+                // Index of an enum construct parameter
+                (Opcode::EnumField { .. } | Opcode::SetEnumField { .. }, _) | (_, None) => {
+                    write!(f, "<span class=\"num\">{}</span>", field.0)
+                }
+                (_, Some(owner)) => self.fmt_reffield(f, ctx, field, owner),
+            },
+            Operand::Global(g) => write!(f, "<span class=\"global\">global@{}</span>", g.0),
+            Operand::EnumConstruct(c) => match owner {
+                Some(owner @ Type::Enum { .. }) => self.fmt_refenumconstruct(f, ctx, c, owner),
+                _ => write!(f, "<span class=\"num\">{}</span>", c.0),
+            },
+        }
+    }
+}
+
+impl BytecodeFmt for HtmlFmt {
+    fn fmt_reg(&self, f: &mut Formatter, _ctx: &Bytecode, v: Reg) -> Result {
+        write!(f, "<span class=\"reg\">{v}</span>")
+    }
+
+    fn fmt_refint(&self, f: &mut Formatter, ctx: &Bytecode, v: RefInt) -> Result {
+        write!(f, "<span class=\"num\">{}</span>", ctx[v])
+    }
+
+    fn fmt_reffloat(&self, f: &mut Formatter, ctx: &Bytecode, v: RefFloat) -> Result {
+        write!(f, "<span class=\"num\">{}</span>", ctx[v])
+    }
+
+    fn fmt_refstring(&self, f: &mut Formatter, ctx: &Bytecode, v: RefString) -> Result {
+        write!(
+            f,
+            "<a class=\"str\" href=\"#string-{}\">{}</a>",
+            v.0,
+            escaped(&ctx[v])
+        )
+    }
+
+    fn fmt_reftype(&self, f: &mut Formatter, ctx: &Bytecode, v: RefType) -> Result {
+        write!(
+            f,
+            "<a class=\"type\" href=\"#type-{}\">{}</a>",
+            v.0,
+            escaped(v.display::<EnhancedFmt>(ctx))
+        )
+    }
+
+    fn fmt_reffield(
+        &self,
+        f: &mut Formatter,
+        ctx: &Bytecode,
+        v: RefField,
+        parent: &Type,
+    ) -> Result {
+        write!(
+            f,
+            "<span class=\"field\">{}</span>",
+            escaped(v.display::<EnhancedFmt>(ctx, parent))
+        )
+    }
+
+    fn fmt_refenumconstruct(
+        &self,
+        f: &mut Formatter,
+        ctx: &Bytecode,
+        v: RefEnumConstruct,
+        parent: &Type,
+    ) -> Result {
+        write!(
+            f,
+            "<span class=\"field\">{}</span>",
+            escaped(v.display::<EnhancedFmt>(ctx, parent))
+        )
+    }
+
+    fn fmt_type(&self, f: &mut Formatter, ctx: &Bytecode, v: &Type) -> Result {
+        match v {
+            Type::Fun(fun) | Type::Method(fun) => self.fmt_typefun(f, ctx, fun),
+            _ => write!(
+                f,
+                "<span class=\"type\">{}</span>",
+                escaped(v.display::<EnhancedFmt>(ctx))
+            ),
+        }
+    }
+
+    fn fmt_typefun(&self, f: &mut Formatter, ctx: &Bytecode, v: &TypeFun) -> Result {
+        f.write_char('(')?;
+        for (i, &a) in v.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            self.fmt_reftype(f, ctx, a)?;
+        }
+        f.write_str(") -&gt; ")?;
+        self.fmt_reftype(f, ctx, v.ret)
+    }
+
+    fn fmt_reffun(&self, f: &mut Formatter, ctx: &Bytecode, v: RefFun) -> Result {
+        write!(
+            f,
+            "<a class=\"fun\" href=\"#fn-{}\">{}</a>",
+            v.0,
+            escaped(v.display::<EnhancedFmt>(ctx))
+        )
+    }
+
+    fn fmt_native(&self, f: &mut Formatter, ctx: &Bytecode, v: &Native) -> Result {
+        write!(
+            f,
+            "native <span class=\"fun\">{}/{}</span>{} ",
+            escaped(&ctx[v.lib]),
+            escaped(&ctx[v.name]),
+            v.findex
+        )?;
+        self.fmt_type(f, ctx, &ctx[v.t])
+    }
+
+    fn fmt_function_header(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+        f.write_str("fn ")?;
+        self.fmt_reffun(f, ctx, v.findex)?;
+        f.write_char(' ')?;
+        self.fmt_type(f, ctx, &ctx[v.t])
+    }
+
+    fn fmt_function(&self, f: &mut Formatter, ctx: &Bytecode, v: &Function) -> Result {
+        let findex = v.findex.0;
+        write!(f, "<pre id=\"fn-{findex}\">")?;
+        self.fmt_function_header(f, ctx, v)?;
+        writeln!(f, " ({} regs, {} ops)", v.regs.len(), v.ops.len())?;
+        for (i, &reg) in v.regs.iter().enumerate() {
+            write!(f, "    <span class=\"reg\">reg{i:<2}</span> ")?;
+            self.fmt_reftype(f, ctx, reg)?;
+            f.write_char('\n')?;
+        }
+        for (i, op) in v.ops.iter().enumerate() {
+            write!(f, "<span id=\"fn-{findex}-{i}\">")?;
+            if let Some((file, line)) = v.debug_info.as_ref().and_then(|d| d.get(i)) {
+                write!(
+                    f,
+                    "{:>12}:{line:<3} ",
+                    escaped(ctx.debug_file(*file).unwrap_or_default())
+                )?;
+            }
+            write!(
+                f,
+                "{i:>3}</span>: <span class=\"op\">{:<11}</span>",
+                op.name()
+            )?;
+            for (j, (_, operand)) in op.operands().into_iter().enumerate() {
+                f.write_str(if j == 0 { " " } else { ", " })?;
+                self.fmt_operand(f, ctx, v, op, i, operand)?;
+            }
+            f.write_char('\n')?;
+        }
+        f.write_str("</pre>\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fmt::html::{escaped, HtmlFmt};
+    use crate::testing::sample;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escaped("<a href=\"x\">&</a>").to_string(),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let code = sample();
+        let html = HtmlFmt::document(&code).to_string();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<pre id=\"fn-1\">fn <a class=\"fun\" href=\"#fn-1\">length@1</a> (<a class=\"type\" href=\"#type-4\">Point</a>) -&gt; <a class=\"type\" href=\"#type-1\">i32</a> (2 regs, 2 ops)"));
+        // Links to the called function, the string and the fields
+        assert!(html.contains(
+            "<a class=\"fun\" href=\"#fn-2\">helper@2</a>, <span class=\"reg\">reg1</span>"
+        ));
+        assert!(html.contains("&quot;<a class=\"str\" href=\"#string-7\">hello</a>&quot;"));
+        assert!(html.contains("<span class=\"field\">x</span>, <span class=\"reg\">reg1</span>"));
+        assert!(html.contains("<span id=\"string-8\">    8</span>: main\n"));
+        assert!(html.contains("class <span class=\"type\">$Main</span> {"));
+        assert!(html.ends_with("</html>\n"));
+    }
+}
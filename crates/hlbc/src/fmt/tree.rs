@@ -0,0 +1,169 @@
+//! Disassembly of a whole module as a directory tree, see [write_tree].
+//!
+//! The tree mirrors the source files of the debug info : each source file becomes a `.hlasm` file holding the
+//! functions declared in it, grouped under their class. Functions without debug info are written to
+//! `unknown.hlasm`. The output is stable (files and functions are sorted) so two modules can be compared with any
+//! diffing tool.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::fmt::{BytecodeFmt, EnhancedFmt};
+use crate::types::{Function, RefType};
+use crate::{Bytecode, Result};
+
+/// File holding the functions without debug info
+const UNKNOWN: &str = "unknown.hlasm";
+
+/// Path of the `.hlasm` file of a source file, relative to the output directory. Absolute paths, drive letters and
+/// parent components are kept inside the output directory.
+pub fn asm_path(debug_file: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    for part in debug_file.split(['/', '\\']) {
+        match part {
+            "" | "." | ".." => {}
+            // Windows drive letter
+            part => path.push(part.trim_end_matches(':')),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return PathBuf::from(UNKNOWN);
+    }
+    path.set_extension("hlasm");
+    path
+}
+
+/// Functions of each file of the tree, grouped by class (`None` for the functions without a parent type). Natives
+/// have no instructions and are not included.
+pub fn tree_layout(
+    code: &Bytecode,
+) -> BTreeMap<PathBuf, BTreeMap<Option<RefType>, Vec<&Function>>> {
+    let mut layout: BTreeMap<_, BTreeMap<_, Vec<_>>> = BTreeMap::new();
+    for f in &code.functions {
+        let path = f
+            .file_index()
+            .and_then(|file| code.debug_file(file))
+            .map_or_else(|| PathBuf::from(UNKNOWN), |file| asm_path(&file));
+        layout
+            .entry(path)
+            .or_default()
+            .entry(f.parent)
+            .or_default()
+            .push(f);
+    }
+    for classes in layout.values_mut() {
+        for funs in classes.values_mut() {
+            funs.sort_by_key(|f| f.findex);
+        }
+    }
+    layout
+}
+
+/// Text of each file of the tree, the functions are displayed with `fmt`
+pub fn render_tree(code: &Bytecode, fmt: &dyn BytecodeFmt) -> BTreeMap<PathBuf, String> {
+    tree_layout(code)
+        .into_iter()
+        .map(|(path, classes)| {
+            let mut text = String::new();
+            for (class, funs) in classes {
+                if let Some(class) = class {
+                    text.push_str(&format!(
+                        "; class {}\n\n",
+                        class.display::<EnhancedFmt>(code)
+                    ));
+                }
+                for f in funs {
+                    text.push_str(&f.display_fmt(fmt, code).to_string());
+                    text.push('\n');
+                }
+            }
+            (path, text)
+        })
+        .collect()
+}
+
+/// Write the disassembly of every function in a directory tree under `dir`, see the [module](self) documentation.
+/// Returns the paths of the written files.
+pub fn write_tree(
+    code: &Bytecode,
+    dir: impl AsRef<Path>,
+    fmt: &dyn BytecodeFmt,
+) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (path, text) in render_tree(code, fmt) {
+        let path = dir.as_ref().join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, text)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::fmt::asm::parse_functions;
+    use crate::fmt::tree::{asm_path, render_tree, tree_layout};
+    use crate::fmt::AsmFmt;
+    use crate::testing::{reload, sample};
+    use crate::types::{RefFun, RefType};
+
+    #[test]
+    fn test_asm_path() {
+        assert_eq!(asm_path("Main.hx"), PathBuf::from("Main.hlasm"));
+        assert_eq!(
+            asm_path("C:\\HaxeToolkit\\haxe\\std/haxe/Log.hx"),
+            PathBuf::from("C/HaxeToolkit/haxe/std/haxe/Log.hlasm")
+        );
+        assert_eq!(
+            asm_path("/usr/../share/Std.hx"),
+            PathBuf::from("usr/share/Std.hlasm")
+        );
+        assert_eq!(asm_path(""), PathBuf::from("unknown.hlasm"));
+    }
+
+    #[test]
+    fn test_tree() -> crate::Result<()> {
+        let mut code = sample();
+        code.synthesize_debug();
+        let code = reload(&code);
+        let layout = tree_layout(&code);
+        let files: Vec<_> = layout
+            .iter()
+            .map(|(path, classes)| {
+                let classes: Vec<_> = classes
+                    .iter()
+                    .map(|(&t, funs)| (t, funs.iter().map(|f| f.findex).collect::<Vec<_>>()))
+                    .collect();
+                (path.clone(), classes)
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (
+                    PathBuf::from("Main.hlasm"),
+                    vec![(Some(RefType(6)), vec![RefFun(0), RefFun(2), RefFun(3)])]
+                ),
+                (
+                    PathBuf::from("Point.hlasm"),
+                    vec![(Some(RefType(4)), vec![RefFun(1)])]
+                ),
+            ]
+        );
+
+        // Every file can be parsed back
+        let tree = render_tree(&code, &AsmFmt::default());
+        assert_eq!(tree.len(), layout.len());
+        let mut parsed = 0;
+        for text in tree.values() {
+            parsed += parse_functions(text)?.len();
+        }
+        assert_eq!(parsed, 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Loading mode decoding function bodies on demand.
+//!
+//! [Bytecode::deserialize_lazy] keeps the encoded instructions of every function in memory and leaves
+//! [Function::ops] empty, [Bytecode::function_ops] decodes them the first time they are requested. Most analyses read
+//! [Function::ops] directly, call [Bytecode::decode_all] before using them or before modifying the functions.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use crate::read::{in_element, Position, Tracked};
+use crate::types::Function;
+use crate::{Bytecode, Opcode, RefFunKnown, Result};
+
+/// Input after the natives
+#[derive(Debug)]
+pub(crate) enum Rest {
+    /// Read in memory, or borrowed when loading from a static buffer
+    Buffer(Cow<'static, [u8]>),
+    /// Memory mapped file from an offset, the mapping is released with the bytecode
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<memmap2::Mmap>, usize),
+}
+
+impl Default for Rest {
+    fn default() -> Self {
+        Rest::Buffer(Cow::Borrowed(&[]))
+    }
+}
+
+impl Deref for Rest {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Rest::Buffer(data) => data,
+            #[cfg(feature = "mmap")]
+            Rest::Mapped(map, start) => &map[*start..],
+        }
+    }
+}
+
+/// Encoded function bodies of a lazily loaded bytecode
+#[derive(Debug, Default)]
+pub(crate) struct LazyBodies {
+    data: Rest,
+    /// Offset of `data` in the input, for errors
+    offset: u64,
+    /// Encoded instructions and their count for each function of the pool
+    bodies: Vec<Option<(Range<usize>, usize)>>,
+    /// Bodies decoded by [Bytecode::function_ops], by index in the functions pool
+    decoded: RwLock<HashMap<usize, Arc<[Opcode]>>>,
+}
+
+impl LazyBodies {
+    pub(crate) fn new(
+        data: Rest,
+        offset: u64,
+        bodies: Vec<Option<(Range<usize>, usize)>>,
+    ) -> Self {
+        Self {
+            data,
+            offset,
+            bodies,
+            decoded: Default::default(),
+        }
+    }
+
+    /// Decode the body of the function `i` of the pool
+    fn decode(&self, i: usize, (range, nops): &(Range<usize>, usize)) -> Result<Vec<Opcode>> {
+        let mut r = Tracked::new(&self.data[range.clone()], self.offset + range.start as u64);
+        let ops: Result<Vec<Opcode>> = (0..*nops)
+            .map(|j| {
+                let start = r.position();
+                in_element(Opcode::read(&mut r), start, || format!("opcode {j}"))
+            })
+            .collect();
+        in_element(ops, 0, || format!("function {i}"))
+    }
+}
+
+/// Instructions of a function, borrowed from the function or decoded on demand
+#[derive(Debug, Clone)]
+pub enum Ops<'a> {
+    Borrowed(&'a [Opcode]),
+    Decoded(Arc<[Opcode]>),
+}
+
+impl Deref for Ops<'_> {
+    type Target = [Opcode];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Ops::Borrowed(ops) => ops,
+            Ops::Decoded(ops) => ops,
+        }
+    }
+}
+
+impl Bytecode {
+    /// Read the bytecode from a file without decoding the function bodies, see [Bytecode::deserialize_lazy].
+    pub fn from_file_lazy(path: impl AsRef<Path>) -> Result<Self> {
+        Self::deserialize_lazy(&mut BufReader::with_capacity(
+            512 * 1024,
+            fs::File::open(path)?,
+        ))
+    }
+
+    /// Load the bytecode without decoding the function bodies, they are decoded on demand by [Bytecode::function_ops].
+    /// The input after the natives is kept in memory.
+    pub fn deserialize_lazy(r: impl BufRead) -> Result<Self> {
+        Self::deserialize_with(r, true, None)
+    }
+
+    /// true if some function bodies have not been decoded in [Function::ops]
+    pub fn is_lazy(&self) -> bool {
+        self.lazy.bodies.iter().any(Option::is_some)
+    }
+
+    /// Instructions of a function, decoding them if the bytecode has been loaded lazily
+    pub fn function_ops<'a>(&'a self, f: &'a Function) -> Result<Ops<'a>> {
+        let body = match self.findexes.get(f.findex.0) {
+            Some(&RefFunKnown::Fun(i)) if f.ops.is_empty() => self
+                .lazy
+                .bodies
+                .get(i)
+                .and_then(Option::as_ref)
+                .map(|body| (i, body)),
+            _ => None,
+        };
+        let Some((i, body)) = body else {
+            return Ok(Ops::Borrowed(&f.ops));
+        };
+        if let Ok(decoded) = self.lazy.decoded.read() {
+            if let Some(ops) = decoded.get(&i) {
+                return Ok(Ops::Decoded(ops.clone()));
+            }
+        }
+        let ops: Arc<[Opcode]> = self.lazy.decode(i, body)?.into();
+        if let Ok(mut decoded) = self.lazy.decoded.write() {
+            decoded.insert(i, ops.clone());
+        }
+        Ok(Ops::Decoded(ops))
+    }
+
+    /// Decode every remaining function body in [Function::ops] and release the encoded input.
+    /// The bytecode is left untouched on error.
+    pub fn decode_all(&mut self) -> Result<()> {
+        let bodies = self
+            .lazy
+            .bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                body.as_ref()
+                    .map(|body| self.lazy.decode(i, body))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (f, ops) in self.functions.iter_mut().zip(bodies) {
+            if let Some(ops) = ops {
+                f.ops = ops;
+            }
+        }
+        self.lazy = LazyBodies::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::unused::UnusedPools;
+    use crate::link::Linker;
+    use crate::testing::{reload, sample};
+    use crate::types::{RefFun, Reg};
+    use crate::{Bytecode, Opcode};
+
+    #[test]
+    fn test_lazy() -> crate::Result<()> {
+        let code = sample();
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        let mut lazy = Bytecode::deserialize_lazy(data.as_slice())?;
+        assert!(lazy.is_lazy());
+        assert!(lazy.functions.iter().all(|f| f.ops.is_empty()));
+        let length = lazy.function_ops(&lazy.functions[1])?;
+        assert_eq!(length.len(), 2);
+        assert!(matches!(length[1], Opcode::Ret { ret: Reg(1) }));
+        for (f, l) in code.functions.iter().zip(&lazy.functions) {
+            assert_eq!(
+                format!("{:?}", f.ops),
+                format!("{:?}", &*lazy.function_ops(l)?)
+            );
+        }
+
+        // Encoding doesn't need the bodies to be decoded
+        let mut reencoded = Vec::new();
+        lazy.serialize(&mut reencoded)?;
+        assert_eq!(reencoded, data);
+
+        lazy.decode_all()?;
+        assert!(!lazy.is_lazy());
+        assert_eq!(lazy.functions[0].ops.len(), 8);
+        for (f, l) in code.functions.iter().zip(&lazy.functions) {
+            assert_eq!(format!("{:?}", f.ops), format!("{:?}", l.ops));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_edits() -> crate::Result<()> {
+        let mut data = Vec::new();
+        sample().serialize(&mut data)?;
+        let lazy = || Bytecode::deserialize_lazy(data.as_slice());
+
+        // Pools used by the bodies still in the stream are not reported as unused
+        assert_eq!(UnusedPools::new(&lazy()?)?, UnusedPools::default());
+
+        let mut code = lazy()?;
+        code.gc_pools()?;
+        assert!(!code.is_lazy());
+        assert!(reload(&code).validate().is_empty());
+
+        let mut code = lazy()?;
+        code.dedup_pools()?;
+        assert!(reload(&code).validate().is_empty());
+
+        // The linker copies from f.ops
+        let mut src = lazy()?;
+        let mut dst = Bytecode {
+            bytes: Some((Vec::new(), Vec::new())),
+            constants: Some(Vec::new()),
+            ..Default::default()
+        };
+        assert!(Linker::new(&mut dst, &src).is_err());
+        src.decode_all()?;
+        let mut linker = Linker::new(&mut dst, &src)?;
+        linker.function(RefFun(0));
+        linker.finish();
+        assert!(reload(&dst).validate().is_empty());
+        Ok(())
+    }
+}
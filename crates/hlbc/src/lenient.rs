@@ -0,0 +1,218 @@
+//! Loading mode tolerating corrupted or truncated inputs.
+//!
+//! [Bytecode::deserialize_lenient] replaces an element that can't be decoded by a placeholder and reports it as a
+//! [Diagnostic]. Elements are variable length, so the end of a broken element is unknown : every element after it is
+//! a placeholder too. The elements decoded before the error are kept as is.
+//!
+//! Placeholder types are `void`, placeholder globals are `void`, placeholder functions and natives have no
+//! instructions, a `() -> void` type and an unused findex. Constants after the error are dropped. A count in the header
+//! larger than what the rest of the input can hold is reduced, the elements past it are dropped.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::summary::Section;
+use crate::types::{RefFun, Type, TypeFun};
+use crate::{Bytecode, Error, Result};
+
+/// Elements of a section replaced by placeholders, or dropped when the count of the section doesn't fit in the input
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub section: Section,
+    /// Index of the first placeholder in the section
+    pub index: usize,
+    /// Number of placeholders
+    pub count: usize,
+    /// Error decoding the first placeholder, None if the section wasn't reached because of a previous error
+    pub error: Option<Error>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}: {} placeholders from index {}",
+            self.section, self.count, self.index
+        )?;
+        match &self.error {
+            Some(e) => write!(f, " ({e})"),
+            None => write!(f, " (not reached)"),
+        }
+    }
+}
+
+impl Bytecode {
+    /// Read the bytecode from a file, see [Bytecode::deserialize_lenient].
+    pub fn from_file_lenient(path: impl AsRef<Path>) -> Result<(Self, Vec<Diagnostic>)> {
+        Self::deserialize_lenient(&mut BufReader::with_capacity(
+            512 * 1024,
+            fs::File::open(path)?,
+        ))
+    }
+
+    /// Load the bytecode, replacing the elements that can't be decoded by placeholders instead of failing.
+    /// Only an invalid header is an error. The diagnostics are empty if the input is valid.
+    ///
+    /// The input is read in memory first, the counts of the header are bounded by its size.
+    pub fn deserialize_lenient(r: impl BufRead) -> Result<(Self, Vec<Diagnostic>)> {
+        let mut diagnostics = Vec::new();
+        let code = Self::deserialize_with(r, false, Some(&mut diagnostics))?;
+        Ok((code, diagnostics))
+    }
+
+    /// Make the placeholders consistent with the rest of the bytecode, before the acceleration structures are built
+    pub(crate) fn fix_placeholders(&mut self, diagnostics: &[Diagnostic]) {
+        if let Some(d) = diagnostics.iter().find(|d| d.section == Section::Constants) {
+            if let Some(constants) = &mut self.constants {
+                constants.truncate(d.index);
+            }
+        }
+
+        // Elements dropped because their count didn't fit in the input are past the end
+        let placeholders = |section: Section, len: usize| -> HashSet<usize> {
+            diagnostics
+                .iter()
+                .filter(|d| d.section == section)
+                .flat_map(|d| d.index..(d.index + d.count).min(len))
+                .collect()
+        };
+        let globals = placeholders(Section::Globals, self.globals.len());
+        let natives = placeholders(Section::Natives, self.natives.len());
+        let functions = placeholders(Section::Functions, self.functions.len());
+        if globals.is_empty() && natives.is_empty() && functions.is_empty() {
+            return;
+        }
+
+        let void = self.intern_type(Type::Void);
+        let fun = self.intern_type(Type::Fun(TypeFun {
+            args: Vec::new(),
+            ret: void,
+        }));
+        for &i in &globals {
+            self.globals[i] = void;
+        }
+
+        // Placeholders take the findexes not used by the decoded functions
+        let total = self.functions.len() + self.natives.len();
+        let mut used = vec![false; total];
+        let decoded = self
+            .natives
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !natives.contains(i))
+            .map(|(_, n)| n.findex)
+            .chain(
+                self.functions
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !functions.contains(i))
+                    .map(|(_, f)| f.findex),
+            );
+        for findex in decoded {
+            if let Some(u) = used.get_mut(findex.0) {
+                *u = true;
+            }
+        }
+        let mut free = (0..total).filter(|&i| !used[i]).map(RefFun);
+        for (i, n) in self.natives.iter_mut().enumerate() {
+            if natives.contains(&i) {
+                n.t = fun;
+                n.findex = free.next().unwrap_or_default();
+            }
+        }
+        for (i, f) in self.functions.iter_mut().enumerate() {
+            if functions.contains(&i) {
+                f.t = fun;
+                f.findex = free.next().unwrap_or_default();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::summary::Section;
+    use crate::testing::sample;
+    use crate::Bytecode;
+
+    #[test]
+    fn test_lenient() -> crate::Result<()> {
+        let code = sample();
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+
+        let (valid, diagnostics) = Bytecode::deserialize_lenient(data.as_slice())?;
+        assert!(diagnostics.is_empty());
+        assert_eq!(valid.functions.len(), code.functions.len());
+
+        // Cut in the middle of the functions
+        let writer = code.sized_writer()?;
+        let functions: usize = writer
+            .sizes()
+            .iter()
+            .take_while(|&&(s, _)| s != Section::Functions)
+            .map(|&(_, size)| size)
+            .sum();
+        data.truncate(functions + writer.section_size(Section::Functions).unwrap() / 2);
+        assert!(Bytecode::deserialize(data.as_slice()).is_err());
+        let (partial, diagnostics) = Bytecode::deserialize_lenient(data.as_slice())?;
+        assert_eq!(partial.functions.len(), 4);
+        assert_eq!(partial.types.len(), code.types.len());
+        // main is decoded, the 3 other functions are placeholders
+        assert_eq!(diagnostics.len(), 2);
+        let first = &diagnostics[0];
+        assert_eq!(
+            (first.section, first.index, first.count),
+            (Section::Functions, 1, 3)
+        );
+        assert!(first.error.is_some());
+        assert_eq!(partial.functions[0].ops.len(), 8);
+        assert!(partial.functions[1..].iter().all(|f| f.ops.is_empty()));
+        assert_eq!(diagnostics[1].section, Section::Constants);
+        assert!(diagnostics[1].error.is_none());
+        // Placeholders have their own findex
+        let findexes: HashSet<_> = partial
+            .functions
+            .iter()
+            .map(|f| f.findex)
+            .chain(partial.natives.iter().map(|n| n.findex))
+            .collect();
+        assert_eq!(findexes.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted() -> crate::Result<()> {
+        let code = sample();
+        let writer = code.sized_writer()?;
+        let data = writer.to_vec()?;
+
+        // Negative size of the strings data
+        let strings: usize = writer
+            .sizes()
+            .iter()
+            .take_while(|&&(s, _)| s != Section::Strings)
+            .map(|&(_, size)| size)
+            .sum();
+        let mut corrupted = data.clone();
+        corrupted[strings + 3] = 0xFF;
+        let (_, diagnostics) = Bytecode::deserialize_lenient(corrupted.as_slice())?;
+        assert_eq!(diagnostics[0].section, Section::Strings);
+        assert!(diagnostics[0].error.is_some());
+
+        // Any corrupted byte, counts included
+        for i in 0..data.len() {
+            for mask in [0x01, 0x40, 0x80, 0xFF] {
+                let mut corrupted = data.clone();
+                corrupted[i] ^= mask;
+                let _ = Bytecode::deserialize_lenient(corrupted.as_slice());
+            }
+        }
+        Ok(())
+    }
+}
@@ -9,18 +9,37 @@ extern crate core;
 
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::ops::Index;
+use std::ops::{Index, IndexMut};
 
+use crate::lazy::LazyBodies;
+use crate::names::NameCache;
 use crate::opcodes::Opcode;
+use crate::rename::Renames;
 use crate::types::{
-    ConstantDef, FunPtr, Function, Native, ObjField, RefFloat, RefFun, RefGlobal, RefInt,
-    RefString, RefType, Type, TypeObj,
+    ConstantDef, ConstantValue, FunPtr, Function, Native, ObjField, RefBytes, RefField, RefFloat,
+    RefFun, RefGlobal, RefInt, RefString, RefType, Type, TypeObj,
 };
 
 pub mod analysis;
+/// Type compatibility rules
+pub mod compat;
+/// Stripping and generating debug information
+mod debug;
+/// Editing instructions and function bodies
+mod edit;
+pub mod embedded;
 pub mod fmt;
+pub mod lazy;
+pub mod lenient;
+/// Copying elements between bytecode files
+pub mod link;
+mod names;
 /// Opcodes definitions.
 pub mod opcodes;
+/// Addressing elements by their Haxe path
+pub mod path;
+/// Constant pools management
+mod pools;
 /// All about reading bytecode
 mod read;
 /// Bytecode elements definitions.
@@ -28,8 +47,24 @@ mod read;
 /// They are required since we cannot use rust references as that would make our structure self-referential.
 /// They makes the code look a bit more complicated than it actually is. Every Ref* struct is cheaply copyable.
 pub mod types;
+/// Renaming bytecode elements
+pub mod rename;
+/// Checking that a bytecode is encoded back identically
+pub mod roundtrip;
+/// Deserialization of [Bytecode] with serde
+#[cfg(feature = "serde")]
+mod serde_impl;
+/// Bytecode summary and sections sizes
+pub mod summary;
+/// Bytecodes built in memory for the tests
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+/// Bytecode verifier
+pub mod validate;
+/// Walking the bytecode with a visitor
+pub mod visit;
 /// All about writing bytecode
-mod write;
+pub mod write;
 
 /// Cheaply cloneable string with inline storage
 // pub type Str = smol_str::SmolStr;
@@ -43,6 +78,15 @@ pub type Result<T> = core::result::Result<T, Error>;
 pub enum Error {
     #[error("Malformed bytecode: {0}")]
     MalformedBytecode(String),
+    /// Error while decoding an element of the bytecode
+    #[error("At offset {offset:#x} in {context}: {source}")]
+    Parse {
+        /// Offset of the innermost element in the input
+        offset: u64,
+        /// Elements being decoded, from the outermost (e.g. `function 2314, opcode 87`)
+        context: String,
+        source: Box<Error>,
+    },
     #[error("Unsupported bytecode version {version} (expected {min} <= version <= {max})")]
     UnsupportedVersion { version: u8, min: u8, max: u8 },
     #[error("Value '{value}' is too big to be serialized (|expected| < {limit})")]
@@ -51,13 +95,21 @@ pub enum Error {
     IoError(#[from] std::io::Error),
     #[error(transparent)]
     Utf8Error(#[from] core::str::Utf8Error),
+    #[error("Malformed renames at line {line}: {msg}")]
+    MalformedRenames { line: usize, msg: String },
+    #[error("Invalid edit: {0}")]
+    InvalidEdit(String),
 }
 
 /// Bytecode structure containing all the information.
 /// Every field is public for flexibility, but you aren't encouraged to modify them.
 ///
 /// This type is like an arena, you usually work with custom
+///
+/// With the `serde` feature, the bytecode can be serialized with any serde format (e.g. to JSON).
+/// Acceleration structures are not serialized but rebuilt on deserialization.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Bytecode {
     /// Bytecode format version
     pub version: u8,
@@ -91,10 +143,25 @@ pub struct Bytecode {
     // Fields below are not part of the data.
     // Those are acceleration structures used to speed up lookup.
     /// Acceleration structure mapping function references (findex) to functions indexes in the native or function pool.
+    #[cfg_attr(feature = "serde", serde(skip))]
     findexes: Vec<RefFunKnown>,
-    /// Acceleration structure mapping function names to function indexes in the function pool
-    fnames: HashMap<Str, usize>,
+    /// Acceleration structure mapping function names to function indexes in the function pool, in pool order
+    #[cfg_attr(feature = "serde", serde(skip))]
+    fnames: HashMap<Str, Vec<usize>>,
+    /// Acceleration structure mapping qualified function names (`pack.Class.method`) to function indexes in the function pool
+    #[cfg_attr(feature = "serde", serde(skip))]
+    qnames: HashMap<Str, usize>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub globals_initializers: HashMap<RefGlobal, usize>,
+    /// User assigned names, see [rename::Renames]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub renames: Renames,
+    /// Cache for [Bytecode::display_names]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    display_names: NameCache,
+    /// Function bodies not decoded yet, see [lazy]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    lazy: LazyBodies,
 }
 
 impl Bytecode {
@@ -103,15 +170,47 @@ impl Bytecode {
         self.get(self.entrypoint).as_fn().unwrap()
     }
 
-    /// Get the main function.
+    /// Get the main function, the last one in the pool if multiple functions are named `main`.
     /// This will panic if there is no main function in the bytecode (there should always be one)
     pub fn main(&self) -> &Function {
-        &self.functions[*self.fnames.get("main").unwrap()]
+        self.functions_by_name("main").last().unwrap()
     }
 
-    /// Get a function by its name.
+    /// Get a function by its name, the first one in the pool if multiple functions share that name.
+    /// See [Bytecode::functions_by_name] and [Bytecode::function_by_qualified_name].
     pub fn function_by_name(&self, name: &str) -> Option<&Function> {
-        self.fnames.get(name).map(|&i| &self.functions[i])
+        self.functions_by_name(name).next()
+    }
+
+    /// Get every function with this name, in pool order.
+    pub fn functions_by_name<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a Function> + 'a {
+        self.fnames
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|&i| &self.functions[i])
+    }
+
+    /// Get a function by its name prefixed with its class and package (`pack.Class.method`).
+    /// Functions without a class are only indexed by their name.
+    pub fn function_by_qualified_name(&self, name: &str) -> Option<&Function> {
+        self.qnames.get(name).map(|&i| &self.functions[i])
+    }
+
+    /// Fields of the constants with a value matching the predicate, as the global initialized and the field index
+    pub fn find_constants(
+        &self,
+        mut pred: impl FnMut(&ConstantValue) -> bool,
+    ) -> Vec<(RefGlobal, RefField)> {
+        let mut found = Vec::new();
+        for c in self.constants.iter().flatten() {
+            for (i, value) in c.values(self).iter().enumerate() {
+                if pred(value) {
+                    found.push((c.global, RefField(i)));
+                }
+            }
+        }
+        found
     }
 
     pub fn findex_max(&self) -> usize {
@@ -122,8 +221,105 @@ impl Bytecode {
         (0..self.findex_max()).map(RefFun).map(|r| self.get(r))
     }
 
+    /// Number of constants in the bytes pool. Before bytecode v5, bytes are stored in the strings pool.
+    pub fn bytes_count(&self) -> usize {
+        match &self.bytes {
+            Some((_, pos)) => pos.len(),
+            None => self.strings.len(),
+        }
+    }
+
+    /// Length of a bytes constant, computed from the positions table
+    pub fn bytes_len(&self, index: RefBytes) -> Option<usize> {
+        match &self.bytes {
+            Some((data, pos)) => {
+                let start = *pos.get(index.0)?;
+                let end = pos.get(index.0 + 1).copied().unwrap_or(data.len());
+                end.checked_sub(start)
+            }
+            None => self.strings.get(index.0).map(|s| s.len()),
+        }
+    }
+
     pub fn debug_file(&self, index: usize) -> Option<Str> {
-        self.debug_files.as_ref().map(|files| files[index].clone())
+        self.debug_files.as_ref()?.get(index).cloned()
+    }
+
+    /// The constant initializing a global, if it is initialized by a constant instead of code
+    pub fn global_constant(&self, global: RefGlobal) -> Option<&ConstantDef> {
+        let &i = self.globals_initializers.get(&global)?;
+        self.constants.as_ref()?.get(i)
+    }
+
+    /// Rebuild the acceleration structures (function indexes, function names and globals initializers).
+    ///
+    /// Those are computed once when loading the bytecode. Call this method after modifying the
+    /// functions, natives or constants pools (e.g. through [ResolveMut] or the public fields),
+    /// otherwise lookups like [Resolve<RefFun>] or [Bytecode::function_by_name] can return stale results.
+    pub fn rebuild_indexes(&mut self) {
+        self.build_findexes();
+        self.build_fnames();
+        self.build_globals_initializers();
+        self.display_names.invalidate();
+    }
+
+    /// Global function indexes
+    pub(crate) fn build_findexes(&mut self) {
+        self.findexes = vec![RefFunKnown::Fun(0); self.functions.len() + self.natives.len()];
+        // Invalid findexes are ignored, the verifier reports them
+        for (i, f) in self.functions.iter().enumerate() {
+            if let Some(e) = self.findexes.get_mut(f.findex.0) {
+                *e = RefFunKnown::Fun(i);
+            }
+        }
+        for (i, n) in self.natives.iter().enumerate() {
+            if let Some(e) = self.findexes.get_mut(n.findex.0) {
+                *e = RefFunKnown::Native(i);
+            }
+        }
+    }
+
+    /// Function names
+    pub(crate) fn build_fnames(&mut self) {
+        self.fnames = HashMap::with_capacity(self.functions.len());
+        self.qnames = HashMap::with_capacity(self.functions.len());
+        for (i, f) in self.functions.iter().enumerate() {
+            let name = self.strings.get(f.name.0).cloned().unwrap_or_default();
+            let parent = f
+                .parent
+                .and_then(|p| self.types.get(p.0))
+                .and_then(Type::get_type_obj);
+            let qualified = match parent {
+                Some(obj) => Str::from(format!(
+                    "{}.{}",
+                    self.strings
+                        .get(obj.name.0)
+                        .map_or("", |s| s.trim_start_matches('$')),
+                    name
+                )),
+                None => name.clone(),
+            };
+            // The first function wins, a class can't have two methods with the same name
+            self.qnames.entry(qualified).or_insert(i);
+            self.fnames.entry(name).or_default().push(i);
+        }
+        let init = match self.findexes.get(self.entrypoint.0) {
+            Some(&RefFunKnown::Fun(x)) => x,
+            _ => 0,
+        };
+        self.fnames.insert(Str::from("init"), vec![init]);
+    }
+
+    pub(crate) fn build_globals_initializers(&mut self) {
+        self.globals_initializers = if let Some(constants) = &self.constants {
+            let mut tmp = HashMap::with_capacity(constants.len());
+            for (i, c) in constants.iter().enumerate() {
+                tmp.insert(c.global, i);
+            }
+            tmp
+        } else {
+            HashMap::new()
+        };
     }
 }
 
@@ -144,7 +340,11 @@ impl Default for Bytecode {
             constants: None,
             findexes: vec![],
             fnames: Default::default(),
+            qnames: Default::default(),
             globals_initializers: Default::default(),
+            renames: Default::default(),
+            display_names: Default::default(),
+            lazy: Default::default(),
         }
     }
 }
@@ -195,6 +395,21 @@ impl Resolve<RefString> for Bytecode {
     }
 }
 
+/// Content of a bytes constant, before bytecode v5 bytes are nul terminated strings
+impl Resolve<RefBytes> for Bytecode {
+    type Output<'a> = &'a [u8];
+
+    fn get(&self, index: RefBytes) -> Self::Output<'_> {
+        match &self.bytes {
+            Some((data, pos)) => {
+                let end = pos.get(index.0 + 1).copied().unwrap_or(data.len());
+                &data[pos[index.0]..end]
+            }
+            None => self.strings[index.0].as_bytes(),
+        }
+    }
+}
+
 impl Resolve<RefType> for Bytecode {
     type Output<'a> = &'a Type;
 
@@ -224,6 +439,140 @@ impl Resolve<RefFun> for Bytecode {
 
 //endregion
 
+//region TryResolve
+
+/// Fallible version of [Resolve], returns [None] instead of panicking when the index is out of bounds.
+///
+/// Useful when working with untrusted or corrupted bytecode.
+pub trait TryResolve<I>: Resolve<I> {
+    fn try_get(&self, index: I) -> Option<<Self as Resolve<I>>::Output<'_>>;
+}
+
+impl TryResolve<RefInt> for Bytecode {
+    fn try_get(&self, index: RefInt) -> Option<i32> {
+        self.ints.get(index.0).copied()
+    }
+}
+
+impl TryResolve<RefFloat> for Bytecode {
+    fn try_get(&self, index: RefFloat) -> Option<f64> {
+        self.floats.get(index.0).copied()
+    }
+}
+
+impl TryResolve<RefString> for Bytecode {
+    fn try_get(&self, index: RefString) -> Option<Str> {
+        if index.0 > 0 {
+            self.strings.get(index.0).cloned()
+        } else {
+            Some(Str::from_static("<none>"))
+        }
+    }
+}
+
+impl TryResolve<RefBytes> for Bytecode {
+    fn try_get(&self, index: RefBytes) -> Option<&[u8]> {
+        match &self.bytes {
+            Some((data, pos)) => {
+                let end = pos.get(index.0 + 1).copied().unwrap_or(data.len());
+                data.get(*pos.get(index.0)?..end)
+            }
+            None => self.strings.get(index.0).map(|s| s.as_bytes()),
+        }
+    }
+}
+
+impl TryResolve<RefType> for Bytecode {
+    fn try_get(&self, index: RefType) -> Option<&Type> {
+        self.types.get(index.0)
+    }
+}
+
+impl TryResolve<RefGlobal> for Bytecode {
+    fn try_get(&self, index: RefGlobal) -> Option<&RefType> {
+        self.globals.get(index.0)
+    }
+}
+
+impl TryResolve<RefFun> for Bytecode {
+    fn try_get(&self, index: RefFun) -> Option<FunPtr<'_>> {
+        match *self.findexes.get(index.0)? {
+            RefFunKnown::Fun(fun) => self.functions.get(fun).map(FunPtr::Fun),
+            RefFunKnown::Native(n) => self.natives.get(n).map(FunPtr::Native),
+        }
+    }
+}
+
+//endregion
+
+//region ResolveMut
+
+/// Mutable counterpart of [Resolve].
+///
+/// Modifying the functions or natives pools this way does not update the acceleration structures,
+/// call [Bytecode::rebuild_indexes] when you're done.
+pub trait ResolveMut<I> {
+    type Output<'a>
+    where
+        Self: 'a;
+
+    fn get_mut(&mut self, index: I) -> Self::Output<'_>;
+}
+
+impl ResolveMut<RefInt> for Bytecode {
+    type Output<'a> = &'a mut i32;
+
+    fn get_mut(&mut self, index: RefInt) -> Self::Output<'_> {
+        &mut self.ints[index.0]
+    }
+}
+
+impl ResolveMut<RefFloat> for Bytecode {
+    type Output<'a> = &'a mut f64;
+
+    fn get_mut(&mut self, index: RefFloat) -> Self::Output<'_> {
+        &mut self.floats[index.0]
+    }
+}
+
+impl ResolveMut<RefString> for Bytecode {
+    type Output<'a> = &'a mut Str;
+
+    fn get_mut(&mut self, index: RefString) -> Self::Output<'_> {
+        &mut self.strings[index.0]
+    }
+}
+
+impl ResolveMut<RefType> for Bytecode {
+    type Output<'a> = &'a mut Type;
+
+    fn get_mut(&mut self, index: RefType) -> Self::Output<'_> {
+        &mut self.types[index.0]
+    }
+}
+
+impl ResolveMut<RefGlobal> for Bytecode {
+    type Output<'a> = &'a mut RefType;
+
+    fn get_mut(&mut self, index: RefGlobal) -> Self::Output<'_> {
+        &mut self.globals[index.0]
+    }
+}
+
+impl ResolveMut<RefFun> for Bytecode {
+    /// Natives have no body to modify, hence the [Option].
+    type Output<'a> = Option<&'a mut Function>;
+
+    fn get_mut(&mut self, index: RefFun) -> Self::Output<'_> {
+        match self.findexes[index.0] {
+            RefFunKnown::Fun(fun) => Some(&mut self.functions[fun]),
+            RefFunKnown::Native(_) => None,
+        }
+    }
+}
+
+//endregion
+
 // region Index impl
 
 impl Index<RefInt> for Bytecode {
@@ -266,4 +615,177 @@ impl Index<RefGlobal> for Bytecode {
     }
 }
 
+impl IndexMut<RefInt> for Bytecode {
+    fn index_mut(&mut self, index: RefInt) -> &mut Self::Output {
+        self.ints.index_mut(index.0)
+    }
+}
+
+impl IndexMut<RefFloat> for Bytecode {
+    fn index_mut(&mut self, index: RefFloat) -> &mut Self::Output {
+        self.floats.index_mut(index.0)
+    }
+}
+
+impl IndexMut<RefString> for Bytecode {
+    fn index_mut(&mut self, index: RefString) -> &mut Self::Output {
+        self.strings.index_mut(index.0)
+    }
+}
+
+impl IndexMut<RefType> for Bytecode {
+    fn index_mut(&mut self, index: RefType) -> &mut Self::Output {
+        self.types.index_mut(index.0)
+    }
+}
+
+impl IndexMut<RefGlobal> for Bytecode {
+    fn index_mut(&mut self, index: RefGlobal) -> &mut Self::Output {
+        self.globals.index_mut(index.0)
+    }
+}
+
 //endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{bytecode, class, fun, obj, reload, sample};
+    use crate::types::{
+        ConstantDef, ConstantValue, RefBytes, RefField, RefFun, RefGlobal, RefInt, RefString,
+        RefType, Type,
+    };
+    use crate::{Bytecode, Resolve, ResolveMut, Str, TryResolve};
+
+    #[test]
+    fn test_get_mut_rebuild_indexes() -> crate::Result<()> {
+        let mut code = sample();
+        let main = code.function_by_name("main").unwrap().findex;
+        assert_eq!(main, RefFun(0));
+        code.strings.push(Str::from("renamed"));
+        let name = RefString(code.strings.len() - 1);
+        code.get_mut(main).unwrap().name = name;
+        code.rebuild_indexes();
+        assert!(code.function_by_name("main").is_none());
+        assert_eq!(code.function_by_name("renamed").unwrap().findex, main);
+        Ok(())
+    }
+
+    #[test]
+    fn test_functions_by_name() -> crate::Result<()> {
+        let code = sample();
+        let findexes = |name| {
+            code.functions_by_name(name)
+                .map(|f| f.findex)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(findexes("main"), vec![RefFun(0)]);
+        assert_eq!(findexes("length"), vec![RefFun(1)]);
+        assert!(findexes("missing").is_empty());
+        assert_eq!(
+            code.function_by_qualified_name("Main.main").unwrap().findex,
+            RefFun(0)
+        );
+        assert_eq!(
+            code.function_by_qualified_name("Point.length")
+                .unwrap()
+                .findex,
+            RefFun(1)
+        );
+        assert!(code.function_by_qualified_name("Point.main").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_main_duplicates() {
+        let code = bytecode(
+            &["", "$Tool", "main", "$Main"],
+            vec![
+                Type::Void,
+                fun(&[], 0),
+                class(1, &[(2, 1, 0)]),
+                class(3, &[(2, 1, 1)]),
+            ],
+            "
+            .fun fn@0 type@1
+            .reg type@0
+                Ret reg0
+            .end
+            .fun fn@1 type@1
+            .reg type@0
+                Ret reg0
+            .end",
+        );
+        assert_eq!(code.function_by_name("main").unwrap().findex, RefFun(0));
+        assert_eq!(code.main().findex, RefFun(1));
+        assert_eq!(
+            code.function_by_qualified_name("Main.main").unwrap().findex,
+            code.main().findex
+        );
+    }
+
+    #[test]
+    fn test_try_get_out_of_bounds() -> crate::Result<()> {
+        let code = sample();
+        assert_eq!(code.try_get(RefInt(1)), Some(1));
+        assert!(code.try_get(RefInt(2)).is_none());
+        assert!(code.try_get(RefType(7)).is_some());
+        assert!(code.try_get(RefType(8)).is_none());
+        assert_eq!(code.findex_max(), 4);
+        assert!(code.try_get(RefFun(4)).is_none());
+        assert!(code.try_get(code.entrypoint).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_pool() {
+        let code = Bytecode {
+            bytes: Some((b"abcdef".to_vec(), vec![0, 2, 2])),
+            ..Default::default()
+        };
+        assert_eq!(code.bytes_count(), 3);
+        assert_eq!(code.get(RefBytes(0)), b"ab");
+        assert_eq!(code.get(RefBytes(1)), b"");
+        assert_eq!(code.get(RefBytes(2)), b"cdef");
+        assert_eq!(code.bytes_len(RefBytes(2)), Some(4));
+        assert_eq!(code.bytes_len(RefBytes(3)), None);
+        assert!(code.try_get(RefBytes(3)).is_none());
+    }
+
+    #[test]
+    fn test_constant_values() -> crate::Result<()> {
+        // static var s = "hello";
+        let mut code = bytecode(
+            &["", "String", "bytes", "length", "hello"],
+            vec![
+                Type::Void,
+                Type::I32,
+                Type::Bytes,
+                Type::Obj(obj(1, None, &[(2, 2), (3, 1)], &[])),
+            ],
+            "",
+        );
+        code.ints = vec![5];
+        code.globals = vec![RefType(3)];
+        code.constants = Some(vec![ConstantDef {
+            global: RefGlobal(0),
+            fields: vec![4, 0],
+        }]);
+        let code = reload(&code);
+        let c = code.global_constant(RefGlobal(0)).unwrap();
+        assert_eq!(
+            c.values(&code),
+            vec![
+                ConstantValue::String(Str::from("hello")),
+                ConstantValue::Int(5)
+            ]
+        );
+        assert_eq!(
+            code.find_constants(|v| *v == ConstantValue::Int(5)),
+            vec![(RefGlobal(0), RefField(1))]
+        );
+        assert!(code
+            .find_constants(|v| *v == ConstantValue::Int(4))
+            .is_empty());
+        Ok(())
+    }
+}
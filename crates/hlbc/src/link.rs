@@ -0,0 +1,573 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::types::{
+    ConstantDef, EnumConstruct, FunPtr, Function, Native, ObjField, ObjProto, RefBytes, RefFloat,
+    RefFun, RefGlobal, RefInt, RefString, RefType, Type, TypeFun, TypeObj,
+};
+use crate::{Bytecode, Error, Opcode, Resolve, Result, Str};
+
+/// Copies functions and types from a bytecode file into another, with all their dependencies.
+///
+/// Every reference is remapped to the destination bytecode. Named types (classes, enums, abstracts) already present
+/// in the destination with the same name are reused instead of being copied, they are assumed to have the same layout
+/// (e.g. a mod compiled against the game classes). Their methods are reused as well. Everything else is copied,
+/// constants are interned to not duplicate existing ones.
+///
+/// ```no_run
+/// # use hlbc::Bytecode;
+/// # use hlbc::link::Linker;
+/// let mut game = Bytecode::from_file("hlboot.dat")?;
+/// let module = Bytecode::from_file("mod.hl")?;
+/// let mut linker = Linker::new(&mut game, &module)?;
+/// let main = linker.function(module.entrypoint);
+/// linker.finish();
+/// # Ok::<(), hlbc::Error>(())
+/// ```
+pub struct Linker<'a> {
+    dst: &'a mut Bytecode,
+    src: &'a Bytecode,
+    /// Named types of the destination
+    dst_names: HashMap<Str, RefType>,
+    types: HashMap<RefType, RefType>,
+    /// Source types which are reused from the destination
+    reused: HashSet<RefType>,
+    /// Named types being copied, their slot in the destination holds a placeholder
+    pending: HashSet<RefType>,
+    functions: HashMap<RefFun, RefFun>,
+    globals: HashMap<RefGlobal, RefGlobal>,
+    /// Next available findex in the destination
+    next_findex: usize,
+}
+
+impl<'a> Linker<'a> {
+    /// Function bodies are copied from [Function::ops], a lazily loaded source must be decoded with
+    /// [Bytecode::decode_all] first.
+    pub fn new(dst: &'a mut Bytecode, src: &'a Bytecode) -> Result<Self> {
+        if src.is_lazy() {
+            return Err(Error::InvalidEdit(
+                "the source bytecode has function bodies not decoded yet".to_owned(),
+            ));
+        }
+        let dst_names = dst
+            .types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| type_name(t).map(|name| (dst[name].clone(), RefType(i))))
+            .collect();
+        let next_findex = dst.functions.len() + dst.natives.len();
+        Ok(Self {
+            dst,
+            src,
+            dst_names,
+            types: HashMap::new(),
+            reused: HashSet::new(),
+            pending: HashSet::new(),
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            next_findex,
+        })
+    }
+
+    /// Update the destination acceleration structures, required before using the destination bytecode.
+    pub fn finish(self) {
+        self.dst.rebuild_indexes();
+    }
+
+    pub fn string(&mut self, s: RefString) -> RefString {
+        if s.0 == 0 {
+            // Index 0 is the 'no string' marker
+            return s;
+        }
+        self.dst.intern_string(self.src[s].clone())
+    }
+
+    pub fn int(&mut self, i: RefInt) -> RefInt {
+        self.dst.intern_int(self.src[i])
+    }
+
+    pub fn float(&mut self, f: RefFloat) -> RefFloat {
+        self.dst.intern_float(self.src[f])
+    }
+
+    pub fn bytes(&mut self, b: RefBytes) -> RefBytes {
+        // Before bytecode v5, bytes are stored nul terminated in the strings pool
+        let data: Vec<u8> = match &self.src.bytes {
+            Some((data, pos)) => {
+                let end = pos.get(b.0 + 1).copied().unwrap_or(data.len());
+                data[pos[b.0]..end].to_vec()
+            }
+            None => {
+                let mut data = self.src[RefString(b.0)].as_bytes().to_vec();
+                data.push(0);
+                data
+            }
+        };
+        match &mut self.dst.bytes {
+            Some((pool, pos)) => {
+                pos.push(pool.len());
+                pool.extend_from_slice(&data);
+                RefBytes(pos.len() - 1)
+            }
+            None => {
+                let s = String::from_utf8_lossy(data.strip_suffix(&[0]).unwrap_or(&data));
+                RefBytes(self.dst.intern_string(s.as_ref()).0)
+            }
+        }
+    }
+
+    /// Copy a type, returns the type in the destination
+    pub fn ty(&mut self, t: RefType) -> RefType {
+        if let Some(&mapped) = self.types.get(&t) {
+            return mapped;
+        }
+        let src = self.src;
+        if let Some(name) = type_name(&src[t]) {
+            if let Some(&existing) = self.dst_names.get(&src[name]) {
+                self.types.insert(t, existing);
+                self.reused.insert(t);
+                return existing;
+            }
+            // Named types can be recursive, reserve the index first
+            let mapped = RefType(self.dst.types.len());
+            self.dst.types.push(Type::Void);
+            self.types.insert(t, mapped);
+            self.pending.insert(mapped);
+            let ty = self.named_type(&src[t]);
+            self.dst.types[mapped.0] = ty;
+            self.pending.remove(&mapped);
+            return mapped;
+        }
+
+        let ty = match &src[t] {
+            Type::Fun(fun) => Type::Fun(self.type_fun(fun)),
+            Type::Method(fun) => Type::Method(self.type_fun(fun)),
+            Type::Ref(inner) => Type::Ref(self.ty(*inner)),
+            Type::Null(inner) => Type::Null(self.ty(*inner)),
+            Type::Packed(inner) => Type::Packed(self.ty(*inner)),
+            Type::Virtual { fields } => Type::Virtual {
+                fields: self.fields(fields),
+            },
+            other => other.clone(),
+        };
+        // Like Bytecode::intern_type, without matching the placeholders
+        let existing = self
+            .dst
+            .types
+            .iter()
+            .enumerate()
+            .position(|(i, e)| *e == ty && !self.pending.contains(&RefType(i)));
+        let mapped = existing.map(RefType).unwrap_or_else(|| {
+            self.dst.types.push(ty);
+            RefType(self.dst.types.len() - 1)
+        });
+        self.types.insert(t, mapped);
+        mapped
+    }
+
+    fn type_fun(&mut self, fun: &TypeFun) -> TypeFun {
+        TypeFun {
+            args: fun.args.iter().map(|&a| self.ty(a)).collect(),
+            ret: self.ty(fun.ret),
+        }
+    }
+
+    fn fields(&mut self, fields: &[ObjField]) -> Vec<ObjField> {
+        fields
+            .iter()
+            .map(|f| ObjField {
+                name: self.string(f.name),
+                t: self.ty(f.t),
+            })
+            .collect()
+    }
+
+    /// Globals references of types are 1-based, 0 means no global
+    fn type_global(&mut self, g: RefGlobal) -> RefGlobal {
+        if g.0 == 0 {
+            g
+        } else {
+            RefGlobal(self.global(RefGlobal(g.0 - 1)).0 + 1)
+        }
+    }
+
+    fn named_type(&mut self, ty: &Type) -> Type {
+        match ty {
+            Type::Obj(obj) => Type::Obj(self.type_obj(obj)),
+            Type::Struct(obj) => Type::Struct(self.type_obj(obj)),
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => Type::Enum {
+                name: self.string(*name),
+                global: self.type_global(*global),
+                constructs: constructs
+                    .iter()
+                    .map(|c| EnumConstruct {
+                        name: self.string(c.name),
+                        params: c.params.iter().map(|&p| self.ty(p)).collect(),
+                    })
+                    .collect(),
+            },
+            Type::Abstract { name } => Type::Abstract {
+                name: self.string(*name),
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn type_obj(&mut self, obj: &TypeObj) -> TypeObj {
+        let super_ = obj.super_.map(|s| self.ty(s));
+        let own_fields = self.fields(&obj.own_fields);
+        let mut fields = super_
+            .and_then(|s| self.dst[s].get_type_obj())
+            .map(|s| s.fields.clone())
+            .unwrap_or_default();
+        fields.extend_from_slice(&own_fields);
+        // In field order, so the findexes given to the copies don't depend on the order of the map
+        let mut bindings: Vec<_> = obj.bindings.iter().map(|(&f, &fun)| (f, fun)).collect();
+        bindings.sort_unstable_by_key(|(f, _)| f.0);
+        TypeObj {
+            name: self.string(obj.name),
+            super_,
+            global: self.type_global(obj.global),
+            own_fields,
+            protos: obj
+                .protos
+                .iter()
+                .map(|p| ObjProto {
+                    name: self.string(p.name),
+                    findex: self.function(p.findex),
+                    pindex: p.pindex,
+                })
+                .collect(),
+            bindings: bindings
+                .into_iter()
+                .map(|(field, fun)| (field, self.function(fun)))
+                .collect(),
+            fields,
+        }
+    }
+
+    /// Copy a global and its initializer, returns the global in the destination
+    pub fn global(&mut self, g: RefGlobal) -> RefGlobal {
+        if let Some(&mapped) = self.globals.get(&g) {
+            return mapped;
+        }
+        let src = self.src;
+        // Static part of a reused type
+        let owner = src.types.iter().enumerate().find(|(_, t)| match t {
+            Type::Obj(obj) | Type::Struct(obj) => obj.global.0 == g.0 + 1,
+            Type::Enum { global, .. } => global.0 == g.0 + 1,
+            _ => false,
+        });
+        if let Some((owner, _)) = owner {
+            let mapped = self.ty(RefType(owner));
+            if self.reused.contains(&RefType(owner)) {
+                let global = match &self.dst[mapped] {
+                    Type::Obj(obj) | Type::Struct(obj) => obj.global,
+                    Type::Enum { global, .. } => *global,
+                    _ => RefGlobal(0),
+                };
+                if global.0 > 0 {
+                    let global = RefGlobal(global.0 - 1);
+                    self.globals.insert(g, global);
+                    return global;
+                }
+            }
+            // Copying the owner may have copied the global already
+            if let Some(&mapped) = self.globals.get(&g) {
+                return mapped;
+            }
+        }
+
+        let mapped = RefGlobal(self.dst.globals.len());
+        self.globals.insert(g, mapped);
+        self.dst.globals.push(RefType(0));
+        let t = self.ty(src.globals[g.0]);
+        self.dst.globals[mapped.0] = t;
+
+        if let Some(&c) = src.globals_initializers.get(&g) {
+            let c = &src.constants.as_ref().unwrap()[c];
+            let def = ConstantDef {
+                global: mapped,
+                fields: self.constant_fields(src.globals[g.0], &c.fields),
+            };
+            if let Some(constants) = &mut self.dst.constants {
+                constants.push(def);
+            }
+        }
+        mapped
+    }
+
+    /// Constant values are indexes in the pool corresponding to the field type, like the Hashlink runtime does
+    fn constant_fields(&mut self, t: RefType, values: &[usize]) -> Vec<usize> {
+        let src = self.src;
+        let fields = match src[t].get_type_obj() {
+            Some(obj) => &obj.fields,
+            None => return values.to_vec(),
+        };
+        values
+            .iter()
+            .zip(fields)
+            .map(|(&v, f)| match &src[f.t] {
+                Type::I32 | Type::UI8 | Type::UI16 | Type::I64 => self.int(RefInt(v)).0,
+                Type::F32 | Type::F64 => self.float(RefFloat(v)).0,
+                Type::Bool => v,
+                Type::Bytes => self.string(RefString(v)).0,
+                Type::Type => self.ty(RefType(v)).0,
+                _ => self.global(RefGlobal(v)).0,
+            })
+            .collect()
+    }
+
+    /// Copy a function or a native with all its dependencies, returns the function in the destination
+    pub fn function(&mut self, f: RefFun) -> RefFun {
+        if let Some(&mapped) = self.functions.get(&f) {
+            return mapped;
+        }
+        let src = self.src;
+        match src.get(f) {
+            FunPtr::Native(n) => {
+                let existing =
+                    self.dst.natives.iter().find(|dn| {
+                        self.dst[dn.lib] == src[n.lib] && self.dst[dn.name] == src[n.name]
+                    });
+                let mapped = match existing {
+                    Some(dn) => dn.findex,
+                    None => {
+                        let findex = self.reserve_findex();
+                        let native = Native {
+                            name: self.string(n.name),
+                            lib: self.string(n.lib),
+                            t: self.ty(n.t),
+                            findex,
+                        };
+                        self.dst.natives.push(native);
+                        findex
+                    }
+                };
+                self.functions.insert(f, mapped);
+                mapped
+            }
+            FunPtr::Fun(fun) => {
+                // Methods of reused types are reused
+                if let Some(parent) = fun.parent {
+                    let mapped_parent = self.ty(parent);
+                    if self.reused.contains(&parent) {
+                        let existing = self.dst.functions.iter().find(|df| {
+                            df.parent == Some(mapped_parent) && self.dst[df.name] == src[fun.name]
+                        });
+                        if let Some(df) = existing {
+                            let mapped = df.findex;
+                            self.functions.insert(f, mapped);
+                            return mapped;
+                        }
+                    }
+                    // Copying the parent copies its methods
+                    if let Some(&mapped) = self.functions.get(&f) {
+                        return mapped;
+                    }
+                }
+                let findex = self.reserve_findex();
+                self.functions.insert(f, findex);
+                let function = self.copy_function(fun, findex);
+                self.dst.functions.push(function);
+                findex
+            }
+        }
+    }
+
+    /// Replace the body (registers, instructions, debug info and assigns) of the destination function `dst` with the
+    /// body of the source function `src`, copying its dependencies. Both functions must have the same type once copied.
+    pub fn replace_body(&mut self, dst: RefFun, src: RefFun) -> Result<()> {
+        let Some(fun) = src.as_fn(self.src) else {
+            return Err(Error::InvalidEdit(format!("fn@{} is a native", src.0)));
+        };
+        let copy = self.copy_function(fun, dst);
+        self.dst.replace_function_body(dst, &copy)
+    }
+
+    /// Copy a function under a new findex even if it has already been copied or belongs to a reused type
+    pub fn clone_function(&mut self, src: RefFun) -> Result<RefFun> {
+        let Some(fun) = src.as_fn(self.src) else {
+            return Err(Error::InvalidEdit(format!("fn@{} is a native", src.0)));
+        };
+        let findex = self.reserve_findex();
+        let copy = self.copy_function(fun, findex);
+        self.dst.functions.push(copy);
+        Ok(findex)
+    }
+
+    fn reserve_findex(&mut self) -> RefFun {
+        let findex = RefFun(self.next_findex);
+        self.next_findex += 1;
+        findex
+    }
+
+    fn copy_function(&mut self, fun: &Function, findex: RefFun) -> Function {
+        let t = self.ty(fun.t);
+        let regs = fun.regs.iter().map(|&r| self.ty(r)).collect();
+        let ops = fun.ops.iter().map(|o| self.op(o)).collect();
+        let (debug_info, assigns) = if self.dst.debug_files.is_some() {
+            let debug_info = match &fun.debug_info {
+                Some(debug_info) => debug_info
+                    .iter()
+                    .map(|&(file, line)| (self.debug_file(self.src.debug_file(file)), line))
+                    .collect(),
+                None => {
+                    let file = self.debug_file(None);
+                    vec![(file, 0); fun.ops.len()]
+                }
+            };
+            let assigns = if self.dst.version >= 3 {
+                Some(
+                    fun.assigns
+                        .iter()
+                        .flatten()
+                        .map(|&(s, pos)| (self.string(s), pos))
+                        .collect(),
+                )
+            } else {
+                None
+            };
+            (Some(debug_info), assigns)
+        } else {
+            (None, None)
+        };
+        Function {
+            t,
+            findex,
+            regs,
+            ops,
+            debug_info,
+            assigns,
+            name: self.string(fun.name),
+            parent: fun.parent.map(|p| self.ty(p)),
+        }
+    }
+
+    /// Index of a debug file in the destination, added if missing
+    fn debug_file(&mut self, file: Option<Str>) -> usize {
+        let file = file.unwrap_or_else(|| Str::from_static("Unknown.hx"));
+        let files = self.dst.debug_files.get_or_insert_with(Vec::new);
+        match files.iter().position(|f| *f == file) {
+            Some(i) => i,
+            None => {
+                files.push(file);
+                files.len() - 1
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Opcode) -> Opcode {
+        let mut op = op.clone();
+        match &mut op {
+            Opcode::Int { ptr, .. } => *ptr = self.int(*ptr),
+            Opcode::Float { ptr, .. } => *ptr = self.float(*ptr),
+            Opcode::Bytes { ptr, .. } => *ptr = self.bytes(*ptr),
+            Opcode::String { ptr, .. } => *ptr = self.string(*ptr),
+            Opcode::Call0 { fun, .. }
+            | Opcode::Call1 { fun, .. }
+            | Opcode::Call2 { fun, .. }
+            | Opcode::Call3 { fun, .. }
+            | Opcode::Call4 { fun, .. }
+            | Opcode::CallN { fun, .. }
+            | Opcode::StaticClosure { fun, .. }
+            | Opcode::InstanceClosure { fun, .. } => *fun = self.function(*fun),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                *global = self.global(*global)
+            }
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                *field = self.string(*field)
+            }
+            Opcode::Type { ty, .. } => *ty = self.ty(*ty),
+            // Fields and enum constructs are indexes relative to the type of a register
+            _ => {}
+        }
+        op
+    }
+}
+
+/// Name of a named type
+fn type_name(t: &Type) -> Option<RefString> {
+    match t {
+        Type::Obj(obj) | Type::Struct(obj) => Some(obj.name),
+        Type::Enum { name, .. } | Type::Abstract { name } => Some(*name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::Linker;
+    use crate::testing::{fun, reload, sample};
+    use crate::types::{RefFun, RefGlobal, RefType, Type};
+    use crate::{Bytecode, Opcode};
+
+    #[test]
+    fn test_link_existing() {
+        let src = sample();
+        let mut dst = sample();
+        let mut linker = Linker::new(&mut dst, &src).unwrap();
+        assert_eq!(linker.function(RefFun(0)), RefFun(0));
+        assert_eq!(linker.ty(RefType(4)), RefType(4));
+        linker.finish();
+        assert_eq!(dst.functions.len(), 4);
+        assert_eq!(dst.types.len(), 8);
+    }
+
+    #[test]
+    fn test_link_empty() -> crate::Result<()> {
+        let src = sample();
+        let mut dst = Bytecode {
+            bytes: Some((Vec::new(), Vec::new())),
+            constants: Some(Vec::new()),
+            ..Default::default()
+        };
+        let mut linker = Linker::new(&mut dst, &src)?;
+        let main = linker.function(RefFun(0));
+        linker.finish();
+        // main gets the first findex, then its dependencies in the order they are reached
+        assert_eq!(main, RefFun(0));
+        let names: Vec<_> = (0..4).map(|i| RefFun(i).name(&dst)).collect();
+        assert_eq!(names, ["main", "length", "helper", "unused"]);
+        // Void isn't confused with Main, which was being copied when Void was first needed
+        assert_eq!(dst.types[1], Type::Void);
+        assert_eq!(dst.types[2], fun(&[], 1));
+
+        let dst = reload(&dst);
+        assert_eq!(main.name(&dst), "main");
+        assert_eq!(dst.functions[0].full_name(&dst), "Point.length");
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_body() -> crate::Result<()> {
+        let src = sample();
+        let mut dst = sample();
+        let mut linker = Linker::new(&mut dst, &src)?;
+        let copy = linker.clone_function(RefFun(1))?;
+        assert_eq!(copy, RefFun(4));
+        // unused has the same type as main
+        linker.replace_body(RefFun(3), RefFun(0))?;
+        assert!(linker.replace_body(RefFun(3), RefFun(2)).is_err());
+        linker.finish();
+        assert_eq!(
+            format!("{:?}", copy.as_fn(&dst).unwrap().ops),
+            format!("{:?}", src.functions[1].ops)
+        );
+        // Globals are copied
+        assert_eq!(dst.globals, vec![RefType(1), RefType(1)]);
+        let ops = &dst.functions[3].ops;
+        assert_eq!(ops.len(), 8);
+        assert!(matches!(
+            ops[5],
+            Opcode::SetGlobal {
+                global: RefGlobal(1),
+                ..
+            }
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Cache of the qualified names of functions.
+//!
+//! Resolving the name of a function goes through the renames and the string pool every time. Listings and searches
+//! iterating over every function use [Bytecode::display_names] instead, computed once and invalidated when the
+//! renames change or the acceleration structures are rebuilt.
+
+use std::sync::{Arc, RwLock};
+
+use crate::types::{FunPtr, RefFun};
+use crate::{Bytecode, Resolve, Str};
+
+/// Qualified names by findex with the renames generation they were computed with
+#[derive(Debug, Default)]
+pub(crate) struct NameCache(RwLock<Option<(usize, Arc<[Str]>)>>);
+
+impl NameCache {
+    pub(crate) fn invalidate(&mut self) {
+        if let Ok(cache) = self.0.get_mut() {
+            *cache = None;
+        }
+    }
+}
+
+impl Bytecode {
+    /// Qualified names of every function indexed by findex, taking renames into account.
+    /// Methods are named `Class.method` and natives `lib.name`.
+    pub fn display_names(&self) -> Arc<[Str]> {
+        let generation = self.renames.generation();
+        if let Ok(cache) = self.display_names.0.read() {
+            if let Some((g, names)) = &*cache {
+                if *g == generation {
+                    return names.clone();
+                }
+            }
+        }
+        let names: Arc<[Str]> = (0..self.findex_max())
+            .map(|i| RefFun(i).qualified_name(self))
+            .collect();
+        if let Ok(mut cache) = self.display_names.0.write() {
+            *cache = Some((generation, names.clone()));
+        }
+        names
+    }
+
+    /// Cached qualified name of a function, see [Bytecode::display_names]
+    pub fn display_name(&self, f: RefFun) -> Str {
+        self.display_names()
+            .get(f.0)
+            .cloned()
+            .unwrap_or_else(|| f.qualified_name(self))
+    }
+}
+
+impl RefFun {
+    /// Name of the function prefixed with its class or native lib, not cached
+    pub fn qualified_name(&self, code: &Bytecode) -> Str {
+        match code.get(*self) {
+            FunPtr::Fun(fun) => match fun.parent.and_then(|p| code[p].get_type_obj()) {
+                Some(obj) => Str::from(format!(
+                    "{}.{}",
+                    obj.name(code).trim_start_matches('$'),
+                    fun.name(code)
+                )),
+                None => fun.name(code),
+            },
+            FunPtr::Native(n) => Str::from(format!(
+                "{}.{}",
+                n.lib(code).trim_start_matches('?'),
+                n.name(code)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rename::Symbol;
+    use crate::testing::sample;
+    use crate::types::RefFun;
+    use crate::Str;
+
+    #[test]
+    fn test_display_names() {
+        let code = sample();
+        assert_eq!(
+            *code.display_names(),
+            ["Main.main", "Point.length", "Main.helper", "Main.unused"].map(Str::from)
+        );
+        code.renames
+            .set(Symbol::Function(RefFun(0)), "entry")
+            .unwrap();
+        assert_eq!(code.display_name(RefFun(0)), "Main.entry");
+        assert_eq!(code.display_name(RefFun(2)), "Main.helper");
+    }
+}
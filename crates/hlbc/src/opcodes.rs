@@ -1,16 +1,20 @@
+use crate::rename::Symbol;
 use crate::types::{
-    InlineBool, InlineInt, JumpOffset, RefBytes, RefEnumConstruct, RefField, RefFloat, RefFun,
-    RefGlobal, RefInt, RefString, RefType, Reg,
+    Function, InlineBool, InlineInt, JumpOffset, JumpOffsets, RefBytes, RefEnumConstruct, RefField,
+    RefFloat, RefFun, RefGlobal, RefInt, RefString, RefType, Reg, Regs, Type,
 };
+use crate::{Bytecode, Resolve, Str};
 
 /// Opcodes definitions. The fields are the opcode arguments.
 ///
 /// The methods for this struct are generated through a macro because there is no way I would have written code for 98
 /// opcodes. The opcode name is directly derived from the variant name. The opcode description is derived from the doc
-/// comment on each variant.
+/// comment on each variant. Register operands named `dst` are written and the others are read, unless specified
+/// otherwise with `#[op(read, write, ignore)]`.
 ///
 /// The order of opcodes here is important as it defines the number used for serialization.
 #[derive(Debug, Clone, hlbc_derive::OpcodeHelper)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Opcode {
     /// Copy value from *src* into *dst*
     ///
@@ -182,12 +186,14 @@ pub enum Opcode {
     ///
     /// `dst++`
     Incr {
+        #[op(read, write)]
         dst: Reg,
     },
     /// Decrement a number
     ///
     /// `dst--`
     Decr {
+        #[op(read, write)]
         dst: Reg,
     },
     /// Call a function with no argument
@@ -241,7 +247,7 @@ pub enum Opcode {
     CallN {
         dst: Reg,
         fun: RefFun,
-        args: Vec<Reg>,
+        args: Regs,
     },
     /// Call a function with N arguments, using the first argument as the receiver
     ///
@@ -250,7 +256,7 @@ pub enum Opcode {
         dst: Reg,
         field: RefField,
         // obj is the first arg
-        args: Vec<Reg>,
+        args: Regs,
     },
     /// Call a function with N arguments.
     ///
@@ -258,7 +264,7 @@ pub enum Opcode {
     CallThis {
         dst: Reg,
         field: RefField,
-        args: Vec<Reg>,
+        args: Regs,
     },
     /// Call a closure with N arguments. Here *fun* is a register.
     ///
@@ -266,7 +272,7 @@ pub enum Opcode {
     CallClosure {
         dst: Reg,
         fun: Reg,
-        args: Vec<Reg>,
+        args: Regs,
     },
     /// Create a closure from a function reference.
     ///
@@ -539,7 +545,9 @@ pub enum Opcode {
     /// `jump by offsets[reg] else jump by end`
     Switch {
         reg: Reg,
-        offsets: Vec<JumpOffset>,
+        offsets: JumpOffsets,
+        /// Values out of range continue with the next instruction, this is the end of the switch
+        #[op(ignore)]
         end: JumpOffset,
     },
     /// Throw an exception if *reg* is null.
@@ -550,11 +558,14 @@ pub enum Opcode {
     },
     /// Setup a try-catch block. If an exception occurs, store it in the given register and jump by an offset.
     Trap {
+        /// Only assigned when catching
+        #[op(write)]
         exc: Reg,
         offset: JumpOffset,
     },
     /// End the **latest** trap section.
     EndTrap {
+        #[op(ignore)]
         exc: Reg,
     },
     /// Read an **i8** from a byte array.
@@ -673,6 +684,8 @@ pub enum Opcode {
     ///
     /// `*dst = src`
     Setref {
+        /// Written through the reference
+        #[op(read)]
         dst: Reg,
         value: Reg,
     },
@@ -682,7 +695,7 @@ pub enum Opcode {
     MakeEnum {
         dst: Reg,
         construct: RefEnumConstruct,
-        args: Vec<Reg>,
+        args: Regs,
     },
     /// Create an enum variant using the default values.
     ///
@@ -750,9 +763,204 @@ pub enum Opcode {
     },
 }
 
+/// A raw operand of an instruction, see [Opcode::operands]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operand<'a> {
+    Reg(Reg),
+    Regs(&'a [Reg]),
+    InlineBool(InlineBool),
+    InlineInt(InlineInt),
+    JumpOffset(JumpOffset),
+    JumpOffsets(&'a [JumpOffset]),
+    Int(RefInt),
+    Float(RefFloat),
+    Bytes(RefBytes),
+    String(RefString),
+    Type(RefType),
+    Fun(RefFun),
+    Field(RefField),
+    Global(RefGlobal),
+    EnumConstruct(RefEnumConstruct),
+}
+
+/// Kind of an operand, see [OperandDesc]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum OperandKind {
+    Reg,
+    Regs,
+    InlineBool,
+    InlineInt,
+    JumpOffset,
+    JumpOffsets,
+    Int,
+    Float,
+    Bytes,
+    String,
+    Type,
+    Fun,
+    Field,
+    Global,
+    EnumConstruct,
+}
+
+/// Static description of an operand, see [Opcode::operand_descs]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OperandDesc {
+    /// Name of the field
+    pub name: &'static str,
+    pub kind: OperandKind,
+    /// The register is read
+    pub read: bool,
+    /// The register is written
+    pub write: bool,
+    /// The offset is a jump target
+    pub jump: bool,
+}
+
+/// An operand of an instruction resolved against the bytecode, see [Function::ops_resolved]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolvedOperand<'a> {
+    /// A register with its type
+    Reg(Reg, RefType),
+    Regs(Vec<(Reg, RefType)>),
+    Bool(bool),
+    Int(i32),
+    Float(f64),
+    Bytes(&'a [u8]),
+    String(Str),
+    Type(RefType),
+    Fun {
+        fun: RefFun,
+        name: Str,
+    },
+    /// An object field, the name is unknown if the type of the object isn't known
+    Field {
+        field: RefField,
+        name: Option<Str>,
+    },
+    Global {
+        global: RefGlobal,
+        ty: RefType,
+    },
+    EnumConstruct {
+        construct: RefEnumConstruct,
+        name: Option<Str>,
+    },
+    /// Absolute position of a jump target
+    Target(usize),
+    Targets(Vec<usize>),
+}
+
+impl Opcode {
+    /// Register holding the object (or enum) a field (or construct) operand refers to
+    pub(crate) fn parent_reg(&self) -> Option<Reg> {
+        match self {
+            Opcode::Field { obj, .. } | Opcode::SetField { obj, .. } => Some(*obj),
+            Opcode::CallMethod { args, .. } => args.first().copied(),
+            Opcode::GetThis { .. } | Opcode::SetThis { .. } | Opcode::CallThis { .. } => {
+                Some(Reg(0))
+            }
+            Opcode::Prefetch { value, .. }
+            | Opcode::EnumField { value, .. }
+            | Opcode::SetEnumField { value, .. } => Some(*value),
+            Opcode::MakeEnum { dst, .. } | Opcode::EnumAlloc { dst, .. } => Some(*dst),
+            _ => None,
+        }
+    }
+
+    /// Resolve the operands of this instruction at position `pos` in function `f`
+    pub fn resolve_operands<'a>(
+        &'a self,
+        code: &'a Bytecode,
+        f: &Function,
+        pos: usize,
+    ) -> Vec<(&'static str, ResolvedOperand<'a>)> {
+        let reg = |r: Reg| (r, f.regs.get(r.0 as usize).copied().unwrap_or_default());
+        let target = |offset: JumpOffset| (pos as i64 + offset as i64 + 1) as usize;
+        let parent = self
+            .parent_reg()
+            .and_then(|r| f.regs.get(r.0 as usize))
+            .map(|&t| &code[t]);
+        self.operands()
+            .into_iter()
+            .map(|(name, operand)| {
+                let resolved = match operand {
+                    Operand::Reg(r) => {
+                        let (r, t) = reg(r);
+                        ResolvedOperand::Reg(r, t)
+                    }
+                    Operand::Regs(regs) => {
+                        ResolvedOperand::Regs(regs.iter().map(|&r| reg(r)).collect())
+                    }
+                    Operand::InlineBool(b) => ResolvedOperand::Bool(b),
+                    Operand::InlineInt(i) => ResolvedOperand::Int(i),
+                    Operand::JumpOffset(offset) => ResolvedOperand::Target(target(offset)),
+                    Operand::JumpOffsets(offsets) => {
+                        ResolvedOperand::Targets(offsets.iter().map(|&o| target(o)).collect())
+                    }
+                    Operand::Int(i) => ResolvedOperand::Int(code[i]),
+                    Operand::Float(fl) => ResolvedOperand::Float(code[fl]),
+                    Operand::Bytes(b) => ResolvedOperand::Bytes(code.get(b)),
+                    Operand::String(s) => ResolvedOperand::String(code.get(s)),
+                    Operand::Type(t) => ResolvedOperand::Type(t),
+                    Operand::Fun(fun) => ResolvedOperand::Fun {
+                        fun,
+                        name: fun.name(code),
+                    },
+                    Operand::Field(field) => ResolvedOperand::Field {
+                        field,
+                        name: match (self, parent) {
+                            // Index of an enum construct parameter
+                            (Opcode::EnumField { .. } | Opcode::SetEnumField { .. }, _) => None,
+                            (_, Some(Type::Virtual { fields })) => {
+                                fields.get(field.0).map(|f| f.virtual_name(code))
+                            }
+                            (_, Some(t)) => t.get_type_obj().and_then(|obj| {
+                                code.renames
+                                    .get(Symbol::Field(obj.name, field))
+                                    .or_else(|| obj.fields.get(field.0).map(|f| code.get(f.name)))
+                            }),
+                            _ => None,
+                        },
+                    },
+                    Operand::Global(global) => ResolvedOperand::Global {
+                        global,
+                        ty: code.globals.get(global.0).copied().unwrap_or_default(),
+                    },
+                    Operand::EnumConstruct(construct) => ResolvedOperand::EnumConstruct {
+                        construct,
+                        name: match parent {
+                            Some(Type::Enum { constructs, .. }) => constructs
+                                .get(construct.0)
+                                .filter(|c| c.name.0 != 0)
+                                .map(|c| code.get(c.name)),
+                            _ => None,
+                        },
+                    },
+                };
+                (name, resolved)
+            })
+            .collect()
+    }
+}
+
+impl Function {
+    /// Iterate on the instructions of this function with their operands resolved against the bytecode.
+    /// This is meant for generic tooling (exporters, scripting) that don't want to handle every opcode.
+    pub fn ops_resolved<'a>(
+        &'a self,
+        code: &'a Bytecode,
+    ) -> impl Iterator<Item = (&'a Opcode, Vec<(&'static str, ResolvedOperand<'a>)>)> + 'a {
+        self.ops
+            .iter()
+            .enumerate()
+            .map(move |(pos, op)| (op, op.resolve_operands(code, self, pos)))
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::opcodes::Opcode;
+    use crate::opcodes::{Opcode, Operand};
     use crate::types::Reg;
 
     #[test]
@@ -770,4 +978,55 @@ mod test {
             Opcode::Null { dst: Reg(0) }.description()
         );
     }
+
+    #[test]
+    fn test_defs_uses() {
+        let op = Opcode::Add {
+            dst: Reg(0),
+            a: Reg(1),
+            b: Reg(2),
+        };
+        assert_eq!(op.defs().as_slice(), &[Reg(0)]);
+        assert_eq!(op.uses().as_slice(), &[Reg(1), Reg(2)]);
+        let op = Opcode::Incr { dst: Reg(3) };
+        assert_eq!(op.defs().as_slice(), &[Reg(3)]);
+        assert_eq!(op.uses().as_slice(), &[Reg(3)]);
+        let op = Opcode::Setref {
+            dst: Reg(0),
+            value: Reg(1),
+        };
+        assert!(op.defs().is_empty());
+        assert_eq!(op.uses().as_slice(), &[Reg(0), Reg(1)]);
+    }
+
+    #[test]
+    fn test_jump_targets() {
+        let op = Opcode::Switch {
+            reg: Reg(0),
+            offsets: [1, 2, -3].into_iter().collect(),
+            end: 5,
+        };
+        assert_eq!(op.jump_targets(10), vec![12, 13, 8]);
+        assert_eq!(Opcode::JAlways { offset: -1 }.jump_targets(0), vec![0]);
+        let descs = op.operand_descs();
+        assert_eq!(descs.len(), op.operands().len());
+        assert!(descs[1].jump && !descs[2].jump);
+    }
+
+    #[test]
+    fn test_operands() {
+        let op = Opcode::Add {
+            dst: Reg(0),
+            a: Reg(1),
+            b: Reg(2),
+        };
+        assert_eq!(
+            vec![
+                ("dst", Operand::Reg(Reg(0))),
+                ("a", Operand::Reg(Reg(1))),
+                ("b", Operand::Reg(Reg(2)))
+            ],
+            op.operands()
+        );
+    }
 }
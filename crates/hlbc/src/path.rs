@@ -0,0 +1,148 @@
+//! Addressing elements by their Haxe path.
+//!
+//! Class names in the bytecode already contain their package (`mypack.MyClass`), the static part of a class is named
+//! `$mypack.MyClass`. Functions outside of a class (module level functions, closures) only have a name, their module
+//! is inferred from the debug file they come from. Names take renames into account.
+
+use crate::rename::Symbol;
+use crate::types::{Function, RefEnumConstruct, RefField, RefFun, RefType, Type};
+use crate::{Bytecode, Str};
+
+/// An element resolved from its path, see [Bytecode::resolve_path]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PathTarget {
+    /// A class, struct, enum or abstract
+    Type(RefType),
+    /// A function or a method
+    Function(RefFun),
+    /// A field of a class or struct, indexed in [crate::types::TypeObj::fields]
+    Field(RefType, RefField),
+    /// A variant of an enum
+    Construct(RefType, RefEnumConstruct),
+}
+
+impl Bytecode {
+    /// Find the element addressed by a path like `mypack.MyClass`, `mypack.MyClass.myMethod` or
+    /// `mypack.MyClass.myField`. Static members are addressed through the class name, without the `$`.
+    ///
+    /// This scans the pools, prefer [Bytecode::function_by_qualified_name] to look up many functions.
+    pub fn resolve_path(&self, path: &str) -> Option<PathTarget> {
+        if let Some(ty) = self.type_by_path(path) {
+            return Some(PathTarget::Type(ty));
+        }
+        if let Some(f) = self
+            .functions
+            .iter()
+            .filter(|f| path.ends_with(&*f.name(self)))
+            .find(|f| &*f.full_name(self) == path)
+        {
+            return Some(PathTarget::Function(f.findex));
+        }
+        let (owner, member) = path.rsplit_once('.')?;
+        let ty = self.type_by_path(owner)?;
+        match &self[ty] {
+            Type::Enum { constructs, .. } => constructs
+                .iter()
+                .position(|c| &*c.name(self) == member)
+                .map(|i| PathTarget::Construct(ty, RefEnumConstruct(i))),
+            _ => {
+                // Fields of the class then the static fields
+                let statics = self.type_by_path(&format!("${owner}"));
+                [Some(ty), statics].into_iter().flatten().find_map(|ty| {
+                    let obj = self[ty].get_type_obj()?;
+                    obj.fields
+                        .iter()
+                        .rposition(|f| &*f.name(self) == member)
+                        .map(|i| PathTarget::Field(ty, RefField(i)))
+                })
+            }
+        }
+    }
+
+    /// A named type by its name, the class before its static part
+    fn type_by_path(&self, path: &str) -> Option<RefType> {
+        let find = |path: &str| {
+            (0..self.types.len())
+                .map(RefType)
+                .find(|&ty| matches!(type_name(self, ty), Some(name) if name == path))
+        };
+        find(path).or_else(|| find(&format!("${path}")))
+    }
+
+    /// Module of a debug file, the longest type path matching the end of the file path or the file name
+    pub fn file_module(&self, file: usize) -> Option<Str> {
+        let file = self.debug_file(file)?.replace('\\', "/");
+        let file = file.strip_suffix(".hx").unwrap_or(&file);
+        let module = (0..self.types.len())
+            .filter_map(|ty| type_name(self, RefType(ty)))
+            .map(|name| name.trim_start_matches('$').to_string())
+            .filter(|name| {
+                let rel = name.replace('.', "/");
+                file == rel || file.ends_with(&format!("/{rel}"))
+            })
+            .max_by_key(String::len);
+        Some(match module {
+            Some(module) => Str::from(module),
+            None => Str::from(file.rsplit('/').next().unwrap_or(file)),
+        })
+    }
+}
+
+impl Function {
+    /// Name of the function prefixed with its class and package like it appears in Haxe source
+    /// (`mypack.MyClass.myMethod`). Functions outside of a class are prefixed with the module inferred from their
+    /// debug file, see [Bytecode::file_module].
+    pub fn full_name(&self, code: &Bytecode) -> Str {
+        let name = self.name(code);
+        let prefix = match self.parent.and_then(|p| code[p].get_type_obj()) {
+            Some(obj) => Some(Str::from(obj.name(code).trim_start_matches('$'))),
+            None => self.file_index().and_then(|file| code.file_module(file)),
+        };
+        match prefix {
+            Some(prefix) => Str::from(format!("{prefix}.{name}")),
+            None => name,
+        }
+    }
+}
+
+/// Name of a class, struct, enum or abstract, taking renames into account
+fn type_name(code: &Bytecode, ty: RefType) -> Option<Str> {
+    let Some(Symbol::Type(name)) = Symbol::of_type(code, ty) else {
+        return None;
+    };
+    Some(
+        code.renames
+            .get(Symbol::Type(name))
+            .unwrap_or_else(|| code[name].clone()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::path::PathTarget;
+    use crate::testing::sample;
+    use crate::types::{RefFun, RefType};
+
+    #[test]
+    fn test_resolve_path() {
+        let code = sample();
+        assert_eq!(code.main().full_name(&code), "Main.main");
+        assert_eq!(
+            code.resolve_path("Main.main"),
+            Some(PathTarget::Function(RefFun(0)))
+        );
+        assert_eq!(
+            code.resolve_path("Point.length"),
+            Some(PathTarget::Function(RefFun(1)))
+        );
+        assert_eq!(
+            code.resolve_path("Point"),
+            Some(PathTarget::Type(RefType(4)))
+        );
+        assert_eq!(
+            code.resolve_path("Main"),
+            Some(PathTarget::Type(RefType(6)))
+        );
+        assert_eq!(code.resolve_path("not.a.Path"), None);
+    }
+}
@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::analysis::unused::{PoolUsage, UnusedPools};
+use crate::rename::Symbol;
+use crate::types::{
+    ConstantDef, ConstantPool, ConstantValue, ObjField, RefFloat, RefFun, RefGlobal, RefInt,
+    RefString, RefType, Type, TypeFun,
+};
+use crate::{Bytecode, Error, Opcode, Result, Str};
+
+/// Adding elements to the constant pools.
+///
+/// Every `intern_*` function returns the reference to an existing element if there is one, so the pools don't grow
+/// with duplicates. Pools lengths in the serialized bytecode are computed from the pools, there is nothing else to
+/// update.
+impl Bytecode {
+    /// Get the reference to a string, adding it to the pool if missing.
+    pub fn intern_string(&mut self, s: impl Into<Str>) -> RefString {
+        let s = s.into();
+        // Index 0 means 'no string' and can't be used
+        if let Some(i) = self.strings.iter().skip(1).position(|e| *e == s) {
+            return RefString(i + 1);
+        }
+        if self.strings.is_empty() {
+            self.strings.push(Str::default());
+        }
+        self.strings.push(s);
+        RefString(self.strings.len() - 1)
+    }
+
+    /// Get the reference to an integer constant, adding it to the pool if missing.
+    pub fn intern_int(&mut self, value: i32) -> RefInt {
+        if let Some(i) = self.ints.iter().position(|&e| e == value) {
+            return RefInt(i);
+        }
+        self.ints.push(value);
+        RefInt(self.ints.len() - 1)
+    }
+
+    /// Get the reference to a float constant, adding it to the pool if missing.
+    /// Floats are compared bitwise, so `NaN` and `-0.0` are interned correctly.
+    pub fn intern_float(&mut self, value: f64) -> RefFloat {
+        if let Some(i) = self
+            .floats
+            .iter()
+            .position(|e| e.to_bits() == value.to_bits())
+        {
+            return RefFloat(i);
+        }
+        self.floats.push(value);
+        RefFloat(self.floats.len() - 1)
+    }
+
+    /// Get the reference to a type, adding it to the pool if there is no equal type.
+    pub fn intern_type(&mut self, ty: Type) -> RefType {
+        if let Some(i) = self.types.iter().position(|e| *e == ty) {
+            return RefType(i);
+        }
+        self.types.push(ty);
+        RefType(self.types.len() - 1)
+    }
+}
+
+/// Editing elements of the pools.
+///
+/// Unlike modifying the public fields, those functions keep the constants and the acceleration structures consistent
+/// with the pools.
+impl Bytecode {
+    /// Replace a string of the pool. Every element using this string is affected, use [Bytecode::intern_string] to
+    /// get a new string instead. Panics if the index is out of bounds or is the reserved index 0.
+    pub fn set_string(&mut self, index: RefString, s: impl Into<Str>) {
+        assert_ne!(index.0, 0, "string 0 means 'no string' and can't be set");
+        self.strings[index.0] = s.into();
+        // Function names may have changed
+        self.rebuild_indexes();
+    }
+
+    /// Replace an integer constant. Every instruction and constant using it is affected.
+    /// Panics if the index is out of bounds.
+    pub fn set_int(&mut self, index: RefInt, value: i32) {
+        self.ints[index.0] = value;
+    }
+
+    /// Replace a float constant. Every instruction and constant using it is affected.
+    /// Panics if the index is out of bounds.
+    pub fn set_float(&mut self, index: RefFloat, value: f64) {
+        self.floats[index.0] = value;
+    }
+
+    /// Add a global of type `ty`, initialized with the values of its fields if `constant` is given.
+    /// See [Bytecode::set_constant].
+    pub fn add_global(
+        &mut self,
+        ty: RefType,
+        constant: Option<&[ConstantValue]>,
+    ) -> Result<RefGlobal> {
+        if ty.0 >= self.types.len() {
+            return Err(Error::InvalidEdit(format!("type {} does not exist", ty.0)));
+        }
+        self.globals.push(ty);
+        let global = RefGlobal(self.globals.len() - 1);
+        if let Some(values) = constant {
+            if let Err(e) = self.set_constant(global, values) {
+                self.globals.pop();
+                return Err(e);
+            }
+        }
+        Ok(global)
+    }
+
+    /// Initialize a global with constant values for each field of its type, replacing its previous constant.
+    /// The values are interned in the pools. Fails if the global is not an object, the values don't match the fields
+    /// or the bytecode version doesn't support constants.
+    pub fn set_constant(&mut self, global: RefGlobal, values: &[ConstantValue]) -> Result<()> {
+        if self.version < 4 {
+            return Err(Error::InvalidEdit(format!(
+                "constants require bytecode v4, this is v{}",
+                self.version
+            )));
+        }
+        let obj = self
+            .globals
+            .get(global.0)
+            .and_then(|g| self.types.get(g.0))
+            .and_then(Type::get_type_obj)
+            .ok_or_else(|| Error::InvalidEdit(format!("global {} is not an object", global.0)))?;
+        if obj.fields.len() != values.len() {
+            return Err(Error::InvalidEdit(format!(
+                "global {} has {} fields, got {} values",
+                global.0,
+                obj.fields.len(),
+                values.len()
+            )));
+        }
+        let pools: Vec<ConstantPool> = obj
+            .fields
+            .iter()
+            .map(|f| ConstantPool::of(self.types.get(f.t.0)))
+            .collect();
+        let mut fields = Vec::with_capacity(values.len());
+        for (i, (pool, value)) in pools.into_iter().zip(values).enumerate() {
+            fields.push(match (pool, value) {
+                (ConstantPool::Int, &ConstantValue::Int(v)) => self.intern_int(v).0,
+                (ConstantPool::Float, &ConstantValue::Float(v)) => self.intern_float(v).0,
+                (ConstantPool::Bool, &ConstantValue::Bool(v)) => v as usize,
+                (ConstantPool::String, ConstantValue::String(v)) => self.intern_string(v.clone()).0,
+                (ConstantPool::Type, &ConstantValue::Type(t)) if t.0 < self.types.len() => t.0,
+                (ConstantPool::Global, &ConstantValue::Global(g)) if g.0 < self.globals.len() => {
+                    g.0
+                }
+                _ => {
+                    return Err(Error::InvalidEdit(format!(
+                        "value {value} does not fit field {i} of global {}",
+                        global.0
+                    )))
+                }
+            });
+        }
+        let def = ConstantDef { global, fields };
+        let constants = self.constants.get_or_insert_with(Vec::new);
+        match self.globals_initializers.get(&global) {
+            Some(&i) => constants[i] = def,
+            None => constants.push(def),
+        }
+        self.build_globals_initializers();
+        Ok(())
+    }
+}
+
+/// Removing unused or duplicate elements from the pools.
+impl Bytecode {
+    /// Remove the pool entries reported by [UnusedPools] and remap every reference to the remaining ones, renames
+    /// included. Natives are removed too, so the findexes after a removed native are shifted. Returns the removed
+    /// entries, with their indexes before the removal.
+    ///
+    /// Lazily loaded function bodies are decoded first, the bytecode is left untouched if one can't be decoded.
+    pub fn gc_pools(&mut self) -> Result<UnusedPools> {
+        self.decode_all()?;
+        let usage = PoolUsage::new(self)?;
+        let unused = usage.unused(self);
+        let remap = Remap::new(self, &usage);
+
+        self.remap_constants(&remap);
+        retain(&mut self.strings, &usage.strings);
+        retain(&mut self.ints, &usage.ints);
+        retain(&mut self.floats, &usage.floats);
+        retain(&mut self.globals, &usage.globals);
+        retain(&mut self.types, &usage.types);
+        retain(&mut self.natives, &usage.natives);
+        self.remap_refs(&remap);
+        Ok(unused)
+    }
+
+    /// Merge the identical strings, ints and floats (compared bitwise) and remap every reference to the remaining
+    /// ones, renames included. Duplicates accumulate when editing the bytecode without the `intern_*` functions.
+    /// Returns the number of removed entries.
+    ///
+    /// Lazily loaded function bodies are decoded first, the bytecode is left untouched if one can't be decoded.
+    pub fn dedup_pools(&mut self) -> Result<usize> {
+        self.decode_all()?;
+        let (strings_kept, strings) = dedup(
+            self.strings
+                .iter()
+                .enumerate()
+                // Index 0 means 'no string', it is never merged
+                .map(|(i, s)| (i == 0, s.clone())),
+        );
+        let (ints_kept, ints) = dedup(self.ints.iter().copied());
+        let (floats_kept, floats) = dedup(self.floats.iter().map(|f| f.to_bits()));
+        let remap = Remap {
+            strings,
+            ints,
+            floats,
+            globals: Vec::new(),
+            types: Vec::new(),
+            funs: Vec::new(),
+            bytes_in_strings: self.bytes.is_none(),
+        };
+        let len = self.strings.len() + self.ints.len() + self.floats.len();
+
+        self.remap_constants(&remap);
+        retain(&mut self.strings, &strings_kept);
+        retain(&mut self.ints, &ints_kept);
+        retain(&mut self.floats, &floats_kept);
+        self.remap_refs(&remap);
+        Ok(len - (self.strings.len() + self.ints.len() + self.floats.len()))
+    }
+
+    /// Constant values depend on the field types, they are remapped before the pools are modified
+    fn remap_constants(&mut self, remap: &Remap) {
+        if let Some(mut constants) = self.constants.take() {
+            for c in &mut constants {
+                if let Some(obj) = self
+                    .globals
+                    .get(c.global.0)
+                    .and_then(|g| self.types.get(g.0))
+                    .and_then(Type::get_type_obj)
+                {
+                    for (value, field) in c.fields.iter_mut().zip(&obj.fields) {
+                        *value = match ConstantPool::of(self.types.get(field.t.0)) {
+                            ConstantPool::Int => get(&remap.ints, *value),
+                            ConstantPool::Float => get(&remap.floats, *value),
+                            ConstantPool::Bool => *value,
+                            ConstantPool::String => get(&remap.strings, *value),
+                            ConstantPool::Type => get(&remap.types, *value),
+                            ConstantPool::Global => get(&remap.globals, *value),
+                        };
+                    }
+                }
+                c.global = remap.global(c.global);
+            }
+            self.constants = Some(constants);
+        }
+    }
+
+    /// Remap every reference after the pools have been modified
+    fn remap_refs(&mut self, remap: &Remap) {
+        for t in &mut self.types {
+            remap.type_def(t);
+        }
+        for g in &mut self.globals {
+            *g = remap.ty(*g);
+        }
+        for n in &mut self.natives {
+            n.name = remap.string(n.name);
+            n.lib = remap.string(n.lib);
+            n.t = remap.ty(n.t);
+            n.findex = remap.fun(n.findex);
+        }
+        for f in &mut self.functions {
+            f.findex = remap.fun(f.findex);
+            f.name = remap.string(f.name);
+            f.t = remap.ty(f.t);
+            for r in &mut f.regs {
+                *r = remap.ty(*r);
+            }
+            f.parent = f.parent.map(|p| remap.ty(p));
+            for (s, _) in f.assigns.iter_mut().flatten() {
+                *s = remap.string(*s);
+            }
+            for op in &mut f.ops {
+                remap.op(op);
+            }
+        }
+        self.entrypoint = remap.fun(self.entrypoint);
+
+        let renames = self.renames.entries();
+        self.renames.clear();
+        for (symbol, name) in renames {
+            if let Some(symbol) = remap.symbol(symbol) {
+                // The name was accepted before
+                let _ = self.renames.set(symbol, name);
+            }
+        }
+
+        self.rebuild_indexes();
+    }
+}
+
+/// Keep the used elements of a pool
+fn retain<T>(pool: &mut Vec<T>, used: &[bool]) {
+    let mut used = used.iter();
+    pool.retain(|_| used.next().copied().unwrap_or(true));
+}
+
+/// New index of an element, invalid indexes are kept as is
+fn get(map: &[Option<usize>], i: usize) -> usize {
+    map.get(i).copied().flatten().unwrap_or(i)
+}
+
+/// Keep the first occurrence of each key, returns the elements kept and the new index of each element
+fn dedup<K: Hash + Eq>(keys: impl Iterator<Item = K>) -> (Vec<bool>, Vec<Option<usize>>) {
+    let mut first = HashMap::new();
+    let mut kept = Vec::new();
+    let mut map = Vec::new();
+    for key in keys {
+        let next = first.len();
+        let i = *first.entry(key).or_insert(next);
+        kept.push(i == next);
+        map.push(Some(i));
+    }
+    (kept, map)
+}
+
+/// New index of each element after the removal of the unused ones, [None] if removed
+fn indexes(used: &[bool]) -> Vec<Option<usize>> {
+    let mut next = 0;
+    used.iter()
+        .map(|&u| {
+            u.then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect()
+}
+
+/// New index of the elements of each pool, indexes out of the maps are kept as is
+struct Remap {
+    strings: Vec<Option<usize>>,
+    ints: Vec<Option<usize>>,
+    floats: Vec<Option<usize>>,
+    globals: Vec<Option<usize>>,
+    types: Vec<Option<usize>>,
+    /// Indexed by findex
+    funs: Vec<Option<usize>>,
+    /// Before bytecode v5, bytes are stored in the strings pool
+    bytes_in_strings: bool,
+}
+
+impl Remap {
+    fn new(code: &Bytecode, usage: &PoolUsage) -> Self {
+        let mut funs = vec![true; code.findex_max()];
+        for (n, &used) in code.natives.iter().zip(&usage.natives) {
+            if let Some(f) = funs.get_mut(n.findex.0) {
+                *f = used;
+            }
+        }
+        Self {
+            strings: indexes(&usage.strings),
+            ints: indexes(&usage.ints),
+            floats: indexes(&usage.floats),
+            globals: indexes(&usage.globals),
+            types: indexes(&usage.types),
+            funs: indexes(&funs),
+            bytes_in_strings: code.bytes.is_none(),
+        }
+    }
+
+    fn string(&self, s: RefString) -> RefString {
+        RefString(get(&self.strings, s.0))
+    }
+
+    fn ty(&self, t: RefType) -> RefType {
+        RefType(get(&self.types, t.0))
+    }
+
+    fn global(&self, g: RefGlobal) -> RefGlobal {
+        RefGlobal(get(&self.globals, g.0))
+    }
+
+    /// Globals references of types are 1-based, 0 means no global
+    fn type_global(&self, g: RefGlobal) -> RefGlobal {
+        if g.0 == 0 {
+            g
+        } else {
+            RefGlobal(self.global(RefGlobal(g.0 - 1)).0 + 1)
+        }
+    }
+
+    fn fun(&self, f: RefFun) -> RefFun {
+        RefFun(get(&self.funs, f.0))
+    }
+
+    fn fields(&self, fields: &mut [ObjField]) {
+        for f in fields {
+            f.name = self.string(f.name);
+            f.t = self.ty(f.t);
+        }
+    }
+
+    fn type_fun(&self, fun: &mut TypeFun) {
+        for arg in &mut fun.args {
+            *arg = self.ty(*arg);
+        }
+        fun.ret = self.ty(fun.ret);
+    }
+
+    fn type_def(&self, ty: &mut Type) {
+        match ty {
+            Type::Fun(fun) | Type::Method(fun) => self.type_fun(fun),
+            Type::Obj(obj) | Type::Struct(obj) => {
+                obj.name = self.string(obj.name);
+                obj.super_ = obj.super_.map(|s| self.ty(s));
+                obj.global = self.type_global(obj.global);
+                self.fields(&mut obj.fields);
+                self.fields(&mut obj.own_fields);
+                for p in &mut obj.protos {
+                    p.name = self.string(p.name);
+                    p.findex = self.fun(p.findex);
+                }
+                for f in obj.bindings.values_mut() {
+                    *f = self.fun(*f);
+                }
+            }
+            Type::Ref(inner) | Type::Null(inner) | Type::Packed(inner) => *inner = self.ty(*inner),
+            Type::Virtual { fields } => self.fields(fields),
+            Type::Abstract { name } => *name = self.string(*name),
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => {
+                *name = self.string(*name);
+                *global = self.type_global(*global);
+                for c in constructs {
+                    c.name = self.string(c.name);
+                    for p in &mut c.params {
+                        *p = self.ty(*p);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn op(&self, op: &mut Opcode) {
+        match op {
+            Opcode::Int { ptr, .. } => *ptr = RefInt(get(&self.ints, ptr.0)),
+            Opcode::Float { ptr, .. } => *ptr = RefFloat(get(&self.floats, ptr.0)),
+            Opcode::Bytes { ptr, .. } if self.bytes_in_strings => ptr.0 = get(&self.strings, ptr.0),
+            Opcode::String { ptr, .. } => *ptr = self.string(*ptr),
+            Opcode::Call0 { fun, .. }
+            | Opcode::Call1 { fun, .. }
+            | Opcode::Call2 { fun, .. }
+            | Opcode::Call3 { fun, .. }
+            | Opcode::Call4 { fun, .. }
+            | Opcode::CallN { fun, .. }
+            | Opcode::StaticClosure { fun, .. }
+            | Opcode::InstanceClosure { fun, .. } => *fun = self.fun(*fun),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                *global = self.global(*global)
+            }
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                *field = self.string(*field)
+            }
+            Opcode::Type { ty, .. } => *ty = self.ty(*ty),
+            _ => {}
+        }
+    }
+
+    /// Symbol after the removal, [None] if the element was removed
+    fn symbol(&self, symbol: Symbol) -> Option<Symbol> {
+        let map = |map: &[Option<usize>], i: usize| match map.get(i) {
+            Some(None) => None,
+            _ => Some(get(map, i)),
+        };
+        Some(match symbol {
+            Symbol::Function(f) => Symbol::Function(RefFun(map(&self.funs, f.0)?)),
+            Symbol::Type(s) => Symbol::Type(RefString(map(&self.strings, s.0)?)),
+            Symbol::Field(s, f) => Symbol::Field(RefString(map(&self.strings, s.0)?), f),
+            Symbol::Global(g) => Symbol::Global(RefGlobal(map(&self.globals, g.0)?)),
+            Symbol::Virtual(t) => Symbol::Virtual(RefType(map(&self.types, t.0)?)),
+            Symbol::VirtualField(s, t) => Symbol::VirtualField(
+                RefString(map(&self.strings, s.0)?),
+                RefType(map(&self.types, t.0)?),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::unused::UnusedPools;
+    use crate::testing::{reload, sample};
+    use crate::types::{ConstantValue, RefFun, RefGlobal, RefInt, RefString, RefType, Type};
+    use crate::Str;
+
+    #[test]
+    fn test_intern() {
+        let mut code = sample();
+        let s = code.intern_string("hlbc_interned");
+        assert_eq!(s, RefString(9));
+        assert_eq!(code.intern_string("hlbc_interned"), s);
+        assert_eq!(code.intern_string("hello"), RefString(7));
+        assert_eq!(code.strings.len(), 10);
+
+        let i = code.intern_int(123456789);
+        assert_eq!(i, RefInt(2));
+        assert_eq!(code.intern_int(123456789), i);
+        assert_eq!(code.intern_int(3), RefInt(0));
+        assert_eq!(code[i], 123456789);
+
+        let f = code.intern_float(f64::NAN);
+        assert_eq!(code.intern_float(f64::NAN), f);
+        assert_ne!(code.intern_float(-0.0), code.intern_float(0.0));
+        assert_eq!(code.floats.len(), 3);
+
+        assert_eq!(code.intern_type(Type::I32), RefType(1));
+        assert_eq!(code.intern_type(Type::F64), RefType(8));
+        assert_eq!(code.types.len(), 9);
+    }
+
+    #[test]
+    fn test_edit_pools() -> crate::Result<()> {
+        let mut code = sample();
+        code.set_string(RefString(8), "hlbc_main");
+        assert_eq!(
+            code.function_by_name("hlbc_main").unwrap().findex,
+            RefFun(0)
+        );
+
+        // A Point with x = 7
+        let values = [ConstantValue::Int(7)];
+        let global = code.add_global(RefType(4), Some(&values))?;
+        assert_eq!(global, RefGlobal(1));
+        assert_eq!(code.ints, vec![3, 1, 7]);
+        let i = code.globals_initializers[&global];
+        assert_eq!(code.constants.as_ref().unwrap()[i].values(&code), values);
+        let more = [ConstantValue::Int(7), ConstantValue::String(Str::from("x"))];
+        assert!(code.set_constant(global, &more).is_err());
+        // Not an object
+        assert!(code.set_constant(RefGlobal(0), &values).is_err());
+
+        let code = reload(&code);
+        let i = code.globals_initializers[&global];
+        assert_eq!(code.constants.as_ref().unwrap()[i].values(&code), values);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_pools() {
+        let mut code = sample();
+        code.intern_string("hlbc_never_used");
+        code.intern_int(42);
+        let removed = code.gc_pools().unwrap();
+        assert_eq!(removed.strings, vec![RefString(9)]);
+        assert_eq!(removed.ints, vec![RefInt(2)]);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(code.strings.len(), 9);
+        assert_eq!(code.ints, vec![3, 1]);
+        assert!(UnusedPools::new(&code).unwrap().is_empty());
+        assert_eq!(code.main().name(&code), "main");
+        reload(&code);
+    }
+
+    #[test]
+    fn test_dedup_pools() {
+        let mut code = sample();
+        // A duplicate string used by main and duplicate ints
+        code.strings.push(Str::from("main"));
+        code.functions[0].name = RefString(9);
+        code.ints.extend([3, 3]);
+        assert_eq!(code.dedup_pools().unwrap(), 3);
+        assert_eq!(code.functions[0].name, RefString(8));
+        assert_eq!(code.strings.len(), 9);
+        assert_eq!(code.ints, vec![3, 1]);
+        reload(&code);
+    }
+}
@@ -1,13 +1,20 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
 use std::path::Path;
 use std::str::from_utf8;
+#[cfg(feature = "mmap")]
+use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
 use crate::{Bytecode, ConstantDef, Opcode, RefFun, RefFunKnown, RefGlobal, Str};
 use crate::{Error, Result};
+use crate::lazy::{LazyBodies, Rest};
+use crate::lenient::Diagnostic;
+use crate::summary::Section;
 use crate::types::{
     EnumConstruct, Function, Native, ObjField, ObjProto, RefField, RefFloat, RefInt, RefString,
     RefType, Type, TypeFun, TypeObj,
@@ -16,32 +23,167 @@ use crate::types::{
 impl Bytecode {
     /// Read the bytecode from a file. This method will skip bytes until the magic header is found.
     ///
-    /// It uses a 512KiB buffer.
+    /// It uses a 512KiB buffer. See [Bytecode::deserialize] for the memory used with the `rayon` feature.
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
         Self::deserialize(&mut BufReader::with_capacity(512 * 1024, fs::File::open(path)?))
     }
 
     /// Load the bytecode from any source. This method will skip bytes until the magic header is found.
-    /// This also means it will read bytes indefinitely if it can't find the magic header.
-    pub fn deserialize(mut r: impl BufRead) -> Result<Self> {
+    /// This also means it will read bytes indefinitely if it can't find the magic header, or until the end of the input.
+    ///
+    /// With the `rayon` feature, the input after the natives (function bodies and constants) is first copied in memory
+    /// to decode the functions in parallel. The copy is freed once loaded, `Bytecode::from_mmap` (`mmap` feature)
+    /// avoids it.
+    pub fn deserialize(r: impl BufRead) -> Result<Self> {
+        Self::deserialize_with(r, false, None)
+    }
+
+    /// Load the bytecode from any source, the function bodies are only delimited if `lazy` is true.
+    /// Undecodable elements are replaced by placeholders if `diagnostics` is given, see [crate::lenient].
+    pub(crate) fn deserialize_with(
+        mut r: impl BufRead,
+        lazy: bool,
+        diagnostics: Option<&mut Vec<Diagnostic>>,
+    ) -> Result<Self> {
         // Search for the magic header
         let finder = memchr::memmem::Finder::new("HLB");
+        let mut skipped = 0;
         loop {
             let buffer = r.fill_buf()?;
+            if buffer.is_empty() {
+                return Err(Error::MalformedBytecode(
+                    "Magic header not found".to_string(),
+                ));
+            }
             if let Some(index) = finder.find(buffer) {
                 r.consume(index);
-                return Self::deserialize_exact(&mut r);
+                return Self::deserialize_at(r, skipped + index as u64, lazy, diagnostics);
             }
             let len = buffer.len();
-            // Edge case is when this buffer ends with 'HL', we must not consume
-            // the last 2 bytes, so they can be used for the next search.
-            r.consume(len - 2);
+            // Edge case is when this buffer ends with 'HL' or 'H', we must not consume
+            // these bytes, so they can be used for the next search.
+            let kept = if buffer.ends_with(b"HL") {
+                2
+            } else {
+                buffer.ends_with(b"H") as usize
+            };
+            if len > kept {
+                r.consume(len - kept);
+                skipped += (len - kept) as u64;
+                continue;
+            }
+            // The buffer is only refilled once these bytes are consumed
+            let mut start = [0; 2];
+            let start = &mut start[..kept];
+            r.read_exact(start)?;
+            if r.fill_buf()?.starts_with(&b"HLB"[kept..]) {
+                return Self::deserialize_at((&*start).chain(r), skipped, lazy, diagnostics);
+            }
+            skipped += kept as u64;
         }
     }
 
+    /// Load the bytecode starting at the magic header, found after `skipped` bytes of the input
+    fn deserialize_at(
+        mut r: impl Read,
+        skipped: u64,
+        lazy: bool,
+        diagnostics: Option<&mut Vec<Diagnostic>>,
+    ) -> Result<Self> {
+        let mut sections = Sections::new(diagnostics);
+        let code = if sections.is_lenient() {
+            // The size of the input bounds the counts of the header
+            let mut data = Vec::new();
+            r.read_to_end(&mut data)?;
+            sections.len = Some(data.len() as u64);
+            Self::deserialize_exact(&mut Tracked::new(data.as_slice(), 0), lazy, &mut sections)
+        } else {
+            Self::deserialize_exact(&mut Tracked::new(&mut r, 0), lazy, &mut sections)
+        }
+        .map_err(|e| shift(e, skipped));
+        sections.shift(skipped);
+        code
+    }
+
+    /// Load the bytecode from a static buffer, skipping bytes until the magic header is found.
+    ///
+    /// Strings are borrowed from the buffer instead of being copied. Useful with data embedded with `include_bytes!`.
+    pub fn deserialize_static(data: &'static [u8]) -> Result<Self> {
+        Self::deserialize_static_with(data, false)
+    }
+
+    /// Same as [Bytecode::deserialize_static] but function bodies are decoded on demand like
+    /// [Bytecode::deserialize_lazy]. The function bodies are borrowed from the buffer too.
+    pub fn deserialize_static_lazy(data: &'static [u8]) -> Result<Self> {
+        Self::deserialize_static_with(data, true)
+    }
+
+    fn deserialize_static_with(data: &'static [u8], lazy: bool) -> Result<Self> {
+        let start = memchr::memmem::find(data, b"HLB")
+            .ok_or_else(|| Error::MalformedBytecode("Magic header not found".to_string()))?;
+        Self::deserialize_exact(
+            &mut Borrowed::new(&data[start..]),
+            lazy,
+            &mut Sections::new(None),
+        )
+        .map_err(|e| shift(e, start as u64))
+    }
+
+    /// # Safety
+    ///
+    /// See [Bytecode::from_mmap]
+    #[cfg(feature = "mmap")]
+    unsafe fn deserialize_mapped(path: &Path, lazy: bool) -> Result<Self> {
+        let map = Arc::new(memmap2::Mmap::map(&fs::File::open(path)?)?);
+        let start = memchr::memmem::find(&map, b"HLB")
+            .ok_or_else(|| Error::MalformedBytecode("Magic header not found".to_string()))?;
+        Self::deserialize_exact(
+            &mut Mapped::new(map, start),
+            lazy,
+            &mut Sections::new(None),
+        )
+        .map_err(|e| shift(e, start as u64))
+    }
+
+    /// Load the bytecode starting at the magic header at the beginning of `data`.
+    /// Returns the number of bytes of the module.
+    pub(crate) fn deserialize_module(data: &[u8]) -> Result<(Self, usize)> {
+        let mut sections = Sections::new(None);
+        let code = Self::deserialize_exact(&mut Tracked::new(data, 0), false, &mut sections)?;
+        Ok((code, sections.end as usize))
+    }
+
+    /// Memory map a file and load the bytecode from it, skipping bytes until the magic header is found.
+    /// The strings are copied, the mapping is released once loaded.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified, by this process or another one, while it is mapped. See [memmap2::Mmap::map].
+    #[cfg(feature = "mmap")]
+    pub unsafe fn from_mmap(path: impl AsRef<Path>) -> Result<Self> {
+        Self::deserialize_mapped(path.as_ref(), false)
+    }
+
+    /// Same as [Bytecode::from_mmap] but function bodies are decoded on demand, see [Bytecode::deserialize_lazy].
+    /// The encoded bodies stay in the mapping, which is released when the bytecode is dropped or
+    /// [Bytecode::decode_all] is called.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified while it is mapped, see [Bytecode::from_mmap].
+    #[cfg(feature = "mmap")]
+    pub unsafe fn from_mmap_lazy(path: impl AsRef<Path>) -> Result<Self> {
+        Self::deserialize_mapped(path.as_ref(), true)
+    }
+
     /// Load the bytecode from any source.
     /// Must be a valid hashlink bytecode binary that starts with the magic header.
-    fn deserialize_exact(r: &mut impl Read) -> Result<Self> {
+    /// Errors are reported with their offset from the magic header.
+    fn deserialize_exact(
+        r: &mut impl Source,
+        lazy: bool,
+        sections: &mut Sections,
+    ) -> Result<Self> {
         let mut header = [0u8; 3];
         r.read_exact(&mut header)?;
         if header != [b'H', b'L', b'B'] {
@@ -79,93 +221,206 @@ impl Bytecode {
         };
         let entrypoint = RefFun::read(r)?;
 
-        let mut ints = vec![0i32; nints];
-        for i in ints.iter_mut() {
-            *i = r.read_i32::<LittleEndian>()?;
-        }
+        let nints = sections.bound(Section::Ints, nints, r.position(), 4);
+        let ints = sections.elements(
+            Section::Ints,
+            nints,
+            |_| Ok(r.read_i32::<LittleEndian>()?),
+            || 0,
+        )?;
 
-        let mut floats = vec![0f64; nfloats];
-        for i in floats.iter_mut() {
-            *i = r.read_f64::<LittleEndian>()?;
-        }
+        let nfloats = sections.bound(Section::Floats, nfloats, r.position(), 8);
+        let floats = sections.elements(
+            Section::Floats,
+            nfloats,
+            |_| Ok(r.read_f64::<LittleEndian>()?),
+            || 0.0,
+        )?;
 
-        let strings = read_strings(r, nstrings)?;
+        let nstrings = sections.bound(Section::Strings, nstrings, r.position(), 1);
+        let strings = sections.whole(
+            Section::Strings,
+            nstrings,
+            || {
+                let start = r.position();
+                in_element(r.read_strings(nstrings), start, || "strings".to_owned())
+            },
+            || vec![Str::default(); nstrings],
+        )?;
 
         let bytes = if let Some(nbytes) = nbytes {
-            let size = r.read_i32::<LittleEndian>()? as usize;
-            let mut bytes = vec![0; size];
-            r.read_exact(&mut bytes)?;
-            let mut pos = Vec::with_capacity(nbytes);
-            for _ in 0..nbytes {
-                pos.push(read_varu(r)? as usize);
-            }
-            Some((bytes, pos))
+            let nbytes = sections.bound(Section::Bytes, nbytes, r.position(), 1);
+            Some(sections.whole(
+                Section::Bytes,
+                nbytes,
+                || {
+                    let bytes = read_block(r)?;
+                    let mut pos = Vec::with_capacity(capacity(nbytes));
+                    for _ in 0..nbytes {
+                        pos.push(read_varu(r)? as usize);
+                    }
+                    Ok((bytes, pos))
+                },
+                || (Vec::new(), vec![0; nbytes]),
+            )?)
         } else {
             None
         };
 
         let debug_files = if has_debug {
-            let n = read_varu(r)? as usize;
-            Some(read_strings(r, n)?)
+            Some(sections.whole(
+                Section::DebugFiles,
+                0,
+                || {
+                    let n = read_varu(r)? as usize;
+                    let start = r.position();
+                    in_element(r.read_strings(n), start, || "debug files".to_owned())
+                },
+                Vec::new,
+            )?)
         } else {
             None
         };
 
-        let mut types = Vec::with_capacity(ntypes);
-        for _ in 0..ntypes {
-            types.push(Type::read(r)?);
-        }
-
-        let mut globals = Vec::with_capacity(nglobals);
-        for _ in 0..nglobals {
-            globals.push(RefType::read(r)?);
-        }
+        let ntypes = sections.bound(Section::Types, ntypes, r.position(), 1);
+        let types = sections.elements(
+            Section::Types,
+            ntypes,
+            |i| {
+                let start = r.position();
+                in_element(Type::read(r), start, || format!("type {i}"))
+            },
+            || Type::Void,
+        )?;
 
-        let mut natives = Vec::with_capacity(nnatives);
-        for _ in 0..nnatives {
-            natives.push(Native::read(r)?);
-        }
+        let nglobals = sections.bound(Section::Globals, nglobals, r.position(), 1);
+        let globals = sections.elements(
+            Section::Globals,
+            nglobals,
+            |i| {
+                let start = r.position();
+                in_element(RefType::read(r), start, || format!("global {i}"))
+            },
+            RefType::default,
+        )?;
 
-        let mut functions = Vec::with_capacity(nfunctions);
-        for _ in 0..nfunctions {
-            functions.push(Function::read(r, has_debug, version)?);
-        }
+        let nnatives = sections.bound(Section::Natives, nnatives, r.position(), 4);
+        let natives = sections.elements(
+            Section::Natives,
+            nnatives,
+            |i| {
+                let start = r.position();
+                in_element(Native::read(r), start, || format!("native {i}"))
+            },
+            || Native {
+                lib: RefString(0),
+                name: RefString(0),
+                t: RefType(0),
+                findex: RefFun(0),
+            },
+        )?;
 
-        let constants = if let Some(n) = nconstants {
-            let mut constants = Vec::with_capacity(n);
-            for _ in 0..n {
-                constants.push(ConstantDef::read(r)?)
-            }
-            Some(constants)
+        // Lazy loading and parallel decoding require the rest of the input in memory
+        let mut rest = Rest::default();
+        let base = r.position();
+        let mut bodies = Vec::new();
+        let (functions, constants) = if (lazy || cfg!(feature = "rayon")) && !sections.is_lenient()
+        {
+            rest = r.read_rest()?;
+            let data = &mut Tracked::new(&*rest, base);
+            let functions = if lazy {
+                let (functions, b) = Function::read_lazy_all(data, nfunctions, has_debug, version)?;
+                bodies = b;
+                functions
+            } else {
+                Function::read_all(data, nfunctions, has_debug, version)?
+            };
+            let constants = read_constants(data, nconstants, sections)?;
+            sections.end = data.position();
+            (functions, constants)
         } else {
-            None
+            let nfunctions = sections.bound(Section::Functions, nfunctions, r.position(), 4);
+            let functions = sections.elements(
+                Section::Functions,
+                nfunctions,
+                |i| {
+                    let start = r.position();
+                    in_element(Function::read(r, has_debug, version), start, || {
+                        format!("function {i}")
+                    })
+                },
+                || Function {
+                    t: RefType(0),
+                    findex: RefFun(0),
+                    regs: Vec::new(),
+                    ops: Vec::new(),
+                    debug_info: has_debug.then(Vec::new),
+                    assigns: (has_debug && version >= 3).then(Vec::new),
+                    name: RefString(0),
+                    parent: None,
+                },
+            )?;
+            let constants = read_constants(r, nconstants, sections)?;
+            sections.end = r.position();
+            (functions, constants)
         };
 
-        // Parsing is finished, we now build links between everything
+        let mut code = Bytecode {
+            version,
+            entrypoint,
+            ints,
+            floats,
+            strings,
+            bytes,
+            debug_files,
+            types,
+            globals,
+            natives,
+            functions,
+            constants,
+            findexes: Vec::new(),
+            fnames: HashMap::new(),
+            qnames: HashMap::new(),
+            globals_initializers: HashMap::new(),
+            renames: Default::default(),
+            display_names: Default::default(),
+            // The input is only kept to decode the bodies later
+            lazy: if lazy {
+                LazyBodies::new(rest, base, bodies)
+            } else {
+                LazyBodies::default()
+            },
+        };
 
-        // Global function indexes
-        let mut findexes = vec![RefFunKnown::Fun(0); nfunctions + nnatives];
-        for (i, f) in functions.iter().enumerate() {
-            findexes[f.findex.0] = RefFunKnown::Fun(i);
-        }
-        for (i, n) in natives.iter().enumerate() {
-            findexes[n.findex.0] = RefFunKnown::Native(i);
+        if let Some(diagnostics) = &sections.diagnostics {
+            code.fix_placeholders(diagnostics);
         }
 
+        // Parsing is finished, we now build links between everything
+
+        code.build_findexes();
+
         // Flatten types fields
         // Start by collecting every field in the hierarchy
         // The order is important because we refer to fields by index
+        let types = &mut code.types;
         let mut new_fields: Vec<Option<Vec<ObjField>>> = Vec::with_capacity(types.len());
-        for t in &types {
+        for t in types.iter() {
             if let Some(obj) = t.get_type_obj() {
-                let mut parent = obj.super_.as_ref().map(|s| &types[s.0]);
+                let mut parent = obj.super_.and_then(|s| types.get(s.0));
                 let mut acc = VecDeque::with_capacity(obj.own_fields.len());
                 acc.extend(obj.own_fields.clone());
+                // Bound the walk in case of a malformed cyclic hierarchy
+                let mut depth = 0;
                 while let Some(p) = parent.and_then(|t| t.get_type_obj()) {
+                    depth += 1;
+                    if depth > types.len() {
+                        break;
+                    }
                     for f in p.own_fields.iter().rev() {
                         acc.push_front(f.clone());
                     }
-                    parent = p.super_.as_ref().map(|s| &types[s.0]);
+                    parent = p.super_.and_then(|s| types.get(s.0));
                 }
                 new_fields.push(Some(acc.into()));
             } else {
@@ -180,69 +435,37 @@ impl Bytecode {
         }
 
         // Give functions name based on object fields bindings and methods
-        for (i, t) in types.iter().enumerate() {
+        for (i, t) in code.types.iter().enumerate() {
             if let Some(TypeObj {
                             protos, bindings, ..
                         }) = t.get_type_obj()
             {
+                // The findexes not used by any function are Fun(0), even without functions
                 for p in protos {
-                    if let RefFunKnown::Fun(x) = findexes[p.findex.0] {
-                        functions[x].name = p.name;
-                        functions[x].parent = Some(RefType(i));
+                    if let Some(&RefFunKnown::Fun(x)) = code.findexes.get(p.findex.0) {
+                        if let Some(f) = code.functions.get_mut(x) {
+                            f.name = p.name;
+                            f.parent = Some(RefType(i));
+                        }
                     }
                 }
                 for (fid, findex) in bindings {
-                    if let Some(field) = t.get_type_obj().map(|o| &o.fields[fid.0]) {
-                        if let RefFunKnown::Fun(x) = findexes[findex.0] {
-                            functions[x].name = field.name;
-                            functions[x].parent = Some(RefType(i));
+                    if let Some(field) = t.get_type_obj().and_then(|o| o.fields.get(fid.0)) {
+                        if let Some(&RefFunKnown::Fun(x)) = code.findexes.get(findex.0) {
+                            if let Some(f) = code.functions.get_mut(x) {
+                                f.name = field.name;
+                                f.parent = Some(RefType(i));
+                            }
                         }
                     }
                 }
             }
         }
 
-        // Function names
-        let mut fnames = HashMap::with_capacity(functions.len());
-        for (i, f) in functions.iter().enumerate() {
-            // FIXME duplicates ?
-            fnames.insert(strings[f.name.0].clone(), i);
-        }
-        fnames.insert(
-            Str::from("init"),
-            match findexes[entrypoint.0] {
-                RefFunKnown::Fun(x) => x,
-                _ => 0,
-            },
-        );
+        code.build_fnames();
+        code.build_globals_initializers();
 
-        let globals_initializers = if let Some(constants) = &constants {
-            let mut tmp = HashMap::with_capacity(constants.len());
-            for (i, c) in constants.iter().enumerate() {
-                tmp.insert(c.global, i);
-            }
-            tmp
-        } else {
-            HashMap::new()
-        };
-
-        Ok(Bytecode {
-            version,
-            entrypoint,
-            ints,
-            floats,
-            strings,
-            bytes,
-            debug_files,
-            types,
-            globals,
-            natives,
-            functions,
-            constants,
-            findexes,
-            fnames,
-            globals_initializers,
-        })
+        Ok(code)
     }
 }
 
@@ -319,11 +542,11 @@ impl TypeObj {
         let nfields = read_varu(r)? as usize;
         let nprotos = read_varu(r)? as usize;
         let nbindings = read_varu(r)? as usize;
-        let mut own_fields = Vec::with_capacity(nfields);
+        let mut own_fields = Vec::with_capacity(capacity(nfields));
         for _ in 0..nfields {
             own_fields.push(ObjField::read(r)?);
         }
-        let mut protos = Vec::with_capacity(nprotos);
+        let mut protos = Vec::with_capacity(capacity(nprotos));
         for _ in 0..nprotos {
             protos.push(ObjProto {
                 name: RefString::read(r)?,
@@ -331,7 +554,7 @@ impl TypeObj {
                 pindex: read_vari(r)?,
             });
         }
-        let mut bindings = HashMap::with_capacity(nbindings);
+        let mut bindings = HashMap::with_capacity(capacity(nbindings));
         for _ in 0..nbindings {
             bindings.insert(RefField::read(r)?, RefFun::read(r)?);
         }
@@ -372,7 +595,7 @@ impl Type {
             14 => Ok(Ref(RefType::read(r)?)),
             15 => {
                 let nfields = read_varu(r)? as usize;
-                let mut fields = Vec::with_capacity(nfields);
+                let mut fields = Vec::with_capacity(capacity(nfields));
                 for _ in 0..nfields {
                     fields.push(ObjField::read(r)?);
                 }
@@ -386,11 +609,11 @@ impl Type {
                 let name = RefString::read(r)?;
                 let global = RefGlobal::read(r)?;
                 let nconstructs = read_varu(r)? as usize;
-                let mut constructs = Vec::with_capacity(nconstructs);
+                let mut constructs = Vec::with_capacity(capacity(nconstructs));
                 for _ in 0..nconstructs {
                     let name = RefString::read(r)?;
                     let nparams = read_varu(r)? as usize;
-                    let mut params = Vec::with_capacity(nparams);
+                    let mut params = Vec::with_capacity(capacity(nparams));
                     for _ in 0..nparams {
                         params.push(RefType::read(r)?);
                     }
@@ -425,52 +648,38 @@ impl Native {
 }
 
 impl Function {
+    /// Offsets in errors are relative to the start of the function
     pub(crate) fn read(r: &mut impl Read, has_debug: bool, version: u8) -> Result<Self> {
+        Self::read_with(&mut Tracked::new(r, 0), has_debug, version, |r, nops| {
+            let mut ops = Vec::with_capacity(capacity(nops));
+            for i in 0..nops {
+                let start = r.position();
+                ops.push(in_element(Opcode::read(r), start, || format!("opcode {i}"))?);
+            }
+            Ok(ops)
+        })
+    }
+
+    /// Decode a function, the instructions are read by `read_ops`
+    fn read_with<R: Read>(
+        r: &mut R,
+        has_debug: bool,
+        version: u8,
+        read_ops: impl FnOnce(&mut R, usize) -> Result<Vec<Opcode>>,
+    ) -> Result<Self> {
         let t = RefType::read(r)?;
         let findex = RefFun::read(r)?;
         let nregs = read_varu(r)? as usize;
         let nops = read_varu(r)? as usize;
-        let mut regs = Vec::with_capacity(nregs);
+        let mut regs = Vec::with_capacity(capacity(nregs));
         for _ in 0..nregs {
             regs.push(RefType::read(r)?);
         }
-        let mut ops = Vec::with_capacity(nops);
-        for _ in 0..nops {
-            ops.push(Opcode::read(r)?);
-        }
+        let ops = read_ops(r, nops)?;
 
-        // This is extracted from the hashlink source code, do not count on me to explain what it does
         let debug_info = if has_debug {
-            let mut tmp = Vec::with_capacity(nops);
-            let mut currfile: i32 = -1;
-            let mut currline: i32 = 0;
-            let mut i = 0;
-            while i < nops {
-                let mut c = r.read_u8()? as i32;
-                if c & 1 != 0 {
-                    c >>= 1;
-                    currfile = (c << 8) | (r.read_u8()? as i32);
-                } else if c & 2 != 0 {
-                    let delta = c >> 6;
-                    let mut count = (c >> 2) & 15;
-                    while count > 0 {
-                        count -= 1;
-                        tmp.push((currfile as usize, currline as usize));
-                        i += 1;
-                    }
-                    currline += delta;
-                } else if c & 4 != 0 {
-                    currline += c >> 3;
-                    tmp.push((currfile as usize, currline as usize));
-                    i += 1;
-                } else {
-                    let b2 = r.read_u8()? as i32;
-                    let b3 = r.read_u8()? as i32;
-                    currline = (c >> 3) | (b2 << 5) | (b3 << 13);
-                    tmp.push((currfile as usize, currline as usize));
-                    i += 1;
-                }
-            }
+            let mut tmp = Vec::with_capacity(capacity(nops));
+            read_debug_info(r, nops, |file, line| tmp.push((file, line)))?;
             Some(tmp)
         } else {
             None
@@ -478,7 +687,7 @@ impl Function {
 
         let assigns = if has_debug && version >= 3 {
             let len = read_varu(r)? as usize;
-            let mut assigns = Vec::with_capacity(len);
+            let mut assigns = Vec::with_capacity(capacity(len));
             for _ in 0..len {
                 assigns.push((RefString::read(r)?, read_vari(r)? as usize));
             }
@@ -499,11 +708,331 @@ impl Function {
     }
 }
 
+impl Function {
+    /// Decode functions, in parallel with the `rayon` feature. The bodies are delimited first by skipping over them.
+    fn read_all(
+        data: &mut Tracked<&[u8]>,
+        n: usize,
+        has_debug: bool,
+        version: u8,
+    ) -> Result<Vec<Self>> {
+        #[cfg(feature = "rayon")]
+        use rayon::prelude::*;
+
+        let mut bodies = Vec::with_capacity(capacity(n));
+        for i in 0..n {
+            let (start, pos) = (data.inner, data.position());
+            in_element(Function::skip(data, has_debug, version), pos, || {
+                format!("function {i}")
+            })?;
+            bodies.push((pos, &start[..start.len() - data.inner.len()]));
+        }
+        #[cfg(feature = "rayon")]
+        let bodies = bodies.into_par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let bodies = bodies.into_iter();
+        bodies
+            .enumerate()
+            .map(|(i, (pos, mut body))| {
+                in_element(Function::read(&mut body, has_debug, version), pos, || {
+                    format!("function {i}")
+                })
+            })
+            .collect()
+    }
+
+    /// Decode functions without their instructions, returns the position of the instructions of each function in
+    /// `data` and their count
+    #[allow(clippy::type_complexity)]
+    fn read_lazy_all(
+        data: &mut Tracked<&[u8]>,
+        n: usize,
+        has_debug: bool,
+        version: u8,
+    ) -> Result<(Vec<Self>, Vec<Option<(Range<usize>, usize)>>)> {
+        let len = data.inner.len();
+        let mut functions = Vec::with_capacity(capacity(n));
+        let mut bodies = Vec::with_capacity(capacity(n));
+        for i in 0..n {
+            let pos = data.position();
+            let f = Function::read_with(data, has_debug, version, |data, nops| {
+                let start = len - data.inner.len();
+                for j in 0..nops {
+                    let op = data.position() - pos;
+                    in_element(Opcode::skip(data), op, || format!("opcode {j}"))?;
+                }
+                bodies.push(Some((start..len - data.inner.len(), nops)));
+                Ok(Vec::new())
+            });
+            functions.push(in_element(f, pos, || format!("function {i}"))?);
+        }
+        Ok((functions, bodies))
+    }
+
+    /// Skip a function body, same layout as [Function::read]
+    fn skip(r: &mut impl Read, has_debug: bool, version: u8) -> Result<()> {
+        RefType::read(r)?;
+        RefFun::read(r)?;
+        let nregs = read_varu(r)?;
+        let nops = read_varu(r)? as usize;
+        for _ in 0..nregs {
+            RefType::read(r)?;
+        }
+        for _ in 0..nops {
+            Opcode::skip(r)?;
+        }
+        if has_debug {
+            read_debug_info(r, nops, |_, _| {})?;
+        }
+        if has_debug && version >= 3 {
+            for _ in 0..read_varu(r)? {
+                RefString::read(r)?;
+                read_vari(r)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_constants<R: Read + Position>(
+    r: &mut R,
+    n: Option<usize>,
+    sections: &mut Sections,
+) -> Result<Option<Vec<ConstantDef>>> {
+    let Some(n) = n else {
+        return Ok(None);
+    };
+    let n = sections.bound(Section::Constants, n, r.position(), 2);
+    // Placeholders are removed by Bytecode::fix_placeholders
+    sections
+        .elements(
+            Section::Constants,
+            n,
+            |i| {
+                let start = r.position();
+                in_element(ConstantDef::read(r), start, || format!("constant {i}"))
+            },
+            || ConstantDef {
+                global: RefGlobal(0),
+                fields: Vec::new(),
+            },
+        )
+        .map(Some)
+}
+
+/// Decoding of the sections, replacing the elements that can't be decoded by placeholders in lenient mode
+struct Sections<'a> {
+    /// Lenient mode if present
+    diagnostics: Option<&'a mut Vec<Diagnostic>>,
+    /// An element couldn't be decoded, the rest of the input is unreadable
+    stopped: bool,
+    /// Offset of the end of the module once decoded
+    end: u64,
+    /// Size of the input in lenient mode
+    len: Option<u64>,
+}
+
+impl<'a> Sections<'a> {
+    fn new(diagnostics: Option<&'a mut Vec<Diagnostic>>) -> Self {
+        Self {
+            diagnostics,
+            stopped: false,
+            end: 0,
+            len: None,
+        }
+    }
+
+    fn is_lenient(&self) -> bool {
+        self.diagnostics.is_some()
+    }
+
+    /// Reduce the count of a section to the elements that fit in the rest of the input, encoded on at least `min_size`
+    /// bytes each, so a corrupted count doesn't make a huge number of placeholders. The dropped elements are reported.
+    fn bound(&mut self, section: Section, n: usize, position: u64, min_size: u64) -> usize {
+        let (Some(len), Some(diagnostics)) = (self.len, &mut self.diagnostics) else {
+            return n;
+        };
+        let max = (len.saturating_sub(position) / min_size) as usize;
+        if n <= max {
+            return n;
+        }
+        diagnostics.push(Diagnostic {
+            section,
+            index: max,
+            count: n - max,
+            error: Some(Error::MalformedBytecode(format!(
+                "{n} elements don't fit in the rest of the input"
+            ))),
+        });
+        max
+    }
+
+    /// Decode the `n` elements of a section one by one
+    fn elements<T>(
+        &mut self,
+        section: Section,
+        n: usize,
+        mut read: impl FnMut(usize) -> Result<T>,
+        placeholder: impl FnMut() -> T,
+    ) -> Result<Vec<T>> {
+        let reached = !self.stopped;
+        let mut elements = Vec::with_capacity(capacity(n));
+        while elements.len() < n && !self.stopped {
+            match read(elements.len()) {
+                Ok(e) => elements.push(e),
+                Err(e) => self.fail(section, elements.len(), n - elements.len(), e)?,
+            }
+        }
+        if !reached {
+            self.skip(section, n);
+        }
+        elements.resize_with(n, placeholder);
+        Ok(elements)
+    }
+
+    /// Decode a section at once, `n` is its number of elements
+    fn whole<T>(
+        &mut self,
+        section: Section,
+        n: usize,
+        read: impl FnOnce() -> Result<T>,
+        placeholder: impl FnOnce() -> T,
+    ) -> Result<T> {
+        if self.stopped {
+            self.skip(section, n);
+            return Ok(placeholder());
+        }
+        match read() {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                self.fail(section, 0, n, e)?;
+                Ok(placeholder())
+            }
+        }
+    }
+
+    /// The element `index` couldn't be decoded, it is an error unless lenient
+    fn fail(&mut self, section: Section, index: usize, count: usize, error: Error) -> Result<()> {
+        let Some(diagnostics) = &mut self.diagnostics else {
+            return Err(error);
+        };
+        diagnostics.push(Diagnostic {
+            section,
+            index,
+            count,
+            error: Some(error),
+        });
+        self.stopped = true;
+        Ok(())
+    }
+
+    /// The section is after an element that couldn't be decoded
+    fn skip(&mut self, section: Section, count: usize) {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.push(Diagnostic {
+                section,
+                index: 0,
+                count,
+                error: None,
+            });
+        }
+    }
+
+    /// Move the offsets of the errors, see [shift]
+    fn shift(self, by: u64) {
+        for d in self.diagnostics.into_iter().flatten() {
+            if let Some(e) = d.error.take() {
+                d.error = Some(shift(e, by));
+            }
+        }
+    }
+}
+
+/// Attach the element being decoded to an error. `start` is the offset of the element, the offsets of errors in
+/// nested elements are relative to it.
+pub(crate) fn in_element<T>(
+    res: Result<T>,
+    start: u64,
+    element: impl FnOnce() -> String,
+) -> Result<T> {
+    res.map_err(|e| match e {
+        Error::Parse {
+            offset,
+            context,
+            source,
+        } => Error::Parse {
+            offset: start + offset,
+            context: format!("{}, {context}", element()),
+            source,
+        },
+        e => Error::Parse {
+            offset: start,
+            context: element(),
+            source: Box::new(e),
+        },
+    })
+}
+
+/// Move the offset of an error, when the input didn't start at the beginning of the data
+fn shift(e: Error, by: u64) -> Error {
+    match e {
+        Error::Parse {
+            offset,
+            context,
+            source,
+        } => Error::Parse {
+            offset: offset + by,
+            context,
+            source,
+        },
+        e => e,
+    }
+}
+
+/// Decode the file and line of each instruction.
+/// This is extracted from the hashlink source code, do not count on me to explain what it does
+fn read_debug_info(
+    r: &mut impl Read,
+    nops: usize,
+    mut row: impl FnMut(usize, usize),
+) -> Result<()> {
+    let mut currfile: i32 = -1;
+    let mut currline: i32 = 0;
+    let mut i = 0;
+    while i < nops {
+        let mut c = r.read_u8()? as i32;
+        if c & 1 != 0 {
+            c >>= 1;
+            currfile = (c << 8) | (r.read_u8()? as i32);
+        } else if c & 2 != 0 {
+            let delta = c >> 6;
+            let mut count = (c >> 2) & 15;
+            while count > 0 {
+                count -= 1;
+                row(currfile as usize, currline as usize);
+                i += 1;
+            }
+            currline += delta;
+        } else if c & 4 != 0 {
+            currline += c >> 3;
+            row(currfile as usize, currline as usize);
+            i += 1;
+        } else {
+            let b2 = r.read_u8()? as i32;
+            let b3 = r.read_u8()? as i32;
+            currline = (c >> 3) | (b2 << 5) | (b3 << 13);
+            row(currfile as usize, currline as usize);
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
 impl ConstantDef {
     pub(crate) fn read(r: &mut impl Read) -> Result<Self> {
         let global = RefGlobal::read(r)?;
         let nfields = read_varu(r)? as usize;
-        let mut fields = Vec::with_capacity(nfields);
+        let mut fields = Vec::with_capacity(capacity(nfields));
         for _ in 0..nfields {
             fields.push(read_varu(r)? as usize);
         }
@@ -538,30 +1067,225 @@ pub(crate) fn read_varu(r: &mut impl Read) -> Result<u32> {
     }
 }
 
+/// Current offset in the input
+pub(crate) trait Position {
+    fn position(&self) -> u64;
+}
+
+/// Input of the decoder
+trait Source: Read + Position {
+    /// Read a strings pool
+    fn read_strings(&mut self, n: usize) -> Result<Vec<Str>>;
+
+    /// Everything left in the input
+    fn read_rest(&mut self) -> Result<Rest>;
+}
+
+/// Any reader counting the bytes read, strings are copied
+pub(crate) struct Tracked<R> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R> Tracked<R> {
+    pub(crate) fn new(inner: R, pos: u64) -> Self {
+        Self { inner, pos }
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl<R> Position for Tracked<R> {
+    fn position(&self) -> u64 {
+        self.pos
+    }
+}
+
+impl<R: Read> Source for Tracked<R> {
+    fn read_strings(&mut self, n: usize) -> Result<Vec<Str>> {
+        read_strings(self, n)
+    }
+
+    fn read_rest(&mut self) -> Result<Rest> {
+        let mut rest = Vec::new();
+        self.read_to_end(&mut rest)?;
+        Ok(Rest::Buffer(Cow::Owned(rest)))
+    }
+}
+
+/// Static buffer, strings are borrowed
+struct Borrowed {
+    data: &'static [u8],
+    len: usize,
+}
+
+impl Borrowed {
+    fn new(data: &'static [u8]) -> Self {
+        Self {
+            data,
+            len: data.len(),
+        }
+    }
+}
+
+impl Read for Borrowed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.data.read_exact(buf)
+    }
+}
+
+impl Position for Borrowed {
+    fn position(&self) -> u64 {
+        (self.len - self.data.len()) as u64
+    }
+}
+
+impl Source for Borrowed {
+    fn read_strings(&mut self, n: usize) -> Result<Vec<Str>> {
+        let size = read_len(&mut self.data)?;
+        if size > self.data.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let (data, rest) = self.data.split_at(size);
+        self.data = rest;
+        let mut strings = Vec::with_capacity(capacity(n));
+        let mut acc = 0;
+        for _ in 0..n {
+            let ssize = read_varu(&mut self.data)? as usize;
+            let s = data.get(acc..acc + ssize).ok_or_else(|| {
+                Error::MalformedBytecode(format!("String of size {ssize} out of the strings data"))
+            })?;
+            strings.push(Str::from_static(from_utf8(s)?));
+            // Strings are null terminated
+            acc += ssize + 1;
+        }
+        Ok(strings)
+    }
+
+    fn read_rest(&mut self) -> Result<Rest> {
+        Ok(Rest::Buffer(Cow::Borrowed(std::mem::take(&mut self.data))))
+    }
+}
+
+/// Memory mapped file, strings are copied and the rest of the input is kept in the mapping
+#[cfg(feature = "mmap")]
+struct Mapped {
+    map: Arc<memmap2::Mmap>,
+    /// Offset of the magic header
+    start: usize,
+    pos: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl Mapped {
+    fn new(map: Arc<memmap2::Mmap>, start: usize) -> Self {
+        Self {
+            map,
+            start,
+            pos: start,
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Read for Mapped {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.map[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Position for Mapped {
+    fn position(&self) -> u64 {
+        (self.pos - self.start) as u64
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Source for Mapped {
+    fn read_strings(&mut self, n: usize) -> Result<Vec<Str>> {
+        read_strings(self, n)
+    }
+
+    fn read_rest(&mut self) -> Result<Rest> {
+        let start = std::mem::replace(&mut self.pos, self.map.len());
+        Ok(Rest::Mapped(self.map.clone(), start))
+    }
+}
+
 fn read_strings(r: &mut impl Read, nstrings: usize) -> Result<Vec<Str>> {
-    let mut strings = Vec::with_capacity(nstrings);
-    let mut string_data = vec![0u8; r.read_i32::<LittleEndian>()? as usize];
-    r.read_exact(&mut string_data)?;
+    let mut strings = Vec::with_capacity(capacity(nstrings));
+    let string_data = read_block(r)?;
     let mut acc = 0;
     for _ in 0..nstrings {
         let ssize = read_varu(r)? as usize + 1;
         //println!("size: {ssize} {:?}", &string_data[acc..(acc + ssize)]);
         //let cstr = unsafe { CStr::from_bytes_with_nul_unchecked(&string_data[acc..(acc + ssize)]) };
-        strings.push(Str::from_ref(from_utf8(
-            &string_data[acc..(acc + ssize - 1)],
-        )?));
+        let s = string_data.get(acc..(acc + ssize - 1)).ok_or_else(|| {
+            Error::MalformedBytecode(format!(
+                "String of size {} out of the strings data",
+                ssize - 1
+            ))
+        })?;
+        strings.push(Str::from_ref(from_utf8(s)?));
         acc += ssize;
     }
     Ok(strings)
 }
 
+/// Read a block of bytes prefixed by its size. The buffer grows as the bytes are read, so a corrupted size fails at the
+/// end of the input instead of being allocated upfront.
+fn read_block(r: &mut impl Read) -> Result<Vec<u8>> {
+    let size = read_len(r)?;
+    let mut data = Vec::with_capacity(capacity(size));
+    r.by_ref().take(size as u64).read_to_end(&mut data)?;
+    if data.len() < size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(data)
+}
+
+/// Size of a block of bytes
+fn read_len(r: &mut impl Read) -> Result<usize> {
+    let size = r.read_i32::<LittleEndian>()?;
+    usize::try_from(size).map_err(|_| {
+        Error::MalformedBytecode(format!("Got negative size '{size}' (expected >= 0)"))
+    })
+}
+
+/// Capacity to reserve for `n` elements read from the input. Counts are not trusted, a corrupted one fails at the end
+/// of the input instead of allocating memory for all the elements upfront.
+pub(crate) fn capacity(n: usize) -> usize {
+    n.min(1 << 16)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
     use std::fs;
-    use std::io::{BufWriter, Write};
+    use std::io::{BufReader, BufWriter, Write};
 
-    use crate::Bytecode;
+    use crate::summary::Section;
+    use crate::testing::sample;
+    use crate::{Bytecode, Error, Str};
 
     #[test]
     fn test_deserialize_all() {
@@ -619,6 +1343,99 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_error_context() -> crate::Result<()> {
+        let code = sample();
+        let writer = code.sized_writer()?;
+        let types: usize = writer
+            .sizes()
+            .iter()
+            .take_while(|&&(s, _)| s != Section::Types)
+            .map(|&(_, size)| size)
+            .sum();
+        let mut data = writer.to_vec()?;
+        // Invalid kind for the first type
+        data[types] = 0xFF;
+        match Bytecode::deserialize(data.as_slice()) {
+            Err(Error::Parse {
+                offset, context, ..
+            }) => {
+                assert_eq!(offset, types as u64);
+                assert_eq!(context, "type 0");
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_magic_search() -> crate::Result<()> {
+        let mut data = b"garbage".to_vec();
+        sample().serialize(&mut data)?;
+        // The magic header is split between two buffers of the reader
+        for capacity in 3..12 {
+            let code = Bytecode::deserialize(BufReader::with_capacity(capacity, data.as_slice()))?;
+            assert_eq!(code.functions.len(), 4);
+        }
+        // The input ends before a magic header
+        assert!(Bytecode::deserialize(&data[..9]).is_err());
+        assert!(Bytecode::deserialize(&b"no header"[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_size() -> crate::Result<()> {
+        let code = sample();
+        let writer = code.sized_writer()?;
+        let strings: usize = writer
+            .sizes()
+            .iter()
+            .take_while(|&&(s, _)| s != Section::Strings)
+            .map(|&(_, size)| size)
+            .sum();
+        let data = writer.to_vec()?;
+        // The size of the strings data is negative, then past the end of the input
+        for size in [-1, i32::MAX] {
+            let mut data = data.clone();
+            data[strings..strings + 4].copy_from_slice(&size.to_le_bytes());
+            assert!(Bytecode::deserialize(data.as_slice()).is_err());
+            assert!(Bytecode::deserialize_static(Vec::leak(data)).is_err());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap() -> crate::Result<()> {
+        let path = std::env::temp_dir().join("hlbc_test_mmap.hl");
+        sample().serialize(&mut fs::File::create(&path)?)?;
+        // Safety : the file isn't modified while it is mapped
+        let lazy = unsafe { Bytecode::from_mmap_lazy(&path)? };
+        assert!(lazy.is_lazy());
+        assert_eq!(lazy.function_ops(&lazy.functions[0])?.len(), 8);
+        drop(lazy);
+        let code = unsafe { Bytecode::from_mmap(&path)? };
+        assert_eq!(code.functions[0].ops.len(), 8);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_static() -> crate::Result<()> {
+        let mut code = sample();
+        code.synthesize_debug();
+        let data: &'static [u8] = Box::leak(code.sized_writer()?.to_vec()?.into_boxed_slice());
+        let borrowed = Bytecode::deserialize_static(data)?;
+        assert_eq!(borrowed.strings, code.strings);
+        assert_eq!(
+            borrowed.debug_files,
+            Some(vec![Str::from("Main.hx"), Str::from("Point.hx")])
+        );
+        assert_eq!(borrowed.functions.len(), 4);
+        assert_eq!(borrowed.functions[1].name(&borrowed), "length");
+        Ok(())
+    }
+
     #[test]
     fn test_special_opcodes() -> crate::Result<()> {
         let code = Bytecode::from_file("../../data/SpecialOpcodes.hl")?;
@@ -0,0 +1,571 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use crate::types::{RefField, RefFun, RefGlobal, RefString, RefType, Type, TypeObj};
+use crate::{Bytecode, Error, Result, Str};
+
+/// A bytecode element that can be renamed
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Symbol {
+    /// A function or a native
+    Function(RefFun),
+    /// A class, struct, enum or abstract, identified by its name in the string pool.
+    /// Use [Symbol::of_type] to get it from a type.
+    Type(RefString),
+    /// A field of a class or struct, identified by the name of the class and the field index.
+    /// Use [Symbol::of_field] to get it from a type.
+    Field(RefString, RefField),
+    /// Globals have no name in the bytecode
+    Global(RefGlobal),
+    /// Virtual types have no name either, identified by their type index
+    Virtual(RefType),
+    /// Fields of virtual types, identified by their name and type. Every virtual field with this name and type is
+    /// renamed, like they are matched at runtime. Use [Symbol::of_field] to get it from a virtual type.
+    VirtualField(RefString, RefType),
+}
+
+impl Symbol {
+    /// The symbol for a named type (class, struct, enum or abstract)
+    pub fn of_type(code: &Bytecode, ty: RefType) -> Option<Symbol> {
+        match &code[ty] {
+            Type::Obj(obj) | Type::Struct(obj) => Some(Symbol::Type(obj.name)),
+            Type::Enum { name, .. } | Type::Abstract { name } => Some(Symbol::Type(*name)),
+            _ => None,
+        }
+    }
+
+    /// The symbol for a field of a class, struct or virtual
+    pub fn of_field(code: &Bytecode, ty: RefType, field: RefField) -> Option<Symbol> {
+        match &code[ty] {
+            Type::Virtual { fields } => fields
+                .get(field.0)
+                .map(|f| Symbol::VirtualField(f.name, f.t)),
+            ty => ty.get_type_obj().map(|obj| Symbol::Field(obj.name, field)),
+        }
+    }
+}
+
+/// User assigned names, consulted when resolving the name of an element.
+///
+/// Renames are stored next to the bytecode (e.g. in a sidecar file) and don't modify the bytecode unless
+/// [Bytecode::apply_renames] is called. This uses interior mutability so elements can be renamed while the bytecode
+/// is shared.
+#[derive(Debug, Default)]
+pub struct Renames {
+    names: RwLock<HashMap<Symbol, Str>>,
+    /// Incremented on every modification
+    generation: AtomicUsize,
+}
+
+impl Renames {
+    /// Get the user assigned name of an element
+    pub fn get(&self, symbol: Symbol) -> Option<Str> {
+        self.names.read().ok()?.get(&symbol).cloned()
+    }
+
+    /// Rename an element, an empty name removes the rename.
+    /// Fails if the name contains a line break, renames are saved one per line.
+    pub fn set(&self, symbol: Symbol, name: impl Into<Str>) -> Result<()> {
+        let name = name.into();
+        if name.contains(['\n', '\r']) {
+            return Err(Error::InvalidEdit(format!(
+                "the name {name:?} contains a line break"
+            )));
+        }
+        if let Ok(mut names) = self.names.write() {
+            self.touch();
+            if name.is_empty() {
+                names.remove(&symbol);
+            } else {
+                names.insert(symbol, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a rename, returns the user assigned name
+    pub fn remove(&self, symbol: Symbol) -> Option<Str> {
+        let mut names = self.names.write().ok()?;
+        self.touch();
+        names.remove(&symbol)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.read().map(|n| n.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Changes every time the renames are modified, used to invalidate caches of resolved names
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Called with the write lock held so a reader never caches old names with the new generation
+    fn touch(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Every rename, sorted by symbol
+    pub fn entries(&self) -> Vec<(Symbol, Str)> {
+        let mut entries: Vec<_> = self
+            .names
+            .read()
+            .map(|n| n.iter().map(|(s, name)| (*s, name.clone())).collect())
+            .unwrap_or_default();
+        entries.sort_by_key(|(s, _)| *s);
+        entries
+    }
+
+    /// Remove every rename
+    pub fn clear(&self) {
+        if let Ok(mut names) = self.names.write() {
+            self.touch();
+            names.clear();
+        }
+    }
+
+    /// Serialize renames to the sidecar format, one rename per line :
+    /// ```text
+    /// fn <findex> <name>
+    /// type <name string index> <name>
+    /// field <class name string index> <field index> <name>
+    /// global <global index> <name>
+    /// virtual <type index> <name>
+    /// vfield <field name string index> <field type index> <name>
+    /// ```
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (symbol, name) in self.entries() {
+            // Writing to a String can't fail
+            let _ = match symbol {
+                Symbol::Function(f) => writeln!(text, "fn {} {name}", f.0),
+                Symbol::Type(s) => writeln!(text, "type {} {name}", s.0),
+                Symbol::Field(s, f) => writeln!(text, "field {} {} {name}", s.0, f.0),
+                Symbol::Global(g) => writeln!(text, "global {} {name}", g.0),
+                Symbol::Virtual(t) => writeln!(text, "virtual {} {name}", t.0),
+                Symbol::VirtualField(s, t) => writeln!(text, "vfield {} {} {name}", s.0, t.0),
+            };
+        }
+        text
+    }
+
+    /// Add the renames from the sidecar format, see [Renames::to_text].
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(&self, text: &str) -> Result<()> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| Error::MalformedRenames {
+                line: i + 1,
+                msg: msg.to_owned(),
+            };
+            let mut rest = line;
+            let mut next = || {
+                let (token, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                rest = tail.trim_start();
+                token
+            };
+            let kind = next();
+            let mut index = || -> Result<usize> {
+                match next() {
+                    "" => Err(err("missing index")),
+                    index => index.parse().map_err(|_| err("invalid index")),
+                }
+            };
+            let symbol = match kind {
+                "fn" => Symbol::Function(RefFun(index()?)),
+                "type" => Symbol::Type(RefString(index()?)),
+                "field" => Symbol::Field(RefString(index()?), RefField(index()?)),
+                "global" => Symbol::Global(RefGlobal(index()?)),
+                "virtual" => Symbol::Virtual(RefType(index()?)),
+                "vfield" => Symbol::VirtualField(RefString(index()?), RefType(index()?)),
+                _ => return Err(err("unknown element kind")),
+            };
+            // The name is the rest of the line and can contain spaces (e.g. `Map<String, Int>`)
+            let name = match rest {
+                "" => return Err(err("missing name")),
+                name => name,
+            };
+            self.set(symbol, name)?;
+        }
+        Ok(())
+    }
+
+    /// Load renames from a sidecar file
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.parse(&fs::read_to_string(path)?)
+    }
+
+    /// Save renames to a sidecar file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(path, self.to_text())?)
+    }
+}
+
+impl Bytecode {
+    /// Bake the renames into the bytecode by adding the new names to the string pool.
+    /// Globals and virtual types have no name in the bytecode, their renames are kept. The renames of natives are kept
+    /// too, the name of a native is the symbol HashLink looks up when loading the library.
+    pub fn apply_renames(&mut self) {
+        for (symbol, name) in self.renames.entries() {
+            match symbol {
+                Symbol::Function(f) if self.natives.iter().any(|n| n.findex == f) => continue,
+                Symbol::Function(f) => {
+                    let name = self.intern_string(name);
+                    self.rename_function(f, name);
+                }
+                Symbol::Type(s) => {
+                    let name = self.intern_string(name);
+                    for ty in &mut self.types {
+                        match ty {
+                            Type::Obj(obj) | Type::Struct(obj) if obj.name == s => obj.name = name,
+                            Type::Enum { name: n, .. } | Type::Abstract { name: n } if *n == s => {
+                                *n = name
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Symbol::Field(s, f) => {
+                    let name = self.intern_string(name);
+                    self.rename_field(s, f, name);
+                }
+                Symbol::VirtualField(s, t) => {
+                    let name = self.intern_string(name);
+                    for ty in &mut self.types {
+                        if let Type::Virtual { fields } = ty {
+                            for f in fields.iter_mut().filter(|f| f.name == s && f.t == t) {
+                                f.name = name;
+                            }
+                        }
+                    }
+                }
+                Symbol::Global(_) | Symbol::Virtual(_) => continue,
+            }
+            self.renames.remove(symbol);
+        }
+        self.rebuild_indexes();
+    }
+
+    /// Functions are named after the method or the field they are bound to when loading, those are renamed too so the
+    /// name is kept in the encoded bytecode.
+    fn rename_function(&mut self, f: RefFun, name: RefString) {
+        if let Some(fun) = self.functions.iter_mut().find(|fun| fun.findex == f) {
+            fun.name = name;
+        }
+        let mut fields = Vec::new();
+        for obj in self.types.iter_mut().filter_map(Type::get_type_obj_mut) {
+            for p in obj.protos.iter_mut().filter(|p| p.findex == f) {
+                p.name = name;
+            }
+            fields.extend(
+                obj.bindings
+                    .iter()
+                    .filter(|(_, &fun)| fun == f)
+                    .map(|(&field, _)| (obj.name, field)),
+            );
+        }
+        for (class, field) in fields {
+            self.rename_field(class, field, name);
+        }
+    }
+
+    /// Rename a field in its class and in the flattened fields of the subclasses, along with the functions bound to it
+    fn rename_field(&mut self, class: RefString, field: RefField, name: RefString) {
+        let owners: Vec<_> = self
+            .objs()
+            .filter(|(_, obj)| obj.name == class)
+            .map(|(i, _)| i)
+            .collect();
+        let types: Vec<_> = self
+            .objs()
+            .map(|(j, _)| j)
+            .filter(|&j| owners.iter().any(|&i| j == i || self.is_subclass(j, i)))
+            .collect();
+        let mut bound = Vec::new();
+        for t in types {
+            let Some(obj) = self.types[t.0].get_type_obj_mut() else {
+                continue;
+            };
+            let Some(f) = obj.fields.get_mut(field.0) else {
+                continue;
+            };
+            f.name = name;
+            // Own fields are the last fields
+            let nparent = obj.fields.len() - obj.own_fields.len();
+            if field.0 >= nparent {
+                obj.own_fields[field.0 - nparent].name = name;
+            }
+            bound.extend(obj.bindings.get(&field).copied());
+        }
+        for fun in self
+            .functions
+            .iter_mut()
+            .filter(|fun| bound.contains(&fun.findex))
+        {
+            fun.name = name;
+        }
+    }
+}
+
+/// Finding the symbols to rename along with another one.
+impl Bytecode {
+    /// Other symbols to rename along with `symbol` to keep the code consistent :
+    /// - a method : the methods overriding it in subclasses, the fields bound to it and the virtual fields matching it
+    /// - a class field : the field in subclasses, the functions bound to it and the virtual fields with the same name
+    ///   and type
+    ///
+    /// Sorted, without `symbol` itself.
+    pub fn rename_propagation(&self, symbol: Symbol) -> Vec<Symbol> {
+        let mut sites = BTreeSet::new();
+        match symbol {
+            Symbol::Function(fun) => {
+                for (i, obj) in self.objs() {
+                    for p in obj.protos.iter().filter(|p| p.findex == fun) {
+                        for (_, sub) in self.objs().filter(|&(j, _)| self.is_subclass(j, i)) {
+                            sites.extend(
+                                sub.protos
+                                    .iter()
+                                    .filter(|sp| self[sp.name] == self[p.name])
+                                    .map(|sp| Symbol::Function(sp.findex)),
+                            );
+                        }
+                        if let Some(f) = fun.as_fn(self) {
+                            sites.extend(self.virtual_methods(p.name, f.t));
+                        }
+                    }
+                    for (&field, _) in obj.bindings.iter().filter(|(_, &f)| f == fun) {
+                        sites.insert(Symbol::Field(obj.name, field));
+                    }
+                }
+            }
+            Symbol::Field(class, field) => {
+                let Some((i, obj)) = self.objs().find(|(_, obj)| obj.name == class) else {
+                    return Vec::new();
+                };
+                for (j, sub) in self
+                    .objs()
+                    .filter(|&(j, _)| j == i || self.is_subclass(j, i))
+                {
+                    if j != i {
+                        sites.insert(Symbol::Field(sub.name, field));
+                    }
+                    if let Some(&f) = sub.bindings.get(&field) {
+                        sites.insert(Symbol::Function(f));
+                    }
+                }
+                if let Some(f) = obj.fields.get(field.0) {
+                    let matching = |t: &Type| match t {
+                        Type::Virtual { fields } => {
+                            fields.iter().any(|vf| vf.name == f.name && vf.t == f.t)
+                        }
+                        _ => false,
+                    };
+                    if self.types.iter().any(matching) {
+                        sites.insert(Symbol::VirtualField(f.name, f.t));
+                    }
+                }
+            }
+            _ => {}
+        }
+        sites.remove(&symbol);
+        sites.into_iter().collect()
+    }
+
+    /// Classes and structs
+    fn objs(&self) -> impl Iterator<Item = (RefType, &TypeObj)> + '_ {
+        self.types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| t.get_type_obj().map(|obj| (RefType(i), obj)))
+    }
+
+    /// Whether `ty` inherits from `parent`, `ty` itself excluded
+    fn is_subclass(&self, ty: RefType, parent: RefType) -> bool {
+        let mut current = self.types.get(ty.0).and_then(Type::get_type_obj);
+        // Bound the walk in case of a malformed cyclic hierarchy
+        for _ in 0..self.types.len() {
+            match current.and_then(|obj| obj.super_) {
+                Some(s) if s == parent => return true,
+                Some(s) => current = self.types.get(s.0).and_then(Type::get_type_obj),
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Virtual fields with the name of a method and a function type matching the method without `this`
+    fn virtual_methods(&self, name: RefString, method: RefType) -> Vec<Symbol> {
+        let Some(Type::Fun(method) | Type::Method(method)) = self.types.get(method.0) else {
+            return Vec::new();
+        };
+        let mut sites = Vec::new();
+        for t in &self.types {
+            let Type::Virtual { fields } = t else {
+                continue;
+            };
+            for f in fields.iter().filter(|f| self[f.name] == self[name]) {
+                if let Some(Type::Fun(fun) | Type::Method(fun)) = self.types.get(f.t.0) {
+                    if fun.ret == method.ret && method.args.get(1..) == Some(&fun.args[..]) {
+                        sites.push(Symbol::VirtualField(f.name, f.t));
+                    }
+                }
+            }
+        }
+        sites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rename::{Renames, Symbol};
+    use crate::testing::{bind, bytecode, fun, obj, reload, sample, shapes};
+    use crate::types::{Native, RefField, RefFun, RefGlobal, RefString, RefType, Type};
+    use crate::{Bytecode, Str};
+
+    #[test]
+    fn test_sidecar_roundtrip() -> crate::Result<()> {
+        let renames = Renames::default();
+        renames.set(Symbol::Function(RefFun(3)), "hello")?;
+        renames.set(Symbol::Global(RefGlobal(1)), "world")?;
+        renames.set(Symbol::Type(RefString(2)), "Map<String, Int>")?;
+        renames.set(Symbol::Virtual(RefType(7)), "LobbyData")?;
+        renames.set(Symbol::VirtualField(RefString(4), RefType(3)), "score")?;
+        let renames2 = Renames::default();
+        renames2.parse(&format!("# comment\n{}", renames.to_text()))?;
+        assert_eq!(renames.entries(), renames2.entries());
+        assert!(renames2.parse("fn x y").is_err());
+        // A line break would end the rename in the sidecar file
+        assert!(renames.set(Symbol::Global(RefGlobal(0)), "a\nb").is_err());
+        assert!(renames.get(Symbol::Global(RefGlobal(0))).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_propagation() -> crate::Result<()> {
+        let code = shapes();
+        // Shape.area is overridden by Square.area
+        assert_eq!(
+            code.rename_propagation(Symbol::Function(RefFun(1))),
+            vec![Symbol::Function(RefFun(2))]
+        );
+        assert!(code
+            .rename_propagation(Symbol::Function(RefFun(2)))
+            .is_empty());
+        // Main.f is bound to a function
+        assert_eq!(
+            code.rename_propagation(Symbol::Field(RefString(5), RefField(1))),
+            vec![Symbol::Function(RefFun(3))]
+        );
+        assert_eq!(
+            code.rename_propagation(Symbol::Function(RefFun(3))),
+            vec![Symbol::Field(RefString(5), RefField(1))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_apply() -> crate::Result<()> {
+        let mut code = sample();
+        code.renames.set(Symbol::Function(RefFun(2)), "increment")?;
+        code.renames.set(Symbol::Function(RefFun(1)), "norm")?;
+        code.renames.set(Symbol::Global(RefGlobal(0)), "count")?;
+        assert_eq!(RefFun(2).name(&code), "increment");
+        code.apply_renames();
+        assert_eq!(
+            code.renames.entries(),
+            vec![(Symbol::Global(RefGlobal(0)), Str::from("count"))]
+        );
+        assert_eq!(&*code[RefFun(2).as_fn(&code).unwrap().name], "increment");
+
+        // The names are kept in the bytecode
+        let code = reload(&code);
+        assert_eq!(
+            code.function_by_name("increment").unwrap().findex,
+            RefFun(2)
+        );
+        assert_eq!(code.function_by_name("norm").unwrap().findex, RefFun(1));
+        assert!(code.function_by_name("helper").is_none());
+        let main = code[RefType(6)].get_type_obj().unwrap();
+        assert_eq!(&*main.fields[1].name(&code), "increment");
+        let point = code[RefType(4)].get_type_obj().unwrap();
+        assert_eq!(&*point.protos[0].name(&code), "norm");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_apply_native() -> crate::Result<()> {
+        let mut code = sample();
+        let name = code.intern_string("log");
+        let lib = code.intern_string("std");
+        let findex = RefFun(code.findex_max());
+        code.natives.push(Native {
+            name,
+            lib,
+            t: RefType(2),
+            findex,
+        });
+        code.rebuild_indexes();
+        code.renames.set(Symbol::Function(findex), "print")?;
+        code.apply_renames();
+        // The native is still resolved by its original name
+        assert_eq!(
+            code.renames.entries(),
+            vec![(Symbol::Function(findex), Str::from("print"))]
+        );
+        assert_eq!(&*reload(&code)[code.natives[0].name], "log");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_apply_field() -> crate::Result<()> {
+        // class Base { var x:Int; } class Derived extends Base { var y:Int; } with Main.f bound to a function
+        let mut code = bytecode(
+            &["", "Base", "x", "Derived", "y", "$Main", "f"],
+            vec![
+                Type::Void,
+                Type::I32,
+                fun(&[], 0),
+                Type::Obj(obj(1, None, &[(2, 1)], &[])),
+                Type::Obj(obj(3, Some(3), &[(4, 1)], &[])),
+                Type::Obj(bind(obj(5, None, &[(6, 2)], &[]), 0, 0)),
+            ],
+            "
+            .fun fn@0 type@2
+            .reg type@0
+                Ret reg0
+            .end",
+        );
+        code.renames
+            .set(Symbol::Field(RefString(1), RefField(0)), "pos")?;
+        code.renames
+            .set(Symbol::Field(RefString(5), RefField(0)), "g")?;
+        code.apply_renames();
+        assert!(code.renames.is_empty());
+        let names = |code: &Bytecode, t: usize| {
+            let obj = code[RefType(t)].get_type_obj().unwrap();
+            obj.fields
+                .iter()
+                .map(|f| code[f.name].to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&code, 4), ["pos", "y"]);
+        assert_eq!(RefFun(0).name(&code), "g");
+
+        let code = reload(&code);
+        assert_eq!(names(&code, 3), ["pos"]);
+        assert_eq!(names(&code, 4), ["pos", "y"]);
+        assert_eq!(names(&code, 5), ["g"]);
+        assert_eq!(RefFun(0).name(&code), "g");
+        Ok(())
+    }
+}
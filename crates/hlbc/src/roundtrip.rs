@@ -0,0 +1,189 @@
+//! Checking that a bytecode is encoded back to the exact bytes it was decoded from, see
+//! [Bytecode::verify_roundtrip].
+//!
+//! An unmodified bytecode must be written back identically. Any difference is a bug of the decoder or the encoder that
+//! would silently corrupt a patched file, checking the round trip before overwriting the original file catches it.
+
+use std::fmt::{Display, Formatter};
+
+use crate::summary::{ByteCounter, Section};
+use crate::{Bytecode, Result};
+
+/// First difference between a bytecode file and the encoding of the bytecode decoded from it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Divergence {
+    /// Position of the first different byte in the original data
+    pub offset: usize,
+    /// Section containing the difference
+    pub section: Section,
+    /// Position of the difference from the start of the section
+    pub section_offset: usize,
+    /// Index of the element (type, global, native, function, ...) containing the difference, for the sections made
+    /// of a list of elements
+    pub element: Option<usize>,
+    /// Original byte, `None` past the end of the original data
+    pub expected: Option<u8>,
+    /// Encoded byte, `None` past the end of the encoded data
+    pub found: Option<u8>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "At offset {:#x} in {}", self.offset, self.section.name())?;
+        if let Some(element) = self.element {
+            write!(f, " (element {element})")?;
+        }
+        write!(f, ", {:#x} bytes into the section: ", self.section_offset)?;
+        match (self.expected, self.found) {
+            (Some(expected), Some(found)) => {
+                write!(f, "expected {expected:#04x}, found {found:#04x}")
+            }
+            (Some(_), None) => write!(f, "the encoded bytecode is too short"),
+            (None, _) => write!(f, "the encoded bytecode is too long"),
+        }
+    }
+}
+
+impl Bytecode {
+    /// Encode the bytecode and compare it with `original`, the data it was decoded from. Returns the first
+    /// difference, `None` if the encoding is identical. Bytes before the magic header are skipped like when decoding.
+    ///
+    /// Fails if a lazily loaded function body can't be decoded.
+    pub fn verify_roundtrip(&self, original: &[u8]) -> Result<Option<Divergence>> {
+        let start = memchr::memmem::find(original, b"HLB").unwrap_or(0);
+        let original = &original[start..];
+        let writer = self.sized_writer()?;
+        let encoded = writer.to_vec()?;
+        let Some(offset) = original
+            .iter()
+            .zip(&encoded)
+            .position(|(a, b)| a != b)
+            .or_else(|| {
+                (original.len() != encoded.len()).then_some(original.len().min(encoded.len()))
+            })
+        else {
+            return Ok(None);
+        };
+
+        // Everything before the difference is identical, so are the positions of the sections up to it
+        let mut section_start = 0;
+        let mut context = None;
+        for &(section, size) in writer.sizes() {
+            if offset < section_start + size {
+                context = Some((section, self.element_at(section, offset - section_start)?));
+                break;
+            }
+            section_start += size;
+        }
+        let (section, element) = match context {
+            Some(context) => context,
+            // Past the end of the encoded data, the difference is at the end of the last section
+            None => {
+                let &(section, size) = writer.sizes().last().unwrap();
+                section_start -= size;
+                (section, None)
+            }
+        };
+        Ok(Some(Divergence {
+            offset: start + offset,
+            section,
+            section_offset: offset - section_start,
+            element,
+            expected: original.get(offset).copied(),
+            found: encoded.get(offset).copied(),
+        }))
+    }
+
+    /// Decode a bytecode and check that it is encoded back to the same bytes, see [Bytecode::verify_roundtrip].
+    pub fn deserialize_roundtrip(data: &[u8]) -> Result<(Self, Option<Divergence>)> {
+        let code = Self::deserialize(data)?;
+        let divergence = code.verify_roundtrip(data)?;
+        Ok((code, divergence))
+    }
+
+    /// Index of the element at a position in the encoding of a section
+    fn element_at(&self, section: Section, offset: usize) -> Result<Option<usize>> {
+        let mut counter = ByteCounter(0);
+        // Position of the end of each element in the section
+        let mut ends = Vec::new();
+        match section {
+            Section::Ints => return Ok(Some(offset / 4)),
+            Section::Floats => return Ok(Some(offset / 8)),
+            Section::Types => {
+                for t in &self.types {
+                    t.write(&mut counter)?;
+                    ends.push(counter.0);
+                }
+            }
+            Section::Globals => {
+                for g in &self.globals {
+                    g.write(&mut counter)?;
+                    ends.push(counter.0);
+                }
+            }
+            Section::Natives => {
+                for n in &self.natives {
+                    n.write(&mut counter)?;
+                    ends.push(counter.0);
+                }
+            }
+            Section::Functions => {
+                for f in &self.functions {
+                    f.write(&mut counter, &self.function_ops(f)?)?;
+                    ends.push(counter.0);
+                }
+            }
+            Section::Constants => {
+                for c in self.constants.iter().flatten() {
+                    c.write(&mut counter)?;
+                    ends.push(counter.0);
+                }
+            }
+            Section::Header | Section::Strings | Section::Bytes | Section::DebugFiles => {
+                return Ok(None)
+            }
+        }
+        Ok(ends.iter().position(|&end| offset < end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::Section;
+    use crate::testing::sample;
+    use crate::Bytecode;
+
+    #[test]
+    fn test_roundtrip() -> crate::Result<()> {
+        let code = sample();
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        let (code, divergence) = Bytecode::deserialize_roundtrip(&data)?;
+        assert_eq!(divergence, None);
+
+        // Change the last byte of the first function
+        let writer = code.sized_writer()?;
+        let before: usize = writer
+            .sizes()
+            .iter()
+            .take_while(|(s, _)| *s != Section::Functions)
+            .map(|(_, size)| size)
+            .sum();
+        let first = code.size_breakdown()?.functions[0];
+        let mut patched = data.clone();
+        patched[before + first - 1] ^= 0xff;
+        let divergence = code.verify_roundtrip(&patched)?.unwrap();
+        assert_eq!(divergence.offset, before + first - 1);
+        assert_eq!(divergence.section, Section::Functions);
+        assert_eq!(divergence.section_offset, first - 1);
+        assert_eq!(divergence.element, Some(0));
+        assert_eq!(divergence.found, Some(data[before + first - 1]));
+
+        // Trailing data
+        data.push(0);
+        let divergence = code.verify_roundtrip(&data)?.unwrap();
+        assert_eq!(divergence.offset, data.len() - 1);
+        assert_eq!(divergence.found, None);
+        Ok(())
+    }
+}
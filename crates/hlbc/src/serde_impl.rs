@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::types::{ConstantDef, Function, Native, RefFun, RefType, Type};
+use crate::{Bytecode, Str};
+
+/// Same as [Bytecode] without the acceleration structures
+#[derive(Deserialize)]
+struct BytecodeData {
+    version: u8,
+    entrypoint: RefFun,
+    ints: Vec<i32>,
+    floats: Vec<f64>,
+    strings: Vec<Str>,
+    bytes: Option<(Vec<u8>, Vec<usize>)>,
+    debug_files: Option<Vec<Str>>,
+    types: Vec<Type>,
+    globals: Vec<RefType>,
+    natives: Vec<Native>,
+    functions: Vec<Function>,
+    constants: Option<Vec<ConstantDef>>,
+}
+
+impl<'de> Deserialize<'de> for Bytecode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = BytecodeData::deserialize(deserializer)?;
+        let mut code = Bytecode {
+            version: data.version,
+            entrypoint: data.entrypoint,
+            ints: data.ints,
+            floats: data.floats,
+            strings: data.strings,
+            bytes: data.bytes,
+            debug_files: data.debug_files,
+            types: data.types,
+            globals: data.globals,
+            natives: data.natives,
+            functions: data.functions,
+            constants: data.constants,
+            findexes: Vec::new(),
+            fnames: HashMap::new(),
+            qnames: HashMap::new(),
+            globals_initializers: HashMap::new(),
+            renames: Default::default(),
+            display_names: Default::default(),
+            lazy: Default::default(),
+        };
+        code.rebuild_indexes();
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::sample;
+    use crate::types::RefFun;
+    use crate::Bytecode;
+
+    #[test]
+    fn test_json_roundtrip() -> crate::Result<()> {
+        let code = sample();
+        let json = serde_json::to_string(&code).unwrap();
+        let code2: Bytecode = serde_json::from_str(&json).unwrap();
+        assert_eq!(code.functions.len(), code2.functions.len());
+        assert_eq!(code.types, code2.types);
+        assert_eq!(
+            code2.main().findex,
+            RefFun(0),
+            "acceleration structures should be rebuilt"
+        );
+        assert_eq!(code2.functions[1].name(&code2), "length");
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        let mut data2 = Vec::new();
+        code2.serialize(&mut data2)?;
+        assert_eq!(data, data2);
+        Ok(())
+    }
+}
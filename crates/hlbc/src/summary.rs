@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use crate::types::{RefFun, RefType};
+use crate::{Bytecode, Result, Str};
+
+/// A section of a bytecode file, in file order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Section {
+    /// Magic, version, flags, pools lengths and entrypoint
+    Header,
+    Ints,
+    Floats,
+    Strings,
+    /// *Since bytecode v5*
+    Bytes,
+    /// *Debug*
+    DebugFiles,
+    Types,
+    Globals,
+    Natives,
+    Functions,
+    /// *Since bytecode v4*
+    Constants,
+}
+
+impl Section {
+    /// Every section in file order
+    pub const ALL: [Section; 11] = [
+        Section::Header,
+        Section::Ints,
+        Section::Floats,
+        Section::Strings,
+        Section::Bytes,
+        Section::DebugFiles,
+        Section::Types,
+        Section::Globals,
+        Section::Natives,
+        Section::Functions,
+        Section::Constants,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Section::Header => "Header",
+            Section::Ints => "Integers",
+            Section::Floats => "Floats",
+            Section::Strings => "Strings",
+            Section::Bytes => "Bytes strings",
+            Section::DebugFiles => "Source files",
+            Section::Types => "Types",
+            Section::Globals => "Globals",
+            Section::Natives => "Natives",
+            Section::Functions => "Functions",
+            Section::Constants => "Constant definitions",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Section::Header => "Bytecode header",
+            Section::Ints => "Integer constants",
+            Section::Floats => "Float constants",
+            Section::Strings => "String constants",
+            Section::Bytes => "Bytes constants (since bytecode v5)",
+            Section::DebugFiles => "Linked source files (debug info)",
+            Section::Types => "Hashlink types (classes, functions, ...)",
+            Section::Globals => "Global variables",
+            Section::Natives => "Native function references",
+            Section::Functions => "Functions, methods, closures",
+            Section::Constants => "Global variables initializers (since bytecode v4)",
+        }
+    }
+}
+
+/// Number of elements and serialized size of a section
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SectionSummary {
+    pub section: Section,
+    /// Number of elements in the section, 0 for the header
+    pub count: usize,
+    /// Size in bytes
+    pub size: usize,
+}
+
+/// General information about a bytecode file
+#[derive(Debug, Clone)]
+pub struct ModuleSummary {
+    /// Bytecode format version
+    pub version: u8,
+    /// Is the bytecode compiled with debug information
+    pub debug: bool,
+    pub entrypoint: RefFun,
+    /// Only the sections present in the bytecode, in file order
+    pub sections: Vec<SectionSummary>,
+    /// Size of the whole file in bytes
+    pub size: usize,
+}
+
+impl ModuleSummary {
+    pub fn section(&self, section: Section) -> Option<&SectionSummary> {
+        self.sections.iter().find(|s| s.section == section)
+    }
+}
+
+impl Bytecode {
+    /// Number of elements of a section, [None] if the section is not present in this bytecode.
+    pub fn section_len(&self, section: Section) -> Option<usize> {
+        match section {
+            Section::Header => Some(0),
+            Section::Ints => Some(self.ints.len()),
+            Section::Floats => Some(self.floats.len()),
+            Section::Strings => Some(self.strings.len()),
+            Section::Bytes => self.bytes.as_ref().map(|(_, pos)| pos.len()),
+            Section::DebugFiles => self.debug_files.as_ref().map(Vec::len),
+            Section::Types => Some(self.types.len()),
+            Section::Globals => Some(self.globals.len()),
+            Section::Natives => Some(self.natives.len()),
+            Section::Functions => Some(self.functions.len()),
+            Section::Constants => self.constants.as_ref().map(Vec::len),
+        }
+    }
+
+    /// Size of a section in bytes once serialized.
+    pub fn section_size(&self, section: Section) -> usize {
+        let mut counter = ByteCounter(0);
+        // Writing to the counter never fails and elements are already known to be serializable
+        self.serialize_section(section, &mut counter).ok();
+        counter.0
+    }
+
+    /// Compute a summary of the bytecode.
+    ///
+    /// Sizes are computed by serializing each section, they're equal to the sizes in the original file
+    /// unless the bytecode has been modified.
+    pub fn summary(&self) -> ModuleSummary {
+        let sections: Vec<SectionSummary> = Section::ALL
+            .into_iter()
+            .filter_map(|section| {
+                self.section_len(section).map(|count| SectionSummary {
+                    section,
+                    count,
+                    size: self.section_size(section),
+                })
+            })
+            .collect();
+        ModuleSummary {
+            version: self.version,
+            debug: self.debug_files.is_some(),
+            entrypoint: self.entrypoint,
+            size: sections.iter().map(|s| s.size).sum(),
+            sections,
+        }
+    }
+}
+
+/// Serialized size of the elements of a bytecode, to find what makes a file big
+#[derive(Debug, Clone, Default)]
+pub struct SizeBreakdown {
+    /// Size of each section present in the bytecode, in file order
+    pub sections: Vec<(Section, usize)>,
+    /// Size of each function (header, registers, instructions, debug info and assigns), in pool order
+    pub functions: Vec<usize>,
+    /// Size of each type definition
+    pub types: Vec<usize>,
+    /// Size of the definition of each class, struct or enum with the size of its methods
+    pub classes: HashMap<RefType, usize>,
+    /// Size of the functions defined in each source file
+    pub files: HashMap<Str, usize>,
+    /// Size of the functions without a parent type (closures, static functions of the entrypoint)
+    pub orphans: usize,
+}
+
+impl SizeBreakdown {
+    /// Classes sorted by decreasing size
+    pub fn largest_classes(&self) -> Vec<(RefType, usize)> {
+        let mut classes: Vec<_> = self.classes.iter().map(|(&t, &s)| (t, s)).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0 .0.cmp(&b.0 .0)));
+        classes
+    }
+
+    /// Files sorted by decreasing size
+    pub fn largest_files(&self) -> Vec<(Str, usize)> {
+        let mut files: Vec<_> = self.files.iter().map(|(f, &s)| (f.clone(), s)).collect();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| (*a.0).cmp(&*b.0)));
+        files
+    }
+}
+
+impl Bytecode {
+    /// Compute the serialized size of each section, function and type, see [SizeBreakdown].
+    /// Fails if a lazily loaded function body can't be decoded.
+    pub fn size_breakdown(&self) -> Result<SizeBreakdown> {
+        let mut breakdown = SizeBreakdown {
+            sections: self.sized_writer()?.sizes().to_vec(),
+            ..Default::default()
+        };
+        for t in &self.types {
+            let mut counter = ByteCounter(0);
+            t.write(&mut counter)?;
+            breakdown.types.push(counter.0);
+        }
+        for (i, t) in self.types.iter().enumerate() {
+            if t.get_type_obj().is_some() || matches!(t, crate::Type::Enum { .. }) {
+                breakdown.classes.insert(RefType(i), breakdown.types[i]);
+            }
+        }
+        for f in &self.functions {
+            let mut counter = ByteCounter(0);
+            f.write(&mut counter, &self.function_ops(f)?)?;
+            let size = counter.0;
+            breakdown.functions.push(size);
+            match f.parent {
+                Some(parent) => *breakdown.classes.entry(parent).or_default() += size,
+                None => breakdown.orphans += size,
+            }
+            if let Some(file) = f.file_index().and_then(|i| self.debug_file(i)) {
+                *breakdown.files.entry(file).or_default() += size;
+            }
+        }
+        Ok(breakdown)
+    }
+}
+
+/// Sink counting the bytes written to it
+pub(crate) struct ByteCounter(pub(crate) usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::Section;
+    use crate::testing::sample;
+    use crate::types::RefType;
+
+    #[test]
+    fn test_summary_size() -> crate::Result<()> {
+        let code = sample();
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        let summary = code.summary();
+        assert_eq!(summary.size, data.len());
+        assert_eq!(summary.section(Section::Functions).unwrap().count, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_breakdown() -> crate::Result<()> {
+        let code = sample();
+        let breakdown = code.size_breakdown()?;
+        let summary = code.summary();
+        assert_eq!(
+            breakdown.functions.iter().sum::<usize>(),
+            summary.section(Section::Functions).unwrap().size
+        );
+        assert_eq!(
+            breakdown.types.iter().sum::<usize>(),
+            summary.section(Section::Types).unwrap().size
+        );
+        // main, length, helper and unused
+        assert_eq!(breakdown.functions, vec![33, 12, 15, 7]);
+        // Point with length, Main with its static functions
+        assert_eq!(
+            breakdown.largest_classes(),
+            vec![(RefType(6), 20 + 33 + 15 + 7), (RefType(4), 13 + 12)]
+        );
+        assert_eq!(breakdown.orphans, 0);
+        // No debug info
+        assert!(breakdown.largest_files().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+//! Small bytecodes built in memory for the tests, in place of files compiled from Haxe sources.
+//!
+//! Functions are written in the [asm](crate::fmt::asm) syntax. [bytecode] encodes and decodes the bytecode back so
+//! the links between its elements (flattened fields, method names, indexes) are built like for a real file.
+//!
+//! Available to the other crates with the `testing` feature.
+
+use std::collections::HashMap;
+
+use crate::fmt::asm::parse_functions;
+use crate::types::{
+    Native, ObjField, ObjProto, RefField, RefFun, RefGlobal, RefString, RefType, Type, TypeFun,
+    TypeObj,
+};
+use crate::{Bytecode, Str};
+
+/// Bytecode with the given strings, types and functions. The first function is the entrypoint.
+pub fn bytecode(strings: &[&str], types: Vec<Type>, asm: &str) -> Bytecode {
+    reload(&build(strings, types, &[], asm))
+}
+
+/// Same as [bytecode] with natives (lib, name, type, findex), without going through the encoder. The assigns of the
+/// functions are dropped when encoding a bytecode without debug info.
+///
+/// Functions are named after the fields and methods they are bound to, like when loading a file. Classes have no
+/// parent, their fields are their own fields.
+pub fn build(
+    strings: &[&str],
+    types: Vec<Type>,
+    natives: &[(usize, usize, usize, usize)],
+    asm: &str,
+) -> Bytecode {
+    let functions = parse_functions(asm).unwrap();
+    let mut code = Bytecode {
+        entrypoint: functions.first().map_or(RefFun(0), |f| f.findex),
+        strings: strings.iter().map(|&s| Str::from(s)).collect(),
+        bytes: Some((Vec::new(), Vec::new())),
+        types,
+        natives: natives
+            .iter()
+            .map(|&(lib, name, t, findex)| Native {
+                name: RefString(name),
+                lib: RefString(lib),
+                t: RefType(t),
+                findex: RefFun(findex),
+            })
+            .collect(),
+        functions,
+        constants: Some(Vec::new()),
+        ..Default::default()
+    };
+    for (i, t) in code.types.iter_mut().enumerate() {
+        let Some(obj) = t.get_type_obj_mut() else {
+            continue;
+        };
+        obj.fields = obj.own_fields.clone();
+        let names = obj.protos.iter().map(|p| (p.findex, p.name)).chain(
+            obj.bindings
+                .iter()
+                .map(|(field, &findex)| (findex, obj.fields[field.0].name)),
+        );
+        for (findex, name) in names {
+            if let Some(f) = code.functions.iter_mut().find(|f| f.findex == findex) {
+                f.name = name;
+                f.parent = Some(RefType(i));
+            }
+        }
+    }
+    code.rebuild_indexes();
+    code
+}
+
+/// Encode and decode the bytecode, to rebuild everything computed when loading a file
+pub fn reload(code: &Bytecode) -> Bytecode {
+    let mut data = Vec::new();
+    code.serialize(&mut data).unwrap();
+    Bytecode::deserialize(data.as_slice()).unwrap()
+}
+
+/// Function type
+pub fn fun(args: &[usize], ret: usize) -> Type {
+    Type::Fun(TypeFun {
+        args: args.iter().map(|&t| RefType(t)).collect(),
+        ret: RefType(ret),
+    })
+}
+
+/// Class named `name` with its own fields (name, type) and its methods (name, findex)
+pub fn obj(
+    name: usize,
+    super_: Option<usize>,
+    fields: &[(usize, usize)],
+    protos: &[(usize, usize)],
+) -> TypeObj {
+    TypeObj {
+        name: RefString(name),
+        super_: super_.map(RefType),
+        global: RefGlobal(0),
+        own_fields: fields
+            .iter()
+            .map(|&(name, t)| ObjField {
+                name: RefString(name),
+                t: RefType(t),
+            })
+            .collect(),
+        protos: protos
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, findex))| ObjProto {
+                name: RefString(name),
+                findex: RefFun(findex),
+                pindex: i as i32,
+            })
+            .collect(),
+        bindings: HashMap::new(),
+        fields: Vec::new(),
+    }
+}
+
+/// Bind the field `field` of a class to the function `findex`, like static methods
+pub fn bind(mut obj: TypeObj, field: usize, findex: usize) -> TypeObj {
+    obj.bindings.insert(RefField(field), RefFun(findex));
+    obj
+}
+
+/// Class named `name` with fields (name, type) bound to functions (findex), like a static class (`$Main`)
+pub fn class(name: usize, fields: &[(usize, usize, usize)]) -> Type {
+    let own: Vec<_> = fields.iter().map(|&(name, t, _)| (name, t)).collect();
+    Type::Obj(
+        fields
+            .iter()
+            .enumerate()
+            .fold(obj(name, None, &own, &[]), |obj, (i, &(_, _, findex))| {
+                bind(obj, i, findex)
+            }),
+    )
+}
+
+/// A small program with a class, static functions, a global and constants :
+///
+/// ```haxe
+/// class Point {
+///     var x:Int;
+///     function length() return x;
+/// }
+///
+/// class Main {
+///     static var count:Int;
+///     static function main() {
+///         var p = new Point();
+///         p.x = 3;
+///         count = helper(p.length());
+///         var s = "hello";
+///     }
+///     static function helper(i:Int) return i + 1;
+///     static function unused() {}
+/// }
+/// ```
+pub fn sample() -> Bytecode {
+    let mut code = bytecode(
+        &[
+            "", "Point", "x", "length", "$Main", "helper", "unused", "hello", "main",
+        ],
+        vec![
+            Type::Void,
+            Type::I32,
+            fun(&[], 0),
+            fun(&[4], 1),
+            Type::Obj(obj(1, None, &[(2, 1)], &[(3, 1)])),
+            fun(&[1], 1),
+            Type::Obj(bind(
+                bind(
+                    bind(obj(4, None, &[(8, 2), (5, 5), (6, 2)], &[]), 0, 0),
+                    1,
+                    2,
+                ),
+                2,
+                3,
+            )),
+            Type::Bytes,
+        ],
+        "
+        .fun fn@0 type@2
+        .reg type@4
+        .reg type@1
+        .reg type@7
+        .reg type@0
+            New reg0
+            Int reg1 int@0
+            SetField reg0 field@0 reg1
+            Call1 reg1 fn@1 reg0
+            Call1 reg1 fn@2 reg1
+            SetGlobal global@0 reg1
+            String reg2 string@7
+            Ret reg3
+        .end
+        .fun fn@1 type@3
+        .reg type@4
+        .reg type@1
+            Field reg1 reg0 field@0
+            Ret reg1
+        .end
+        .fun fn@2 type@5
+        .reg type@1
+        .reg type@1
+            Int reg1 int@1
+            Add reg1 reg0 reg1
+            Ret reg1
+        .end
+        .fun fn@3 type@2
+        .reg type@0
+            Ret reg0
+        .end",
+    );
+    code.ints = vec![3, 1];
+    code.globals = vec![RefType(1)];
+    reload(&code)
+}
+
+/// Classes with a method call, an override and closure calls. `Square` extends `Shape` and overrides `area`, `main`
+/// calls `area` on a `Shape` as a method and through a closure, then calls `Main.f` through its field and directly.
+pub fn shapes() -> Bytecode {
+    bytecode(
+        &["", "Shape", "area", "Square", "main", "$Main", "f"],
+        vec![
+            Type::Void,
+            Type::I32,
+            fun(&[], 0),
+            fun(&[4], 1),
+            Type::Obj(obj(1, None, &[], &[(2, 1)])),
+            Type::Obj(obj(3, Some(4), &[], &[(2, 2)])),
+            fun(&[5], 1),
+            Type::Obj(bind(bind(obj(5, None, &[(4, 2), (6, 3)], &[]), 0, 0), 1, 3)),
+        ],
+        "
+        .fun fn@0 type@2
+        .reg type@4
+        .reg type@1
+        .reg type@7
+        .reg type@3
+        .reg type@0
+            New reg0
+            CallMethod reg1 field@0 [reg0]
+            StaticClosure reg3 fn@1
+            CallClosure reg1 reg3 [reg0]
+            Null reg2
+            Field reg3 reg2 field@1
+            CallClosure reg1 reg3 [reg0]
+            Call1 reg1 fn@3 reg0
+            Ret reg4
+        .end
+        .fun fn@1 type@3
+        .reg type@4
+        .reg type@1
+            CallThis reg1 field@0 []
+            Ret reg1
+        .end
+        .fun fn@2 type@6
+        .reg type@5
+        .reg type@1
+            Ret reg1
+        .end
+        .fun fn@3 type@3
+        .reg type@4
+        .reg type@1
+            Ret reg1
+        .end",
+    )
+}
@@ -1,34 +1,61 @@
 use std::collections::HashMap;
 use std::ops::Index;
 
-use crate::{Bytecode, Opcode, Resolve, Str};
+use smallvec::SmallVec;
+
+use crate::rename::Symbol;
+use crate::{Bytecode, Opcode, Resolve, Str, TryResolve};
 
 /// Offset for a jump instruction. Can be negative, indicating a backward jump.
 pub type JumpOffset = i32;
 
+/// Registers operands of an instruction (e.g. call arguments).
+///
+/// Up to 4 registers are stored inline without any allocation, which is the case of most instructions. This is the
+/// same size as a [Vec].
+pub type Regs = SmallVec<[Reg; 4]>;
+
+/// Jump offsets of a [Opcode::Switch], stored inline up to 4 offsets like [Regs].
+pub type JumpOffsets = SmallVec<[JumpOffset; 4]>;
+
+/// Symbolic target of a jump, its offset is computed when the instructions are spliced in a function, see
+/// [Function::splice_ops_with_targets].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum JumpTarget {
+    /// Position in the new instructions, their length targets the instruction following them
+    Local(usize),
+    /// Position of an instruction of the function before the edit, it can't be one of the replaced instructions
+    Function(usize),
+}
+
 pub type InlineInt = i32;
 pub type InlineBool = bool;
 
 /// A register argument
 ///
 /// Registers are a function local variables.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Reg(pub u32);
 
 /// A reference to the i32 constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefInt(pub usize);
 
 /// A reference to the f64 constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefFloat(pub usize);
 
 /// A reference to the bytes constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefBytes(pub usize);
 
 /// Reference to the string constant pool
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefString(pub usize);
 
 impl RefString {
@@ -39,11 +66,13 @@ impl RefString {
 }
 
 /// A reference to a global
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefGlobal(pub usize);
 
 /// An object field definition
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObjField {
     /// Field name
     pub name: RefString,
@@ -55,14 +84,23 @@ impl ObjField {
     pub fn name(&self, code: &Bytecode) -> Str {
         code.get(self.name)
     }
+
+    /// Name of a field of a virtual type, taking renames into account
+    pub fn virtual_name(&self, code: &Bytecode) -> Str {
+        code.renames
+            .get(Symbol::VirtualField(self.name, self.t))
+            .unwrap_or_else(|| self.name(code))
+    }
 }
 
 /// A reference to an object field
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefField(pub usize);
 
 /// An object method definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObjProto {
     /// Method name
     pub name: RefString,
@@ -80,6 +118,7 @@ impl ObjProto {
 
 /// An enum variant definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnumConstruct {
     /// Variant name
     pub name: RefString,
@@ -95,10 +134,12 @@ impl EnumConstruct {
 
 /// A reference to an enum variant
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefEnumConstruct(pub usize);
 
 /// Common type for [Type::Fun] and [Type::Method]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeFun {
     pub args: Vec<RefType>,
     pub ret: RefType,
@@ -106,6 +147,7 @@ pub struct TypeFun {
 
 /// Common type for [Type::Obj] and [Type::Struct]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeObj {
     pub name: RefString,
     pub super_: Option<RefType>,
@@ -124,7 +166,9 @@ pub struct TypeObj {
 
 impl TypeObj {
     pub fn name(&self, code: &Bytecode) -> Str {
-        code.get(self.name)
+        code.renames
+            .get(Symbol::Type(self.name))
+            .unwrap_or_else(|| code.get(self.name))
     }
 
     /// Get the static part of this class
@@ -139,6 +183,7 @@ impl TypeObj {
 
 /// Type available in the hashlink type system. Every type is one of those.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Void,
     UI8,
@@ -214,6 +259,25 @@ impl Type {
         }
     }
 
+    /// u8, u16, i32 and i64
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::UI8 | Type::UI16 | Type::I32 | Type::I64)
+    }
+
+    /// f32 and f64
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F32 | Type::F64)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    /// Every type except void, numbers and bool can hold null
+    pub fn is_nullable(&self) -> bool {
+        !(self.is_numeric() || matches!(self, Type::Void | Type::Bool))
+    }
+
     /// If this type is a wrapper type, return the inner type.
     pub fn get_inner(&self) -> Option<RefType> {
         match self {
@@ -224,7 +288,8 @@ impl Type {
 }
 
 /// Reference to a type in the constant pool
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefType(pub usize);
 
 impl RefType {
@@ -273,16 +338,17 @@ impl RefType {
     }
 
     pub fn field<'a>(&self, field: RefField, ctx: &'a Bytecode) -> Option<&'a ObjField> {
-        self.as_obj(ctx).map(|obj| &obj.fields[field.0])
+        self.as_obj(ctx).and_then(|obj| obj.fields.get(field.0))
     }
 
     pub fn method<'a>(&self, meth: usize, ctx: &'a Bytecode) -> Option<&'a ObjProto> {
-        self.as_obj(ctx).map(|obj| &obj.protos[meth])
+        self.as_obj(ctx).and_then(|obj| obj.protos.get(meth))
     }
 }
 
 /// A native function reference. Contains no code but indicates the library from where to load it.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Native {
     /// Native function name
     pub name: RefString,
@@ -294,7 +360,9 @@ pub struct Native {
 
 impl Native {
     pub fn name(&self, code: &Bytecode) -> Str {
-        code.get(self.name)
+        code.renames
+            .get(Symbol::Function(self.findex))
+            .unwrap_or_else(|| code.get(self.name))
     }
 
     pub fn lib(&self, code: &Bytecode) -> Str {
@@ -323,13 +391,14 @@ impl Native {
 
 /// A function definition with its code.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Function {
     /// Type of the function : args and return type. Guaranteed to be a [TypeFun].
     pub t: RefType,
     pub findex: RefFun,
     /// The types of the registers used by this function
     pub regs: Vec<RefType>,
-    /// Instructions
+    /// Instructions, empty until decoded for a lazily loaded bytecode (see [crate::lazy])
     pub ops: Vec<Opcode>,
     /// *Debug* File and line information for each instruction
     pub debug_info: Option<Vec<(usize, usize)>>,
@@ -350,9 +419,11 @@ impl Function {
         self[reg]
     }
 
-    /// Convenience method to resolve the function name
+    /// Convenience method to resolve the function name, taking renames into account
     pub fn name(&self, code: &Bytecode) -> Str {
-        code.get(self.name)
+        code.renames
+            .get(Symbol::Function(self.findex))
+            .unwrap_or_else(|| code.get(self.name))
     }
 
     /// Get the function signature type
@@ -371,33 +442,59 @@ impl Function {
         code.index(self.ty(code).ret)
     }
 
-    /// Uses the assigns to find the name of an argument
+    /// Uses the assigns to find the name of an argument. `pos` doesn't count the `this` argument of methods, which
+    /// isn't named.
     pub fn arg_name(&self, code: &Bytecode, pos: usize) -> Option<Str> {
-        self.assigns.as_ref().and_then(|a| {
-            a.iter()
-                .filter(|&&(_, i)| i == 0)
-                .enumerate()
-                .find_map(|(j, &(s, _))| {
-                    if j == pos {
-                        Some(code[s].clone())
-                    } else {
-                        None
-                    }
-                })
-        })
-    }
-
-    /// Uses the assigns to find the name of a variable
-    pub fn var_name(&self, code: &Bytecode, pos: usize) -> Option<Str> {
-        self.assigns.as_ref().and_then(|a| {
-            a.iter().find_map(|&(s, i)| {
-                if pos + 1 == i {
-                    Some(code[s].clone())
-                } else {
-                    None
-                }
+        self.assigns
+            .iter()
+            .flatten()
+            .filter(|&&(_, i)| i == 0)
+            .nth(pos)
+            .and_then(|&(s, _)| code.try_get(s))
+    }
+
+    /// Uses the assigns to find the name of the variable assigned by the instruction at `pos`
+    pub fn assigned_name(&self, code: &Bytecode, pos: usize) -> Option<Str> {
+        self.assigns
+            .iter()
+            .flatten()
+            .find(|&&(_, i)| i == pos + 1)
+            .and_then(|&(s, _)| code.try_get(s))
+    }
+
+    /// Uses the assigns to find the name of a register as seen by the instruction at `pos`.
+    ///
+    /// This is the name of the last assignment of the register before `pos` (a register can hold multiple variables),
+    /// or the name of the argument if the register hasn't been assigned yet. None without debug info or if the
+    /// register is a temporary.
+    pub fn var_name(&self, code: &Bytecode, pos: usize, reg: Reg) -> Option<Str> {
+        let assigns = self.assigns.as_ref()?;
+        let assigned = assigns
+            .iter()
+            .filter(|&&(_, i)| i > 0 && i <= pos + 1)
+            .filter(|&&(_, i)| {
+                self.ops
+                    .get(i - 1)
+                    .map_or(false, |op| op.defs().contains(&reg))
             })
-        })
+            .max_by_key(|&&(_, i)| i);
+        if let Some(&(s, _)) = assigned {
+            return code.try_get(s);
+        }
+        // Arguments are named in order, methods have an unnamed `this` first
+        let nargs = self.t.as_fun(code).map_or(0, |t| t.args.len());
+        let named = assigns.iter().filter(|&&(_, i)| i == 0).count();
+        let first = nargs.checked_sub(named)?;
+        (reg.0 as usize)
+            .checked_sub(first)
+            .filter(|_| (reg.0 as usize) < nargs)
+            .and_then(|arg| self.arg_name(code, arg))
+    }
+
+    /// Index of the debug file of the function taken from its last instruction, usually a `Ret` which is not inlined
+    /// code. None without debug info or for a function without instructions.
+    pub fn file_index(&self) -> Option<usize> {
+        self.debug_info.as_ref()?.last().map(|&(file, _)| file)
     }
 
     /// A function is a method if the first argument has the same type as the parent type
@@ -424,6 +521,7 @@ impl Index<Reg> for Function {
 
 /// Index reference to a function or a native in the pool (findex)
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefFun(pub usize);
 
 impl RefFun {
@@ -495,7 +593,76 @@ impl<'a> FunPtr<'a> {
 
 /// A constant definition
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstantDef {
     pub global: RefGlobal,
+    /// Indexes in the pool corresponding to each field type, see [ConstantDef::values]
     pub fields: Vec<usize>,
 }
+
+impl ConstantDef {
+    /// Type of the global initialized by this constant
+    pub fn obj<'a>(&self, code: &'a Bytecode) -> Option<&'a TypeObj> {
+        code.globals
+            .get(self.global.0)
+            .and_then(|g| code.types.get(g.0))
+            .and_then(Type::get_type_obj)
+    }
+
+    /// Values of the fields, resolved with the field types of the global. Empty if the global is not an object.
+    pub fn values(&self, code: &Bytecode) -> Vec<ConstantValue> {
+        let Some(obj) = self.obj(code) else {
+            return Vec::new();
+        };
+        self.fields
+            .iter()
+            .zip(&obj.fields)
+            .map(|(&v, f)| match ConstantPool::of(code.types.get(f.t.0)) {
+                ConstantPool::Int => ConstantValue::Int(code[RefInt(v)]),
+                ConstantPool::Float => ConstantValue::Float(code[RefFloat(v)]),
+                ConstantPool::Bool => ConstantValue::Bool(v != 0),
+                ConstantPool::String => ConstantValue::String(code.get(RefString(v))),
+                ConstantPool::Type => ConstantValue::Type(RefType(v)),
+                ConstantPool::Global => ConstantValue::Global(RefGlobal(v)),
+            })
+            .collect()
+    }
+}
+
+/// The value of a constant field
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstantValue {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    /// Bytes fields are initialized with a string
+    String(Str),
+    Type(RefType),
+    /// Other fields hold a global
+    Global(RefGlobal),
+}
+
+/// Pool a constant value is an index into, depending on the type of the field. Like the Hashlink runtime does.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum ConstantPool {
+    Int,
+    Float,
+    /// The value itself
+    Bool,
+    String,
+    Type,
+    Global,
+}
+
+impl ConstantPool {
+    pub(crate) fn of(ty: Option<&Type>) -> Self {
+        match ty {
+            Some(Type::I32 | Type::UI8 | Type::UI16 | Type::I64) => ConstantPool::Int,
+            Some(Type::F32 | Type::F64) => ConstantPool::Float,
+            Some(Type::Bool) => ConstantPool::Bool,
+            Some(Type::Bytes) => ConstantPool::String,
+            Some(Type::Type) => ConstantPool::Type,
+            _ => ConstantPool::Global,
+        }
+    }
+}
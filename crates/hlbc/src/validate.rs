@@ -0,0 +1,925 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::types::{EnumConstruct, JumpOffset, RefEnumConstruct, RefField, Reg};
+use crate::{Bytecode, Function, Opcode, RefFun, RefGlobal, RefType, TryResolve, Type};
+
+/// A constant pool of the bytecode
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pool {
+    Int,
+    Float,
+    String,
+    Bytes,
+    DebugFile,
+    Type,
+    Global,
+    Function,
+}
+
+impl Display for Pool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Pool::Int => "int",
+            Pool::Float => "float",
+            Pool::String => "string",
+            Pool::Bytes => "bytes",
+            Pool::DebugFile => "debug file",
+            Pool::Type => "type",
+            Pool::Global => "global",
+            Pool::Function => "function",
+        })
+    }
+}
+
+/// Where a [Diagnostic] has been found
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Location {
+    /// The bytecode header (e.g. the entrypoint)
+    Header,
+    Type(RefType),
+    Global(RefGlobal),
+    /// Index in the natives pool
+    Native(usize),
+    /// Index in the constants pool
+    Constant(usize),
+    /// Function definition when `op` is [None], instruction at index `op` otherwise.
+    Function {
+        findex: RefFun,
+        op: Option<usize>,
+    },
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Header => write!(f, "header"),
+            Location::Type(t) => write!(f, "type@{}", t.0),
+            Location::Global(g) => write!(f, "global@{}", g.0),
+            Location::Native(n) => write!(f, "native #{n}"),
+            Location::Constant(c) => write!(f, "constant #{c}"),
+            Location::Function { findex, op: None } => write!(f, "fn@{}", findex.0),
+            Location::Function {
+                findex,
+                op: Some(op),
+            } => write!(f, "fn@{} at {op}", findex.0),
+        }
+    }
+}
+
+/// A problem found by the verifier
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Problem {
+    /// Reference to an element outside a constant pool
+    OutOfBounds {
+        pool: Pool,
+        index: usize,
+        len: usize,
+    },
+    /// Register index outside the function registers
+    InvalidRegister { reg: Reg, nregs: usize },
+    /// Jump target outside the function
+    InvalidJump {
+        offset: JumpOffset,
+        target: i64,
+        nops: usize,
+    },
+    /// The type isn't of the expected kind
+    UnexpectedType { ty: RefType, expected: &'static str },
+    /// Field index outside the fields of a type or an enum construct
+    InvalidField {
+        ty: RefType,
+        field: usize,
+        nfields: usize,
+    },
+    /// Construct index outside the constructs of an enum
+    InvalidConstruct {
+        ty: RefType,
+        construct: usize,
+        nconstructs: usize,
+    },
+    /// Wrong number of arguments
+    ArgCount { expected: usize, got: usize },
+    /// Multiple functions or natives share the same findex
+    DuplicateFindex(RefFun),
+    /// Debug info must have an entry for each instruction
+    DebugInfoLength { nops: usize, len: usize },
+    /// The type of a register can't be used by the instruction
+    RegisterType {
+        reg: Reg,
+        ty: RefType,
+        expected: &'static str,
+    },
+    /// Two registers of an instruction must have the same type
+    DifferentTypes {
+        a: Reg,
+        ta: RefType,
+        b: Reg,
+        tb: RefType,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::OutOfBounds { pool, index, len } => {
+                write!(f, "{pool}@{index} is out of bounds (len: {len})")
+            }
+            Problem::InvalidRegister { reg, nregs } => {
+                write!(
+                    f,
+                    "register reg{} does not exist ({nregs} registers)",
+                    reg.0
+                )
+            }
+            Problem::InvalidJump {
+                offset,
+                target,
+                nops,
+            } => write!(
+                f,
+                "jump offset {offset} targets {target}, outside the function ({nops} instructions)"
+            ),
+            Problem::UnexpectedType { ty, expected } => {
+                write!(f, "type@{} is not {expected}", ty.0)
+            }
+            Problem::InvalidField { ty, field, nfields } => write!(
+                f,
+                "field {field} does not exist in type@{} ({nfields} fields)",
+                ty.0
+            ),
+            Problem::InvalidConstruct {
+                ty,
+                construct,
+                nconstructs,
+            } => write!(
+                f,
+                "construct {construct} does not exist in type@{} ({nconstructs} constructs)",
+                ty.0
+            ),
+            Problem::ArgCount { expected, got } => {
+                write!(f, "expected {expected} arguments, got {got}")
+            }
+            Problem::DuplicateFindex(findex) => write!(f, "findex {} is used twice", findex.0),
+            Problem::DebugInfoLength { nops, len } => write!(
+                f,
+                "debug info has {len} entries but the function has {nops} instructions"
+            ),
+            Problem::RegisterType { reg, ty, expected } => {
+                write!(f, "reg{} has type@{} which is not {expected}", reg.0, ty.0)
+            }
+            Problem::DifferentTypes { a, ta, b, tb } => write!(
+                f,
+                "reg{} (type@{}) and reg{} (type@{}) should have the same type",
+                a.0, ta.0, b.0, tb.0
+            ),
+        }
+    }
+}
+
+/// A problem found by [Bytecode::validate] and its location
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub location: Location,
+    pub problem: Problem,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.problem)
+    }
+}
+
+impl Bytecode {
+    /// Check the bytecode for invalid references, registers, jumps, fields and constructs.
+    ///
+    /// This should be called before serializing a modified bytecode, the Hashlink VM does not do
+    /// these checks and would crash. An empty list means no problem has been found.
+    /// This does not type check instructions, see [Bytecode::type_check].
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut v = Validator {
+            code: self,
+            diagnostics: Vec::new(),
+        };
+        v.header();
+        v.types();
+        for (i, &g) in self.globals.iter().enumerate() {
+            v.ty(Location::Global(RefGlobal(i)), g);
+        }
+        v.natives();
+        if let Some(constants) = &self.constants {
+            for (i, c) in constants.iter().enumerate() {
+                v.pool(
+                    Location::Constant(i),
+                    Pool::Global,
+                    c.global.0,
+                    self.globals.len(),
+                );
+            }
+        }
+        for f in &self.functions {
+            v.function(f);
+        }
+        v.diagnostics
+    }
+}
+
+impl Bytecode {
+    /// Check each instruction against the types of its registers, like the Hashlink VM does when loading
+    /// the bytecode (e.g. `Add` on non-numeric registers or `JTrue` on a non-bool register).
+    ///
+    /// Registers out of bounds are skipped, they are reported by [Bytecode::validate].
+    pub fn type_check(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for f in &self.functions {
+            for (pos, op) in f.ops.iter().enumerate() {
+                let mut check = TypeChecker {
+                    code: self,
+                    f,
+                    loc: Location::Function {
+                        findex: f.findex,
+                        op: Some(pos),
+                    },
+                    diagnostics: &mut diagnostics,
+                };
+                check.op(op);
+            }
+        }
+        diagnostics
+    }
+}
+
+struct Validator<'a> {
+    code: &'a Bytecode,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, location: Location, problem: Problem) {
+        self.diagnostics.push(Diagnostic { location, problem });
+    }
+
+    /// Returns true if the index is in bounds
+    fn pool(&mut self, location: Location, pool: Pool, index: usize, len: usize) -> bool {
+        if index < len {
+            true
+        } else {
+            self.report(location, Problem::OutOfBounds { pool, index, len });
+            false
+        }
+    }
+
+    fn ty(&mut self, location: Location, ty: RefType) -> Option<&'a Type> {
+        let code = self.code;
+        if self.pool(location, Pool::Type, ty.0, code.types.len()) {
+            Some(&code.types[ty.0])
+        } else {
+            None
+        }
+    }
+
+    /// Check the type is a function type
+    fn fun_ty(&mut self, location: Location, ty: RefType) -> Option<&'a [RefType]> {
+        match self.ty(location, ty)? {
+            Type::Fun(fun) | Type::Method(fun) => Some(&fun.args),
+            _ => {
+                self.report(
+                    location,
+                    Problem::UnexpectedType {
+                        ty,
+                        expected: "a function",
+                    },
+                );
+                None
+            }
+        }
+    }
+
+    fn findex(&mut self, location: Location, findex: RefFun) -> bool {
+        self.pool(
+            location,
+            Pool::Function,
+            findex.0,
+            self.code.functions.len() + self.code.natives.len(),
+        )
+    }
+
+    fn string(&mut self, location: Location, s: crate::types::RefString) {
+        self.pool(location, Pool::String, s.0, self.code.strings.len());
+    }
+
+    fn header(&mut self) {
+        self.findex(Location::Header, self.code.entrypoint);
+        let mut seen = HashSet::with_capacity(self.code.findex_max());
+        let findexes = self
+            .code
+            .functions
+            .iter()
+            .map(|f| f.findex)
+            .chain(self.code.natives.iter().map(|n| n.findex));
+        for findex in findexes {
+            if !self.findex(Location::Header, findex) {
+                continue;
+            }
+            if !seen.insert(findex) {
+                self.report(Location::Header, Problem::DuplicateFindex(findex));
+            }
+        }
+    }
+
+    fn types(&mut self) {
+        let code = self.code;
+        for (i, t) in code.types.iter().enumerate() {
+            let loc = Location::Type(RefType(i));
+            match t {
+                Type::Fun(fun) | Type::Method(fun) => {
+                    for &arg in &fun.args {
+                        self.ty(loc, arg);
+                    }
+                    self.ty(loc, fun.ret);
+                }
+                Type::Obj(obj) | Type::Struct(obj) => {
+                    self.string(loc, obj.name);
+                    if let Some(sup) = obj.super_ {
+                        self.ty(loc, sup);
+                    }
+                    // The global is stored shifted by one, 0 means no global
+                    if obj.global.0 > 0 {
+                        self.pool(loc, Pool::Global, obj.global.0 - 1, code.globals.len());
+                    }
+                    for f in &obj.own_fields {
+                        self.string(loc, f.name);
+                        self.ty(loc, f.t);
+                    }
+                    for p in &obj.protos {
+                        self.string(loc, p.name);
+                        self.findex(loc, p.findex);
+                    }
+                    for (field, &findex) in &obj.bindings {
+                        self.findex(loc, findex);
+                        if field.0 >= obj.fields.len() {
+                            self.report(
+                                loc,
+                                Problem::InvalidField {
+                                    ty: RefType(i),
+                                    field: field.0,
+                                    nfields: obj.fields.len(),
+                                },
+                            );
+                        }
+                    }
+                }
+                &Type::Ref(inner) | &Type::Null(inner) | &Type::Packed(inner) => {
+                    self.ty(loc, inner);
+                }
+                Type::Virtual { fields } => {
+                    for f in fields {
+                        self.string(loc, f.name);
+                        self.ty(loc, f.t);
+                    }
+                }
+                Type::Abstract { name } => self.string(loc, *name),
+                Type::Enum {
+                    name,
+                    global,
+                    constructs,
+                } => {
+                    self.string(loc, *name);
+                    // Enums without global have it set to 0, like classes
+                    if global.0 > 0 {
+                        self.pool(loc, Pool::Global, global.0 - 1, code.globals.len());
+                    }
+                    for c in constructs {
+                        self.string(loc, c.name);
+                        for &p in &c.params {
+                            self.ty(loc, p);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn natives(&mut self) {
+        for (i, n) in self.code.natives.iter().enumerate() {
+            let loc = Location::Native(i);
+            self.string(loc, n.name);
+            self.string(loc, n.lib);
+            self.fun_ty(loc, n.t);
+        }
+    }
+
+    fn function(&mut self, f: &'a Function) {
+        let code = self.code;
+        let loc = Location::Function {
+            findex: f.findex,
+            op: None,
+        };
+        self.string(loc, f.name);
+        for &r in &f.regs {
+            self.ty(loc, r);
+        }
+        if let Some(args) = self.fun_ty(loc, f.t) {
+            if args.len() > f.regs.len() {
+                self.report(
+                    loc,
+                    Problem::ArgCount {
+                        expected: args.len(),
+                        got: f.regs.len(),
+                    },
+                );
+            }
+        }
+        if let (Some(debug_info), Some(files)) = (&f.debug_info, &code.debug_files) {
+            if debug_info.len() != f.ops.len() {
+                self.report(
+                    loc,
+                    Problem::DebugInfoLength {
+                        nops: f.ops.len(),
+                        len: debug_info.len(),
+                    },
+                );
+            }
+            if let Some(&(file, _)) = debug_info.iter().find(|&&(file, _)| file >= files.len()) {
+                self.pool(loc, Pool::DebugFile, file, files.len());
+            }
+        }
+
+        for (pos, op) in f.ops.iter().enumerate() {
+            let mut check = OpValidator {
+                v: self,
+                f,
+                loc: Location::Function {
+                    findex: f.findex,
+                    op: Some(pos),
+                },
+                pos,
+            };
+            check.op(op);
+        }
+    }
+}
+
+/// Validator for a single instruction
+struct OpValidator<'a, 'v> {
+    v: &'v mut Validator<'a>,
+    f: &'a Function,
+    loc: Location,
+    pos: usize,
+}
+
+impl<'a, 'v> OpValidator<'a, 'v> {
+    fn report(&mut self, problem: Problem) {
+        self.v.report(self.loc, problem);
+    }
+
+    /// Get the type of a register if it exists
+    fn reg(&mut self, reg: Reg) -> Option<RefType> {
+        if let Some(&t) = self.f.regs.get(reg.0 as usize) {
+            Some(t)
+        } else {
+            self.report(Problem::InvalidRegister {
+                reg,
+                nregs: self.f.regs.len(),
+            });
+            None
+        }
+    }
+
+    fn pool(&mut self, pool: Pool, index: usize, len: usize) {
+        self.v.pool(self.loc, pool, index, len);
+    }
+
+    fn jump(&mut self, offset: JumpOffset) {
+        let target = self.pos as i64 + offset as i64 + 1;
+        let nops = self.f.ops.len();
+        if target < 0 || target >= nops as i64 {
+            self.report(Problem::InvalidJump {
+                offset,
+                target,
+                nops,
+            });
+        }
+    }
+
+    /// Check a call to a function with `nargs` arguments
+    fn call(&mut self, fun: RefFun, nargs: usize) {
+        if !self.v.findex(self.loc, fun) {
+            return;
+        }
+        let t = match self.v.code.try_get(fun) {
+            Some(crate::types::FunPtr::Fun(f)) => f.t,
+            Some(crate::types::FunPtr::Native(n)) => n.t,
+            // findexes are stale or inconsistent, reported in the header
+            None => return,
+        };
+        if let Some(args) = self.v.fun_ty(self.loc, t) {
+            if args.len() != nargs {
+                self.report(Problem::ArgCount {
+                    expected: args.len(),
+                    got: nargs,
+                });
+            }
+        }
+    }
+
+    /// Check a field access on the type of a register
+    fn field(&mut self, reg: Reg, field: RefField) {
+        let Some(ty) = self.reg(reg) else {
+            return;
+        };
+        let nfields = match self.v.code.try_get(ty) {
+            Some(Type::Obj(obj) | Type::Struct(obj)) => obj.fields.len(),
+            Some(Type::Virtual { fields }) => fields.len(),
+            Some(_) => {
+                self.report(Problem::UnexpectedType {
+                    ty,
+                    expected: "an obj, struct or virtual",
+                });
+                return;
+            }
+            // Reported with the function registers
+            None => return,
+        };
+        if field.0 >= nfields {
+            self.report(Problem::InvalidField {
+                ty,
+                field: field.0,
+                nfields,
+            });
+        }
+    }
+
+    /// Check an enum construct on the type of a register
+    fn construct(&mut self, reg: Reg, construct: RefEnumConstruct) -> Option<&'a EnumConstruct> {
+        let ty = self.reg(reg)?;
+        match self.v.code.try_get(ty)? {
+            Type::Enum { constructs, .. } => {
+                let c = constructs.get(construct.0);
+                if c.is_none() {
+                    self.report(Problem::InvalidConstruct {
+                        ty,
+                        construct: construct.0,
+                        nconstructs: constructs.len(),
+                    });
+                }
+                c
+            }
+            _ => {
+                self.report(Problem::UnexpectedType {
+                    ty,
+                    expected: "an enum",
+                });
+                None
+            }
+        }
+    }
+
+    fn construct_field(&mut self, reg: Reg, construct: RefEnumConstruct, field: RefField) {
+        if let Some(c) = self.construct(reg, construct) {
+            if field.0 >= c.params.len() {
+                let ty = self.f.regs[reg.0 as usize];
+                self.report(Problem::InvalidField {
+                    ty,
+                    field: field.0,
+                    nfields: c.params.len(),
+                });
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Opcode) {
+        let code = self.v.code;
+        for r in op.registers() {
+            self.reg(r);
+        }
+        match op {
+            Opcode::Int { ptr, .. } => self.pool(Pool::Int, ptr.0, code.ints.len()),
+            Opcode::Float { ptr, .. } => self.pool(Pool::Float, ptr.0, code.floats.len()),
+            Opcode::Bytes { ptr, .. } => {
+                // Before bytecode v5, bytes are stored in the strings pool
+                if let Some((_, pos)) = &code.bytes {
+                    self.pool(Pool::Bytes, ptr.0, pos.len())
+                } else {
+                    self.pool(Pool::String, ptr.0, code.strings.len())
+                }
+            }
+            Opcode::String { ptr, .. } => self.pool(Pool::String, ptr.0, code.strings.len()),
+            Opcode::Call0 { fun, .. } => self.call(*fun, 0),
+            Opcode::Call1 { fun, .. } => self.call(*fun, 1),
+            Opcode::Call2 { fun, .. } => self.call(*fun, 2),
+            Opcode::Call3 { fun, .. } => self.call(*fun, 3),
+            Opcode::Call4 { fun, .. } => self.call(*fun, 4),
+            Opcode::CallN { fun, args, .. } => self.call(*fun, args.len()),
+            // The object is the first argument
+            Opcode::CallMethod { args, .. } if args.is_empty() => self.report(Problem::ArgCount {
+                expected: 1,
+                got: 0,
+            }),
+            Opcode::CallThis { .. } => {
+                self.reg(Reg(0));
+            }
+            Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+                self.v.findex(self.loc, *fun);
+            }
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                self.pool(Pool::Global, global.0, code.globals.len())
+            }
+            Opcode::Field { obj, field, .. } | Opcode::SetField { obj, field, .. } => {
+                self.field(*obj, *field)
+            }
+            Opcode::GetThis { field, .. } | Opcode::SetThis { field, .. } => {
+                self.field(Reg(0), *field)
+            }
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                self.pool(Pool::String, field.0, code.strings.len())
+            }
+            Opcode::JTrue { offset, .. }
+            | Opcode::JFalse { offset, .. }
+            | Opcode::JNull { offset, .. }
+            | Opcode::JNotNull { offset, .. }
+            | Opcode::JSLt { offset, .. }
+            | Opcode::JSGte { offset, .. }
+            | Opcode::JSGt { offset, .. }
+            | Opcode::JSLte { offset, .. }
+            | Opcode::JULt { offset, .. }
+            | Opcode::JUGte { offset, .. }
+            | Opcode::JNotLt { offset, .. }
+            | Opcode::JNotGte { offset, .. }
+            | Opcode::JEq { offset, .. }
+            | Opcode::JNotEq { offset, .. }
+            | Opcode::JAlways { offset }
+            | Opcode::Trap { offset, .. } => self.jump(*offset),
+            Opcode::Switch { offsets, end, .. } => {
+                for &offset in offsets {
+                    self.jump(offset);
+                }
+                self.jump(*end);
+            }
+            Opcode::Type { ty, .. } => self.pool(Pool::Type, ty.0, code.types.len()),
+            Opcode::MakeEnum {
+                dst,
+                construct,
+                args,
+            } => {
+                if let Some(c) = self.construct(*dst, *construct) {
+                    if c.params.len() != args.len() {
+                        self.report(Problem::ArgCount {
+                            expected: c.params.len(),
+                            got: args.len(),
+                        });
+                    }
+                }
+            }
+            Opcode::EnumAlloc { dst, construct } => {
+                self.construct(*dst, *construct);
+            }
+            Opcode::EnumField {
+                value,
+                construct,
+                field,
+                ..
+            } => self.construct_field(*value, *construct, *field),
+            Opcode::SetEnumField { value, field, .. } => {
+                self.construct_field(*value, RefEnumConstruct(0), *field)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Type checker for a single instruction
+struct TypeChecker<'a, 'd> {
+    code: &'a Bytecode,
+    f: &'a Function,
+    loc: Location,
+    diagnostics: &'d mut Vec<Diagnostic>,
+}
+
+impl TypeChecker<'_, '_> {
+    fn report(&mut self, problem: Problem) {
+        self.diagnostics.push(Diagnostic {
+            location: self.loc,
+            problem,
+        });
+    }
+
+    fn type_of(&self, reg: Reg) -> Option<(RefType, &Type)> {
+        let &ty = self.f.regs.get(reg.0 as usize)?;
+        Some((ty, self.code.types.get(ty.0)?))
+    }
+
+    /// Check the type of a register with a predicate
+    fn expect(&mut self, reg: Reg, expected: &'static str, pred: fn(&Type) -> bool) {
+        if let Some((ty, t)) = self.type_of(reg) {
+            if !pred(t) {
+                self.report(Problem::RegisterType { reg, ty, expected });
+            }
+        }
+    }
+
+    fn same(&mut self, a: Reg, b: Reg) {
+        if let (Some(&ta), Some(&tb)) =
+            (self.f.regs.get(a.0 as usize), self.f.regs.get(b.0 as usize))
+        {
+            if ta != tb {
+                self.report(Problem::DifferentTypes { a, ta, b, tb });
+            }
+        }
+    }
+
+    /// Binary operation on numbers of the same type
+    fn numeric(
+        &mut self,
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+        expected: &'static str,
+        pred: fn(&Type) -> bool,
+    ) {
+        self.expect(dst, expected, pred);
+        self.same(dst, a);
+        self.same(dst, b);
+    }
+
+    fn op(&mut self, op: &Opcode) {
+        const NUMBER: &str = "a number";
+        const INTEGER: &str = "an integer";
+        const FLOAT: &str = "a float";
+        const BOOL: &str = "a bool";
+        const BYTES: &str = "bytes";
+        const NULLABLE: &str = "nullable";
+        const ARRAY: &str = "an array";
+        const REF: &str = "a reference";
+        const TYPE: &str = "a type";
+        let bool = |t: &Type| matches!(t, Type::Bool);
+        let bytes = |t: &Type| matches!(t, Type::Bytes);
+        let array = |t: &Type| matches!(t, Type::Array);
+        let reference = |t: &Type| matches!(t, Type::Ref(_));
+        let ty = |t: &Type| matches!(t, Type::Type);
+        match *op {
+            Opcode::Add { dst, a, b }
+            | Opcode::Sub { dst, a, b }
+            | Opcode::Mul { dst, a, b }
+            | Opcode::SDiv { dst, a, b }
+            | Opcode::SMod { dst, a, b } => self.numeric(dst, a, b, NUMBER, Type::is_numeric),
+            Opcode::UDiv { dst, a, b }
+            | Opcode::UMod { dst, a, b }
+            | Opcode::Shl { dst, a, b }
+            | Opcode::SShr { dst, a, b }
+            | Opcode::UShr { dst, a, b }
+            | Opcode::And { dst, a, b }
+            | Opcode::Or { dst, a, b }
+            | Opcode::Xor { dst, a, b } => self.numeric(dst, a, b, INTEGER, Type::is_integer),
+            Opcode::Neg { dst, src } => {
+                self.expect(dst, NUMBER, Type::is_numeric);
+                self.same(dst, src);
+            }
+            Opcode::Not { dst, src } => {
+                self.expect(dst, BOOL, bool);
+                self.expect(src, BOOL, bool);
+            }
+            Opcode::Incr { dst } | Opcode::Decr { dst } => {
+                self.expect(dst, INTEGER, Type::is_integer)
+            }
+            Opcode::Int { dst, .. } => self.expect(dst, INTEGER, Type::is_integer),
+            Opcode::Float { dst, .. } => self.expect(dst, FLOAT, Type::is_float),
+            Opcode::Bool { dst, .. } => self.expect(dst, BOOL, bool),
+            Opcode::Bytes { dst, .. } | Opcode::String { dst, .. } => {
+                self.expect(dst, BYTES, bytes)
+            }
+            Opcode::Null { dst } => self.expect(dst, NULLABLE, Type::is_nullable),
+            Opcode::JTrue { cond, .. } | Opcode::JFalse { cond, .. } => {
+                self.expect(cond, BOOL, bool)
+            }
+            Opcode::JNull { reg, .. }
+            | Opcode::JNotNull { reg, .. }
+            | Opcode::NullCheck { reg } => self.expect(reg, NULLABLE, Type::is_nullable),
+            Opcode::JULt { a, b, .. } | Opcode::JUGte { a, b, .. } => {
+                self.expect(a, INTEGER, Type::is_integer);
+                self.same(a, b);
+            }
+            Opcode::Switch { reg, .. } => self.expect(reg, INTEGER, Type::is_integer),
+            Opcode::ToSFloat { dst, src } => {
+                self.expect(dst, FLOAT, Type::is_float);
+                self.expect(src, NUMBER, Type::is_numeric);
+            }
+            Opcode::ToUFloat { dst, src } => {
+                self.expect(dst, FLOAT, Type::is_float);
+                self.expect(src, INTEGER, Type::is_integer);
+            }
+            Opcode::ToInt { dst, src } => {
+                self.expect(dst, INTEGER, Type::is_integer);
+                self.expect(src, NUMBER, Type::is_numeric);
+            }
+            Opcode::GetI8 {
+                bytes: b, index, ..
+            }
+            | Opcode::GetI16 {
+                bytes: b, index, ..
+            }
+            | Opcode::GetMem {
+                bytes: b, index, ..
+            }
+            | Opcode::SetI8 {
+                bytes: b, index, ..
+            }
+            | Opcode::SetI16 {
+                bytes: b, index, ..
+            }
+            | Opcode::SetMem {
+                bytes: b, index, ..
+            } => {
+                self.expect(b, BYTES, bytes);
+                self.expect(index, INTEGER, Type::is_integer);
+            }
+            Opcode::GetArray {
+                array: a, index, ..
+            }
+            | Opcode::SetArray {
+                array: a, index, ..
+            } => {
+                self.expect(a, ARRAY, array);
+                self.expect(index, INTEGER, Type::is_integer);
+            }
+            Opcode::ArraySize { dst, array: a } => {
+                self.expect(dst, INTEGER, Type::is_integer);
+                self.expect(a, ARRAY, array);
+            }
+            Opcode::Ref { dst, .. } | Opcode::Setref { dst, .. } => {
+                self.expect(dst, REF, reference)
+            }
+            Opcode::Unref { src, .. } => self.expect(src, REF, reference),
+            Opcode::Type { dst, .. } | Opcode::GetType { dst, .. } => self.expect(dst, TYPE, ty),
+            Opcode::GetTID { dst, .. } => self.expect(dst, INTEGER, Type::is_integer),
+            Opcode::New { dst } => self.expect(dst, "an object", |t| {
+                matches!(
+                    t,
+                    Type::Obj(_) | Type::Struct(_) | Type::Virtual { .. } | Type::DynObj
+                )
+            }),
+            Opcode::CallClosure { fun, .. } => {
+                self.expect(fun, "a function", |t| matches!(t, Type::Fun(_) | Type::Dyn))
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opcodes::Opcode;
+    use crate::testing::{sample, shapes};
+    use crate::types::{RefGlobal, Reg, Type};
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(sample().validate(), vec![]);
+        assert_eq!(shapes().validate(), vec![]);
+
+        // Class global out of bounds, stored shifted by one
+        let mut code = sample();
+        if let Type::Obj(obj) = &mut code.types[4] {
+            obj.global = RefGlobal(2);
+        }
+        let problems: Vec<_> = code.validate().iter().map(|d| d.to_string()).collect();
+        assert_eq!(problems, vec!["type@4: global@1 is out of bounds (len: 1)"]);
+    }
+
+    #[test]
+    fn test_type_check() {
+        let mut code = sample();
+        assert_eq!(code.type_check(), vec![]);
+        // Not from an int to the Bytes register
+        code.functions[0].ops.insert(
+            0,
+            Opcode::Not {
+                dst: Reg(2),
+                src: Reg(1),
+            },
+        );
+        let problems: Vec<_> = code.type_check().iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "fn@0 at 0: reg2 has type@7 which is not a bool",
+                "fn@0 at 0: reg1 has type@1 which is not a bool"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_invalid_register() {
+        let mut code = sample();
+        code.functions[1]
+            .ops
+            .insert(0, Opcode::Null { dst: Reg(2) });
+        let problems: Vec<_> = code.validate().iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            problems,
+            vec!["fn@1 at 0: register reg2 does not exist (2 registers)"]
+        );
+    }
+}
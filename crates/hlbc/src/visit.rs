@@ -0,0 +1,146 @@
+//! Walking the whole bytecode with a visitor.
+//!
+//! Implement [BytecodeVisitor] with the callbacks of interest, every callback does nothing by default. [Bytecode::visit]
+//! calls them in order : types (with their fields), globals, natives, then functions with their instructions and the
+//! operands of each instruction.
+
+use crate::opcodes::{Opcode, Operand};
+use crate::types::{Function, Native, ObjField, RefField, RefGlobal, RefType, Type};
+use crate::Bytecode;
+
+/// Callbacks called while walking a [Bytecode], see [Bytecode::visit]
+#[allow(unused_variables)]
+pub trait BytecodeVisitor {
+    fn visit_type(&mut self, code: &Bytecode, t: RefType, ty: &Type) {}
+
+    /// Field of an object, a struct or a virtual, only the fields defined by this type. Called after
+    /// [BytecodeVisitor::visit_type] for the parent type.
+    fn visit_field(&mut self, code: &Bytecode, parent: RefType, index: RefField, field: &ObjField) {
+    }
+
+    fn visit_global(&mut self, code: &Bytecode, global: RefGlobal, ty: RefType) {}
+
+    fn visit_native(&mut self, code: &Bytecode, native: &Native) {}
+
+    /// Return false to skip the instructions of this function
+    fn visit_function(&mut self, code: &Bytecode, f: &Function) -> bool {
+        true
+    }
+
+    fn visit_opcode(&mut self, code: &Bytecode, f: &Function, pos: usize, op: &Opcode) {}
+
+    /// Called after [BytecodeVisitor::visit_opcode] for each operand, in declaration order
+    fn visit_operand(
+        &mut self,
+        code: &Bytecode,
+        f: &Function,
+        pos: usize,
+        name: &'static str,
+        operand: Operand<'_>,
+    ) {
+    }
+}
+
+impl Bytecode {
+    /// Walk the whole bytecode, see [BytecodeVisitor]
+    pub fn visit(&self, visitor: &mut impl BytecodeVisitor) {
+        for (i, ty) in self.types.iter().enumerate() {
+            let t = RefType(i);
+            visitor.visit_type(self, t, ty);
+            let (fields, first) = match ty {
+                Type::Obj(obj) | Type::Struct(obj) => (
+                    &obj.own_fields,
+                    obj.fields.len().saturating_sub(obj.own_fields.len()),
+                ),
+                Type::Virtual { fields } => (fields, 0),
+                _ => continue,
+            };
+            for (j, field) in fields.iter().enumerate() {
+                visitor.visit_field(self, t, RefField(first + j), field);
+            }
+        }
+        for (i, &ty) in self.globals.iter().enumerate() {
+            visitor.visit_global(self, RefGlobal(i), ty);
+        }
+        for n in &self.natives {
+            visitor.visit_native(self, n);
+        }
+        for f in &self.functions {
+            if !visitor.visit_function(self, f) {
+                continue;
+            }
+            for (pos, op) in f.ops() {
+                visitor.visit_opcode(self, f, pos, op);
+                for (name, operand) in op.operands() {
+                    visitor.visit_operand(self, f, pos, name, operand);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opcodes::{Opcode, Operand};
+    use crate::testing::sample;
+    use crate::types::{Function, ObjField, RefField, RefType, Reg, Type};
+    use crate::visit::BytecodeVisitor;
+    use crate::Bytecode;
+
+    #[derive(Default)]
+    struct Counter {
+        types: usize,
+        fields: usize,
+        ops: usize,
+        type_operands: usize,
+    }
+
+    impl BytecodeVisitor for Counter {
+        fn visit_type(&mut self, _: &Bytecode, _: RefType, _: &Type) {
+            self.types += 1;
+        }
+
+        fn visit_field(&mut self, code: &Bytecode, parent: RefType, index: RefField, _: &ObjField) {
+            if let Some(obj) = code[parent].get_type_obj() {
+                assert!(index.0 < obj.fields.len());
+            }
+            self.fields += 1;
+        }
+
+        fn visit_opcode(&mut self, _: &Bytecode, _: &Function, _: usize, _: &Opcode) {
+            self.ops += 1;
+        }
+
+        fn visit_operand(
+            &mut self,
+            _: &Bytecode,
+            _: &Function,
+            _: usize,
+            _: &'static str,
+            operand: Operand<'_>,
+        ) {
+            if matches!(operand, Operand::Type(_)) {
+                self.type_operands += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit() {
+        let mut code = sample();
+        code.functions[3].ops.insert(
+            0,
+            Opcode::Type {
+                dst: Reg(0),
+                ty: RefType(1),
+            },
+        );
+        let mut counter = Counter::default();
+        code.visit(&mut counter);
+        assert_eq!(counter.types, 8);
+        // Point.x and the 3 fields of Main
+        assert_eq!(counter.fields, 4);
+        assert_eq!(counter.ops, 15);
+        assert_eq!(counter.type_operands, 1);
+    }
+}
@@ -3,64 +3,96 @@ use std::io::Write;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
+use crate::summary::{ByteCounter, Section};
 use crate::types::{RefField, RefFloat, RefFun, RefGlobal, RefInt, RefString, RefType, TypeFun};
-use crate::{Bytecode, ConstantDef, Function, Native, ObjField, Str, Type, TypeObj};
+use crate::{Bytecode, ConstantDef, Function, Native, ObjField, Opcode, Str, Type, TypeObj};
 use crate::{Error, Result};
 
 impl Bytecode {
     /// Serialize the bytecode to any sink.
     /// Bytecode is serialized to the same format.
     pub fn serialize(&self, w: &mut impl Write) -> Result<()> {
-        w.write_all(&[b'H', b'L', b'B'])?;
-        w.write_u8(self.version)?;
-        write_var(w, if self.debug_files.is_some() { 1 } else { 0 })?;
-        write_var(w, self.ints.len() as i32)?;
-        write_var(w, self.floats.len() as i32)?;
-        write_var(w, self.strings.len() as i32)?;
-        if let Some((_, pos)) = &self.bytes {
-            write_var(w, pos.len() as i32)?;
+        for section in Section::ALL {
+            self.serialize_section(section, w)?;
         }
-        write_var(w, self.types.len() as i32)?;
-        write_var(w, self.globals.len() as i32)?;
-        write_var(w, self.natives.len() as i32)?;
-        write_var(w, self.functions.len() as i32)?;
-        if let Some(constants) = &self.constants {
-            write_var(w, constants.len() as i32)?;
-        }
-        self.entrypoint.write(w)?;
-        for &i in &self.ints {
-            w.write_i32::<LittleEndian>(i)?;
-        }
-        for &f in &self.floats {
-            w.write_f64::<LittleEndian>(f)?;
-        }
-        write_strings(w, &self.strings)?;
-        if let Some((bytes, pos)) = &self.bytes {
-            w.write_i32::<LittleEndian>(bytes.len() as i32)?;
-            w.write_all(bytes)?;
-            for &p in pos {
-                write_var(w, p as i32)?;
+        Ok(())
+    }
+
+    /// Serialize a single section of the bytecode.
+    /// Does nothing if the section is not present in this bytecode version.
+    pub(crate) fn serialize_section(&self, section: Section, w: &mut impl Write) -> Result<()> {
+        match section {
+            Section::Header => {
+                w.write_all(&[b'H', b'L', b'B'])?;
+                w.write_u8(self.version)?;
+                write_var(w, if self.debug_files.is_some() { 1 } else { 0 })?;
+                write_var(w, self.ints.len() as i32)?;
+                write_var(w, self.floats.len() as i32)?;
+                write_var(w, self.strings.len() as i32)?;
+                if let Some((_, pos)) = &self.bytes {
+                    write_var(w, pos.len() as i32)?;
+                }
+                write_var(w, self.types.len() as i32)?;
+                write_var(w, self.globals.len() as i32)?;
+                write_var(w, self.natives.len() as i32)?;
+                write_var(w, self.functions.len() as i32)?;
+                if let Some(constants) = &self.constants {
+                    write_var(w, constants.len() as i32)?;
+                }
+                self.entrypoint.write(w)?;
             }
-        }
-        if let Some(debug_files) = &self.debug_files {
-            write_var(w, debug_files.len() as i32)?;
-            write_strings(w, debug_files)?;
-        }
-        for t in &self.types {
-            t.write(w)?;
-        }
-        for g in &self.globals {
-            g.write(w)?;
-        }
-        for n in &self.natives {
-            n.write(w)?;
-        }
-        for f in &self.functions {
-            f.write(w)?;
-        }
-        if let Some(constants) = &self.constants {
-            for c in constants {
-                c.write(w)?;
+            Section::Ints => {
+                for &i in &self.ints {
+                    w.write_i32::<LittleEndian>(i)?;
+                }
+            }
+            Section::Floats => {
+                for &f in &self.floats {
+                    w.write_f64::<LittleEndian>(f)?;
+                }
+            }
+            Section::Strings => write_strings(w, &self.strings)?,
+            Section::Bytes => {
+                if let Some((bytes, pos)) = &self.bytes {
+                    w.write_i32::<LittleEndian>(bytes.len() as i32)?;
+                    w.write_all(bytes)?;
+                    for &p in pos {
+                        write_var(w, p as i32)?;
+                    }
+                }
+            }
+            Section::DebugFiles => {
+                if let Some(debug_files) = &self.debug_files {
+                    write_var(w, debug_files.len() as i32)?;
+                    write_strings(w, debug_files)?;
+                }
+            }
+            Section::Types => {
+                for t in &self.types {
+                    t.write(w)?;
+                }
+            }
+            Section::Globals => {
+                for g in &self.globals {
+                    g.write(w)?;
+                }
+            }
+            Section::Natives => {
+                for n in &self.natives {
+                    n.write(w)?;
+                }
+            }
+            Section::Functions => {
+                for f in &self.functions {
+                    f.write(w, &self.function_ops(f)?)?;
+                }
+            }
+            Section::Constants => {
+                if let Some(constants) = &self.constants {
+                    for c in constants {
+                        c.write(w)?;
+                    }
+                }
             }
         }
         Ok(())
@@ -142,7 +174,11 @@ impl TypeObj {
             p.findex.write(w)?;
             write_var(w, p.pindex)?;
         }
-        for (fi, fun) in &self.bindings {
+        // Bindings are written in the order of the fields, like the compiler does, so the output doesn't depend on the
+        // order of the map
+        let mut bindings: Vec<_> = self.bindings.iter().collect();
+        bindings.sort_unstable_by_key(|(fi, _)| fi.0);
+        for (fi, fun) in bindings {
             fi.write(w)?;
             fun.write(w)?;
         }
@@ -227,6 +263,88 @@ impl Type {
     }
 }
 
+/// Serialization in two phases : the size of every section is computed first, the bytecode is written afterward.
+///
+/// Obtained with [Bytecode::sized_writer].
+#[derive(Debug, Clone)]
+pub struct SizedWriter<'a> {
+    code: &'a Bytecode,
+    /// Sections present in the bytecode with their size, in file order
+    sizes: Vec<(Section, usize)>,
+}
+
+impl Bytecode {
+    /// Compute the encoded size of every section to write the bytecode afterward.
+    pub fn sized_writer(&self) -> Result<SizedWriter<'_>> {
+        let sizes = Section::ALL
+            .into_iter()
+            .filter(|&section| self.section_len(section).is_some())
+            .map(|section| {
+                let mut counter = ByteCounter(0);
+                self.serialize_section(section, &mut counter)?;
+                Ok((section, counter.0))
+            })
+            .collect::<Result<_>>()?;
+        Ok(SizedWriter { code: self, sizes })
+    }
+}
+
+impl SizedWriter<'_> {
+    /// Sections present in the bytecode with their size in bytes, in file order
+    pub fn sizes(&self) -> &[(Section, usize)] {
+        &self.sizes
+    }
+
+    /// Size of a section in bytes, None if the section is not present in this bytecode
+    pub fn section_size(&self, section: Section) -> Option<usize> {
+        self.sizes
+            .iter()
+            .find_map(|&(s, size)| if s == section { Some(size) } else { None })
+    }
+
+    /// Size of the output in bytes
+    pub fn size(&self) -> usize {
+        self.sizes.iter().map(|&(_, size)| size).sum()
+    }
+
+    /// Same as [Bytecode::serialize]
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.code.serialize(w)
+    }
+
+    /// Serialize to a buffer allocated once with the exact size.
+    /// With the `rayon` feature, sections are written in parallel.
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        #[cfg(feature = "rayon")]
+        use rayon::prelude::*;
+
+        let mut buf = vec![0; self.size()];
+        let mut rest = buf.as_mut_slice();
+        let mut parts = Vec::with_capacity(self.sizes.len());
+        for &(section, size) in &self.sizes {
+            let (part, tail) = std::mem::take(&mut rest).split_at_mut(size);
+            parts.push((section, part));
+            rest = tail;
+        }
+        #[cfg(feature = "rayon")]
+        let parts = parts.into_par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let mut parts = parts.into_iter();
+        parts.try_for_each(|(section, mut part)| {
+            self.code.serialize_section(section, &mut part)?;
+            if part.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::MalformedBytecode(format!(
+                    "Section {} changed size since it was computed",
+                    section.name()
+                )))
+            }
+        })?;
+        Ok(buf)
+    }
+}
+
 impl Native {
     pub(crate) fn write(&self, w: &mut impl Write) -> Result<()> {
         self.lib.write(w)?;
@@ -237,15 +355,16 @@ impl Native {
 }
 
 impl Function {
-    pub(crate) fn write(&self, w: &mut impl Write) -> Result<()> {
+    /// Encode a function with its instructions, which are not in [Function::ops] for a lazily loaded bytecode
+    pub(crate) fn write(&self, w: &mut impl Write, ops: &[Opcode]) -> Result<()> {
         self.t.write(w)?;
         self.findex.write(w)?;
         write_var(w, self.regs.len() as i32)?;
-        write_var(w, self.ops.len() as i32)?;
+        write_var(w, ops.len() as i32)?;
         for r in &self.regs {
             r.write(w)?;
         }
-        for o in &self.ops {
+        for o in ops {
             o.write(w)?;
         }
         // https://github.com/HaxeFoundation/haxe/blob/ea57ab1ef60d212228c8657b7bc5b1085c62714e/src/generators/genhl.ml#L3910
@@ -387,8 +506,24 @@ fn flush_repeat(
 mod tests {
     use std::fs;
 
+    use crate::summary::Section;
+    use crate::testing::sample;
     use crate::Bytecode;
 
+    #[test]
+    fn test_sized_writer() -> crate::Result<()> {
+        let code = sample();
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        let writer = code.sized_writer()?;
+        assert_eq!(writer.size(), data.len());
+        assert_eq!(writer.size(), 202);
+        assert_eq!(writer.section_size(Section::Functions), Some(67));
+        assert_eq!(code.section_size(Section::Functions), 67);
+        assert_eq!(writer.to_vec()?, data);
+        Ok(())
+    }
+
     //#[test]
     fn ser_eq_deser() {
         // FIXME this test fails because we are not generating the same bytecode after deserialization
@@ -0,0 +1,118 @@
+//! Search across every kind of element of the bytecode.
+//!
+//! Functions, classes, strings and source files are scored with the same fuzzy matcher so the results can be mixed
+//! in a single list. Queries with a findex, a qualified name or a signature (see [Query]) only return functions.
+
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+use hlbc::fmt::EnhancedFmt;
+use hlbc::types::{RefFun, RefString, RefType, Type};
+use hlbc::Bytecode;
+
+use crate::{top_candidates, Query, Ranked, Searcher, SkimSearcher};
+
+/// A search result
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Element {
+    Function(RefFun),
+    /// Obj, struct or enum type
+    Class(RefType),
+    String(RefString),
+    /// Index in the debug files
+    File(usize),
+}
+
+impl Element {
+    /// Short name of the kind of element
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Element::Function(_) => "fn",
+            Element::Class(_) => "class",
+            Element::String(_) => "string",
+            Element::File(_) => "file",
+        }
+    }
+}
+
+/// Searches functions, classes, strings and files at once
+pub struct ElementSearcher {
+    functions: Ranked<SkimSearcher>,
+    matcher: SkimMatcherV2,
+}
+
+impl ElementSearcher {
+    pub fn new() -> Self {
+        Self {
+            functions: Ranked(SkimSearcher::new()),
+            matcher: SkimMatcherV2::default().ignore_case(),
+        }
+    }
+
+    /// At most `limit` elements, best match first
+    pub fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<Element> {
+        if needle.trim().is_empty() {
+            return Vec::new();
+        }
+        if !matches!(
+            Query::parse(needle),
+            Query::Function {
+                parent: None,
+                args: None,
+                ret: None,
+                ..
+            }
+        ) {
+            return self
+                .functions
+                .search(code, needle, limit)
+                .into_iter()
+                .map(Element::Function)
+                .collect();
+        }
+
+        let needle = needle.trim();
+        let score = |candidate: &str| {
+            self.matcher
+                .fuzzy_match(candidate, needle)
+                .map(|s| s as f32)
+                .unwrap_or(0.0)
+        };
+        let names = code.display_names();
+        let functions = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (Element::Function(RefFun(i)), score(name)));
+        let classes = code
+            .types
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| matches!(t, Type::Obj(_) | Type::Struct(_) | Type::Enum { .. }))
+            .map(|(i, _)| {
+                let t = RefType(i);
+                let name = t.display::<EnhancedFmt>(code).to_string();
+                (Element::Class(t), score(&name))
+            });
+        let strings = code
+            .strings
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (Element::String(RefString(i)), score(s)));
+        let files = code
+            .debug_files
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, file)| (Element::File(i), score(file)));
+        top_candidates(limit, functions.chain(classes).chain(strings).chain(files))
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect()
+    }
+}
+
+impl Default for ElementSearcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
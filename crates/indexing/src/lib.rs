@@ -4,6 +4,13 @@ use std::collections::BinaryHeap;
 use hlbc::types::RefFun;
 use hlbc::Bytecode;
 
+pub use elements::*;
+pub use ranking::*;
+#[cfg(feature = "tantivy")]
+pub use tantivy::{content_hash, default_cache_dir, TantivySearcher};
+
+mod elements;
+mod ranking;
 #[cfg(feature = "tantivy")]
 mod tantivy;
 
@@ -54,16 +61,16 @@ pub struct Contains;
 impl Searcher for Contains {
     fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
         let needle_len = needle.len() as f32;
+        let names = code.display_names();
         top_candidates(
             limit,
-            code.functions().map(|f| {
-                let name = f.name(code);
+            names.iter().enumerate().map(|(i, name)| {
                 let len = name.len() as f32;
                 (
-                    f.findex(),
+                    RefFun(i),
                     if name.contains(needle) {
                         needle_len / len
-                    } else if needle.contains(&*name) {
+                    } else if needle.contains(&**name) {
                         len / needle_len
                     } else {
                         0.0
@@ -109,12 +116,13 @@ impl ClangdSearcher {
 
 impl Searcher for ClangdSearcher {
     fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
+        let names = code.display_names();
         top_candidates(
             limit,
-            code.functions().map(|f| {
+            names.iter().enumerate().map(|(i, name)| {
                 (
-                    f.findex(),
-                    fuzzy_matcher::FuzzyMatcher::fuzzy_match(&self.0, &f.name(code), needle)
+                    RefFun(i),
+                    fuzzy_matcher::FuzzyMatcher::fuzzy_match(&self.0, name, needle)
                         .map(|s| s as f32)
                         .unwrap_or(0.0),
                 )
@@ -136,12 +144,13 @@ impl SkimSearcher {
 
 impl Searcher for SkimSearcher {
     fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
+        let names = code.display_names();
         top_candidates(
             limit,
-            code.functions().map(|f| {
+            names.iter().enumerate().map(|(i, name)| {
                 (
-                    f.findex(),
-                    fuzzy_matcher::FuzzyMatcher::fuzzy_match(&self.0, &f.name(code), needle)
+                    RefFun(i),
+                    fuzzy_matcher::FuzzyMatcher::fuzzy_match(&self.0, name, needle)
                         .map(|s| s as f32)
                         .unwrap_or(0.0),
                 )
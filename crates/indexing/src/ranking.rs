@@ -0,0 +1,429 @@
+//! Signature aware ranking of search results.
+//!
+//! Queries can be more than a function name :
+//! - `@1234` : the function with this findex
+//! - `Class.method` : functions named `method` in class `Class`, matched exactly
+//! - `update(dt:Float)` : argument names and types (and the return type after `):`) weight the results
+//!
+//! Name only queries are handled by the wrapped [Searcher].
+
+use hlbc::fmt::EnhancedFmt;
+use hlbc::types::{FunPtr, RefFun, RefType, Type};
+use hlbc::{Bytecode, Resolve};
+
+use crate::{top_candidates, Searcher};
+
+/// Number of candidates asked to the fuzzy searcher for each requested result
+const OVERFETCH: usize = 4;
+
+/// An argument in a signature query, `name:Type`, `name:` or `Type`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ArgQuery<'a> {
+    pub name: Option<&'a str>,
+    pub ty: Option<&'a str>,
+}
+
+/// A parsed search query
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Query<'a> {
+    /// `@1234`
+    Findex(RefFun),
+    /// `[Class.]name[(args)][:Ret]`
+    Function {
+        parent: Option<&'a str>,
+        name: &'a str,
+        /// `None` when the query has no parentheses
+        args: Option<Vec<ArgQuery<'a>>>,
+        ret: Option<&'a str>,
+    },
+}
+
+impl<'a> Query<'a> {
+    pub fn parse(query: &'a str) -> Self {
+        let query = query.trim();
+        if let Some(findex) = query.strip_prefix('@').and_then(|n| n.parse().ok()) {
+            return Query::Findex(RefFun(findex));
+        }
+
+        let (path, args, ret) = match query.split_once('(') {
+            Some((path, rest)) => {
+                let (args, ret) = rest.split_once(')').unwrap_or((rest, ""));
+                let args = args
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .map(|a| match a.split_once(':') {
+                        Some((name, ty)) => ArgQuery {
+                            name: non_empty(name),
+                            ty: non_empty(ty),
+                        },
+                        None => ArgQuery {
+                            name: None,
+                            ty: Some(a),
+                        },
+                    })
+                    .collect();
+                let ret = ret.trim().strip_prefix(':').and_then(non_empty);
+                (path, Some(args), ret)
+            }
+            None => (query, None, None),
+        };
+        let (parent, name) = match path.trim().rsplit_once('.') {
+            Some((parent, name)) => (non_empty(parent), name),
+            None => (None, path.trim()),
+        };
+        Query::Function {
+            parent,
+            name,
+            args,
+            ret,
+        }
+    }
+}
+
+fn non_empty(s: &str) -> Option<&str> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+/// Wraps a [Searcher] to handle findex, qualified and signature queries.
+///
+/// Fuzzy results on the name are ranked again with the parent class, the arguments and the return type.
+pub struct Ranked<S>(pub S);
+
+impl<S: Searcher> Searcher for Ranked<S> {
+    fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
+        let (parent, name, args, ret) = match Query::parse(needle) {
+            Query::Findex(findex) => {
+                return if findex.0 < code.findex_max() {
+                    vec![findex]
+                } else {
+                    Vec::new()
+                };
+            }
+            Query::Function {
+                parent,
+                name,
+                args,
+                ret,
+            } => (parent, name, args, ret),
+        };
+
+        // Qualified names are exact, no need for fuzzy matching
+        if let (Some(parent), None, None) = (parent, &args, ret) {
+            let exact: Vec<RefFun> = code
+                .functions()
+                .filter(|f| {
+                    &*f.name(code) == name
+                        && matches!(parent_name(code, *f), Some(p) if path_matches(&p, parent))
+                })
+                .map(|f| f.findex())
+                .take(limit)
+                .collect();
+            if !exact.is_empty() {
+                return exact;
+            }
+        }
+
+        let mut candidates = self.0.search(code, name, limit * OVERFETCH);
+        if parent.is_none() && args.is_none() && ret.is_none() {
+            candidates.truncate(limit);
+            return candidates;
+        }
+
+        let n = candidates.len() as f32;
+        top_candidates(
+            limit,
+            candidates.into_iter().enumerate().map(|(i, findex)| {
+                // Keep the order of the fuzzy searcher as the base score
+                let mut score = 1.0 - i as f32 / n;
+                let f = code.get(findex);
+                if let Some(parent) = parent {
+                    if matches!(parent_name(code, f), Some(p) if path_matches(&p, parent)) {
+                        score += 1.0;
+                    }
+                }
+                if let Some(args) = &args {
+                    score += args_score(code, f, args);
+                }
+                if let Some(ret) = ret {
+                    let ret_ty = match f {
+                        FunPtr::Fun(fun) => fun.ty(code).ret,
+                        FunPtr::Native(n) => n.ty(code).ret,
+                    };
+                    if type_matches(code, ret_ty, ret) {
+                        score += 0.5;
+                    }
+                }
+                (findex, score)
+            }),
+        )
+        .into_iter()
+        .map(|(c, _)| c)
+        .collect()
+    }
+}
+
+/// Class of a function or lib of a native
+fn parent_name(code: &Bytecode, f: FunPtr) -> Option<String> {
+    match f {
+        FunPtr::Fun(fun) => fun
+            .parent
+            .and_then(|p| p.as_obj(code))
+            .map(|obj| obj.name(code).to_string()),
+        FunPtr::Native(n) => Some(n.lib(code).to_string()),
+    }
+}
+
+/// `Class` matches `Class` and `pack.Class`
+fn path_matches(path: &str, query: &str) -> bool {
+    path.eq_ignore_ascii_case(query)
+        || path.len() > query.len()
+            && path.as_bytes()[path.len() - query.len() - 1] == b'.'
+            && path[path.len() - query.len()..].eq_ignore_ascii_case(query)
+}
+
+fn args_score(code: &Bytecode, f: FunPtr, query: &[ArgQuery]) -> f32 {
+    let (args, offset) = match f {
+        // Skip the implicit this
+        FunPtr::Fun(fun) => (fun.args(code), usize::from(fun.is_method())),
+        FunPtr::Native(n) => (n.args(code), 0),
+    };
+    let args = &args[offset.min(args.len())..];
+    let mut score = if args.len() == query.len() { 0.5 } else { 0.0 };
+    for (i, (q, &ty)) in query.iter().zip(args).enumerate() {
+        if matches!(q.ty, Some(q) if type_matches(code, ty, q)) {
+            score += 0.5;
+        }
+        if let (Some(q), FunPtr::Fun(fun)) = (q.name, f) {
+            if matches!(fun.arg_name(code, i + offset), Some(name) if name.eq_ignore_ascii_case(q))
+            {
+                score += 0.5;
+            }
+        }
+    }
+    score
+}
+
+/// Compare a type with a type name from a query, Haxe names of the primitive types are accepted
+fn type_matches(code: &Bytecode, ty: RefType, query: &str) -> bool {
+    let query = match query.to_ascii_lowercase().as_str() {
+        "int" => "i32".to_string(),
+        "float" => "f64".to_string(),
+        "single" => "f32".to_string(),
+        "dyn" => "dynamic".to_string(),
+        q => q.to_string(),
+    };
+    // Null<Int> matches Int
+    let ty = match &code[ty] {
+        Type::Null(inner) => *inner,
+        _ => ty,
+    };
+    let name = ty.display::<EnhancedFmt>(code).to_string();
+    // Types other than the primitives are displayed with their index : `game.Player@12`
+    let name = match name.rsplit_once('@') {
+        Some((name, index)) if index.parse::<usize>().is_ok() => name,
+        _ => &name,
+    };
+    path_matches(name, &query)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::fmt::asm::parse_functions;
+    use hlbc::types::{RefFun, RefGlobal, RefString, RefType, Type, TypeFun, TypeObj};
+    use hlbc::{Bytecode, Str};
+
+    use crate::ranking::{path_matches, type_matches, ArgQuery, Query, Ranked};
+    use crate::Searcher;
+
+    /// Returns every function, in order
+    struct All;
+
+    impl Searcher for All {
+        fn search(&self, code: &Bytecode, _needle: &str, limit: usize) -> Vec<RefFun> {
+            (0..code.findex_max()).map(RefFun).take(limit).collect()
+        }
+    }
+
+    #[test]
+    fn parse_findex() {
+        assert_eq!(Query::parse("@1234"), Query::Findex(RefFun(1234)));
+        assert_eq!(Query::parse("  @7 "), Query::Findex(RefFun(7)));
+        // Not a number, searched as a name
+        assert_eq!(
+            Query::parse("@abc"),
+            Query::Function {
+                parent: None,
+                name: "@abc",
+                args: None,
+                ret: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_function() {
+        assert_eq!(
+            Query::parse("update"),
+            Query::Function {
+                parent: None,
+                name: "update",
+                args: None,
+                ret: None,
+            }
+        );
+        assert_eq!(
+            Query::parse("pack.Class.method"),
+            Query::Function {
+                parent: Some("pack.Class"),
+                name: "method",
+                args: None,
+                ret: None,
+            }
+        );
+        assert_eq!(
+            Query::parse("Game.update(dt:Float, :Int, String, name:):Void"),
+            Query::Function {
+                parent: Some("Game"),
+                name: "update",
+                args: Some(vec![
+                    ArgQuery {
+                        name: Some("dt"),
+                        ty: Some("Float"),
+                    },
+                    ArgQuery {
+                        name: None,
+                        ty: Some("Int"),
+                    },
+                    ArgQuery {
+                        name: None,
+                        ty: Some("String"),
+                    },
+                    ArgQuery {
+                        name: Some("name"),
+                        ty: None,
+                    },
+                ]),
+                ret: Some("Void"),
+            }
+        );
+        // Empty parentheses and an unclosed argument list
+        assert_eq!(
+            Query::parse("f()"),
+            Query::Function {
+                parent: None,
+                name: "f",
+                args: Some(Vec::new()),
+                ret: None,
+            }
+        );
+        assert_eq!(
+            Query::parse(".f(x:Int"),
+            Query::Function {
+                parent: None,
+                name: "f",
+                args: Some(vec![ArgQuery {
+                    name: Some("x"),
+                    ty: Some("Int"),
+                }]),
+                ret: None,
+            }
+        );
+    }
+
+    #[test]
+    fn path() {
+        assert!(path_matches("Main", "Main"));
+        assert!(path_matches("game.Main", "main"));
+        assert!(path_matches("pack.sub.Main", "sub.Main"));
+        assert!(!path_matches("game.MyMain", "Main"));
+        assert!(!path_matches("Main", "game.Main"));
+        assert!(!path_matches("game.Main", ""));
+    }
+
+    #[test]
+    fn types() {
+        let mut code = Bytecode::default();
+        code.strings = vec![Str::from(""), Str::from("game.Player")];
+        code.types = vec![
+            Type::I32,
+            Type::F64,
+            Type::F32,
+            Type::Dyn,
+            Type::Null(RefType(0)),
+            Type::Null(RefType(20)),
+        ];
+        // Far from the base types, like in a real bytecode
+        code.types.resize(20, Type::Void);
+        code.types.push(Type::Obj(TypeObj {
+            name: RefString(1),
+            super_: None,
+            global: RefGlobal(0),
+            own_fields: Vec::new(),
+            protos: Vec::new(),
+            bindings: Default::default(),
+            fields: Vec::new(),
+        }));
+        assert!(type_matches(&code, RefType(0), "Int"));
+        assert!(type_matches(&code, RefType(0), "i32"));
+        assert!(type_matches(&code, RefType(1), "Float"));
+        assert!(type_matches(&code, RefType(2), "Single"));
+        assert!(type_matches(&code, RefType(3), "Dynamic"));
+        assert!(type_matches(&code, RefType(3), "dyn"));
+        // Null<Int> matches Int
+        assert!(type_matches(&code, RefType(4), "Int"));
+        // Displayed with its index
+        assert!(type_matches(&code, RefType(20), "Player"));
+        assert!(type_matches(&code, RefType(20), "game.Player"));
+        assert!(type_matches(&code, RefType(5), "player"));
+        assert!(!type_matches(&code, RefType(0), "Float"));
+        assert!(!type_matches(&code, RefType(20), "Play"));
+    }
+    #[test]
+    fn ranked() {
+        let fun = |arg| {
+            Type::Fun(TypeFun {
+                args: vec![RefType(arg)],
+                ret: RefType(0),
+            })
+        };
+        let mut code = Bytecode::default();
+        code.strings = ["", "update", "n", "dt"].map(Str::from).to_vec();
+        code.types = vec![Type::Void, Type::I32, Type::F64, fun(1), fun(2)];
+        code.functions = parse_functions(
+            "
+            .fun fn@0 type@3
+            .assigns
+            .assign string@2 0
+            .reg type@1
+            .reg type@0
+                Ret reg1
+            .end
+            .fun fn@1 type@4
+            .assigns
+            .assign string@3 0
+            .reg type@2
+            .reg type@0
+                Ret reg1
+            .end",
+        )
+        .unwrap();
+        for f in &mut code.functions {
+            f.name = RefString(1);
+        }
+        code.rebuild_indexes();
+
+        let search = |query| Ranked(All).search(&code, query, 2);
+        assert_eq!(search("update"), [RefFun(0), RefFun(1)]);
+        assert_eq!(search("update(Float)"), [RefFun(1), RefFun(0)]);
+        assert_eq!(search("update(dt:)"), [RefFun(1), RefFun(0)]);
+        assert_eq!(search("update(n:Int)"), [RefFun(0), RefFun(1)]);
+        assert_eq!(search("@1"), [RefFun(1)]);
+        assert_eq!(search("@2"), []);
+    }
+}
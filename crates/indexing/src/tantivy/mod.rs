@@ -1,33 +1,38 @@
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use tantivy::collector::TopDocs;
 use tantivy::query::QueryParser;
 use tantivy::schema::{
-    Field, IndexRecordOption, NumericOptions, Schema, TextFieldIndexing, TextOptions, STORED,
+    IndexRecordOption, NumericOptions, Schema, TextFieldIndexing, TextOptions, Value, STORED,
 };
 use tantivy::tokenizer::{LowerCaser, NgramTokenizer, TextAnalyzer};
-use tantivy::{doc, Index};
+use tantivy::{doc, Index, TantivyDocument};
 
 use hlbc::types::RefFun;
 use hlbc::Bytecode;
 
+use crate::Searcher;
+
 mod tokenizer;
 
-struct TantivySearcher {
+/// Bump when the schema or the tokenizer changes, so older indexes on disk are not reused
+const INDEX_VERSION: u32 = 1;
+/// Number of indexes kept in the cache directory
+const CACHE_SIZE: usize = 8;
+/// File touched each time an index is opened
+const USED: &str = "used";
+
+pub struct TantivySearcher {
     index: Index,
 }
 
 impl TantivySearcher {
-    pub fn new_functions(code: &Bytecode) -> Self {
-        // let code_tokenizer = TextAnalyzer::from(FunctionTokenizer)
-        //     .filter(LowerCaser)
-        //     .filter(Stemmer::new(Language::English));
-        let code_tokenizer =
-            TextAnalyzer::from(NgramTokenizer::all_ngrams(3, 10)).filter(LowerCaser);
-
+    fn schema() -> Schema {
         let mut schema_builder = Schema::builder();
-        let findex = schema_builder.add_u64_field("findex", NumericOptions::default() | STORED);
-        let name = schema_builder.add_text_field(
+        schema_builder.add_u64_field("findex", NumericOptions::default() | STORED);
+        schema_builder.add_text_field(
             "name",
             TextOptions::default().set_indexing_options(
                 TextFieldIndexing::default()
@@ -36,51 +41,156 @@ impl TantivySearcher {
                     .set_tokenizer("function"),
             ),
         );
-        let schema = schema_builder.build();
+        schema_builder.build()
+    }
 
-        let index = Index::create_in_ram(schema.clone());
+    fn register_tokenizers(index: &Index) {
+        // let code_tokenizer = TextAnalyzer::from(FunctionTokenizer)
+        //     .filter(LowerCaser)
+        //     .filter(Stemmer::new(Language::English));
+        let code_tokenizer = TextAnalyzer::builder(NgramTokenizer::all_ngrams(3, 10).unwrap())
+            .filter(LowerCaser)
+            .build();
         index.tokenizers().register("function", code_tokenizer);
+    }
 
-        let mut writer = index.writer_with_num_threads(1, 10_000_000).unwrap();
+    fn index_functions(index: &Index, code: &Bytecode) -> tantivy::Result<()> {
+        let schema = index.schema();
+        let findex = schema.get_field("findex")?;
+        let name = schema.get_field("name")?;
+
+        let mut writer = index.writer_with_num_threads(1, 15_000_000)?;
         let start = Instant::now();
         for f in code.functions() {
-            writer
-                .add_document(doc!(
-                    findex => f.findex().0 as u64,
-                    name => &*f.name(code)
-                ))
-                .unwrap();
+            writer.add_document(doc!(
+                findex => f.findex().0 as u64,
+                name => &*f.name(code)
+            ))?;
         }
-        writer.commit().unwrap();
+        writer.commit()?;
         println!(
             "Indexed all documents in {} ms",
             start.elapsed().as_millis()
         );
+        Ok(())
+    }
 
+    /// Build the index in memory
+    pub fn new_functions(code: &Bytecode) -> Self {
+        let index = Index::create_in_ram(Self::schema());
+        Self::register_tokenizers(&index);
+        Self::index_functions(&index, code).unwrap();
         Self { index }
     }
-}
 
-impl TantivySearcher {
-    pub fn query(&self, query_text: &str) -> Vec<RefFun> {
-        let reader = self.index.reader().unwrap();
+    /// Open the index of a bytecode file from `cache_dir`, or build it there if it doesn't exist yet.
+    ///
+    /// `hash` identifies the file content (see [content_hash]), a modified file gets a new index. Only the
+    /// [CACHE_SIZE] most recently used indexes are kept in the directory. Renames are not part of the key, the index
+    /// holds the names of the functions at the time it was built.
+    pub fn open_cached(code: &Bytecode, hash: u64, cache_dir: &Path) -> tantivy::Result<Self> {
+        let dir = cache_dir.join(format!("v{INDEX_VERSION}-{hash:016x}"));
+        if let Ok(index) = Index::open_in_dir(&dir) {
+            Self::register_tokenizers(&index);
+            // Mark as recently used
+            let _ = fs::write(dir.join(USED), []);
+            return Ok(Self { index });
+        }
+
+        // Build in a temporary directory so an interrupted build is never reused
+        let tmp = cache_dir.join(format!("tmp-{hash:016x}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp)?;
+        let index = Index::create_in_dir(&tmp, Self::schema())?;
+        Self::register_tokenizers(&index);
+        Self::index_functions(&index, code)?;
+        drop(index);
+        let _ = fs::remove_dir_all(&dir);
+        fs::rename(&tmp, &dir)?;
+        let _ = fs::write(dir.join(USED), []);
+        evict(cache_dir);
+
+        let index = Index::open_in_dir(&dir)?;
+        Self::register_tokenizers(&index);
+        Ok(Self { index })
+    }
+
+    pub fn query(&self, query_text: &str, limit: usize) -> tantivy::Result<Vec<RefFun>> {
+        let reader = self.index.reader()?;
         let searcher = reader.searcher();
-        let parser = QueryParser::for_index(&self.index, vec![Field::from_field_id(1)]);
-        let query = parser.parse_query(query_text).unwrap();
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(10)).unwrap();
-        top_docs
-            .into_iter()
-            .map(|(_, d)| {
-                RefFun(
-                    searcher
-                        .doc(d)
-                        .unwrap()
-                        .get_first(Field::from_field_id(0))
-                        .unwrap()
-                        .as_u64()
-                        .unwrap() as usize,
-                )
-            })
-            .collect()
+        let schema = self.index.schema();
+        let findex = schema.get_field("findex")?;
+        let parser = QueryParser::for_index(&self.index, vec![schema.get_field("name")?]);
+        let query = parser.parse_query(query_text)?;
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+        let mut results = Vec::with_capacity(top_docs.len());
+        for (_, d) in top_docs {
+            let doc: TantivyDocument = searcher.doc(d)?;
+            if let Some(f) = doc.get_first(findex).and_then(|v| v.as_u64()) {
+                results.push(RefFun(f as usize));
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl Searcher for TantivySearcher {
+    fn search(&self, _code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
+        self.query(needle, limit).unwrap_or_default()
+    }
+}
+
+/// Remove the least recently used indexes and leftovers of interrupted builds
+fn evict(cache_dir: &Path) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    let mut indexes = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with("tmp-") {
+            // Might be another process building its index right now
+            let age = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.elapsed().ok());
+            if matches!(age, Some(age) if age.as_secs() > 3600) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        } else if name.starts_with('v') {
+            if !name.starts_with(&format!("v{INDEX_VERSION}-")) {
+                let _ = fs::remove_dir_all(entry.path());
+                continue;
+            }
+            let used = fs::metadata(entry.path().join(USED)).and_then(|m| m.modified());
+            indexes.push((used.ok(), entry.path()));
+        }
+    }
+    if indexes.len() > CACHE_SIZE {
+        indexes.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        for (_, path) in indexes.drain(CACHE_SIZE..) {
+            let _ = fs::remove_dir_all(path);
+        }
     }
 }
+
+/// Stable hash of a file content to key its index, FNV-1a
+pub fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Platform cache directory for the indexes (`$XDG_CACHE_HOME/hlbc/index`, `~/.cache/hlbc/index` or
+/// `%LOCALAPPDATA%\hlbc\index`)
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    }?;
+    Some(base.join("hlbc").join("index"))
+}